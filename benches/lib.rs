@@ -122,6 +122,10 @@ fn bench_hash(c: &mut Criterion) {
         .bench_function(BenchmarkId::new("hash", 1024), |b| {
             let data = [0u8; 1024];
             b.iter(|| hash::hash(0, black_box(&data)))
+        })
+        .bench_function(BenchmarkId::new("blake3", 1024), |b| {
+            let data = [0u8; 1024];
+            b.iter(|| hash::blake3(black_box(&data)))
         });
 }
 
@@ -138,6 +142,19 @@ fn bench_oklab(c: &mut Criterion) {
         });
 }
 
+fn bench_simd_hex(c: &mut Criterion) {
+    let mut group = c.benchmark_group("simd");
+    let src: Vec<u8> = (0..MEBI).map(|i| i as u8).collect();
+    let mut hex = vec![0u8; 2 * MEBI];
+    let mut dst = vec![0u8; MEBI];
+    simd::hex_encode(&mut hex, &src);
+
+    group
+        .throughput(Throughput::Bytes(MEBI as u64))
+        .bench_function("hex_encode", |b| b.iter(|| simd::hex_encode(&mut hex, black_box(&src))))
+        .bench_function("hex_decode", |b| b.iter(|| simd::hex_decode(&mut dst, black_box(&hex))));
+}
+
 fn bench_simd_lines_fwd(c: &mut Criterion) {
     let mut group = c.benchmark_group("simd");
     let buf = vec![b'\n'; 128 * MEBI];
@@ -153,6 +170,21 @@ fn bench_simd_lines_fwd(c: &mut Criterion) {
     }
 }
 
+fn bench_simd_lines_bwd(c: &mut Criterion) {
+    let mut group = c.benchmark_group("simd");
+    let buf = vec![b'\n'; 128 * MEBI];
+
+    for &lines in &[1, 8, 128, KIBI, 128 * KIBI, 128 * MEBI] {
+        group.throughput(Throughput::Bytes(lines as u64)).bench_with_input(
+            BenchmarkId::new("lines_bwd", lines),
+            &lines,
+            |b, &lines| {
+                b.iter(|| simd::lines_bwd(black_box(&buf), buf.len(), lines as CoordType, 0));
+            },
+        );
+    }
+}
+
 fn bench_simd_memchr2(c: &mut Criterion) {
     let mut group = c.benchmark_group("simd");
     let mut buf = vec![0u8; 128 * MEBI + KIBI];
@@ -173,6 +205,38 @@ fn bench_simd_memchr2(c: &mut Criterion) {
     }
 }
 
+fn bench_simd_columns_fwd(c: &mut Criterion) {
+    let mut group = c.benchmark_group("simd");
+    let buf = vec![b'a'; 128 * MEBI];
+
+    for &columns in &[8, 128, KIBI, 128 * KIBI, 128 * MEBI] {
+        group.throughput(Throughput::Bytes(columns as u64)).bench_with_input(
+            BenchmarkId::new("columns_fwd", columns),
+            &columns,
+            |b, &columns| {
+                b.iter(|| simd::columns_fwd(black_box(&buf), 0, 0, columns as CoordType, 4));
+            },
+        );
+    }
+}
+
+fn bench_simd_find_ascii_icase(c: &mut Criterion) {
+    let mut group = c.benchmark_group("simd");
+    let mut buf = vec![0u8; 128 * MEBI + KIBI];
+
+    for &bytes in &[8usize, 128 + 8, KIBI, 128 * KIBI, 128 * MEBI] {
+        group.throughput(Throughput::Bytes(bytes as u64 + 5)).bench_with_input(
+            BenchmarkId::new("find_ascii_icase", bytes),
+            &bytes,
+            |b, &size| {
+                buf.fill(b'a');
+                buf[size..size + 5].copy_from_slice(b"NEEDL");
+                b.iter(|| simd::find_ascii_icase(black_box(&buf), 0, b"needl"));
+            },
+        );
+    }
+}
+
 fn bench_simd_memset<T: MemsetSafe + Copy + Default>(c: &mut Criterion) {
     let mut group = c.benchmark_group("simd");
     let name = format!("memset<{}>", std::any::type_name::<T>());
@@ -232,7 +296,11 @@ fn bench(c: &mut Criterion) {
     bench_buffer(c);
     bench_hash(c);
     bench_oklab(c);
+    bench_simd_hex(c);
     bench_simd_lines_fwd(c);
+    bench_simd_lines_bwd(c);
+    bench_simd_columns_fwd(c);
+    bench_simd_find_ascii_icase(c);
     bench_simd_memchr2(c);
     bench_simd_memset::<u32>(c);
     bench_simd_memset::<u8>(c);
@@ -3,12 +3,16 @@ use std::fmt::Write as _;
 
 use crate::helpers::env_opt;
 
+/// The CLDR plural categories, in the order of the generated `PLURAL_TRANSLATIONS`.
+const PLURAL_CATEGORIES: [&str; 6] = ["zero", "one", "two", "few", "many", "other"];
+
 pub fn generate(definitions: &str) -> String {
     let i18n = toml_span::parse(definitions).expect("Failed to parse i18n file");
     let root = i18n.as_table().unwrap();
     let mut languages = Vec::new();
     let mut aliases = Vec::new();
     let mut translations: BTreeMap<String, HashMap<String, String>> = BTreeMap::new();
+    let mut plurals: BTreeMap<String, HashMap<String, HashMap<String, String>>> = BTreeMap::new();
 
     for (k, v) in root.iter() {
         match &k.name[..] {
@@ -28,11 +32,40 @@ pub fn generate(definitions: &str) -> String {
                 }));
             }
             _ => {
-                const ERROR: &str = "i18n: LocId must be str->str";
+                const ERROR: &str = "i18n: LocId must be str->str or str->{category->str}";
+                let table = v.as_table().expect(ERROR);
+
+                // Messages with plural forms map each language to a table of CLDR categories.
+                if table.iter().any(|(_, v)| v.as_table().is_some()) {
+                    let mut forms = HashMap::new();
+                    for (lang, v) in table.iter() {
+                        let v = v.as_table().expect(ERROR);
+                        let mut categories = HashMap::new();
+                        for (category, v) in v.iter() {
+                            if !PLURAL_CATEGORIES.contains(&&category.name[..]) {
+                                panic!("i18n: invalid plural category \"{}\"", category.name);
+                            }
+                            categories.insert(
+                                category.name.to_string(),
+                                v.as_str().expect(ERROR).to_string(),
+                            );
+                        }
+                        if !categories.contains_key("other") {
+                            panic!(
+                                "i18n: {}.{} lacks the \"other\" plural category",
+                                k.name, lang.name
+                            );
+                        }
+                        forms.insert(lang.name.to_string(), categories);
+                    }
+                    plurals.insert(k.name.to_string(), forms);
+                    continue;
+                }
+
                 translations.insert(
                     k.name.to_string(),
                     HashMap::from_iter(
-                        v.as_table().expect(ERROR).iter().map(|(k, v)| {
+                        table.iter().map(|(k, v)| {
                             (k.name.to_string(), v.as_str().expect(ERROR).to_string())
                         }),
                     ),
@@ -86,6 +119,11 @@ pub fn generate(definitions: &str) -> String {
                 available.insert(lang.as_str());
             }
         }
+        for v in plurals.values() {
+            for lang in v.keys() {
+                available.insert(lang.as_str());
+            }
+        }
 
         let mut invalid = Vec::new();
         for lang in &languages {
@@ -150,6 +188,20 @@ pub enum LocId {{",
             "\
 }}
 
+const LOC_ID_NAMES: [&str; {}] = [
+",
+            translations.len(),
+        );
+
+        for k in translations.keys() {
+            _ = writeln!(out, "    {k:?},");
+        }
+
+        _ = write!(
+            out,
+            "\
+];
+
 #[allow(non_camel_case_types)]
 #[derive(Clone, Copy, PartialEq, Eq)]
 pub enum LangId {{
@@ -178,7 +230,23 @@ const LANGUAGES: &[(&str, LangId)] = &[
             "\
 ];
 
-const TRANSLATIONS: [[&str; {}]; {}] = [
+const LANGUAGE_TAGS: [(&str, LangId); {}] = [
+",
+            languages.len(),
+        );
+
+        for lang in &languages {
+            _ = writeln!(out, "    ({lang:?}, LangId::{lang}),");
+        }
+
+        // A `static`, because `loc()` indexes into it at runtime and a `const` this
+        // large would be copied at every use (see `clippy::large_const_arrays`).
+        _ = write!(
+            out,
+            "\
+];
+
+static TRANSLATIONS: [[&str; {}]; {}] = [
 ",
             translations.len(),
             languages.len(),
@@ -197,5 +265,67 @@ const TRANSLATIONS: [[&str; {}]; {}] = [
         _ = writeln!(out, "];");
     }
 
+    // Generate the plural forms and the rules for picking one.
+    {
+        _ = write!(
+            out,
+            "\
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub enum PluralId {{
+",
+        );
+
+        for k in plurals.keys() {
+            _ = writeln!(out, "    {k},");
+        }
+
+        _ = write!(
+            out,
+            "\
+}}
+
+const PLURAL_ID_NAMES: [&str; {}] = [
+",
+            plurals.len(),
+        );
+
+        for k in plurals.keys() {
+            _ = writeln!(out, "    {k:?},");
+        }
+
+        _ = write!(
+            out,
+            "\
+];
+
+const PLURAL_TRANSLATIONS: [[[&str; {}]; {}]; {}] = [
+",
+            PLURAL_CATEGORIES.len(),
+            plurals.len(),
+            languages.len(),
+        );
+
+        for lang in &languages {
+            _ = writeln!(out, "    [");
+            for v in plurals.values() {
+                let Some(forms) = v.get(lang).or_else(|| v.get("en")) else {
+                    _ = writeln!(out, "        [\"\"; {}],", PLURAL_CATEGORIES.len());
+                    continue;
+                };
+                // Categories that a translation doesn't use (or that the
+                // English fallback doesn't have) fall back to "other".
+                _ = write!(out, "        [");
+                for category in PLURAL_CATEGORIES {
+                    let v = forms.get(category).unwrap_or(&forms["other"]);
+                    _ = write!(out, "{v:?}, ");
+                }
+                _ = writeln!(out, "],");
+            }
+            _ = writeln!(out, "    ],");
+        }
+
+        _ = writeln!(out, "];");
+    }
+
     out
 }
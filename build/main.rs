@@ -47,6 +47,9 @@ fn configure_icu(target_os: TargetOs) {
     let cpp_exports = env_opt("EDIT_CFG_ICU_CPP_EXPORTS");
     let renaming_version = env_opt("EDIT_CFG_ICU_RENAMING_VERSION");
     let renaming_auto_detect = env_opt("EDIT_CFG_ICU_RENAMING_AUTO_DETECT");
+    let icu_static = env_opt("EDIT_CFG_ICU_STATIC");
+    let icu_lib_dir = env_opt("EDIT_CFG_ICU_LIB_DIR");
+    let icu_static = !icu_static.is_empty() && icu_static.parse::<bool>().unwrap();
 
     // If none of the `EDIT_CFG_ICU*` environment variables are set,
     // we default to enabling `EDIT_CFG_ICU_RENAMING_AUTO_DETECT` on UNIX.
@@ -55,6 +58,7 @@ fn configure_icu(target_os: TargetOs) {
         renaming_auto_detect.parse::<bool>().unwrap()
     } else {
         target_os == TargetOs::Unix
+            && !icu_static
             && icuuc_soname.is_empty()
             && icui18n_soname.is_empty()
             && cpp_exports.is_empty()
@@ -66,6 +70,12 @@ fn configure_icu(target_os: TargetOs) {
             "Either `EDIT_CFG_ICU_RENAMING_AUTO_DETECT` or `EDIT_CFG_ICU_RENAMING_VERSION` must be set, but not both"
         );
     }
+    if renaming_auto_detect && icu_static {
+        // The symbol names of a static library must be known at build time.
+        panic!(
+            "`EDIT_CFG_ICU_RENAMING_AUTO_DETECT` can't be used with `EDIT_CFG_ICU_STATIC`, use `EDIT_CFG_ICU_RENAMING_VERSION` instead"
+        );
+    }
 
     let icuuc_soname = if !icuuc_soname.is_empty() {
         &icuuc_soname
@@ -103,6 +113,30 @@ fn configure_icu(target_os: TargetOs) {
     if renaming_auto_detect {
         println!("cargo::rustc-cfg=edit_icu_renaming_auto_detect");
     }
+
+    println!("cargo::rerun-if-env-changed=EDIT_CFG_ICU_STATIC");
+    println!("cargo::rerun-if-env-changed=EDIT_CFG_ICU_LIB_DIR");
+    println!("cargo::rustc-check-cfg=cfg(edit_icu_static)");
+    if icu_static {
+        println!("cargo::rustc-cfg=edit_icu_static");
+        if !icu_lib_dir.is_empty() {
+            println!("cargo::rustc-link-search=native={icu_lib_dir}");
+        }
+
+        // ICU is written in C++ and the data library must come last, as the others depend on it.
+        let (libs, cpp_runtime) = match target_os {
+            TargetOs::Windows => (["sicuin", "sicuuc", "sicudt"], None),
+            TargetOs::MacOS => (["icui18n", "icuuc", "icudata"], Some("c++")),
+            TargetOs::Unix => (["icui18n", "icuuc", "icudata"], Some("stdc++")),
+        };
+        for lib in libs {
+            // Leave it to the linker to find the library in the system paths.
+            println!("cargo::rustc-link-lib=static:-bundle={lib}");
+        }
+        if let Some(lib) = cpp_runtime {
+            println!("cargo::rustc-link-lib=dylib={lib}");
+        }
+    }
 }
 
 #[cfg(windows)]
@@ -3,22 +3,38 @@
 
 //! Provides a transparent error type for edit.
 
+use std::borrow::Cow;
 use std::{io, result};
 
 use crate::sys;
 
 pub const APP_ICU_MISSING: Error = Error::new_app(0);
+pub const APP_JSON_INVALID: Error = Error::new_app(1);
+pub const APP_FILE_TOO_LARGE: Error = Error::new_app(2);
+pub const APP_KEY_REMAP_INVALID: Error = Error::new_app(3);
+pub const APP_TRANSLATIONS_INVALID: Error = Error::new_app(4);
 
 /// Edit's transparent `Result` type.
 pub type Result<T> = result::Result<T, Error>;
 
 /// Edit's transparent `Error` type.
 /// Abstracts over system and application errors.
-#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[derive(Debug, Clone, PartialEq, Eq)]
 pub enum Error {
     App(u32),
     Icu(u32),
     Sys(u32),
+    /// Another error, along with a description of what was being done.
+    /// See [`Error::context`].
+    Context(Box<ErrorContext>),
+}
+
+/// See [`Error::Context`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ErrorContext {
+    /// A user-facing, and thus localized, description like "Couldn't save foo.txt".
+    pub message: Cow<'static, str>,
+    pub source: Error,
 }
 
 impl Error {
@@ -33,6 +49,52 @@ impl Error {
     pub const fn new_sys(code: u32) -> Self {
         Self::Sys(code)
     }
+
+    /// Wraps the error with a description of what was being done when it occurred.
+    /// Contexts can be nested, from the operation down to the path that failed.
+    pub fn context(self, message: impl Into<Cow<'static, str>>) -> Self {
+        Self::Context(Box::new(ErrorContext { message: message.into(), source: self }))
+    }
+
+    /// Returns the underlying error without any context.
+    /// That's the one to compare against the `APP_*` constants, for instance.
+    pub fn root(&self) -> &Self {
+        let mut err = self;
+        while let Self::Context(ctx) = err {
+            err = &ctx.source;
+        }
+        err
+    }
+
+    /// Iterates over the context messages, from the outermost to the innermost one.
+    pub fn contexts(&self) -> impl Iterator<Item = &str> {
+        let mut err = self;
+        std::iter::from_fn(move || match err {
+            Self::Context(ctx) => {
+                err = &ctx.source;
+                Some(&*ctx.message)
+            }
+            _ => None,
+        })
+    }
+}
+
+/// Adds [`Error::context`] to [`Result`]s.
+pub trait ResultExt<T> {
+    fn context(self, message: impl Into<Cow<'static, str>>) -> Result<T>;
+
+    /// Like [`ResultExt::context`], but only creates the message if there's an error.
+    fn with_context<S: Into<Cow<'static, str>>>(self, f: impl FnOnce() -> S) -> Result<T>;
+}
+
+impl<T, E: Into<Error>> ResultExt<T> for result::Result<T, E> {
+    fn context(self, message: impl Into<Cow<'static, str>>) -> Result<T> {
+        self.map_err(|err| err.into().context(message))
+    }
+
+    fn with_context<S: Into<Cow<'static, str>>>(self, f: impl FnOnce() -> S) -> Result<T> {
+        self.map_err(|err| err.into().context(f()))
+    }
 }
 
 impl From<io::Error> for Error {
@@ -40,3 +102,18 @@ impl From<io::Error> for Error {
         sys::io_error_to_apperr(err)
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_context() {
+        let res: Result<()> = Err(APP_FILE_TOO_LARGE);
+        let err = res.context("inner").with_context(|| format!("outer {}", 1)).unwrap_err();
+        assert_eq!(err.root(), &APP_FILE_TOO_LARGE);
+        assert_eq!(err.contexts().collect::<Vec<_>>(), ["outer 1", "inner"]);
+        assert!(matches!(err.root(), &APP_FILE_TOO_LARGE));
+        assert_eq!(APP_JSON_INVALID.contexts().count(), 0);
+    }
+}
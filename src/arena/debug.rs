@@ -103,6 +103,11 @@ impl Arena {
         unsafe { self.delegate_target().reset(to) }
     }
 
+    #[cfg(feature = "debug-arena-stats")]
+    pub fn stats(&self) -> super::ArenaStats {
+        self.delegate_target_unchecked().stats()
+    }
+
     pub fn alloc_uninit<T>(&self) -> &mut MaybeUninit<T> {
         self.delegate_target().alloc_uninit()
     }
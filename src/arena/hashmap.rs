@@ -0,0 +1,228 @@
+// Copyright (c) Microsoft Corporation.
+// Licensed under the MIT License.
+
+use std::borrow::Borrow;
+use std::hash::{Hash, Hasher};
+use std::mem;
+
+use super::Arena;
+use crate::hash::WyHash;
+
+/// A hash map, because `std` lacks allocator support for [`std::collections::HashMap`].
+///
+/// It's meant for short-lived lookups, like the ones made during a single frame,
+/// and so it's deliberately simple: Open addressing with linear probing,
+/// a load factor of at most 50%, and no support for removing entries.
+/// Since arenas can't free memory, growing it wastes the old slots.
+/// Use [`ArenaHashMap::with_capacity_in`] if you know the size in advance.
+pub struct ArenaHashMap<'a, K, V> {
+    slots: Vec<Option<(u64, K, V)>, &'a Arena>,
+    len: usize,
+}
+
+impl<'a, K: Hash + Eq, V> ArenaHashMap<'a, K, V> {
+    /// Creates a new, empty [`ArenaHashMap`] in the given arena.
+    #[must_use]
+    pub const fn new_in(arena: &'a Arena) -> Self {
+        Self { slots: Vec::new_in(arena), len: 0 }
+    }
+
+    /// Creates an [`ArenaHashMap`] that can hold `capacity` entries without growing.
+    #[must_use]
+    pub fn with_capacity_in(capacity: usize, arena: &'a Arena) -> Self {
+        let mut map = Self::new_in(arena);
+        if capacity > 0 {
+            map.resize((2 * capacity).next_power_of_two());
+        }
+        map
+    }
+
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    pub fn contains_key<Q>(&self, key: &Q) -> bool
+    where
+        K: Borrow<Q>,
+        Q: Hash + Eq + ?Sized,
+    {
+        self.get(key).is_some()
+    }
+
+    pub fn get<Q>(&self, key: &Q) -> Option<&V>
+    where
+        K: Borrow<Q>,
+        Q: Hash + Eq + ?Sized,
+    {
+        let idx = self.find(hash_key(key), key).ok()?;
+        self.slots[idx].as_ref().map(|(_, _, v)| v)
+    }
+
+    pub fn get_mut<Q>(&mut self, key: &Q) -> Option<&mut V>
+    where
+        K: Borrow<Q>,
+        Q: Hash + Eq + ?Sized,
+    {
+        let idx = self.find(hash_key(key), key).ok()?;
+        self.slots[idx].as_mut().map(|(_, _, v)| v)
+    }
+
+    /// Inserts `value` for `key`. Returns the previous value, if any.
+    pub fn insert(&mut self, key: K, value: V) -> Option<V> {
+        let hash = hash_key(&key);
+        match self.find(hash, &key) {
+            Ok(idx) => {
+                let (_, _, v) = self.slots[idx].as_mut().unwrap();
+                Some(mem::replace(v, value))
+            }
+            Err(_) => {
+                let idx = self.vacant_slot(hash);
+                self.slots[idx] = Some((hash, key, value));
+                self.len += 1;
+                None
+            }
+        }
+    }
+
+    /// Returns the value for `key`, inserting the result of `f` first if it's missing.
+    pub fn get_or_insert_with(&mut self, key: K, f: impl FnOnce() -> V) -> &mut V {
+        let hash = hash_key(&key);
+        let idx = match self.find(hash, &key) {
+            Ok(idx) => idx,
+            Err(_) => {
+                let idx = self.vacant_slot(hash);
+                self.slots[idx] = Some((hash, key, f()));
+                self.len += 1;
+                idx
+            }
+        };
+        self.slots[idx].as_mut().map(|(_, _, v)| v).unwrap()
+    }
+
+    /// Iterates over all entries in an unspecified order.
+    pub fn iter(&self) -> impl Iterator<Item = (&K, &V)> {
+        self.slots.iter().flatten().map(|(_, k, v)| (k, v))
+    }
+
+    pub fn clear(&mut self) {
+        self.slots.fill_with(|| None);
+        self.len = 0;
+    }
+
+    /// Returns `Ok` with the index of the slot holding `key`,
+    /// or `Err` with the index of the empty slot that ended the search.
+    fn find<Q>(&self, hash: u64, key: &Q) -> Result<usize, usize>
+    where
+        K: Borrow<Q>,
+        Q: Eq + ?Sized,
+    {
+        if self.slots.is_empty() {
+            return Err(0);
+        }
+
+        let mask = self.slots.len() - 1;
+        let mut idx = hash as usize & mask;
+
+        loop {
+            match &self.slots[idx] {
+                None => return Err(idx),
+                Some((h, k, _)) if *h == hash && k.borrow() == key => return Ok(idx),
+                _ => idx = (idx + 1) & mask,
+            }
+        }
+    }
+
+    /// Returns the index of an empty slot for a new entry, growing the map if needed.
+    fn vacant_slot(&mut self, hash: u64) -> usize {
+        if 2 * (self.len + 1) > self.slots.len() {
+            self.resize((2 * self.slots.len()).max(8));
+        }
+
+        let mask = self.slots.len() - 1;
+        let mut idx = hash as usize & mask;
+        while self.slots[idx].is_some() {
+            idx = (idx + 1) & mask;
+        }
+        idx
+    }
+
+    fn resize(&mut self, capacity: usize) {
+        debug_assert!(capacity.is_power_of_two());
+
+        let arena = *self.slots.allocator();
+        let mut slots = Vec::with_capacity_in(capacity, arena);
+        slots.resize_with(capacity, || None);
+        let old = mem::replace(&mut self.slots, slots);
+
+        let mask = capacity - 1;
+        for (hash, key, value) in old.into_iter().flatten() {
+            let mut idx = hash as usize & mask;
+            while self.slots[idx].is_some() {
+                idx = (idx + 1) & mask;
+            }
+            self.slots[idx] = Some((hash, key, value));
+        }
+    }
+}
+
+fn hash_key<Q: Hash + ?Sized>(key: &Q) -> u64 {
+    let mut hasher = WyHash::default();
+    key.hash(&mut hasher);
+    hasher.finish()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_hashmap() {
+        let arena = Arena::new(1024 * 1024).unwrap();
+        let mut map = ArenaHashMap::new_in(&arena);
+        assert!(map.is_empty());
+        assert_eq!(map.get("missing"), None);
+
+        for i in 0..1000 {
+            assert_eq!(map.insert(format!("key{i}"), i), None);
+        }
+        assert_eq!(map.len(), 1000);
+        assert_eq!(map.insert("key42".to_string(), 4242), Some(42));
+        assert_eq!(map.len(), 1000);
+
+        for i in 0..1000 {
+            let expected = if i == 42 { 4242 } else { i };
+            assert_eq!(map.get(format!("key{i}").as_str()), Some(&expected));
+        }
+        assert!(!map.contains_key("key1000"));
+
+        *map.get_mut("key0").unwrap() += 1;
+        *map.get_or_insert_with("key0".to_string(), || 100) += 1;
+        *map.get_or_insert_with("new".to_string(), || 100) += 1;
+        assert_eq!(map.get("key0"), Some(&2));
+        assert_eq!(map.get("new"), Some(&101));
+        assert_eq!(
+            map.iter().map(|(_, v)| v).sum::<i32>(),
+            (0..1000).sum::<i32>() + 4200 + 2 + 101
+        );
+
+        map.clear();
+        assert!(map.is_empty());
+        assert_eq!(map.get("key1"), None);
+    }
+
+    #[test]
+    fn test_hashmap_with_capacity() {
+        let arena = Arena::new(1024 * 1024).unwrap();
+        let mut map = ArenaHashMap::with_capacity_in(100, &arena);
+        let slots = map.slots.len();
+        for i in 0u64..100 {
+            map.insert(i, ());
+        }
+        assert_eq!(map.slots.len(), slots);
+        assert!(map.contains_key(&99));
+    }
+}
@@ -5,13 +5,21 @@
 
 #[cfg(debug_assertions)]
 mod debug;
+mod hashmap;
 mod release;
 mod scratch;
+#[cfg(feature = "debug-arena-stats")]
+mod stats;
 mod string;
 
 #[cfg(all(not(doc), debug_assertions))]
 pub use self::debug::Arena;
+pub use self::hashmap::ArenaHashMap;
 #[cfg(any(doc, not(debug_assertions)))]
 pub use self::release::Arena;
+#[cfg(feature = "debug-arena-stats")]
+pub use self::scratch::scratch_arena_stats;
 pub use self::scratch::{ScratchArena, init, scratch_arena};
+#[cfg(feature = "debug-arena-stats")]
+pub use self::stats::ArenaStats;
 pub use self::string::ArenaString;
@@ -49,6 +49,9 @@ pub struct Arena {
     /// See [`super::debug`], which uses this for borrow tracking.
     #[cfg(debug_assertions)]
     pub(super) borrows: Cell<usize>,
+
+    #[cfg(feature = "debug-arena-stats")]
+    stats: Cell<super::ArenaStats>,
 }
 
 impl Arena {
@@ -61,6 +64,9 @@ impl Arena {
 
             #[cfg(debug_assertions)]
             borrows: Cell::new(0),
+
+            #[cfg(feature = "debug-arena-stats")]
+            stats: Cell::new(super::ArenaStats::new()),
         }
     }
 
@@ -76,6 +82,9 @@ impl Arena {
 
             #[cfg(debug_assertions)]
             borrows: Cell::new(0),
+
+            #[cfg(feature = "debug-arena-stats")]
+            stats: Cell::new(super::ArenaStats::new()),
         })
     }
 
@@ -87,6 +96,22 @@ impl Arena {
         self.offset.get()
     }
 
+    #[cfg(feature = "debug-arena-stats")]
+    pub fn stats(&self) -> super::ArenaStats {
+        super::ArenaStats { live_bytes: self.offset.get(), ..self.stats.get() }
+    }
+
+    #[cfg(feature = "debug-arena-stats")]
+    pub(super) fn record_scope(
+        &self,
+        bytes: usize,
+        location: &'static std::panic::Location<'static>,
+    ) {
+        let mut stats = self.stats.get();
+        stats.record_scope(bytes, location);
+        self.stats.set(stats);
+    }
+
     /// "Deallocates" the memory in the arena down to the given offset.
     ///
     /// # Safety
@@ -116,6 +141,13 @@ impl Arena {
         let beg = (offset + alignment - 1) & !(alignment - 1);
         let end = beg + bytes;
 
+        #[cfg(feature = "debug-arena-stats")]
+        {
+            let mut stats = self.stats.get();
+            stats.record_alloc(bytes, end);
+            self.stats.set(stats);
+        }
+
         if end > commit {
             return self.alloc_raw_bump(beg, end);
         }
@@ -2,7 +2,11 @@
 // Licensed under the MIT License.
 
 use std::ops::Deref;
+#[cfg(feature = "debug-arena-stats")]
+use std::panic::Location;
 
+#[cfg(feature = "debug-arena-stats")]
+use super::ArenaStats;
 #[cfg(debug_assertions)]
 use super::debug;
 use super::{Arena, release};
@@ -16,6 +20,8 @@ use crate::helpers::*;
 pub struct ScratchArena<'a> {
     arena: debug::Arena,
     offset: usize,
+    #[cfg(feature = "debug-arena-stats")]
+    location: &'static Location<'static>,
     _phantom: std::marker::PhantomData<&'a ()>,
 }
 
@@ -23,26 +29,57 @@ pub struct ScratchArena<'a> {
 pub struct ScratchArena<'a> {
     arena: &'a Arena,
     offset: usize,
+    #[cfg(feature = "debug-arena-stats")]
+    location: &'static Location<'static>,
 }
 
 #[cfg(debug_assertions)]
 impl<'a> ScratchArena<'a> {
+    #[track_caller]
     fn new(arena: &'a release::Arena) -> Self {
         let offset = arena.offset();
-        ScratchArena { arena: Arena::delegated(arena), _phantom: std::marker::PhantomData, offset }
+        ScratchArena {
+            arena: Arena::delegated(arena),
+            offset,
+            #[cfg(feature = "debug-arena-stats")]
+            location: Location::caller(),
+            _phantom: std::marker::PhantomData,
+        }
+    }
+
+    #[cfg(feature = "debug-arena-stats")]
+    fn target(&self) -> &release::Arena {
+        self.arena.delegate_target_unchecked()
     }
 }
 
 #[cfg(not(debug_assertions))]
 impl<'a> ScratchArena<'a> {
+    #[track_caller]
     fn new(arena: &'a release::Arena) -> Self {
         let offset = arena.offset();
-        ScratchArena { arena, offset }
+        ScratchArena {
+            arena,
+            offset,
+            #[cfg(feature = "debug-arena-stats")]
+            location: Location::caller(),
+        }
+    }
+
+    #[cfg(feature = "debug-arena-stats")]
+    fn target(&self) -> &release::Arena {
+        self.arena
     }
 }
 
 impl Drop for ScratchArena<'_> {
     fn drop(&mut self) {
+        #[cfg(feature = "debug-arena-stats")]
+        {
+            let target = self.target();
+            target.record_scope(target.offset().saturating_sub(self.offset), self.location);
+        }
+
         unsafe { self.arena.reset(self.offset) };
     }
 }
@@ -105,6 +142,7 @@ mod single_threaded {
     ///
     /// If your function takes an [`Arena`] argument, you **MUST** pass it to `scratch_arena` as `Some(&arena)`.
     #[allow(dead_code)]
+    #[track_caller]
     pub fn scratch_arena(conflict: Option<&Arena>) -> ScratchArena<'static> {
         unsafe {
             #[cfg(debug_assertions)]
@@ -115,6 +153,13 @@ mod single_threaded {
             ScratchArena::new(arena)
         }
     }
+
+    /// Returns the combined allocation statistics of both scratch arenas.
+    #[cfg(feature = "debug-arena-stats")]
+    #[allow(dead_code)]
+    pub fn scratch_arena_stats() -> ArenaStats {
+        unsafe { S_SCRATCH[0].stats().merge(S_SCRATCH[1].stats()) }
+    }
 }
 
 mod multi_threaded {
@@ -136,6 +181,7 @@ mod multi_threaded {
 
     /// See `single_threaded::scratch_arena`.
     #[allow(dead_code)]
+    #[track_caller]
     pub fn scratch_arena(conflict: Option<&Arena>) -> ScratchArena<'static> {
         #[cfg(debug_assertions)]
         let conflict = conflict.map(|a| a.delegate_target_unchecked());
@@ -147,14 +193,23 @@ mod multi_threaded {
             }
         }
 
-        S_SCRATCH.with(|s| {
+        let arena = S_SCRATCH.with(|s| {
             let index = ptr::eq(opt_ptr(conflict), s[0].as_ptr()) as usize;
             let arena = unsafe { &*s[index].as_ptr() };
             if arena.is_empty() {
                 init(s);
             }
-            ScratchArena::new(arena)
-        })
+            arena as *const release::Arena
+        });
+        // Outside of the closure, so that `#[track_caller]` sees our caller.
+        ScratchArena::new(unsafe { &*arena })
+    }
+
+    /// Returns the combined allocation statistics of this thread's scratch arenas.
+    #[cfg(feature = "debug-arena-stats")]
+    #[allow(dead_code)]
+    pub fn scratch_arena_stats() -> ArenaStats {
+        S_SCRATCH.with(|s| unsafe { (*s[0].as_ptr()).stats().merge((*s[1].as_ptr()).stats()) })
     }
 }
 
@@ -0,0 +1,98 @@
+// Copyright (c) Microsoft Corporation.
+// Licensed under the MIT License.
+
+//! Allocation statistics, enabled via the `debug-arena-stats` feature.
+//! They help with tuning arena capacities and with finding code that
+//! allocates unexpectedly much per frame.
+
+use std::panic::Location;
+
+/// Allocation statistics of an [`super::Arena`].
+#[derive(Clone, Copy)]
+pub struct ArenaStats {
+    /// Number of allocations so far.
+    pub allocations: usize,
+    /// Number of bytes allocated so far. Resets don't subtract from it.
+    pub allocated_bytes: usize,
+    /// The current offset. Outside of any [`super::ScratchArena`] scope
+    /// it should be 0 for scratch arenas. Anything else is a leak.
+    pub live_bytes: usize,
+    /// The highest offset the arena ever reached, i.e. the capacity it needs.
+    pub high_water_mark: usize,
+    /// The most bytes allocated within a single [`super::ScratchArena`] scope.
+    pub largest_scope: usize,
+    /// Where the scope with the most bytes was created.
+    pub largest_scope_location: Option<&'static Location<'static>>,
+}
+
+impl ArenaStats {
+    pub(super) const fn new() -> Self {
+        Self {
+            allocations: 0,
+            allocated_bytes: 0,
+            live_bytes: 0,
+            high_water_mark: 0,
+            largest_scope: 0,
+            largest_scope_location: None,
+        }
+    }
+
+    #[inline]
+    pub(super) fn record_alloc(&mut self, bytes: usize, end: usize) {
+        self.allocations += 1;
+        self.allocated_bytes += bytes;
+        self.high_water_mark = self.high_water_mark.max(end);
+    }
+
+    pub(super) fn record_scope(&mut self, bytes: usize, location: &'static Location<'static>) {
+        if bytes > self.largest_scope {
+            self.largest_scope = bytes;
+            self.largest_scope_location = Some(location);
+        }
+    }
+
+    /// Combines the statistics of two arenas.
+    pub fn merge(self, other: Self) -> Self {
+        let (largest_scope, largest_scope_location) = if self.largest_scope >= other.largest_scope {
+            (self.largest_scope, self.largest_scope_location)
+        } else {
+            (other.largest_scope, other.largest_scope_location)
+        };
+        Self {
+            allocations: self.allocations + other.allocations,
+            allocated_bytes: self.allocated_bytes + other.allocated_bytes,
+            live_bytes: self.live_bytes + other.live_bytes,
+            high_water_mark: self.high_water_mark.max(other.high_water_mark),
+            largest_scope,
+            largest_scope_location,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::{Arena, scratch_arena, scratch_arena_stats};
+
+    #[test]
+    fn test_arena_stats() {
+        let arena = Arena::new(1024 * 1024).unwrap();
+        arena.alloc_uninit_slice::<u8>(100);
+        arena.alloc_uninit::<u64>();
+        let stats = arena.stats();
+        assert_eq!(stats.allocations, 2);
+        assert_eq!(stats.allocated_bytes, 108);
+        assert_eq!(stats.live_bytes, 112);
+        assert_eq!(stats.high_water_mark, 112);
+
+        let line = line!() + 2;
+        {
+            let scratch = scratch_arena(None);
+            scratch.alloc_uninit_slice::<u8>(1000 * 1000);
+        }
+        let stats = scratch_arena_stats();
+        let location = stats.largest_scope_location.unwrap();
+        assert!(stats.largest_scope >= 1000 * 1000);
+        assert_eq!((location.file(), location.line()), (file!(), line));
+        assert_eq!(stats.live_bytes, 0);
+    }
+}
@@ -3,9 +3,30 @@
 
 //! Base64 facilities.
 
-use crate::arena::ArenaString;
+use crate::arena::{ArenaString, scratch_arena};
 
 const CHARSET: [u8; 64] = *b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+const CHARSET_URL_SAFE: [u8; 64] =
+    *b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789-_";
+
+/// The two alphabets of RFC 4648. They only differ in the last two characters.
+#[derive(Default, Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Alphabet {
+    /// Uses `+` and `/`.
+    #[default]
+    Standard,
+    /// Uses `-` and `_`, so that the result can be used in URLs and file names.
+    UrlSafe,
+}
+
+impl Alphabet {
+    const fn charset(self) -> &'static [u8; 64] {
+        match self {
+            Self::Standard => &CHARSET,
+            Self::UrlSafe => &CHARSET_URL_SAFE,
+        }
+    }
+}
 
 /// One aspect of base64 is that the encoded length can be
 /// calculated accurately in advance, which is what this returns.
@@ -14,8 +35,21 @@ pub fn encode_len(src_len: usize) -> usize {
     src_len.div_ceil(3) * 4
 }
 
+/// Same as [`encode_len`], but for encoding without padding.
+#[inline]
+pub fn encode_len_unpadded(src_len: usize) -> usize {
+    (src_len * 4).div_ceil(3)
+}
+
 /// Encodes the given bytes as base64 and appends them to the destination string.
 pub fn encode(dst: &mut ArenaString, src: &[u8]) {
+    encode_with(dst, src, Alphabet::Standard, true);
+}
+
+/// Like [`encode`], but with the given `alphabet` and optionally without the `=` padding.
+pub fn encode_with(dst: &mut ArenaString, src: &[u8], alphabet: Alphabet, padding: bool) {
+    let charset = alphabet.charset();
+
     unsafe {
         let mut inp = src.as_ptr();
         let mut remaining = src.len();
@@ -23,6 +57,7 @@ pub fn encode(dst: &mut ArenaString, src: &[u8]) {
 
         let out_len = encode_len(src.len());
         // ... we can then use this fact to reserve space all at once.
+        // Even without padding, because we write it regardless and then cut it off.
         dst.reserve(out_len);
 
         // SAFETY: Getting a pointer to the reserved space is only safe
@@ -39,13 +74,13 @@ pub fn encode(dst: &mut ArenaString, src: &[u8]) {
                 inp = inp.add(3);
                 remaining -= 3;
 
-                *out = CHARSET[(val >> 26) as usize];
+                *out = charset[(val >> 26) as usize];
                 out = out.add(1);
-                *out = CHARSET[(val >> 20) as usize & 0x3f];
+                *out = charset[(val >> 20) as usize & 0x3f];
                 out = out.add(1);
-                *out = CHARSET[(val >> 14) as usize & 0x3f];
+                *out = charset[(val >> 14) as usize & 0x3f];
                 out = out.add(1);
-                *out = CHARSET[(val >> 8) as usize & 0x3f];
+                *out = charset[(val >> 8) as usize & 0x3f];
                 out = out.add(1);
             }
 
@@ -60,27 +95,148 @@ pub fn encode(dst: &mut ArenaString, src: &[u8]) {
 
             if remaining >= 3 {
                 in2 = inp.add(2).read() as usize;
-                *out.add(3) = CHARSET[in2 & 0x3f];
+                *out.add(3) = charset[in2 & 0x3f];
             }
 
             if remaining >= 2 {
                 in1 = inp.add(1).read() as usize;
-                *out.add(2) = CHARSET[(in1 << 2 | in2 >> 6) & 0x3f];
+                *out.add(2) = charset[(in1 << 2 | in2 >> 6) & 0x3f];
             }
 
             let in0 = inp.add(0).read() as usize;
-            *out.add(1) = CHARSET[(in0 << 4 | in1 >> 4) & 0x3f];
-            *out.add(0) = CHARSET[in0 >> 2];
+            *out.add(1) = charset[(in0 << 4 | in1 >> 4) & 0x3f];
+            *out.add(0) = charset[in0 >> 2];
+        }
+
+        let len = if padding { out_len } else { encode_len_unpadded(src.len()) };
+        dst.set_len(dst.len() + len);
+    }
+}
+
+/// A base64 encoder for input that arrives in pieces of arbitrary length,
+/// with the same result as encoding it all at once.
+///
+/// Since the output only ever contains full groups of 4 characters (except for
+/// the end), it can be split up into separate sequences after every [`Encoder::update`].
+pub struct Encoder {
+    alphabet: Alphabet,
+    padding: bool,
+    pending: [u8; 3],
+    pending_len: usize,
+}
+
+impl Encoder {
+    pub fn new(alphabet: Alphabet, padding: bool) -> Self {
+        Self { alphabet, padding, pending: [0; 3], pending_len: 0 }
+    }
+
+    /// Encodes as much of `src` as possible and appends it to `dst`.
+    /// Up to 2 bytes are held back until the next call.
+    pub fn update(&mut self, dst: &mut ArenaString, mut src: &[u8]) {
+        if self.pending_len > 0 {
+            let take = (3 - self.pending_len).min(src.len());
+            self.pending[self.pending_len..self.pending_len + take].copy_from_slice(&src[..take]);
+            self.pending_len += take;
+            src = &src[take..];
+
+            if self.pending_len < 3 {
+                return;
+            }
+            encode_with(dst, &self.pending, self.alphabet, self.padding);
+            self.pending_len = 0;
+        }
+
+        let full = src.len() / 3 * 3;
+        encode_with(dst, &src[..full], self.alphabet, self.padding);
+
+        let rest = &src[full..];
+        self.pending[..rest.len()].copy_from_slice(rest);
+        self.pending_len = rest.len();
+    }
+
+    /// Encodes the remaining bytes, if any, and appends them to `dst`.
+    pub fn finish(self, dst: &mut ArenaString) {
+        encode_with(dst, &self.pending[..self.pending_len], self.alphabet, self.padding);
+    }
+}
+
+const INVALID: u8 = 0xff;
+
+const fn decode_lut(charset: &[u8; 64]) -> [u8; 256] {
+    let mut lut = [INVALID; 256];
+    let mut i = 0;
+    while i < charset.len() {
+        lut[charset[i] as usize] = i as u8;
+        i += 1;
+    }
+    lut
+}
+
+/// Decodes the given base64 string and appends the result to the destination.
+///
+/// The padding is optional, but anything other than the base64 alphabet
+/// is rejected, in which case `false` is returned and `dst` is left as-is.
+pub fn decode(dst: &mut Vec<u8>, src: &[u8]) -> bool {
+    decode_with(dst, src, Alphabet::Standard)
+}
+
+/// Like [`decode`], but ignores ASCII whitespace, like the line breaks
+/// that MIME and PEM insert every 64 to 76 characters.
+pub fn decode_lenient(dst: &mut Vec<u8>, src: &[u8], alphabet: Alphabet) -> bool {
+    if !src.iter().any(u8::is_ascii_whitespace) {
+        return decode_with(dst, src, alphabet);
+    }
+
+    let scratch = scratch_arena(None);
+    let mut stripped = Vec::with_capacity_in(src.len(), &*scratch);
+    stripped.extend(src.iter().copied().filter(|b| !b.is_ascii_whitespace()));
+    decode_with(dst, &stripped, alphabet)
+}
+
+/// Like [`decode`], but with the given `alphabet`.
+pub fn decode_with(dst: &mut Vec<u8>, src: &[u8], alphabet: Alphabet) -> bool {
+    const LUT_STANDARD: [u8; 256] = decode_lut(&CHARSET);
+    const LUT_URL_SAFE: [u8; 256] = decode_lut(&CHARSET_URL_SAFE);
+    let lut = match alphabet {
+        Alphabet::Standard => &LUT_STANDARD,
+        Alphabet::UrlSafe => &LUT_URL_SAFE,
+    };
+
+    let src = match src {
+        [rest @ .., b'=', b'='] | [rest @ .., b'='] => rest,
+        _ => src,
+    };
+    if src.len() % 4 == 1 {
+        return false;
+    }
+
+    let len_before = dst.len();
+    dst.reserve(src.len() / 4 * 3 + 2);
+
+    for chunk in src.chunks(4) {
+        let mut val = 0u32;
+        for &c in chunk {
+            let v = lut[c as usize];
+            if v == INVALID {
+                dst.truncate(len_before);
+                return false;
+            }
+            val = val << 6 | v as u32;
         }
 
-        dst.set_len(dst.len() + out_len);
+        // Left-align the 6-bit groups of a trailing, shorter chunk.
+        val <<= 6 * (4 - chunk.len()) as u32;
+        let bytes = val.to_be_bytes();
+        dst.extend_from_slice(&bytes[1..chunk.len()]);
     }
+
+    true
 }
 
 #[cfg(test)]
 mod tests {
-    use super::encode;
-    use crate::arena::{Arena, ArenaString};
+    use super::*;
+    use crate::arena::Arena;
 
     #[test]
     fn test_basic() {
@@ -118,4 +274,80 @@ mod tests {
         assert_eq!(enc(b"abcdefghijklmNOPQRSTUVWXY"), "YWJjZGVmZ2hpamtsbU5PUFFSU1RVVldYWQ==");
         assert_eq!(enc(b"abcdefghijklmNOPQRSTUVWXYZ"), "YWJjZGVmZ2hpamtsbU5PUFFSU1RVVldYWVo=");
     }
+
+    #[test]
+    fn test_decode() {
+        let arena = Arena::new(4 * 1024).unwrap();
+        let dec = |s: &str| {
+            let mut dst = Vec::new();
+            decode(&mut dst, s.as_bytes()).then_some(dst)
+        };
+
+        let input = b"abcdefghijklmNOPQRSTUVWXYZ\x00\xff";
+        for len in 0..input.len() {
+            let mut enc = ArenaString::new_in(&arena);
+            encode(&mut enc, &input[..len]);
+            assert_eq!(dec(&enc).as_deref(), Some(&input[..len]));
+            assert_eq!(dec(enc.trim_end_matches('=')).as_deref(), Some(&input[..len]));
+        }
+
+        assert_eq!(dec("YQ"), Some(b"a".to_vec()));
+        assert_eq!(dec("Y"), None);
+        assert_eq!(dec("YW J"), None);
+        assert_eq!(dec("YQ==="), None);
+    }
+
+    #[test]
+    fn test_url_safe() {
+        let arena = Arena::new(4 * 1024).unwrap();
+        let input = b"\xfb\xff\xbf?";
+        for (alphabet, padding, expected) in [
+            (Alphabet::Standard, true, "+/+/Pw=="),
+            (Alphabet::Standard, false, "+/+/Pw"),
+            (Alphabet::UrlSafe, true, "-_-_Pw=="),
+            (Alphabet::UrlSafe, false, "-_-_Pw"),
+        ] {
+            let mut enc = ArenaString::new_in(&arena);
+            encode_with(&mut enc, input, alphabet, padding);
+            assert_eq!(enc, expected);
+
+            let mut dec = Vec::new();
+            assert!(decode_with(&mut dec, enc.as_bytes(), alphabet));
+            assert_eq!(dec, input);
+        }
+
+        assert!(!decode_with(&mut Vec::new(), b"-_-_", Alphabet::Standard));
+        assert!(!decode_with(&mut Vec::new(), b"+/+/", Alphabet::UrlSafe));
+        assert_eq!(encode_len_unpadded(4), 6);
+    }
+
+    #[test]
+    fn test_encoder() {
+        let arena = Arena::new(64 * 1024).unwrap();
+        let input: Vec<u8> = (0..=255).collect();
+        let mut expected = ArenaString::new_in(&arena);
+        encode(&mut expected, &input);
+
+        for step in 1..8 {
+            let mut actual = ArenaString::new_in(&arena);
+            let mut encoder = Encoder::new(Alphabet::Standard, true);
+            for chunk in input.chunks(step) {
+                encoder.update(&mut actual, chunk);
+                assert_eq!(actual.len() % 4, 0);
+            }
+            encoder.finish(&mut actual);
+            assert_eq!(actual, expected);
+        }
+    }
+
+    #[test]
+    fn test_decode_lenient() {
+        let dec = |s: &str| {
+            let mut dst = Vec::new();
+            decode_lenient(&mut dst, s.as_bytes(), Alphabet::Standard).then_some(dst)
+        };
+        assert_eq!(dec("YWJj\r\nZGVm\nZw==\n"), Some(b"abcdefg".to_vec()));
+        assert_eq!(dec(" Y W J j "), Some(b"abc".to_vec()));
+        assert_eq!(dec("YW-J"), None);
+    }
 }
@@ -0,0 +1,97 @@
+// Copyright (c) Microsoft Corporation.
+// Licensed under the MIT License.
+
+//! The user's settings, stored in `settings.conf` in the config directory.
+//!
+//! It's a list of `key = value` lines, simple enough to be edited by hand.
+//! Empty lines and those starting with `#` are ignored, but preserved when saving.
+
+use std::fs;
+use std::io::Write as _;
+use std::path::PathBuf;
+
+use edit::sys::{self, KnownDir};
+use edit::{apperr, path};
+
+#[derive(Default)]
+pub struct Config {
+    /// Where to save the settings. `None` if there's no config directory.
+    path: Option<PathBuf>,
+    lines: Vec<String>,
+}
+
+impl Config {
+    /// Reads the settings. A missing or unreadable file results in the defaults.
+    pub fn load() -> Self {
+        let path = path::app_dir(KnownDir::Config).map(|dir| dir.join("settings.conf"));
+        let text = path.as_deref().and_then(|p| fs::read_to_string(p).ok()).unwrap_or_default();
+        Self::from_text(path, &text)
+    }
+
+    fn from_text(path: Option<PathBuf>, text: &str) -> Self {
+        Self { path, lines: text.lines().map(str::to_string).collect() }
+    }
+
+    pub fn get(&self, key: &str) -> Option<&str> {
+        self.lines
+            .iter()
+            .find_map(|line| parse_line(line).filter(|&(k, _)| k == key))
+            .map(|(_, v)| v)
+    }
+
+    /// Sets `key` to `value`, or removes it if it's `None`.
+    /// The change only makes it to disk with [`Config::save`].
+    pub fn set(&mut self, key: &str, value: Option<&str>) {
+        let idx =
+            self.lines.iter().position(|line| parse_line(line).is_some_and(|(k, _)| k == key));
+        match (idx, value) {
+            (Some(idx), Some(value)) => self.lines[idx] = format!("{key} = {value}"),
+            (Some(idx), None) => _ = self.lines.remove(idx),
+            (None, Some(value)) => self.lines.push(format!("{key} = {value}")),
+            (None, None) => {}
+        }
+    }
+
+    pub fn save(&self) -> apperr::Result<()> {
+        let Some(path) = &self.path else {
+            return Ok(());
+        };
+        if let Some(dir) = path.parent() {
+            fs::create_dir_all(dir)?;
+        }
+        sys::safe_write(path, |file| {
+            for line in &self.lines {
+                writeln!(file, "{line}")?;
+            }
+            Ok(())
+        })
+    }
+}
+
+fn parse_line(line: &str) -> Option<(&str, &str)> {
+    let line = line.trim();
+    if line.starts_with('#') {
+        return None;
+    }
+    let (key, value) = line.split_once('=')?;
+    Some((key.trim_end(), value.trim_start()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_get_set() {
+        let mut config = Config::from_text(None, "# comment = no\n\n  language=de \nfoo = a = b\n");
+        assert_eq!(config.get("comment"), None);
+        assert_eq!(config.get("# comment"), None);
+        assert_eq!(config.get("language"), Some("de"));
+        assert_eq!(config.get("foo"), Some("a = b"));
+
+        config.set("language", Some("fr"));
+        config.set("foo", None);
+        config.set("bar", Some("1"));
+        assert_eq!(config.lines, ["# comment = no", "", "language = fr", "bar = 1"]);
+    }
+}
@@ -3,13 +3,20 @@
 
 use std::collections::LinkedList;
 use std::ffi::OsStr;
-use std::fs::File;
+use std::fs::{self, File};
+use std::io::Read as _;
 use std::path::{Path, PathBuf};
+use std::ptr;
+use std::time::SystemTime;
 
+use edit::apperr::{self, ResultExt as _};
 use edit::buffer::{RcTextBuffer, TextBuffer};
-use edit::helpers::{CoordType, Point};
-use edit::{apperr, path, sys};
+use edit::hash::{HashAlgorithm, checksum, hash_file};
+use edit::helpers::{CoordType, KIBI, Point};
+use edit::{icu, path, sys};
 
+use crate::language::{self, Language};
+use crate::localization::*;
 use crate::state::DisplayablePathBuf;
 
 pub struct Document {
@@ -18,22 +25,51 @@ pub struct Document {
     pub dir: Option<DisplayablePathBuf>,
     pub filename: String,
     pub file_id: Option<sys::FileId>,
+    /// The modification time of the file when it was last read or written.
+    pub modified: Option<SystemTime>,
+    /// A checksum of the file contents when it was last read or written.
+    /// Used to ignore change notifications that didn't actually change anything.
+    pub checksum: Option<u64>,
     pub new_file_counter: usize,
+    /// Detected from the filename, unless the user picked one.
+    pub language: &'static Language,
+    /// Whether the user picked the language, which then sticks even if the file gets renamed.
+    pub language_pinned: bool,
 }
 
+/// The points in a document's life at which the hooks registered
+/// via [`DocumentManager::subscribe`] get to run.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum DocumentEvent {
+    /// The document was created, be it from a file or as a new, untitled one.
+    Open,
+    /// The document is about to be written to disk.
+    BeforeSave,
+    /// The document was written to disk. Its path may have changed ("Save As").
+    AfterSave,
+    /// The document is about to be closed.
+    Close,
+    /// The document became the active one.
+    Focus,
+}
+
+pub type DocumentHook = Box<dyn FnMut(DocumentEvent, &mut Document)>;
+
 impl Document {
-    pub fn save(&mut self, new_path: Option<PathBuf>) -> apperr::Result<()> {
+    fn save(&mut self, new_path: Option<PathBuf>) -> apperr::Result<()> {
         let path = new_path.as_deref().unwrap_or_else(|| self.path.as_ref().unwrap().as_path());
-        let mut file = DocumentManager::open_for_writing(path)?;
 
         {
             let mut tb = self.buffer.borrow_mut();
-            tb.write_file(&mut file)?;
+            sys::safe_write(path, |file| tb.write_file(file))
+                .with_context(|| error_context(LocId::ErrorSaveFile, path))?;
+            self.checksum = Some(tb.file_checksum());
         }
 
         if let Ok(id) = sys::file_id(None, path) {
             self.file_id = Some(id);
         }
+        self.modified = DocumentManager::modified_time(path);
 
         if let Some(path) = new_path {
             self.set_path(path);
@@ -44,41 +80,125 @@ impl Document {
 
     pub fn reread(&mut self, encoding: Option<&'static str>) -> apperr::Result<()> {
         let path = self.path.as_ref().unwrap().as_path();
-        let mut file = DocumentManager::open_for_reading(path)?;
+        let mut file = DocumentManager::open_for_reading(path)
+            .with_context(|| error_context(LocId::ErrorOpenFile, path))?;
 
         {
             let mut tb = self.buffer.borrow_mut();
-            tb.read_file(&mut file, encoding)?;
+            tb.read_file(&mut file, encoding)
+                .with_context(|| error_context(LocId::ErrorOpenFile, path))?;
+            self.checksum = Some(tb.file_checksum());
         }
 
         if let Ok(id) = sys::file_id(None, path) {
             self.file_id = Some(id);
         }
+        self.modified = DocumentManager::modified_time(path);
 
         Ok(())
     }
 
+    /// Whether another program changed the file since it was last read or written.
+    pub fn changed_on_disk(&self) -> bool {
+        let Some(path) = self.path.as_deref() else {
+            return false;
+        };
+        let modified = DocumentManager::modified_time(path);
+        // Only hash the file if the cheap check says it may have changed.
+        modified.is_some() && modified != self.modified && hash_file(0, path).ok() != self.checksum
+    }
+
+    /// Guesses the encoding of the file on disk. See [`icu::detect_encoding`].
+    pub fn detect_encoding(&self) -> Option<&'static str> {
+        let path = self.path.as_deref()?;
+        let file = DocumentManager::open_for_reading(path).ok()?;
+        let mut data = Vec::new();
+        file.take(64 * KIBI as u64).read_to_end(&mut data).ok()?;
+        icu::detect_encoding(&data).first().map(|&(enc, _)| enc)
+    }
+
+    /// Switches to the given language and applies its defaults.
+    /// Whatever the user changed about the ruler or word wrap before is reset.
+    pub fn set_language(&mut self, language: &'static Language) {
+        self.language = language;
+
+        let mut tb = self.buffer.borrow_mut();
+        tb.set_ruler(language.ruler);
+        tb.set_word_wrap(language.word_wrap);
+        if let Some((tabs, width)) = language.indentation
+            && tb.text_length() == 0
+        {
+            tb.set_indent_with_tabs(tabs);
+            tb.set_tab_size(width);
+        }
+    }
+
     fn set_path(&mut self, path: PathBuf) {
         let filename = path.file_name().unwrap_or_default().to_string_lossy().into_owned();
         let dir = path.parent().map(ToOwned::to_owned).unwrap_or_default();
         self.filename = filename;
         self.dir = Some(DisplayablePathBuf::from_path(dir));
+
+        // The new path gets picked up by `file_watch_hook` after the save.
+        if let Some(old) = &self.path {
+            sys::file_unwatch(old);
+        }
         self.path = Some(path);
-        self.update_file_mode();
     }
+}
 
-    fn update_file_mode(&mut self) {
-        let mut tb = self.buffer.borrow_mut();
-        tb.set_ruler(if self.filename == "COMMIT_EDITMSG" { 72 } else { 0 });
+/// Picks the [`Language`] based on the filename, which may have changed with "Save As".
+fn language_hook(event: DocumentEvent, doc: &mut Document) {
+    if matches!(event, DocumentEvent::Open | DocumentEvent::AfterSave) && !doc.language_pinned {
+        let language = language::detect(&doc.filename);
+        if event == DocumentEvent::Open || !ptr::eq(language, doc.language) {
+            doc.set_language(language);
+        }
+    }
+}
+
+/// Watches the files of open documents, so that [`DocumentManager::reload_changed`] gets called.
+fn file_watch_hook(event: DocumentEvent, doc: &mut Document) {
+    let Some(path) = &doc.path else {
+        return;
+    };
+    match event {
+        // Watching is merely a convenience. Not being able to is no reason to fail.
+        DocumentEvent::Open | DocumentEvent::AfterSave => _ = sys::file_watch(path),
+        DocumentEvent::Close => sys::file_unwatch(path),
+        _ => {}
     }
 }
 
-#[derive(Default)]
 pub struct DocumentManager {
     list: LinkedList<Document>,
+    hooks: Vec<DocumentHook>,
+}
+
+impl Default for DocumentManager {
+    fn default() -> Self {
+        let mut manager = Self { list: Default::default(), hooks: Default::default() };
+        manager.subscribe(language_hook);
+        manager.subscribe(file_watch_hook);
+        manager
+    }
 }
 
 impl DocumentManager {
+    /// Registers a hook that runs on every [`DocumentEvent`], in the order of registration.
+    pub fn subscribe(&mut self, hook: impl FnMut(DocumentEvent, &mut Document) + 'static) {
+        self.hooks.push(Box::new(hook));
+    }
+
+    /// Runs the hooks for the active document.
+    fn emit(&mut self, event: DocumentEvent) {
+        if let Some(doc) = self.list.front_mut() {
+            for hook in &mut self.hooks {
+                hook(event, doc);
+            }
+        }
+    }
+
     #[inline]
     pub fn len(&self) -> usize {
         self.list.len()
@@ -94,6 +214,11 @@ impl DocumentManager {
         self.list.front_mut()
     }
 
+    /// The open documents, starting with the active one.
+    pub fn iter(&self) -> impl Iterator<Item = &Document> {
+        self.list.iter()
+    }
+
     #[inline]
     pub fn update_active<F: FnMut(&Document) -> bool>(&mut self, mut func: F) -> bool {
         let mut cursor = self.list.cursor_front_mut();
@@ -101,6 +226,7 @@ impl DocumentManager {
             if func(doc) {
                 let list = cursor.remove_current_as_list().unwrap();
                 self.list.cursor_front_mut().splice_before(list);
+                self.emit(DocumentEvent::Focus);
                 return true;
             }
             cursor.move_next();
@@ -109,7 +235,78 @@ impl DocumentManager {
     }
 
     pub fn remove_active(&mut self) {
-        self.list.pop_front();
+        self.emit(DocumentEvent::Close);
+        if self.list.pop_front().is_some() {
+            self.emit(DocumentEvent::Focus);
+        }
+    }
+
+    /// Saves the active document, optionally under a new path.
+    pub fn save_active(&mut self, new_path: Option<PathBuf>) -> apperr::Result<()> {
+        self.emit(DocumentEvent::BeforeSave);
+        if let Some(doc) = self.list.front_mut() {
+            doc.save(new_path)?;
+            self.emit(DocumentEvent::AfterSave);
+        }
+        Ok(())
+    }
+
+    /// Writes the unsaved changes of all documents into the "recovery" state directory,
+    /// named after their file with a ".save" suffix.
+    /// It's meant for when the editor gets terminated and can't ask the user what to do.
+    pub fn save_recovery(&mut self) {
+        let Some(dir) = path::app_dir(sys::KnownDir::State).map(|dir| dir.join("recovery")) else {
+            return;
+        };
+
+        for doc in &mut self.list {
+            let mut tb = doc.buffer.borrow_mut();
+            if !tb.is_dirty() || fs::create_dir_all(&dir).is_err() {
+                continue;
+            }
+            // Files with the same name in different directories mustn't overwrite each other.
+            // A collision would silently lose data, hence the collision-resistant hash.
+            let id = checksum(
+                HashAlgorithm::Blake3,
+                doc.path.as_ref().map_or(&[][..], |p| p.as_os_str().as_encoded_bytes()),
+            );
+            let path = dir.join(format!("{}.{id:.16}.save", doc.filename));
+            if let Ok(mut file) = File::create(&path) {
+                _ = tb.write_file(&mut file);
+            }
+        }
+    }
+
+    /// Reloads the documents for `path` after it changed on disk,
+    /// unless they have unsaved changes, which we don't want to lose.
+    pub fn reload_changed(&mut self, path: &Path) -> apperr::Result<()> {
+        for doc in &mut self.list {
+            if doc.path.as_deref() != Some(path) {
+                continue;
+            }
+
+            // Our own saves cause change notifications as well.
+            let modified = Self::modified_time(path);
+            if modified.is_none() || modified == doc.modified || doc.buffer.borrow().is_dirty() {
+                continue;
+            }
+
+            // Tools like `touch` or formatters may not change the contents.
+            // There's no need to reload (and lose the undo history) in that case.
+            let checksum = hash_file(0, path).ok();
+            if checksum.is_some() && checksum == doc.checksum {
+                doc.modified = modified;
+                continue;
+            }
+
+            let (encoding, pos) = {
+                let tb = doc.buffer.borrow();
+                (tb.encoding(), tb.cursor_logical_pos())
+            };
+            doc.reread(Some(encoding))?;
+            doc.buffer.borrow_mut().cursor_move_to_logical(pos);
+        }
+        Ok(())
     }
 
     pub fn add_untitled(&mut self) -> apperr::Result<&mut Document> {
@@ -120,11 +317,15 @@ impl DocumentManager {
             dir: Default::default(),
             filename: Default::default(),
             file_id: None,
+            modified: None,
+            checksum: None,
             new_file_counter: 0,
+            language: language::plain_text(),
+            language_pinned: false,
         };
         self.gen_untitled_name(&mut doc);
 
-        self.list.push_front(doc);
+        self.push_active(doc);
         Ok(self.list.front_mut().unwrap())
     }
 
@@ -145,14 +346,25 @@ impl DocumentManager {
 
         let mut file = match Self::open_for_reading(&path) {
             Ok(file) => Some(file),
-            Err(err) if sys::apperr_is_not_found(err) => None,
-            Err(err) => return Err(err),
+            Err(err) if sys::apperr_is_not_found(&err) => None,
+            Err(err) => return Err(err.context(error_context(LocId::ErrorOpenFile, &path))),
         };
 
-        let file_id = if file.is_some() { Some(sys::file_id(file.as_ref(), &path)?) } else { None };
+        let file_id = if file.is_some() {
+            Some(
+                sys::file_id(file.as_ref(), &path)
+                    .with_context(|| error_context(LocId::ErrorOpenFile, &path))?,
+            )
+        } else {
+            None
+        };
 
-        // Check if the file is already open.
-        if file_id.is_some() && self.update_active(|doc| doc.file_id == file_id) {
+        // Check if the file is already open. Files that don't exist yet have no ID,
+        // but may still have been opened under a differently spelled path.
+        if self.update_active(|doc| match &file_id {
+            Some(_) => doc.file_id == file_id,
+            None => doc.path.as_deref().is_some_and(|p| path::equals(p, &path)),
+        }) {
             let doc = self.active_mut().unwrap();
             if let Some(goto) = goto {
                 doc.buffer.borrow_mut().cursor_move_to_logical(goto);
@@ -161,10 +373,13 @@ impl DocumentManager {
         }
 
         let buffer = Self::create_buffer()?;
+        let mut checksum = None;
         {
             if let Some(file) = &mut file {
                 let mut tb = buffer.borrow_mut();
-                tb.read_file(file, None)?;
+                tb.read_file(file, None)
+                    .with_context(|| error_context(LocId::ErrorOpenFile, &path))?;
+                checksum = Some(tb.file_checksum());
 
                 if let Some(goto) = goto
                     && goto != Default::default()
@@ -180,7 +395,11 @@ impl DocumentManager {
             dir: None,
             filename: Default::default(),
             file_id,
+            modified: if file.is_some() { Self::modified_time(&path) } else { None },
+            checksum,
             new_file_counter: 0,
+            language: language::plain_text(),
+            language_pinned: false,
         };
         doc.set_path(path);
 
@@ -191,13 +410,21 @@ impl DocumentManager {
         {
             // If the current document is a pristine Untitled document with no
             // name and no ID, replace it with the new document.
-            self.remove_active();
+            // The document behind it doesn't get focused in between.
+            self.emit(DocumentEvent::Close);
+            self.list.pop_front();
         }
 
-        self.list.push_front(doc);
+        self.push_active(doc);
         Ok(self.list.front_mut().unwrap())
     }
 
+    fn push_active(&mut self, doc: Document) {
+        self.list.push_front(doc);
+        self.emit(DocumentEvent::Open);
+        self.emit(DocumentEvent::Focus);
+    }
+
     pub fn reflow_all(&self) {
         for doc in &self.list {
             let mut tb = doc.buffer.borrow_mut();
@@ -209,8 +436,8 @@ impl DocumentManager {
         File::open(path).map_err(apperr::Error::from)
     }
 
-    pub fn open_for_writing(path: &Path) -> apperr::Result<File> {
-        File::create(path).map_err(apperr::Error::from)
+    fn modified_time(path: &Path) -> Option<SystemTime> {
+        fs::metadata(path).and_then(|m| m.modified()).ok()
     }
 
     fn create_buffer() -> apperr::Result<RcTextBuffer> {
@@ -282,10 +509,91 @@ impl DocumentManager {
     }
 }
 
+/// Formats one of the "Couldn't ... {path}" messages for [`apperr::Error::context`].
+fn error_context(id: LocId, path: &Path) -> String {
+    loc(id).replace("{path}", &path.to_string_lossy())
+}
+
 #[cfg(test)]
 mod tests {
+    use std::cell::RefCell;
+    use std::mem;
+    use std::rc::Rc;
+
     use super::*;
 
+    #[test]
+    fn test_hooks() {
+        let events = Rc::new(RefCell::new(Vec::new()));
+        let mut documents = DocumentManager::default();
+        documents.subscribe({
+            let events = events.clone();
+            move |event, doc| events.borrow_mut().push((event, doc.filename.clone()))
+        });
+        let take = || mem::take(&mut *events.borrow_mut());
+
+        documents.add_untitled().unwrap();
+        documents.add_untitled().unwrap();
+        assert_eq!(
+            take(),
+            [
+                (DocumentEvent::Open, "Untitled-1.txt".to_string()),
+                (DocumentEvent::Focus, "Untitled-1.txt".to_string()),
+                (DocumentEvent::Open, "Untitled-2.txt".to_string()),
+                (DocumentEvent::Focus, "Untitled-2.txt".to_string()),
+            ]
+        );
+
+        documents.update_active(|doc| doc.filename == "Untitled-1.txt");
+        assert_eq!(take(), [(DocumentEvent::Focus, "Untitled-1.txt".to_string())]);
+
+        let path = std::env::temp_dir().join(format!("edit-test-hooks-{}", std::process::id()));
+        documents.save_active(Some(path.clone())).unwrap();
+        _ = fs::remove_file(&path);
+        let filename = path.file_name().unwrap().to_string_lossy().into_owned();
+        assert_eq!(
+            take(),
+            [
+                (DocumentEvent::BeforeSave, "Untitled-1.txt".to_string()),
+                (DocumentEvent::AfterSave, filename.clone()),
+            ]
+        );
+
+        documents.remove_active();
+        assert_eq!(
+            take(),
+            [
+                (DocumentEvent::Close, filename),
+                (DocumentEvent::Focus, "Untitled-2.txt".to_string())
+            ]
+        );
+    }
+
+    #[test]
+    fn test_changed_on_disk() {
+        let path = std::env::temp_dir().join(format!("edit-test-changed-{}", std::process::id()));
+        let later = |secs| SystemTime::now() + std::time::Duration::from_secs(secs);
+
+        let mut documents = DocumentManager::default();
+        documents.add_untitled().unwrap().buffer.borrow_mut().write_raw(b"foo");
+        documents.save_active(Some(path.clone())).unwrap();
+        let doc = documents.active().unwrap();
+        assert!(!doc.changed_on_disk());
+
+        // Touching the file doesn't change its contents.
+        File::options().write(true).open(&path).unwrap().set_modified(later(10)).unwrap();
+        assert!(!doc.changed_on_disk());
+
+        fs::write(&path, "bar").unwrap();
+        File::options().write(true).open(&path).unwrap().set_modified(later(20)).unwrap();
+        assert!(doc.changed_on_disk());
+
+        documents.save_active(None).unwrap();
+        assert!(!documents.active().unwrap().changed_on_disk());
+        assert!(fs::read_to_string(&path).unwrap().starts_with("foo"));
+        _ = fs::remove_file(&path);
+    }
+
     #[test]
     fn test_parse_last_numbers() {
         fn parse(s: &str) -> (&str, Option<Point>) {
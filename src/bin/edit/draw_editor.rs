@@ -1,19 +1,20 @@
 // Copyright (c) Microsoft Corporation.
 // Licensed under the MIT License.
 
-use std::num::ParseIntError;
-
-use edit::framebuffer::IndexedColor;
+use edit::arena::ArenaString;
+use edit::framebuffer::{Attributes, IndexedColor};
 use edit::helpers::*;
-use edit::icu;
 use edit::input::{kbmod, vk};
 use edit::tui::*;
+use edit::{apperr, icu, json};
 
+use crate::draw_statusbar::styled_label_add_fuzzy_match;
+use crate::goto;
 use crate::localization::*;
 use crate::state::*;
 
 pub fn draw_editor(ctx: &mut Context, state: &mut State) {
-    if !matches!(state.wants_search.kind, StateSearchKind::Hidden | StateSearchKind::Disabled) {
+    if state.wants_search.kind != StateSearchKind::Hidden {
         draw_search(ctx, state);
     }
 
@@ -26,23 +27,52 @@ pub fn draw_editor(ctx: &mut Context, state: &mut State) {
     };
 
     if let Some(doc) = state.documents.active() {
+        doc.buffer.borrow_mut().set_goal_column_enabled(state.goal_column);
         ctx.textarea("textarea", doc.buffer.clone());
         ctx.inherit_focus();
+        ctx.attr_intrinsic_size(Size { width: 0, height: size.height - height_reduction });
+        // Edits are rejected if the document would get too large.
+        let write_error = doc.buffer.borrow_mut().take_write_error();
+        if let Some(err) = write_error {
+            error_log_add(ctx, state, err);
+        }
+        draw_editor_context_menu(ctx, state);
     } else {
         ctx.block_begin("empty");
         ctx.block_end();
+        ctx.attr_intrinsic_size(Size { width: 0, height: size.height - height_reduction });
     }
-
-    ctx.attr_intrinsic_size(Size { width: 0, height: size.height - height_reduction });
 }
 
-fn draw_search(ctx: &mut Context, state: &mut State) {
-    if let Err(err) = icu::init() {
-        error_log_add(ctx, state, err);
-        state.wants_search.kind = StateSearchKind::Disabled;
+fn draw_editor_context_menu(ctx: &mut Context, state: &mut State) {
+    let doc = state.documents.active().unwrap();
+    let can_paste = !ctx.clipboard_ref().read().is_empty();
+    let items = [
+        MenuItem::new(loc(LocId::EditCut)).accelerator('T').shortcut(kbmod::CTRL | vk::X),
+        MenuItem::new(loc(LocId::EditCopy)).accelerator('C').shortcut(kbmod::CTRL | vk::C),
+        MenuItem::new(loc(LocId::EditPaste))
+            .accelerator('P')
+            .shortcut(kbmod::CTRL | vk::V)
+            .enabled(can_paste),
+        MenuItem::SEPARATOR,
+        MenuItem::new(loc(LocId::EditSelectAll)).accelerator('A').shortcut(kbmod::CTRL | vk::A),
+    ];
+
+    let Some(index) = ctx.context_menu("context-menu", &items) else {
         return;
+    };
+
+    let mut tb = doc.buffer.borrow_mut();
+    match index {
+        0 => tb.cut(ctx.clipboard_mut()),
+        1 => tb.copy(ctx.clipboard_mut()),
+        2 => tb.paste(ctx.clipboard_ref()),
+        _ => tb.select_all(),
     }
+    ctx.needs_rerender();
+}
 
+fn draw_search(ctx: &mut Context, state: &mut State) {
     let Some(doc) = state.documents.active() else {
         state.wants_search.kind = StateSearchKind::Hidden;
         return;
@@ -90,8 +120,12 @@ fn draw_search(ctx: &mut Context, state: &mut State) {
                 if focus == StateSearchKind::Search {
                     ctx.steal_focus();
                 }
-                if ctx.is_focused() && ctx.consume_shortcut(vk::RETURN) {
-                    action = Some(SearchAction::Search);
+                if ctx.is_focused() {
+                    if ctx.consume_shortcut(vk::RETURN) {
+                        action = Some(SearchAction::Search);
+                    } else if ctx.consume_shortcut(kbmod::SHIFT | vk::RETURN) {
+                        action = Some(SearchAction::SearchPrev);
+                    }
                 }
             }
 
@@ -165,6 +199,7 @@ fn draw_search(ctx: &mut Context, state: &mut State) {
 
 pub enum SearchAction {
     Search,
+    SearchPrev,
     Replace,
     ReplaceAll,
 }
@@ -174,30 +209,146 @@ pub fn search_execute(ctx: &mut Context, state: &mut State, action: SearchAction
         return;
     };
 
-    state.search_success = match action {
+    let result = match action {
         SearchAction::Search => {
             doc.buffer.borrow_mut().find_and_select(&state.search_needle, state.search_options)
         }
+        SearchAction::SearchPrev => {
+            doc.buffer.borrow_mut().find_and_select_prev(&state.search_needle, state.search_options)
+        }
         SearchAction::Replace => doc.buffer.borrow_mut().find_and_replace(
             &state.search_needle,
             state.search_options,
             state.search_replacement.as_bytes(),
         ),
-        SearchAction::ReplaceAll => doc.buffer.borrow_mut().find_and_replace_all(
-            &state.search_needle,
-            state.search_options,
-            state.search_replacement.as_bytes(),
-        ),
+        SearchAction::ReplaceAll => doc
+            .buffer
+            .borrow_mut()
+            .find_and_replace_all(
+                &state.search_needle,
+                state.search_options,
+                state.search_replacement.as_bytes(),
+            )
+            .map(|count| {
+                let n = count as u64;
+                let text = loc_format(loc_plural(PluralId::SearchReplacedCount, n), &[&n]);
+                ctx.toast(ToastKind::Info, text);
+            }),
+    };
+
+    state.search_success = result.is_ok();
+    ctx.needs_rerender();
+
+    // Without ICU only plain text searches work. Let the user know why the regex one failed.
+    if let Err(err @ apperr::APP_ICU_MISSING) = result {
+        error_log_add(ctx, state, err);
+    }
+}
+
+/// Reformats the selection, or the entire document if there's none, as JSON.
+/// The indentation follows the document's indentation settings.
+pub fn format_json(ctx: &mut Context, state: &mut State, minify: bool) {
+    let Some(doc) = state.documents.active() else {
+        return;
+    };
+
+    let err_off;
+    {
+        let mut tb = doc.buffer.borrow_mut();
+        let text = match tb.extract_user_selection(false) {
+            Some(text) => text,
+            None => {
+                tb.select_all();
+                tb.extract_user_selection(false).unwrap_or_default()
+            }
+        };
+        let beg = tb.selection_range().map_or(0, |(beg, _)| beg.offset);
+
+        let result = if minify {
+            json::minify(&text)
+        } else {
+            let mut indent = ArenaString::new_in(ctx.arena());
+            if tb.indent_with_tabs() {
+                indent.push('\t');
+            } else {
+                indent.push_repeat(' ', tb.tab_size() as usize);
+            }
+            json::pretty(&text, &indent)
+        };
+
+        err_off = match result {
+            Ok(formatted) => {
+                tb.write_raw(&formatted);
+                None
+            }
+            Err(off) => Some(beg + off),
+        };
+
+        if let Some(off) = err_off {
+            // Jump to the offending character, so the user can fix it.
+            tb.cursor_move_to_offset(off);
+            tb.make_cursor_visible();
+        }
+    }
+
+    if err_off.is_some() {
+        error_log_add(ctx, state, apperr::APP_JSON_INVALID);
     }
-    .is_ok();
 
     ctx.needs_rerender();
 }
 
+/// Runs the ICU transform `id` (e.g. "Any-Latin") over the selection, or the entire document.
+pub fn transliterate(ctx: &mut Context, state: &mut State, id: &str) {
+    let trans = match icu::Transliterator::new(id) {
+        Ok(trans) => trans,
+        Err(err) => {
+            error_log_add(ctx, state, err);
+            return;
+        }
+    };
+    let Some(doc) = state.documents.active() else {
+        return;
+    };
+
+    let mut tb = doc.buffer.borrow_mut();
+    let text = match tb.extract_user_selection(false) {
+        Some(text) => text,
+        None => {
+            tb.select_all();
+            tb.extract_user_selection(false).unwrap_or_default()
+        }
+    };
+    let result = trans.transliterate(&String::from_utf8_lossy(&text));
+    if result.as_bytes() != text {
+        tb.write_raw(result.as_bytes());
+    }
+    ctx.needs_rerender();
+}
+
 pub fn draw_handle_save(ctx: &mut Context, state: &mut State) {
-    if let Some(doc) = state.documents.active_mut() {
+    if let Some(doc) = state.documents.active() {
         if doc.path.is_some() {
-            if let Err(err) = doc.save(None) {
+            // Don't silently overwrite what another program wrote in the meantime.
+            if !state.save_conflict && doc.changed_on_disk() {
+                state.save_conflict = true;
+            }
+            if state.save_conflict {
+                match draw_dialog_save_conflict(ctx) {
+                    None => return,
+                    Some(true) => state.save_conflict = false,
+                    Some(false) => {
+                        state.save_conflict = false;
+                        state.wants_save = false;
+                        state.wants_close = false;
+                        state.wants_exit = false;
+                        ctx.needs_rerender();
+                        return;
+                    }
+                }
+            }
+
+            if let Err(err) = state.documents.save_active(None) {
                 error_log_add(ctx, state, err);
             }
         } else {
@@ -211,6 +362,55 @@ pub fn draw_handle_save(ctx: &mut Context, state: &mut State) {
     state.wants_save = false;
 }
 
+/// Asks whether to overwrite a file that was changed on disk.
+/// Returns `None` as long as the user hasn't made up their mind.
+fn draw_dialog_save_conflict(ctx: &mut Context) -> Option<bool> {
+    let mut result = None;
+
+    ctx.modal_begin("save-conflict", loc(LocId::FileChangedOnDisk));
+    ctx.attr_background_rgba(ctx.indexed(IndexedColor::Red));
+    ctx.attr_foreground_rgba(ctx.indexed(IndexedColor::BrightWhite));
+    {
+        let contains_focus = ctx.contains_focus();
+
+        ctx.label("description", loc(LocId::FileChangedOnDiskDescription));
+        ctx.attr_overflow(Overflow::TruncateTail);
+        ctx.attr_padding(Rect::three(1, 2, 1));
+
+        ctx.table_begin("choices");
+        ctx.inherit_focus();
+        ctx.attr_padding(Rect::three(0, 2, 1));
+        ctx.attr_position(Position::Center);
+        ctx.table_set_cell_gap(Size { width: 2, height: 0 });
+        {
+            ctx.table_next_row();
+            ctx.inherit_focus();
+
+            if ctx.button("yes", loc(LocId::Yes), ButtonStyle::default()) {
+                result = Some(true);
+            }
+            ctx.inherit_focus();
+            if ctx.button("no", loc(LocId::No), ButtonStyle::default()) {
+                result = Some(false);
+            }
+        }
+        ctx.table_end();
+
+        if contains_focus {
+            if ctx.consume_shortcut(vk::Y) {
+                result = Some(true);
+            } else if ctx.consume_shortcut(vk::N) {
+                result = Some(false);
+            }
+        }
+    }
+    if ctx.modal_end() {
+        result = Some(false);
+    }
+
+    result
+}
+
 pub fn draw_handle_wants_close(ctx: &mut Context, state: &mut State) {
     let Some(doc) = state.documents.active() else {
         state.wants_close = false;
@@ -303,55 +503,114 @@ pub fn draw_handle_wants_close(ctx: &mut Context, state: &mut State) {
 }
 
 pub fn draw_goto_menu(ctx: &mut Context, state: &mut State) {
+    let mut pick = None;
     let mut done = false;
 
-    if let Some(doc) = state.documents.active_mut() {
-        ctx.modal_begin("goto", loc(LocId::FileGoto));
-        {
-            if ctx.editline("goto-line", &mut state.goto_target) {
-                state.goto_invalid = false;
-            }
-            if state.goto_invalid {
-                ctx.attr_background_rgba(ctx.indexed(IndexedColor::Red));
-                ctx.attr_foreground_rgba(ctx.indexed(IndexedColor::BrightWhite));
-            }
+    if goto::poll_file_walk(state) || state.goto_results.is_none() {
+        state.goto_results = Some(goto::search(state, &state.goto_needle.clone()));
+    }
 
-            ctx.attr_intrinsic_size(Size { width: 24, height: 1 });
-            ctx.steal_focus();
+    ctx.modal_begin("goto", loc(LocId::ViewGotoAnything));
+    {
+        let width = (ctx.size().width - 20).max(10);
+        let height = (ctx.size().height - 10).max(10);
+        let spec = EditlineSpec {
+            placeholder: loc(LocId::GotoPlaceholder),
+            error: state.goto_invalid.then(|| loc(LocId::FileGotoInvalid)),
+            ..Default::default()
+        };
+        if ctx.editline_with_spec("needle", &mut state.goto_needle, &spec) {
+            state.goto_invalid = false;
+            state.goto_results = None;
+            ctx.needs_rerender();
+        }
+        ctx.attr_intrinsic_size(Size { width, height: 1 });
+        ctx.inherit_focus();
 
-            if ctx.consume_shortcut(vk::RETURN) {
-                match validate_goto_point(&state.goto_target) {
-                    Ok(point) => {
-                        let mut buf = doc.buffer.borrow_mut();
-                        buf.cursor_move_to_logical(point);
-                        buf.make_cursor_visible();
-                        done = true;
+        if ctx.is_focused() && ctx.consume_shortcut(vk::RETURN) {
+            // Enter in the search field goes to the best match.
+            pick = Some(0);
+        }
+
+        let results = state.goto_results.as_deref().unwrap_or_default();
+        if !results.is_empty() {
+            ctx.scrollarea_begin("scrollarea", Size { width, height });
+            ctx.attr_background_rgba(ctx.indexed_alpha(IndexedColor::Black, 1, 4));
+            {
+                ctx.list_begin("results");
+                for (i, item) in results.iter().enumerate() {
+                    ctx.styled_list_item_begin();
+                    ctx.attr_overflow(Overflow::TruncateTail);
+                    styled_label_add_fuzzy_match(ctx, &item.label, &item.positions);
+                    if !item.detail.is_empty() {
+                        ctx.styled_label_add_text("   ");
+                        ctx.styled_label_set_attributes(Attributes::Italic);
+                        ctx.styled_label_add_text(&item.detail);
+                        ctx.styled_label_set_attributes(Attributes::None);
+                    }
+                    if ctx.styled_list_item_end(false) == ListSelection::Activated {
+                        pick = Some(i);
                     }
-                    Err(_) => state.goto_invalid = true,
                 }
-                ctx.needs_rerender();
+                ctx.list_end();
             }
+            ctx.scrollarea_end();
         }
-        done |= ctx.modal_end();
-    } else {
-        done = true;
+    }
+    done |= ctx.modal_end();
+
+    if let Some(i) = pick {
+        let target =
+            state.goto_results.as_mut().filter(|r| i < r.len()).map(|r| r.swap_remove(i).target);
+        match target {
+            Some(target) => {
+                goto_target(ctx, state, target);
+                done = true;
+            }
+            // Nothing matched, most likely because the line number isn't one.
+            None => state.goto_invalid = true,
+        }
+        ctx.needs_rerender();
     }
 
     if done {
         state.wants_goto = false;
-        state.goto_target.clear();
+        state.goto_needle.clear();
         state.goto_invalid = false;
+        state.goto_results = None;
+        goto::reset_file_walk(state);
         ctx.needs_rerender();
     }
 }
 
-fn validate_goto_point(line: &str) -> Result<Point, ParseIntError> {
-    let mut coords = [0; 2];
-    let (y, x) = line.split_once(':').unwrap_or((line, "0"));
-    // Using a loop here avoids 2 copies of the str->int code.
-    // This makes the binary more compact.
-    for (i, s) in [x, y].iter().enumerate() {
-        coords[i] = s.parse::<CoordType>()?.saturating_sub(1);
+fn goto_target(ctx: &mut Context, state: &mut State, target: goto::Target) {
+    match target {
+        goto::Target::Position(pos) => {
+            if let Some(doc) = state.documents.active() {
+                let mut tb = doc.buffer.borrow_mut();
+                tb.cursor_move_to_logical(pos);
+                tb.make_cursor_visible();
+            }
+        }
+        goto::Target::Offset(offset) => {
+            if let Some(doc) = state.documents.active() {
+                let mut tb = doc.buffer.borrow_mut();
+                let pos = tb.offset_to_logical(offset);
+                tb.cursor_move_to_logical(pos);
+                tb.make_cursor_visible();
+            }
+        }
+        goto::Target::Document(index) => {
+            let mut i = 0;
+            state.documents.update_active(|_| {
+                i += 1;
+                i > index
+            });
+        }
+        goto::Target::Path(path) => {
+            if let Err(err) = state.documents.add_file_path(&path) {
+                error_log_add(ctx, state, err);
+            }
+        }
     }
-    Ok(Point { x: coords[0], y: coords[1] })
 }
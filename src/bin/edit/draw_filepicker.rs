@@ -10,7 +10,7 @@ use edit::framebuffer::IndexedColor;
 use edit::helpers::*;
 use edit::input::{kbmod, vk};
 use edit::tui::*;
-use edit::{icu, path};
+use edit::{icu, path, sys, unicode};
 
 use crate::localization::*;
 use crate::state::*;
@@ -52,8 +52,12 @@ pub fn draw_file_picker(ctx: &mut Context, state: &mut State) {
         {
             ctx.table_next_row();
 
-            ctx.label("dir-label", loc(LocId::SaveAsDialogPathLabel));
-            ctx.label("dir", state.file_picker_pending_dir.as_str());
+            let dir_label = loc(LocId::SaveAsDialogPathLabel);
+            // The padding and the cell gap take up 3 columns.
+            let dir_width = width - 3 - unicode::measure_width(dir_label);
+            let dir = path::shorten_for_display(state.file_picker_pending_dir.as_path(), dir_width);
+            ctx.label("dir-label", dir_label);
+            ctx.label("dir", &dir);
             ctx.attr_overflow(Overflow::TruncateMiddle);
 
             ctx.table_next_row();
@@ -162,6 +166,24 @@ pub fn draw_file_picker(ctx: &mut Context, state: &mut State) {
                         ListSelection::Activated => activated = true,
                     }
                     ctx.attr_overflow(Overflow::TruncateMiddle);
+
+                    let items = [
+                        MenuItem::new(loc(LocId::FilePickerOpen)).accelerator('O'),
+                        MenuItem::new(loc(LocId::FilePickerCopyPath)).accelerator('C'),
+                    ];
+                    match ctx.context_menu("context-menu", &items) {
+                        Some(0) => {
+                            state.file_picker_pending_name = entry.as_path().into();
+                            activated = true;
+                        }
+                        Some(_) => {
+                            let path =
+                                state.file_picker_pending_dir.as_path().join(entry.as_path());
+                            ctx.clipboard_mut().write(path.as_os_str().as_encoded_bytes().to_vec());
+                            ctx.toast(ToastKind::Info, path.to_string_lossy());
+                        }
+                        None => {}
+                    }
                 }
             }
 
@@ -242,10 +264,8 @@ pub fn draw_file_picker(ctx: &mut Context, state: &mut State) {
     if let Some(path) = doit {
         let res = if state.wants_file_picker == StateFilePicker::Open {
             state.documents.add_file_path(&path).map(|_| ())
-        } else if let Some(doc) = state.documents.active_mut() {
-            doc.save(Some(path))
         } else {
-            Ok(())
+            state.documents.save_active(Some(path))
         };
         match res {
             Ok(..) => {
@@ -260,6 +280,7 @@ pub fn draw_file_picker(ctx: &mut Context, state: &mut State) {
         state.wants_file_picker = StateFilePicker::None;
         state.file_picker_pending_name = Default::default();
         state.file_picker_entries = Default::default();
+        file_picker_watch_dir(state, None);
         state.file_picker_overwrite_warning = Default::default();
         state.file_picker_autocomplete = Default::default();
     }
@@ -268,8 +289,7 @@ pub fn draw_file_picker(ctx: &mut Context, state: &mut State) {
 // Returns Some(path) if the path refers to a file.
 fn draw_file_picker_update_path(state: &mut State) -> Option<PathBuf> {
     let old_path = state.file_picker_pending_dir.as_path();
-    let path = old_path.join(&state.file_picker_pending_name);
-    let path = path::normalize(&path);
+    let path = path::expand(old_path, &state.file_picker_pending_name);
 
     let (dir, name) = if path.is_dir() {
         // If the current path is C:\ and the user selects "..", we want to
@@ -301,11 +321,30 @@ fn draw_file_picker_update_path(state: &mut State) -> Option<PathBuf> {
     if state.file_picker_pending_name.as_os_str().is_empty() { None } else { Some(path) }
 }
 
+// Watches the directory shown in the file picker, so that its listing stays up to date.
+fn file_picker_watch_dir(state: &mut State, dir: Option<&Path>) {
+    if state.file_picker_watched_dir.as_deref() == dir {
+        return;
+    }
+    if let Some(old) = state.file_picker_watched_dir.take() {
+        sys::file_unwatch(&old);
+    }
+    if let Some(dir) = dir
+        && sys::file_watch(dir).is_ok()
+    {
+        state.file_picker_watched_dir = Some(dir.to_path_buf());
+    }
+}
+
 fn draw_dialog_saveas_refresh_files(state: &mut State) {
-    let dir = state.file_picker_pending_dir.as_path();
+    let dir = state.file_picker_pending_dir.as_path().to_path_buf();
+    let dir = dir.as_path();
     // ["..", directories, files]
     let mut dirs_files = [Vec::new(), Vec::new(), Vec::new()];
 
+    // The drive picker on Windows has no directory to watch.
+    file_picker_watch_dir(state, Some(dir).filter(|d| !d.as_os_str().is_empty()));
+
     #[cfg(windows)]
     if dir.as_os_str().is_empty() {
         // If the path is empty, we are at the drive picker.
@@ -369,6 +408,7 @@ fn update_autocomplete_suggestions(state: &mut State) {
     let scratch = scratch_arena(None);
     let needle = state.file_picker_pending_name.as_os_str().as_encoded_bytes();
     let mut matches = Vec::new();
+    let numeric = icu::is_collation_numeric();
 
     // Using binary search below we'll quickly find the lower bound
     // of items that match the needle (= share a common prefix).
@@ -397,6 +437,8 @@ fn update_autocomplete_suggestions(state: &mut State) {
                     }
                     // We're looking for suggestions, not for matches.
                     Ordering::Equal => {}
+                    // With numeric collation, "a10" sorts after "a2", even though it matches "a1".
+                    Ordering::Greater if numeric => {}
                     // No more matches possible.
                     Ordering::Greater => break,
                 }
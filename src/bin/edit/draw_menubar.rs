@@ -1,12 +1,17 @@
 // Copyright (c) Microsoft Corporation.
 // Licensed under the MIT License.
 
-use edit::arena_format;
+use std::iter;
+
+use edit::arena::ArenaString;
+use edit::hash::{self, HashAlgorithm};
 use edit::helpers::*;
 use edit::input::{kbmod, vk};
 use edit::tui::*;
+use edit::{arena_format, icu, simd};
 
-use crate::localization::*;
+use crate::draw_editor::{format_json, transliterate};
+use crate::localization::{self, *};
 use crate::state::*;
 
 pub fn draw_menubar(ctx: &mut Context, state: &mut State) {
@@ -51,6 +56,15 @@ fn draw_menu_file(ctx: &mut Context, state: &mut State) {
         if ctx.menubar_menu_button(loc(LocId::FileSaveAs), 'A', vk::NULL) {
             state.wants_file_picker = StateFilePicker::SaveAs;
         }
+        if let Some(path) = state.documents.active().and_then(|doc| doc.path.clone())
+            && ctx.menubar_menu_button(loc(LocId::FileCopyChecksum), 'H', vk::NULL)
+        {
+            // A cryptographic hash, unlike the one used to detect changes on disk.
+            match hash::checksum_file(HashAlgorithm::Blake3, &path) {
+                Ok(digest) => ctx.clipboard_mut().write(digest.to_string().into_bytes()),
+                Err(err) => error_log_add(ctx, state, err),
+            }
+        }
         if ctx.menubar_menu_button(loc(LocId::FileClose), 'C', kbmod::CTRL | vk::W) {
             state.wants_close = true;
         }
@@ -85,21 +99,71 @@ fn draw_menu_edit(ctx: &mut Context, state: &mut State) {
         tb.paste(ctx.clipboard_ref());
         ctx.needs_rerender();
     }
-    if state.wants_search.kind != StateSearchKind::Disabled {
-        if ctx.menubar_menu_button(loc(LocId::EditFind), 'F', kbmod::CTRL | vk::F) {
-            state.wants_search.kind = StateSearchKind::Search;
-            state.wants_search.focus = true;
-        }
-        if ctx.menubar_menu_button(loc(LocId::EditReplace), 'L', kbmod::CTRL | vk::R) {
-            state.wants_search.kind = StateSearchKind::Replace;
-            state.wants_search.focus = true;
-        }
+    if ctx.menubar_menu_button(loc(LocId::EditPasteVerbatim), 'V', kbmod::CTRL_SHIFT | vk::V) {
+        tb.paste_verbatim(ctx.clipboard_ref());
+        ctx.needs_rerender();
+    }
+    if ctx.menubar_menu_button(loc(LocId::EditPasteReindent), 'I', kbmod::CTRL_ALT | vk::V) {
+        tb.paste_reindent(ctx.clipboard_ref());
+        ctx.needs_rerender();
+    }
+    if ctx.menubar_menu_button(loc(LocId::EditFind), 'F', kbmod::CTRL | vk::F) {
+        state.wants_search.kind = StateSearchKind::Search;
+        state.wants_search.focus = true;
+    }
+    if ctx.menubar_menu_button(loc(LocId::EditReplace), 'L', kbmod::CTRL | vk::R) {
+        state.wants_search.kind = StateSearchKind::Replace;
+        state.wants_search.focus = true;
     }
     if ctx.menubar_menu_button(loc(LocId::EditSelectAll), 'A', kbmod::CTRL | vk::A) {
         tb.select_all();
         ctx.needs_rerender();
     }
+    if ctx.menubar_menu_button(
+        loc(LocId::EditExpandSelection),
+        'E',
+        KBMOD_FOR_EXPAND_SELECTION | vk::RIGHT,
+    ) {
+        tb.selection_expand();
+        ctx.needs_rerender();
+    }
+    if ctx.menubar_menu_button(
+        loc(LocId::EditShrinkSelection),
+        'H',
+        KBMOD_FOR_EXPAND_SELECTION | vk::LEFT,
+    ) {
+        tb.selection_shrink();
+        ctx.needs_rerender();
+    }
+    if ctx.menubar_menu_button(loc(LocId::EditSortLines), 'S', vk::NULL) {
+        tb.sort_selected_lines();
+        ctx.needs_rerender();
+    }
+
+    let mut transform = None;
+    if ctx.menubar_menu_button(loc(LocId::EditToLatin), 'N', vk::NULL) {
+        transform = Some("Any-Latin");
+    }
+    if ctx.menubar_menu_button(loc(LocId::EditRemoveDiacritics), 'D', vk::NULL) {
+        transform = Some(icu::Transliterator::REMOVE_DIACRITICS);
+    }
+
+    let mut format = None;
+    if ctx.menubar_menu_button(loc(LocId::EditFormatJson), 'J', vk::NULL) {
+        format = Some(false);
+    }
+    if ctx.menubar_menu_button(loc(LocId::EditMinifyJson), 'M', vk::NULL) {
+        format = Some(true);
+    }
     ctx.menubar_menu_end();
+
+    drop(tb);
+    if let Some(id) = transform {
+        transliterate(ctx, state, id);
+    }
+    if let Some(minify) = format {
+        format_json(ctx, state, minify);
+    }
 }
 
 fn draw_menu_view(ctx: &mut Context, state: &mut State) {
@@ -109,13 +173,14 @@ fn draw_menu_view(ctx: &mut Context, state: &mut State) {
 
         // All values on the statusbar are currently document specific.
         if ctx.menubar_menu_button(loc(LocId::ViewFocusStatusbar), 'S', vk::NULL) {
-            state.wants_statusbar_focus = true;
+            ctx.request_focus("newline");
         }
         if ctx.menubar_menu_button(loc(LocId::ViewGoToFile), 'F', kbmod::CTRL | vk::P) {
             state.wants_go_to_file = true;
         }
         if ctx.menubar_menu_button(loc(LocId::FileGoto), 'G', kbmod::CTRL | vk::G) {
             state.wants_goto = true;
+            state.goto_needle = ":".to_string();
         }
         if ctx.menubar_menu_checkbox(loc(LocId::ViewWordWrap), 'W', kbmod::ALT | vk::Z, word_wrap) {
             tb.set_word_wrap(!word_wrap);
@@ -123,6 +188,20 @@ fn draw_menu_view(ctx: &mut Context, state: &mut State) {
         }
     }
 
+    if ctx.menubar_menu_button(loc(LocId::ViewGotoAnything), 'A', kbmod::CTRL | vk::E) {
+        state.wants_goto = true;
+    }
+    if ctx.menubar_menu_checkbox(loc(LocId::ViewGoalColumn), 'C', vk::NULL, state.goal_column) {
+        state.goal_column = !state.goal_column;
+        state.config.set("goal_column", Some(if state.goal_column { "true" } else { "false" }));
+        if let Err(err) = state.config.save() {
+            error_log_add(ctx, state, err);
+        }
+    }
+    if ctx.menubar_menu_button(loc(LocId::ViewDisplayLanguage), 'L', vk::NULL) {
+        state.wants_display_language = true;
+    }
+
     ctx.menubar_menu_end();
 }
 
@@ -156,6 +235,22 @@ pub fn draw_dialog_about(ctx: &mut Context, state: &mut State) {
             ctx.attr_overflow(Overflow::TruncateHead);
             ctx.attr_position(Position::Center);
 
+            // Helps to diagnose issues with a particular vector path. See `EDIT_SIMD`.
+            let mut features = ArenaString::new_in(ctx.arena());
+            features.push_str("SIMD: ");
+            let mut separator = "";
+            for feature in simd::active_features() {
+                features.push_str(separator);
+                features.push_str(feature);
+                separator = ", ";
+            }
+            if separator.is_empty() {
+                features.push_str("fallback");
+            }
+            ctx.label("simd", &features);
+            ctx.attr_overflow(Overflow::TruncateTail);
+            ctx.attr_position(Position::Center);
+
             ctx.label("copyright", "Copyright (c) Microsoft Corp 2025");
             ctx.attr_overflow(Overflow::TruncateTail);
             ctx.attr_position(Position::Center);
@@ -178,3 +273,74 @@ pub fn draw_dialog_about(ctx: &mut Context, state: &mut State) {
         state.wants_about = false;
     }
 }
+
+pub fn draw_dialog_display_language(ctx: &mut Context, state: &mut State) {
+    // Resolved the same way as `localization::init()`: Unknown tags mean the system language.
+    let saved =
+        state.config.get("language").and_then(|tag| localization::languages().find(|&t| t == tag));
+    let current = state.display_language_preview.unwrap_or(saved);
+    let mut change = None;
+    let mut confirmed = false;
+
+    ctx.modal_begin("display-language", loc(LocId::ViewDisplayLanguage));
+    {
+        let count = localization::languages().count() as CoordType + 1;
+        let height = count.min((ctx.size().height - 10).max(5));
+
+        ctx.scrollarea_begin("scrollarea", Size { width: 0, height });
+        ctx.inherit_focus();
+        {
+            ctx.list_begin("languages");
+            ctx.inherit_focus();
+            ctx.attr_padding(Rect::two(0, 1));
+
+            // Languages are applied as soon as they're selected, so that the UI doubles as
+            // a preview. They're only saved once confirmed, however.
+            for lang in iter::once(None).chain(localization::languages().map(Some)) {
+                let name = match lang {
+                    Some(lang) => localization::language_name(lang),
+                    None => loc(LocId::DisplayLanguageSystem),
+                };
+                match ctx.list_item(current == lang, name) {
+                    ListSelection::Unchanged => {}
+                    ListSelection::Selected => change = Some(lang),
+                    ListSelection::Activated => {
+                        change = Some(lang);
+                        confirmed = true;
+                    }
+                }
+            }
+
+            ctx.list_end();
+        }
+        ctx.scrollarea_end();
+    }
+    let cancelled = ctx.modal_end() && !confirmed;
+
+    // Going back to the saved language on cancel is just another change.
+    if cancelled {
+        change = Some(saved);
+    }
+
+    if let Some(lang) = change
+        && lang != current
+    {
+        localization::init(lang);
+        ctx.set_rtl(localization::is_rtl());
+        state.display_language_preview = Some(lang);
+        // Every label on the screen needs to be laid out again.
+        ctx.needs_rerender();
+    }
+
+    if confirmed && change != Some(saved) {
+        state.config.set("language", change.flatten());
+        if let Err(err) = state.config.save() {
+            error_log_add(ctx, state, err);
+        }
+    }
+
+    if confirmed || cancelled {
+        state.display_language_preview = None;
+        state.wants_display_language = false;
+    }
+}
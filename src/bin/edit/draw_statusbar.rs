@@ -1,18 +1,25 @@
 // Copyright (c) Microsoft Corporation.
 // Licensed under the MIT License.
 
-use edit::arena::scratch_arena;
+use std::ptr;
+use std::sync::atomic::Ordering;
+
+use edit::arena::{ArenaString, scratch_arena};
+use edit::buffer::ControlChars;
 use edit::framebuffer::{Attributes, IndexedColor};
-use edit::fuzzy::score_fuzzy;
+use edit::fuzzy::{FuzzyProfile, score_fuzzy};
 use edit::helpers::*;
 use edit::input::vk;
 use edit::tui::*;
-use edit::{arena_format, icu};
+use edit::{arena_format, icu, path, unicode};
 
+use crate::language;
 use crate::localization::*;
 use crate::state::*;
 
 pub fn draw_statusbar(ctx: &mut Context, state: &mut State) {
+    let mut language_change = None;
+
     ctx.table_begin("statusbar");
     ctx.attr_focus_well();
     ctx.attr_background_rgba(state.menubar_color_bg);
@@ -30,10 +37,6 @@ pub fn draw_statusbar(ctx: &mut Context, state: &mut State) {
             let is_crlf = tb.is_crlf();
             tb.normalize_newlines(!is_crlf);
         }
-        if state.wants_statusbar_focus {
-            state.wants_statusbar_focus = false;
-            ctx.steal_focus();
-        }
 
         state.wants_encoding_picker |=
             ctx.button("encoding", tb.encoding(), ButtonStyle::default());
@@ -52,6 +55,7 @@ pub fn draw_statusbar(ctx: &mut Context, state: &mut State) {
                 {
                     if ctx.button("reopen", loc(LocId::EncodingReopen), ButtonStyle::default()) {
                         state.wants_encoding_change = StateEncodingChange::Reopen;
+                        state.encoding_picker_detected = doc.detect_encoding();
                     }
                     ctx.focus_on_first_present();
                     if ctx.button("convert", loc(LocId::EncodingConvert), ButtonStyle::default()) {
@@ -147,6 +151,91 @@ pub fn draw_statusbar(ctx: &mut Context, state: &mut State) {
             }
         }
 
+        state.wants_language_picker |=
+            ctx.button("language", doc.language.name(), ButtonStyle::default());
+        if state.wants_language_picker {
+            ctx.table_begin("language-picker");
+            ctx.attr_float(FloatSpec {
+                anchor: Anchor::Last,
+                gravity_x: 0.0,
+                gravity_y: 1.0,
+                offset_x: 0.0,
+                offset_y: 0.0,
+            });
+            ctx.attr_border();
+            ctx.attr_padding(Rect::two(0, 1));
+            ctx.table_set_cell_gap(Size { width: 1, height: 0 });
+            {
+                if ctx.contains_focus() && ctx.consume_shortcut(vk::RETURN) {
+                    ctx.toss_focus_up();
+                }
+
+                ctx.table_next_row();
+
+                ctx.scrollarea_begin("scrollarea", Size { width: 0, height: 10 });
+                {
+                    ctx.list_begin("languages");
+                    ctx.focus_on_first_present();
+                    ctx.attr_padding(Rect::two(0, 1));
+                    for language in &language::ALL {
+                        if ctx.list_item(ptr::eq(language, doc.language), language.name())
+                            != ListSelection::Unchanged
+                        {
+                            language_change = Some(language);
+                        }
+                    }
+                    ctx.list_end();
+                }
+                ctx.scrollarea_end();
+
+                ctx.block_begin("toggles");
+                ctx.attr_padding(Rect::two(0, 1));
+                {
+                    let mut word_wrap = tb.is_word_wrap_enabled();
+                    if ctx.checkbox("word-wrap", loc(LocId::ViewWordWrap), &mut word_wrap) {
+                        tb.set_word_wrap(word_wrap);
+                        ctx.needs_rerender();
+                    }
+
+                    let mut ruler = tb.ruler() > 0;
+                    if ctx.checkbox("ruler", loc(LocId::LanguageRuler), &mut ruler) {
+                        // Languages without a ruler of their own get the classic 80 columns.
+                        let column = if doc.language.ruler > 0 { doc.language.ruler } else { 80 };
+                        tb.set_ruler(if ruler { column } else { 0 });
+                        ctx.needs_rerender();
+                    }
+
+                    // Pastes are inserted as they are, unless the user opts into cleaning them up.
+                    let mut paste_options = tb.paste_options();
+                    let mut convert = paste_options.convert_indentation;
+                    if ctx.checkbox(
+                        "paste-indentation",
+                        loc(LocId::LanguagePasteIndentation),
+                        &mut convert,
+                    ) {
+                        paste_options.convert_indentation = convert;
+                    }
+                    let mut visualize = paste_options.control_chars == ControlChars::Visualize;
+                    if ctx.checkbox(
+                        "paste-control-chars",
+                        loc(LocId::LanguagePasteControlChars),
+                        &mut visualize,
+                    ) {
+                        paste_options.control_chars =
+                            if visualize { ControlChars::Visualize } else { ControlChars::Keep };
+                    }
+                    tb.set_paste_options(paste_options);
+                }
+                ctx.block_end();
+            }
+            ctx.table_end();
+
+            if !ctx.contains_focus() {
+                state.wants_language_picker = false;
+                ctx.needs_rerender();
+            }
+        }
+
         ctx.label(
             "location",
             &arena_format!(
@@ -157,13 +246,32 @@ pub fn draw_statusbar(ctx: &mut Context, state: &mut State) {
             ),
         );
 
+        // The count stays visible while the search bar is open or a hit is selected,
+        // so that stepping through them with F3 shows where one is.
+        if let Some(status) = tb.search_status()
+            && (state.wants_search.kind != StateSearchKind::Hidden || status.index.is_some())
+        {
+            let count = status.count as u64;
+            let mut label = match status.index {
+                Some(i) => loc_format(loc(LocId::SearchMatchIndex), &[&(i + 1), &count]),
+                None => loc_format(loc_plural(PluralId::SearchMatchCount, count), &[&count]),
+            };
+            if status.wrapped && status.index.is_some() {
+                label.push(' ');
+                label.push_str(loc(LocId::SearchWrapped));
+            }
+            ctx.label("search-status", &label);
+        }
+
         #[cfg(feature = "debug-latency")]
         ctx.label(
             "stats",
             &arena_format!(ctx.arena(), "{}/{}", tb.logical_line_count(), tb.visual_line_count(),),
         );
 
-        if tb.is_overtype() && ctx.button("overtype", "OVR", ButtonStyle::default()) {
+        if tb.is_overtype()
+            && ctx.button("overtype", loc(LocId::StatusOvertype), ButtonStyle::default())
+        {
             tb.set_overtype(false);
             ctx.needs_rerender();
         }
@@ -172,11 +280,49 @@ pub fn draw_statusbar(ctx: &mut Context, state: &mut State) {
             ctx.label("dirty", "*");
         }
 
+        if let Some(task) = &state.background_task {
+            match task.progress {
+                Some(progress) => ctx.progress_bar("task-progress", progress, 10),
+                None => ctx.spinner("task-progress"),
+            }
+            ctx.label("task-title", &task.title);
+            if ctx.button("task-cancel", loc(LocId::Cancel), ButtonStyle::default()) {
+                task.cancel.store(true, Ordering::Relaxed);
+            }
+        }
+
+        if let Some(received) = ctx.paste_progress() {
+            let size = arena_format!(ctx.arena(), "{}", MetricFormatter(received));
+            let mut label = ArenaString::new_in(ctx.arena());
+            label.push_str(loc(LocId::PasteProgress));
+            label.replace_once_in_place("{size}", &size);
+
+            ctx.spinner("paste-progress");
+            ctx.label("paste-title", &label);
+        }
+
+        if let Some(prefix) = ctx.chord_pending()
+            && let Some(key) = ctx.shortcut_text(prefix)
+        {
+            let mut label = ArenaString::new_in(ctx.arena());
+            label.push_str(loc(LocId::ChordPending));
+            label.replace_once_in_place("{key}", &key);
+            ctx.label("chord-pending", &label);
+        }
+
         ctx.block_begin("filename-container");
         ctx.attr_intrinsic_size(Size { width: COORD_TYPE_SAFE_MAX, height: 1 });
         {
             let total = state.documents.len();
-            let mut filename = doc.filename.as_str();
+            // The path gets a third of the statusbar, which leaves enough room for the other items.
+            let path_buf;
+            let mut filename = match &doc.path {
+                Some(p) => {
+                    path_buf = path::shorten_for_display(p, ctx.size().width / 3);
+                    path_buf.as_str()
+                }
+                None => doc.filename.as_str(),
+            };
             let filename_buf;
 
             if total > 1 {
@@ -191,17 +337,28 @@ pub fn draw_statusbar(ctx: &mut Context, state: &mut State) {
         }
         ctx.block_end();
     } else {
-        state.wants_statusbar_focus = false;
         state.wants_encoding_picker = false;
         state.wants_indentation_picker = false;
+        state.wants_language_picker = false;
     }
 
     ctx.table_end();
+
+    // A language picked by the user sticks, even when the file gets renamed.
+    if let Some(language) = language_change
+        && let Some(doc) = state.documents.active_mut()
+    {
+        doc.language_pinned = true;
+        doc.set_language(language);
+        ctx.needs_rerender();
+    }
 }
 
 pub fn draw_dialog_encoding_change(ctx: &mut Context, state: &mut State) {
     let encoding = state.documents.active_mut().map_or("", |doc| doc.buffer.borrow().encoding());
     let reopen = state.wants_encoding_change == StateEncodingChange::Reopen;
+    let preselected =
+        if reopen { state.encoding_picker_detected.unwrap_or(encoding) } else { encoding };
     let width = (ctx.size().width - 20).max(10);
     let height = (ctx.size().height - 10).max(10);
     let mut change = None;
@@ -235,12 +392,21 @@ pub fn draw_dialog_encoding_change(ctx: &mut Context, state: &mut State) {
             ctx.list_begin("encodings");
             ctx.inherit_focus();
 
-            for enc in state
-                .encoding_picker_results
-                .as_deref()
-                .unwrap_or_else(|| icu::get_available_encodings().preferred)
-            {
-                if ctx.list_item(enc.canonical == encoding, enc.label) == ListSelection::Activated {
+            // Without a needle, the preferred encodings are listed instead of the results.
+            let (results, preferred) = match &state.encoding_picker_results {
+                Some(results) => (results.as_slice(), &[][..]),
+                None => (&[][..], icu::get_available_encodings().preferred),
+            };
+            let results = results.iter().map(|(enc, positions)| (enc, positions.as_slice()));
+            let preferred = preferred.iter().map(|enc| (enc, &[][..]));
+
+            for (enc, positions) in results.chain(preferred) {
+                ctx.styled_list_item_begin();
+                styled_label_add_fuzzy_match(ctx, enc.label, positions);
+
+                if ctx.styled_list_item_end(enc.canonical == preselected)
+                    == ListSelection::Activated
+                {
                     change = Some(enc.canonical);
                     break;
                 }
@@ -259,9 +425,11 @@ pub fn draw_dialog_encoding_change(ctx: &mut Context, state: &mut State) {
         if reopen && doc.path.is_some() {
             let mut res = Ok(());
             if doc.buffer.borrow().is_dirty() {
-                res = doc.save(None);
+                res = state.documents.save_active(None);
             }
-            if res.is_ok() {
+            if res.is_ok()
+                && let Some(doc) = state.documents.active_mut()
+            {
                 res = doc.reread(Some(encoding));
             }
             if let Err(err) = res {
@@ -276,10 +444,36 @@ pub fn draw_dialog_encoding_change(ctx: &mut Context, state: &mut State) {
         state.wants_encoding_change = StateEncodingChange::None;
         state.encoding_picker_needle.clear();
         state.encoding_picker_results = None;
+        state.encoding_picker_detected = None;
         ctx.needs_rerender();
     }
 }
 
+/// Adds `text` to the current styled label and underlines the characters
+/// at the given `positions`, as returned by [`score_fuzzy`].
+pub fn styled_label_add_fuzzy_match(ctx: &mut Context, text: &str, positions: &[usize]) {
+    let mut positions = positions.iter().copied().peekable();
+    let mut beg = 0;
+    let mut underlined = false;
+
+    for (i, (off, _)) in text.char_indices().enumerate() {
+        let matched = positions.next_if_eq(&i).is_some();
+        if matched != underlined {
+            ctx.styled_label_add_text(&text[beg..off]);
+            ctx.styled_label_set_attributes(if matched {
+                Attributes::Underlined
+            } else {
+                Attributes::None
+            });
+            beg = off;
+            underlined = matched;
+        }
+    }
+
+    ctx.styled_label_add_text(&text[beg..]);
+    ctx.styled_label_set_attributes(Attributes::None);
+}
+
 fn encoding_picker_update_list(state: &mut State) {
     state.encoding_picker_results = None;
 
@@ -294,15 +488,17 @@ fn encoding_picker_update_list(state: &mut State) {
 
     for enc in encodings.all {
         let local_scratch = scratch_arena(Some(&scratch));
-        let (score, _) = score_fuzzy(&local_scratch, enc.label, needle, true);
+        let (score, positions) =
+            score_fuzzy(&local_scratch, enc.label, needle, true, &FuzzyProfile::DEFAULT);
 
         if score > 0 {
-            matches.push((score, *enc));
+            matches.push((score, *enc, positions.to_vec()));
         }
     }
 
     matches.sort_by(|a, b| b.0.cmp(&a.0));
-    state.encoding_picker_results = Some(Vec::from_iter(matches.iter().map(|(_, enc)| *enc)));
+    state.encoding_picker_results =
+        Some(Vec::from_iter(matches.into_iter().map(|(_, enc, positions)| (enc, positions))));
 }
 
 pub fn draw_go_to_file(ctx: &mut Context, state: &mut State) {
@@ -326,10 +522,12 @@ pub fn draw_go_to_file(ctx: &mut Context, state: &mut State) {
                 ctx.styled_label_add_text(if tb.is_dirty() { "* " } else { "  " });
                 ctx.styled_label_add_text(&doc.filename);
 
-                if let Some(path) = &doc.dir {
+                if let Some(dir) = &doc.dir {
+                    // The selection marker, "* ", the gap and the scrollbar take up 8 columns.
+                    let dir_width = width - 8 - unicode::measure_width(&doc.filename);
                     ctx.styled_label_add_text("   ");
                     ctx.styled_label_set_attributes(Attributes::Italic);
-                    ctx.styled_label_add_text(path.as_str());
+                    ctx.styled_label_add_text(&path::shorten_for_display(dir.as_path(), dir_width));
                 }
 
                 ctx.styled_list_item_end(false) == ListSelection::Activated
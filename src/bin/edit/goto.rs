@@ -0,0 +1,460 @@
+// Copyright (c) Microsoft Corporation.
+// Licensed under the MIT License.
+
+//! The providers behind the goto bar. The first character of the query picks one:
+//! `:` goes to a line, `@` to a symbol, `#` searches the text and anything else finds files.
+
+use std::cmp::Reverse;
+use std::num::ParseIntError;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::mpsc::{self, TryRecvError};
+use std::{fs, thread};
+
+use edit::arena::{ArenaHashMap, scratch_arena};
+use edit::fuzzy::{FuzzyProfile, score_fuzzy};
+use edit::helpers::*;
+use edit::{path, sys};
+
+use crate::localization::*;
+use crate::state::*;
+
+/// More results aren't useful in a list that's meant to be narrowed down by typing.
+const MAX_RESULTS: usize = 500;
+/// Limits the walk of the working directory, so that opening the bar in `/` finishes eventually.
+/// The depth limit also ends symlink cycles.
+const MAX_FILES: usize = 10000;
+const MAX_DEPTH: usize = 8;
+/// The providers don't know the width of the list, so directories get shortened to a fixed width.
+const DETAIL_WIDTH: CoordType = 40;
+
+/// Where to go when an [`Item`] is picked.
+pub enum Target {
+    /// A position in the active document.
+    Position(Point),
+    /// A byte offset in the active document.
+    Offset(usize),
+    /// The n-th open document, in the order of [`crate::documents::DocumentManager::iter`].
+    Document(usize),
+    /// A file that isn't open yet.
+    Path(PathBuf),
+}
+
+pub struct Item {
+    pub label: String,
+    /// Shown after the label, e.g. the kind of symbol or the directory.
+    pub detail: String,
+    /// The `char` indices in `label` that match the query, for highlighting.
+    pub positions: Vec<usize>,
+    pub target: Target,
+    score: i32,
+}
+
+/// Searches for [`Item`]s of one kind.
+pub struct Provider {
+    /// The character the query starts with to select this provider, or `None` for the default.
+    pub prefix: Option<char>,
+    /// Appends the items matching `needle`, which is the query without the prefix.
+    pub search: fn(&mut State, &str, &mut Vec<Item>),
+}
+
+/// All providers. The default one, without a prefix, comes last.
+pub static PROVIDERS: [Provider; 4] = [
+    Provider { prefix: Some(':'), search: search_line },
+    Provider { prefix: Some('@'), search: search_symbols },
+    Provider { prefix: Some('#'), search: search_text },
+    Provider { prefix: None, search: search_files },
+];
+
+/// Runs the provider selected by the `query` and returns the results, best first.
+pub fn search(state: &mut State, query: &str) -> Vec<Item> {
+    let (provider, needle) = provider_for(query);
+    let mut items = Vec::new();
+    (provider.search)(state, needle.trim(), &mut items);
+    // The sort is stable, so that equally good results stay in document order.
+    items.sort_by_key(|i| Reverse(i.score));
+    items.truncate(MAX_RESULTS);
+    items
+}
+
+fn provider_for(query: &str) -> (&'static Provider, &str) {
+    let mut chars = query.chars();
+    let first = chars.next();
+    for provider in &PROVIDERS {
+        match provider.prefix {
+            Some(prefix) if first == Some(prefix) => return (provider, chars.as_str()),
+            Some(_) => {}
+            None => return (provider, query),
+        }
+    }
+    unreachable!()
+}
+
+/// Parses "line" or "line:column", both 1-based, into a 0-based position.
+pub fn parse_position(text: &str) -> Result<Point, ParseIntError> {
+    let mut coords = [0; 2];
+    let (y, x) = text.split_once(':').unwrap_or((text, "0"));
+    // Using a loop here avoids 2 copies of the str->int code.
+    // This makes the binary more compact.
+    for (i, s) in [x, y].iter().enumerate() {
+        coords[i] = s.parse::<CoordType>()?.saturating_sub(1);
+    }
+    Ok(Point { x: coords[0], y: coords[1] })
+}
+
+fn search_line(state: &mut State, needle: &str, items: &mut Vec<Item>) {
+    if state.documents.active().is_none() {
+        return;
+    }
+    let Ok(pos) = parse_position(needle) else {
+        return;
+    };
+
+    let label = if needle.contains(':') {
+        loc_format(loc(LocId::GotoLineColumn), &[&(pos.y + 1), &(pos.x + 1)])
+    } else {
+        loc_format(loc(LocId::GotoLine), &[&(pos.y + 1)])
+    };
+    items.push(Item {
+        label,
+        detail: String::new(),
+        positions: Vec::new(),
+        target: Target::Position(pos),
+        score: 0,
+    });
+}
+
+fn search_symbols(state: &mut State, needle: &str, items: &mut Vec<Item>) {
+    let Some(doc) = state.documents.active() else {
+        return;
+    };
+    let markdown = doc.language.id == "markdown";
+    let tb = doc.buffer.borrow();
+
+    for line in tb.lines(0..tb.text_length()) {
+        let offset = line.range().start;
+        let text = line.without_newline().to_cow();
+        let text = String::from_utf8_lossy(&text);
+        let indent = text.len() - text.trim_start().len();
+
+        let Some((kind, name)) = parse_symbol(&text[indent..], markdown) else {
+            continue;
+        };
+        let Some((score, positions)) = fuzzy_match(name, needle, &FuzzyProfile::DEFAULT) else {
+            continue;
+        };
+        items.push(Item {
+            label: name.to_string(),
+            detail: kind.to_string(),
+            positions,
+            target: Target::Offset(offset + indent),
+            score,
+        });
+    }
+}
+
+/// Recognizes the declarations of the popular languages with a few keywords,
+/// which is crude, but language-agnostic and cheap. Returns the kind and name.
+fn parse_symbol(line: &str, markdown: bool) -> Option<(&str, &str)> {
+    const MODIFIERS: &[&str] = &[
+        "abstract",
+        "async",
+        "default",
+        "export",
+        "extern",
+        "final",
+        "private",
+        "protected",
+        "pub",
+        "pub(crate)",
+        "pub(super)",
+        "public",
+        "static",
+        "unsafe",
+    ];
+    const KEYWORDS: &[&str] = &[
+        "class",
+        "def",
+        "enum",
+        "fn",
+        "func",
+        "function",
+        "impl",
+        "interface",
+        "macro_rules!",
+        "mod",
+        "namespace",
+        "struct",
+        "trait",
+        "type",
+        "union",
+    ];
+
+    if markdown {
+        let heading = line.trim_start_matches('#');
+        let level = line.len() - heading.len();
+        return if (1..=6).contains(&level) && heading.starts_with(' ') {
+            Some((&line[..level], heading.trim()))
+        } else {
+            None
+        };
+    }
+
+    let mut rest = line;
+    loop {
+        let (word, tail) = rest.split_once(char::is_whitespace)?;
+        rest = tail.trim_start();
+        // Generic parameters, as in "impl<T>", aren't part of the keyword.
+        let word = word.split_once('<').map_or(word, |(w, _)| w);
+        if MODIFIERS.contains(&word) {
+            continue;
+        }
+        if !KEYWORDS.contains(&word) {
+            return None;
+        }
+
+        let name = if word == "impl" {
+            // "impl<T> Foo for Bar<T> {" is best described by everything between the generics
+            // and the brace. `split_once` above splits "impl<T>" off as a single word.
+            rest.split(['{', ';']).next().unwrap_or(rest).trim_end()
+        } else {
+            let end = rest
+                .find(|c: char| !c.is_alphanumeric() && c != '_' && c != '$')
+                .unwrap_or(rest.len());
+            &rest[..end]
+        };
+        return if name.is_empty() { None } else { Some((word, name)) };
+    }
+}
+
+fn search_text(state: &mut State, needle: &str, items: &mut Vec<Item>) {
+    let Some(doc) = state.documents.active() else {
+        return;
+    };
+    if needle.is_empty() {
+        return;
+    }
+    let tb = doc.buffer.borrow();
+    let needle = needle.to_ascii_lowercase();
+
+    for (y, line) in tb.lines(0..tb.text_length()).enumerate() {
+        if items.len() >= MAX_RESULTS {
+            break;
+        }
+
+        let offset = line.range().start;
+        let text = line.without_newline().to_cow();
+        let text = String::from_utf8_lossy(&text);
+        let indent = text.len() - text.trim_start().len();
+        let label = &text[indent..];
+        // Lowercasing may change the length of the text, so this is limited to ASCII
+        // for the sake of mapping the match back. Most code is ASCII anyway.
+        let Some(beg) = label.to_ascii_lowercase().find(&needle) else {
+            continue;
+        };
+
+        let first = label[..beg].chars().count();
+        let count = label[beg..beg + needle.len()].chars().count();
+        items.push(Item {
+            label: label.to_string(),
+            detail: (y + 1).to_string(),
+            positions: (first..first + count).collect(),
+            target: Target::Offset(offset + indent + beg),
+            score: 0,
+        });
+    }
+}
+
+fn search_files(state: &mut State, needle: &str, items: &mut Vec<Item>) {
+    for (i, doc) in state.documents.iter().enumerate() {
+        let Some((score, positions)) = fuzzy_match(&doc.filename, needle, &FuzzyProfile::PATHS)
+        else {
+            continue;
+        };
+        items.push(Item {
+            label: doc.filename.clone(),
+            detail: doc
+                .dir
+                .as_ref()
+                .map(|d| path::shorten_for_display(d.as_path(), DETAIL_WIDTH))
+                .unwrap_or_default(),
+            positions,
+            target: Target::Document(i),
+            // Open documents come first, because it's likely that they're wanted.
+            score: score.saturating_add(1),
+        });
+    }
+
+    // An empty query lists the open documents, like a document switcher.
+    if needle.is_empty() {
+        return;
+    }
+
+    if state.goto_files.is_none() && state.goto_walk.is_none() {
+        start_file_walk(state);
+    }
+    // Until the walk is done, only the open documents are listed.
+    let Some(files) = &state.goto_files else {
+        return;
+    };
+
+    // The open documents are listed above already. The files are relative to the working directory.
+    let scratch = scratch_arena(None);
+    let cwd = std::env::current_dir().unwrap_or_default();
+    let mut open = ArenaHashMap::with_capacity_in(state.documents.len(), &scratch);
+    for doc in state.documents.iter() {
+        if let Some(path) = doc.path.as_deref().and_then(|p| p.strip_prefix(&cwd).ok()) {
+            open.insert(path, ());
+        }
+    }
+
+    for file in files.iter() {
+        if open.contains_key(file.as_path()) {
+            continue;
+        }
+        let label = file.to_string_lossy();
+        let Some((score, positions)) = fuzzy_match(&label, needle, &FuzzyProfile::PATHS) else {
+            continue;
+        };
+        items.push(Item {
+            label: label.into_owned(),
+            detail: String::new(),
+            positions,
+            target: Target::Path(file.clone()),
+            score,
+        });
+    }
+}
+
+/// Collects the files in the working directory on a background thread,
+/// so that a large directory doesn't block the UI. See [`poll_file_walk`].
+fn start_file_walk(state: &mut State) {
+    let Ok(cwd) = std::env::current_dir() else {
+        state.goto_files = Some(Vec::new());
+        return;
+    };
+
+    let task = BackgroundTask::new(loc(LocId::GotoListingFiles).to_string());
+    let cancel = task.cancel.clone();
+    let (tx, rx) = mpsc::channel();
+
+    thread::spawn(move || {
+        let mut files = Vec::new();
+        walk_dir(&cwd, Path::new(""), 0, &cancel, &mut files);
+        // If the receiver is gone, the goto bar was closed. That's fine.
+        _ = tx.send(files);
+        sys::wakeup();
+    });
+
+    state.goto_walk = Some(rx);
+    state.background_task = Some(task);
+}
+
+/// Moves the files collected by the background walk into [`State::goto_files`] once it's done.
+/// Returns `true` if that happened, in which case the results need to be updated.
+pub fn poll_file_walk(state: &mut State) -> bool {
+    let Some(rx) = &state.goto_walk else {
+        return false;
+    };
+    let files = match rx.try_recv() {
+        Ok(files) => files,
+        Err(TryRecvError::Empty) => return false,
+        // The thread panicked. Continue without the files.
+        Err(TryRecvError::Disconnected) => Vec::new(),
+    };
+    state.goto_files = Some(files);
+    state.goto_walk = None;
+    state.background_task = None;
+    true
+}
+
+/// Stops the background walk, if any, and forgets the files. Called when the goto bar closes.
+pub fn reset_file_walk(state: &mut State) {
+    if state.goto_walk.take().is_some()
+        && let Some(task) = state.background_task.take()
+    {
+        task.cancel.store(true, Ordering::Relaxed);
+    }
+    state.goto_files = None;
+}
+
+/// Collects the files below `root`, relative to it. Hidden files and directories are skipped.
+/// Symlinks are followed. If `cancel` gets set, the files found so far are kept.
+fn walk_dir(root: &Path, dir: &Path, depth: usize, cancel: &AtomicBool, files: &mut Vec<PathBuf>) {
+    let Ok(entries) = fs::read_dir(root.join(dir)) else {
+        return;
+    };
+
+    let mut subdirs = Vec::new();
+    for entry in entries.flatten() {
+        if files.len() >= MAX_FILES || cancel.load(Ordering::Relaxed) {
+            return;
+        }
+        let name = entry.file_name();
+        if name.as_encoded_bytes().starts_with(b".") {
+            continue;
+        }
+        // Unlike `entry.file_type()`, this follows symlinks. Broken ones are skipped.
+        let Ok(metadata) = fs::metadata(entry.path()) else {
+            continue;
+        };
+        if metadata.is_dir() {
+            subdirs.push(dir.join(name));
+        } else {
+            files.push(dir.join(name));
+        }
+    }
+
+    if depth < MAX_DEPTH {
+        subdirs.sort_by(|a, b| path::compare(a, b));
+        for subdir in subdirs {
+            walk_dir(root, &subdir, depth + 1, cancel, files);
+        }
+    }
+}
+
+/// Like [`score_fuzzy`], but an empty `needle` matches everything equally.
+fn fuzzy_match(haystack: &str, needle: &str, profile: &FuzzyProfile) -> Option<(i32, Vec<usize>)> {
+    if needle.is_empty() {
+        return Some((0, Vec::new()));
+    }
+    let scratch = scratch_arena(None);
+    let (score, positions) = score_fuzzy(&scratch, haystack, needle, true, profile);
+    if score > 0 { Some((score, positions.to_vec())) } else { None }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_provider_for() {
+        let prefix = |query| {
+            let (provider, needle) = provider_for(query);
+            (provider.prefix, needle)
+        };
+        assert_eq!(prefix(":12:3"), (Some(':'), "12:3"));
+        assert_eq!(prefix("@main"), (Some('@'), "main"));
+        assert_eq!(prefix("#todo"), (Some('#'), "todo"));
+        assert_eq!(prefix("main.rs"), (None, "main.rs"));
+        assert_eq!(prefix(""), (None, ""));
+    }
+
+    #[test]
+    fn test_parse_symbol() {
+        assert_eq!(parse_symbol("pub(crate) fn foo<T>(x: T) {", false), Some(("fn", "foo")));
+        assert_eq!(
+            parse_symbol("impl<T> Foo for Bar<T> {", false),
+            Some(("impl", "Foo for Bar<T>"))
+        );
+        assert_eq!(
+            parse_symbol("export default class App extends Base {", false),
+            Some(("class", "App"))
+        );
+        assert_eq!(parse_symbol("def __init__(self):", false), Some(("def", "__init__")));
+        assert_eq!(parse_symbol("let fn = 1;", false), None);
+        assert_eq!(parse_symbol("fn", false), None);
+        assert_eq!(parse_symbol("## Getting started ", true), Some(("##", "Getting started")));
+        assert_eq!(parse_symbol("#hashtag", true), None);
+    }
+}
@@ -0,0 +1,173 @@
+// Copyright (c) Microsoft Corporation.
+// Licensed under the MIT License.
+
+//! The kinds of files the editor knows about and the defaults that come with them.
+
+use edit::helpers::CoordType;
+
+use crate::localization::*;
+
+pub struct Language {
+    /// A stable identifier, as used by LSP and Markdown code fences.
+    pub id: &'static str,
+    /// The name shown in the statusbar.
+    name: &'static str,
+    /// Lowercase file extensions without the leading dot.
+    extensions: &'static [&'static str],
+    /// Filenames that don't go by their extension.
+    filenames: &'static [&'static str],
+    /// Whether to indent with tabs and how wide. Only applies to new files,
+    /// because the indentation of existing ones is detected when reading them.
+    pub indentation: Option<(bool, CoordType)>,
+    /// The column of the ruler, or 0 for none.
+    pub ruler: CoordType,
+    pub word_wrap: bool,
+}
+
+impl Language {
+    const DEFAULT: Self = Self {
+        id: "",
+        name: "",
+        extensions: &[],
+        filenames: &[],
+        indentation: None,
+        ruler: 0,
+        word_wrap: false,
+    };
+
+    pub fn name(&self) -> &'static str {
+        // Unlike the names of programming languages, this one gets translated.
+        if self.id == "plaintext" { loc(LocId::LanguagePlainText) } else { self.name }
+    }
+}
+
+/// All known languages, in the order they're listed in the statusbar.
+/// Plain text comes first, because it's the fallback.
+pub static ALL: [Language; 22] = [
+    Language { id: "plaintext", extensions: &["txt"], ..Language::DEFAULT },
+    Language { id: "bat", name: "Batch", extensions: &["bat", "cmd"], ..Language::DEFAULT },
+    Language { id: "c", name: "C", extensions: &["c", "h"], ..Language::DEFAULT },
+    Language { id: "csharp", name: "C#", extensions: &["cs"], ..Language::DEFAULT },
+    Language {
+        id: "cpp",
+        name: "C++",
+        extensions: &["cc", "cpp", "cxx", "hh", "hpp", "hxx"],
+        ..Language::DEFAULT
+    },
+    Language { id: "css", name: "CSS", extensions: &["css"], ..Language::DEFAULT },
+    Language {
+        id: "git-commit",
+        name: "Git Commit Message",
+        filenames: &["COMMIT_EDITMSG", "MERGE_MSG", "TAG_EDITMSG"],
+        ruler: 72,
+        ..Language::DEFAULT
+    },
+    Language {
+        id: "go",
+        name: "Go",
+        extensions: &["go"],
+        indentation: Some((true, 4)),
+        ..Language::DEFAULT
+    },
+    Language { id: "html", name: "HTML", extensions: &["htm", "html"], ..Language::DEFAULT },
+    Language { id: "ini", name: "INI", extensions: &["cfg", "conf", "ini"], ..Language::DEFAULT },
+    Language {
+        id: "javascript",
+        name: "JavaScript",
+        extensions: &["cjs", "js", "jsx", "mjs"],
+        ..Language::DEFAULT
+    },
+    Language { id: "json", name: "JSON", extensions: &["json", "jsonc"], ..Language::DEFAULT },
+    Language {
+        id: "makefile",
+        name: "Makefile",
+        extensions: &["mk"],
+        filenames: &["GNUmakefile", "Makefile", "makefile"],
+        indentation: Some((true, 4)),
+        ..Language::DEFAULT
+    },
+    Language {
+        id: "markdown",
+        name: "Markdown",
+        extensions: &["markdown", "md"],
+        word_wrap: true,
+        ..Language::DEFAULT
+    },
+    Language {
+        id: "powershell",
+        name: "PowerShell",
+        extensions: &["ps1", "psd1", "psm1"],
+        ..Language::DEFAULT
+    },
+    Language {
+        id: "python",
+        name: "Python",
+        extensions: &["py", "pyi"],
+        indentation: Some((false, 4)),
+        ..Language::DEFAULT
+    },
+    Language {
+        id: "rust",
+        name: "Rust",
+        extensions: &["rs"],
+        indentation: Some((false, 4)),
+        ..Language::DEFAULT
+    },
+    Language {
+        id: "shellscript",
+        name: "Shell Script",
+        extensions: &["bash", "sh", "zsh"],
+        ..Language::DEFAULT
+    },
+    Language { id: "toml", name: "TOML", extensions: &["toml"], ..Language::DEFAULT },
+    Language {
+        id: "typescript",
+        name: "TypeScript",
+        extensions: &["cts", "mts", "ts", "tsx"],
+        ..Language::DEFAULT
+    },
+    Language { id: "xml", name: "XML", extensions: &["svg", "xaml", "xml"], ..Language::DEFAULT },
+    Language {
+        id: "yaml",
+        name: "YAML",
+        extensions: &["yaml", "yml"],
+        indentation: Some((false, 2)),
+        ..Language::DEFAULT
+    },
+];
+
+pub fn plain_text() -> &'static Language {
+    &ALL[0]
+}
+
+/// Guesses the language from a filename, falling back to plain text.
+pub fn detect(filename: &str) -> &'static Language {
+    if let Some(language) = ALL.iter().find(|l| l.filenames.contains(&filename)) {
+        return language;
+    }
+
+    if let Some((_, ext)) = filename.rsplit_once('.') {
+        let ext = ext.to_ascii_lowercase();
+        if let Some(language) = ALL.iter().find(|l| l.extensions.contains(&ext.as_str())) {
+            return language;
+        }
+    }
+
+    plain_text()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_detect() {
+        assert_eq!(detect("main.rs").id, "rust");
+        assert_eq!(detect("README.MD").id, "markdown");
+        assert_eq!(detect("COMMIT_EDITMSG").id, "git-commit");
+        assert_eq!(detect("Makefile").id, "makefile");
+        assert_eq!(detect("archive.tar.gz").id, "plaintext");
+        assert_eq!(detect("Untitled-1.txt").id, "plaintext");
+        assert_eq!(detect(".bashrc").id, "plaintext");
+    }
+}
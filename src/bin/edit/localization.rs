@@ -1,33 +1,618 @@
 // Copyright (c) Microsoft Corporation.
 // Licensed under the MIT License.
 
+use std::fmt::{Display, Write as _};
+use std::path::Path;
+use std::{fs, io};
+
 use edit::arena::scratch_arena;
 use edit::helpers::AsciiStringHelpers;
-use edit::sys;
+use edit::{apperr, sys};
 
 include!(concat!(env!("OUT_DIR"), "/i18n_edit.rs"));
 
-static mut S_LANG: LangId = LangId::en;
+/// The names of the CLDR plural categories, in the order of [`PluralCategory`].
+const PLURAL_CATEGORY_NAMES: [&str; 6] = ["zero", "one", "two", "few", "many", "other"];
+
+/// Translations loaded at runtime. See [`load_external`].
+struct ExternalLanguage {
+    tag: &'static str,
+    /// Indexed by [`LocId`]. `None` falls back to the built-in translation.
+    strings: Box<[Option<&'static str>]>,
+    /// Indexed by [`PluralId`] and then by [`PluralCategory`].
+    plurals: Box<[Option<[&'static str; 6]>]>,
+}
+
+#[derive(Clone, Copy)]
+struct Language {
+    tag: &'static str,
+    /// The built-in translation, or English for languages that only exist externally.
+    builtin: LangId,
+    external: Option<&'static ExternalLanguage>,
+    plural_rule: PluralRule,
+}
+
+static mut S_LANG: Language =
+    Language { tag: "en", builtin: LangId::en, external: None, plural_rule: PluralRule::One };
+static mut S_EXTERNAL: Vec<&'static ExternalLanguage> = Vec::new();
+
+/// Picks the UI language. `tag` is the one chosen by the user (see [`languages`]).
+/// Without it, or if it's unknown, the OS-preferred languages are used.
+///
+/// Can be called again at any time. All strings are looked up every frame anyway.
+pub fn init(tag: Option<&str>) {
+    if let Some(tag) = tag
+        && let Some(tag) = languages().find(|&t| t == tag)
+    {
+        set_language(tag);
+        return;
+    }
 
-pub fn init() {
     let scratch = scratch_arena(None);
     let langs = sys::preferred_languages(&scratch);
-    let mut lang = LangId::en;
+    let mut tag = "en";
 
     'outer: for l in langs {
-        for (prefix, id) in LANGUAGES {
+        for ext in external() {
+            if l.starts_with_ignore_ascii_case(ext.tag) {
+                tag = ext.tag;
+                break 'outer;
+            }
+        }
+        for &(prefix, id) in LANGUAGES {
             if l.starts_with_ignore_ascii_case(prefix) {
-                lang = *id;
+                tag = LANGUAGE_TAGS[id as usize].0;
                 break 'outer;
             }
         }
     }
 
+    set_language(tag);
+}
+
+fn set_language(tag: &'static str) {
+    let builtin = LANGUAGE_TAGS.iter().find(|&&(t, _)| t == tag).map_or(LangId::en, |&(_, id)| id);
+    let external = external().find(|ext| ext.tag == tag);
+    let plural_rule = plural_rule(tag);
+    unsafe { S_LANG = Language { tag, builtin, external, plural_rule } };
+}
+
+fn external() -> impl Iterator<Item = &'static ExternalLanguage> {
+    #[allow(static_mut_refs)]
     unsafe {
-        S_LANG = lang;
+        S_EXTERNAL.iter().copied()
     }
 }
 
 pub fn loc(id: LocId) -> &'static str {
-    TRANSLATIONS[unsafe { S_LANG as usize }][id as usize]
+    let lang = unsafe { S_LANG };
+    if let Some(ext) = lang.external
+        && let Some(str) = ext.strings[id as usize]
+    {
+        return str;
+    }
+    TRANSLATIONS[lang.builtin as usize][id as usize]
+}
+
+/// The tag of the current UI language, e.g. "pt_br".
+pub fn language() -> &'static str {
+    unsafe { S_LANG.tag }
+}
+
+/// Whether the current UI language is written from right to left.
+pub fn is_rtl() -> bool {
+    let tag = language();
+    let primary = tag.split('_').next().unwrap_or(tag);
+    matches!(primary, "ar" | "ckb" | "dv" | "fa" | "he" | "ps" | "sd" | "ug" | "ur" | "yi")
+}
+
+/// The tags of all the languages the UI is available in, in the order they should be listed.
+pub fn languages() -> impl Iterator<Item = &'static str> {
+    let builtin = LANGUAGE_TAGS.iter().map(|&(tag, _)| tag);
+    let added =
+        external().map(|ext| ext.tag).filter(|&tag| !LANGUAGE_TAGS.iter().any(|&(t, _)| t == tag));
+    builtin.chain(added)
+}
+
+/// The name of the language with the given `tag` in that very language, e.g. "Deutsch".
+pub fn language_name(tag: &'static str) -> &'static str {
+    if let Some(ext) = external().find(|ext| ext.tag == tag)
+        && let Some(name) = ext.strings[LocId::LanguageName as usize]
+    {
+        return name;
+    }
+    match LANGUAGE_TAGS.iter().find(|&&(t, _)| t == tag) {
+        Some(&(_, id)) => TRANSLATIONS[id as usize][LocId::LanguageName as usize],
+        None => tag,
+    }
+}
+
+/// Returns the plural form of `id` that goes with the count `n`.
+/// The count itself is usually passed on to [`loc_format`] as `{0}`.
+pub fn loc_plural(id: PluralId, n: u64) -> &'static str {
+    let lang = unsafe { S_LANG };
+    let category = plural_category(lang.plural_rule, n) as usize;
+    if let Some(ext) = lang.external
+        && let Some(forms) = &ext.plurals[id as usize]
+    {
+        return forms[category];
+    }
+    PLURAL_TRANSLATIONS[lang.builtin as usize][id as usize][category]
+}
+
+/// Loads translations from a TOML file with the same layout as the built-in ones:
+/// A `[LocId]` section per string with a `tag = "text"` line per language,
+/// where plural forms are given as `tag = { one = "text", other = "text" }`.
+/// It can override built-in translations as well as add new languages.
+/// Unknown sections are skipped, so that the file works across versions.
+///
+/// A missing file isn't an error. Should be called once, before [`init`].
+pub fn load_external(path: &Path) -> apperr::Result<()> {
+    let text = match fs::read_to_string(path) {
+        Ok(text) => text,
+        Err(err) if err.kind() == io::ErrorKind::NotFound => return Ok(()),
+        Err(err) => return Err(err.into()),
+    };
+    let languages = parse_external(&text).map_err(|line| {
+        apperr::APP_TRANSLATIONS_INVALID.context(format!("{}:{line}", path.display()))
+    })?;
+    #[allow(static_mut_refs)]
+    unsafe {
+        S_EXTERNAL.extend(languages.into_iter().map(|l| &*Box::leak(Box::new(l))))
+    };
+    Ok(())
+}
+
+/// A value in the format described in [`load_external`].
+enum Value {
+    String(String),
+    /// An inline table of plural forms, e.g. `{ one = "text", other = "text" }`.
+    Table(Vec<(String, String)>),
+}
+
+/// Parses the format described in [`load_external`]. On failure, returns the 1-based line number.
+///
+/// It's the subset of TOML that the format needs: Tables, bare and quoted keys, all four kinds
+/// of strings, and inline tables of strings, which may span multiple lines like in TOML 1.1.
+fn parse_external(text: &str) -> Result<Vec<ExternalLanguage>, usize> {
+    enum Section {
+        None,
+        Skip,
+        Loc(usize),
+        Plural(usize),
+    }
+
+    let mut languages: Vec<ExternalLanguage> = Vec::new();
+    let mut section = Section::None;
+    let mut rest = text;
+
+    loop {
+        rest = skip_trivia(rest);
+        if rest.is_empty() {
+            break;
+        }
+        // Errors are reported at the line the statement starts at.
+        let err = text[..text.len() - rest.len()].matches('\n').count() + 1;
+
+        if let Some(r) = rest.strip_prefix('[') {
+            let (name, r) = parse_key(skip_spaces(r)).ok_or(err)?;
+            let r = skip_spaces(r).strip_prefix(']').ok_or(err)?;
+            rest = skip_end_of_line(r).ok_or(err)?;
+            section = if let Some(i) = LOC_ID_NAMES.iter().position(|&n| n == name) {
+                Section::Loc(i)
+            } else if let Some(i) = PLURAL_ID_NAMES.iter().position(|&n| n == name) {
+                Section::Plural(i)
+            } else {
+                Section::Skip
+            };
+            continue;
+        }
+
+        let (key, r) = parse_key(rest).ok_or(err)?;
+        let r = skip_spaces(r).strip_prefix('=').ok_or(err)?;
+        let (value, r) = parse_value(skip_spaces(r)).ok_or(err)?;
+        rest = skip_end_of_line(r).ok_or(err)?;
+
+        let tag = normalize_tag(&key).ok_or(err)?;
+        match section {
+            Section::None => return Err(err),
+            Section::Skip => continue,
+            _ => {}
+        }
+
+        let idx = match languages.iter().position(|l| l.tag == tag) {
+            Some(idx) => idx,
+            None => {
+                languages.push(ExternalLanguage {
+                    tag: tag.leak(),
+                    strings: vec![None; LOC_ID_NAMES.len()].into(),
+                    plurals: vec![None; PLURAL_ID_NAMES.len()].into(),
+                });
+                languages.len() - 1
+            }
+        };
+        let lang = &mut languages[idx];
+
+        match (&section, value) {
+            (Section::Loc(id), Value::String(str)) => {
+                lang.strings[*id] = Some(str.leak());
+            }
+            (Section::Plural(id), Value::Table(entries)) => {
+                let mut forms: [Option<String>; 6] = Default::default();
+                for (category, str) in entries {
+                    let category =
+                        PLURAL_CATEGORY_NAMES.iter().position(|&c| c == category).ok_or(err)?;
+                    forms[category] = Some(str);
+                }
+
+                // Like the built-in ones, missing categories fall back to "other".
+                let other: &'static str = forms[5].take().ok_or(err)?.leak();
+                lang.plurals[*id] = Some(forms.map(|f| f.map_or(other, |f| &*f.leak())));
+            }
+            _ => return Err(err),
+        }
+    }
+
+    Ok(languages)
+}
+
+/// Turns a language tag like "pt-BR" into the form used by the built-in ones ("pt_br").
+fn normalize_tag(tag: &str) -> Option<String> {
+    if tag.is_empty() || !tag.bytes().all(|b| b.is_ascii_alphabetic() || b == b'-' || b == b'_') {
+        return None;
+    }
+    Some(tag.to_ascii_lowercase().replace('-', "_"))
+}
+
+/// Parses a bare key (`pt-BR`) or a quoted one (`"pt-BR"`) at the start of `s`.
+/// Returns the key and whatever follows it.
+fn parse_key(s: &str) -> Option<(String, &str)> {
+    if s.starts_with(['"', '\'']) {
+        return parse_string(s).filter(|(key, _)| !key.contains('\n'));
+    }
+    let len =
+        s.find(|c: char| !c.is_ascii_alphanumeric() && c != '-' && c != '_').unwrap_or(s.len());
+    (len != 0).then(|| (s[..len].to_string(), &s[len..]))
+}
+
+/// Parses a string or an inline table of strings at the start of `s`.
+/// Returns the value and whatever follows it.
+fn parse_value(s: &str) -> Option<(Value, &str)> {
+    let Some(mut rest) = s.strip_prefix('{') else {
+        let (str, rest) = parse_string(s)?;
+        return Some((Value::String(str), rest));
+    };
+
+    let mut entries = Vec::new();
+    loop {
+        rest = skip_trivia(rest);
+        if let Some(r) = rest.strip_prefix('}') {
+            return Some((Value::Table(entries), r));
+        }
+        let (key, r) = parse_key(rest)?;
+        let r = skip_spaces(r).strip_prefix('=')?;
+        let (str, r) = parse_string(skip_spaces(r))?;
+        entries.push((key, str));
+        rest = skip_trivia(r);
+        rest = rest.strip_prefix(',').unwrap_or(rest);
+    }
+}
+
+/// Parses a TOML string at the start of `s`: A basic (`"..."`), literal (`'...'`),
+/// multi-line basic (`"""..."""`) or multi-line literal (`'''...'''`) one.
+/// Returns the contents and whatever follows the closing quotes.
+fn parse_string(s: &str) -> Option<(String, &str)> {
+    // A newline right after the opening quotes of a multi-line string isn't part of it.
+    fn skip_first_newline(s: &str) -> &str {
+        let s = s.strip_prefix('\r').unwrap_or(s);
+        s.strip_prefix('\n').unwrap_or(s)
+    }
+
+    if let Some(s) = s.strip_prefix("'''") {
+        let (str, rest) = skip_first_newline(s).split_once("'''")?;
+        return Some((str.replace("\r\n", "\n"), rest));
+    }
+    if let Some(s) = s.strip_prefix('\'') {
+        let (str, rest) = s.split_once('\'')?;
+        return (!str.contains('\n')).then(|| (str.to_string(), rest));
+    }
+
+    let (s, multiline) = match s.strip_prefix("\"\"\"") {
+        Some(s) => (skip_first_newline(s), true),
+        None => (s.strip_prefix('"')?, false),
+    };
+    let mut str = String::new();
+    let mut chars = s.char_indices();
+
+    while let Some((i, c)) = chars.next() {
+        match c {
+            '"' if !multiline => return Some((str, &s[i + 1..])),
+            '"' if s[i..].starts_with("\"\"\"") => return Some((str, &s[i + 3..])),
+            '\n' if !multiline => return None,
+            // Line endings are normalized to LF.
+            '\r' if s[i..].starts_with("\r\n") => {}
+            '\\' => {
+                let c = match chars.next()?.1 {
+                    'n' => '\n',
+                    't' => '\t',
+                    'r' => '\r',
+                    '"' => '"',
+                    '\\' => '\\',
+                    c @ ('u' | 'U') => {
+                        let len = if c == 'u' { 4 } else { 8 };
+                        let beg = chars.offset();
+                        let hex = s.get(beg..beg + len)?;
+                        for _ in 0..len {
+                            chars.next();
+                        }
+                        char::from_u32(u32::from_str_radix(hex, 16).ok()?)?
+                    }
+                    // A backslash at the end of a line trims all whitespace up to the next text.
+                    ' ' | '\t' | '\r' | '\n' if multiline => {
+                        while chars.as_str().starts_with([' ', '\t', '\r', '\n']) {
+                            chars.next();
+                        }
+                        continue;
+                    }
+                    _ => return None,
+                };
+                str.push(c);
+            }
+            c => str.push(c),
+        }
+    }
+
+    None
+}
+
+/// Skips spaces and tabs, but not newlines.
+fn skip_spaces(s: &str) -> &str {
+    s.trim_start_matches([' ', '\t'])
+}
+
+/// Skips whitespace, newlines and comments.
+fn skip_trivia(mut s: &str) -> &str {
+    loop {
+        s = s.trim_start();
+        if !s.starts_with('#') {
+            return s;
+        }
+        s = s.split_once('\n').map_or("", |(_, rest)| rest);
+    }
+}
+
+/// Skips the rest of the line, which may only contain a comment. Returns the next line.
+fn skip_end_of_line(s: &str) -> Option<&str> {
+    let s = skip_spaces(s);
+    if s.is_empty() || s.starts_with('#') {
+        return Some(s.split_once('\n').map_or("", |(_, rest)| rest));
+    }
+    let s = s.strip_prefix('\r').unwrap_or(s);
+    s.strip_prefix('\n')
+}
+
+/// Maps a language tag to the [`PluralRule`] that implements its CLDR plural rules for integers.
+/// Unknown languages get the English rule.
+fn plural_rule(tag: &str) -> PluralRule {
+    // Unlike Brazilian Portuguese, European Portuguese treats 0 as plural.
+    if tag == "pt_pt" {
+        return PluralRule::One;
+    }
+
+    let primary = tag.split('_').next().unwrap_or(tag);
+    match primary {
+        "id" | "ja" | "ko" | "ms" | "th" | "vi" | "zh" => PluralRule::Other,
+        "bn" | "fa" | "fr" | "hi" | "pt" => PluralRule::ZeroOne,
+        "be" | "ru" | "uk" => PluralRule::EastSlavic,
+        "bs" | "hr" | "sr" => PluralRule::SerboCroatian,
+        "cs" | "sk" => PluralRule::Czech,
+        "is" => PluralRule::Icelandic,
+        "pl" => PluralRule::Polish,
+        "ro" => PluralRule::Romanian,
+        _ => PluralRule::One,
+    }
+}
+
+/// Replaces the numbered placeholders `{0}` to `{9}` in `template` with `args`.
+/// Unlike named ones, translations can reorder them freely without the need
+/// for a separate `replace` per argument. Unknown placeholders are kept as is.
+pub fn loc_format(template: &str, args: &[&dyn Display]) -> String {
+    let mut out = String::with_capacity(template.len());
+    let mut rest = template;
+
+    while let Some(beg) = rest.find('{') {
+        out.push_str(&rest[..beg]);
+        rest = &rest[beg..];
+
+        let b = rest.as_bytes();
+        if b.len() >= 3
+            && b[1].is_ascii_digit()
+            && b[2] == b'}'
+            && let Some(arg) = args.get((b[1] - b'0') as usize)
+        {
+            _ = write!(out, "{arg}");
+            rest = &rest[3..];
+        } else {
+            out.push('{');
+            rest = &rest[1..];
+        }
+    }
+
+    out.push_str(rest);
+    out
+}
+
+/// The CLDR plural categories. Their order matches `PLURAL_TRANSLATIONS`
+/// and [`PLURAL_CATEGORY_NAMES`].
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+#[allow(dead_code)] // Zero and Two only exist for languages like Arabic, which we don't ship.
+enum PluralCategory {
+    Zero,
+    One,
+    Two,
+    Few,
+    Many,
+    Other,
+}
+
+/// The families of CLDR plural rules the supported languages fall into.
+/// Only the rules for integers are implemented, since we don't count fractions.
+#[derive(Clone, Copy)]
+enum PluralRule {
+    /// No plural forms at all, e.g. Japanese.
+    Other,
+    /// 1 is singular, e.g. English.
+    One,
+    /// 0 and 1 are singular, e.g. French.
+    ZeroOne,
+    /// E.g. Russian: 1, 21, 31 / 2-4, 22-24 / 5-20, 25-30.
+    EastSlavic,
+    /// Like [`PluralRule::EastSlavic`], but with "other" instead of "many".
+    SerboCroatian,
+    /// 1 / 2-4 / everything else.
+    Czech,
+    /// 1, 21, 31 (but not 11) / everything else.
+    Icelandic,
+    /// 1 / 2-4, 22-24 (but not 12-14) / everything else.
+    Polish,
+    /// 1 / 0, 2-19, 101-119 / everything else.
+    Romanian,
+}
+
+fn plural_category(rule: PluralRule, n: u64) -> PluralCategory {
+    let n10 = n % 10;
+    let n100 = n % 100;
+    let slavic_few = (2..=4).contains(&n10) && !(12..=14).contains(&n100);
+
+    match rule {
+        PluralRule::Other => PluralCategory::Other,
+        PluralRule::One if n == 1 => PluralCategory::One,
+        PluralRule::ZeroOne if n <= 1 => PluralCategory::One,
+        PluralRule::EastSlavic | PluralRule::SerboCroatian if n10 == 1 && n100 != 11 => {
+            PluralCategory::One
+        }
+        PluralRule::EastSlavic | PluralRule::SerboCroatian if slavic_few => PluralCategory::Few,
+        PluralRule::EastSlavic => PluralCategory::Many,
+        PluralRule::Czech | PluralRule::Polish if n == 1 => PluralCategory::One,
+        PluralRule::Czech if (2..=4).contains(&n) => PluralCategory::Few,
+        PluralRule::Polish if slavic_few => PluralCategory::Few,
+        PluralRule::Polish => PluralCategory::Many,
+        PluralRule::Icelandic if n10 == 1 && n100 != 11 => PluralCategory::One,
+        PluralRule::Romanian if n == 1 => PluralCategory::One,
+        PluralRule::Romanian if n == 0 || (1..=19).contains(&n100) => PluralCategory::Few,
+        _ => PluralCategory::Other,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_loc_format() {
+        assert_eq!(loc_format("{1} of {0}", &[&3, &"x"]), "x of 3");
+        assert_eq!(loc_format("{0}{0}", &[&1]), "11");
+        assert_eq!(loc_format("{2} {x} {", &[&1]), "{2} {x} {");
+    }
+
+    #[test]
+    fn test_plural_category() {
+        use PluralCategory::*;
+
+        let categories =
+            |rule, ns: &[u64]| -> Vec<_> { ns.iter().map(|&n| plural_category(rule, n)).collect() };
+        let ns = [0, 1, 2, 5, 11, 12, 21, 22, 25, 101, 102, 111];
+
+        assert_eq!(categories(PluralRule::Other, &ns), [Other; 12]);
+        assert_eq!(
+            categories(PluralRule::One, &ns),
+            [Other, One, Other, Other, Other, Other, Other, Other, Other, Other, Other, Other]
+        );
+        assert_eq!(
+            categories(PluralRule::ZeroOne, &ns),
+            [One, One, Other, Other, Other, Other, Other, Other, Other, Other, Other, Other]
+        );
+        assert_eq!(
+            categories(PluralRule::EastSlavic, &ns),
+            [Many, One, Few, Many, Many, Many, One, Few, Many, One, Few, Many]
+        );
+        assert_eq!(
+            categories(PluralRule::SerboCroatian, &ns),
+            [Other, One, Few, Other, Other, Other, One, Few, Other, One, Few, Other]
+        );
+        assert_eq!(
+            categories(PluralRule::Czech, &ns),
+            [Other, One, Few, Other, Other, Other, Other, Other, Other, Other, Other, Other]
+        );
+        assert_eq!(
+            categories(PluralRule::Icelandic, &ns),
+            [Other, One, Other, Other, Other, Other, One, Other, Other, One, Other, Other]
+        );
+        assert_eq!(
+            categories(PluralRule::Polish, &ns),
+            [Many, One, Few, Many, Many, Many, Many, Few, Many, Many, Few, Many]
+        );
+        assert_eq!(
+            categories(PluralRule::Romanian, &ns),
+            [Few, One, Few, Few, Few, Few, Other, Other, Other, Few, Few, Few]
+        );
+    }
+
+    #[test]
+    fn test_parse_external() {
+        let text = r#"
+# Comments and unknown sections are skipped.
+[SomeFutureString]
+xx = "?"
+
+[LanguageName]
+tlh-KX = "tlhIngan Hol" # Klingon
+de = 'Deutsch (Schweiz)'
+
+[SearchReplacedCount]
+tlh_kx = { one = "{0} \"x\"ä", other = "{0}" }
+"#;
+        let languages = parse_external(text).unwrap();
+        assert_eq!(languages.len(), 2);
+
+        let tlh = &languages[0];
+        assert_eq!(tlh.tag, "tlh_kx");
+        assert_eq!(tlh.strings[LocId::LanguageName as usize], Some("tlhIngan Hol"));
+        assert_eq!(tlh.strings[LocId::Ok as usize], None);
+        let forms = tlh.plurals[PluralId::SearchReplacedCount as usize].unwrap();
+        assert_eq!(forms[PluralCategory::One as usize], "{0} \"x\"ä");
+        assert_eq!(forms[PluralCategory::Few as usize], "{0}");
+
+        assert_eq!(languages[1].tag, "de");
+        assert_eq!(languages[1].strings[LocId::LanguageName as usize], Some("Deutsch (Schweiz)"));
+
+        // Quoted keys, multi-line strings and inline tables spanning lines.
+        let text = r#"
+["Ok"]
+"pt-BR" = """
+Certo\
+   !"""
+'de' = '''
+"Gut"'''
+
+[SearchReplacedCount]
+pt-BR = {
+    one = "{0} item", # Comments are allowed, too.
+    other = '{0} itens',
+}
+"#;
+        let languages = parse_external(text).unwrap();
+        assert_eq!(languages.len(), 2);
+        assert_eq!(languages[0].tag, "pt_br");
+        assert_eq!(languages[0].strings[LocId::Ok as usize], Some("Certo!"));
+        let forms = languages[0].plurals[PluralId::SearchReplacedCount as usize].unwrap();
+        assert_eq!(forms[PluralCategory::One as usize], "{0} item");
+        assert_eq!(forms[PluralCategory::Other as usize], "{0} itens");
+        assert_eq!(languages[1].strings[LocId::Ok as usize], Some("\"Gut\""));
+
+        assert_eq!(parse_external("de = \"x\"").err(), Some(1));
+        assert_eq!(parse_external("[Ok]\n\nde = \"x").err(), Some(3));
+        assert_eq!(parse_external("[Ok]\nde = \"x\" y").err(), Some(2));
+        assert_eq!(parse_external("[SearchReplacedCount]\nde = { one = \"x\" }").err(), Some(2));
+    }
 }
@@ -3,33 +3,40 @@
 
 #![feature(allocator_api, linked_list_cursors, string_from_utf8_lossy_owned)]
 
+mod config;
 mod documents;
 mod draw_editor;
 mod draw_filepicker;
 mod draw_menubar;
 mod draw_statusbar;
+mod goto;
+mod language;
 mod localization;
+mod perf;
 mod state;
 
 use std::borrow::Cow;
 #[cfg(feature = "debug-latency")]
 use std::fmt::Write;
+use std::fs::{self, File};
 use std::path::{Path, PathBuf};
-use std::time::Duration;
-use std::{env, process};
+use std::time::{Duration, Instant};
+use std::{env, mem, process};
 
 use draw_editor::*;
 use draw_filepicker::*;
 use draw_menubar::*;
 use draw_statusbar::*;
 use edit::arena::{self, Arena, ArenaString, scratch_arena};
+use edit::capabilities::Capabilities;
 use edit::framebuffer::{self, IndexedColor};
-use edit::helpers::{CoordType, KIBI, MEBI, MetricFormatter, Rect, Size};
+use edit::helpers::{CoordType, KIBI, MEBI, MetricFormatter, Point, Rect, Size};
 use edit::input::{self, kbmod, vk};
 use edit::oklab::StraightRgba;
+use edit::recording::{Recorder, Recording};
 use edit::tui::*;
 use edit::vt::{self, Token};
-use edit::{apperr, arena_format, base64, path, sys, unicode};
+use edit::{apperr, arena_format, base64, icu, path, simd, sys, unicode};
 use localization::*;
 use state::*;
 
@@ -42,7 +49,13 @@ fn main() -> process::ExitCode {
     if cfg!(debug_assertions) {
         let hook = std::panic::take_hook();
         std::panic::set_hook(Box::new(move |info| {
-            drop(RestoreModes);
+            // Popping the kitty keyboard flags is harmless if they weren't pushed.
+            drop(RestoreModes {
+                cursor_style: 0,
+                kitty_keyboard: true,
+                win32_input_mode: true,
+                modify_other_keys: None,
+            });
             drop(sys::Deinit);
             hook(info);
         }));
@@ -63,14 +76,53 @@ fn run() -> apperr::Result<()> {
     // Next init `arena`, so that `scratch_arena` works. `loc` depends on it.
     arena::init(SCRATCH_ARENA_CAPACITY)?;
     // Init the `loc` module, so that error messages are localized.
-    localization::init();
+    // Translations in the config directory may add languages, so they're loaded first.
+    // The language picked by the user takes precedence over the OS settings.
+    let translations = match path::app_dir(sys::KnownDir::Config) {
+        Some(dir) => localization::load_external(&dir.join("translations.toml")),
+        None => Ok(()),
+    };
+    let config = config::Config::load();
+    localization::init(config.get("language"));
+    // Searching follows the case folding rules of the user's language (e.g. Turkish "I" and "ı").
+    if let Some(lang) = sys::preferred_languages(&scratch_arena(None)).first() {
+        icu::set_case_folding_locale(lang);
+    }
 
     let mut state = State::new()?;
+    // The translations are optional, and so a broken file only results in an error message.
+    if let Err(err) = translations {
+        state.startup_errors.push(err);
+    }
+    state.goal_column = config.get("goal_column") != Some("false");
+    state.config = config;
     if handle_args(&mut state)? {
         return Ok(());
     }
 
-    // This will reopen stdin if it's redirected (which may fail) and switch
+    // The locale to sort with, optionally followed by options, e.g. `de,numeric`.
+    // Without ICU, sorting falls back to ASCII anyway, so errors are ignored.
+    if let Ok(spec) = env::var("EDIT_COLLATION") {
+        let mut parts = spec.split(',');
+        let locale = parts.next().unwrap_or("");
+        let numeric = parts.any(|p| p == "numeric");
+        _ = icu::set_collation(locale, numeric);
+    }
+
+    // Keys to rewrite before they're dispatched, e.g. `ctrl+h=backspace,backspace=ctrl+h`.
+    // They're set via `key_remap` in settings.conf, which the environment variable overrides.
+    let key_remap = match env::var("EDIT_KEY_REMAP")
+        .ok()
+        .or_else(|| state.config.get("key_remap").map(str::to_string))
+    {
+        Some(spec) => input::KeyRemap::parse(&spec).unwrap_or_else(|| {
+            state.startup_errors.push(apperr::APP_KEY_REMAP_INVALID);
+            input::KeyRemap::default()
+        }),
+        None => input::KeyRemap::default(),
+    };
+
+    // This will reopen stdin and stdout if they're redirected (which may fail) and switch
     // the terminal to raw mode which prevents the user from pressing Ctrl+C.
     // `handle_args` may want to print a help message (must not fail),
     // and reads files (may hang; should be cancelable with Ctrl+C).
@@ -79,9 +131,14 @@ fn run() -> apperr::Result<()> {
 
     let mut vt_parser = vt::Parser::new();
     let mut input_parser = input::Parser::new();
+    input_parser.set_remap(key_remap);
     let mut tui = Tui::new()?;
 
-    let _restore = setup_terminal(&mut tui, &mut state, &mut vt_parser);
+    let restore = setup_terminal(&mut tui, &mut state, &mut vt_parser);
+
+    state.native_clipboard = sys::clipboard_available();
+    tui.setup_native_clipboard(state.native_clipboard);
+    tui.setup_rtl(localization::is_rtl());
 
     state.menubar_color_bg = tui.indexed(IndexedColor::Background).oklab_blend(tui.indexed_alpha(
         IndexedColor::BrightBlue,
@@ -98,10 +155,9 @@ fn run() -> apperr::Result<()> {
         alt: loc(LocId::Alt),
         shift: loc(LocId::Shift),
     });
-    tui.set_floater_default_bg(floater_bg);
-    tui.set_floater_default_fg(floater_fg);
-    tui.set_modal_default_bg(floater_bg);
-    tui.set_modal_default_fg(floater_fg);
+    let floater = ThemeColors { bg: floater_bg, fg: floater_fg };
+    tui.set_theme_colors(ThemeRole::Floater, floater);
+    tui.set_theme_colors(ThemeRole::Modal, floater);
 
     sys::inject_window_size_into_stdin();
 
@@ -109,6 +165,7 @@ fn run() -> apperr::Result<()> {
     let mut last_latency_width = 0;
 
     loop {
+        let time_layout;
         #[cfg(feature = "debug-latency")]
         let time_beg;
         #[cfg(feature = "debug-latency")]
@@ -118,9 +175,15 @@ fn run() -> apperr::Result<()> {
         {
             let scratch = scratch_arena(None);
             let read_timeout = vt_parser.read_timeout().min(tui.read_timeout());
-            let Some(input) = sys::read_stdin(&scratch, read_timeout) else {
+            let Some(input) = read_input(&mut state, &scratch, read_timeout) else {
                 break;
             };
+            handle_file_changes(&mut state);
+            if handle_signals(&mut state, &mut tui, &restore) {
+                break;
+            }
+
+            time_layout = Instant::now();
 
             #[cfg(feature = "debug-latency")]
             {
@@ -145,6 +208,8 @@ fn run() -> apperr::Result<()> {
 
                 more
             } {}
+
+            record_input(&mut state, &tui, &input);
         }
 
         // Continue rendering until the layout has settled.
@@ -166,6 +231,7 @@ fn run() -> apperr::Result<()> {
 
         // Render the UI and write it to the terminal.
         {
+            let time_render = Instant::now();
             let scratch = scratch_arena(None);
             let mut output = tui.render(&scratch);
 
@@ -214,10 +280,37 @@ fn run() -> apperr::Result<()> {
                 last_latency_width = cols;
             }
 
+            if let Some(perf) = &mut state.perf {
+                let now = Instant::now();
+                perf.record_frame(
+                    time_render - time_layout,
+                    tui.buffer_render_time(),
+                    now - time_render,
+                );
+                perf.write_overlay(&mut output, tui.size().width);
+            }
+
+            if tui.capabilities().synchronized_output && !output.is_empty() {
+                // BSU/ESU: Lets the terminal apply the entire frame at once.
+                output.replace_range(0..0, "\x1b[?2026h");
+                output.push_str("\x1b[?2026l");
+            }
+
+            let time_flush = Instant::now();
             sys::write_stdout(&output);
+
+            if let Some(perf) = &mut state.perf {
+                perf.record_flush(time_flush.elapsed());
+            }
         }
     }
 
+    // The summary must be printed after leaving the alternate screen buffer.
+    drop(restore);
+    if let Some(perf) = &state.perf {
+        sys::write_stdout(&perf.summary());
+    }
+
     Ok(())
 }
 
@@ -248,10 +341,23 @@ fn handle_args(state: &mut State) -> apperr::Result<bool> {
                 print_version();
                 return Ok(true);
             }
+            if arg == "--perf" {
+                state.perf = Some(Default::default());
+                continue;
+            }
+            if let Some(path) = arg.to_str().and_then(|a| a.strip_prefix("--record=")) {
+                state.record_file = Some(File::create(path::expand(&cwd, Path::new(path)))?);
+                continue;
+            }
+            if let Some(path) = arg.to_str().and_then(|a| a.strip_prefix("--replay=")) {
+                let path = path::expand(&cwd, Path::new(path));
+                let recording = Recording::parse(&fs::read_to_string(path)?)?;
+                state.replay = Some(Replay { recording, next: 0, start: Instant::now() });
+                continue;
+            }
         }
 
-        let p = cwd.join(Path::new(&arg));
-        let p = path::normalize(&p);
+        let p = path::expand(&cwd, Path::new(&arg));
         if p.is_dir() {
             state.wants_file_picker = StateFilePicker::Open;
             dir = Some(p);
@@ -290,12 +396,87 @@ fn print_help() {
         "Options:\n",
         "    -h, --help       Print this help message\n",
         "    -v, --version    Print the version number\n",
+        "        --perf       Show frame timings and print a summary on exit\n",
+        "        --record=FILE    Record all input into FILE\n",
+        "        --replay=FILE    Play back the input recorded in FILE\n",
+        "\n",
+        "Environment:\n",
+        "    EDIT_KEY_REMAP    Keys to rewrite, e.g. \"ctrl+h=backspace,capslock=escape\"\n",
+        "    EDIT_COLLATION    The locale to sort with, e.g. \"de\" or \"sv,numeric\"\n",
         "\n",
         "Arguments:\n",
         "    FILE[:LINE[:COLUMN]]    The file to open, optionally with line and column (e.g., foo.txt:123:45)\n",
     ));
 }
 
+/// Reads the next batch of input, from the `--replay` recording
+/// if there's one, and from stdin otherwise.
+fn read_input<'a>(
+    state: &mut State,
+    arena: &'a Arena,
+    timeout: Duration,
+) -> Option<ArenaString<'a>> {
+    if let Some(replay) = &mut state.replay {
+        if let Some(event) = replay.recording.events.get(replay.next) {
+            // Recorded timeouts are empty reads, so we don't need to honor `timeout` ourselves.
+            let elapsed = replay.start.elapsed();
+            if event.time > elapsed {
+                std::thread::sleep(event.time - elapsed);
+            }
+            replay.next += 1;
+            return Some(ArenaString::from_str(arena, &event.input));
+        }
+        state.replay = None;
+    }
+
+    sys::read_stdin(arena, timeout)
+}
+
+/// Reacts to [`sys::Signal`]s. Returns `true` if the editor should exit.
+fn handle_signals(state: &mut State, tui: &mut Tui, modes: &RestoreModes) -> bool {
+    while let Some(signal) = sys::take_signal() {
+        match signal {
+            sys::Signal::Terminate => {
+                state.documents.save_recovery();
+                return true;
+            }
+            sys::Signal::Suspend => {
+                modes.restore();
+                sys::suspend();
+                modes.reenable();
+                state.osc_title_file_status = Default::default();
+                tui.invalidate();
+            }
+            // Someone else may have drawn over us while we were stopped.
+            sys::Signal::Resume => tui.invalidate(),
+        }
+    }
+    false
+}
+
+/// Reacts to changes of files and directories watched via [`sys::file_watch`].
+fn handle_file_changes(state: &mut State) {
+    for path in sys::file_watch_changes() {
+        if state.file_picker_watched_dir.as_ref() == Some(&path) {
+            state.file_picker_entries = None;
+        }
+        // If the file is still being written to, the next change notification will retry.
+        _ = state.documents.reload_changed(&path);
+    }
+}
+
+/// Appends the `input` that was just processed to the `--record` recording.
+fn record_input(state: &mut State, tui: &Tui, input: &str) {
+    if let Some(file) = state.record_file.take() {
+        state.recorder = Recorder::new(file, tui.size()).ok();
+    }
+    if let Some(recorder) = &mut state.recorder
+        && recorder.record(input).is_err()
+    {
+        state.recorder = None;
+    }
+}
+
 fn print_version() {
     sys::write_stdout(concat!("edit version ", env!("CARGO_PKG_VERSION"), "\n"));
 }
@@ -305,7 +486,8 @@ fn draw(ctx: &mut Context, state: &mut State) {
     draw_editor(ctx, state);
     draw_statusbar(ctx, state);
 
-    if state.wants_close {
+    // A pending save (e.g. one asking about a conflict) must finish first.
+    if state.wants_close && !state.wants_save {
         draw_handle_wants_close(ctx, state);
     }
     if state.wants_exit {
@@ -329,13 +511,31 @@ fn draw(ctx: &mut Context, state: &mut State) {
     if state.wants_about {
         draw_dialog_about(ctx, state);
     }
+    if state.wants_display_language {
+        draw_dialog_display_language(ctx, state);
+    }
     if ctx.clipboard_ref().wants_host_sync() {
         draw_handle_clipboard_change(ctx, state);
     }
+    for err in mem::take(&mut state.startup_errors) {
+        error_log_add(ctx, state, err);
+    }
     if state.error_log_count != 0 {
         draw_error_log(ctx, state);
     }
 
+    // Above the statusbar.
+    let size = ctx.size();
+    ctx.toasts("toast", Point { x: size.width, y: size.height - 1 });
+
+    // Chords have to be checked in every frame. See `Context::consume_chord()`.
+    if ctx.consume_chord(kbmod::CTRL | vk::K, kbmod::CTRL | vk::L)
+        && let Some(doc) = state.documents.active()
+    {
+        doc.buffer.borrow_mut().sort_selected_lines();
+        ctx.needs_rerender();
+    }
+
     if let Some(key) = ctx.keyboard_input() {
         // Shortcuts that are not handled as part of the textarea, etc.
 
@@ -355,16 +555,19 @@ fn draw(ctx: &mut Context, state: &mut State) {
             state.wants_exit = true;
         } else if key == kbmod::CTRL | vk::G {
             state.wants_goto = true;
-        } else if key == kbmod::CTRL | vk::F && state.wants_search.kind != StateSearchKind::Disabled
-        {
+            state.goto_needle = ":".to_string();
+        } else if key == kbmod::CTRL | vk::E {
+            state.wants_goto = true;
+        } else if key == kbmod::CTRL | vk::F {
             state.wants_search.kind = StateSearchKind::Search;
             state.wants_search.focus = true;
-        } else if key == kbmod::CTRL | vk::R && state.wants_search.kind != StateSearchKind::Disabled
-        {
+        } else if key == kbmod::CTRL | vk::R {
             state.wants_search.kind = StateSearchKind::Replace;
             state.wants_search.focus = true;
         } else if key == vk::F3 {
             search_execute(ctx, state, SearchAction::Search);
+        } else if key == kbmod::SHIFT | vk::F3 {
+            search_execute(ctx, state, SearchAction::SearchPrev);
         } else {
             return;
         }
@@ -389,13 +592,19 @@ fn draw_handle_wants_exit(_ctx: &mut Context, state: &mut State) {
     }
 }
 
+/// Terminals show only so much of the title in their tab bar.
+const TERMINAL_TITLE_PATH_WIDTH: CoordType = 60;
+
 fn write_terminal_title(output: &mut ArenaString, state: &mut State) {
-    let (filename, dirty) = state
-        .documents
-        .active()
-        .map_or(("", false), |d| (&d.filename, d.buffer.borrow().is_dirty()));
+    let (filename, dirty) = state.documents.active().map_or((Cow::Borrowed(""), false), |d| {
+        let filename = match &d.path {
+            Some(p) => Cow::Owned(path::shorten_for_display(p, TERMINAL_TITLE_PATH_WIDTH)),
+            None => Cow::Borrowed(d.filename.as_str()),
+        };
+        (filename, d.buffer.borrow().is_dirty())
+    });
 
-    if filename == state.osc_title_file_status.filename
+    if *filename == state.osc_title_file_status.filename
         && dirty == state.osc_title_file_status.dirty
     {
         return;
@@ -406,7 +615,7 @@ fn write_terminal_title(output: &mut ArenaString, state: &mut State) {
         if dirty {
             output.push_str("● ");
         }
-        output.push_str(&sanitize_control_chars(filename));
+        output.push_str(&sanitize_control_chars(&filename));
         output.push_str(" - ");
     }
     output.push_str("edit\x1b\\");
@@ -418,6 +627,22 @@ fn write_terminal_title(output: &mut ArenaString, state: &mut State) {
 const LARGE_CLIPBOARD_THRESHOLD: usize = 128 * KIBI;
 
 fn draw_handle_clipboard_change(ctx: &mut Context, state: &mut State) {
+    // If writing to the OS clipboard fails, we still have OSC 52.
+    if state.native_clipboard {
+        let clipboard = ctx.clipboard_ref();
+        // Only the Windows clipboard can hold several formats at once.
+        let html = cfg!(windows).then(|| {
+            clipboard.read_html(
+                ctx.indexed(IndexedColor::Foreground),
+                ctx.indexed(IndexedColor::Background),
+            )
+        });
+        if sys::clipboard_write(clipboard.read(), html.as_deref()).is_ok() {
+            ctx.clipboard_mut().mark_as_synchronized();
+            return;
+        }
+    }
+
     let data_len = ctx.clipboard_ref().read().len();
 
     if state.osc_clipboard_always_send || data_len < LARGE_CLIPBOARD_THRESHOLD {
@@ -510,6 +735,7 @@ fn draw_handle_clipboard_change(ctx: &mut Context, state: &mut State) {
 
 #[cold]
 fn write_osc_clipboard(tui: &mut Tui, state: &mut State, output: &mut ArenaString) {
+    let multiplexer = tui.capabilities().multiplexer;
     let clipboard = tui.clipboard_mut();
     let data = clipboard.read();
 
@@ -519,22 +745,65 @@ fn write_osc_clipboard(tui: &mut Tui, state: &mut State, output: &mut ArenaStrin
         // the size of the `output` from e.g. 100MB to 200MB. Not good.
         // We can avoid that by reserving the needed size in advance.
         output.reserve_exact(base64::encode_len(data.len()) + 16);
+        let beg = output.len();
         output.push_str("\x1b]52;c;");
         base64::encode(output, data);
         output.push_str("\x1b\\");
+        vt::wrap_passthrough(output, beg, multiplexer);
     }
 
     state.osc_clipboard_sync = false;
 }
 
-struct RestoreModes;
+struct RestoreModes {
+    /// The DECSCUSR cursor style at startup. 0 is the terminal's default.
+    cursor_style: u16,
+    /// Whether we pushed kitty keyboard protocol flags that need to be popped.
+    kitty_keyboard: bool,
+    /// Whether we enabled win32-input-mode.
+    win32_input_mode: bool,
+    /// The modifyOtherKeys level to restore, if we changed it.
+    modify_other_keys: Option<u16>,
+}
 
-impl Drop for RestoreModes {
-    fn drop(&mut self) {
+impl RestoreModes {
+    /// Enables the modes again after [`RestoreModes::restore`], e.g. when resuming from a suspend.
+    fn reenable(&self) {
+        sys::write_stdout("\x1b[?1049h\x1b[?1002;1006;2004h\x1b[?1036h");
+        if self.kitty_keyboard {
+            sys::write_stdout("\x1b[>3u");
+        }
+        if self.win32_input_mode {
+            sys::write_stdout("\x1b[?9001h");
+        }
+        if self.modify_other_keys.is_some() {
+            sys::write_stdout("\x1b[>4;2m");
+        }
+    }
+
+    fn restore(&self) {
         // Same as in the beginning but in the reverse order.
-        // It also includes DECSCUSR 0 to reset the cursor style and DECTCEM to show the cursor.
+        // It also includes DECSCUSR to restore the cursor style and DECTCEM to show the cursor.
         // We specifically don't reset mode 1036, because most applications expect it to be set nowadays.
-        sys::write_stdout("\x1b[0 q\x1b[?25h\x1b]0;\x07\x1b[?1002;1006;2004l\x1b[?1049l");
+        if self.kitty_keyboard {
+            sys::write_stdout("\x1b[<u");
+        }
+        if self.win32_input_mode {
+            sys::write_stdout("\x1b[?9001l");
+        }
+        if let Some(level) = self.modify_other_keys {
+            sys::write_stdout(&format!("\x1b[>4;{level}m"));
+        }
+        sys::write_stdout(&format!(
+            "\x1b[{} q\x1b[?25h\x1b]0;\x07\x1b[?1002;1006;2004l\x1b[?1049l",
+            self.cursor_style
+        ));
+    }
+}
+
+impl Drop for RestoreModes {
+    fn drop(&mut self) {
+        self.restore();
     }
 }
 
@@ -559,37 +828,63 @@ fn setup_terminal(tui: &mut Tui, state: &mut State, vt_parser: &mut vt::Parser)
         // actual display width of the character and assigns it columns accordingly.
         // We detect it by writing the character and asking for the cursor position.
         "\r…\x1b[6n",
-        // CSI c reports the terminal capabilities.
-        // It also helps us to detect the end of the responses, because not all
-        // terminals support the OSC queries, but all of them support CSI c.
-        "\x1b[c",
+        // Same for emoji presentation sequences (VS16), which some terminals draw narrow.
+        "\r❤️\x1b[6n",
     ));
+    // This ends with CSI c, which reports the terminal capabilities.
+    // It also helps us to detect the end of the responses, because not all
+    // terminals support the other queries, but all of them support CSI c.
+    sys::write_stdout(Capabilities::QUERIES);
 
     let mut done = false;
     let mut osc_buffer = String::new();
     let mut indexed_colors = framebuffer::DEFAULT_THEME;
-    let mut color_responses = 0;
+    // A bitmask of the `indexed_colors` the terminal reported.
+    let mut color_responses = 0u32;
     let mut ambiguous_width = 1;
+    let mut emoji_presentation_width = 2;
+    let mut cursor_position_reports = 0;
+    let mut capabilities = Capabilities::from_env();
+
+    // We explicitly set a high read timeout, because we're not
+    // waiting for user keyboard input. If we encounter a lone ESC,
+    // it's unlikely to be from a ESC keypress, but rather from a VT sequence.
+    // It's a deadline for all responses, so that a terminal that trickles in
+    // unrelated input can't delay the startup indefinitely.
+    let deadline = Instant::now() + Duration::from_secs(3);
 
     while !done {
         let scratch = scratch_arena(None);
 
-        // We explicitly set a high read timeout, because we're not
-        // waiting for user keyboard input. If we encounter a lone ESC,
-        // it's unlikely to be from a ESC keypress, but rather from a VT sequence.
-        let Some(input) = sys::read_stdin(&scratch, Duration::from_secs(3)) else {
+        let timeout = deadline.saturating_duration_since(Instant::now());
+        if timeout.is_zero() {
+            break;
+        }
+        let Some(input) = sys::read_stdin(&scratch, timeout) else {
             break;
         };
 
         let mut vt_stream = vt_parser.parse(&input);
         while let Some(token) = vt_stream.next() {
+            if capabilities.parse_response(&token) {
+                continue;
+            }
             match token {
                 Token::Csi(csi) => match csi.final_byte {
                     'c' => done = true,
                     // CPR (Cursor Position Report) response.
-                    'R' => ambiguous_width = csi.params[1] as CoordType - 1,
+                    'R' => {
+                        let width = csi.params[1] as CoordType - 1;
+                        match cursor_position_reports {
+                            0 => ambiguous_width = width,
+                            1 => emoji_presentation_width = width,
+                            _ => {}
+                        }
+                        cursor_position_reports += 1;
+                    }
                     _ => {}
                 },
+                Token::Cancel => osc_buffer.clear(),
                 Token::Osc { mut data, partial } => {
                     if partial {
                         osc_buffer.push_str(data);
@@ -602,16 +897,16 @@ fn setup_terminal(tui: &mut Tui, state: &mut State, vt_parser: &mut vt::Parser)
 
                     let mut splits = data.split_terminator(';');
 
-                    let color = match splits.next().unwrap_or("") {
+                    let index = match splits.next().unwrap_or("") {
                         // The response is `4;<color>;rgb:<r>/<g>/<b>`.
                         "4" => match splits.next().unwrap_or("").parse::<usize>() {
-                            Ok(val) if val < 16 => &mut indexed_colors[val],
+                            Ok(val) if val < 16 => val,
                             _ => continue,
                         },
                         // The response is `10;rgb:<r>/<g>/<b>`.
-                        "10" => &mut indexed_colors[IndexedColor::Foreground as usize],
+                        "10" => IndexedColor::Foreground as usize,
                         // The response is `11;rgb:<r>/<g>/<b>`.
-                        "11" => &mut indexed_colors[IndexedColor::Background as usize],
+                        "11" => IndexedColor::Background as usize,
                         _ => continue,
                     };
 
@@ -637,8 +932,8 @@ fn setup_terminal(tui: &mut Tui, state: &mut State, vt_parser: &mut vt::Parser)
                         }
                     }
 
-                    *color = StraightRgba::from_le(rgb | 0xff000000);
-                    color_responses += 1;
+                    indexed_colors[index] = StraightRgba::from_le(rgb | 0xff000000);
+                    color_responses |= 1 << index;
                     osc_buffer.clear();
                 }
                 _ => {}
@@ -646,16 +941,55 @@ fn setup_terminal(tui: &mut Tui, state: &mut State, vt_parser: &mut vt::Parser)
         }
     }
 
-    if ambiguous_width == 2 {
-        unicode::setup_ambiguous_width(2);
+    if ambiguous_width == 2 || emoji_presentation_width == 1 {
+        if ambiguous_width == 2 {
+            unicode::setup_ambiguous_width(2);
+        }
+        if emoji_presentation_width == 1 {
+            unicode::setup_emoji_presentation_width(1);
+        }
         state.documents.reflow_all();
     }
 
-    if color_responses == indexed_colors.len() {
+    tui.setup_capabilities(capabilities);
+    let cursor_style = capabilities.cursor_style.unwrap_or(0);
+
+    // Kitty keyboard protocol flags 1 (disambiguate escape codes) and 2 (report event types).
+    let kitty_keyboard = capabilities.kitty_keyboard;
+    if kitty_keyboard {
+        sys::write_stdout("\x1b[>3u");
+    }
+
+    // Otherwise, Windows Terminal's win32-input-mode, which reports just as much.
+    let win32_input_mode = capabilities.win32_input_mode && !kitty_keyboard;
+    if win32_input_mode {
+        sys::write_stdout("\x1b[?9001h");
+    }
+
+    // Otherwise, fall back to xterm's modifyOtherKeys level 2, which reports
+    // all keys with modifiers, if the terminal understood the query for it.
+    let modify_other_keys =
+        capabilities.modify_other_keys.filter(|_| !kitty_keyboard && !win32_input_mode);
+    if modify_other_keys.is_some() {
+        sys::write_stdout("\x1b[>4;2m");
+    }
+
+    let all_colors = (1 << indexed_colors.len()) - 1;
+    let default_colors =
+        (1 << IndexedColor::Background as u32) | (1 << IndexedColor::Foreground as u32);
+    if color_responses == all_colors {
         tui.setup_indexed_colors(indexed_colors);
+    } else if color_responses & default_colors == default_colors {
+        // Some terminals only report their default colors. Derive the rest from those,
+        // so that the UI colors we blend from the palette still match the terminal.
+        tui.setup_indexed_colors(framebuffer::generate_palette(
+            indexed_colors[IndexedColor::Background as usize],
+            indexed_colors[IndexedColor::Foreground as usize],
+            framebuffer::DEFAULT_THEME[IndexedColor::Blue as usize],
+        ));
     }
 
-    RestoreModes
+    RestoreModes { cursor_style, kitty_keyboard, win32_input_mode, modify_other_keys }
 }
 
 /// Strips all C0 control characters from the string and replaces them with "_".
@@ -663,7 +997,17 @@ fn setup_terminal(tui: &mut Tui, state: &mut State, vt_parser: &mut vt::Parser)
 /// Jury is still out on whether this should also strip C1 control characters.
 /// That requires parsing UTF8 codepoints, which is annoying.
 fn sanitize_control_chars(text: &str) -> Cow<'_, str> {
-    if let Some(off) = text.bytes().position(|b| (..0x20).contains(&b)) {
+    let bytes = text.as_bytes();
+    let mut off = 0;
+    // `find_control` also stops at DEL and C1 controls, which we skip over.
+    while {
+        off = simd::find_control(bytes, off);
+        off < bytes.len() && bytes[off] >= 0x20
+    } {
+        off += 1;
+    }
+
+    if off < bytes.len() {
         let mut sanitized = text.to_string();
         // SAFETY: We only search for ASCII and replace it with ASCII.
         let vec = unsafe { sanitized.as_bytes_mut() };
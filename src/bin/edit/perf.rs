@@ -0,0 +1,178 @@
+// Copyright (c) Microsoft Corporation.
+// Licensed under the MIT License.
+
+//! Frame timing instrumentation, enabled via `--perf`.
+//! With the `debug-arena-stats` feature, it also reports scratch arena usage.
+
+use std::fmt::Write as _;
+use std::time::Duration;
+
+use edit::arena::ArenaString;
+#[cfg(feature = "debug-arena-stats")]
+use edit::arena::scratch_arena_stats;
+use edit::helpers::CoordType;
+
+#[derive(Default)]
+struct PerfTimer {
+    last: Duration,
+    total: Duration,
+    max: Duration,
+}
+
+impl PerfTimer {
+    fn record(&mut self, duration: Duration) {
+        self.last = duration;
+        self.total += duration;
+        self.max = self.max.max(duration);
+    }
+}
+
+/// Accumulates how long each frame spent in its three phases:
+/// * layout: running `draw()` until the UI has settled
+/// * render: drawing into the framebuffer and diffing it into VT sequences
+/// * flush: writing those to the terminal
+///
+/// The part of the render phase spent rendering the text buffers is tracked separately as "buffer".
+#[derive(Default)]
+pub struct PerfStats {
+    frames: u32,
+    layout: PerfTimer,
+    buffer: PerfTimer,
+    render: PerfTimer,
+    flush: PerfTimer,
+    #[cfg(feature = "debug-arena-stats")]
+    arena: ArenaFrameStats,
+    last_overlay_width: CoordType,
+}
+
+/// Scratch arena allocations per frame, derived from the running totals.
+#[cfg(feature = "debug-arena-stats")]
+#[derive(Default)]
+struct ArenaFrameStats {
+    total_allocations: usize,
+    total_bytes: usize,
+    last_allocations: usize,
+    last_bytes: usize,
+    max_allocations: usize,
+    max_bytes: usize,
+}
+
+impl PerfStats {
+    pub fn record_frame(&mut self, layout: Duration, buffer: Duration, render: Duration) {
+        self.frames += 1;
+        self.layout.record(layout);
+        self.buffer.record(buffer);
+        self.render.record(render);
+
+        #[cfg(feature = "debug-arena-stats")]
+        {
+            let stats = scratch_arena_stats();
+            let a = &mut self.arena;
+            a.last_allocations = stats.allocations - a.total_allocations;
+            a.last_bytes = stats.allocated_bytes - a.total_bytes;
+            a.total_allocations = stats.allocations;
+            a.total_bytes = stats.allocated_bytes;
+            a.max_allocations = a.max_allocations.max(a.last_allocations);
+            a.max_bytes = a.max_bytes.max(a.last_bytes);
+        }
+    }
+
+    pub fn record_flush(&mut self, flush: Duration) {
+        self.flush.record(flush);
+    }
+
+    /// Prints the timings of the last frame in the top right corner.
+    /// The flush time is necessarily that of the frame before.
+    pub fn write_overlay(&mut self, output: &mut ArenaString, width: CoordType) {
+        let mut status = String::new();
+        _ = write!(
+            status,
+            "L {:.2}ms B {:.2}ms R {:.2}ms F {:.2}ms",
+            millis(self.layout.last),
+            millis(self.buffer.last),
+            millis(self.render.last),
+            millis(self.flush.last),
+        );
+        #[cfg(feature = "debug-arena-stats")]
+        {
+            let a = &self.arena;
+            _ = write!(status, " A {} {:.1}KiB", a.last_allocations, a.last_bytes as f64 / 1024.0);
+        }
+
+        // The status is pure ASCII, so its length equals its width.
+        let cols = status.len() as CoordType;
+        // Since the status may shrink and grow, we may have to overwrite the previous one with whitespace.
+        let padding = (self.last_overlay_width - cols).max(0);
+
+        // To avoid moving the cursor, push and pop it onto the VT cursor stack.
+        _ = write!(
+            output,
+            "\x1b7\x1b[0;44;97m\x1b[1;{0}H{1:2$}{3}\x1b8",
+            (width - cols - padding + 1).max(1),
+            "",
+            padding as usize,
+            status
+        );
+
+        self.last_overlay_width = cols;
+    }
+
+    /// Returns a human-readable summary to be printed on exit.
+    /// It uses CRLF, because the terminal is still in raw mode at that point.
+    pub fn summary(&self) -> String {
+        let frames = self.frames.max(1);
+        let mut str = String::new();
+
+        _ = write!(str, "Frames: {}\r\n", self.frames);
+        str.push_str("           avg         max\r\n");
+        for (name, timer) in [
+            ("layout", &self.layout),
+            ("buffer", &self.buffer),
+            ("render", &self.render),
+            ("flush", &self.flush),
+        ] {
+            _ = write!(
+                str,
+                "{:<6} {:>9.3}ms {:>9.3}ms\r\n",
+                name,
+                millis(timer.total / frames),
+                millis(timer.max)
+            );
+        }
+
+        #[cfg(feature = "debug-arena-stats")]
+        {
+            let stats = scratch_arena_stats();
+            let a = &self.arena;
+            _ = write!(
+                str,
+                "Arena allocations per frame: {} avg, {} max\r\n",
+                a.total_allocations / frames as usize,
+                a.max_allocations
+            );
+            _ = write!(
+                str,
+                "Arena bytes per frame: {} avg, {} max\r\n",
+                a.total_bytes / frames as usize,
+                a.max_bytes
+            );
+            _ = write!(str, "Arena high-water mark: {} bytes\r\n", stats.high_water_mark);
+            if let Some(location) = stats.largest_scope_location {
+                _ = write!(
+                    str,
+                    "Largest scratch scope: {} bytes at {location}\r\n",
+                    stats.largest_scope
+                );
+            }
+            if stats.live_bytes != 0 {
+                _ = write!(str, "Leaked: {} bytes\r\n", stats.live_bytes);
+            }
+        }
+
+        str
+    }
+}
+
+fn millis(d: Duration) -> f64 {
+    d.as_secs_f64() * 1000.0
+}
@@ -3,17 +3,26 @@
 
 use std::borrow::Cow;
 use std::ffi::{OsStr, OsString};
+use std::fmt::Write as _;
+use std::fs::File;
 use std::mem;
 use std::path::{Path, PathBuf};
+use std::sync::atomic::AtomicBool;
+use std::sync::{Arc, mpsc};
+use std::time::Instant;
 
 use edit::framebuffer::IndexedColor;
 use edit::helpers::*;
 use edit::oklab::StraightRgba;
+use edit::recording::{Recorder, Recording};
 use edit::tui::*;
 use edit::{apperr, buffer, icu, sys};
 
+use crate::config::Config;
 use crate::documents::DocumentManager;
+use crate::goto;
 use crate::localization::*;
+use crate::perf::PerfStats;
 
 #[repr(transparent)]
 pub struct FormatApperr(apperr::Error);
@@ -26,15 +35,33 @@ impl From<apperr::Error> for FormatApperr {
 
 impl std::fmt::Display for FormatApperr {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        match self.0 {
+        for context in self.0.contexts() {
+            write!(f, "{context}: ")?;
+        }
+        match *self.0.root() {
             apperr::APP_ICU_MISSING => f.write_str(loc(LocId::ErrorIcuMissing)),
+            apperr::APP_JSON_INVALID => f.write_str(loc(LocId::ErrorJsonInvalid)),
+            apperr::APP_FILE_TOO_LARGE => f.write_str(loc(LocId::ErrorFileTooLarge)),
+            apperr::APP_KEY_REMAP_INVALID => f.write_str(loc(LocId::ErrorKeyRemapInvalid)),
+            apperr::APP_TRANSLATIONS_INVALID => f.write_str(loc(LocId::ErrorTranslationsInvalid)),
             apperr::Error::App(code) => write!(f, "Unknown app error code: {code}"),
             apperr::Error::Icu(code) => icu::apperr_format(f, code),
             apperr::Error::Sys(code) => sys::apperr_format(f, code),
+            apperr::Error::Context(_) => unreachable!(),
         }
     }
 }
 
+/// An entry in [`State::error_log`].
+#[derive(Default)]
+pub struct ErrorLogEntry {
+    /// What went wrong, e.g. "Couldn't save foo.txt".
+    pub message: String,
+    /// The remaining context and the underlying error, one per line.
+    /// They're only shown on request, and empty if there's nothing to add.
+    pub details: String,
+}
+
 pub struct DisplayablePathBuf {
     value: PathBuf,
     str: Cow<'static, str>,
@@ -100,7 +127,6 @@ pub struct StateSearch {
 #[derive(Clone, Copy, PartialEq, Eq)]
 pub enum StateSearchKind {
     Hidden,
-    Disabled,
     Search,
     Replace,
 }
@@ -127,23 +153,45 @@ pub struct OscTitleFileStatus {
     pub dirty: bool,
 }
 
+/// A long-running operation, shown in the statusbar until it's removed.
+pub struct BackgroundTask {
+    pub title: String,
+    /// From 0 to 1, or `None` if the duration is unknown.
+    pub progress: Option<f32>,
+    /// Set when the user cancels the task. The operation should check it regularly.
+    pub cancel: Arc<AtomicBool>,
+}
+
+impl BackgroundTask {
+    pub fn new(title: String) -> Self {
+        Self { title, progress: None, cancel: Default::default() }
+    }
+}
+
 pub struct State {
     pub menubar_color_bg: StraightRgba,
     pub menubar_color_fg: StraightRgba,
 
     pub documents: DocumentManager,
+    pub config: Config,
+    /// Whether the cursor returns to its column when moving up or down through shorter lines.
+    pub goal_column: bool,
 
     // A ring buffer of the last 10 errors.
-    pub error_log: [String; 10],
+    pub error_log: [ErrorLogEntry; 10],
     pub error_log_index: usize,
     pub error_log_count: usize,
+    pub error_log_show_details: bool,
+    /// Errors that occurred before the TUI was up. They're added to the error log in the first frame.
+    pub startup_errors: Vec<apperr::Error>,
 
     pub wants_file_picker: StateFilePicker,
     pub file_picker_pending_dir: DisplayablePathBuf,
     pub file_picker_pending_dir_revision: u64, // Bumped every time `file_picker_pending_dir` changes.
     pub file_picker_pending_name: PathBuf,
     pub file_picker_entries: Option<[Vec<DisplayablePathBuf>; 3]>, // ["..", directories, files]
-    pub file_picker_overwrite_warning: Option<PathBuf>,            // The path the warning is about.
+    pub file_picker_watched_dir: Option<PathBuf>, // Refreshes `file_picker_entries` when it changes.
+    pub file_picker_overwrite_warning: Option<PathBuf>, // The path the warning is about.
     pub file_picker_autocomplete: Vec<DisplayablePathBuf>,
 
     pub wants_search: StateSearch,
@@ -155,25 +203,55 @@ pub struct State {
     pub wants_encoding_picker: bool,
     pub wants_encoding_change: StateEncodingChange,
     pub encoding_picker_needle: String,
-    pub encoding_picker_results: Option<Vec<icu::Encoding>>,
+    /// The matching encodings and the positions of the matched characters in their labels.
+    pub encoding_picker_results: Option<Vec<(icu::Encoding, Vec<usize>)>>,
+    /// The likely encoding of the file, preselected when reopening it.
+    pub encoding_picker_detected: Option<&'static str>,
 
     pub wants_save: bool,
-    pub wants_statusbar_focus: bool,
+    /// Set while asking whether to overwrite a file that was changed on disk.
+    pub save_conflict: bool,
     pub wants_indentation_picker: bool,
+    pub wants_language_picker: bool,
     pub wants_go_to_file: bool,
     pub wants_about: bool,
+    pub wants_display_language: bool,
+    /// The language previewed in the display language dialog. Only saved once confirmed.
+    pub display_language_preview: Option<Option<&'static str>>,
     pub wants_close: bool,
     pub wants_exit: bool,
     pub wants_goto: bool,
-    pub goto_target: String,
+    pub goto_needle: String,
     pub goto_invalid: bool,
+    /// `None` if they need to be updated. See [`crate::goto::search`].
+    pub goto_results: Option<Vec<goto::Item>>,
+    /// The files in the working directory, collected when first needed.
+    pub goto_files: Option<Vec<PathBuf>>,
+    /// Receives [`State::goto_files`] from the background walk. See [`crate::goto::poll_file_walk`].
+    pub goto_walk: Option<mpsc::Receiver<Vec<PathBuf>>>,
 
     pub osc_title_file_status: OscTitleFileStatus,
+    /// Whether the OS clipboard is accessible directly. OSC 52 is the fallback.
+    pub native_clipboard: bool,
     pub osc_clipboard_sync: bool,
     pub osc_clipboard_always_send: bool,
+    pub perf: Option<PerfStats>,
+    /// `--record`: The file to record the input into. It's moved into
+    /// `recorder` after the first frame, once the terminal size is known.
+    pub record_file: Option<File>,
+    pub recorder: Option<Recorder<File>>,
+    /// `--replay`: Recorded input that is played back before reading from stdin.
+    pub replay: Option<Replay>,
+    pub background_task: Option<BackgroundTask>,
     pub exit: bool,
 }
 
+pub struct Replay {
+    pub recording: Recording,
+    pub next: usize,
+    pub start: Instant,
+}
+
 impl State {
     pub fn new() -> apperr::Result<Self> {
         Ok(Self {
@@ -181,16 +259,21 @@ impl State {
             menubar_color_fg: StraightRgba::zero(),
 
             documents: Default::default(),
+            config: Default::default(),
+            goal_column: true,
 
-            error_log: [const { String::new() }; 10],
+            error_log: Default::default(),
             error_log_index: 0,
             error_log_count: 0,
+            error_log_show_details: false,
+            startup_errors: Vec::new(),
 
             wants_file_picker: StateFilePicker::None,
             file_picker_pending_dir: Default::default(),
             file_picker_pending_dir_revision: 0,
             file_picker_pending_name: Default::default(),
             file_picker_entries: None,
+            file_picker_watched_dir: None,
             file_picker_overwrite_warning: None,
             file_picker_autocomplete: Vec::new(),
 
@@ -203,22 +286,35 @@ impl State {
             wants_encoding_picker: false,
             encoding_picker_needle: Default::default(),
             encoding_picker_results: Default::default(),
+            encoding_picker_detected: None,
 
             wants_save: false,
-            wants_statusbar_focus: false,
+            save_conflict: false,
             wants_encoding_change: StateEncodingChange::None,
             wants_indentation_picker: false,
+            wants_language_picker: false,
             wants_go_to_file: false,
             wants_about: false,
+            wants_display_language: false,
+            display_language_preview: None,
             wants_close: false,
             wants_exit: false,
             wants_goto: false,
-            goto_target: Default::default(),
+            goto_needle: Default::default(),
             goto_invalid: false,
+            goto_results: None,
+            goto_files: None,
+            goto_walk: None,
 
             osc_title_file_status: Default::default(),
+            native_clipboard: false,
             osc_clipboard_sync: false,
             osc_clipboard_always_send: false,
+            perf: None,
+            record_file: None,
+            recorder: None,
+            replay: None,
+            background_task: None,
             exit: false,
         })
     }
@@ -231,9 +327,25 @@ pub fn draw_add_untitled_document(ctx: &mut Context, state: &mut State) {
 }
 
 pub fn error_log_add(ctx: &mut Context, state: &mut State, err: apperr::Error) {
-    let msg = format!("{}", FormatApperr::from(err));
-    if !msg.is_empty() {
-        state.error_log[state.error_log_index] = msg;
+    let mut contexts = err.contexts();
+    let entry = match contexts.next() {
+        // With context, the message says what failed and the details say why.
+        Some(message) => {
+            let mut details = String::new();
+            for context in contexts {
+                details.push_str(context);
+                details.push('\n');
+            }
+            _ = write!(details, "{}", FormatApperr::from(err.root().clone()));
+            ErrorLogEntry { message: message.to_string(), details }
+        }
+        None => ErrorLogEntry {
+            message: format!("{}", FormatApperr::from(err.root().clone())),
+            details: String::new(),
+        },
+    };
+    if !entry.message.is_empty() {
+        state.error_log[state.error_log_index] = entry;
         state.error_log_index = (state.error_log_index + 1) % state.error_log.len();
         state.error_log_count = state.error_log.len().min(state.error_log_count + 1);
         ctx.needs_rerender();
@@ -252,24 +364,60 @@ pub fn draw_error_log(ctx: &mut Context, state: &mut State) {
 
             for i in 0..state.error_log_count {
                 let idx = (off + i) % state.error_log.len();
-                let msg = &state.error_log[idx][..];
+                let entry = &state.error_log[idx];
 
-                if !msg.is_empty() {
+                if !entry.message.is_empty() {
                     ctx.next_block_id_mixin(i as u64);
-                    ctx.label("error", msg);
+                    ctx.label("error", &entry.message);
                     ctx.attr_overflow(Overflow::TruncateTail);
+
+                    if state.error_log_show_details {
+                        for (j, line) in entry.details.lines().enumerate() {
+                            ctx.next_block_id_mixin((i << 16 | j) as u64);
+                            ctx.label("details", line);
+                            ctx.attr_overflow(Overflow::TruncateTail);
+                            ctx.attr_padding(Rect { left: 2, ..Default::default() });
+                        }
+                    }
                 }
             }
         }
         ctx.block_end();
 
-        if ctx.button("ok", loc(LocId::Ok), ButtonStyle::default()) {
-            state.error_log_count = 0;
-        }
-        ctx.attr_position(Position::Center);
+        let off = state.error_log_index + state.error_log.len() - state.error_log_count;
+        let has_details = (0..state.error_log_count)
+            .any(|i| !state.error_log[(off + i) % state.error_log.len()].details.is_empty());
+
+        ctx.table_begin("choices");
         ctx.inherit_focus();
+        ctx.attr_padding(Rect::three(0, 2, 1));
+        ctx.attr_position(Position::Center);
+        ctx.table_set_cell_gap(Size { width: 2, height: 0 });
+        {
+            ctx.table_next_row();
+            ctx.inherit_focus();
+
+            if ctx.button("ok", loc(LocId::Ok), ButtonStyle::default()) {
+                error_log_clear(state);
+            }
+            ctx.inherit_focus();
+
+            if has_details
+                && !state.error_log_show_details
+                && ctx.button("details", loc(LocId::ErrorDialogShowDetails), ButtonStyle::default())
+            {
+                state.error_log_show_details = true;
+                ctx.needs_rerender();
+            }
+        }
+        ctx.table_end();
     }
     if ctx.modal_end() {
-        state.error_log_count = 0;
+        error_log_clear(state);
     }
 }
+
+fn error_log_clear(state: &mut State) {
+    state.error_log_count = 0;
+    state.error_log_show_details = false;
+}
@@ -0,0 +1,111 @@
+// Copyright (c) Microsoft Corporation.
+// Licensed under the MIT License.
+
+/// A handle to a position in a [`super::TextBuffer`] that moves along with the text.
+/// See [`super::TextBuffer::anchor_create()`].
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
+pub struct Anchor(u32);
+
+/// Which way an anchor moves when text is inserted right at its position.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum AnchorBias {
+    /// The anchor stays in front of the inserted text.
+    /// Use this for the end of a range, so it doesn't grow when typing after it.
+    Left,
+    /// The anchor moves behind the inserted text.
+    /// Use this for the start of a range, so it doesn't grow when typing before it.
+    Right,
+}
+
+struct Slot {
+    id: u32,
+    offset: usize,
+    bias: AnchorBias,
+}
+
+/// The anchors of a [`super::GapBuffer`].
+///
+/// The number of anchors is expected to be small (bookmarks, diagnostics, etc.),
+/// so this is just a list that is adjusted linearly on every modification.
+/// It's sorted by ID, so that lookups are a binary search.
+#[derive(Default)]
+pub(super) struct Anchors {
+    slots: Vec<Slot>,
+    next_id: u32,
+}
+
+impl Anchors {
+    pub fn create(&mut self, offset: usize, bias: AnchorBias) -> Anchor {
+        let id = self.next_id;
+        self.next_id += 1;
+        self.slots.push(Slot { id, offset, bias });
+        Anchor(id)
+    }
+
+    pub fn remove(&mut self, anchor: Anchor) {
+        if let Ok(idx) = self.slots.binary_search_by_key(&anchor.0, |s| s.id) {
+            self.slots.remove(idx);
+        }
+    }
+
+    /// Returns `None` if the anchor was removed.
+    pub fn offset(&self, anchor: Anchor) -> Option<usize> {
+        let idx = self.slots.binary_search_by_key(&anchor.0, |s| s.id).ok()?;
+        Some(self.slots[idx].offset)
+    }
+
+    /// Text was inserted at `off`.
+    pub fn insert(&mut self, off: usize, len: usize) {
+        for s in &mut self.slots {
+            if s.offset > off || (s.offset == off && s.bias == AnchorBias::Right) {
+                s.offset += len;
+            }
+        }
+    }
+
+    /// Text was deleted at `off`. Anchors inside the deleted range collapse to `off`.
+    pub fn delete(&mut self, off: usize, len: usize) {
+        for s in &mut self.slots {
+            if s.offset > off {
+                s.offset = off.max(s.offset - len.min(s.offset));
+            }
+        }
+    }
+
+    /// All text was deleted.
+    pub fn clear(&mut self) {
+        for s in &mut self.slots {
+            s.offset = 0;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_adjust() {
+        let mut anchors = Anchors::default();
+        let left = anchors.create(5, AnchorBias::Left);
+        let right = anchors.create(5, AnchorBias::Right);
+        let after = anchors.create(10, AnchorBias::Left);
+
+        anchors.insert(5, 3);
+        assert_eq!(anchors.offset(left), Some(5));
+        assert_eq!(anchors.offset(right), Some(8));
+        assert_eq!(anchors.offset(after), Some(13));
+
+        anchors.delete(2, 4);
+        assert_eq!(anchors.offset(left), Some(2));
+        assert_eq!(anchors.offset(right), Some(4));
+        assert_eq!(anchors.offset(after), Some(9));
+
+        anchors.remove(right);
+        assert_eq!(anchors.offset(right), None);
+        assert_eq!(anchors.offset(after), Some(9));
+
+        anchors.clear();
+        assert_eq!(anchors.offset(after), Some(0));
+    }
+}
@@ -0,0 +1,306 @@
+// Copyright (c) Microsoft Corporation.
+// Licensed under the MIT License.
+
+//! A line-level diff based on Myers' O(ND) algorithm, in its linear space variant.
+//!
+//! See: "An O(ND) Difference Algorithm and Its Variations", Eugene W. Myers, 1986.
+
+use std::borrow::Cow;
+use std::ops::Range;
+
+use crate::document::ReadableDocument;
+use crate::hash::hash;
+use crate::helpers::CoordType;
+use crate::simd::lines_fwd;
+
+/// A region in which two documents differ.
+///
+/// An empty `old_lines` range denotes a pure insertion and
+/// an empty `new_lines` range a pure deletion.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct DiffHunk {
+    /// The affected lines in the old document.
+    pub old_lines: Range<CoordType>,
+    /// The affected lines in the new document.
+    pub new_lines: Range<CoordType>,
+    /// The same as `old_lines`, but in bytes.
+    pub old_bytes: Range<usize>,
+    /// The same as `new_lines`, but in bytes.
+    pub new_bytes: Range<usize>,
+}
+
+/// Computes a minimal line-level diff that turns `old` into `new`.
+///
+/// Lines are compared including their line endings. The hunks are sorted
+/// and never touch each other: there's always at least 1 equal line between them.
+pub fn diff_documents(old: &dyn ReadableDocument, new: &dyn ReadableDocument) -> Vec<DiffHunk> {
+    let old = DocLines::new(old);
+    let new = DocLines::new(new);
+    let mut differ =
+        Differ { old: &old, new: &new, vf: Vec::new(), vb: Vec::new(), hunks: Vec::new() };
+
+    let max_d = max_d(old.len(), new.len());
+    differ.vf.resize(2 * max_d + 1, 0);
+    differ.vb.resize(2 * max_d + 1, 0);
+    differ.conquer(0..old.len(), 0..new.len());
+    differ.hunks
+}
+
+struct DocLines<'a> {
+    lines: Vec<Cow<'a, [u8]>>,
+    hashes: Vec<u64>,
+    /// The start offset of each line, plus the total length at the end.
+    offsets: Vec<usize>,
+}
+
+impl<'a> DocLines<'a> {
+    fn new(doc: &'a dyn ReadableDocument) -> Self {
+        let mut lines = Vec::new();
+        let mut offsets = vec![0];
+        let mut off = 0;
+
+        loop {
+            let chunk = doc.read_forward(off);
+            if chunk.is_empty() {
+                break;
+            }
+
+            let (end, found) = lines_fwd(chunk, 0, 0, 1);
+            let mut line = Cow::Borrowed(&chunk[..end]);
+            off += end;
+
+            // The line straddles multiple chunks.
+            if found == 0 {
+                loop {
+                    let chunk = doc.read_forward(off);
+                    if chunk.is_empty() {
+                        break;
+                    }
+                    let (end, found) = lines_fwd(chunk, 0, 0, 1);
+                    line.to_mut().extend_from_slice(&chunk[..end]);
+                    off += end;
+                    if found != 0 {
+                        break;
+                    }
+                }
+            }
+
+            lines.push(line);
+            offsets.push(off);
+        }
+
+        let hashes = lines.iter().map(|l| hash(0, l)).collect();
+        Self { lines, hashes, offsets }
+    }
+
+    fn len(&self) -> usize {
+        self.lines.len()
+    }
+}
+
+struct Differ<'a> {
+    old: &'a DocLines<'a>,
+    new: &'a DocLines<'a>,
+    // The furthest reaching forward/backward paths per diagonal `k`, stored at `max_d + k`.
+    vf: Vec<usize>,
+    vb: Vec<usize>,
+    hunks: Vec<DiffHunk>,
+}
+
+impl Differ<'_> {
+    fn eq(&self, o: usize, n: usize) -> bool {
+        self.old.hashes[o] == self.new.hashes[n] && self.old.lines[o] == self.new.lines[n]
+    }
+
+    fn conquer(&mut self, mut old: Range<usize>, mut new: Range<usize>) {
+        while !old.is_empty() && !new.is_empty() && self.eq(old.start, new.start) {
+            old.start += 1;
+            new.start += 1;
+        }
+        while !old.is_empty() && !new.is_empty() && self.eq(old.end - 1, new.end - 1) {
+            old.end -= 1;
+            new.end -= 1;
+        }
+
+        if old.is_empty() && new.is_empty() {
+            // Nothing to do.
+        } else if old.is_empty() || new.is_empty() {
+            self.emit(old, new);
+        } else if let Some((x, y)) = self.find_middle_snake(old.clone(), new.clone()) {
+            self.conquer(old.start..x, new.start..y);
+            self.conquer(x..old.end, y..new.end);
+        } else {
+            self.emit(old, new);
+        }
+    }
+
+    /// Finds the start of the middle snake of the shortest edit script
+    /// between `old` and `new`, which splits the problem into two halves.
+    /// Both ranges must be non-empty and not share a common prefix or suffix.
+    fn find_middle_snake(
+        &mut self,
+        old: Range<usize>,
+        new: Range<usize>,
+    ) -> Option<(usize, usize)> {
+        let n = old.len() as isize;
+        let m = new.len() as isize;
+        let delta = n - m;
+        let odd = delta & 1 != 0;
+        let center = max_d(self.old.len(), self.new.len()) as isize;
+        let at = |k: isize| (center + k) as usize;
+
+        self.vf[at(1)] = 0;
+        self.vb[at(1)] = 0;
+
+        for d in 0..max_d(old.len(), new.len()) as isize {
+            for k in (-d..=d).rev().step_by(2) {
+                let mut x = if k == -d || (k != d && self.vf[at(k - 1)] < self.vf[at(k + 1)]) {
+                    self.vf[at(k + 1)] as isize
+                } else {
+                    self.vf[at(k - 1)] as isize + 1
+                };
+                let mut y = x - k;
+                let (x0, y0) = (x, y);
+
+                while x < n && y < m && self.eq(old.start + x as usize, new.start + y as usize) {
+                    x += 1;
+                    y += 1;
+                }
+
+                self.vf[at(k)] = x as usize;
+
+                if odd && (k - delta).abs() < d && x + self.vb[at(delta - k)] as isize >= n {
+                    return Some((old.start + x0 as usize, new.start + y0 as usize));
+                }
+            }
+
+            for k in (-d..=d).rev().step_by(2) {
+                let mut x = if k == -d || (k != d && self.vb[at(k - 1)] < self.vb[at(k + 1)]) {
+                    self.vb[at(k + 1)] as isize
+                } else {
+                    self.vb[at(k - 1)] as isize + 1
+                };
+                let mut y = x - k;
+
+                while x < n && y < m && self.eq(old.end - 1 - x as usize, new.end - 1 - y as usize)
+                {
+                    x += 1;
+                    y += 1;
+                }
+
+                self.vb[at(k)] = x as usize;
+
+                if !odd && (k - delta).abs() <= d && x + self.vf[at(delta - k)] as isize >= n {
+                    return Some((old.end - x as usize, new.end - y as usize));
+                }
+            }
+        }
+
+        None
+    }
+
+    fn emit(&mut self, old: Range<usize>, new: Range<usize>) {
+        if let Some(last) = self.hunks.last_mut()
+            && last.old_lines.end as usize == old.start
+            && last.new_lines.end as usize == new.start
+        {
+            last.old_lines.end = old.end as CoordType;
+            last.new_lines.end = new.end as CoordType;
+            last.old_bytes.end = self.old.offsets[old.end];
+            last.new_bytes.end = self.new.offsets[new.end];
+            return;
+        }
+
+        self.hunks.push(DiffHunk {
+            old_lines: old.start as CoordType..old.end as CoordType,
+            new_lines: new.start as CoordType..new.end as CoordType,
+            old_bytes: self.old.offsets[old.start]..self.old.offsets[old.end],
+            new_bytes: self.new.offsets[new.start]..self.new.offsets[new.end],
+        });
+    }
+}
+
+fn max_d(n: usize, m: usize) -> usize {
+    (n + m).div_ceil(2) + 1
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn diff(old: &str, new: &str) -> Vec<(Range<CoordType>, Range<CoordType>)> {
+        diff_documents(&old.as_bytes(), &new.as_bytes())
+            .into_iter()
+            .map(|h| (h.old_lines, h.new_lines))
+            .collect()
+    }
+
+    /// Applies the hunks to `old` and checks that the result equals `new`.
+    fn check_roundtrip(old: &str, new: &str) -> usize {
+        let hunks = diff_documents(&old.as_bytes(), &new.as_bytes());
+        let mut result = old.to_string();
+        for h in hunks.iter().rev() {
+            result.replace_range(h.old_bytes.clone(), &new[h.new_bytes.clone()]);
+        }
+        assert_eq!(result, new);
+        hunks.iter().map(|h| h.old_lines.len() + h.new_lines.len()).sum()
+    }
+
+    #[test]
+    fn test_basic() {
+        assert_eq!(diff("", ""), vec![]);
+        assert_eq!(diff("a\nb\n", "a\nb\n"), vec![]);
+        assert_eq!(diff("", "a\n"), vec![(0..0, 0..1)]);
+        assert_eq!(diff("a\n", ""), vec![(0..1, 0..0)]);
+        assert_eq!(diff("a\nb\nc\n", "a\nx\nc\n"), vec![(1..2, 1..2)]);
+        assert_eq!(diff("a\nb\nc\n", "a\nc\n"), vec![(1..2, 1..1)]);
+        assert_eq!(diff("a\nc\n", "a\nb\nc\n"), vec![(1..1, 1..2)]);
+        // A missing final newline makes the last line differ.
+        assert_eq!(diff("a\nb", "a\nb\n"), vec![(1..2, 1..2)]);
+        assert_eq!(
+            diff("a\nb\nc\nd\ne\n", "x\nb\nc\ny\ne\nz\n"),
+            vec![(0..1, 0..1), (3..4, 3..4), (5..5, 5..6)]
+        );
+    }
+
+    #[test]
+    fn test_minimal() {
+        // The classic example from the paper: D = 5.
+        let old = "a\nb\nc\na\nb\nb\na\n";
+        let new = "c\nb\na\nb\na\nc\n";
+        assert_eq!(check_roundtrip(old, new), 5);
+
+        let mut state = 1442695040888963407u64;
+        let mut rng = || {
+            state = state.wrapping_mul(6364136223846793005).wrapping_add(1442695040888963407);
+            (state >> 33) as usize
+        };
+        for _ in 0..200 {
+            let mut gen_text = || {
+                let mut s = String::new();
+                for _ in 0..rng() % 30 {
+                    s.push((b'a' + (rng() % 4) as u8) as char);
+                    s.push('\n');
+                }
+                s
+            };
+            let old = gen_text();
+            let new = gen_text();
+            check_roundtrip(&old, &new);
+        }
+    }
+
+    #[test]
+    fn test_chunked() {
+        let mut gb = super::super::GapBuffer::new(true).unwrap();
+        gb.replace(0..0, b"foo\nbar\nbaz\n");
+        // Put the gap in the middle of "bar".
+        gb.allocate_gap(5, 0, 0);
+
+        let hunks = diff_documents(&b"foo\nbaz\n".as_slice(), &gb);
+        assert_eq!(
+            hunks,
+            vec![DiffHunk { old_lines: 1..1, new_lines: 1..2, old_bytes: 4..4, new_bytes: 4..8 }]
+        );
+    }
+}
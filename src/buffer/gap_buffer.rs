@@ -5,14 +5,15 @@ use std::ops::Range;
 use std::ptr::{self, NonNull};
 use std::slice;
 
+use super::anchors::Anchors;
 use crate::document::{ReadableDocument, WriteableDocument};
 use crate::helpers::*;
 use crate::{apperr, sys};
 
 #[cfg(target_pointer_width = "32")]
-const LARGE_CAPACITY: usize = 128 * MEBI;
+pub(super) const LARGE_CAPACITY: usize = 128 * MEBI;
 #[cfg(target_pointer_width = "64")]
-const LARGE_CAPACITY: usize = 4 * GIBI;
+pub(super) const LARGE_CAPACITY: usize = 4 * GIBI;
 const LARGE_ALLOC_CHUNK: usize = 64 * KIBI;
 const LARGE_GAP_CHUNK: usize = 4 * KIBI;
 
@@ -20,6 +21,16 @@ const SMALL_CAPACITY: usize = 128 * KIBI;
 const SMALL_ALLOC_CHUNK: usize = 256;
 const SMALL_GAP_CHUNK: usize = 16;
 
+/// The consumers of [`GapBuffer::take_dirty()`]. Each one tracks
+/// the modifications since its own last call independently.
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub enum DirtyTracker {
+    Snapshot,
+    Search,
+}
+
+const DIRTY_TRACKERS: usize = 2;
+
 // TODO: Instead of having a specialization for small buffers here,
 // tui.rs could also just keep a MRU set of large buffers around.
 enum BackingBuffer {
@@ -56,6 +67,14 @@ pub struct GapBuffer {
     gap_len: usize,
     /// Increments every time the buffer is modified.
     generation: u32,
+    /// Per [`DirtyTracker`], the number of leading bytes that are unmodified
+    /// since the last [`GapBuffer::take_dirty()`].
+    dirty_head: [usize; DIRTY_TRACKERS],
+    /// Per [`DirtyTracker`], the number of trailing bytes that are unmodified
+    /// since the last [`GapBuffer::take_dirty()`].
+    dirty_tail: [usize; DIRTY_TRACKERS],
+    /// Positions that are adjusted on every modification.
+    anchors: Anchors,
     /// If `Vec(..)`, the buffer is optimized for small amounts of text
     /// and uses the standard heap. Otherwise, it uses virtual memory.
     buffer: BackingBuffer,
@@ -85,6 +104,9 @@ impl GapBuffer {
             gap_off: 0,
             gap_len: 0,
             generation: 0,
+            dirty_head: [0; DIRTY_TRACKERS],
+            dirty_tail: [0; DIRTY_TRACKERS],
+            anchors: Anchors::default(),
             buffer,
         })
     }
@@ -102,6 +124,26 @@ impl GapBuffer {
         self.generation = generation;
     }
 
+    pub(super) fn anchors(&self) -> &Anchors {
+        &self.anchors
+    }
+
+    pub(super) fn anchors_mut(&mut self) -> &mut Anchors {
+        &mut self.anchors
+    }
+
+    /// Returns how many leading and trailing bytes have remained unmodified
+    /// since the last call for the same `tracker`, and resets its tracking
+    /// to "all unmodified". The two counts never overlap.
+    pub fn take_dirty(&mut self, tracker: DirtyTracker) -> (usize, usize) {
+        let i = tracker as usize;
+        let head = self.dirty_head[i].min(self.text_length);
+        let tail = self.dirty_tail[i].min(self.text_length - head);
+        self.dirty_head[i] = usize::MAX;
+        self.dirty_tail[i] = usize::MAX;
+        (head, tail)
+    }
+
     /// WARNING: The returned slice must not necessarily be the same length as `len` (due to OOM).
     pub fn allocate_gap(&mut self, off: usize, len: usize, delete: usize) -> &mut [u8] {
         // Sanitize parameters
@@ -116,6 +158,7 @@ impl GapBuffer {
         // Delete the text
         if delete > 0 {
             self.delete_text(delete);
+            self.anchors.delete(off, delete);
         }
 
         // Enlarge the gap if needed
@@ -123,6 +166,13 @@ impl GapBuffer {
             self.enlarge_gap(len);
         }
 
+        // Whatever gets written into the gap will be committed at `off`, and so the
+        // text after the gap remains unmodified, even after `commit_gap()`.
+        for (head, tail) in self.dirty_head.iter_mut().zip(&mut self.dirty_tail) {
+            *head = (*head).min(off);
+            *tail = (*tail).min(self.text_length - off);
+        }
+
         self.generation = self.generation.wrapping_add(1);
         unsafe { slice::from_raw_parts_mut(self.text.add(self.gap_off).as_ptr(), self.gap_len) }
     }
@@ -227,6 +277,7 @@ impl GapBuffer {
 
     pub fn commit_gap(&mut self, len: usize) {
         assert!(len <= self.gap_len);
+        self.anchors.insert(self.gap_off, len);
         self.text_length += len;
         self.gap_off += len;
         self.gap_len -= len;
@@ -239,6 +290,9 @@ impl GapBuffer {
     }
 
     pub fn clear(&mut self) {
+        self.dirty_head = [0; DIRTY_TRACKERS];
+        self.dirty_tail = [0; DIRTY_TRACKERS];
+        self.anchors.clear();
         self.gap_off = 0;
         self.gap_len += self.text_length;
         self.generation = self.generation.wrapping_add(1);
@@ -0,0 +1,216 @@
+// Copyright (c) Microsoft Corporation.
+// Licensed under the MIT License.
+
+use std::borrow::Cow;
+use std::ops::Range;
+use std::str;
+
+use super::GapBuffer;
+use crate::document::ReadableDocument;
+use crate::simd::{lines_bwd, lines_fwd};
+
+/// A single line of a [`GapBuffer`], including its trailing newline, if any.
+///
+/// The text is split in two halves, because a line may straddle the gap.
+/// Most lines don't, in which case `tail` is empty.
+#[derive(Clone, Copy)]
+pub struct Line<'a> {
+    offset: usize,
+    head: &'a [u8],
+    tail: &'a [u8],
+}
+
+impl<'a> Line<'a> {
+    /// The byte range of this line in the buffer.
+    pub fn range(&self) -> Range<usize> {
+        self.offset..self.offset + self.len()
+    }
+
+    #[allow(clippy::len_without_is_empty)]
+    pub fn len(&self) -> usize {
+        self.head.len() + self.tail.len()
+    }
+
+    /// Returns the two halves of the line. See [`Line`].
+    pub fn as_slices(&self) -> (&'a [u8], &'a [u8]) {
+        (self.head, self.tail)
+    }
+
+    /// Returns the line as a single slice, unless it straddles the gap.
+    pub fn as_contiguous(&self) -> Option<&'a [u8]> {
+        if self.tail.is_empty() {
+            Some(self.head)
+        } else if self.head.is_empty() {
+            Some(self.tail)
+        } else {
+            None
+        }
+    }
+
+    /// Returns the line as a `&str`, if it's contiguous and valid UTF-8.
+    pub fn as_str(&self) -> Option<&'a str> {
+        self.as_contiguous().and_then(|s| str::from_utf8(s).ok())
+    }
+
+    /// Returns the line as a single slice. Only copies if it straddles the gap.
+    pub fn to_cow(&self) -> Cow<'a, [u8]> {
+        match self.as_contiguous() {
+            Some(s) => Cow::Borrowed(s),
+            None => Cow::Owned([self.head, self.tail].concat()),
+        }
+    }
+
+    /// Strips the trailing "\n" or "\r\n", if any.
+    pub fn without_newline(mut self) -> Self {
+        let mut strip = |expected: u8| {
+            let half = if self.tail.is_empty() { &mut self.head } else { &mut self.tail };
+            let s: &'a [u8] = half;
+            let ok = s.last() == Some(&expected);
+            if ok {
+                *half = &s[..s.len() - 1];
+            }
+            ok
+        };
+        if strip(b'\n') {
+            strip(b'\r');
+        }
+        self
+    }
+}
+
+/// An iterator over the lines in a range of a [`GapBuffer`].
+/// It's double-ended, so use `.rev()` to iterate backwards.
+pub struct Lines<'a> {
+    buffer: &'a GapBuffer,
+    beg: usize,
+    end: usize,
+}
+
+impl<'a> Lines<'a> {
+    pub fn new(buffer: &'a GapBuffer, range: Range<usize>) -> Self {
+        let end = range.end.min(buffer.len());
+        Self { buffer, beg: range.start.min(end), end }
+    }
+}
+
+// A gap buffer consists of exactly 2 chunks, which is why
+// the methods below need to look at most at 2 of them per line.
+impl<'a> Iterator for Lines<'a> {
+    type Item = Line<'a>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.beg >= self.end {
+            return None;
+        }
+
+        let remaining = self.end - self.beg;
+        let first = self.buffer.read_forward(self.beg);
+        let first = &first[..first.len().min(remaining)];
+        let (first_end, found) = lines_fwd(first, 0, 0, 1);
+
+        let mut line = Line { offset: self.beg, head: &first[..first_end], tail: &[] };
+
+        if found == 0 && first.len() < remaining {
+            let second = self.buffer.read_forward(self.beg + first.len());
+            let second = &second[..second.len().min(remaining - first.len())];
+            let (second_end, _) = lines_fwd(second, 0, 0, 1);
+            line.tail = &second[..second_end];
+        }
+
+        self.beg += line.len();
+        Some(line)
+    }
+}
+
+impl DoubleEndedIterator for Lines<'_> {
+    fn next_back(&mut self) -> Option<Self::Item> {
+        if self.beg >= self.end {
+            return None;
+        }
+
+        let remaining = self.end - self.beg;
+        let last = self.buffer.read_backward(self.end);
+        let last = &last[last.len().saturating_sub(remaining)..];
+        // The last byte is either the newline that belongs to this line or
+        // the end of the range. Either way, we must not stop at it.
+        let (last_beg, _) = lines_bwd(last, last.len() - 1, 0, 0);
+
+        let mut line = Line { offset: 0, head: &last[last_beg..], tail: &[] };
+
+        // `lines_bwd` returns 0 both when it found a newline right before the chunk
+        // and when it found none. In both cases the previous chunk tells us more.
+        if last_beg == 0 && last.len() < remaining {
+            let prev = self.buffer.read_backward(self.end - last.len());
+            let prev = &prev[prev.len().saturating_sub(remaining - last.len())..];
+            let (prev_beg, _) = lines_bwd(prev, prev.len(), 0, 0);
+            line.head = &prev[prev_beg..];
+            line.tail = last;
+        }
+
+        self.end -= line.len();
+        line.offset = self.end;
+        Some(line)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn collect<'a>(lines: impl Iterator<Item = Line<'a>>) -> Vec<(Range<usize>, String)> {
+        lines.map(|l| (l.range(), String::from_utf8(l.to_cow().into_owned()).unwrap())).collect()
+    }
+
+    fn make_buffer(text: &str, gap: usize) -> GapBuffer {
+        let mut gb = GapBuffer::new(true).unwrap();
+        gb.replace(0..0, text.as_bytes());
+        // Move the gap to the given offset.
+        gb.allocate_gap(gap, 0, 0);
+        gb
+    }
+
+    #[test]
+    fn test_lines() {
+        let text = "foo\nbar\r\n\nbaz";
+        let expected = vec![
+            (0..4, "foo\n".to_string()),
+            (4..9, "bar\r\n".to_string()),
+            (9..10, "\n".to_string()),
+            (10..13, "baz".to_string()),
+        ];
+
+        for gap in 0..=text.len() {
+            let gb = make_buffer(text, gap);
+            assert_eq!(collect(Lines::new(&gb, 0..usize::MAX)), expected, "gap at {gap}");
+
+            let mut reversed = expected.clone();
+            reversed.reverse();
+            assert_eq!(collect(Lines::new(&gb, 0..usize::MAX).rev()), reversed, "gap at {gap}");
+        }
+    }
+
+    #[test]
+    fn test_subrange_and_mixed() {
+        let gb = make_buffer("ab\ncd\nef\n", 4);
+
+        assert_eq!(
+            collect(Lines::new(&gb, 1..7)),
+            vec![(1..3, "b\n".to_string()), (3..6, "cd\n".to_string()), (6..7, "e".to_string())]
+        );
+
+        let mut it = Lines::new(&gb, 0..usize::MAX);
+        assert_eq!(it.next().unwrap().range(), 0..3);
+        assert_eq!(it.next_back().unwrap().range(), 6..9);
+        assert_eq!(it.next_back().unwrap().range(), 3..6);
+        assert!(it.next().is_none());
+    }
+
+    #[test]
+    fn test_without_newline() {
+        let gb = make_buffer("a\r\nb\n\nc", 2);
+        let lines: Vec<_> = Lines::new(&gb, 0..usize::MAX)
+            .map(|l| l.without_newline().to_cow().into_owned())
+            .collect();
+        assert_eq!(lines, vec![b"a".to_vec(), b"b".to_vec(), b"".to_vec(), b"c".to_vec()]);
+    }
+}
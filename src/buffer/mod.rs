@@ -20,8 +20,14 @@
 //! The solution to the former is to keep line caches, which further complicates the architecture.
 //! There's no solution for the latter. However, there's a chance that the performance will still be sufficient.
 
+mod anchors;
+mod diff;
 mod gap_buffer;
+mod lines;
 mod navigation;
+mod paste;
+mod search;
+mod snapshot;
 
 use std::borrow::Cow;
 use std::cell::UnsafeCell;
@@ -33,20 +39,41 @@ use std::mem::{self, MaybeUninit};
 use std::ops::Range;
 use std::rc::Rc;
 use std::str;
-
-pub use gap_buffer::GapBuffer;
+use std::sync::atomic::AtomicBool;
+use std::time::{Duration, Instant};
+
+pub use anchors::{Anchor, AnchorBias};
+pub use diff::{DiffHunk, diff_documents};
+pub use gap_buffer::{DirtyTracker, GapBuffer};
+pub use lines::{Line, Lines};
+use paste::PasteSanitizer;
+pub use paste::{ControlChars, PasteOptions};
+pub use search::{Search, SearchOptions};
+pub use snapshot::Snapshot;
 
 use crate::arena::{Arena, ArenaString, scratch_arena};
 use crate::cell::SemiRefCell;
 use crate::clipboard::Clipboard;
 use crate::document::{ReadableDocument, WriteableDocument};
-use crate::framebuffer::{Framebuffer, IndexedColor};
+use crate::framebuffer::{Attributes, CursorStyle, Framebuffer, IndexedColor};
+use crate::hash::HashStream;
 use crate::helpers::*;
 use crate::oklab::StraightRgba;
-use crate::simd::memchr2;
+use crate::simd::{memchr2, memchr3};
 use crate::unicode::{self, Cursor, MeasurementConfig, Utf8Chars};
 use crate::{apperr, icu, simd};
 
+/// The largest document that can be loaded, in bytes. Larger files are rejected with
+/// [`apperr::APP_FILE_TOO_LARGE`] instead of being loaded partially.
+pub const MAX_TEXT_LENGTH: usize = gap_buffer::LARGE_CAPACITY;
+/// Lines longer than this (in bytes) may exceed [`COORD_TYPE_SAFE_MAX`] columns,
+/// because a single byte can be up to 8 columns wide (= a tab).
+/// Documents with such lines are always word-wrapped, as that keeps all
+/// column positions within the width of the viewport. They're detected when loading
+/// a file and when an edit makes a line this long. Once detected,
+/// word wrap stays forced on, even if the lines get shortened again.
+pub const MAX_LINE_LENGTH_FOR_LAYOUT: usize = COORD_TYPE_SAFE_MAX as usize / 8;
+
 /// The margin template is used for line numbers.
 /// The max. line number we should ever expect is probably 64-bit,
 /// and so this template fits 19 digits, followed by " │ ".
@@ -84,6 +111,24 @@ enum HistoryType {
     Other,
     Write,
     Delete,
+    /// A chunk of a paste that arrives in pieces. See [`TextBuffer::paste_chunk()`].
+    Paste,
+}
+
+/// Controls how consecutive typing and deleting is grouped into undo steps.
+/// Other kinds of edits (pasting, replacing, etc.) are always separate steps.
+#[derive(Copy, Clone, Default, Debug, PartialEq, Eq)]
+pub enum UndoGrouping {
+    /// All consecutive keystrokes of the same kind form a single step.
+    #[default]
+    Continuous,
+    /// Like `Continuous`, but typing is split into words: A new step
+    /// begins when a word is started after typing whitespace.
+    Word,
+    /// Like `Continuous`, but a new step begins after a pause in typing.
+    Pause(Duration),
+    /// Each keystroke is its own step.
+    Keystroke,
 }
 
 /// An undo/redo entry.
@@ -107,20 +152,24 @@ struct HistoryEntry {
     added: Vec<u8>,
 }
 
+/// The appearance of a virtual text run. See [`TextBuffer::virtual_text_add()`].
+#[derive(Clone, Copy)]
+pub struct VirtualTextStyle {
+    pub fg: IndexedColor,
+    pub attributes: Attributes,
+}
+
+/// Text that is drawn as part of the buffer, but isn't part of its contents.
+struct VirtualText {
+    anchor: Anchor,
+    text: String,
+    style: VirtualTextStyle,
+}
+
 /// Caches an ICU search operation.
 struct ActiveSearch {
-    /// The search pattern.
-    pattern: String,
-    /// The search options.
-    options: SearchOptions,
-    /// The ICU `UText` object.
-    text: icu::Text,
-    /// The ICU `URegularExpression` object.
-    regex: icu::Regex,
-    /// [`GapBuffer::generation`] when the search was created.
-    /// This is used to detect if we need to refresh the
-    /// [`ActiveSearch::regex`] object.
-    buffer_generation: u32,
+    /// The compiled search pattern and its position.
+    search: Search,
     /// [`TextBuffer::selection_generation`] when the search was
     /// created. When the user manually selects text, we need to
     /// refresh the [`ActiveSearch::pattern`] with it.
@@ -129,17 +178,29 @@ struct ActiveSearch {
     next_search_offset: usize,
     /// If we know there were no hits, we can skip searching.
     no_matches: bool,
+    /// Whether the last step to the next or previous hit went past the end of the buffer.
+    wrapped: bool,
+    /// All hits, once [`TextBuffer::search_status()`] asked for them.
+    matches: Option<SearchMatches>,
+}
+
+/// The hits of an [`ActiveSearch`], kept up to date with the modifications
+/// reported by [`GapBuffer::take_dirty()`] instead of searching the entire buffer again.
+struct SearchMatches {
+    /// Sorted and non-overlapping.
+    ranges: Vec<Range<usize>>,
+    /// The length of the text when `ranges` were last updated.
+    text_length: usize,
 }
 
-/// Options for a search operation.
-#[derive(Default, Clone, Copy, Eq, PartialEq)]
-pub struct SearchOptions {
-    /// If true, the search is case-sensitive.
-    pub match_case: bool,
-    /// If true, the search matches whole words.
-    pub whole_word: bool,
-    /// If true, the search uses regex.
-    pub use_regex: bool,
+/// Where the selection is among the hits of the active search.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub struct SearchStatus {
+    /// The 0-based index of the selected hit, or `None` if the selection isn't one.
+    pub index: Option<usize>,
+    pub count: usize,
+    /// Whether the last step to the selected hit went past the end of the buffer.
+    pub wrapped: bool,
 }
 
 enum RegexReplacement<'a> {
@@ -178,6 +239,19 @@ struct ActiveEditGroupInfo {
     generation_before: u32,
 }
 
+/// The state of a paste that arrives in chunks. See [`TextBuffer::paste_chunk()`].
+#[derive(Clone, Copy, Default)]
+struct PasteStream {
+    /// Whether a chunk is being written right now.
+    writing: bool,
+    /// Whether more chunks will follow the one being written.
+    more: bool,
+    /// Whether the previous chunk ended in a CR, in case a CRLF got split in two.
+    pending_cr: bool,
+    /// Carries the [`PasteOptions`] state from one chunk to the next.
+    sanitizer: PasteSanitizer,
+}
+
 /// Char- or word-wise navigation? Your choice.
 pub enum CursorMovement {
     Grapheme,
@@ -212,12 +286,18 @@ pub struct TextBuffer {
     undo_stack: LinkedList<SemiRefCell<HistoryEntry>>,
     redo_stack: LinkedList<SemiRefCell<HistoryEntry>>,
     last_history_type: HistoryType,
+    last_history_time: Instant,
     last_save_generation: u32,
+    undo_grouping: UndoGrouping,
 
     active_edit_group: Option<ActiveEditGroupInfo>,
     active_edit_line_info: Option<ActiveEditLineInfo>,
     active_edit_depth: i32,
     active_edit_off: usize,
+    /// Set when a write of the active edit didn't fit. All further writes of it are skipped.
+    active_edit_failed: bool,
+    paste_stream: PasteStream,
+    paste_options: PasteOptions,
 
     stats: TextBufferStatistics,
     cursor: Cursor,
@@ -228,6 +308,11 @@ pub struct TextBuffer {
     cursor_for_rendering: Option<Cursor>,
     selection: Option<TextBufferSelection>,
     selection_generation: u32,
+    /// The selections before each [`TextBuffer::selection_expand()`], for shrinking it again.
+    selection_expansions: Vec<Range<usize>>,
+    /// The selection and text generations after the last expansion or shrink.
+    /// If either changed since, `selection_expansions` is stale.
+    selection_expansions_generation: (u32, u32),
     search: Option<UnsafeCell<ActiveSearch>>,
 
     width: CoordType,
@@ -235,16 +320,24 @@ pub struct TextBuffer {
     margin_enabled: bool,
     word_wrap_column: CoordType,
     word_wrap_enabled: bool,
+    has_overlong_lines: bool,
+    /// [`MAX_LINE_LENGTH_FOR_LAYOUT`], except in tests, which can't afford such long lines.
+    overlong_line_length: usize,
     tab_size: CoordType,
     indent_with_tabs: bool,
     line_highlight_enabled: bool,
     ruler: CoordType,
     encoding: &'static str,
+    file_checksum: u64,
     newlines_are_crlf: bool,
     insert_final_newline: bool,
     overtype: bool,
+    goal_column_enabled: bool,
 
     wants_cursor_visibility: bool,
+    write_error: Option<apperr::Error>,
+    last_snapshot: Option<Snapshot>,
+    virtual_texts: Vec<VirtualText>,
 }
 
 impl TextBuffer {
@@ -264,18 +357,25 @@ impl TextBuffer {
             undo_stack: LinkedList::new(),
             redo_stack: LinkedList::new(),
             last_history_type: HistoryType::Other,
+            last_history_time: Instant::now(),
             last_save_generation: 0,
+            undo_grouping: UndoGrouping::Continuous,
 
             active_edit_group: None,
             active_edit_line_info: None,
             active_edit_depth: 0,
+            active_edit_failed: false,
             active_edit_off: 0,
+            paste_stream: Default::default(),
+            paste_options: Default::default(),
 
             stats: TextBufferStatistics { logical_lines: 1, visual_lines: 1 },
             cursor: Default::default(),
             cursor_for_rendering: None,
             selection: None,
             selection_generation: 0,
+            selection_expansions: Vec::new(),
+            selection_expansions_generation: (0, 0),
             search: None,
 
             width: 0,
@@ -283,16 +383,23 @@ impl TextBuffer {
             margin_enabled: false,
             word_wrap_column: 0,
             word_wrap_enabled: false,
+            has_overlong_lines: false,
+            overlong_line_length: MAX_LINE_LENGTH_FOR_LAYOUT,
             tab_size: 4,
             indent_with_tabs: false,
             line_highlight_enabled: false,
             ruler: 0,
             encoding: "UTF-8",
+            file_checksum: 0,
             newlines_are_crlf: cfg!(windows), // Windows users want CRLF
             insert_final_newline: false,
             overtype: false,
+            goal_column_enabled: true,
 
             wants_cursor_visibility: false,
+            write_error: None,
+            last_snapshot: None,
+            virtual_texts: Vec::new(),
         })
     }
 
@@ -339,6 +446,12 @@ impl TextBuffer {
         self.encoding
     }
 
+    /// The [`hash`](crate::hash::hash) of the bytes last read by [`TextBuffer::read_file()`]
+    /// or written by [`TextBuffer::write_file()`], i.e. of the file as it is on disk.
+    pub fn file_checksum(&self) -> u64 {
+        self.file_checksum
+    }
+
     /// Set the encoding used during reading/writing.
     pub fn set_encoding(&mut self, encoding: &'static str) {
         if self.encoding != encoding {
@@ -442,6 +555,33 @@ impl TextBuffer {
         self.overtype = overtype;
     }
 
+    /// Whether moving the cursor up or down returns to the column it came from,
+    /// after passing through shorter lines. The textarea keeps track of that column.
+    pub fn is_goal_column_enabled(&self) -> bool {
+        self.goal_column_enabled
+    }
+
+    /// Sets whether moving the cursor up or down returns to the column it came from.
+    pub fn set_goal_column_enabled(&mut self, enabled: bool) {
+        self.goal_column_enabled = enabled;
+    }
+
+    /// Returns how keystrokes are grouped into undo steps.
+    pub fn undo_grouping(&self) -> UndoGrouping {
+        self.undo_grouping
+    }
+
+    /// Sets how keystrokes are grouped into undo steps.
+    pub fn set_undo_grouping(&mut self, grouping: UndoGrouping) {
+        self.undo_grouping = grouping;
+    }
+
+    /// Ensures that the next edit starts a new undo step,
+    /// instead of being merged with the preceding typing or deleting.
+    pub fn undo_barrier(&mut self) {
+        self.last_history_type = HistoryType::Other;
+    }
+
     /// Gets the logical cursor position, that is,
     /// the position in lines and graphemes per line.
     pub fn cursor_logical_pos(&self) -> Point {
@@ -491,6 +631,12 @@ impl TextBuffer {
         mem::take(&mut self.wants_cursor_visibility)
     }
 
+    /// For the TUI code to retrieve the reason why the last edit was rejected, if it was.
+    /// Currently that's only [`apperr::APP_FILE_TOO_LARGE`], if the text didn't fit.
+    pub fn take_write_error(&mut self) -> Option<apperr::Error> {
+        self.write_error.take()
+    }
+
     /// Is word-wrap enabled?
     ///
     /// Technically, this is a misnomer, because it's line-wrapping.
@@ -502,7 +648,11 @@ impl TextBuffer {
     ///
     /// NOTE: It's expected that the tui code calls `set_width()` sometime after this.
     /// This will then trigger the actual recalculation of the cursor position.
+    ///
+    /// Word wrap can't be disabled if the document contains lines
+    /// longer than [`MAX_LINE_LENGTH_FOR_LAYOUT`].
     pub fn set_word_wrap(&mut self, enabled: bool) {
+        let enabled = enabled || self.has_overlong_lines;
         if self.word_wrap_enabled != enabled {
             self.word_wrap_enabled = enabled;
             self.width = 0; // Force a reflow.
@@ -573,11 +723,26 @@ impl TextBuffer {
         self.indent_with_tabs = indent_with_tabs;
     }
 
+    /// Returns how pasted text gets cleaned up.
+    pub fn paste_options(&self) -> PasteOptions {
+        self.paste_options
+    }
+
+    /// Sets how pasted text gets cleaned up. See [`PasteOptions`].
+    pub fn set_paste_options(&mut self, options: PasteOptions) {
+        self.paste_options = options;
+    }
+
     /// Sets whether the line the cursor is on should be highlighted.
     pub fn set_line_highlight_enabled(&mut self, enabled: bool) {
         self.line_highlight_enabled = enabled;
     }
 
+    /// Returns the ruler column, or 0 if there's none.
+    pub fn ruler(&self) -> CoordType {
+        self.ruler
+    }
+
     /// Sets a ruler column, e.g. 80.
     pub fn set_ruler(&mut self, column: CoordType) {
         self.ruler = column;
@@ -644,6 +809,39 @@ impl TextBuffer {
             if delete != 0 {
                 self.buffer.allocate_gap(self.cursor.offset, 0, delete);
             }
+            self.recalc_overlong_lines();
+        }
+    }
+
+    /// Replaces the entire buffer contents with the given `text`, which may span multiple lines.
+    /// Unlike [`TextBuffer::copy_from_str`], the cursor is placed at the very end.
+    pub fn copy_from_str_multiline(&mut self, text: &dyn ReadableDocument) {
+        if self.buffer.copy_from(text) {
+            let mut offset = 0;
+            let mut lines = 0;
+            loop {
+                let chunk = self.buffer.read_forward(offset);
+                if chunk.is_empty() {
+                    break;
+                }
+                (_, lines) = simd::lines_fwd(chunk, 0, lines, CoordType::MAX);
+                offset += chunk.len();
+            }
+            self.stats.logical_lines = lines + 1;
+            self.stats.visual_lines = self.stats.logical_lines;
+
+            self.recalc_after_content_swap();
+            self.recalc_overlong_lines();
+            self.cursor_move_to_logical(Point::MAX);
+        }
+    }
+
+    /// Scans the entire buffer for lines longer than [`MAX_LINE_LENGTH_FOR_LAYOUT`].
+    fn recalc_overlong_lines(&mut self) {
+        self.has_overlong_lines =
+            self.has_long_line(0..self.text_length(), self.overlong_line_length);
+        if self.has_overlong_lines {
+            self.set_word_wrap(true);
         }
     }
 
@@ -670,6 +868,12 @@ impl TextBuffer {
         file: &mut File,
         encoding: Option<&'static str>,
     ) -> apperr::Result<()> {
+        if let Ok(m) = file.metadata()
+            && m.len() > MAX_TEXT_LENGTH as u64
+        {
+            return Err(apperr::APP_FILE_TOO_LARGE);
+        }
+
         let scratch = scratch_arena(None);
         let mut buf = scratch.alloc_uninit().transpose();
         let mut first_chunk_len = 0;
@@ -684,11 +888,16 @@ impl TextBuffer {
             first_chunk_len += read;
         }
 
+        let mut hasher = HashStream::new(0);
+        hasher.update(unsafe { buf[..first_chunk_len].assume_init_ref() });
+
         if let Some(encoding) = encoding {
             self.encoding = encoding;
         } else {
-            let bom = detect_bom(unsafe { buf[..first_chunk_len].assume_init_ref() });
-            self.encoding = bom.unwrap_or("UTF-8");
+            let first_chunk = unsafe { buf[..first_chunk_len].assume_init_ref() };
+            self.encoding = detect_bom(first_chunk)
+                .or_else(|| guess_encoding(first_chunk, read == 0))
+                .unwrap_or("UTF-8");
         }
 
         // TODO: Since reading the file can fail, we should ensure that we also reset the cursor here.
@@ -697,10 +906,11 @@ impl TextBuffer {
 
         let done = read == 0;
         if self.encoding == "UTF-8" {
-            self.read_file_as_utf8(file, &mut buf, first_chunk_len, done)?;
+            self.read_file_as_utf8(file, &mut hasher, &mut buf, first_chunk_len, done)?;
         } else {
-            self.read_file_with_icu(file, &mut buf, first_chunk_len, done)?;
+            self.read_file_with_icu(file, &mut hasher, &mut buf, first_chunk_len, done)?;
         }
+        self.file_checksum = hasher.finish();
 
         // Figure out
         // * the logical line count
@@ -799,6 +1009,12 @@ impl TextBuffer {
 
             let final_newline = chunk.ends_with(b"\n");
 
+            // A line at least twice as long as the limit must fully contain one of these
+            // aligned windows. This makes checking for overlong lines cheap.
+            let has_overlong_lines = chunk
+                .chunks_exact(self.overlong_line_length)
+                .any(|w| memchr2(b'\n', b'\n', w, 0) == w.len());
+
             // Add 1, because the last line doesn't end in a newline (it ends in the literal end).
             self.stats.logical_lines = lines + 1;
             self.stats.visual_lines = self.stats.logical_lines;
@@ -806,6 +1022,10 @@ impl TextBuffer {
             self.insert_final_newline = final_newline;
             self.indent_with_tabs = indent_with_tabs;
             self.tab_size = tab_size;
+            self.has_overlong_lines = has_overlong_lines;
+            if has_overlong_lines {
+                self.set_word_wrap(true);
+            }
         }
 
         self.recalc_after_content_swap();
@@ -815,6 +1035,7 @@ impl TextBuffer {
     fn read_file_as_utf8(
         &mut self,
         file: &mut File,
+        hasher: &mut HashStream,
         buf: &mut [MaybeUninit<u8>; 4 * KIBI],
         first_chunk_len: usize,
         done: bool,
@@ -851,6 +1072,10 @@ impl TextBuffer {
         loop {
             let gap = self.buffer.allocate_gap(self.text_length(), chunk_size, 0);
             if gap.is_empty() {
+                // If we asked for space but didn't get any, we may have run out of it.
+                if chunk_size > 0 && file.read(&mut [0])? != 0 {
+                    return Err(apperr::APP_FILE_TOO_LARGE);
+                }
                 break;
             }
 
@@ -859,6 +1084,7 @@ impl TextBuffer {
                 break;
             }
 
+            hasher.update(&gap[..read]);
             self.buffer.commit_gap(read);
             chunk_size = extra_chunk_size;
         }
@@ -869,6 +1095,7 @@ impl TextBuffer {
     fn read_file_with_icu(
         &mut self,
         file: &mut File,
+        hasher: &mut HashStream,
         buf: &mut [MaybeUninit<u8>; 4 * KIBI],
         first_chunk_len: usize,
         mut done: bool,
@@ -904,13 +1131,14 @@ impl TextBuffer {
         loop {
             if !done {
                 let read = file_read_uninit(file, &mut buf[buf_len..])?;
+                hasher.update(unsafe { buf[buf_len..buf_len + read].assume_init_ref() });
                 buf_len += read;
                 done = read == 0;
             }
 
             let gap = self.buffer.allocate_gap(self.text_length(), 8 * KIBI, 0);
             if gap.is_empty() {
-                break;
+                return Err(apperr::APP_FILE_TOO_LARGE);
             }
 
             let read = unsafe { buf[..buf_len].assume_init_ref() };
@@ -932,10 +1160,12 @@ impl TextBuffer {
 
     /// Writes the text buffer contents to a file, handling BOM and encoding.
     pub fn write_file(&mut self, file: &mut File) -> apperr::Result<()> {
+        let mut hasher = HashStream::new(0);
         let mut offset = 0;
 
         if self.encoding.starts_with("UTF-8") {
             if self.encoding == "UTF-8 BOM" {
+                hasher.update(b"\xEF\xBB\xBF");
                 file.write_all(b"\xEF\xBB\xBF")?;
             }
             loop {
@@ -943,18 +1173,24 @@ impl TextBuffer {
                 if chunk.is_empty() {
                     break;
                 }
+                hasher.update(chunk);
                 file.write_all(chunk)?;
                 offset += chunk.len();
             }
         } else {
-            self.write_file_with_icu(file)?;
+            self.write_file_with_icu(file, &mut hasher)?;
         }
 
+        self.file_checksum = hasher.finish();
         self.mark_as_clean();
         Ok(())
     }
 
-    fn write_file_with_icu(&mut self, file: &mut File) -> apperr::Result<()> {
+    fn write_file_with_icu(
+        &mut self,
+        file: &mut File,
+        hasher: &mut HashStream,
+    ) -> apperr::Result<()> {
         let scratch = scratch_arena(None);
         let pivot_buffer = scratch.alloc_uninit_slice(4 * KIBI);
         let buf = scratch.alloc_uninit_slice(4 * KIBI);
@@ -968,6 +1204,7 @@ impl TextBuffer {
         {
             let (_, output_advance) = c.convert(b"\xEF\xBB\xBF", buf)?;
             let chunk = unsafe { buf[..output_advance].assume_init_ref() };
+            hasher.update(chunk);
             file.write_all(chunk)?;
         }
 
@@ -976,6 +1213,7 @@ impl TextBuffer {
             let (input_advance, output_advance) = c.convert(chunk, buf)?;
             let chunk = unsafe { buf[..output_advance].assume_init_ref() };
 
+            hasher.update(chunk);
             file.write_all(chunk)?;
             offset += input_advance;
 
@@ -1026,7 +1264,13 @@ impl TextBuffer {
 
     /// Select the current word.
     pub fn select_word(&mut self) {
-        let Range { start, end } = navigation::word_select(&self.buffer, self.cursor.offset);
+        let Range { start, end } =
+            match icu::BreakIterator::with_text_buffer(icu::BreakKind::Word, self) {
+                Ok(mut brk) => {
+                    navigation::word_select_icu(&mut brk, &self.buffer, self.cursor.offset)
+                }
+                Err(_) => navigation::word_select(&self.buffer, self.cursor.offset),
+            };
         let beg = self.cursor_move_to_offset_internal(self.cursor, start);
         let end = self.cursor_move_to_offset_internal(beg, end);
         unsafe { self.set_cursor(end) };
@@ -1062,6 +1306,152 @@ impl TextBuffer {
         }));
     }
 
+    /// Grows the selection to the smallest of these that contains more than it:
+    /// The word at the cursor, the lines of the selection, the surrounding
+    /// blocks of deeper indentation, the paragraph and the entire document.
+    pub fn selection_expand(&mut self) {
+        let current = match self.selection_range() {
+            Some((beg, end)) => beg.offset..end.offset,
+            None => self.cursor.offset..self.cursor.offset,
+        };
+        let Some(range) = self.selection_expand_candidate(current.clone()) else {
+            return;
+        };
+
+        let generation = (self.selection_generation, self.buffer.generation());
+        if self.selection_expansions_generation != generation {
+            self.selection_expansions.clear();
+        }
+        self.selection_expansions.push(current);
+        self.select_offsets(range);
+    }
+
+    /// Reverts the last [`TextBuffer::selection_expand()`],
+    /// unless the selection or the text changed since.
+    pub fn selection_shrink(&mut self) {
+        let generation = (self.selection_generation, self.buffer.generation());
+        if self.selection_expansions_generation != generation {
+            self.selection_expansions.clear();
+        }
+        if let Some(range) = self.selection_expansions.pop() {
+            self.select_offsets(range);
+        }
+    }
+
+    fn select_offsets(&mut self, range: Range<usize>) {
+        let beg = self.cursor_move_to_offset_internal(self.cursor, range.start);
+        let end = self.cursor_move_to_offset_internal(beg, range.end);
+        unsafe { self.set_cursor(end) };
+        let generation = self.set_selection(Some(TextBufferSelection {
+            beg: beg.logical_pos,
+            end: end.logical_pos,
+        }));
+        self.selection_expansions_generation = (generation, self.buffer.generation());
+    }
+
+    fn selection_expand_candidate(&self, current: Range<usize>) -> Option<Range<usize>> {
+        let mut best: Option<Range<usize>> = None;
+        let mut consider = |r: Range<usize>| {
+            if r.start <= current.start
+                && r.end >= current.end
+                && r.len() > current.len()
+                && best.as_ref().is_none_or(|b| r.len() < b.len())
+            {
+                best = Some(r);
+            }
+        };
+
+        consider(match icu::BreakIterator::with_text_buffer(icu::BreakKind::Word, self) {
+            Ok(mut brk) => navigation::word_select_icu(&mut brk, &self.buffer, current.start),
+            Err(_) => navigation::word_select(&self.buffer, current.start),
+        });
+
+        // The lines the selection touches. A selection that ends at
+        // the start of a line doesn't include that line.
+        let first = self.goto_line_start(self.cursor, self.offset_to_logical(current.start).y);
+        let last = if current.end > current.start {
+            self.offset_to_logical(current.end - 1).y
+        } else {
+            first.logical_pos.y
+        };
+        let lines = first.offset..self.goto_line_start(first, last + 1).offset;
+        consider(lines.clone());
+
+        // Blank lines are `None`.
+        let indentation = |line: Line| {
+            let mut columns = 0;
+            for &b in line.without_newline().to_cow().iter() {
+                match b {
+                    b' ' => columns += 1,
+                    b'\t' => columns += self.tab_size_eval(columns),
+                    _ => return Some(columns),
+                }
+            }
+            None
+        };
+        // Extends `range` by the adjacent lines for which `include` is true.
+        // Blank lines are only included if they're followed by such a line.
+        // Returns the indentation of the lines that stopped the extension.
+        let extend = |range: &mut Range<usize>, include: &dyn Fn(CoordType) -> bool| {
+            let mut stops = [None, None];
+            for line in self.lines(0..range.start).rev() {
+                match indentation(line) {
+                    Some(i) if include(i) => range.start = line.range().start,
+                    Some(i) => {
+                        stops[0] = Some(i);
+                        break;
+                    }
+                    None => {}
+                }
+            }
+            for line in self.lines(range.end..self.text_length()) {
+                match indentation(line) {
+                    Some(i) if include(i) => range.end = line.range().end,
+                    Some(i) => {
+                        stops[1] = Some(i);
+                        break;
+                    }
+                    None => {}
+                }
+            }
+            stops
+        };
+
+        // The paragraph, i.e. the surrounding lines up to the next blank ones.
+        if self.lines(lines.clone()).any(|line| indentation(line).is_some()) {
+            let mut paragraph = lines.clone();
+            for line in self.lines(0..paragraph.start).rev() {
+                if indentation(line).is_none() {
+                    break;
+                }
+                paragraph.start = line.range().start;
+            }
+            for line in self.lines(paragraph.end..self.text_length()) {
+                if indentation(line).is_none() {
+                    break;
+                }
+                paragraph.end = line.range().end;
+            }
+            consider(paragraph);
+        }
+
+        // The blocks of lines indented at least as deep as the selection, from the innermost out.
+        let mut level = self.lines(lines.clone()).filter_map(indentation).min().unwrap_or(0);
+        loop {
+            let mut block = lines.clone();
+            let stops = extend(&mut block, &|i| i >= level);
+            let contains_more = block.len() > current.len();
+            consider(block);
+            match stops.into_iter().flatten().max() {
+                Some(next) if !contains_more => level = next,
+                _ => break,
+            }
+        }
+
+        consider(0..self.text_length());
+        best
+    }
+
     /// Starts a new selection, if there's none already.
     pub fn start_selection(&mut self) {
         if self.selection.is_none() {
@@ -1081,10 +1471,28 @@ impl TextBuffer {
 
     /// Find the next occurrence of the given `pattern` and select it.
     pub fn find_and_select(&mut self, pattern: &str, options: SearchOptions) -> apperr::Result<()> {
+        self.find_and_select_impl(pattern, options, false)
+    }
+
+    /// Find the previous occurrence of the given `pattern` and select it.
+    pub fn find_and_select_prev(
+        &mut self,
+        pattern: &str,
+        options: SearchOptions,
+    ) -> apperr::Result<()> {
+        self.find_and_select_impl(pattern, options, true)
+    }
+
+    fn find_and_select_impl(
+        &mut self,
+        pattern: &str,
+        options: SearchOptions,
+        backwards: bool,
+    ) -> apperr::Result<()> {
         if let Some(search) = &mut self.search {
             let search = search.get_mut();
             // When the search input changes we must reset the search.
-            if search.pattern != pattern || search.options != options {
+            if search.search.pattern() != pattern || search.search.options() != options {
                 self.search = None;
             }
 
@@ -1109,12 +1517,28 @@ impl TextBuffer {
             }
         };
 
+        // There's no backwards search, so this needs the list of all hits.
+        if backwards {
+            self.find_update_matches(search);
+        }
+
         // If we previously searched through the entire document and found 0 matches,
         // then we can avoid searching again.
         if search.no_matches {
             return Ok(());
         }
 
+        if backwards {
+            let offset = match self.selection {
+                Some(TextBufferSelection { beg, end }) => {
+                    self.cursor_move_to_logical_internal(self.cursor, beg.min(end)).offset
+                }
+                _ => self.cursor.offset,
+            };
+            self.find_select_prev(search, offset);
+            return Ok(());
+        }
+
         // If the user moved the cursor since the last search, but the needle remained the same,
         // we still need to move the start of the search to the new cursor position.
         let next_search_offset = match self.selection {
@@ -1156,16 +1580,21 @@ impl TextBuffer {
     }
 
     /// Find all occurrences of the given `pattern` and replace them with `replacement`.
+    /// Returns the number of replacements.
     pub fn find_and_replace_all(
         &mut self,
         pattern: &str,
         options: SearchOptions,
         replacement: &[u8],
-    ) -> apperr::Result<()> {
+    ) -> apperr::Result<usize> {
         let scratch = scratch_arena(None);
         let mut search = self.find_construct_search(pattern, options)?;
         let mut offset = 0;
         let parsed_replacements = Self::find_parse_replacement(&scratch, &mut search, replacement);
+        let mut count = 0;
+
+        // All replacements form a single undo step.
+        self.edit_begin_grouping();
 
         loop {
             self.find_select_next(&mut search, offset, false);
@@ -1177,9 +1606,16 @@ impl TextBuffer {
                 self.find_fill_replacement(&mut search, replacement, &parsed_replacements);
             self.write(&replacement, self.cursor, true);
             offset = self.cursor.offset;
+            count += 1;
         }
 
-        Ok(())
+        self.edit_end_grouping();
+        Ok(count)
+    }
+
+    /// Compiles `pattern` into a [`Search`], which yields the matches in this buffer.
+    pub fn search(&self, pattern: &str, options: SearchOptions) -> apperr::Result<Search> {
+        Search::new(self, pattern, options)
     }
 
     fn find_construct_search(
@@ -1187,100 +1623,144 @@ impl TextBuffer {
         pattern: &str,
         options: SearchOptions,
     ) -> apperr::Result<ActiveSearch> {
-        if pattern.is_empty() {
-            return Err(apperr::Error::Icu(1)); // U_ILLEGAL_ARGUMENT_ERROR
-        }
-
-        let sanitized_pattern = if options.whole_word && options.use_regex {
-            Cow::Owned(format!(r"\b(?:{pattern})\b"))
-        } else if options.whole_word {
-            let mut p = String::with_capacity(pattern.len() + 16);
-            p.push_str(r"\b");
-
-            // Escape regex special characters.
-            let b = unsafe { p.as_mut_vec() };
-            for &byte in pattern.as_bytes() {
-                match byte {
-                    b'*' | b'?' | b'+' | b'[' | b'(' | b')' | b'{' | b'}' | b'^' | b'$' | b'|'
-                    | b'\\' | b'.' => {
-                        b.push(b'\\');
-                        b.push(byte);
-                    }
-                    _ => b.push(byte),
-                }
-            }
+        Ok(ActiveSearch {
+            search: Search::new(self, pattern, options)?,
+            selection_generation: 0,
+            next_search_offset: 0,
+            no_matches: false,
+            wrapped: false,
+            matches: None,
+        })
+    }
 
-            p.push_str(r"\b");
-            Cow::Owned(p)
+    /// Returns the number of hits of the active search and which one is selected,
+    /// or `None` if there's no active search.
+    pub fn search_status(&mut self) -> Option<SearchStatus> {
+        let search = unsafe { &mut *self.search.as_ref()?.get() };
+        self.find_update_matches(search);
+
+        let ranges = &search.matches.as_ref()?.ranges;
+        // After selecting a hit, the cursor is at its end.
+        let index = if self.selection.is_some()
+            && self.selection_generation == search.selection_generation
+        {
+            ranges.binary_search_by_key(&self.cursor.offset, |r| r.end).ok()
         } else {
-            Cow::Borrowed(pattern)
+            None
         };
 
-        let mut flags = icu::Regex::MULTILINE;
-        if !options.match_case {
-            flags |= icu::Regex::CASE_INSENSITIVE;
-        }
-        if !options.use_regex && !options.whole_word {
-            flags |= icu::Regex::LITERAL;
+        Some(SearchStatus { index, count: ranges.len(), wrapped: search.wrapped })
+    }
+
+    /// Brings [`ActiveSearch::matches`] up to date. The first call searches the
+    /// entire buffer. After that, only the lines that were modified are searched again.
+    fn find_update_matches(&mut self, search: &mut ActiveSearch) {
+        let text_length = self.text_length();
+        let (head, tail) = self.buffer.take_dirty(DirtyTracker::Search);
+        let cancel = AtomicBool::new(false);
+
+        let matches = match &mut search.matches {
+            Some(m) if m.text_length == text_length && head == text_length => return,
+            Some(m) => m,
+            None => {
+                let mut ranges = Vec::new();
+                search.search.find_in_range(self, 0..text_length, &cancel, |hit| {
+                    ranges.push(hit);
+                    true
+                });
+                search.matches.insert(SearchMatches { ranges, text_length })
+            }
+        };
+
+        if matches.text_length != text_length || head != text_length {
+            // Hits may depend on the surrounding text, e.g. for whole-word searches,
+            // so the modified range is extended to whole lines.
+            let beg = self.goto_line_start(self.cursor, self.offset_to_logical(head).y).offset;
+            let end = self.offset_to_logical(text_length - tail).y + 1;
+            let end = self.goto_line_start(self.cursor, end).offset;
+            let end_old = end + matches.text_length - text_length;
+
+            let first = matches.ranges.partition_point(|r| r.end <= beg);
+            let last = matches.ranges.partition_point(|r| r.start < end_old);
+            for r in &mut matches.ranges[last..] {
+                r.start = r.start + text_length - matches.text_length;
+                r.end = r.end + text_length - matches.text_length;
+            }
+
+            let mut hits = Vec::new();
+            search.search.find_in_range(self, beg..end, &cancel, |hit| {
+                hits.push(hit);
+                true
+            });
+            matches.ranges.splice(first..last, hits);
+            matches.text_length = text_length;
         }
 
-        // Move the start of the search to the start of the selection,
-        // or otherwise to the current cursor position.
+        // The hits were found with the same `Search`, so it has to go back to where it was.
+        search.search.seek(search.next_search_offset);
+        search.no_matches = matches.ranges.is_empty();
+    }
 
-        let text = unsafe { icu::Text::new(self)? };
-        let regex = unsafe { icu::Regex::new(&sanitized_pattern, flags, &text)? };
+    fn find_select_prev(&mut self, search: &mut ActiveSearch, offset: usize) {
+        let Some(matches) = &search.matches else {
+            return;
+        };
 
-        Ok(ActiveSearch {
-            pattern: pattern.to_string(),
-            options,
-            text,
-            regex,
-            buffer_generation: self.buffer.generation(),
-            selection_generation: 0,
-            next_search_offset: 0,
-            no_matches: false,
-        })
+        let ranges = &matches.ranges;
+        let mut idx = ranges.partition_point(|r| r.start < offset);
+        search.wrapped = idx == 0;
+        if search.wrapped {
+            idx = ranges.len();
+        }
+        let Some(range) = idx.checked_sub(1).map(|i| ranges[i].clone()) else {
+            return;
+        };
+
+        search.next_search_offset = range.end;
+        search.search.seek(range.end);
+        self.find_select_range(search, range);
     }
 
     fn find_select_next(&mut self, search: &mut ActiveSearch, offset: usize, wrap: bool) {
-        if search.buffer_generation != self.buffer.generation() {
-            unsafe { search.regex.set_text(&mut search.text, offset) };
-            search.buffer_generation = self.buffer.generation();
-            search.next_search_offset = offset;
-        } else if search.next_search_offset != offset {
+        if search.next_search_offset != offset {
             search.next_search_offset = offset;
-            search.regex.reset(offset);
+            search.search.seek(offset);
         }
 
-        let mut hit = search.regex.next();
+        let mut hit = search.search.next_match(self);
+        search.wrapped = false;
 
         // If we hit the end of the buffer, and we know that there's something to find,
         // start the search again from the beginning (= wrap around).
         if wrap && hit.is_none() && search.next_search_offset != 0 {
             search.next_search_offset = 0;
-            search.regex.reset(0);
-            hit = search.regex.next();
+            search.search.seek(0);
+            hit = search.search.next_match(self);
+            search.wrapped = hit.is_some();
         }
 
-        search.selection_generation = if let Some(range) = hit {
+        if let Some(range) = hit {
             // Now the search offset is no more at the start of the buffer.
             search.next_search_offset = range.end;
-
-            let beg = self.cursor_move_to_offset_internal(self.cursor, range.start);
-            let end = self.cursor_move_to_offset_internal(beg, range.end);
-
-            unsafe { self.set_cursor(end) };
-            self.make_cursor_visible();
-
-            self.set_selection(Some(TextBufferSelection {
-                beg: beg.logical_pos,
-                end: end.logical_pos,
-            }))
+            self.find_select_range(search, range);
         } else {
             // Avoid searching through the entire document again if we know there's nothing to find.
             search.no_matches = true;
-            self.set_selection(None)
-        };
+            search.selection_generation = self.set_selection(None);
+        }
+    }
+
+    fn find_select_range(&mut self, search: &mut ActiveSearch, range: Range<usize>) {
+        let beg = self.cursor_move_to_offset_internal(self.cursor, range.start);
+        let end = self.cursor_move_to_offset_internal(beg, range.end);
+
+        unsafe { self.set_cursor(end) };
+        self.make_cursor_visible();
+
+        search.selection_generation = self.set_selection(Some(TextBufferSelection {
+            beg: beg.logical_pos,
+            end: end.logical_pos,
+        }));
     }
 
     fn find_parse_replacement<'a>(
@@ -1290,11 +1770,11 @@ impl TextBuffer {
     ) -> Vec<RegexReplacement<'a>, &'a Arena> {
         let mut res = Vec::new_in(arena);
 
-        if !search.options.use_regex {
+        let Some(regex) = search.search.regex() else {
             return res;
-        }
+        };
 
-        let group_count = search.regex.group_count();
+        let group_count = regex.group_count();
         let mut text = Vec::new_in(arena);
         let mut text_beg = 0;
 
@@ -1352,6 +1832,15 @@ impl TextBuffer {
                             end += 1;
                             end < replacement.len() && replacement[end].is_ascii_digit()
                         } {}
+                    } else if ch == b'{'
+                        && let Some(len) = replacement[end..].iter().position(|&c| c == b'}')
+                        && let Ok(name) = std::str::from_utf8(&replacement[end + 1..end + len])
+                        && let Some(idx) = regex.group_from_name(name)
+                    {
+                        // Parse "${name}" into the index of the named group.
+                        acc = idx;
+                        acc_bad = false;
+                        end += len + 1;
                     }
                 }
 
@@ -1387,7 +1876,7 @@ impl TextBuffer {
         replacement: &'a [u8],
         parsed_replacements: &[RegexReplacement],
     ) -> Cow<'a, [u8]> {
-        if !search.options.use_regex {
+        if !search.search.options().use_regex {
             Cow::Borrowed(replacement)
         } else {
             let mut res = Vec::new();
@@ -1396,7 +1885,7 @@ impl TextBuffer {
                 match replacement {
                     RegexReplacement::Text(text) => res.extend_from_slice(text),
                     RegexReplacement::Group(group) => {
-                        if let Some(range) = search.regex.group(*group) {
+                        if let Some(range) = search.search.regex().and_then(|r| r.group(*group)) {
                             self.buffer.extract_raw(range, &mut res, usize::MAX);
                         }
                     }
@@ -1623,14 +2112,16 @@ impl TextBuffer {
             }
             CursorMovement::Word => {
                 let doc = &self.buffer as &dyn ReadableDocument;
+                let mut brk = icu::BreakIterator::with_text_buffer(icu::BreakKind::Word, self).ok();
                 let mut offset = self.cursor.offset;
 
                 while delta != 0 {
-                    if delta < 0 {
-                        offset = navigation::word_backward(doc, offset);
-                    } else {
-                        offset = navigation::word_forward(doc, offset);
-                    }
+                    offset = match (&mut brk, delta < 0) {
+                        (Some(brk), true) => navigation::word_backward_icu(brk, doc, offset),
+                        (Some(brk), false) => navigation::word_forward_icu(brk, doc, offset),
+                        (None, true) => navigation::word_backward(doc, offset),
+                        (None, false) => navigation::word_forward(doc, offset),
+                    };
                     delta -= sign;
                 }
 
@@ -1737,6 +2228,15 @@ impl TextBuffer {
             Some(TextBufferSelection { beg, end }) => minmax(beg, end),
         };
 
+        // Resolve the line each virtual text is on, sorted by line.
+        let mut virtual_lines = Vec::with_capacity_in(self.virtual_texts.len(), &*scratch);
+        for (i, v) in self.virtual_texts.iter().enumerate() {
+            if let Some(off) = self.anchor_offset(v.anchor) {
+                virtual_lines.push((self.offset_to_logical(off).y, i));
+            }
+        }
+        virtual_lines.sort_unstable();
+
         line.reserve(width as usize * 2);
 
         for y in 0..height {
@@ -1874,10 +2374,33 @@ impl TextBuffer {
                     let chunk = &chunk[..chunk.len().min(cursor_end.offset - global_off)];
                     let mut it = Utf8Chars::new(chunk, 0);
 
-                    // TODO: Looping char-by-char is bad for performance.
-                    // >25% of the total rendering time is spent here.
                     loop {
                         let chunk_off = it.offset();
+
+                        // Only control characters (incl. tabs) and selected spaces need to be
+                        // visualized. Everything up to the next one can be copied as is.
+                        let mut plain_end = simd::find_control(chunk, chunk_off);
+                        if selection_off.start < global_off + plain_end
+                            && selection_off.end > global_off + chunk_off
+                        {
+                            plain_end =
+                                selection_off.start.saturating_sub(global_off).max(chunk_off);
+                        }
+                        if plain_end > chunk_off {
+                            let run = &chunk[chunk_off..plain_end];
+                            // The chunk may end in the middle of a character, and the text may
+                            // be invalid. Those parts are left to the loop below.
+                            let valid = match str::from_utf8(run) {
+                                Ok(str) => str.len(),
+                                Err(err) => err.valid_up_to(),
+                            };
+                            if valid > 0 {
+                                line.push_str(unsafe { str::from_utf8_unchecked(&run[..valid]) });
+                                it.seek(chunk_off + valid);
+                                continue;
+                            }
+                        }
+
                         let global_off = global_off + chunk_off;
                         let Some(ch) = it.next() else {
                             break;
@@ -1965,6 +2488,40 @@ impl TextBuffer {
 
             fb.replace_text(destination.top + y, destination.left, destination.right, &line);
 
+            // Draw the virtual text after the last row of each logical line.
+            if cursor_beg.visual_pos.y == visual_line
+                && matches!(
+                    self.read_forward(cursor_end.offset).first(),
+                    None | Some(b'\r' | b'\n')
+                )
+                && cursor_end.visual_pos.x >= origin.x
+            {
+                let logical_y = cursor_beg.logical_pos.y;
+                let beg = virtual_lines.partition_point(|&(y, _)| y < logical_y);
+                let top = destination.top + y;
+                let mut left =
+                    destination.left + self.margin_width + cursor_end.visual_pos.x - origin.x;
+
+                for &(_, i) in virtual_lines[beg..].iter().take_while(|&&(y, _)| y == logical_y) {
+                    let v = &self.virtual_texts[i];
+                    let cols = unicode::MeasurementConfig::new(&v.text.as_bytes())
+                        .goto_visual(Point { x: CoordType::MAX, y: 0 })
+                        .visual_pos
+                        .x;
+
+                    left += 1;
+                    if left >= destination.right {
+                        break;
+                    }
+
+                    let rect = Rect { left, top, right: left + cols, bottom: top + 1 };
+                    fb.replace_text(top, left, destination.right, &v.text);
+                    fb.blend_fg(rect, fb.indexed(v.style.fg));
+                    fb.replace_attr(rect, Attributes::All, v.style.attributes);
+                    left += cols;
+                }
+            }
+
             cursor = cursor_end;
         }
 
@@ -2014,7 +2571,8 @@ impl TextBuffer {
             };
 
             if text.contains(cursor) {
-                fb.set_cursor(cursor, self.overtype);
+                let style = if self.overtype { CursorStyle::OVERTYPE } else { CursorStyle::INSERT };
+                fb.set_cursor(cursor, style);
 
                 if self.line_highlight_enabled && selection_beg >= selection_end {
                     fb.blend_bg(
@@ -2048,7 +2606,23 @@ impl TextBuffer {
         clipboard.write_was_line_copy(line_copy);
     }
 
+    /// Inserts the clipboard contents, cleaned up according to [`TextBuffer::paste_options()`].
     pub fn paste(&mut self, clipboard: &Clipboard) {
+        self.paste_impl(clipboard, true, false);
+    }
+
+    /// Like [`TextBuffer::paste()`], but shifts the indentation of the pasted lines
+    /// to match the indentation at the cursor. Useful for moving code between blocks.
+    pub fn paste_reindent(&mut self, clipboard: &Clipboard) {
+        self.paste_impl(clipboard, true, true);
+    }
+
+    /// Inserts the clipboard contents as they are, except for newlines being normalized.
+    pub fn paste_verbatim(&mut self, clipboard: &Clipboard) {
+        self.paste_impl(clipboard, false, false);
+    }
+
+    fn paste_impl(&mut self, clipboard: &Clipboard, sanitize: bool, reindent: bool) {
         let data = clipboard.read();
         if data.is_empty() {
             return;
@@ -2061,13 +2635,97 @@ impl TextBuffer {
             self.cursor
         };
 
-        self.write(data, at, true);
+        let data = if sanitize {
+            let mut sanitizer = PasteSanitizer::new(at.logical_pos.x == 0);
+            sanitizer.apply(data, true, &self.paste_options, self.tab_size, self.indent_with_tabs)
+        } else {
+            Cow::Borrowed(data)
+        };
+
+        let data = if reindent {
+            let line_start = self.goto_line_start(at, at.logical_pos.y);
+            let (indent_chars, indent_columns) =
+                self.measure_indent_internal(line_start.offset, CoordType::MAX);
+
+            let (first, target) = if clipboard.is_line_copy() {
+                (Some(0), indent_columns)
+            } else if at.logical_pos.x <= indent_chars {
+                // Within the indentation, the column of the cursor is the one to match.
+                let mut column = 0;
+                let mut off = line_start.offset;
+                while off < at.offset {
+                    let chunk = self.read_forward(off);
+                    let chunk = &chunk[..chunk.len().min(at.offset - off)];
+                    for &b in chunk {
+                        column += if b == b'\t' { self.tab_size_eval(column) } else { 1 };
+                    }
+                    off += chunk.len();
+                }
+                (Some(column), column)
+            } else {
+                (None, indent_columns)
+            };
+
+            Cow::Owned(paste::reindent(
+                &data,
+                first,
+                clipboard.is_line_copy(),
+                target,
+                self.tab_size,
+                self.indent_with_tabs,
+            ))
+        } else {
+            data
+        };
+
+        self.write(&data, at, true);
 
         if clipboard.is_line_copy() {
             self.cursor_move_to_logical(Point { x: pos.x, y: pos.y + 1 });
         }
     }
 
+    /// Inserts one chunk of a paste that arrives in pieces, like a large bracketed paste.
+    ///
+    /// The chunks after the `first` one are merged into its undo step, so that the
+    /// entire paste is undone at once. After the `last` chunk, the final newline is
+    /// added if needed, just like for [`TextBuffer::write_raw()`].
+    /// The text is cleaned up according to [`TextBuffer::paste_options()`].
+    pub fn paste_chunk(&mut self, mut text: &[u8], first: bool, last: bool) {
+        if first {
+            // Don't merge with a preceding paste.
+            self.last_history_type = HistoryType::Other;
+            self.paste_stream.pending_cr = false;
+            self.paste_stream.sanitizer = PasteSanitizer::new(self.cursor.logical_pos.x == 0);
+        }
+
+        // A CR at the end of the previous chunk was already written as a newline.
+        if self.paste_stream.pending_cr && text.first() == Some(&b'\n') {
+            text = &text[1..];
+        }
+        if !text.is_empty() {
+            self.paste_stream.pending_cr = text.last() == Some(&b'\r');
+        }
+
+        let text = self.paste_stream.sanitizer.apply(
+            text,
+            last,
+            &self.paste_options,
+            self.tab_size,
+            self.indent_with_tabs,
+        );
+
+        self.paste_stream.writing = true;
+        self.paste_stream.more = !last;
+        self.write(&text, self.cursor, true);
+        self.paste_stream.writing = false;
+        self.paste_stream.more = false;
+
+        if last {
+            self.last_history_type = HistoryType::Other;
+        }
+    }
+
     /// Inserts the user input `text` at the current cursor position.
     /// Replaces tabs with whitespace if needed, etc.
     pub fn write_canon(&mut self, text: &[u8]) {
@@ -2081,9 +2739,25 @@ impl TextBuffer {
     }
 
     fn write(&mut self, text: &[u8], at: Cursor, raw: bool) {
-        let history_type = if raw { HistoryType::Other } else { HistoryType::Write };
+        let history_type = if self.paste_stream.writing {
+            HistoryType::Paste
+        } else if raw {
+            HistoryType::Other
+        } else {
+            HistoryType::Write
+        };
         let mut edit_begun = false;
 
+        if self.undo_grouping == UndoGrouping::Word
+            && self.last_history_type == HistoryType::Write
+            && text.first().is_some_and(|&c| !c.is_ascii_whitespace())
+            && self.undo_stack.back().is_some_and(|entry| {
+                entry.borrow().added.last().is_some_and(|c| c.is_ascii_whitespace())
+            })
+        {
+            self.undo_barrier();
+        }
+
         // If we have an active selection, writing an empty `text`
         // will still delete the selection. As such, we check this first.
         if let Some((beg, end)) = self.selection_range_internal(false) {
@@ -2103,6 +2777,9 @@ impl TextBuffer {
             return;
         }
 
+        // Typing over a selection only replaces the selection.
+        let overtype = !raw && self.overtype && !edit_begun;
+
         if !edit_begun {
             self.edit_begin(history_type, at);
         }
@@ -2110,37 +2787,42 @@ impl TextBuffer {
         let mut offset = 0;
         let scratch = scratch_arena(None);
         let mut newline_buffer = ArenaString::new_in(&scratch);
+        let expand_tabs = !raw && !self.indent_with_tabs;
 
         loop {
-            // Can't use `unicode::newlines_forward` because bracketed paste uses CR instead of LF/CRLF.
-            let offset_next = memchr2(b'\r', b'\n', text, offset);
-            let line = &text[offset..offset_next];
             let column_before = self.cursor.logical_pos.x;
 
-            // Write the contents of the line into the buffer.
-            let mut line_off = 0;
-            while line_off < line.len() {
-                // Split the line into chunks of non-tabs and tabs.
-                let mut plain = line;
-                if !raw && !self.indent_with_tabs {
-                    let end = memchr2(b'\t', b'\t', line, line_off);
-                    plain = &line[line_off..end];
-                }
+            // Write the contents of the line into the buffer, split into chunks of non-tabs and tabs.
+            // Can't use `unicode::newlines_forward` because bracketed paste uses CR instead of LF/CRLF.
+            let mut line_end = offset;
+            loop {
+                let plain_beg = line_end;
+                line_end = if expand_tabs {
+                    memchr3(b'\r', b'\n', b'\t', text, line_end)
+                } else {
+                    memchr2(b'\r', b'\n', text, line_end)
+                };
 
                 // Non-tabs are written as-is, because the outer loop already handles newline translation.
-                self.edit_write(plain);
-                line_off += plain.len();
+                if line_end > plain_beg {
+                    self.edit_write(&text[plain_beg..line_end]);
+                }
 
                 // Now replace tabs with spaces.
-                while line_off < line.len() && line[line_off] == b'\t' {
+                while line_end < text.len() && text[line_end] == b'\t' {
                     let spaces = self.tab_size_eval(self.cursor.column);
                     let spaces = &TAB_WHITESPACE.as_bytes()[..spaces as usize];
                     self.edit_write(spaces);
-                    line_off += 1;
+                    line_end += 1;
+                }
+
+                if line_end >= text.len() || matches!(text[line_end], b'\r' | b'\n') {
+                    break;
                 }
             }
+            let line = &text[offset..line_end];
 
-            if !raw && self.overtype {
+            if overtype && !self.active_edit_failed {
                 let delete = self.cursor.logical_pos.x - column_before;
                 let end = self.cursor_move_to_logical_internal(
                     self.cursor,
@@ -2225,6 +2907,7 @@ impl TextBuffer {
         // In order to not annoy people with this, we only add a
         // newline if you just edited the very end of the buffer.
         if self.insert_final_newline
+            && !self.paste_stream.more
             && self.cursor.offset > 0
             && self.cursor.offset == self.text_length()
             && self.cursor.logical_pos.x > 0
@@ -2460,6 +3143,45 @@ impl TextBuffer {
         }));
     }
 
+    /// Sorts the lines spanned by the selection, or the entire document without one,
+    /// using the collation of [`icu::compare_strings`].
+    pub fn sort_selected_lines(&mut self) {
+        let selection = self.selection;
+        let [beg, mut end] = match selection {
+            Some(s) => minmax(s.beg, s.end),
+            None => [Point::default(), Point { x: 0, y: self.stats.logical_lines }],
+        };
+        // A selection that ends at the start of a line doesn't include that line.
+        if end.x != 0 || end.y == beg.y {
+            end.y += 1;
+        }
+
+        let beg = self.cursor_move_to_logical_internal(self.cursor, Point { x: 0, y: beg.y });
+        let end = self.cursor_move_to_logical_internal(beg, Point { x: 0, y: end.y });
+        let mut text = Vec::new();
+        self.buffer.extract_raw(beg.offset..end.offset, &mut text, 0);
+
+        let trailing_newline = text.ends_with(b"\n");
+        let body = if trailing_newline { &text[..text.len() - 1] } else { &text[..] };
+        let mut lines: Vec<&[u8]> = body
+            .split(|&c| c == b'\n')
+            .map(|line| line.strip_suffix(b"\r").unwrap_or(line))
+            .collect();
+        lines.sort_by(|a, b| icu::compare_strings(a, b));
+
+        let newline: &[u8] = if self.newlines_are_crlf { b"\r\n" } else { b"\n" };
+        let mut sorted = lines.join(newline);
+        if trailing_newline {
+            sorted.extend_from_slice(newline);
+        }
+        if sorted == text {
+            return;
+        }
+
+        self.replace_range(beg.offset..end.offset, &sorted);
+        self.set_selection(selection);
+    }
+
     /// Extracts the contents of the current selection.
     /// May optionally delete it, if requested. This is meant to be used for Ctrl+X.
     fn extract_selection(&mut self, delete: bool) -> Vec<u8> {
@@ -2554,11 +3276,26 @@ impl TextBuffer {
 
         let cursor_before = self.cursor;
         self.set_cursor_internal(cursor);
+        self.active_edit_failed = false;
 
         // If both the last and this are a Write/Delete operation, we skip allocating a new undo history item.
-        if history_type != self.last_history_type
-            || !matches!(history_type, HistoryType::Write | HistoryType::Delete)
-        {
+        let mut coalesce = history_type == self.last_history_type
+            && matches!(history_type, HistoryType::Write | HistoryType::Delete);
+        match self.undo_grouping {
+            UndoGrouping::Continuous | UndoGrouping::Word => {}
+            UndoGrouping::Pause(pause) => {
+                let now = Instant::now();
+                coalesce &= now.duration_since(self.last_history_time) <= pause;
+                self.last_history_time = now;
+            }
+            UndoGrouping::Keystroke => coalesce = false,
+        }
+        // The chunks of a paste are always a single step, regardless of the grouping.
+        if history_type == HistoryType::Paste {
+            coalesce = self.last_history_type == HistoryType::Paste;
+        }
+
+        if !coalesce {
             self.redo_stack.clear();
             while self.undo_stack.len() > 1000 {
                 self.undo_stack.pop_front();
@@ -2609,7 +3346,26 @@ impl TextBuffer {
     /// Writes `text` into the buffer at the current cursor position.
     /// It records the change in the undo stack.
     fn edit_write(&mut self, text: &[u8]) {
+        if self.active_edit_failed {
+            return;
+        }
+
         let logical_y_before = self.cursor.logical_pos.y;
+        let write_off = self.active_edit_off;
+
+        // Write! If the buffer can't grow enough, nothing is written,
+        // because a partial write could split a line or UTF-8 sequence.
+        {
+            let gap = self.buffer.allocate_gap(self.active_edit_off, text.len(), 0);
+            if gap.len() < text.len() {
+                self.buffer.commit_gap(0);
+                self.active_edit_failed = true;
+                self.write_error = Some(apperr::APP_FILE_TOO_LARGE);
+                return;
+            }
+            gap[..text.len()].copy_from_slice(text);
+            self.buffer.commit_gap(text.len());
+        }
 
         // Copy the written portion into the undo entry.
         {
@@ -2617,14 +3373,13 @@ impl TextBuffer {
             undo.added.extend_from_slice(text);
         }
 
-        // Write!
-        self.buffer.replace(self.active_edit_off..self.active_edit_off, text);
-
         // Move self.cursor to the end of the newly written text. Can't use `self.set_cursor_internal`,
         // because we're still in the progress of recalculating the line stats.
         self.active_edit_off += text.len();
         self.cursor = self.cursor_move_to_offset_internal(self.cursor, self.active_edit_off);
         self.stats.logical_lines += self.cursor.logical_pos.y - logical_y_before;
+
+        self.update_overlong_lines(write_off..self.active_edit_off, false);
     }
 
     /// Deletes the text between the current cursor position and `to`.
@@ -2635,6 +3390,7 @@ impl TextBuffer {
         let logical_y_before = self.cursor.logical_pos.y;
         let off = self.active_edit_off;
         let mut out_off = usize::MAX;
+        let touched_overlong = self.touches_overlong_line(off..to.offset);
 
         let mut undo = self.undo_stack.back_mut().unwrap().borrow_mut();
 
@@ -2654,6 +3410,81 @@ impl TextBuffer {
         self.buffer.allocate_gap(off, 0, count);
 
         self.stats.logical_lines += logical_y_before - to.logical_pos.y;
+
+        // Deleting a newline joins two lines, which may make the result overlong.
+        drop(undo);
+        self.update_overlong_lines(off..off, touched_overlong);
+    }
+
+    /// Returns whether the given `range` overlaps a line longer than [`MAX_LINE_LENGTH_FOR_LAYOUT`].
+    /// It's meant to be called before the `range` gets deleted, for [`TextBuffer::update_overlong_lines`].
+    fn touches_overlong_line(&self, range: Range<usize>) -> bool {
+        self.has_overlong_lines && self.has_long_line(range, self.overlong_line_length)
+    }
+
+    /// Updates `has_overlong_lines` after the text in `range` was written (or deleted, if empty).
+    /// `touched_overlong` is the result of [`TextBuffer::touches_overlong_line`] for the deleted text.
+    fn update_overlong_lines(&mut self, range: Range<usize>, touched_overlong: bool) {
+        if touched_overlong {
+            // The edited line may have been the only overlong one. Only in that case,
+            // when it isn't overlong anymore, does the entire buffer get scanned.
+            self.has_overlong_lines = self.has_long_line(range, self.overlong_line_length)
+                || self.has_long_line(0..self.text_length(), self.overlong_line_length);
+        } else if !self.has_overlong_lines {
+            self.has_overlong_lines = self.has_long_line(range, self.overlong_line_length);
+        }
+    }
+
+    /// Returns whether any of the lines that overlap the given `range` of offsets is at least
+    /// `limit` bytes long. Outside of the `range` at most `limit` bytes are scanned,
+    /// so that this stays cheap, even if it's called for every edit of a long line.
+    fn has_long_line(&self, range: Range<usize>, limit: usize) -> bool {
+        let text_length = self.text_length();
+        let mut len = 0;
+
+        // Measure the part of the first line that precedes the range.
+        let mut off = range.start;
+        while off > 0 && len < limit {
+            let chunk = self.buffer.read_backward(off);
+            let chunk = &chunk[chunk.len().saturating_sub(limit - len)..];
+            let (delta, _) = simd::lines_bwd(chunk, chunk.len(), 0, 0);
+            len += chunk.len() - delta;
+            if delta > 0 {
+                break;
+            }
+            off -= chunk.len();
+        }
+
+        // Measure each line up to the end of the one that contains the end of the range.
+        let mut off = range.start;
+        loop {
+            if len >= limit {
+                return true;
+            }
+            if off >= text_length {
+                return false;
+            }
+
+            let chunk = self.buffer.read_forward(off);
+            let chunk = &chunk[..chunk.len().min(range.end.saturating_sub(off) + limit - len)];
+            let (delta, line) = simd::lines_fwd(chunk, 0, 0, 1);
+            if line == 0 {
+                len += chunk.len();
+                off += chunk.len();
+                continue;
+            }
+
+            // `delta` points past the newline.
+            len += delta - 1;
+            if len >= limit {
+                return true;
+            }
+            off += delta;
+            if off > range.end {
+                return false;
+            }
+            len = 0;
+        }
     }
 
     /// Finalizes the current edit operation
@@ -2668,7 +3499,9 @@ impl TextBuffer {
         #[cfg(debug_assertions)]
         {
             let entry = self.undo_stack.back_mut().unwrap().borrow_mut();
-            debug_assert!(!entry.deleted.is_empty() || !entry.added.is_empty());
+            debug_assert!(
+                self.active_edit_failed || !entry.deleted.is_empty() || !entry.added.is_empty()
+            );
         }
 
         if let Some(info) = self.active_edit_line_info.take() {
@@ -2701,6 +3534,21 @@ impl TextBuffer {
             self.stats.visual_lines = self.stats.logical_lines;
         }
 
+        // A failed write may have left nothing to undo.
+        if self.active_edit_failed
+            && self.undo_stack.back().is_some_and(|entry| {
+                let entry = entry.borrow();
+                entry.deleted.is_empty() && entry.added.is_empty()
+            })
+        {
+            self.undo_stack.pop_back();
+            self.last_history_type = HistoryType::Other;
+        }
+
+        if self.has_overlong_lines {
+            self.set_word_wrap(true);
+        }
+
         self.recalc_after_content_changed();
     }
 
@@ -2761,6 +3609,8 @@ impl TextBuffer {
                 cursor
             };
 
+            let touched_overlong;
+            let written_range;
             {
                 let mut change = change.borrow_mut();
                 let change = &mut *change;
@@ -2769,6 +3619,8 @@ impl TextBuffer {
                 mem::swap(&mut change.deleted, &mut change.added);
 
                 // Delete the inserted portion.
+                touched_overlong =
+                    self.touches_overlong_line(cursor.offset..cursor.offset + change.deleted.len());
                 self.buffer.allocate_gap(cursor.offset, 0, change.deleted.len());
 
                 // Reinsert the deleted portion.
@@ -2805,6 +3657,8 @@ impl TextBuffer {
                         beg = end;
                         offset += written;
                     }
+
+                    written_range = cursor.offset..offset;
                 }
 
                 // Restore the previous line statistics.
@@ -2828,24 +3682,195 @@ impl TextBuffer {
                     self.last_history_type = HistoryType::Other;
                 }
             }
+
+            self.update_overlong_lines(written_range, touched_overlong);
         }
 
         if entry_buffer_generation.is_some() {
+            if self.has_overlong_lines {
+                self.set_word_wrap(true);
+            }
             self.recalc_after_content_changed();
         }
     }
 
-    /// For interfacing with ICU.
-    pub(crate) fn read_backward(&self, off: usize) -> &[u8] {
+    /// Replaces the text in `range` with `text`, verbatim.
+    ///
+    /// Unlike [`TextBuffer::write_raw()`] this doesn't depend on the cursor or selection,
+    /// which makes it the primary way to modify the buffer programmatically.
+    /// The change is recorded as a single undo step. Afterwards, the cursor is placed
+    /// at the end of the inserted text and the selection is cleared.
+    ///
+    /// Offsets are in bytes and are clamped to the text length.
+    /// They're snapped to the nearest preceding grapheme cluster boundary.
+    pub fn replace_range(&mut self, range: Range<usize>, text: &[u8]) {
+        let beg = self.cursor_move_to_offset_internal(self.cursor, range.start);
+        let end = self.cursor_move_to_offset_internal(beg, range.end.max(range.start));
+
+        if beg.offset == end.offset && text.is_empty() {
+            return;
+        }
+
+        self.set_selection(None);
+        self.edit_begin(HistoryType::Other, beg);
+        if end.offset > beg.offset {
+            self.edit_delete(end);
+        }
+        if !text.is_empty() {
+            self.edit_write(text);
+        }
+        self.edit_end();
+    }
+
+    /// Inserts `text` at the given byte `offset`.
+    /// See [`TextBuffer::replace_range()`].
+    pub fn insert(&mut self, offset: usize, text: &[u8]) {
+        self.replace_range(offset..offset, text);
+    }
+
+    /// Deletes the text in the given byte `range`.
+    /// See [`TextBuffer::replace_range()`].
+    pub fn delete_range(&mut self, range: Range<usize>) {
+        self.replace_range(range, b"");
+    }
+
+    /// Appends the text in the given byte `range` to `out`.
+    pub fn extract_range(&self, range: Range<usize>, out: &mut Vec<u8>) {
+        self.buffer.extract_raw(range, out, usize::MAX);
+    }
+
+    /// Returns an iterator over the contiguous chunks of text in `range`.
+    /// No copies are made, but the chunk boundaries are arbitrary.
+    pub fn chunks(&self, range: Range<usize>) -> Chunks<'_> {
+        let end = range.end.min(self.text_length());
+        Chunks { buffer: &self.buffer, off: range.start.min(end), end }
+    }
+
+    /// Creates an anchor at the given byte offset, which is then kept up to date
+    /// as text is inserted or deleted around it. Use it for bookmarks, diagnostics, etc.
+    ///
+    /// The offset is clamped and snapped to a grapheme cluster boundary.
+    pub fn anchor_create(&mut self, offset: usize, bias: AnchorBias) -> Anchor {
+        let offset = self.cursor_move_to_offset_internal(self.cursor, offset).offset;
+        self.buffer.anchors_mut().create(offset, bias)
+    }
+
+    /// Returns the current byte offset of the anchor, or `None` if it was removed.
+    /// Use [`TextBuffer::offset_to_logical()`] to convert it to a line/column position.
+    pub fn anchor_offset(&self, anchor: Anchor) -> Option<usize> {
+        self.buffer.anchors().offset(anchor)
+    }
+
+    /// Removes the anchor. It'll no longer be adjusted.
+    pub fn anchor_remove(&mut self, anchor: Anchor) {
+        self.buffer.anchors_mut().remove(anchor);
+    }
+
+    /// Attaches `text` to the end of the line containing `offset`, e.g. for
+    /// diagnostics or blame annotations. Virtual text is only drawn: It's not
+    /// part of the contents, won't be saved, and the cursor can't enter it.
+    ///
+    /// Only end-of-line annotations are supported. Inline hints in the middle of a line
+    /// would have to be accounted for by [`MeasurementConfig`], word wrap and every
+    /// cursor movement, which isn't worth it until something actually needs them.
+    ///
+    /// Returns the anchor the text is attached to, which is also its handle.
+    pub fn virtual_text_add(
+        &mut self,
+        offset: usize,
+        text: &str,
+        style: VirtualTextStyle,
+    ) -> Anchor {
+        let anchor = self.anchor_create(offset, AnchorBias::Left);
+        // Control characters would mess up the terminal.
+        let text = text.chars().map(|c| if c.is_control() { ' ' } else { c }).collect();
+        self.virtual_texts.push(VirtualText { anchor, text, style });
+        anchor
+    }
+
+    /// Removes the virtual text returned by [`TextBuffer::virtual_text_add()`].
+    pub fn virtual_text_remove(&mut self, anchor: Anchor) {
+        self.virtual_texts.retain(|v| v.anchor != anchor);
+        self.anchor_remove(anchor);
+    }
+
+    /// Removes all virtual text.
+    pub fn virtual_text_clear(&mut self) {
+        for v in mem::take(&mut self.virtual_texts) {
+            self.anchor_remove(v.anchor);
+        }
+    }
+
+    /// Computes a line-level diff from `old` to the current contents.
+    /// `old` may be an earlier [`Snapshot`] or e.g. the file contents on disk.
+    pub fn diff(&self, old: &dyn ReadableDocument) -> Vec<DiffHunk> {
+        diff_documents(old, &self.buffer)
+    }
+
+    /// Returns an immutable copy of the buffer contents, for use on other threads.
+    ///
+    /// The buffer retains the last snapshot, so that the next one
+    /// only needs to copy the parts that were modified in the meantime.
+    pub fn snapshot(&mut self) -> Snapshot {
+        let dirty = self.buffer.take_dirty(DirtyTracker::Snapshot);
+        let snapshot = Snapshot::new(&self.buffer, self.last_snapshot.as_ref(), dirty);
+        self.last_snapshot = Some(snapshot.clone());
+        snapshot
+    }
+
+    /// Returns a double-ended iterator over the lines in the given byte `range`.
+    /// Unless a line straddles the gap, no copies are made. See [`Line`].
+    pub fn lines(&self, range: Range<usize>) -> Lines<'_> {
+        Lines::new(&self.buffer, range)
+    }
+
+    /// Converts a byte offset into a logical position (line and grapheme column).
+    pub fn offset_to_logical(&self, offset: usize) -> Point {
+        self.cursor_move_to_offset_internal(self.cursor, offset).logical_pos
+    }
+
+    /// Converts a logical position (line and grapheme column) into a byte offset.
+    /// Positions past the end of a line are clamped to it.
+    pub fn logical_to_offset(&self, pos: Point) -> usize {
+        self.cursor_move_to_logical_internal(self.cursor, pos).offset
+    }
+
+    /// Returns the contiguous chunk of text that ends at `off`.
+    /// See [`ReadableDocument::read_backward()`].
+    pub fn read_backward(&self, off: usize) -> &[u8] {
         self.buffer.read_backward(off)
     }
 
-    /// For interfacing with ICU.
+    /// Returns the contiguous chunk of text that starts at `off`.
+    /// See [`ReadableDocument::read_forward()`].
     pub fn read_forward(&self, off: usize) -> &[u8] {
         self.buffer.read_forward(off)
     }
 }
 
+/// An iterator over the chunks of a [`TextBuffer`].
+/// See [`TextBuffer::chunks()`].
+pub struct Chunks<'a> {
+    buffer: &'a GapBuffer,
+    off: usize,
+    end: usize,
+}
+
+impl<'a> Iterator for Chunks<'a> {
+    type Item = &'a [u8];
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.off >= self.end {
+            return None;
+        }
+
+        let chunk = self.buffer.read_forward(self.off);
+        let chunk = &chunk[..chunk.len().min(self.end - self.off)];
+        self.off += chunk.len();
+        Some(chunk)
+    }
+}
+
 pub enum Bom {
     None,
     UTF8,
@@ -2857,6 +3882,9 @@ pub enum Bom {
 }
 
 const BOM_MAX_LEN: usize = 4;
+/// The minimum confidence (0-100) of [`icu::detect_encoding`] to switch away from UTF-8.
+/// Below that, showing the invalid bytes as such is more useful than a wild guess.
+const GUESS_ENCODING_MIN_CONFIDENCE: i32 = 30;
 
 fn detect_bom(bytes: &[u8]) -> Option<&'static str> {
     if bytes.len() >= 4 {
@@ -2883,3 +3911,581 @@ fn detect_bom(bytes: &[u8]) -> Option<&'static str> {
     }
     None
 }
+
+/// Guesses the encoding of text without a BOM. Since most files are UTF-8 nowadays,
+/// it only does so if the text isn't valid UTF-8. `complete` is false if there's more to come.
+fn guess_encoding(bytes: &[u8], complete: bool) -> Option<&'static str> {
+    // Mostly-ASCII UTF-16 is valid UTF-8, and its NULs would make it look binary.
+    if let Some(enc) = guess_utf16(bytes) {
+        return Some(enc);
+    }
+    match str::from_utf8(bytes) {
+        Ok(_) => return None,
+        // The chunk may end in the middle of a character.
+        Err(e) if e.error_len().is_none() && !complete => return None,
+        Err(_) => {}
+    }
+    if looks_binary(bytes) {
+        return None;
+    }
+    // If ICU thinks it's most likely UTF-8 after all, it's just a few invalid bytes.
+    match icu::detect_encoding(bytes).first() {
+        Some(&(enc, confidence))
+            if enc != "UTF-8" && confidence >= GUESS_ENCODING_MIN_CONFIDENCE =>
+        {
+            Some(enc)
+        }
+        _ => None,
+    }
+}
+
+/// Detects UTF-16 without a BOM by its NULs: In Latin scripts, every other byte is one.
+/// Binary files have them in both places, and so do CJK texts, which ICU detects instead.
+fn guess_utf16(bytes: &[u8]) -> Option<&'static str> {
+    let pairs = bytes.len() / 2;
+    if pairs == 0 {
+        return None;
+    }
+
+    let mut nuls = [0; 2];
+    for pair in bytes.chunks_exact(2) {
+        nuls[0] += (pair[0] == 0) as usize;
+        nuls[1] += (pair[1] == 0) as usize;
+    }
+
+    // Most pairs have a NUL on one side and (almost) none have it on the other.
+    match nuls {
+        [even, odd] if odd * 2 > pairs && even * 16 <= odd => Some("UTF-16LE"),
+        [even, odd] if even * 2 > pairs && odd * 16 <= even => Some("UTF-16BE"),
+        _ => None,
+    }
+}
+
+/// Binary files contain control characters that text files don't, like NUL.
+/// Any encoding we'd guess for them would only mangle them further.
+fn looks_binary(bytes: &[u8]) -> bool {
+    let mut off = 0;
+    loop {
+        off = simd::find_control(bytes, off);
+        match bytes.get(off) {
+            None => return false,
+            // Everything but whitespace, SUB (the DOS end-of-file marker) and ESC.
+            Some(0x00..=0x08 | 0x0e..=0x19 | 0x1c..=0x1f) => return true,
+            _ => off += 1,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::framebuffer::CaptureFormat;
+
+    fn contents(tb: &TextBuffer) -> Vec<u8> {
+        let mut out = Vec::new();
+        tb.extract_range(0..usize::MAX, &mut out);
+        out
+    }
+
+    /// Renders `tb` into a 16x3 framebuffer and returns it as plain text.
+    fn render(tb: &mut TextBuffer) -> String {
+        let arena = Arena::new(64 * 1024).unwrap();
+        let rect = Rect { left: 0, top: 0, right: 16, bottom: 3 };
+        let mut fb = Framebuffer::new();
+        fb.flip(Size { width: 16, height: 3 });
+        tb.render(Point::default(), rect, false, &mut fb);
+        fb.capture(&arena, CaptureFormat::PlainText).to_string()
+    }
+
+    #[test]
+    fn test_replace_range() {
+        let mut tb = TextBuffer::new(true).unwrap();
+        tb.insert(0, b"hello world");
+        tb.insert(5, b",\nbig");
+        assert_eq!(contents(&tb), b"hello,\nbig world");
+        assert_eq!(tb.logical_line_count(), 2);
+
+        tb.replace_range(7..10, b"small");
+        assert_eq!(contents(&tb), b"hello,\nsmall world");
+
+        tb.delete_range(5..7);
+        assert_eq!(contents(&tb), b"hellosmall world");
+        assert_eq!(tb.logical_line_count(), 1);
+
+        tb.undo();
+        assert_eq!(contents(&tb), b"hello,\nsmall world");
+        tb.undo();
+        tb.undo();
+        assert_eq!(contents(&tb), b"hello world");
+    }
+
+    // Requires ICU.
+    #[ignore]
+    #[test]
+    fn test_replace_named_groups() {
+        let mut tb = TextBuffer::new(true).unwrap();
+        tb.insert(0, b"a=1 b=2");
+        let options = SearchOptions { use_regex: true, ..Default::default() };
+        tb.find_and_replace_all(r"(?<k>\w)=(?<v>\d)", options, b"${v}:$1 ${x}").unwrap();
+        assert_eq!(contents(&tb), b"1:a ${x} 2:b ${x}");
+    }
+
+    #[test]
+    fn test_sort_selected_lines() {
+        let mut tb = TextBuffer::new(true).unwrap();
+        tb.insert(0, b"c\nb\na\nd");
+        tb.sort_selected_lines();
+        assert_eq!(contents(&tb), b"a\nb\nc\nd");
+
+        // Only the selected lines get sorted, even if the selection ends at the start of a line.
+        tb.replace_range(0..usize::MAX, b"z\ny\nx\nw\n");
+        tb.set_selection(Some(TextBufferSelection {
+            beg: Point { x: 1, y: 1 },
+            end: Point { x: 0, y: 3 },
+        }));
+        tb.sort_selected_lines();
+        assert_eq!(contents(&tb), b"z\nx\ny\nw\n");
+        tb.undo();
+        assert_eq!(contents(&tb), b"z\ny\nx\nw\n");
+    }
+
+    // Requires ICU.
+    #[ignore]
+    #[test]
+    fn test_word_motion_icu() {
+        let mut tb = TextBuffer::new(true).unwrap();
+        tb.insert(0, "foo สวัสดีครับ".as_bytes());
+        tb.cursor_move_to_logical(Point { x: 4, y: 0 });
+        tb.cursor_move_delta(CursorMovement::Word, 1);
+        // "สวัสดี" is 4 grapheme clusters.
+        assert_eq!(tb.cursor_logical_pos(), Point { x: 8, y: 0 });
+        tb.select_word();
+        assert_eq!(tb.extract_user_selection(false).unwrap(), "ครับ".as_bytes());
+    }
+
+    #[test]
+    fn test_grapheme_motion() {
+        let text = "a👨\u{200d}👩\u{200d}👧🇩🇪🇫🇷e\u{301}\r\nb";
+        let mut tb = TextBuffer::new(true).unwrap();
+        tb.insert(0, text.as_bytes());
+
+        // Cursor movement stops at exactly the cluster boundaries.
+        let clusters: Vec<_> = unicode::Graphemes::new(text.as_bytes()).collect();
+        tb.cursor_move_to_offset(0);
+        for r in &clusters {
+            assert_eq!(tb.cursor.offset, r.start);
+            tb.cursor_move_delta(CursorMovement::Grapheme, 1);
+        }
+        assert_eq!(tb.cursor.offset, text.len());
+        for r in clusters.iter().rev() {
+            tb.cursor_move_delta(CursorMovement::Grapheme, -1);
+            assert_eq!(tb.cursor.offset, r.start);
+        }
+
+        // Selection extends by whole clusters.
+        tb.cursor_move_to_offset(1);
+        tb.selection_update_delta(CursorMovement::Grapheme, 2);
+        assert_eq!(
+            tb.extract_user_selection(false).unwrap(),
+            "👨\u{200d}👩\u{200d}👧🇩🇪".as_bytes()
+        );
+        tb.set_selection(None);
+
+        // Backspace deletes whole clusters.
+        let flags_end = clusters[3].end;
+        tb.cursor_move_to_offset(flags_end);
+        tb.delete(CursorMovement::Grapheme, -1);
+        tb.delete(CursorMovement::Grapheme, -1);
+        tb.delete(CursorMovement::Grapheme, -1);
+        assert_eq!(contents(&tb), "ae\u{301}\r\nb".as_bytes());
+    }
+
+    #[test]
+    fn test_write_too_large() {
+        let mut tb = TextBuffer::new(true).unwrap();
+        let text = vec![b'a'; 100 * KIBI];
+        tb.write_raw(&text);
+        assert_eq!(tb.text_length(), text.len());
+        assert!(tb.take_write_error().is_none());
+
+        // The newline fits, but the write after it doesn't. Nothing of the latter must be written.
+        tb.write_raw(b"\n");
+        tb.write_raw(&text);
+        assert_eq!(tb.text_length(), text.len() + 1);
+        assert_eq!(tb.take_write_error(), Some(apperr::APP_FILE_TOO_LARGE));
+
+        // There's nothing to undo for the failed write, only the newline.
+        tb.undo();
+        assert_eq!(tb.text_length(), text.len());
+    }
+
+    #[test]
+    fn test_has_long_line() {
+        let mut tb = TextBuffer::new(false).unwrap();
+        tb.write_raw(b"abc\ndefgh\nij");
+        let len = tb.text_length();
+        assert!(tb.has_long_line(0..0, 3));
+        assert!(!tb.has_long_line(0..0, 4));
+        assert!(tb.has_long_line(0..len, 5));
+        assert!(!tb.has_long_line(0..len, 6));
+        // Only the lines that overlap the range count.
+        assert!(!tb.has_long_line(len..len, 3));
+        assert!(tb.has_long_line(len - 3..len, 5));
+
+        // Joining two lines makes one that's longer than either of them.
+        tb.cursor_move_to_offset(3);
+        tb.delete(CursorMovement::Grapheme, 1);
+        assert!(tb.has_long_line(3..3, 8));
+        assert!(!tb.has_long_line(3..3, 9));
+    }
+
+    #[test]
+    fn test_overlong_lines() {
+        let mut tb = TextBuffer::new(false).unwrap();
+        tb.overlong_line_length = 16;
+        tb.write_raw(b"short\n");
+        tb.write_raw(&[b'a'; 17]);
+        // An overlong line forces word wrap on.
+        assert!(tb.is_word_wrap_enabled());
+        tb.set_word_wrap(false);
+        assert!(tb.is_word_wrap_enabled());
+
+        // Deleting it lets it be turned off again.
+        tb.replace_range(6..tb.text_length(), b"");
+        tb.set_word_wrap(false);
+        assert!(!tb.is_word_wrap_enabled());
+
+        // Undoing the deletion brings it back.
+        tb.undo();
+        assert!(tb.is_word_wrap_enabled());
+
+        // Shortening it, but not enough, keeps it forced on.
+        tb.replace_range(6..7, b"");
+        tb.set_word_wrap(false);
+        assert!(tb.is_word_wrap_enabled());
+
+        // Replacing the contents checks them anew.
+        tb.copy_from_str_multiline(&"short\nline".to_string());
+        tb.set_word_wrap(false);
+        assert!(!tb.is_word_wrap_enabled());
+    }
+
+    #[test]
+    fn test_file_checksum() {
+        let path = std::env::temp_dir().join(format!("edit-test-checksum-{}", std::process::id()));
+        let data = b"\xEF\xBB\xBFhello\r\nworld\r\n";
+        std::fs::write(&path, data).unwrap();
+
+        // The checksum covers the bytes on disk, including the BOM.
+        let mut tb = TextBuffer::new(true).unwrap();
+        tb.read_file(&mut File::open(&path).unwrap(), None).unwrap();
+        assert_eq!(tb.encoding(), "UTF-8 BOM");
+        assert_eq!(tb.file_checksum(), crate::hash::hash(0, data));
+
+        tb.insert(0, b"x");
+        tb.write_file(&mut File::create(&path).unwrap()).unwrap();
+        assert_eq!(tb.file_checksum(), crate::hash::hash_file(0, &path).unwrap());
+
+        _ = std::fs::remove_file(&path);
+    }
+
+    #[test]
+    fn test_looks_binary() {
+        assert!(!looks_binary(b""));
+        assert!(!looks_binary(b"foo\tbar\r\n\x0c\x1b[0m\x7f\xc2\x85\x1a"));
+        assert!(looks_binary(b"\x7fELF\x02\x01\x01\x00"));
+        assert!(looks_binary(b"PK\x03\x04"));
+    }
+
+    #[test]
+    fn test_guess_utf16() {
+        let le: Vec<u8> = "héllo\r\n".encode_utf16().flat_map(u16::to_le_bytes).collect();
+        let be: Vec<u8> = "héllo\r\n".encode_utf16().flat_map(u16::to_be_bytes).collect();
+        assert_eq!(guess_encoding(&le, true), Some("UTF-16LE"));
+        assert_eq!(guess_encoding(&be, true), Some("UTF-16BE"));
+        assert_eq!(guess_utf16(b""), None);
+        assert_eq!(guess_utf16(b"hello"), None);
+        assert_eq!(guess_utf16(b"\x7fELF\x02\x01\x01\x00\x00\x00\x00\x00"), None);
+    }
+
+    #[test]
+    fn test_copy_from_str_multiline() {
+        let mut tb = TextBuffer::new(true).unwrap();
+        tb.copy_from_str_multiline(&"foo\nbar\nbaz".to_string());
+        assert_eq!(contents(&tb), b"foo\nbar\nbaz");
+        assert_eq!(tb.logical_line_count(), 3);
+        assert_eq!(tb.cursor_logical_pos(), Point { x: 3, y: 2 });
+        assert!(!tb.is_dirty());
+    }
+
+    #[test]
+    fn test_undo_grouping() {
+        let type_text = |tb: &mut TextBuffer, text: &str| {
+            for c in text.bytes() {
+                tb.write_canon(&[c]);
+            }
+        };
+
+        let mut tb = TextBuffer::new(true).unwrap();
+        type_text(&mut tb, "foo bar");
+        tb.undo();
+        assert_eq!(contents(&tb), b"");
+
+        tb.set_undo_grouping(UndoGrouping::Word);
+        type_text(&mut tb, "foo bar ");
+        tb.undo();
+        assert_eq!(contents(&tb), b"foo ");
+        tb.undo();
+        assert_eq!(contents(&tb), b"");
+
+        tb.set_undo_grouping(UndoGrouping::Keystroke);
+        type_text(&mut tb, "ab");
+        tb.undo();
+        assert_eq!(contents(&tb), b"a");
+        tb.undo();
+
+        tb.set_undo_grouping(UndoGrouping::Continuous);
+        type_text(&mut tb, "ab");
+        tb.undo_barrier();
+        type_text(&mut tb, "cd");
+        tb.undo();
+        assert_eq!(contents(&tb), b"ab");
+    }
+
+    #[test]
+    fn test_paste_chunk() {
+        let mut tb = TextBuffer::new(true).unwrap();
+        tb.set_undo_grouping(UndoGrouping::Keystroke);
+        tb.write_canon(b"x");
+        tb.paste_chunk(b"foo\r", true, false);
+        tb.paste_chunk(b"\nbar", false, false);
+        tb.paste_chunk(b"\rbaz", false, true);
+        assert_eq!(contents(&tb), b"xfoo\nbar\nbaz");
+        tb.undo();
+        assert_eq!(contents(&tb), b"x");
+        tb.redo();
+        assert_eq!(contents(&tb), b"xfoo\nbar\nbaz");
+
+        // A second paste is its own undo step.
+        tb.paste_chunk(b"!", true, true);
+        tb.undo();
+        assert_eq!(contents(&tb), b"xfoo\nbar\nbaz");
+    }
+
+    #[test]
+    fn test_paste_options() {
+        let mut clipboard = Clipboard::default();
+        clipboard.write(b"\tif x:\r\n\t\ty\x1b\r\n".to_vec());
+
+        let mut tb = TextBuffer::new(true).unwrap();
+        tb.set_indent_with_tabs(false);
+        tb.set_paste_options(PasteOptions {
+            control_chars: ControlChars::Visualize,
+            convert_indentation: true,
+        });
+        tb.paste(&clipboard);
+        assert_eq!(contents(&tb), "    if x:\n        y\u{241b}\n".as_bytes());
+
+        tb.select_all();
+        tb.paste_verbatim(&clipboard);
+        assert_eq!(contents(&tb), b"\tif x:\n\t\ty\x1b\n");
+    }
+
+    #[test]
+    fn test_paste_reindent() {
+        let mut clipboard = Clipboard::default();
+        clipboard.write(b"if x:\n\t\ty\n\tz".to_vec());
+
+        let mut tb = TextBuffer::new(true).unwrap();
+        tb.set_indent_with_tabs(false);
+        tb.write_raw(b"def f():\n    \n");
+        tb.cursor_move_to_logical(Point { x: 4, y: 1 });
+        tb.paste_reindent(&clipboard);
+        assert_eq!(contents(&tb), b"def f():\n    if x:\n        y\n    z\n");
+
+        // Whole lines go above the current one, at its indentation.
+        clipboard.write(b"a\n    b\n".to_vec());
+        clipboard.write_was_line_copy(true);
+        tb.cursor_move_to_logical(Point { x: 0, y: 3 });
+        tb.paste_reindent(&clipboard);
+        assert_eq!(contents(&tb), b"def f():\n    if x:\n        y\n    a\n        b\n    z\n");
+    }
+
+    #[test]
+    fn test_overtype() {
+        let mut tb = TextBuffer::new(true).unwrap();
+        tb.set_overtype(true);
+        tb.write_raw("ae\u{301}b\ncd".as_bytes());
+
+        // Whole grapheme clusters are replaced.
+        tb.cursor_move_to_logical(Point { x: 1, y: 0 });
+        tb.write_canon(b"x");
+        assert_eq!(contents(&tb), b"axb\ncd");
+
+        // The end of the line isn't overwritten.
+        tb.write_canon(b"yz");
+        assert_eq!(contents(&tb), b"axyz\ncd");
+
+        tb.select_all();
+        tb.write_canon(b"q");
+        assert_eq!(contents(&tb), b"q");
+    }
+
+    #[test]
+    fn test_search_status() {
+        let options = SearchOptions::default();
+        let mut tb = TextBuffer::new(true).unwrap();
+        tb.write_raw(b"foo bar\nfoo\nbaz foo\n");
+        tb.cursor_move_to_offset(0);
+
+        let status = |tb: &mut TextBuffer| {
+            let s = tb.search_status().unwrap();
+            (s.index, s.count, s.wrapped)
+        };
+        let matches = |tb: &TextBuffer| {
+            let search = unsafe { &*tb.search.as_ref().unwrap().get() };
+            search.matches.as_ref().unwrap().ranges.clone()
+        };
+
+        assert_eq!(tb.search_status(), None);
+        tb.find_and_select("foo", options).unwrap();
+        assert_eq!(status(&mut tb), (Some(0), 3, false));
+        tb.find_and_select("foo", options).unwrap();
+        tb.find_and_select("foo", options).unwrap();
+        assert_eq!(status(&mut tb), (Some(2), 3, false));
+        tb.find_and_select("foo", options).unwrap();
+        assert_eq!(status(&mut tb), (Some(0), 3, true));
+        tb.find_and_select_prev("foo", options).unwrap();
+        assert_eq!(status(&mut tb), (Some(2), 3, true));
+        tb.find_and_select_prev("foo", options).unwrap();
+        assert_eq!(status(&mut tb), (Some(1), 3, false));
+
+        // Modifications only update the hits on the affected lines.
+        tb.insert(0, b"foofoo\n");
+        tb.delete_range(15..19);
+        assert_eq!(tb.search_status().unwrap().count, 4);
+        assert_eq!(matches(&tb), [0..3, 3..6, 7..10, 19..22]);
+
+        tb.insert(18, b"d");
+        tb.insert(tb.text_length(), b"foo");
+        assert_eq!(tb.search_status().unwrap().count, 5);
+        assert_eq!(matches(&tb), [0..3, 3..6, 7..10, 20..23, 24..27]);
+    }
+
+    #[test]
+    fn test_selection_expand() {
+        let mut tb = TextBuffer::new(true).unwrap();
+        tb.write_raw(
+            b"fn main() {\n    if x {\n        foo(bar);\n        baz();\n    }\n}\n\nnext para\n",
+        );
+        tb.cursor_move_to_offset(36);
+
+        let mut steps = Vec::new();
+        for _ in 0..7 {
+            tb.selection_expand();
+            steps.push(tb.selection_range().map(|(beg, end)| beg.offset..end.offset).unwrap());
+        }
+        // Word, line, the 2 indented blocks, the paragraph and the document, which is as far as it goes.
+        assert_eq!(steps, [35..38, 23..41, 23..56, 12..62, 0..64, 0..75, 0..75]);
+
+        tb.selection_shrink();
+        tb.selection_shrink();
+        assert_eq!(tb.selection_range().map(|(beg, end)| beg.offset..end.offset), Some(12..62));
+        for _ in 0..4 {
+            tb.selection_shrink();
+        }
+        assert!(!tb.has_selection());
+        assert_eq!(tb.cursor.offset, 36);
+
+        // A new selection starts over.
+        tb.selection_expand();
+        tb.cursor_move_to_offset(66);
+        tb.selection_expand();
+        tb.selection_shrink();
+        tb.selection_shrink();
+        assert_eq!(tb.cursor.offset, 66);
+    }
+
+    #[test]
+    fn test_anchors() {
+        let mut tb = TextBuffer::new(true).unwrap();
+        tb.insert(0, b"foo\nbar\nbaz\n");
+        let bar = tb.anchor_create(4, AnchorBias::Right);
+        let baz = tb.anchor_create(8, AnchorBias::Left);
+
+        tb.insert(0, b"hello\n");
+        tb.delete_range(12..14);
+        assert_eq!(tb.anchor_offset(bar), Some(10));
+        assert_eq!(tb.anchor_offset(baz), Some(12));
+        assert_eq!(tb.offset_to_logical(12), Point { x: 2, y: 2 });
+
+        tb.anchor_remove(baz);
+        assert_eq!(tb.anchor_offset(baz), None);
+
+        tb.undo();
+        tb.undo();
+        assert_eq!(tb.anchor_offset(bar), Some(4));
+    }
+
+    #[test]
+    fn test_render_control_chars() {
+        let mut tb = TextBuffer::new(true).unwrap();
+        tb.insert(0, "a\tb\x01c\u{85}d\u{a0}e\x7f\n a b ".as_bytes());
+        assert_eq!(render(&mut tb), "a   b␁c␦d\u{a0}e␡\n a b\n\n");
+
+        // Only selected whitespace is visualized.
+        tb.cursor_move_to_offset(tb.text_length() - 3);
+        tb.selection_update_offset(tb.text_length());
+        assert_eq!(render(&mut tb), "a   b␁c␦d\u{a0}e␡\n a･b･\n\n");
+    }
+
+    #[test]
+    fn test_virtual_text() {
+        let style =
+            VirtualTextStyle { fg: IndexedColor::BrightBlack, attributes: Attributes::Italic };
+        let mut tb = TextBuffer::new(true).unwrap();
+        tb.insert(0, b"foo\nbar");
+        tb.virtual_text_add(1, "hint", style);
+        tb.virtual_text_add(5, "a\tb", style);
+
+        // Rendered after the end of the line, with control characters replaced.
+        assert_eq!(render(&mut tb), "foo hint\nbar a b\n\n");
+
+        // The anchors follow edits.
+        tb.insert(0, b"x\n");
+        assert_eq!(render(&mut tb), "x\nfoo hint\nbar a b\n");
+        tb.delete_range(0..2);
+        tb.delete_range(3..4);
+        assert_eq!(render(&mut tb), "foobar hint a b\n\n\n");
+
+        // The cursor can't enter it.
+        tb.cursor_move_to_logical(Point { x: 10, y: 0 });
+        assert_eq!(tb.cursor_logical_pos(), Point { x: 6, y: 0 });
+
+        // It's not part of the contents.
+        assert_eq!(contents(&tb), b"foobar");
+        let mut saved = String::new();
+        tb.save_as_string(&mut saved);
+        assert_eq!(saved, "foobar");
+
+        tb.virtual_text_clear();
+        assert_eq!(render(&mut tb), "foobar\n\n\n");
+    }
+
+    #[test]
+    fn test_chunks_and_positions() {
+        let mut tb = TextBuffer::new(true).unwrap();
+        tb.insert(0, b"abc\ndef\nghi");
+        // Move the gap into the middle, so that there are 2 chunks.
+        tb.delete_range(5..6);
+        tb.insert(5, b"e");
+
+        let joined: Vec<u8> = tb.chunks(2..9).flatten().copied().collect();
+        assert_eq!(joined, b"c\ndef\ng");
+        assert_eq!(tb.chunks(100..200).count(), 0);
+
+        assert_eq!(tb.offset_to_logical(5), Point { x: 1, y: 1 });
+        assert_eq!(tb.logical_to_offset(Point { x: 2, y: 2 }), 10);
+        assert_eq!(tb.logical_to_offset(Point { x: 99, y: 0 }), 3);
+    }
+}
@@ -4,6 +4,7 @@
 use std::ops::Range;
 
 use crate::document::ReadableDocument;
+use crate::icu;
 
 #[derive(Clone, Copy, PartialEq, Eq)]
 enum CharClass {
@@ -200,6 +201,72 @@ impl WordNavigation for WordBackward<'_> {
     }
 }
 
+/// Like [`word_forward`], but with the word boundaries of `brk`.
+pub fn word_forward_icu(
+    brk: &mut icu::BreakIterator,
+    doc: &dyn ReadableDocument,
+    offset: usize,
+) -> usize {
+    let mut beg = offset;
+
+    // Skip one newline and any whitespace, followed by one segment.
+    while let Some(end) = brk.following(beg) {
+        let first = doc.read_forward(beg).first().copied().unwrap_or(b'\n');
+        match WORD_CLASSIFIER[first as usize] {
+            CharClass::Newline if beg == offset => {}
+            CharClass::Newline => break,
+            CharClass::Whitespace => {}
+            _ => return end,
+        }
+        beg = end;
+    }
+
+    beg
+}
+
+/// The backward version of `word_forward_icu`.
+pub fn word_backward_icu(
+    brk: &mut icu::BreakIterator,
+    doc: &dyn ReadableDocument,
+    offset: usize,
+) -> usize {
+    let mut end = offset;
+
+    while let Some(beg) = brk.preceding(end) {
+        let last = doc.read_backward(end).last().copied().unwrap_or(b'\n');
+        match WORD_CLASSIFIER[last as usize] {
+            CharClass::Newline if end == offset => {}
+            CharClass::Newline => break,
+            CharClass::Whitespace => {}
+            _ => return beg,
+        }
+        end = beg;
+    }
+
+    end
+}
+
+/// Like [`word_select`], but with the word boundaries of `brk`.
+pub fn word_select_icu(
+    brk: &mut icu::BreakIterator,
+    doc: &dyn ReadableDocument,
+    offset: usize,
+) -> Range<usize> {
+    let is_newline =
+        |b: Option<&u8>| b.is_none_or(|&b| WORD_CLASSIFIER[b as usize] == CharClass::Newline);
+
+    // Prefer the segment after the offset, unless it's a newline (or the end of the document).
+    if !is_newline(doc.read_forward(offset).first()) {
+        let beg = if brk.is_boundary(offset) { offset } else { brk.preceding(offset).unwrap_or(0) };
+        let end = brk.following(offset).unwrap_or(offset);
+        return beg..end;
+    }
+    if !is_newline(doc.read_backward(offset).last()) {
+        return brk.preceding(offset).unwrap_or(0)..offset;
+    }
+    offset..offset
+}
+
 /// Returns the offset range of the "word" at the given offset.
 /// Does not cross newlines. Works similar to VS Code.
 pub fn word_select(doc: &dyn ReadableDocument, offset: usize) -> Range<usize> {
@@ -287,4 +354,39 @@ mod test {
         assert_eq!(word_backward(&"Hello   ".as_bytes(), 7), 0);
         assert_eq!(word_backward(&"Hello\n\n".as_bytes(), 7), 6);
     }
+
+    // Requires ICU.
+    #[ignore]
+    #[test]
+    fn test_word_navigation_icu() {
+        let forward = |text: &str, offset| {
+            let mut brk = icu::BreakIterator::new(icu::BreakKind::Word, text).unwrap();
+            word_forward_icu(&mut brk, &text.as_bytes(), offset)
+        };
+        let backward = |text: &str, offset| {
+            let mut brk = icu::BreakIterator::new(icu::BreakKind::Word, text).unwrap();
+            word_backward_icu(&mut brk, &text.as_bytes(), offset)
+        };
+        let select = |text: &str, offset| {
+            let mut brk = icu::BreakIterator::new(icu::BreakKind::Word, text).unwrap();
+            word_select_icu(&mut brk, &text.as_bytes(), offset)
+        };
+
+        assert_eq!(forward("Hello World", 0), 5);
+        assert_eq!(forward("Hello World", 5), 11);
+        assert_eq!(forward("   Hello", 0), 8);
+        assert_eq!(forward("\n\nHello", 0), 1);
+        // Thai has no spaces between words.
+        assert_eq!(forward("สวัสดีครับ", 0), 18);
+
+        assert_eq!(backward("Hello World", 11), 6);
+        assert_eq!(backward("Hello   ", 7), 0);
+        assert_eq!(backward("Hello\n\n", 7), 6);
+        assert_eq!(backward("สวัสดีครับ", 30), 18);
+
+        assert_eq!(select("foo bar", 5), 4..7);
+        assert_eq!(select("foo bar", 7), 4..7);
+        assert_eq!(select("foo  bar", 3), 3..5);
+        assert_eq!(select("foo\n", 3), 0..3);
+    }
 }
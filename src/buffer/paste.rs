@@ -0,0 +1,283 @@
+// Copyright (c) Microsoft Corporation.
+// Licensed under the MIT License.
+
+use std::borrow::Cow;
+
+use crate::helpers::CoordType;
+
+/// What [`super::TextBuffer::paste()`] does with control characters
+/// other than tabs and newlines.
+#[derive(Default, Clone, Copy, PartialEq, Eq, Debug)]
+pub enum ControlChars {
+    /// Insert them as they are.
+    #[default]
+    Keep,
+    /// Drop them.
+    Strip,
+    /// Replace them with their counterpart from the Control Pictures block, e.g. ␛ for ESC.
+    Visualize,
+}
+
+/// How [`super::TextBuffer::paste()`] cleans up the pasted text.
+///
+/// Newlines are always converted to those of the document.
+/// [`super::TextBuffer::paste_verbatim()`] ignores these options.
+#[derive(Default, Clone, Copy, PartialEq, Eq, Debug)]
+pub struct PasteOptions {
+    pub control_chars: ControlChars,
+    /// Convert the indentation of each line to tabs or spaces,
+    /// depending on [`super::TextBuffer::indent_with_tabs()`].
+    pub convert_indentation: bool,
+}
+
+impl PasteOptions {
+    fn is_noop(&self) -> bool {
+        self.control_chars == ControlChars::Keep && !self.convert_indentation
+    }
+}
+
+/// Applies [`PasteOptions`] to text that may arrive in chunks.
+/// The state carries the indentation of the current line across chunk boundaries.
+#[derive(Clone, Copy, Default)]
+pub(super) struct PasteSanitizer {
+    /// Whether we're still inside the leading whitespace of a line.
+    in_indent: bool,
+    /// The column the leading whitespace read so far extends to.
+    column: CoordType,
+    /// The column the whitespace written so far extends to.
+    emitted: CoordType,
+}
+
+impl PasteSanitizer {
+    /// `at_line_start` should be true if the paste begins at the start of a line.
+    /// Otherwise, the leading whitespace of the first line isn't indentation.
+    pub fn new(at_line_start: bool) -> Self {
+        Self { in_indent: at_line_start, column: 0, emitted: 0 }
+    }
+
+    /// Sanitizes the next chunk of text. `last` indicates whether it's the final one.
+    pub fn apply<'a>(
+        &mut self,
+        text: &'a [u8],
+        last: bool,
+        options: &PasteOptions,
+        tab_size: CoordType,
+        indent_with_tabs: bool,
+    ) -> Cow<'a, [u8]> {
+        if options.is_noop() {
+            return Cow::Borrowed(text);
+        }
+
+        let tab_size = tab_size.max(1);
+        let mut out = Vec::with_capacity(text.len());
+
+        for &b in text {
+            if self.in_indent && options.convert_indentation {
+                match b {
+                    b' ' => {
+                        self.column += 1;
+                        continue;
+                    }
+                    b'\t' => {
+                        self.column = (self.column / tab_size + 1) * tab_size;
+                        continue;
+                    }
+                    _ => self.flush_indent(&mut out, tab_size, indent_with_tabs),
+                }
+            }
+
+            match b {
+                b'\r' | b'\n' => {
+                    out.push(b);
+                    self.in_indent = true;
+                    self.column = 0;
+                    self.emitted = 0;
+                    continue;
+                }
+                b'\t' => out.push(b),
+                0x00..0x20 | 0x7f => match options.control_chars {
+                    ControlChars::Keep => out.push(b),
+                    ControlChars::Strip => {}
+                    // U+2400 to U+241F mirror the C0 controls and U+2421 is DEL.
+                    ControlChars::Visualize => {
+                        let c = if b == 0x7f { 0x21 } else { b };
+                        out.extend_from_slice(&[0xe2, 0x90, 0x80 + c]);
+                    }
+                },
+                _ => out.push(b),
+            }
+
+            self.in_indent = false;
+        }
+
+        // Pending indentation is held back, because the next chunk may continue it.
+        if last && self.in_indent && options.convert_indentation {
+            self.flush_indent(&mut out, tab_size, indent_with_tabs);
+        }
+
+        Cow::Owned(out)
+    }
+
+    fn flush_indent(&mut self, out: &mut Vec<u8>, tab_size: CoordType, indent_with_tabs: bool) {
+        if indent_with_tabs {
+            loop {
+                let next = (self.emitted / tab_size + 1) * tab_size;
+                if next > self.column {
+                    break;
+                }
+                out.push(b'\t');
+                self.emitted = next;
+            }
+        }
+        while self.emitted < self.column {
+            out.push(b' ');
+            self.emitted += 1;
+        }
+    }
+}
+
+/// Shifts the indentation of `text` so that its least indented line ends up at column `target`,
+/// while the lines keep their indentation relative to each other. Blank lines lose their whitespace.
+///
+/// `first` is the column at which the first line gets inserted, if that's within the indentation
+/// of the line. If it's `None`, the paste follows other text, and the first line is left as is.
+/// Unless `whole_lines` is true, a first line without indentation is assumed to have been copied
+/// from after the indentation and doesn't count towards the least indented one.
+pub(super) fn reindent(
+    text: &[u8],
+    first: Option<CoordType>,
+    whole_lines: bool,
+    target: CoordType,
+    tab_size: CoordType,
+    indent_with_tabs: bool,
+) -> Vec<u8> {
+    let tab_size = tab_size.max(1);
+    let measure = |line: &[u8]| {
+        let mut columns = 0;
+        let mut len = 0;
+        for &b in line {
+            match b {
+                b' ' => columns += 1,
+                b'\t' => columns = (columns / tab_size + 1) * tab_size,
+                _ => break,
+            }
+            len += 1;
+        }
+        let blank = line[len..].iter().all(|&b| b == b'\r' || b == b'\n');
+        (len, columns, blank)
+    };
+
+    let mut lines = text.split_inclusive(|&b| b == b'\n');
+    let first_line = lines.next().unwrap_or_default();
+    let (_, first_columns, first_blank) = measure(first_line);
+    let skip_first = first_blank || (first_columns == 0 && !whole_lines && text.contains(&b'\n'));
+    let base = lines
+        .filter_map(|line| {
+            let (_, columns, blank) = measure(line);
+            if blank { None } else { Some(columns) }
+        })
+        .chain((first.is_some() && !skip_first).then_some(first_columns))
+        .min()
+        .unwrap_or(0);
+
+    let mut out = Vec::with_capacity(text.len());
+    for (i, line) in text.split_inclusive(|&b| b == b'\n').enumerate() {
+        let start = if i == 0 { first } else { Some(0) };
+        let Some(start) = start else {
+            out.extend_from_slice(line);
+            continue;
+        };
+
+        let (len, columns, blank) = measure(line);
+        if !blank {
+            let column = if i == 0 && skip_first { target } else { target + columns - base };
+            PasteSanitizer { in_indent: false, column, emitted: start }.flush_indent(
+                &mut out,
+                tab_size,
+                indent_with_tabs,
+            );
+        }
+        out.extend_from_slice(&line[len..]);
+    }
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn sanitize(chunks: &[&str], options: PasteOptions, indent_with_tabs: bool) -> String {
+        let mut sanitizer = PasteSanitizer::new(true);
+        let mut out = Vec::new();
+        for (i, chunk) in chunks.iter().enumerate() {
+            let last = i == chunks.len() - 1;
+            out.extend_from_slice(&sanitizer.apply(
+                chunk.as_bytes(),
+                last,
+                &options,
+                4,
+                indent_with_tabs,
+            ));
+        }
+        String::from_utf8(out).unwrap()
+    }
+
+    #[test]
+    fn test_control_chars() {
+        let text = ["a\x1b[0m\tb\x00\x7f\r\n"];
+        let options = |control_chars| PasteOptions { control_chars, convert_indentation: false };
+
+        assert_eq!(sanitize(&text, options(ControlChars::Keep), false), text[0]);
+        assert_eq!(sanitize(&text, options(ControlChars::Strip), false), "a[0m\tb\r\n");
+        assert_eq!(sanitize(&text, options(ControlChars::Visualize), false), "a␛[0m\tb␀␡\r\n");
+    }
+
+    #[test]
+    fn test_convert_indentation() {
+        let options = PasteOptions { control_chars: ControlChars::Keep, convert_indentation: true };
+
+        assert_eq!(sanitize(&["\tfoo\n  \t  bar\n"], options, false), "    foo\n      bar\n");
+        assert_eq!(sanitize(&["        foo\n   \tbar\n"], options, true), "\t\tfoo\n\tbar\n");
+        // Whitespace after the indentation is left alone.
+        assert_eq!(sanitize(&["  a \tb"], options, true), "  a \tb");
+        // The indentation may be split across chunks.
+        assert_eq!(sanitize(&["a\n    ", "\n"], options, true), "a\n\t\n");
+        assert_eq!(sanitize(&["a\n  ", "  b"], options, true), "a\n\tb");
+        assert_eq!(sanitize(&["a\n\t", "  b"], options, false), "a\n      b");
+
+        // A paste in the middle of a line has no indentation on its first line.
+        let mut sanitizer = PasteSanitizer::new(false);
+        assert_eq!(&*sanitizer.apply(b"    a\n    b", true, &options, 4, true), b"    a\n\tb");
+    }
+
+    #[test]
+    fn test_reindent() {
+        let reindent = |text: &str, first, whole_lines, target, indent_with_tabs| {
+            String::from_utf8(reindent(
+                text.as_bytes(),
+                first,
+                whole_lines,
+                target,
+                4,
+                indent_with_tabs,
+            ))
+            .unwrap()
+        };
+
+        // Copied from after the indentation of the first line, pasted into an empty line.
+        let text = "if x {\n            foo();\n  \n        }\n";
+        assert_eq!(reindent(text, Some(4), false, 4, false), "if x {\n        foo();\n\n    }\n");
+        assert_eq!(reindent(text, Some(0), false, 4, true), "\tif x {\n\t\tfoo();\n\n\t}\n");
+        // The same, pasted after other text on a line indented by 2.
+        assert_eq!(reindent(text, None, false, 2, false), "if x {\n      foo();\n\n  }\n");
+
+        // Whole lines keep the indentation of the first one relative to the others.
+        let text = "a\n    b\n";
+        assert_eq!(reindent(text, Some(0), true, 4, false), "    a\n        b\n");
+        assert_eq!(reindent(text, Some(0), false, 4, false), "    a\n    b\n");
+
+        // Tabs count up to the next tab stop, and the existing indentation isn't repeated.
+        assert_eq!(reindent("\t  a\r\n\tb", Some(2), true, 2, false), "  a\r\n  b");
+        assert_eq!(reindent("  x", Some(4), false, 4, true), "x");
+    }
+}
@@ -0,0 +1,420 @@
+// Copyright (c) Microsoft Corporation.
+// Licensed under the MIT License.
+
+use std::borrow::Cow;
+use std::ops::Range;
+use std::sync::atomic::{AtomicBool, Ordering};
+
+use super::TextBuffer;
+use crate::unicode::Utf8Chars;
+use crate::{apperr, icu, simd};
+
+/// Options for a search operation.
+#[derive(Default, Clone, Copy, Eq, PartialEq)]
+pub struct SearchOptions {
+    /// If true, the search is case-sensitive.
+    pub match_case: bool,
+    /// If true, the search matches whole words.
+    pub whole_word: bool,
+    /// If true, the search uses regex.
+    pub use_regex: bool,
+}
+
+/// A compiled search pattern that yields the matches in a [`TextBuffer`] one by one.
+///
+/// It's not bound to the buffer it was created with. Instead, the buffer is passed to each
+/// call and if it was modified in the meantime, the search resumes from its last position.
+/// This allows callers to interleave searching with editing (e.g. replace all),
+/// or spread a long search across multiple frames.
+///
+/// Case-insensitive plain text searches use a built-in matcher with full case folding
+/// (see [`icu::fold_char`]), since ICU regexes ignore locale-specific case folding rules.
+/// Without ICU, it's used for case-sensitive plain text searches as well.
+/// Case-insensitive searches for ASCII text skip ahead with [`simd::find_ascii_icase`].
+pub struct Search {
+    pattern: String,
+    options: SearchOptions,
+    matcher: Matcher,
+    /// The buffer and its [`TextBuffer::generation()`] that the regex is synchronized with.
+    buffer: *const TextBuffer,
+    buffer_generation: u32,
+    /// The offset after the last match, or where the search was moved to.
+    offset: usize,
+    /// Set by [`Search::seek()`], because resetting the regex requires a buffer.
+    needs_reset: bool,
+}
+
+enum Matcher {
+    Icu {
+        text: icu::Text,
+        regex: icu::Regex,
+    },
+    /// The characters of the pattern, case folded unless `match_case` is set.
+    /// `ascii` holds the same pattern if it's ASCII and case-insensitive, and is empty otherwise.
+    /// See [`find_literal`].
+    Literal {
+        needle: Vec<char>,
+        ascii: Vec<u8>,
+    },
+}
+
+impl Search {
+    /// Compiles `pattern`. Without `use_regex`, it's matched literally.
+    pub fn new(tb: &TextBuffer, pattern: &str, options: SearchOptions) -> apperr::Result<Self> {
+        if pattern.is_empty() {
+            return Err(apperr::Error::Icu(1)); // U_ILLEGAL_ARGUMENT_ERROR
+        }
+
+        let icu = icu::init();
+        if !options.use_regex && (!options.match_case || icu.is_err()) {
+            let mut needle = Vec::with_capacity(pattern.len());
+            for c in pattern.chars() {
+                needle.extend_from_slice(&fold(c, options));
+            }
+            let ascii = if !options.match_case && needle.iter().all(char::is_ascii) {
+                needle.iter().map(|&c| c as u8).collect()
+            } else {
+                Vec::new()
+            };
+            let matcher = Matcher::Literal { needle, ascii };
+            return Ok(Self::with_matcher(tb, pattern, options, matcher));
+        }
+        icu?;
+
+        let sanitized_pattern = if options.whole_word && options.use_regex {
+            Cow::Owned(format!(r"\b(?:{pattern})\b"))
+        } else if options.whole_word {
+            let mut p = String::with_capacity(pattern.len() + 16);
+            p.push_str(r"\b");
+
+            // Escape regex special characters.
+            let b = unsafe { p.as_mut_vec() };
+            for &byte in pattern.as_bytes() {
+                match byte {
+                    b'*' | b'?' | b'+' | b'[' | b'(' | b')' | b'{' | b'}' | b'^' | b'$' | b'|'
+                    | b'\\' | b'.' => {
+                        b.push(b'\\');
+                        b.push(byte);
+                    }
+                    _ => b.push(byte),
+                }
+            }
+
+            p.push_str(r"\b");
+            Cow::Owned(p)
+        } else {
+            Cow::Borrowed(pattern)
+        };
+
+        let mut flags = icu::Regex::MULTILINE;
+        if !options.match_case {
+            flags |= icu::Regex::CASE_INSENSITIVE;
+        }
+        if !options.use_regex && !options.whole_word {
+            flags |= icu::Regex::LITERAL;
+        }
+
+        let text = unsafe { icu::Text::new(tb)? };
+        let regex = unsafe { icu::Regex::new(&sanitized_pattern, flags, &text)? };
+        Ok(Self::with_matcher(tb, pattern, options, Matcher::Icu { text, regex }))
+    }
+
+    fn with_matcher(
+        tb: &TextBuffer,
+        pattern: &str,
+        options: SearchOptions,
+        matcher: Matcher,
+    ) -> Self {
+        Self {
+            pattern: pattern.to_string(),
+            options,
+            matcher,
+            buffer: tb,
+            buffer_generation: tb.generation(),
+            offset: 0,
+            needs_reset: false,
+        }
+    }
+
+    pub fn pattern(&self) -> &str {
+        &self.pattern
+    }
+
+    pub fn options(&self) -> SearchOptions {
+        self.options
+    }
+
+    /// Returns the compiled regex, if this is a regex search.
+    pub(super) fn regex(&mut self) -> Option<&mut icu::Regex> {
+        match &mut self.matcher {
+            Matcher::Icu { regex, .. } if self.options.use_regex => Some(regex),
+            _ => None,
+        }
+    }
+
+    /// Makes the next search start at the given byte offset.
+    pub fn seek(&mut self, offset: usize) {
+        self.offset = offset;
+        self.needs_reset = true;
+    }
+
+    /// Returns the byte range of the next match, or `None` at the end of the buffer.
+    pub fn next_match(&mut self, tb: &TextBuffer) -> Option<Range<usize>> {
+        let hit = match &mut self.matcher {
+            Matcher::Icu { text, regex } => {
+                let buffer = tb as *const TextBuffer;
+
+                if self.buffer != buffer || self.buffer_generation != tb.generation() {
+                    // The buffer may have moved in memory, or it may be a different one.
+                    unsafe {
+                        text.set_text_buffer(tb);
+                        regex.set_text(text, self.offset);
+                    }
+                    self.buffer = buffer;
+                    self.buffer_generation = tb.generation();
+                } else if self.needs_reset {
+                    regex.reset(self.offset);
+                }
+                self.needs_reset = false;
+
+                regex.next()?
+            }
+            Matcher::Literal { needle, ascii } => {
+                find_literal(tb, needle, ascii, self.offset, self.options)?
+            }
+        };
+
+        self.offset = hit.end;
+        Some(hit)
+    }
+
+    /// Streams the matches within `range` to `on_match`, until it returns `false`.
+    /// `cancel` is checked before each match, allowing e.g. a timer or another thread to abort it.
+    ///
+    /// Returns `true` if the end of the range was reached.
+    pub fn find_in_range(
+        &mut self,
+        tb: &TextBuffer,
+        range: Range<usize>,
+        cancel: &AtomicBool,
+        mut on_match: impl FnMut(Range<usize>) -> bool,
+    ) -> bool {
+        self.seek(range.start);
+
+        loop {
+            if cancel.load(Ordering::Relaxed) {
+                return false;
+            }
+            match self.next_match(tb) {
+                Some(hit) if hit.end <= range.end => {
+                    if !on_match(hit) {
+                        return false;
+                    }
+                }
+                _ => return true,
+            }
+        }
+    }
+}
+
+fn fold(c: char, options: SearchOptions) -> icu::CaseFold {
+    if options.match_case { c.into() } else { icu::fold_char(c) }
+}
+
+/// Finds the next occurrence of `needle` at or after `offset`.
+/// A match must consist of whole characters, so "s" doesn't match half of "ß" (= "ss").
+///
+/// If given, `ascii` is the ASCII equivalent of `needle`, used to skip to the next candidate.
+fn find_literal(
+    tb: &TextBuffer,
+    needle: &[char],
+    ascii: &[u8],
+    mut offset: usize,
+    options: SearchOptions,
+) -> Option<Range<usize>> {
+    // Like `\b` in ICU regexes.
+    let is_boundary = |a: Option<char>, b: Option<char>| is_word_char(a) != is_word_char(b);
+    let mut prev = char_before(tb, offset);
+    // Up to here, every character has to be checked individually. See `skip_ascii`.
+    let mut slow_until = 0;
+
+    loop {
+        if !ascii.is_empty() && offset >= slow_until {
+            let next = skip_ascii(tb, ascii, offset, &mut slow_until);
+            if next != offset {
+                prev = char_before(tb, next);
+                offset = next;
+            }
+        }
+
+        let (first, first_len) = char_at(tb, offset)?;
+        let mut end = offset;
+        let mut last = None;
+        let mut rest = needle;
+
+        while !rest.is_empty() {
+            match char_at(tb, end) {
+                Some((c, len)) if let Some(r) = rest.strip_prefix(&*fold(c, options)) => {
+                    rest = r;
+                    end += len;
+                    last = Some(c);
+                }
+                _ => {
+                    last = None;
+                    break;
+                }
+            }
+        }
+
+        if last.is_some()
+            && (!options.whole_word
+                || (is_boundary(prev, Some(first))
+                    && is_boundary(last, char_at(tb, end).map(|(c, _)| c))))
+        {
+            return Some(offset..end);
+        }
+
+        prev = Some(first);
+        offset += first_len;
+    }
+}
+
+/// Returns the offset of the next ASCII case-insensitive match of `needle` at or after `offset`.
+///
+/// Non-ASCII characters may fold to ASCII ("ß" to "ss", "K" (Kelvin) to "k"), and the gap
+/// splits the text into two chunks. If a candidate overlaps with either, it returns early
+/// and sets `slow_until` to the end of the region that `find_literal` must check on its own.
+fn skip_ascii(tb: &TextBuffer, needle: &[u8], offset: usize, slow_until: &mut usize) -> usize {
+    let chunk = tb.read_forward(offset);
+    let hit = simd::find_ascii_icase(chunk, 0, needle);
+
+    if hit < chunk.len() && chunk[hit].is_ascii() {
+        return offset + hit;
+    }
+
+    // All bytes before `hit` are ASCII, so these are all character boundaries.
+    *slow_until = offset + hit + 1;
+    offset + hit.saturating_sub(needle.len() - 1)
+}
+
+fn is_word_char(c: Option<char>) -> bool {
+    c.is_some_and(|c| c.is_alphanumeric() || c == '_')
+}
+
+/// Returns the character at `offset` and its length in bytes.
+fn char_at(tb: &TextBuffer, offset: usize) -> Option<(char, usize)> {
+    // A character may straddle the gap, so copy it out first.
+    let mut buf = [0; 4];
+    let mut len = 0;
+    while len < buf.len() {
+        let chunk = tb.read_forward(offset + len);
+        if chunk.is_empty() {
+            break;
+        }
+        let n = chunk.len().min(buf.len() - len);
+        buf[len..len + n].copy_from_slice(&chunk[..n]);
+        len += n;
+    }
+
+    let mut chars = Utf8Chars::new(&buf[..len], 0);
+    let c = chars.next()?;
+    Some((c, chars.offset()))
+}
+
+/// Returns the character that ends at `offset`.
+fn char_before(tb: &TextBuffer, offset: usize) -> Option<char> {
+    let chunk = tb.read_backward(offset);
+    let tail = &chunk[chunk.len().saturating_sub(4)..];
+    // Find the last byte that isn't a continuation byte.
+    let beg = tail.iter().rposition(|&b| (b as i8) >= -0x40)?;
+    Utf8Chars::new(&tail[beg..], 0).next()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Requires ICU.
+    #[ignore]
+    #[test]
+    fn test_search() {
+        let mut tb = TextBuffer::new(true).unwrap();
+        tb.insert(0, b"foo Bar\nbar baz bar");
+
+        let mut search = tb.search("bar", SearchOptions::default()).unwrap();
+        let cancel = AtomicBool::new(false);
+        let mut hits = Vec::new();
+        assert!(search.find_in_range(&tb, 0..usize::MAX, &cancel, |r| {
+            hits.push(r);
+            true
+        }));
+        assert_eq!(hits, vec![4..7, 8..11, 16..19]);
+
+        // The search resumes after edits.
+        search.seek(0);
+        assert_eq!(search.next_match(&tb), Some(4..7));
+        tb.delete_range(0..4);
+        assert_eq!(search.next_match(&tb), Some(12..15));
+        assert_eq!(search.next_match(&tb), None);
+
+        cancel.store(true, Ordering::Relaxed);
+        assert!(!search.find_in_range(&tb, 0..usize::MAX, &cancel, |_| true));
+    }
+
+    #[test]
+    fn test_find_literal() {
+        let mut tb = TextBuffer::new(true).unwrap();
+        tb.insert(0, "Ärger über ärger_x ÄRGER".as_bytes());
+
+        let find = |tb: &TextBuffer, needle: &str, options: SearchOptions| {
+            let needle: Vec<char> =
+                needle.chars().flat_map(|c| fold(c, options).to_vec()).collect();
+            let mut hits = Vec::new();
+            let mut offset = 0;
+            while let Some(hit) = find_literal(tb, &needle, &[], offset, options) {
+                offset = hit.end;
+                hits.push(hit);
+            }
+
+            // The ASCII fast path must yield the same results.
+            if !options.match_case && needle.iter().all(char::is_ascii) {
+                let ascii: Vec<u8> = needle.iter().map(|&c| c as u8).collect();
+                let mut offset = 0;
+                let mut hits_ascii = Vec::new();
+                while let Some(hit) = find_literal(tb, &needle, &ascii, offset, options) {
+                    offset = hit.end;
+                    hits_ascii.push(hit);
+                }
+                assert_eq!(hits, hits_ascii);
+            }
+
+            hits
+        };
+
+        assert_eq!(find(&tb, "ärger", SearchOptions::default()), vec![0..6, 13..19, 22..28]);
+        let whole_word = SearchOptions { whole_word: true, ..Default::default() };
+        assert_eq!(find(&tb, "ärger", whole_word), vec![0..6, 22..28]);
+        let match_case = SearchOptions { match_case: true, ..Default::default() };
+        assert_eq!(find(&tb, "ÄRGER", match_case), vec![22..28]);
+        assert_eq!(find(&tb, "r ü", SearchOptions::default()), vec![5..9]);
+        assert_eq!(find(&tb, "ÄRGER ÜBER", SearchOptions::default()), vec![0..12]);
+
+        // Full case folding: "ß" matches "SS", but "S" doesn't match half of it.
+        tb.replace_range(0..usize::MAX, "STRASSE straße".as_bytes());
+        assert_eq!(find(&tb, "straße", SearchOptions::default()), vec![0..7, 8..15]);
+        assert_eq!(find(&tb, "se", SearchOptions::default()), vec![5..7]);
+        assert_eq!(find(&tb, "xyz", SearchOptions::default()), vec![]);
+
+        // Matches that straddle the gap, or that contain non-ASCII characters
+        // which fold to ASCII, aren't found by the SIMD search alone.
+        let text = "x".repeat(100) + " Straße STRASSE \u{212a}elvin kelvin xx";
+        tb.replace_range(0..usize::MAX, text.as_bytes());
+        tb.delete_range(110..112);
+        tb.insert(110, b"TR");
+        assert_eq!(find(&tb, "strasse", SearchOptions::default()), vec![101..108, 109..116]);
+        assert_eq!(find(&tb, "kelvin", whole_word), vec![117..125, 126..132]);
+        assert_eq!(find(&tb, "x xx", SearchOptions::default()), vec![]);
+        assert_eq!(find(&tb, "n xx", SearchOptions::default()), vec![131..135]);
+        assert_eq!(find(&tb, "xxx", whole_word), vec![]);
+    }
+}
@@ -0,0 +1,223 @@
+// Copyright (c) Microsoft Corporation.
+// Licensed under the MIT License.
+
+use std::ops::Range;
+use std::sync::Arc;
+
+use super::GapBuffer;
+use crate::document::ReadableDocument;
+use crate::helpers::*;
+use crate::simd::lines_bwd;
+
+const CHUNK_SIZE: usize = 64 * KIBI;
+
+#[derive(Clone)]
+struct Chunk {
+    off: usize,
+    data: Arc<[u8]>,
+}
+
+/// An immutable copy of the contents of a [`super::TextBuffer`].
+///
+/// It's `Send + Sync` and cheap to clone, so it can be handed off
+/// to background threads for searching, indexing, highlighting, etc.
+///
+/// The contents are stored in reference-counted chunks. When a new snapshot
+/// is taken, all chunks that weren't modified since the previous one are shared.
+/// Chunks are split after newlines, wherever possible.
+#[derive(Clone, Default)]
+pub struct Snapshot {
+    chunks: Arc<[Chunk]>,
+    len: usize,
+    generation: u32,
+}
+
+impl Snapshot {
+    /// Creates a new snapshot of `buffer`, reusing the chunks of `prev`.
+    /// `dirty` is the result of [`GapBuffer::take_dirty()`] since `prev` was taken.
+    pub(super) fn new(buffer: &GapBuffer, prev: Option<&Snapshot>, dirty: (usize, usize)) -> Self {
+        let new_len = buffer.len();
+        let mut chunks = Vec::new();
+        let mut suffix: &[Chunk] = &[];
+        let mut suffix_shift = 0;
+
+        if let Some(prev) = prev {
+            let old_len = prev.len;
+            let head = dirty.0.min(old_len);
+            let tail = dirty.1.min(old_len - head);
+
+            // Leading chunks are reused as-is...
+            chunks.extend(prev.chunks.iter().take_while(|c| c.off + c.data.len() <= head).cloned());
+
+            // ...and trailing chunks have their offset adjusted.
+            let idx = prev.chunks.partition_point(|c| c.off < old_len - tail);
+            suffix = &prev.chunks[idx..];
+            suffix_shift = new_len as isize - old_len as isize;
+
+            // Don't reuse a chunk if it doesn't start after a newline anymore.
+            // Otherwise, we may split a grapheme cluster across chunks.
+            let mid_beg = chunks.last().map_or(0, |c: &Chunk| c.off + c.data.len());
+            while let Some(c) = suffix.first() {
+                let off = c.off.wrapping_add_signed(suffix_shift);
+                if off <= mid_beg || buffer.read_backward(off).last() == Some(&b'\n') {
+                    break;
+                }
+                suffix = &suffix[1..];
+            }
+        }
+
+        let mid_beg = chunks.last().map_or(0, |c| c.off + c.data.len());
+        let mid_end = suffix.first().map_or(new_len, |c| c.off.wrapping_add_signed(suffix_shift));
+        let mut off = mid_beg;
+
+        while off < mid_end {
+            let end = (off + CHUNK_SIZE).min(mid_end);
+            let mut data = Vec::with_capacity(end - off);
+            buffer.extract_raw(off..end, &mut data, 0);
+
+            if end < mid_end {
+                let (newline, _) = lines_bwd(&data, data.len(), 0, 0);
+                if newline > 0 {
+                    data.truncate(newline);
+                } else {
+                    // A really long line. Let's at least not split a UTF-8 sequence.
+                    let mut cut = data.len();
+                    let is_continuation = |b: u8| b & 0xC0 == 0x80;
+                    if is_continuation(buffer.read_forward(end)[0]) {
+                        while cut > 1 && is_continuation(data[cut - 1]) {
+                            cut -= 1;
+                        }
+                        // `data[cut - 1]` is now the lead byte of the split sequence.
+                        cut -= 1;
+                    }
+                    data.truncate(cut.max(1));
+                }
+            }
+
+            let len = data.len();
+            chunks.push(Chunk { off, data: data.into() });
+            off += len;
+        }
+
+        chunks.extend(
+            suffix.iter().map(|c| Chunk {
+                off: c.off.wrapping_add_signed(suffix_shift),
+                data: c.data.clone(),
+            }),
+        );
+
+        Self { chunks: chunks.into(), len: new_len, generation: buffer.generation() }
+    }
+
+    #[allow(clippy::len_without_is_empty)]
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    /// The [`super::TextBuffer::generation()`] at the time the snapshot was taken.
+    pub fn generation(&self) -> u32 {
+        self.generation
+    }
+
+    /// Appends the text in the given byte `range` to `out`.
+    pub fn extract_range(&self, range: Range<usize>, out: &mut Vec<u8>) {
+        let end = range.end.min(self.len);
+        let mut off = range.start.min(end);
+
+        while off < end {
+            let chunk = self.read_forward(off);
+            let chunk = &chunk[..chunk.len().min(end - off)];
+            out.extend_from_slice(chunk);
+            off += chunk.len();
+        }
+    }
+}
+
+impl ReadableDocument for Snapshot {
+    fn read_forward(&self, off: usize) -> &[u8] {
+        let idx = self.chunks.partition_point(|c| c.off + c.data.len() <= off);
+        match self.chunks.get(idx) {
+            Some(c) => &c.data[off - c.off..],
+            None => &[],
+        }
+    }
+
+    fn read_backward(&self, off: usize) -> &[u8] {
+        let off = off.min(self.len);
+        let idx = self.chunks.partition_point(|c| c.off + c.data.len() < off);
+        match self.chunks.get(idx) {
+            Some(c) if off > 0 => &c.data[..off - c.off],
+            _ => &[],
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::buffer::DirtyTracker;
+
+    fn contents(s: &Snapshot) -> Vec<u8> {
+        let mut out = Vec::new();
+        s.extract_range(0..usize::MAX, &mut out);
+        out
+    }
+
+    fn take(gb: &mut GapBuffer, prev: Option<&Snapshot>) -> Snapshot {
+        let dirty = gb.take_dirty(DirtyTracker::Snapshot);
+        Snapshot::new(gb, prev, dirty)
+    }
+
+    #[test]
+    fn test_reuse() {
+        let mut text = String::new();
+        for i in 0..20000 {
+            text.push_str(&format!("line {i}\n"));
+        }
+
+        let mut gb = GapBuffer::new(false).unwrap();
+        gb.replace(0..0, text.as_bytes());
+
+        let s1 = take(&mut gb, None);
+        assert_eq!(contents(&s1), text.as_bytes());
+        assert!(s1.chunks.len() > 2);
+        assert!(s1.chunks.iter().all(|c| c.data.ends_with(b"\n")));
+
+        // Modify the middle of the text.
+        let mid = text.len() / 2;
+        gb.replace(mid..mid + 3, b"hello\nworld");
+        text.replace_range(mid..mid + 3, "hello\nworld");
+
+        let s2 = take(&mut gb, Some(&s1));
+        assert_eq!(contents(&s2), text.as_bytes());
+        assert_eq!(contents(&s1).len(), text.len() - 8);
+
+        // The first and last chunks must be shared.
+        assert!(Arc::ptr_eq(&s1.chunks[0].data, &s2.chunks[0].data));
+        assert!(Arc::ptr_eq(&s1.chunks.last().unwrap().data, &s2.chunks.last().unwrap().data));
+
+        // No modifications = everything is shared.
+        let s3 = take(&mut gb, Some(&s2));
+        assert_eq!(s3.chunks.len(), s2.chunks.len());
+        assert!(s2.chunks.iter().zip(s3.chunks.iter()).all(|(a, b)| Arc::ptr_eq(&a.data, &b.data)));
+    }
+
+    #[test]
+    fn test_read() {
+        let mut gb = GapBuffer::new(true).unwrap();
+        gb.replace(0..0, b"abc\ndef");
+        let s = take(&mut gb, None);
+
+        assert_eq!(s.read_forward(0), b"abc\ndef");
+        assert_eq!(s.read_forward(5), b"ef");
+        assert_eq!(s.read_forward(7), b"");
+        assert_eq!(s.read_backward(0), b"");
+        assert_eq!(s.read_backward(5), b"abc\nd");
+        assert_eq!(s.read_backward(100), b"abc\ndef");
+
+        gb.clear();
+        let s = take(&mut gb, Some(&s));
+        assert_eq!(s.len(), 0);
+        assert_eq!(s.read_forward(0), b"");
+    }
+}
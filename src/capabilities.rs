@@ -0,0 +1,338 @@
+// Copyright (c) Microsoft Corporation.
+// Licensed under the MIT License.
+
+//! Detection of optional terminal features.
+//!
+//! Wherever possible, we ask the terminal directly and assume that anything it doesn't
+//! respond to is unsupported. Only what can't be queried reliably, like the color depth,
+//! is inferred from `$TERM` and similar. See [`Capabilities::from_env`].
+
+use crate::framebuffer::ColorDepth;
+use crate::helpers::{CoordType, Size};
+use crate::image::ImageProtocol;
+use crate::vt::{Multiplexer, Token};
+
+/// Terminals we recognize by their XTVERSION response.
+/// Only used to fill in the [`Capabilities`] that they don't report.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+enum Terminal {
+    Xterm,
+    Kitty,
+    WezTerm,
+    Foot,
+    Ghostty,
+    Iterm2,
+    Tmux,
+    /// The terminal responded, but we don't know it.
+    Other,
+}
+
+impl Terminal {
+    /// Parses the name from an XTVERSION response, e.g. `XTerm(388)` or `tmux 3.4`.
+    fn from_version(version: &str) -> Self {
+        let name = version.split(['(', ' ']).next().unwrap_or("");
+        match name.to_ascii_lowercase().as_str() {
+            "xterm" => Self::Xterm,
+            "kitty" => Self::Kitty,
+            "wezterm" => Self::WezTerm,
+            "foot" => Self::Foot,
+            "ghostty" => Self::Ghostty,
+            "iterm2" => Self::Iterm2,
+            "tmux" => Self::Tmux,
+            _ => Self::Other,
+        }
+    }
+}
+
+/// Optional features supported by the terminal.
+#[derive(Default, Clone, Copy, PartialEq, Eq, Debug)]
+pub struct Capabilities {
+    /// The multiplexer we're running in, if any.
+    ///
+    /// It answers most of the [`Capabilities::QUERIES`] itself, so we don't pass them
+    /// through to the terminal. The same goes for synchronized output, which tmux implements.
+    pub multiplexer: Multiplexer,
+    /// Synchronized output, DEC private mode 2026.
+    pub synchronized_output: bool,
+    /// Curly, dotted, dashed and double underlines (SGR 4:x) and underline colors (SGR 58).
+    pub extended_underlines: bool,
+    /// The DECSCUSR cursor style at startup, if the terminal reported it.
+    /// It's restored on exit.
+    pub cursor_style: Option<u16>,
+    /// The number of supported colors.
+    pub color_depth: ColorDepth,
+    /// The preferred protocol for inline images.
+    pub image_protocol: ImageProtocol,
+    /// The size of a cell in pixels, if the terminal reported it.
+    pub cell_pixel_size: Option<Size>,
+    /// The kitty keyboard protocol, which reports otherwise ambiguous keys
+    /// (like Ctrl+I vs. Tab), Ctrl+Shift combinations and key releases.
+    pub kitty_keyboard: bool,
+    /// The xterm modifyOtherKeys level at startup, if the terminal reported it.
+    /// It's the fallback for the kitty keyboard protocol and restored on exit.
+    pub modify_other_keys: Option<u16>,
+    /// Windows Terminal's win32-input-mode, DEC private mode 9001. It reports keys with their
+    /// virtual key codes, key releases, and the exact text a key produced (e.g. after dead keys).
+    pub win32_input_mode: bool,
+    /// Whether the terminal is known to accept clipboard writes via OSC 52.
+    /// Many terminals that do, don't say so, which is why it's only a hint.
+    pub osc52_clipboard: bool,
+}
+
+impl Capabilities {
+    /// Returns the capabilities that can be inferred from the environment.
+    ///
+    /// The color depth can't be queried reliably, so we look for known terminals without
+    /// truecolor support. We don't assume that the lack of `$COLORTERM` means the opposite,
+    /// because it's commonly lost over SSH, even if the terminal supports it.
+    pub fn from_env() -> Self {
+        let env = |key| std::env::var(key).unwrap_or_default();
+        let colorterm = env("COLORTERM");
+        let term = env("TERM");
+
+        let color_depth = if colorterm == "truecolor" || colorterm == "24bit" {
+            ColorDepth::TrueColor
+        } else if term == "linux" || term == "vt100" || term == "vt220" {
+            ColorDepth::Indexed16
+        } else if env("TERM_PROGRAM") == "Apple_Terminal" {
+            ColorDepth::Indexed256
+        } else {
+            ColorDepth::TrueColor
+        };
+
+        // iTerm2's protocol can't be queried. If the terminal supports
+        // something better, the responses to the queries will override it.
+        let image_protocol = match env("TERM_PROGRAM").as_str() {
+            "iTerm.app" | "WezTerm" => ImageProtocol::Iterm2,
+            _ => ImageProtocol::None,
+        };
+
+        let multiplexer = if !env("TMUX").is_empty() {
+            Multiplexer::Tmux
+        } else if !env("STY").is_empty() {
+            Multiplexer::Screen
+        } else {
+            Multiplexer::None
+        };
+
+        Self { color_depth, image_protocol, multiplexer, ..Default::default() }
+    }
+
+    /// The queries understood by [`Capabilities::parse_response`].
+    ///
+    /// It ends with a DA1 request (`CSI c`), because all terminals respond to it.
+    /// Its response marks the end of the responses to the other queries.
+    pub const QUERIES: &str = concat!(
+        // DECRQM for synchronized output and win32-input-mode.
+        "\x1b[?2026$p",
+        "\x1b[?9001$p",
+        // XTVERSION for the terminal name and version.
+        "\x1b[>0q",
+        // XTGETTCAP for "Smulx", the terminfo capability for styled underlines.
+        // Terminals that support it, support underline colors as well.
+        // Each name is queried separately, because xterm stops at the first unknown one.
+        "\x1bP+q536d756c78\x1b\\",
+        // XTGETTCAP for "RGB" and tmux's "Tc", both of which indicate truecolor support.
+        "\x1bP+q524742\x1b\\",
+        "\x1bP+q5463\x1b\\",
+        // XTGETTCAP for "Ms", the terminfo capability for setting the clipboard via OSC 52.
+        "\x1bP+q4d73\x1b\\",
+        // DECRQSS for the current DECSCUSR cursor style.
+        "\x1bP$q q\x1b\\",
+        // A kitty graphics query with a 1x1 image. Only the response matters.
+        "\x1b_Gi=31,s=1,v=1,a=q,t=d,f=24;AAAA\x1b\\",
+        // XTWINOPS for the cell size in pixels.
+        "\x1b[16t",
+        // The current kitty keyboard protocol flags.
+        "\x1b[?u",
+        // XTQMODKEYS for the current modifyOtherKeys level.
+        "\x1b[?4m",
+        // DA1. A parameter of 4 indicates Sixel support.
+        "\x1b[c",
+    );
+
+    /// Records the response to one of the [`Capabilities::QUERIES`].
+    /// Returns `false` if the token isn't one.
+    ///
+    /// The DA1 response is recorded, but `false` is returned for it
+    /// nonetheless, so that the caller can detect the end of the responses.
+    pub fn parse_response(&mut self, token: &Token) -> bool {
+        match *token {
+            // DECRPM: `CSI ? <mode> ; <value> $ y`.
+            // 1 and 2 mean set and reset, 3 means permanently set.
+            Token::Csi(csi) if csi.private_byte == '?' && csi.final_byte == 'y' => {
+                let supported = matches!(csi.params[1], 1..=3);
+                match csi.params[0] {
+                    2026 => self.synchronized_output = supported,
+                    9001 => self.win32_input_mode = supported,
+                    _ => {}
+                }
+                true
+            }
+            // DA1: `CSI ? <class> ; <extensions...> c`.
+            Token::Csi(csi) if csi.private_byte == '?' && csi.final_byte == 'c' => {
+                let params = &csi.params[1..csi.param_count.max(1)];
+                if params.contains(&4) && self.image_protocol == ImageProtocol::None {
+                    self.image_protocol = ImageProtocol::Sixel;
+                }
+                false
+            }
+            // The kitty keyboard protocol flags: `CSI ? <flags> u`.
+            Token::Csi(csi) if csi.private_byte == '?' && csi.final_byte == 'u' => {
+                self.kitty_keyboard = true;
+                true
+            }
+            // XTQMODKEYS: `CSI > 4 ; <level> m`.
+            Token::Csi(csi) if csi.private_byte == '>' && csi.final_byte == 'm' => {
+                if csi.params[0] == 4 {
+                    self.modify_other_keys = Some(csi.params[1]);
+                }
+                true
+            }
+            // XTWINOPS: `CSI 6 ; <height> ; <width> t`.
+            Token::Csi(csi) if csi.final_byte == 't' && csi.params[0] == 6 => {
+                let (height, width) = (csi.params[1], csi.params[2]);
+                if width != 0 && height != 0 {
+                    self.cell_pixel_size =
+                        Some(Size { width: width as CoordType, height: height as CoordType });
+                }
+                true
+            }
+            // The kitty graphics response: `APC G i=31 ; OK ST`, or an error message instead of OK.
+            Token::Apc { data, .. } => {
+                if let Some(data) = data.strip_prefix('G')
+                    && let Some((keys, message)) = data.split_once(';')
+                    && keys.split(',').any(|kv| kv == "i=31")
+                    && message == "OK"
+                {
+                    self.image_protocol = ImageProtocol::Kitty;
+                }
+                true
+            }
+            Token::Dcs { data, .. } => {
+                // DECRQSS: `DCS 1 $ r <style> SP q ST` if the request was valid.
+                if let Some(data) = data.strip_prefix("1$r")
+                    && let Some(style) = data.strip_suffix(" q")
+                {
+                    self.cursor_style = style.parse().ok();
+                }
+                // XTGETTCAP: `DCS 1 + r <name> = <value> ST` if the capability exists.
+                if let Some(data) = data.strip_prefix("1+r") {
+                    let name = data.split('=').next().unwrap_or("");
+                    if name.eq_ignore_ascii_case("536d756c78") {
+                        self.extended_underlines = true;
+                    } else if name.eq_ignore_ascii_case("524742")
+                        || name.eq_ignore_ascii_case("5463")
+                    {
+                        self.color_depth = ColorDepth::TrueColor;
+                    } else if name.eq_ignore_ascii_case("4d73") {
+                        self.osc52_clipboard = true;
+                    }
+                }
+                // XTVERSION: `DCS > | <name and version> ST`.
+                if let Some(version) = data.strip_prefix(">|") {
+                    self.set_terminal(Terminal::from_version(version));
+                }
+                true
+            }
+            _ => false,
+        }
+    }
+
+    /// Applies what we know about the given terminal, where it can't be queried.
+    fn set_terminal(&mut self, terminal: Terminal) {
+        if terminal == Terminal::Tmux {
+            // `$TMUX` doesn't make it through SSH either.
+            self.multiplexer = Multiplexer::Tmux;
+        }
+
+        match terminal {
+            // All of these support OSC 52 and truecolor, but not all of them
+            // respond to XTGETTCAP, or they do, but without the above names.
+            Terminal::Kitty
+            | Terminal::WezTerm
+            | Terminal::Foot
+            | Terminal::Ghostty
+            | Terminal::Iterm2
+            | Terminal::Tmux => {
+                self.osc52_clipboard = true;
+                self.color_depth = ColorDepth::TrueColor;
+            }
+            _ => {}
+        }
+
+        // `TERM_PROGRAM` doesn't make it through SSH, but XTVERSION does.
+        if matches!(terminal, Terminal::WezTerm | Terminal::Iterm2)
+            && self.image_protocol == ImageProtocol::None
+        {
+            self.image_protocol = ImageProtocol::Iterm2;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::vt;
+
+    #[test]
+    fn test_parse_response() {
+        let mut parser = vt::Parser::new();
+        let mut caps = Capabilities::default();
+        let mut stream = parser.parse(concat!(
+            "\x1b[?2026;2$y\x1b[?9001;2$y\x1bP1+r536d756c78=abc\x1b\\\x1bP1$r2 q\x1b\\",
+            "\x1b_Gi=31;OK\x1b\\\x1b[6;20;9t\x1b[?0u\x1b[>4;1m\x1b[?62;4;22c"
+        ));
+        while let Some(token) = stream.next() {
+            caps.parse_response(&token);
+        }
+        assert_eq!(
+            caps,
+            Capabilities {
+                synchronized_output: true,
+                extended_underlines: true,
+                cursor_style: Some(2),
+                color_depth: ColorDepth::TrueColor,
+                // Kitty wins over Sixel.
+                image_protocol: ImageProtocol::Kitty,
+                cell_pixel_size: Some(Size { width: 9, height: 20 }),
+                kitty_keyboard: true,
+                modify_other_keys: Some(1),
+                win32_input_mode: true,
+                ..Default::default()
+            }
+        );
+
+        let mut caps = Capabilities { color_depth: ColorDepth::Indexed16, ..Default::default() };
+        let mut stream = parser.parse("\x1bP>|WezTerm 20240203\x1b\\\x1bP1+r5463=\x1b\\");
+        while let Some(token) = stream.next() {
+            caps.parse_response(&token);
+        }
+        assert_eq!(
+            caps,
+            Capabilities {
+                multiplexer: Multiplexer::None,
+                color_depth: ColorDepth::TrueColor,
+                image_protocol: ImageProtocol::Iterm2,
+                osc52_clipboard: true,
+                ..Default::default()
+            }
+        );
+        assert_eq!(Terminal::from_version("XTerm(388)"), Terminal::Xterm);
+        assert_eq!(Terminal::from_version("tmux 3.4"), Terminal::Tmux);
+
+        let mut caps = Capabilities::default();
+        let mut stream = parser.parse("\x1b_Gi=31;ENOTSUPPORTED:\x1b\\\x1b[?62;4c");
+        while let Some(token) = stream.next() {
+            caps.parse_response(&token);
+        }
+        assert_eq!(caps.image_protocol, ImageProtocol::Sixel);
+
+        let mut caps = Capabilities::default();
+        let mut stream = parser.parse("\x1b[?2026;0$y\x1bP0+r536d756c78\x1b\\");
+        while let Some(token) = stream.next() {
+            caps.parse_response(&token);
+        }
+        assert_eq!(caps, Capabilities::default());
+    }
+}
@@ -1,5 +1,21 @@
 //! Clipboard facilities for the editor.
 
+use std::fmt::Write as _;
+
+use crate::oklab::StraightRgba;
+
+/// How the contents of the clipboard were selected, which determines how they get pasted.
+///
+/// There's no variant for block (rectangular) selections, because the editor doesn't have them.
+#[derive(Default, Clone, Copy, PartialEq, Eq, Debug)]
+pub enum SelectionKind {
+    /// A regular selection from one position to another. It's pasted at the cursor.
+    #[default]
+    Stream,
+    /// The current line, copied without a selection. See [`Clipboard::is_line_copy`].
+    Line,
+}
+
 /// The builtin, internal clipboard of the editor.
 ///
 /// This is useful particularly when the terminal doesn't support
@@ -7,7 +23,7 @@
 #[derive(Default)]
 pub struct Clipboard {
     data: Vec<u8>,
-    line_copy: bool,
+    kind: SelectionKind,
     wants_host_sync: bool,
 }
 
@@ -29,7 +45,12 @@ impl Clipboard {
     /// This effectively prepends the current line with the copied line.
     /// `clipboard_line_start` is true in that case.
     pub fn is_line_copy(&self) -> bool {
-        self.line_copy
+        self.kind == SelectionKind::Line
+    }
+
+    /// How the current contents were selected.
+    pub fn kind(&self) -> SelectionKind {
+        self.kind
     }
 
     /// Returns the current contents of the clipboard.
@@ -37,17 +58,68 @@ impl Clipboard {
         &self.data
     }
 
+    /// Formats the current contents as an HTML fragment for rich-text applications,
+    /// so that pasting them there preserves the editor's monospace font and colors.
+    ///
+    /// Only the default `foreground` and `background` colors are used.
+    /// Per-token colors would need syntax highlighting, which the editor doesn't have.
+    pub fn read_html(&self, foreground: StraightRgba, background: StraightRgba) -> String {
+        let text = String::from_utf8_lossy(&self.data);
+        let mut html = String::with_capacity(text.len() + 128);
+        let hex = |c: StraightRgba| format!("#{:02x}{:02x}{:02x}", c.red(), c.green(), c.blue());
+
+        _ = write!(
+            html,
+            "<pre style=\"font-family:monospace;color:{};background-color:{}\">",
+            hex(foreground),
+            hex(background)
+        );
+        for ch in text.chars() {
+            match ch {
+                '&' => html.push_str("&amp;"),
+                '<' => html.push_str("&lt;"),
+                '>' => html.push_str("&gt;"),
+                '"' => html.push_str("&quot;"),
+                '\r' => {}
+                _ => html.push(ch),
+            }
+        }
+        html.push_str("</pre>");
+        html
+    }
+
     /// Fill the clipboard with the given data.
     pub fn write(&mut self, data: Vec<u8>) {
         if !data.is_empty() {
             self.data = data;
-            self.line_copy = false;
+            self.kind = SelectionKind::Stream;
             self.wants_host_sync = true;
         }
     }
 
     /// See [`Clipboard::is_line_copy`].
     pub fn write_was_line_copy(&mut self, line_copy: bool) {
-        self.line_copy = line_copy;
+        self.kind = if line_copy { SelectionKind::Line } else { SelectionKind::Stream };
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_read_html() {
+        let mut clipboard = Clipboard::default();
+        clipboard.write(b"if a < b && c > \"d\" {\r\n}".to_vec());
+        clipboard.write_was_line_copy(true);
+        assert_eq!(clipboard.kind(), SelectionKind::Line);
+
+        let html = clipboard
+            .read_html(StraightRgba::from_le(0xff332211), StraightRgba::from_le(0xffccbbaa));
+        assert_eq!(
+            html,
+            "<pre style=\"font-family:monospace;color:#112233;background-color:#aabbcc\">\
+             if a &lt; b &amp;&amp; c &gt; &quot;d&quot; {\n}</pre>"
+        );
     }
 }
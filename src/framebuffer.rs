@@ -5,13 +5,15 @@
 
 use std::cell::Cell;
 use std::fmt::Write;
-use std::ops::{BitOr, BitXor};
-use std::ptr;
-use std::slice::ChunksExact;
+use std::ops::{BitOr, BitXor, Range};
+use std::rc::Rc;
+use std::{mem, ptr, slice};
 
-use crate::arena::{Arena, ArenaString};
+use crate::arena::{Arena, ArenaString, scratch_arena};
+use crate::hash::hash;
 use crate::helpers::{CoordType, Point, Rect, Size};
-use crate::oklab::StraightRgba;
+use crate::image::{self, Image, ImageProtocol};
+use crate::oklab::{self, StraightRgba};
 use crate::simd::{MemsetSafe, memset};
 use crate::unicode::MeasurementConfig;
 
@@ -53,6 +55,26 @@ pub enum IndexedColor {
     Foreground,
 }
 
+/// The number of colors supported by the terminal.
+#[derive(Default, Clone, Copy, PartialEq, Eq, Debug)]
+pub enum ColorDepth {
+    /// 24-bit RGB colors.
+    #[default]
+    TrueColor,
+    /// The xterm 256-color palette.
+    Indexed256,
+    /// Just the 16 VT colors.
+    Indexed16,
+}
+
+/// The output format of [`Framebuffer::capture`].
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum CaptureFormat {
+    PlainText,
+    /// Text with SGR and OSC 8 sequences.
+    Ansi,
+}
+
 /// Number of indices used by [`IndexedColor`].
 pub const INDEXED_COLORS_COUNT: usize = 18;
 
@@ -79,6 +101,48 @@ pub const DEFAULT_THEME: [StraightRgba; INDEXED_COLORS_COUNT] = [
     StraightRgba::from_be(0xbebebeff), // Foreground
 ];
 
+/// Derives a full palette from just a few seed colors.
+///
+/// The grays are a lightness ramp between `background` and `foreground`.
+/// The 6 accent colors use the lightness and chroma of `accent`,
+/// but keep the hues of the [`DEFAULT_THEME`], so that red stays red.
+/// Their bright variants are a bit lighter.
+pub fn generate_palette(
+    background: StraightRgba,
+    foreground: StraightRgba,
+    accent: StraightRgba,
+) -> [StraightRgba; INDEXED_COLORS_COUNT] {
+    let mut palette = DEFAULT_THEME;
+
+    let bg = background.as_oklch();
+    let fg = foreground.as_oklch();
+    let (dark, light) = if bg.lightness() <= fg.lightness() { (bg, fg) } else { (fg, bg) };
+    // Black, BrightBlack, White, BrightWhite. The grays get the chroma of the dark seed,
+    // so that a tinted background results in similarly tinted grays.
+    let gray_indices = [
+        IndexedColor::Black,
+        IndexedColor::BrightBlack,
+        IndexedColor::White,
+        IndexedColor::BrightWhite,
+    ];
+    for (i, gray) in gray_indices.into_iter().zip(dark.lightness_ramp(light.lightness(), 4)) {
+        palette[i as usize] = gray.as_rgba();
+    }
+
+    let accent = accent.as_oklch();
+    let bright = (accent.lightness() + 0.1).min(1.0);
+    for i in IndexedColor::Red as usize..=IndexedColor::Cyan as usize {
+        let hue = DEFAULT_THEME[i].as_oklch().hue();
+        let color = accent.with_hue(hue);
+        palette[i] = color.as_rgba();
+        palette[i + 8] = color.with_lightness(bright).as_rgba();
+    }
+
+    palette[IndexedColor::Background as usize] = background;
+    palette[IndexedColor::Foreground as usize] = foreground;
+    palette
+}
+
 /// A shoddy framebuffer for terminal applications.
 ///
 /// The idea is that you create a [`Framebuffer`], draw a bunch of text and
@@ -103,8 +167,16 @@ pub struct Framebuffer {
     /// A cache table for previously contrasted colors.
     /// See: <https://fgiesen.wordpress.com/2019/02/11/cache-tables/>
     contrast_colors: [Cell<(StraightRgba, StraightRgba)>; CACHE_TABLE_SIZE],
+    color_depth: ColorDepth,
+    /// A cache table for the palette indices of previously quantized colors.
+    quantized_colors: [Cell<(StraightRgba, u8)>; CACHE_TABLE_SIZE],
     background_fill: StraightRgba,
     foreground_fill: StraightRgba,
+    /// Whether the terminal supports SGR 4:x underline styles and SGR 58 underline colors.
+    extended_underlines: bool,
+    image_protocol: ImageProtocol,
+    /// The size of a cell in pixels. Sixel images are scaled to it.
+    cell_pixel_size: Size,
 }
 
 impl Framebuffer {
@@ -120,8 +192,13 @@ impl Framebuffer {
             ],
             contrast_colors: [const { Cell::new((StraightRgba::zero(), StraightRgba::zero())) };
                 CACHE_TABLE_SIZE],
+            color_depth: ColorDepth::TrueColor,
+            quantized_colors: [const { Cell::new((StraightRgba::zero(), 0)) }; CACHE_TABLE_SIZE],
             background_fill: DEFAULT_THEME[IndexedColor::Background as usize],
             foreground_fill: DEFAULT_THEME[IndexedColor::Foreground as usize],
+            extended_underlines: false,
+            image_protocol: ImageProtocol::None,
+            cell_pixel_size: Size { width: 10, height: 20 },
         }
     }
 
@@ -141,6 +218,34 @@ impl Framebuffer {
         if !Self::is_dark(self.auto_colors[0]) {
             self.auto_colors.swap(0, 1);
         }
+
+        self.clear_quantized_colors();
+    }
+
+    /// Sets the number of colors supported by the terminal.
+    /// Colors are mapped to the perceptually closest palette entry, unless it's [`ColorDepth::TrueColor`].
+    pub fn set_color_depth(&mut self, depth: ColorDepth) {
+        self.color_depth = depth;
+        self.clear_quantized_colors();
+    }
+
+    fn clear_quantized_colors(&mut self) {
+        // Zero is never quantized, because it's the default color. See `format_color`.
+        for slot in &self.quantized_colors {
+            slot.set((StraightRgba::zero(), 0));
+        }
+    }
+
+    /// Enables curly, dotted, dashed and double underlines, as well as underline colors.
+    /// Otherwise, they fall back to a plain underline in the foreground color.
+    pub fn set_extended_underlines(&mut self, enabled: bool) {
+        self.extended_underlines = enabled;
+    }
+
+    /// Sets the protocol used by [`Framebuffer::draw_image`] and the size of a cell in pixels.
+    pub fn set_image_protocol(&mut self, protocol: ImageProtocol, cell_pixel_size: Size) {
+        self.image_protocol = protocol;
+        self.cell_pixel_size = cell_pixel_size;
     }
 
     /// Begins a new frame with the given `size`.
@@ -150,14 +255,12 @@ impl Framebuffer {
                 buffer.text = LineBuffer::new(size);
                 buffer.bg_bitmap = Bitmap::new(size);
                 buffer.fg_bitmap = Bitmap::new(size);
+                buffer.ul_bitmap = Bitmap::new(size);
                 buffer.attributes = AttributeBuffer::new(size);
+                buffer.links = LinkBuffer::new(size);
             }
 
-            let front = &mut self.buffers[self.frame_counter & 1];
-            // Trigger a full redraw. (Yes, it's a hack.)
-            front.fg_bitmap.fill(StraightRgba::from_le(1));
-            // Trigger a cursor update as well, just to be sure.
-            front.cursor = Cursor::new_invalid();
+            self.invalidate();
         }
 
         self.frame_counter = self.frame_counter.wrapping_add(1);
@@ -167,10 +270,23 @@ impl Framebuffer {
         back.text.fill_whitespace();
         back.bg_bitmap.fill(self.background_fill);
         back.fg_bitmap.fill(self.foreground_fill);
+        back.ul_bitmap.fill(StraightRgba::zero());
         back.attributes.reset();
+        back.links.reset();
+        back.images.clear();
         back.cursor = Cursor::new_disabled();
     }
 
+    /// Makes the next [`Framebuffer::render`] redraw everything,
+    /// because the terminal contents got lost, e.g. during a suspend.
+    pub fn invalidate(&mut self) {
+        let front = &mut self.buffers[self.frame_counter & 1];
+        // Trigger a full redraw. (Yes, it's a hack.)
+        front.fg_bitmap.fill(StraightRgba::from_le(1));
+        // Trigger a cursor update as well, just to be sure.
+        front.cursor = Cursor::new_invalid();
+    }
+
     /// Replaces text contents in a single line of the framebuffer.
     /// All coordinates are in viewport coordinates.
     /// Assumes that control characters have been replaced or escaped.
@@ -209,56 +325,14 @@ impl Framebuffer {
             return 0;
         }
 
-        let viewport_height = track.height();
-        // The content height is at least the viewport height.
-        let content_height = content_height.max(viewport_height);
-
-        // No need to draw a scrollbar if the content fits in the viewport.
-        let content_offset_max = content_height - viewport_height;
-        if content_offset_max == 0 {
+        let Some(thumb) = ScrollbarThumb::new(track.height(), content_offset, content_height)
+        else {
             return 0;
-        }
-
-        // The content offset must be at least one viewport height from the bottom.
-        // You don't want to scroll past the end after all...
-        let content_offset = content_offset.clamp(0, content_offset_max);
-
-        // In order to increase the visual resolution of the scrollbar,
-        // we'll use 1/8th blocks to represent the thumb.
-        // First, scale the offsets to get that 1/8th resolution.
-        let viewport_height = viewport_height as i64 * 8;
-        let content_offset_max = content_offset_max as i64 * 8;
-        let content_offset = content_offset as i64 * 8;
-        let content_height = content_height as i64 * 8;
-
-        // The proportional thumb height (0-1) is the fraction of viewport and
-        // content height. The taller the content, the smaller the thumb:
-        // = viewport_height / content_height
-        // We then scale that to the viewport height to get the height in 1/8th units.
-        // = viewport_height * viewport_height / content_height
-        // We add content_height/2 to round the integer division, which results in a numerator of:
-        // = viewport_height * viewport_height + content_height / 2
-        let numerator = viewport_height * viewport_height + content_height / 2;
-        let thumb_height = numerator / content_height;
-        // Ensure the thumb has a minimum size of 1 row.
-        let thumb_height = thumb_height.max(8);
-
-        // The proportional thumb top position (0-1) is:
-        // = content_offset / content_offset_max
-        // The maximum thumb top position is the viewport height minus the thumb height:
-        // = viewport_height - thumb_height
-        // To get the thumb top position in 1/8th units, we multiply both:
-        // = (viewport_height - thumb_height) * content_offset / content_offset_max
-        // We add content_offset_max/2 to round the integer division, which results in a numerator of:
-        // = (viewport_height - thumb_height) * content_offset + content_offset_max / 2
-        let numerator = (viewport_height - thumb_height) * content_offset + content_offset_max / 2;
-        let thumb_top = numerator / content_offset_max;
-        // The thumb bottom position is the thumb top position plus the thumb height.
-        let thumb_bottom = thumb_top + thumb_height;
+        };
 
         // Shift to absolute coordinates.
-        let thumb_top = thumb_top + track.top as i64 * 8;
-        let thumb_bottom = thumb_bottom + track.top as i64 * 8;
+        let thumb_top = thumb.start + track.top as i64 * 8;
+        let thumb_bottom = thumb.end + track.top as i64 * 8;
 
         // Clamp to the visible area.
         let thumb_top = thumb_top.max(track_clipped.top as i64 * 8);
@@ -305,7 +379,80 @@ impl Framebuffer {
             self.blend_fg(rect, self.indexed(IndexedColor::BrightBlack));
         }
 
-        ((thumb_height + 4) / 8) as CoordType
+        thumb.size()
+    }
+
+    /// Like [`Framebuffer::draw_scrollbar`], but for a horizontal `track`,
+    /// where `content_offset` and `content_width` are horizontal as well.
+    pub fn draw_scrollbar_horizontal(
+        &mut self,
+        clip_rect: Rect,
+        track: Rect,
+        content_offset: CoordType,
+        content_width: CoordType,
+    ) -> CoordType {
+        let track_clipped = track.intersect(clip_rect);
+        if track_clipped.is_empty() {
+            return 0;
+        }
+
+        let Some(thumb) = ScrollbarThumb::new(track.width(), content_offset, content_width) else {
+            return 0;
+        };
+
+        // Shift to absolute coordinates and clamp to the visible area.
+        let thumb_left = (thumb.start + track.left as i64 * 8).max(track_clipped.left as i64 * 8);
+        let thumb_right = (thumb.end + track.left as i64 * 8).min(track_clipped.right as i64 * 8);
+
+        let left_fract = (thumb_left % 8) as CoordType;
+        let right_fract = (thumb_right % 8) as CoordType;
+        let thumb_left = ((thumb_left + 7) / 8) as CoordType;
+        let thumb_right = (thumb_right / 8) as CoordType;
+
+        self.blend_bg(track_clipped, self.indexed(IndexedColor::BrightBlack));
+        self.blend_fg(track_clipped, self.indexed(IndexedColor::BrightWhite));
+
+        for y in track_clipped.top..track_clipped.bottom {
+            let mut x = thumb_left;
+            while x < thumb_right {
+                self.replace_text(y, x, x + 1, "█");
+                x += 1;
+            }
+        }
+
+        // U+2589 to U+258F are the 7/8th to 1/8th left block elements: ▉▊▋▌▍▎▏
+        // There are no right-aligned equivalents, so the left edge of the thumb
+        // is drawn by filling the left part of the cell with the track color.
+        let mut fract_buf = [0xE2, 0x96, 0x88];
+        let inverted = |fb: &mut Self, x: CoordType| {
+            let rect = Rect {
+                left: x,
+                top: track_clipped.top,
+                right: x + 1,
+                bottom: track_clipped.bottom,
+            };
+            fb.blend_bg(rect, fb.indexed(IndexedColor::BrightWhite));
+            fb.blend_fg(rect, fb.indexed(IndexedColor::BrightBlack));
+        };
+        if left_fract != 0 {
+            fract_buf[2] = (0x88 + 8 - left_fract) as u8;
+            for y in track_clipped.top..track_clipped.bottom {
+                self.replace_text(y, thumb_left - 1, thumb_left, unsafe {
+                    std::str::from_utf8_unchecked(&fract_buf)
+                });
+            }
+            inverted(self, thumb_left - 1);
+        }
+        if right_fract != 0 {
+            fract_buf[2] = (0x88 + 8 - right_fract) as u8;
+            for y in track_clipped.top..track_clipped.bottom {
+                self.replace_text(y, thumb_right, thumb_right + 1, unsafe {
+                    std::str::from_utf8_unchecked(&fract_buf)
+                });
+            }
+        }
+
+        thumb.size()
     }
 
     #[inline]
@@ -329,7 +476,7 @@ impl Framebuffer {
         StraightRgba::from_le(a << 24 | (c & 0x00ffffff))
     }
 
-    /// Returns a color opposite to the brightness of the given `color`.
+    /// Returns the palette's black or white, whichever is more readable on the given `color`.
     pub fn contrasted(&self, color: StraightRgba) -> StraightRgba {
         let idx = (color.to_ne() as usize).wrapping_mul(HASH_MULTIPLIER) >> CACHE_TABLE_SHIFT;
         let slot = self.contrast_colors[idx].get();
@@ -339,7 +486,7 @@ impl Framebuffer {
     #[cold]
     fn contrasted_slow(&self, color: StraightRgba) -> StraightRgba {
         let idx = (color.to_ne() as usize).wrapping_mul(HASH_MULTIPLIER) >> CACHE_TABLE_SHIFT;
-        let contrast = self.auto_colors[Self::is_dark(color) as usize];
+        let contrast = color.most_readable(&self.auto_colors);
         self.contrast_colors[idx].set((color, contrast));
         contrast
     }
@@ -366,6 +513,15 @@ impl Framebuffer {
         back.fg_bitmap.blend(target, fg);
     }
 
+    /// Sets the underline color in the given rectangle.
+    /// [`StraightRgba::zero()`] resets it to the foreground color.
+    ///
+    /// Only has an effect if [`Framebuffer::set_extended_underlines`] was enabled.
+    pub fn set_underline_color(&mut self, target: Rect, color: StraightRgba) {
+        let back = &mut self.buffers[self.frame_counter & 1];
+        back.ul_bitmap.replace(target, color);
+    }
+
     /// Reverses the foreground and background colors in the given rectangle.
     pub fn reverse(&mut self, target: Rect) {
         let back = &mut self.buffers[self.frame_counter & 1];
@@ -396,13 +552,50 @@ impl Framebuffer {
         back.attributes.replace(target, mask, attr);
     }
 
-    /// Sets the current visible cursor position and type.
+    /// Turns the given rectangle into a hyperlink to `uri` (OSC 8).
+    /// An empty `uri` removes any existing hyperlinks instead.
+    ///
+    /// `id` is optional and allows the terminal to recognize cells that belong
+    /// to the same link, e.g. when a link spans multiple lines.
+    pub fn set_hyperlink(&mut self, target: Rect, uri: &str, id: &str) {
+        let back = &mut self.buffers[self.frame_counter & 1];
+        back.links.replace(target, uri, id);
+    }
+
+    /// Draws `image` scaled to fill the `target` cells. It's drawn on top of the text.
+    ///
+    /// Images can't be clipped, so nothing is drawn if `target` isn't entirely visible.
+    /// The same goes for Sixel images on the last row, as drawing them would scroll the screen.
+    /// Without an [`Framebuffer::set_image_protocol`], this does nothing either.
+    pub fn draw_image(&mut self, target: Rect, image: &Rc<Image>) {
+        let back = &mut self.buffers[self.frame_counter & 1];
+        let mut screen = back.text.size.as_rect();
+        if self.image_protocol == ImageProtocol::Sixel {
+            screen.bottom -= 1;
+        }
+        if self.image_protocol == ImageProtocol::None
+            || target.is_empty()
+            || target.intersect(screen) != target
+        {
+            return;
+        }
+        back.images.push(ImagePlacement { rect: target, image: image.clone() });
+    }
+
+    /// Sets the current visible cursor position and style.
     ///
     /// Call this when focus is inside an editable area and you want to show the cursor.
-    pub fn set_cursor(&mut self, pos: Point, overtype: bool) {
+    pub fn set_cursor(&mut self, pos: Point, style: CursorStyle) {
         let back = &mut self.buffers[self.frame_counter & 1];
         back.cursor.pos = pos;
-        back.cursor.overtype = overtype;
+        back.cursor.style = style;
+    }
+
+    /// Changes the style of the cursor set via [`Framebuffer::set_cursor`].
+    /// This allows you to signal modal states, without having to know the cursor position.
+    pub fn set_cursor_style(&mut self, style: CursorStyle) {
+        let back = &mut self.buffers[self.frame_counter & 1];
+        back.cursor.style = style;
     }
 
     /// Renders the framebuffer contents accumulated since the
@@ -418,102 +611,104 @@ impl Framebuffer {
             (back, front)
         };
 
-        let mut front_lines = front.text.lines.iter(); // hahaha
-        let mut front_bgs = front.bg_bitmap.iter();
-        let mut front_fgs = front.fg_bitmap.iter();
-        let mut front_attrs = front.attributes.iter();
+        let height = back.text.size.height as usize;
+        let mut result = ArenaString::new_in(arena);
+        let mut sgr = SgrState::new();
+
+        // For each row, the row in the front buffer that the terminal currently displays there.
+        // It only differs from the identity if we scrolled and is `None` for the revealed rows.
+        let scratch = scratch_arena(Some(arena));
+        let mut front_rows = Vec::with_capacity_in(height, &*scratch);
+        front_rows.extend((0..height).map(Some));
+        // Whether a row was (partially) redrawn. Sixel and iTerm2 images
+        // are part of the text area and need to be drawn again on top.
+        let mut redrawn = Vec::with_capacity_in(height, &*scratch);
+        redrawn.resize(height, false);
+        let images_changed = front.images != back.images;
+
+        // The terminal would scroll images along with the text, which our diffing doesn't know.
+        let scroll = if front.images.is_empty() && back.images.is_empty() {
+            self.find_scroll(front, back)
+        } else {
+            None
+        };
 
-        let mut back_lines = back.text.lines.iter();
-        let mut back_bgs = back.bg_bitmap.iter();
-        let mut back_fgs = back.fg_bitmap.iter();
-        let mut back_attrs = back.attributes.iter();
+        if let Some(scroll) = scroll {
+            let region = scroll.region;
+            result.push_str("\x1b[m");
+            // DECSTBM to restrict the scroll to the region, SU/SD to scroll, DECSTBM to reset it.
+            _ = write!(
+                result,
+                "\x1b[{};{}r\x1b[{}{}\x1b[r",
+                region.start + 1,
+                region.end,
+                scroll.delta.unsigned_abs(),
+                if scroll.delta > 0 { 'S' } else { 'T' }
+            );
+            for y in region.clone() {
+                let src = y.wrapping_add_signed(scroll.delta);
+                front_rows[y] = if region.contains(&src) { Some(src) } else { None };
+            }
+        }
 
-        let mut result = ArenaString::new_in(arena);
-        let mut last_bg = u64::MAX;
-        let mut last_fg = u64::MAX;
-        let mut last_attr = Attributes::None;
-
-        for y in 0..front.text.size.height {
-            // SAFETY: The only thing that changes the size of these containers,
-            // is the reset() method and it always resets front/back to the same size.
-            let front_line = unsafe { front_lines.next().unwrap_unchecked() };
-            let front_bg = unsafe { front_bgs.next().unwrap_unchecked() };
-            let front_fg = unsafe { front_fgs.next().unwrap_unchecked() };
-            let front_attr = unsafe { front_attrs.next().unwrap_unchecked() };
-
-            let back_line = unsafe { back_lines.next().unwrap_unchecked() };
-            let back_bg = unsafe { back_bgs.next().unwrap_unchecked() };
-            let back_fg = unsafe { back_fgs.next().unwrap_unchecked() };
-            let back_attr = unsafe { back_attrs.next().unwrap_unchecked() };
-
-            // TODO: Ideally, we should properly diff the contents and so if
-            // only parts of a line change, we should only update those parts.
-            if front_line == back_line
-                && front_bg == back_bg
-                && front_fg == back_fg
-                && front_attr == back_attr
-            {
-                continue;
+        // Removed images must be overwritten with text, unless the terminal can delete them.
+        if images_changed && self.image_protocol != ImageProtocol::Kitty {
+            for p in &front.images {
+                if !back.images.contains(p) {
+                    let rows = p.rect.top as usize..(p.rect.bottom as usize).min(height);
+                    front_rows[rows].fill(None);
+                }
             }
+        }
 
-            let line_bytes = back_line.as_bytes();
-            let mut cfg = MeasurementConfig::new(&line_bytes);
-            let mut chunk_end = 0;
+        for (y, &front_row) in front_rows.iter().enumerate() {
+            // Only redraw the part of the line, starting at the first difference.
+            let x = match front_row {
+                Some(src) => match self.row_diff(front, src, back, y) {
+                    Some(x) => x,
+                    None => continue,
+                },
+                None => 0,
+            };
+            redrawn[y] = true;
 
             if result.is_empty() {
                 result.push_str("\x1b[m");
             }
-            _ = write!(result, "\x1b[{};1H", y + 1);
-
-            while {
-                let bg = back_bg[chunk_end];
-                let fg = back_fg[chunk_end];
-                let attr = back_attr[chunk_end];
+            _ = write!(result, "\x1b[{};{}H", y + 1, x + 1);
 
-                // Chunk into runs of the same color.
-                while {
-                    chunk_end += 1;
-                    chunk_end < back_bg.len()
-                        && back_bg[chunk_end] == bg
-                        && back_fg[chunk_end] == fg
-                        && back_attr[chunk_end] == attr
-                } {}
+            self.write_row(&mut result, &mut sgr, back, y, x);
+        }
 
-                if last_bg != bg.to_ne() as u64 {
-                    last_bg = bg.to_ne() as u64;
-                    self.format_color(&mut result, false, bg);
-                }
+        if images_changed && self.image_protocol == ImageProtocol::Kitty {
+            if result.is_empty() {
+                result.push_str("\x1b[m");
+            }
+            result.push_str(image::KITTY_DELETE_ALL);
+        }
 
-                if last_fg != fg.to_ne() as u64 {
-                    last_fg = fg.to_ne() as u64;
-                    self.format_color(&mut result, true, fg);
+        for p in &back.images {
+            let redraw = match self.image_protocol {
+                ImageProtocol::None => false,
+                ImageProtocol::Kitty => images_changed,
+                _ => {
+                    !front.images.contains(p)
+                        || redrawn[p.rect.top as usize..p.rect.bottom as usize].contains(&true)
                 }
-
-                if last_attr != attr {
-                    let diff = last_attr ^ attr;
-                    if diff.is(Attributes::Italic) {
-                        if attr.is(Attributes::Italic) {
-                            result.push_str("\x1b[3m");
-                        } else {
-                            result.push_str("\x1b[23m");
-                        }
-                    }
-                    if diff.is(Attributes::Underlined) {
-                        if attr.is(Attributes::Underlined) {
-                            result.push_str("\x1b[4m");
-                        } else {
-                            result.push_str("\x1b[24m");
-                        }
-                    }
-                    last_attr = attr;
+            };
+            if redraw {
+                if result.is_empty() {
+                    result.push_str("\x1b[m");
                 }
-
-                let beg = cfg.cursor().offset;
-                let end = cfg.goto_visual(Point { x: chunk_end as CoordType, y: 0 }).offset;
-                result.push_str(&back_line[beg..end]);
-
-                chunk_end < back_bg.len()
-            } {}
+                _ = write!(result, "\x1b[{};{}H", p.rect.top + 1, p.rect.left + 1);
+                image::write_image(
+                    &mut result,
+                    self.image_protocol,
+                    &p.image,
+                    Size { width: p.rect.width(), height: p.rect.height() },
+                    self.cell_pixel_size,
+                );
+            }
         }
 
         // If the cursor has changed since the last frame we naturally need to update it,
@@ -529,7 +724,7 @@ impl Framebuffer {
                     "\x1b[{};{}H\x1b[{} q\x1b[?25h",
                     back.cursor.pos.y + 1,
                     back.cursor.pos.x + 1,
-                    if back.cursor.overtype { 1 } else { 5 }
+                    back.cursor.style.decscusr()
                 );
             } else {
                 // DECTCEM to hide the cursor.
@@ -540,6 +735,280 @@ impl Framebuffer {
         result
     }
 
+    /// Serializes the most recently rendered frame, e.g. for bug reports or golden-file tests.
+    ///
+    /// Trailing whitespace is trimmed from each line in [`CaptureFormat::PlainText`].
+    /// [`CaptureFormat::Ansi`] preserves colors, attributes and hyperlinks,
+    /// but not the cursor, nor images.
+    pub fn capture<'a>(&self, arena: &'a Arena, format: CaptureFormat) -> ArenaString<'a> {
+        let buf = &self.buffers[self.frame_counter & 1];
+        let mut result = ArenaString::new_in(arena);
+
+        for y in 0..buf.text.size.height as usize {
+            match format {
+                CaptureFormat::PlainText => {
+                    result.push_str(buf.text.lines[y].trim_end_matches(' '))
+                }
+                CaptureFormat::Ansi => {
+                    let mut sgr = SgrState::new();
+                    result.push_str("\x1b[m");
+                    self.write_row(&mut result, &mut sgr, buf, y, 0);
+                    // Reset the colors before the newline, in case it scrolls the
+                    // viewport and the terminal fills the new line with the background color.
+                    result.push_str("\x1b[m");
+                }
+            }
+            result.push('\n');
+        }
+
+        result
+    }
+
+    /// Writes the row `y` of `buf`, starting at column `x`, with the SGR sequences needed to
+    /// get from `sgr` to the row's styling. The hyperlink is closed at the end of the row.
+    fn write_row(
+        &self,
+        dst: &mut ArenaString,
+        sgr: &mut SgrState,
+        buf: &Buffer,
+        y: usize,
+        x: usize,
+    ) {
+        let row = buf.row(y);
+
+        let line_bytes = row.text.as_bytes();
+        let mut cfg = MeasurementConfig::new(&line_bytes);
+        cfg.goto_visual(Point { x: x as CoordType, y: 0 });
+        let mut chunk_end = x;
+
+        while {
+            let bg = row.bg[chunk_end];
+            let fg = row.fg[chunk_end];
+            let ul =
+                if self.extended_underlines { row.ul[chunk_end] } else { StraightRgba::zero() };
+            let attr = row.attr[chunk_end];
+            let link = row.link[chunk_end];
+
+            // Chunk into runs of the same color.
+            while {
+                chunk_end += 1;
+                chunk_end < row.bg.len()
+                    && row.bg[chunk_end] == bg
+                    && row.fg[chunk_end] == fg
+                    && (!self.extended_underlines || row.ul[chunk_end] == ul)
+                    && row.attr[chunk_end] == attr
+                    && row.link[chunk_end] == link
+            } {}
+
+            if sgr.bg != bg.to_ne() as u64 {
+                sgr.bg = bg.to_ne() as u64;
+                self.format_color(dst, false, bg);
+            }
+
+            if sgr.fg != fg.to_ne() as u64 {
+                sgr.fg = fg.to_ne() as u64;
+                self.format_color(dst, true, fg);
+            }
+
+            if sgr.attr != attr {
+                let diff = sgr.attr ^ attr;
+                for (a, on, off) in [
+                    (Attributes::Italic, "\x1b[3m", "\x1b[23m"),
+                    (Attributes::Faint, "\x1b[2m", "\x1b[22m"),
+                    (Attributes::Strikethrough, "\x1b[9m", "\x1b[29m"),
+                    (Attributes::Overlined, "\x1b[53m", "\x1b[55m"),
+                ] {
+                    if diff.is(a) {
+                        dst.push_str(if attr.is(a) { on } else { off });
+                    }
+                }
+                let last_style = sgr.attr.underline_style();
+                let style = attr.underline_style();
+                if style != last_style {
+                    if style == 0 {
+                        dst.push_str("\x1b[24m");
+                    } else if style == 1 || !self.extended_underlines {
+                        if last_style == 0 || self.extended_underlines {
+                            dst.push_str("\x1b[4m");
+                        }
+                    } else {
+                        _ = write!(dst, "\x1b[4:{style}m");
+                    }
+                }
+                sgr.attr = attr;
+            }
+
+            if sgr.ul != ul.to_ne() {
+                sgr.ul = ul.to_ne();
+                if ul.to_ne() == 0 {
+                    dst.push_str("\x1b[59m");
+                } else {
+                    let ul = if ul.alpha() != 0xff {
+                        self.indexed(IndexedColor::Foreground).oklab_blend(ul)
+                    } else {
+                        ul
+                    };
+                    let r = ul.red();
+                    let g = ul.green();
+                    let b = ul.blue();
+                    _ = write!(dst, "\x1b[58:2::{r}:{g}:{b}m");
+                }
+            }
+
+            if sgr.link != link {
+                sgr.link = link;
+                match buf.links.get(link) {
+                    Some(l) if l.id.is_empty() => _ = write!(dst, "\x1b]8;;{}\x1b\\", l.uri),
+                    Some(l) => _ = write!(dst, "\x1b]8;id={};{}\x1b\\", l.id, l.uri),
+                    None => dst.push_str("\x1b]8;;\x1b\\"),
+                }
+            }
+
+            let beg = cfg.cursor().offset;
+            let end = cfg.goto_visual(Point { x: chunk_end as CoordType, y: 0 }).offset;
+            dst.push_str(&row.text[beg..end]);
+
+            chunk_end < row.bg.len()
+        } {}
+
+        // Close the hyperlink at the end of each line, so that it doesn't
+        // leak into whatever gets written next, e.g. after the next CUP.
+        if sgr.link != 0 {
+            sgr.link = 0;
+            dst.push_str("\x1b]8;;\x1b\\");
+        }
+    }
+
+    /// Finds the largest region of rows that moved up or down between the front and back buffer.
+    fn find_scroll(&self, front: &Buffer, back: &Buffer) -> Option<Scroll> {
+        let height = back.text.size.height as usize;
+
+        // Most frames don't change much. Avoid hashing everything in that case.
+        let changed = (0..height).filter(|&y| self.row_diff(front, y, back, y).is_some()).count();
+        if changed < MIN_SCROLL_ROWS {
+            return None;
+        }
+
+        let scratch = scratch_arena(None);
+        let mut front_hashes = Vec::with_capacity_in(height, &*scratch);
+        let mut back_hashes = Vec::with_capacity_in(height, &*scratch);
+        front_hashes.extend((0..height).map(|y| self.row_hash(front, y)));
+        back_hashes.extend((0..height).map(|y| self.row_hash(back, y)));
+
+        let mut best = None;
+        let mut best_saved = MIN_SCROLL_ROWS - 1;
+
+        // A positive `delta` means that the contents moved up, i.e. `back[y] == front[y + delta]`.
+        // This is O(height^2), but the height is small and comparing hashes is cheap.
+        for distance in 1..height as isize {
+            for delta in [distance, -distance] {
+                let mut beg = 0;
+                let mut saved = 0;
+
+                for y in 0..=height {
+                    let src = y.wrapping_add_signed(delta);
+                    if y < height && src < height && back_hashes[y] == front_hashes[src] {
+                        // Rows that are unchanged without scrolling don't count.
+                        saved += (back_hashes[y] != front_hashes[y]) as usize;
+                        continue;
+                    }
+
+                    if saved > best_saved {
+                        best_saved = saved;
+                        // The region spans both, the rows `beg..y` and where they came from.
+                        let region = if delta > 0 {
+                            beg..y.wrapping_add_signed(delta)
+                        } else {
+                            beg.wrapping_add_signed(delta)..y
+                        };
+                        best = Some(Scroll { region, delta });
+                    }
+
+                    beg = y + 1;
+                    saved = 0;
+                }
+            }
+        }
+
+        best
+    }
+
+    fn row_hash(&self, buffer: &Buffer, y: usize) -> u64 {
+        let row = buffer.row(y);
+        let mut h = hash(0, row.text.as_bytes());
+        h = hash(h, as_bytes(row.bg));
+        h = hash(h, as_bytes(row.fg));
+        h = hash(h, as_bytes(row.attr));
+        if self.extended_underlines {
+            h = hash(h, as_bytes(row.ul));
+        }
+        if !buffer.links.links.is_empty() {
+            for (x, &link) in row.link.iter().enumerate() {
+                if let Some(link) = buffer.links.get(link) {
+                    h = hash(h ^ x as u64, link.uri.as_bytes());
+                    h = hash(h, link.id.as_bytes());
+                }
+            }
+        }
+        h
+    }
+
+    /// Returns the first column at which row `b` in the `back` buffer differs from
+    /// row `a` in the `front` buffer, or `None` if they are identical.
+    ///
+    /// The column is guaranteed to be at the start of a glyph in both rows,
+    /// so that redrawing from there doesn't cut wide glyphs in half.
+    fn row_diff(&self, front: &Buffer, a: usize, back: &Buffer, b: usize) -> Option<usize> {
+        let fr = front.row(a);
+        let br = back.row(b);
+        let links = !front.links.links.is_empty() || !back.links.links.is_empty();
+        let ul = self.extended_underlines;
+
+        if fr.text == br.text
+            && fr.bg == br.bg
+            && fr.fg == br.fg
+            && (!ul || fr.ul == br.ul)
+            && fr.attr == br.attr
+            && (!links || fr.link == br.link && front.links.links == back.links.links)
+        {
+            return None;
+        }
+
+        let width = br.bg.len();
+        let mut x = (0..width)
+            .find(|&x| {
+                fr.bg[x] != br.bg[x]
+                    || fr.fg[x] != br.fg[x]
+                    || (ul && fr.ul[x] != br.ul[x])
+                    || fr.attr[x] != br.attr[x]
+                    || (links && front.links.get(fr.link[x]) != back.links.get(br.link[x]))
+            })
+            .unwrap_or(width) as CoordType;
+
+        if fr.text != br.text {
+            let prefix = fr.text.bytes().zip(br.text.bytes()).take_while(|(f, b)| f == b).count();
+            x = x.min(column_at_offset(fr.text, prefix)).min(column_at_offset(br.text, prefix));
+        }
+
+        if x as usize >= width {
+            // Links may be registered in a different order, but otherwise identical.
+            return None;
+        }
+
+        // Since `goto_visual` stops short of wide glyphs, this converges to a common glyph start.
+        loop {
+            let fx = column_before_visual(fr.text, x);
+            let bx = column_before_visual(br.text, x);
+            let m = fx.min(bx);
+            if m == x {
+                break;
+            }
+            x = m;
+        }
+
+        Some(x as usize)
+    }
+
     fn format_color(&self, dst: &mut ArenaString, fg: bool, mut color: StraightRgba) {
         let typ = if fg { '3' } else { '4' };
 
@@ -567,10 +1036,50 @@ impl Framebuffer {
             color = dst.oklab_blend(color);
         }
 
-        let r = color.red();
-        let g = color.green();
-        let b = color.blue();
-        _ = write!(dst, "\x1b[{typ}8;2;{r};{g};{b}m");
+        match self.color_depth {
+            ColorDepth::TrueColor => {
+                let r = color.red();
+                let g = color.green();
+                let b = color.blue();
+                _ = write!(dst, "\x1b[{typ}8;2;{r};{g};{b}m");
+            }
+            ColorDepth::Indexed256 => {
+                let idx = self.quantize(color);
+                _ = write!(dst, "\x1b[{typ}8;5;{idx}m");
+            }
+            ColorDepth::Indexed16 => {
+                let idx = self.quantize(color);
+                if idx < 8 {
+                    _ = write!(dst, "\x1b[{typ}{idx}m");
+                } else {
+                    // The bright colors are 90-97 and 100-107.
+                    let typ = if fg { "9" } else { "10" };
+                    _ = write!(dst, "\x1b[{typ}{}m", idx - 8);
+                }
+            }
+        }
+    }
+
+    /// Returns the palette index that's closest to `color`.
+    fn quantize(&self, color: StraightRgba) -> u8 {
+        let idx = (color.to_ne() as usize).wrapping_mul(HASH_MULTIPLIER) >> CACHE_TABLE_SHIFT;
+        let slot = self.quantized_colors[idx].get();
+        if slot.0 == color { slot.1 } else { self.quantize_slow(color) }
+    }
+
+    #[cold]
+    fn quantize_slow(&self, color: StraightRgba) -> u8 {
+        // The 16 VT colors vary between terminals, so the 256-color mode only uses
+        // the standardized 6x6x6 cube and grayscale ramp. Otherwise, we rely on
+        // `indexed_colors` to contain the actual palette of the terminal.
+        let best = match self.color_depth {
+            ColorDepth::Indexed16 => oklab::nearest_color(&self.indexed_colors[..16], color) as u8,
+            _ => oklab::nearest_xterm_color(color),
+        };
+
+        let idx = (color.to_ne() as usize).wrapping_mul(HASH_MULTIPLIER) >> CACHE_TABLE_SHIFT;
+        self.quantized_colors[idx].set((color, best));
+        best
     }
 }
 
@@ -579,10 +1088,100 @@ struct Buffer {
     text: LineBuffer,
     bg_bitmap: Bitmap,
     fg_bitmap: Bitmap,
+    ul_bitmap: Bitmap,
     attributes: AttributeBuffer,
+    links: LinkBuffer,
+    images: Vec<ImagePlacement>,
     cursor: Cursor,
 }
 
+/// An image drawn via [`Framebuffer::draw_image`].
+struct ImagePlacement {
+    rect: Rect,
+    image: Rc<Image>,
+}
+
+impl PartialEq for ImagePlacement {
+    fn eq(&self, other: &Self) -> bool {
+        self.rect == other.rect && self.image.id() == other.image.id()
+    }
+}
+
+impl Buffer {
+    fn row(&self, y: usize) -> Row<'_> {
+        let width = self.bg_bitmap.size.width as usize;
+        let range = y * width..(y + 1) * width;
+        Row {
+            text: &self.text.lines[y],
+            bg: &self.bg_bitmap.data[range.clone()],
+            fg: &self.fg_bitmap.data[range.clone()],
+            ul: &self.ul_bitmap.data[range.clone()],
+            attr: &self.attributes.data[range.clone()],
+            link: &self.links.data[range],
+        }
+    }
+}
+
+/// A single row of a [`Buffer`].
+struct Row<'a> {
+    text: &'a str,
+    bg: &'a [StraightRgba],
+    fg: &'a [StraightRgba],
+    ul: &'a [StraightRgba],
+    attr: &'a [Attributes],
+    link: &'a [u16],
+}
+
+/// Scrolling is only worth it if it saves redrawing at least this many rows.
+const MIN_SCROLL_ROWS: usize = 4;
+
+/// The rows in `region` moved up by `delta` rows, or down if it's negative.
+struct Scroll {
+    region: Range<usize>,
+    delta: isize,
+}
+
+/// The SGR state of the terminal while writing a frame, so that only changes are emitted.
+struct SgrState {
+    bg: u64,
+    fg: u64,
+    ul: u32,
+    attr: Attributes,
+    link: u16,
+}
+
+impl SgrState {
+    /// The state right after a `\x1b[m`, except that the colors are unknown.
+    fn new() -> Self {
+        // `\x1b[m` resets the underline color to the default (= 0) as well.
+        Self { bg: u64::MAX, fg: u64::MAX, ul: 0, attr: Attributes::None, link: 0 }
+    }
+}
+
+fn as_bytes<T: MemsetSafe>(slice: &[T]) -> &[u8] {
+    // SAFETY: `MemsetSafe` types are plain old data.
+    unsafe { slice::from_raw_parts(slice.as_ptr() as *const u8, mem::size_of_val(slice)) }
+}
+
+/// Returns the column of the glyph that contains the given byte `offset`.
+fn column_at_offset(text: &str, offset: usize) -> CoordType {
+    let bytes = text.as_bytes();
+    let cursor = MeasurementConfig::new(&bytes).goto_offset(offset);
+    if cursor.offset <= offset {
+        cursor.visual_pos.x
+    } else {
+        // `goto_offset` stopped after the grapheme cluster that contains `offset`.
+        let target = Point { x: cursor.logical_pos.x - 1, y: 0 };
+        MeasurementConfig::new(&bytes).goto_logical(target).visual_pos.x
+    }
+}
+
+/// Returns the start column of the glyph at column `x`.
+fn column_before_visual(text: &str, x: CoordType) -> CoordType {
+    let bytes = text.as_bytes();
+    MeasurementConfig::new(&bytes).goto_visual(Point { x, y: 0 }).visual_pos.x
+}
+
 /// A buffer for the text contents of the framebuffer.
 #[derive(Default)]
 struct LineBuffer {
@@ -797,9 +1396,24 @@ impl Bitmap {
         }
     }
 
-    /// Iterates over each row in the bitmap.
-    fn iter(&self) -> ChunksExact<'_, StraightRgba> {
-        self.data.chunks_exact(self.size.width as usize)
+    /// Replaces the colors in the given rectangle, without blending.
+    fn replace(&mut self, target: Rect, color: StraightRgba) {
+        let target = target.intersect(self.size.as_rect());
+        if target.is_empty() {
+            return;
+        }
+
+        let top = target.top as usize;
+        let bottom = target.bottom as usize;
+        let left = target.left as usize;
+        let right = target.right as usize;
+        let stride = self.size.width as usize;
+
+        for y in top..bottom {
+            let beg = y * stride + left;
+            let end = y * stride + right;
+            memset(&mut self.data[beg..end], color);
+        }
     }
 }
 
@@ -808,18 +1422,36 @@ impl Bitmap {
 /// It being a bitfield allows for simple diffing.
 #[repr(transparent)]
 #[derive(Default, Clone, Copy, PartialEq, Eq)]
-pub struct Attributes(u8);
+pub struct Attributes(u16);
 
 #[allow(non_upper_case_globals)]
 impl Attributes {
     pub const None: Self = Self(0);
     pub const Italic: Self = Self(0b1);
     pub const Underlined: Self = Self(0b10);
-    pub const All: Self = Self(0b11);
+    // The extended underline styles are mutually exclusive with `Underlined` and each other.
+    // See [`Framebuffer::set_extended_underlines`].
+    pub const DoubleUnderlined: Self = Self(0b100);
+    pub const CurlyUnderlined: Self = Self(0b1000);
+    pub const DottedUnderlined: Self = Self(0b10000);
+    pub const DashedUnderlined: Self = Self(0b100000);
+    pub const AnyUnderline: Self = Self(0b111110);
+    pub const Strikethrough: Self = Self(0b1000000);
+    /// Also known as "dim".
+    pub const Faint: Self = Self(0b10000000);
+    pub const Overlined: Self = Self(0b100000000);
+    pub const All: Self = Self(0b111111111);
 
     pub const fn is(self, attr: Self) -> bool {
         (self.0 & attr.0) == attr.0
     }
+
+    /// Returns the SGR 4:x underline style, with 0 being none.
+    const fn underline_style(self) -> u8 {
+        // The bit order matches the order of the SGR 4:x styles.
+        let bits = (self.0 & Self::AnyUnderline.0) >> 1;
+        if bits == 0 { 0 } else { bits.trailing_zeros() as u8 + 1 }
+    }
 }
 
 unsafe impl MemsetSafe for Attributes {}
@@ -882,26 +1514,454 @@ impl AttributeBuffer {
             }
         }
     }
+}
 
-    /// Iterates over each row in the bitmap.
-    fn iter(&self) -> ChunksExact<'_, Attributes> {
-        self.data.chunks_exact(self.size.width as usize)
+#[derive(PartialEq, Eq)]
+struct Hyperlink {
+    id: String,
+    uri: String,
+}
+
+/// Stores OSC 8 hyperlinks for the framebuffer.
+#[derive(Default)]
+struct LinkBuffer {
+    /// 1 + the index into `links` for each cell, or 0 for no link.
+    data: Vec<u16>,
+    links: Vec<Hyperlink>,
+    size: Size,
+}
+
+impl LinkBuffer {
+    fn new(size: Size) -> Self {
+        Self { data: vec![0; (size.width * size.height) as usize], links: Vec::new(), size }
+    }
+
+    fn reset(&mut self) {
+        memset(&mut self.data, 0);
+        self.links.clear();
     }
+
+    fn replace(&mut self, target: Rect, uri: &str, id: &str) {
+        let target = target.intersect(self.size.as_rect());
+        if target.is_empty() {
+            return;
+        }
+
+        let link = if uri.is_empty() {
+            0
+        } else {
+            // Control characters would terminate the OSC sequence and
+            // the id must not contain the parameter separators ':' and ';'.
+            let uri: String = uri.chars().filter(|c| !c.is_control()).collect();
+            let id: String =
+                id.chars().filter(|&c| !c.is_control() && c != ':' && c != ';').collect();
+            let link = Hyperlink { id, uri };
+
+            match self.links.iter().position(|l| *l == link) {
+                Some(idx) => idx + 1,
+                None if self.links.len() < u16::MAX as usize => {
+                    self.links.push(link);
+                    self.links.len()
+                }
+                None => return,
+            }
+        };
+
+        let top = target.top as usize;
+        let bottom = target.bottom as usize;
+        let left = target.left as usize;
+        let right = target.right as usize;
+        let stride = self.size.width as usize;
+
+        for y in top..bottom {
+            let beg = y * stride + left;
+            let end = y * stride + right;
+            memset(&mut self.data[beg..end], link as u16);
+        }
+    }
+
+    fn get(&self, link: u16) -> Option<&Hyperlink> {
+        self.links.get((link as usize).wrapping_sub(1))
+    }
+}
+
+#[derive(Default, Clone, Copy, PartialEq, Eq, Debug)]
+pub enum CursorShape {
+    Block,
+    Underline,
+    #[default]
+    Bar,
 }
 
-/// Stores cursor position and type for the framebuffer.
+/// The appearance of the terminal cursor.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub struct CursorStyle {
+    pub shape: CursorShape,
+    pub blink: bool,
+}
+
+impl CursorStyle {
+    /// The style used while inserting text.
+    pub const INSERT: Self = Self { shape: CursorShape::Bar, blink: true };
+    /// The style used while overwriting text.
+    pub const OVERTYPE: Self = Self { shape: CursorShape::Block, blink: true };
+
+    /// Returns the DECSCUSR parameter for this style.
+    pub const fn decscusr(self) -> u16 {
+        let base = match self.shape {
+            CursorShape::Block => 1,
+            CursorShape::Underline => 3,
+            CursorShape::Bar => 5,
+        };
+        // The even numbers are the steady variants.
+        if self.blink { base } else { base + 1 }
+    }
+}
+
+impl Default for CursorStyle {
+    fn default() -> Self {
+        Self::INSERT
+    }
+}
+
+/// Stores cursor position and style for the framebuffer.
 #[derive(Default, PartialEq, Eq)]
 struct Cursor {
     pos: Point,
-    overtype: bool,
+    style: CursorStyle,
 }
 
 impl Cursor {
     const fn new_invalid() -> Self {
-        Self { pos: Point::MIN, overtype: false }
+        Self { pos: Point::MIN, style: CursorStyle::INSERT }
     }
 
     const fn new_disabled() -> Self {
-        Self { pos: Point { x: -1, y: -1 }, overtype: false }
+        Self { pos: Point { x: -1, y: -1 }, style: CursorStyle::INSERT }
+    }
+}
+
+/// The position of a scrollbar thumb in 1/8th cells, relative to the start of the track.
+struct ScrollbarThumb {
+    start: i64,
+    end: i64,
+}
+
+impl ScrollbarThumb {
+    /// Returns `None` if the content fits into the track and no scrollbar is needed.
+    fn new(
+        track_len: CoordType,
+        content_offset: CoordType,
+        content_len: CoordType,
+    ) -> Option<Self> {
+        let viewport_len = track_len;
+        // The content length is at least the viewport length.
+        let content_len = content_len.max(viewport_len);
+
+        // No need to draw a scrollbar if the content fits in the viewport.
+        let content_offset_max = content_len - viewport_len;
+        if content_offset_max == 0 {
+            return None;
+        }
+
+        // The content offset must be at least one viewport length from the end.
+        // You don't want to scroll past the end after all...
+        let content_offset = content_offset.clamp(0, content_offset_max);
+
+        // In order to increase the visual resolution of the scrollbar,
+        // we'll use 1/8th blocks to represent the thumb.
+        // First, scale the offsets to get that 1/8th resolution.
+        let viewport_len = viewport_len as i64 * 8;
+        let content_offset_max = content_offset_max as i64 * 8;
+        let content_offset = content_offset as i64 * 8;
+        let content_len = content_len as i64 * 8;
+
+        // The proportional thumb length (0-1) is the fraction of viewport and
+        // content length. The longer the content, the smaller the thumb:
+        // = viewport_len / content_len
+        // We then scale that to the viewport length to get the length in 1/8th units.
+        // = viewport_len * viewport_len / content_len
+        // We add content_len/2 to round the integer division, which results in a numerator of:
+        // = viewport_len * viewport_len + content_len / 2
+        let numerator = viewport_len * viewport_len + content_len / 2;
+        let thumb_len = numerator / content_len;
+        // Ensure the thumb has a minimum size of 1 cell.
+        let thumb_len = thumb_len.max(8);
+
+        // The proportional thumb start position (0-1) is:
+        // = content_offset / content_offset_max
+        // The maximum thumb start position is the viewport length minus the thumb length:
+        // = viewport_len - thumb_len
+        // To get the thumb start position in 1/8th units, we multiply both:
+        // = (viewport_len - thumb_len) * content_offset / content_offset_max
+        // We add content_offset_max/2 to round the integer division, which results in a numerator of:
+        // = (viewport_len - thumb_len) * content_offset + content_offset_max / 2
+        let numerator = (viewport_len - thumb_len) * content_offset + content_offset_max / 2;
+        let start = numerator / content_offset_max;
+
+        Some(Self { start, end: start + thumb_len })
+    }
+
+    /// The length of the thumb in cells, rounded.
+    fn size(&self) -> CoordType {
+        ((self.end - self.start + 4) / 8) as CoordType
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn render(fb: &mut Framebuffer) -> String {
+        let arena = Arena::new(64 * 1024).unwrap();
+        fb.render(&arena).to_string()
+    }
+
+    #[test]
+    fn test_hyperlinks() {
+        let size = Size { width: 8, height: 2 };
+        let mut fb = Framebuffer::new();
+        // The first frame is always a full redraw.
+        fb.flip(size);
+        render(&mut fb);
+
+        fb.flip(size);
+        fb.replace_text(0, 0, 8, "ab link");
+        fb.set_hyperlink(Rect { left: 3, top: 0, right: 7, bottom: 1 }, "https://a\x1b.b", "x;1");
+        let out = render(&mut fb);
+        assert!(out.contains("ab \x1b]8;id=x1;https://a.b\x1b\\link\x1b]8;;\x1b\\ "));
+        assert!(!out.contains("\x1b[2;1H"));
+
+        // Unchanged links don't cause a redraw, even if registered in a different order.
+        fb.flip(size);
+        fb.replace_text(0, 0, 8, "ab link");
+        fb.set_hyperlink(Rect { left: 0, top: 1, right: 1, bottom: 2 }, "other", "");
+        fb.set_hyperlink(Rect { left: 3, top: 0, right: 7, bottom: 1 }, "https://a.b", "x1");
+        let out = render(&mut fb);
+        assert!(!out.contains("\x1b[1;1H"));
+        assert!(out.contains("\x1b[2;1H"));
+        assert!(out.contains("\x1b]8;;other\x1b\\ \x1b]8;;\x1b\\ "));
+    }
+
+    #[test]
+    fn test_images() {
+        let size = Size { width: 4, height: 4 };
+        let rect = Rect { left: 1, top: 1, right: 3, bottom: 3 };
+        let image = Rc::new(Image::new(1, 1, vec![StraightRgba::from_be(0xff0000ff)]));
+        let mut fb = Framebuffer::new();
+        fb.set_image_protocol(ImageProtocol::Sixel, Size { width: 1, height: 3 });
+        fb.flip(size);
+        render(&mut fb);
+
+        fb.flip(size);
+        fb.draw_image(rect, &image);
+        let out = render(&mut fb);
+        assert!(out.contains("\x1b[2;2H\x1bP0;1;0q\"1;1;2;6"));
+
+        // Unchanged images aren't drawn again, unless the text below them changed.
+        fb.flip(size);
+        fb.draw_image(rect, &image);
+        assert_eq!(render(&mut fb), "");
+        fb.flip(size);
+        fb.replace_text(2, 0, 4, "abcd");
+        fb.draw_image(rect, &image);
+        assert!(render(&mut fb).contains("\x1bP"));
+
+        // Removed images are overwritten.
+        fb.flip(size);
+        fb.replace_text(2, 0, 4, "abcd");
+        let out = render(&mut fb);
+        assert!(out.contains("\x1b[2;1H") && out.contains("\x1b[3;1H"));
+        assert!(!out.contains("\x1bP"));
+
+        // Kitty images are deleted instead.
+        fb.set_image_protocol(ImageProtocol::Kitty, Size { width: 1, height: 3 });
+        fb.flip(size);
+        fb.replace_text(2, 0, 4, "abcd");
+        fb.draw_image(rect, &image);
+        assert!(render(&mut fb).contains("\x1b_Ga=T"));
+        fb.flip(size);
+        fb.replace_text(2, 0, 4, "abcd");
+        assert!(
+            render(&mut fb).starts_with(&format!("\x1b[m{}\x1b[?25l", image::KITTY_DELETE_ALL))
+        );
+
+        // Images that don't fit are ignored.
+        fb.flip(size);
+        fb.draw_image(Rect { left: 3, top: 3, right: 5, bottom: 4 }, &image);
+        assert_eq!(fb.buffers[fb.frame_counter & 1].images.len(), 0);
+    }
+
+    #[test]
+    fn test_capture() {
+        let size = Size { width: 6, height: 2 };
+        let arena = Arena::new(64 * 1024).unwrap();
+        let mut fb = Framebuffer::new();
+        fb.flip(size);
+        fb.replace_text(0, 0, 6, "foo");
+        fb.replace_text(1, 1, 6, "bär");
+        fb.replace_attr(
+            Rect { left: 0, top: 0, right: 2, bottom: 1 },
+            Attributes::All,
+            Attributes::Italic,
+        );
+        render(&mut fb);
+
+        assert_eq!(fb.capture(&arena, CaptureFormat::PlainText), "foo\n bär\n");
+
+        let ansi = fb.capture(&arena, CaptureFormat::Ansi);
+        let lines: Vec<_> = ansi.split('\n').collect();
+        assert_eq!(lines.len(), 3);
+        assert!(lines[0].starts_with("\x1b[m\x1b[48;2;0;0;0m"));
+        assert!(lines[0].ends_with("\x1b[3mfo\x1b[23mo   \x1b[m"));
+        assert!(lines[1].ends_with(" bär  \x1b[m"));
+        assert_eq!(lines[2], "");
+    }
+
+    #[test]
+    fn test_extended_underlines() {
+        let size = Size { width: 4, height: 1 };
+        let draw = |fb: &mut Framebuffer| {
+            fb.flip(size);
+            render(fb);
+            fb.flip(size);
+            fb.replace_attr(
+                Rect { left: 0, top: 0, right: 2, bottom: 1 },
+                Attributes::All,
+                Attributes::CurlyUnderlined,
+            );
+            fb.replace_attr(
+                Rect { left: 2, top: 0, right: 4, bottom: 1 },
+                Attributes::All,
+                Attributes::Underlined,
+            );
+            fb.set_underline_color(
+                Rect { left: 0, top: 0, right: 1, bottom: 1 },
+                StraightRgba::from_be(0xff0000ff),
+            );
+            render(fb)
+        };
+
+        let mut fb = Framebuffer::new();
+        let out = draw(&mut fb);
+        assert!(out.contains("\x1b[4m  "));
+        assert!(!out.contains("\x1b[4:"));
+        assert!(!out.contains("\x1b[58"));
+
+        let mut fb = Framebuffer::new();
+        fb.set_extended_underlines(true);
+        let out = draw(&mut fb);
+        assert!(out.contains("\x1b[4:3m\x1b[58:2::255:0:0m \x1b[59m \x1b[4m  "));
+    }
+
+    #[test]
+    fn test_attributes() {
+        let size = Size { width: 3, height: 1 };
+        let mut fb = Framebuffer::new();
+        fb.flip(size);
+        render(&mut fb);
+
+        fb.flip(size);
+        let attr = Attributes::Faint | Attributes::Strikethrough;
+        fb.replace_attr(Rect { left: 0, top: 0, right: 2, bottom: 1 }, attr, attr);
+        let attr = Attributes::Overlined;
+        fb.replace_attr(Rect { left: 1, top: 0, right: 3, bottom: 1 }, attr, attr);
+        let out = render(&mut fb);
+        assert!(out.ends_with("\x1b[2m\x1b[9m \x1b[53m \x1b[22m\x1b[29m \x1b[?25l"));
+    }
+
+    #[test]
+    fn test_partial_redraw() {
+        let size = Size { width: 8, height: 1 };
+        let mut fb = Framebuffer::new();
+        fb.flip(size);
+        fb.replace_text(0, 0, 8, "a😀b cd");
+        render(&mut fb);
+
+        fb.flip(size);
+        fb.replace_text(0, 0, 8, "a😀b xy");
+        assert!(
+            render(&mut fb).starts_with("\x1b[m\x1b[1;6H\x1b[48;2;0;0;0m\x1b[38;2;190;190;190mxy ")
+        );
+
+        // Replacing the wide glyph must redraw it entirely.
+        fb.flip(size);
+        fb.replace_text(0, 0, 8, "a😀b xy");
+        fb.replace_text(0, 2, 8, "c");
+        assert!(render(&mut fb).contains("\x1b[1;2H\x1b[48;2;0;0;0m\x1b[38;2;190;190;190m cb xy "));
+    }
+
+    #[test]
+    fn test_scroll() {
+        let size = Size { width: 4, height: 10 };
+        let draw = |fb: &mut Framebuffer, first: usize| {
+            fb.flip(size);
+            for y in 0..9 {
+                fb.replace_text(y, 0, 4, &format!("{}", first + y as usize));
+            }
+            fb.replace_text(9, 0, 4, "stat");
+            render(fb)
+        };
+
+        let mut fb = Framebuffer::new();
+        draw(&mut fb, 10);
+
+        // Scroll down by 2 lines = the contents move up.
+        let out = draw(&mut fb, 12);
+        assert!(out.starts_with("\x1b[m\x1b[1;9r\x1b[2S\x1b[r"));
+        assert!(!out.contains("\x1b[1;1H"));
+        assert!(out.contains("\x1b[8;1H"));
+        assert!(out.contains("\x1b[9;1H"));
+        assert!(!out.contains("\x1b[10;1H"));
+
+        let out = draw(&mut fb, 11);
+        assert!(out.starts_with("\x1b[m\x1b[1;9r\x1b[1T\x1b[r\x1b[1;1H"));
+        assert!(!out.contains("\x1b[2;1H"));
+    }
+
+    #[test]
+    fn test_quantize() {
+        let mut fb = Framebuffer::new();
+        fb.set_color_depth(ColorDepth::Indexed256);
+        assert_eq!(fb.quantize(StraightRgba::from_be(0xff0000ff)), 196);
+        assert_eq!(fb.quantize(StraightRgba::from_be(0x808080ff)), 244);
+        assert_eq!(fb.quantize(StraightRgba::from_be(0x000000ff)), 16);
+        // Cached.
+        assert_eq!(fb.quantize(StraightRgba::from_be(0xff0000ff)), 196);
+
+        fb.set_color_depth(ColorDepth::Indexed16);
+        assert_eq!(fb.quantize(StraightRgba::from_be(0xff0000ff)), IndexedColor::BrightRed as u8);
+        assert_eq!(fb.quantize(StraightRgba::from_be(0x101010ff)), IndexedColor::Black as u8);
+
+        let arena = Arena::new(64 * 1024).unwrap();
+        let mut out = ArenaString::new_in(&arena);
+        fb.format_color(&mut out, false, StraightRgba::from_be(0xfefefeff));
+        fb.format_color(&mut out, true, StraightRgba::from_be(0x204dbeff));
+        assert_eq!(out, "\x1b[107m\x1b[34m");
+    }
+
+    #[test]
+    fn test_generate_palette() {
+        let bg = StraightRgba::from_be(0x1e1e2eff);
+        let fg = StraightRgba::from_be(0xcdd6f4ff);
+        let accent = StraightRgba::from_be(0x89b4faff);
+        let palette = generate_palette(bg, fg, accent);
+        let lch = |i: IndexedColor| palette[i as usize].as_oklch();
+
+        assert_eq!(palette[IndexedColor::Background as usize], bg);
+        assert_eq!(palette[IndexedColor::Foreground as usize], fg);
+        assert!(lch(IndexedColor::Black).lightness() < lch(IndexedColor::BrightBlack).lightness());
+        assert!(lch(IndexedColor::White).lightness() < lch(IndexedColor::BrightWhite).lightness());
+
+        for (normal, bright) in [
+            (IndexedColor::Red, IndexedColor::BrightRed),
+            (IndexedColor::Green, IndexedColor::BrightGreen),
+            (IndexedColor::Blue, IndexedColor::BrightBlue),
+        ] {
+            let expected = DEFAULT_THEME[normal as usize].as_oklch().hue();
+            assert!((lch(normal).hue() - expected).abs() < 5.0);
+            assert!((lch(normal).lightness() - accent.as_oklch().lightness()).abs() < 0.02);
+            assert!(lch(normal).lightness() < lch(bright).lightness());
+        }
     }
 }
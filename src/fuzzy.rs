@@ -12,11 +12,60 @@ use crate::icu;
 
 const NO_MATCH: i32 = 0;
 
+/// The weights used by [`score_fuzzy`], so that pickers can tune the ranking to their content.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct FuzzyProfile {
+    /// Bonus for a match at the very start of the haystack.
+    pub start_bonus: i32,
+    /// Bonus for each previous consecutive match, favoring contiguous runs.
+    pub consecutive_bonus: i32,
+    /// Bonus for a match that has the same case as the needle.
+    pub same_case_bonus: i32,
+    /// Bonus for a match after a path separator (`/` or `\`).
+    pub path_separator_bonus: i32,
+    /// Bonus for a match after another separator, such as `_`, `-`, `.` or a space.
+    pub word_boundary_bonus: i32,
+    /// Bonus for an uppercase match inside a word, e.g. "NPE" in "NullPointerException".
+    pub camel_case_bonus: i32,
+    /// Penalty for each gap between two matched characters.
+    pub gap_penalty: i32,
+    /// Bonus for a match in the last path component, i.e. the filename.
+    pub filename_bonus: i32,
+}
+
+impl FuzzyProfile {
+    /// The weights of VS Code's scorer. Suitable for commands and other short labels.
+    pub const DEFAULT: Self = Self {
+        start_bonus: 8,
+        consecutive_bonus: 5,
+        same_case_bonus: 1,
+        path_separator_bonus: 5,
+        word_boundary_bonus: 4,
+        camel_case_bonus: 2,
+        gap_penalty: 0,
+        filename_bonus: 0,
+    };
+
+    /// Prefers matches in the filename over matches in the directories leading up to it.
+    pub const PATHS: Self = Self { gap_penalty: 1, filename_bonus: 3, ..Self::DEFAULT };
+}
+
+impl Default for FuzzyProfile {
+    fn default() -> Self {
+        Self::DEFAULT
+    }
+}
+
+/// Scores how well `needle` matches `haystack`. A score of 0 means that it doesn't match.
+///
+/// Also returns the indices of the matched characters in `haystack` in ascending order,
+/// so that they can be highlighted. They're `char` indices, not byte offsets.
 pub fn score_fuzzy<'a>(
     arena: &'a Arena,
     haystack: &str,
     needle: &str,
     allow_non_contiguous_matches: bool,
+    profile: &FuzzyProfile,
 ) -> (i32, Vec<usize, &'a Arena>) {
     if haystack.is_empty() || needle.is_empty() {
         // return early if target or query are empty
@@ -32,10 +81,9 @@ pub fn score_fuzzy<'a>(
         return (NO_MATCH, Vec::new_in(arena));
     }
 
-    let target_lower = icu::fold_case(&scratch, haystack);
-    let query_lower = icu::fold_case(&scratch, needle);
-    let target_lower = map_chars(&scratch, &target_lower);
-    let query_lower = map_chars(&scratch, &query_lower);
+    let target_lower = map_chars_folded(&scratch, &target);
+    let query_lower = map_chars_folded(&scratch, &query);
+    let filename_start = target.iter().rposition(|&c| c == '/' || c == '\\').map_or(0, |i| i + 1);
 
     let area = query.len() * target.len();
     let mut scores = vec::from_elem_in(0, area, &*scratch);
@@ -82,14 +130,28 @@ pub fn score_fuzzy<'a>(
             let score = if diag_score == 0 && query_index != 0 {
                 0
             } else {
-                compute_char_score(
+                let score = compute_char_score(
+                    profile,
                     query[query_index],
                     query_lower[query_index],
                     if target_index != 0 { Some(target[target_index - 1]) } else { None },
                     target[target_index],
                     target_lower[target_index],
                     matches_sequence_len,
-                )
+                );
+                if score == 0 {
+                    0
+                } else {
+                    let mut bonus = 0;
+                    if target_index >= filename_start {
+                        bonus += profile.filename_bonus;
+                    }
+                    if query_index > 0 && matches_sequence_len == 0 {
+                        bonus -= profile.gap_penalty;
+                    }
+                    // A score of 0 means "no match", so penalties must not go below 1.
+                    (score + bonus).max(1)
+                }
             };
 
             // We have a score and its equal or larger than the left score
@@ -153,6 +215,7 @@ pub fn score_fuzzy<'a>(
 }
 
 fn compute_char_score(
+    profile: &FuzzyProfile,
     query: char,
     query_lower: char,
     target_prev: Option<char>,
@@ -171,17 +234,17 @@ fn compute_char_score(
 
     // Consecutive match bonus
     if matches_sequence_len > 0 {
-        score += matches_sequence_len * 5;
+        score += matches_sequence_len * profile.consecutive_bonus;
     }
 
     // Same case bonus
     if query == target_curr {
-        score += 1;
+        score += profile.same_case_bonus;
     }
 
     if let Some(target_prev) = target_prev {
         // After separator bonus
-        let separator_bonus = score_separator_at_pos(target_prev);
+        let separator_bonus = score_separator_at_pos(profile, target_prev);
         if separator_bonus > 0 {
             score += separator_bonus;
         }
@@ -190,11 +253,11 @@ fn compute_char_score(
         // NPE => NullPointerException = boost
         // HTTP => HTTP = not boost
         else if target_curr != target_curr_lower && matches_sequence_len == 0 {
-            score += 2;
+            score += profile.camel_case_bonus;
         }
     } else {
         // Start of word bonus
-        score += 8;
+        score += profile.start_bonus;
     }
 
     score
@@ -205,10 +268,10 @@ fn consider_as_equal(a: char, b: char) -> bool {
     a == b || (a == '/' && b == '\\') || (a == '\\' && b == '/')
 }
 
-fn score_separator_at_pos(ch: char) -> i32 {
+fn score_separator_at_pos(profile: &FuzzyProfile, ch: char) -> i32 {
     match ch {
-        '/' | '\\' => 5,                               // prefer path separators...
-        '_' | '-' | '.' | ' ' | '\'' | '"' | ':' => 4, // ...over other separators
+        '/' | '\\' => profile.path_separator_bonus,
+        '_' | '-' | '.' | ' ' | '\'' | '"' | ':' => profile.word_boundary_bonus,
         _ => 0,
     }
 }
@@ -219,3 +282,75 @@ fn map_chars<'a>(arena: &'a Arena, s: &str) -> Vec<char, &'a Arena> {
     chars.shrink_to_fit();
     chars
 }
+
+/// Folds each character on its own, keeping only the first one of multi-character foldings
+/// (e.g. "ß" becomes "s"). Otherwise, the indices wouldn't line up with the original string.
+fn map_chars_folded<'a>(arena: &'a Arena, chars: &[char]) -> Vec<char, &'a Arena> {
+    let mut folded = Vec::with_capacity_in(chars.len(), arena);
+    folded.extend(chars.iter().map(|&c| icu::fold_char(c).first().copied().unwrap_or(c)));
+    folded
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn positions(haystack: &str, needle: &str, allow_non_contiguous_matches: bool) -> Vec<usize> {
+        let scratch = scratch_arena(None);
+        let (score, positions) = score_fuzzy(
+            &scratch,
+            haystack,
+            needle,
+            allow_non_contiguous_matches,
+            &FuzzyProfile::DEFAULT,
+        );
+        assert_eq!(score > 0, !positions.is_empty());
+        positions.to_vec()
+    }
+
+    #[test]
+    fn test_score_fuzzy_positions() {
+        assert_eq!(positions("utf-8", "utf8", true), [0, 1, 2, 4]);
+        assert_eq!(positions("utf-8", "utf8", false), []);
+        assert_eq!(positions("UTF-16LE", "16", false), [4, 5]);
+        assert_eq!(positions("NullPointerException", "npe", true), [0, 4, 11]);
+        assert_eq!(positions("windows-1252", "xyz", true), []);
+        // Indices are in characters, not bytes.
+        assert_eq!(positions("Grüße.txt", "get", true), [0, 4, 6]);
+    }
+
+    #[test]
+    fn test_score_fuzzy_profile() {
+        let scratch = scratch_arena(None);
+        let score = |haystack: &str, needle: &str, profile: &FuzzyProfile| {
+            score_fuzzy(&scratch, haystack, needle, true, profile).0
+        };
+
+        // By default, the match in the directory name wins, because it's at the start.
+        let a = "main/lib.rs";
+        let b = "src/main.rs";
+        assert!(
+            score(a, "main", &FuzzyProfile::DEFAULT) > score(b, "main", &FuzzyProfile::DEFAULT)
+        );
+        assert!(score(a, "main", &FuzzyProfile::PATHS) < score(b, "main", &FuzzyProfile::PATHS));
+
+        // Gaps are penalized, but a match never scores 0.
+        let gappy = FuzzyProfile { gap_penalty: 100, ..FuzzyProfile::DEFAULT };
+        assert!(score("a_b_c", "abc", &gappy) > 0);
+        assert!(score("a_b_c", "abc", &gappy) < score("a_b_c", "abc", &FuzzyProfile::DEFAULT));
+        assert_eq!(score("abc", "abc", &gappy), score("abc", "abc", &FuzzyProfile::DEFAULT));
+
+        // Weights can be turned off entirely.
+        let flat = FuzzyProfile {
+            start_bonus: 0,
+            consecutive_bonus: 0,
+            same_case_bonus: 0,
+            path_separator_bonus: 0,
+            word_boundary_bonus: 0,
+            camel_case_bonus: 0,
+            gap_penalty: 0,
+            filename_bonus: 0,
+        };
+        assert_eq!(score("Foo/Bar-Baz", "fbb", &flat), 3);
+    }
+}
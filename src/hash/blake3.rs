@@ -0,0 +1,292 @@
+// Copyright (c) Microsoft Corporation.
+// Licensed under the MIT License.
+
+//! A straightforward port of the BLAKE3 reference implementation.
+//! See: <https://github.com/BLAKE3-team/BLAKE3/blob/master/reference_impl/reference_impl.rs>
+//!
+//! It's a lot slower than the SIMD-optimized official crate, but it's small,
+//! and we only use it where integrity matters more than speed.
+
+const OUT_LEN: usize = 32;
+const BLOCK_LEN: usize = 64;
+const CHUNK_LEN: usize = 1024;
+
+const CHUNK_START: u32 = 1 << 0;
+const CHUNK_END: u32 = 1 << 1;
+const PARENT: u32 = 1 << 2;
+const ROOT: u32 = 1 << 3;
+
+const IV: [u32; 8] = [
+    0x6a09e667, 0xbb67ae85, 0x3c6ef372, 0xa54ff53a, 0x510e527f, 0x9b05688c, 0x1f83d9ab, 0x5be0cd19,
+];
+
+const MSG_PERMUTATION: [usize; 16] = [2, 6, 3, 10, 7, 0, 4, 13, 1, 11, 12, 5, 9, 14, 15, 8];
+
+/// Computes the 32-byte BLAKE3 hash of `data`.
+///
+/// Unlike [`super::hash`], it's collision-resistant, which makes it suitable
+/// for checksums that guard against more than accidental changes.
+pub fn blake3(data: &[u8]) -> [u8; OUT_LEN] {
+    let mut hasher = Blake3::new();
+    hasher.update(data);
+    hasher.finalize()
+}
+
+/// An incremental BLAKE3 hasher. See [`blake3`].
+#[derive(Clone)]
+pub struct Blake3 {
+    chunk: ChunkState,
+    // 54 is enough for 2^64 bytes of input.
+    cv_stack: [[u32; 8]; 54],
+    cv_stack_len: usize,
+}
+
+impl Default for Blake3 {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Blake3 {
+    pub fn new() -> Self {
+        Self { chunk: ChunkState::new(0), cv_stack: [[0; 8]; 54], cv_stack_len: 0 }
+    }
+
+    pub fn update(&mut self, mut data: &[u8]) {
+        while !data.is_empty() {
+            // Only finish a chunk once more input arrives,
+            // because the last chunk may have to be the root.
+            if self.chunk.len() == CHUNK_LEN {
+                let cv = self.chunk.output().chaining_value();
+                let total_chunks = self.chunk.counter + 1;
+                self.add_chunk_chaining_value(cv, total_chunks);
+                self.chunk = ChunkState::new(total_chunks);
+            }
+
+            let take = (CHUNK_LEN - self.chunk.len()).min(data.len());
+            self.chunk.update(&data[..take]);
+            data = &data[take..];
+        }
+    }
+
+    pub fn finalize(&self) -> [u8; OUT_LEN] {
+        let mut output = self.chunk.output();
+        for cv in self.cv_stack[..self.cv_stack_len].iter().rev() {
+            output = Output::parent(cv, &output.chaining_value());
+        }
+        output.root_bytes()
+    }
+
+    // Each completed subtree gets merged with its sibling on the stack.
+    // The number of trailing 0 bits in the chunk count is the number of merges.
+    fn add_chunk_chaining_value(&mut self, mut cv: [u32; 8], mut total_chunks: u64) {
+        while total_chunks & 1 == 0 {
+            self.cv_stack_len -= 1;
+            cv = Output::parent(&self.cv_stack[self.cv_stack_len], &cv).chaining_value();
+            total_chunks >>= 1;
+        }
+        self.cv_stack[self.cv_stack_len] = cv;
+        self.cv_stack_len += 1;
+    }
+}
+
+#[derive(Clone)]
+struct ChunkState {
+    cv: [u32; 8],
+    counter: u64,
+    block: [u8; BLOCK_LEN],
+    block_len: usize,
+    blocks_compressed: usize,
+}
+
+impl ChunkState {
+    fn new(counter: u64) -> Self {
+        Self { cv: IV, counter, block: [0; BLOCK_LEN], block_len: 0, blocks_compressed: 0 }
+    }
+
+    fn len(&self) -> usize {
+        BLOCK_LEN * self.blocks_compressed + self.block_len
+    }
+
+    fn start_flag(&self) -> u32 {
+        if self.blocks_compressed == 0 { CHUNK_START } else { 0 }
+    }
+
+    fn update(&mut self, mut data: &[u8]) {
+        while !data.is_empty() {
+            if self.block_len == BLOCK_LEN {
+                let words = words_from_le_bytes(&self.block);
+                let out = compress(&self.cv, &words, self.counter, BLOCK_LEN, self.start_flag());
+                self.cv = first_8_words(out);
+                self.blocks_compressed += 1;
+                self.block = [0; BLOCK_LEN];
+                self.block_len = 0;
+            }
+
+            let take = (BLOCK_LEN - self.block_len).min(data.len());
+            self.block[self.block_len..self.block_len + take].copy_from_slice(&data[..take]);
+            self.block_len += take;
+            data = &data[take..];
+        }
+    }
+
+    fn output(&self) -> Output {
+        Output {
+            cv: self.cv,
+            block: words_from_le_bytes(&self.block),
+            counter: self.counter,
+            block_len: self.block_len,
+            flags: self.start_flag() | CHUNK_END,
+        }
+    }
+}
+
+/// The inputs to the final compression of a node. Deferred, because the root node
+/// gets compressed with the `ROOT` flag and we only know which one it is at the end.
+struct Output {
+    cv: [u32; 8],
+    block: [u32; 16],
+    counter: u64,
+    block_len: usize,
+    flags: u32,
+}
+
+impl Output {
+    fn parent(left: &[u32; 8], right: &[u32; 8]) -> Self {
+        let mut block = [0; 16];
+        block[..8].copy_from_slice(left);
+        block[8..].copy_from_slice(right);
+        Self { cv: IV, block, counter: 0, block_len: BLOCK_LEN, flags: PARENT }
+    }
+
+    fn chaining_value(&self) -> [u32; 8] {
+        first_8_words(compress(&self.cv, &self.block, self.counter, self.block_len, self.flags))
+    }
+
+    fn root_bytes(&self) -> [u8; OUT_LEN] {
+        let words = compress(&self.cv, &self.block, 0, self.block_len, self.flags | ROOT);
+        let mut out = [0; OUT_LEN];
+        for (dst, word) in out.chunks_exact_mut(4).zip(words) {
+            dst.copy_from_slice(&word.to_le_bytes());
+        }
+        out
+    }
+}
+
+fn compress(
+    cv: &[u32; 8],
+    block: &[u32; 16],
+    counter: u64,
+    block_len: usize,
+    flags: u32,
+) -> [u32; 16] {
+    #[rustfmt::skip]
+    let mut state = [
+        cv[0], cv[1], cv[2], cv[3], cv[4], cv[5], cv[6], cv[7],
+        IV[0], IV[1], IV[2], IV[3],
+        counter as u32, (counter >> 32) as u32, block_len as u32, flags,
+    ];
+    let mut m = *block;
+
+    for i in 0..7 {
+        round(&mut state, &m);
+        if i < 6 {
+            m = MSG_PERMUTATION.map(|j| m[j]);
+        }
+    }
+
+    for i in 0..8 {
+        state[i] ^= state[i + 8];
+        state[i + 8] ^= cv[i];
+    }
+    state
+}
+
+fn round(s: &mut [u32; 16], m: &[u32; 16]) {
+    // Columns
+    g(s, 0, 4, 8, 12, m[0], m[1]);
+    g(s, 1, 5, 9, 13, m[2], m[3]);
+    g(s, 2, 6, 10, 14, m[4], m[5]);
+    g(s, 3, 7, 11, 15, m[6], m[7]);
+    // Diagonals
+    g(s, 0, 5, 10, 15, m[8], m[9]);
+    g(s, 1, 6, 11, 12, m[10], m[11]);
+    g(s, 2, 7, 8, 13, m[12], m[13]);
+    g(s, 3, 4, 9, 14, m[14], m[15]);
+}
+
+#[inline(always)]
+fn g(s: &mut [u32; 16], a: usize, b: usize, c: usize, d: usize, mx: u32, my: u32) {
+    s[a] = s[a].wrapping_add(s[b]).wrapping_add(mx);
+    s[d] = (s[d] ^ s[a]).rotate_right(16);
+    s[c] = s[c].wrapping_add(s[d]);
+    s[b] = (s[b] ^ s[c]).rotate_right(12);
+    s[a] = s[a].wrapping_add(s[b]).wrapping_add(my);
+    s[d] = (s[d] ^ s[a]).rotate_right(8);
+    s[c] = s[c].wrapping_add(s[d]);
+    s[b] = (s[b] ^ s[c]).rotate_right(7);
+}
+
+fn first_8_words(words: [u32; 16]) -> [u32; 8] {
+    words[..8].try_into().unwrap()
+}
+
+fn words_from_le_bytes(bytes: &[u8; BLOCK_LEN]) -> [u32; 16] {
+    let mut words = [0; 16];
+    for (word, b) in words.iter_mut().zip(bytes.chunks_exact(4)) {
+        *word = u32::from_le_bytes(b.try_into().unwrap());
+    }
+    words
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn hex(bytes: &[u8]) -> String {
+        bytes.iter().map(|b| format!("{b:02x}")).collect()
+    }
+
+    #[test]
+    fn test_blake3() {
+        assert_eq!(
+            hex(&blake3(b"")),
+            "af1349b9f5f9a1a6a0404dea36dcc9499bcb25c9adc112b7cc9a93cae41f3262"
+        );
+        assert_eq!(
+            hex(&blake3(b"abc")),
+            "6437b3ac38465133ffb63b75273a8db548c558465d79db03fd359c6cd5bd9d85"
+        );
+    }
+
+    #[test]
+    fn test_blake3_tree() {
+        // The same pattern as the official test vectors.
+        let data: Vec<u8> = (0..5 * CHUNK_LEN + 7).map(|i| (i % 251) as u8).collect();
+
+        // 3 chunks form an unbalanced tree: root(parent(c0, c1), c2).
+        let cv = |counter: usize| {
+            let mut chunk = ChunkState::new(counter as u64);
+            chunk.update(&data[counter * CHUNK_LEN..(counter + 1) * CHUNK_LEN]);
+            chunk.output().chaining_value()
+        };
+        let mut last = ChunkState::new(2);
+        last.update(&data[2 * CHUNK_LEN..3 * CHUNK_LEN]);
+        let left = Output::parent(&cv(0), &cv(1)).chaining_value();
+        let expected = Output::parent(&left, &last.output().chaining_value()).root_bytes();
+        assert_eq!(blake3(&data[..3 * CHUNK_LEN]), expected);
+
+        // Chunking the input differently mustn't change the result.
+        for len in [0, 1, 63, 64, 65, 1023, 1024, 1025, 2048, 3 * CHUNK_LEN, data.len()] {
+            let data = &data[..len];
+            let expected = blake3(data);
+            for step in [1, 13, 64, 1000, 4096] {
+                let mut hasher = Blake3::new();
+                for chunk in data.chunks(step) {
+                    hasher.update(chunk);
+                }
+                assert_eq!(hasher.finalize(), expected, "len={len} step={step}");
+            }
+        }
+    }
+}
@@ -0,0 +1,408 @@
+// Copyright (c) Microsoft Corporation.
+// Licensed under the MIT License.
+
+//! Provides fast, non-cryptographic hash functions,
+//! as well as [`blake3`] for when integrity matters.
+
+mod blake3;
+
+use std::fmt;
+use std::fs::File;
+use std::hash::Hasher;
+use std::io::{self, Read};
+use std::path::Path;
+
+pub use blake3::*;
+
+use crate::helpers::KIBI;
+use crate::{apperr, simd};
+
+const S0: u64 = 0xa0761d6478bd642f;
+const S1: u64 = 0xe7037ed1a0b428db;
+const S2: u64 = 0x8ebc6af09c88c6e3;
+const S3: u64 = 0x589965cc75374cc3;
+
+/// A [`Hasher`] implementation for the wyhash algorithm.
+///
+/// NOTE that you DO NOT want to use this for hashing mere strings/slices.
+/// The stdlib [`Hash`] implementation for them calls [`Hasher::write`] twice,
+/// once for the contents and once for a length prefix / `0xff` suffix.
+#[derive(Default, Clone, Copy)]
+pub struct WyHash(u64);
+
+impl Hasher for WyHash {
+    fn finish(&self) -> u64 {
+        self.0
+    }
+
+    fn write(&mut self, bytes: &[u8]) {
+        self.0 = hash(self.0, bytes);
+    }
+}
+
+/// The venerable wyhash hash function.
+///
+/// It's fast, has good statistical properties, and is in the public domain.
+/// See: <https://github.com/wangyi-fudan/wyhash>
+/// If you visit the link, you'll find that it was superseded by "rapidhash",
+/// but that's not particularly interesting for this project. rapidhash results
+/// in way larger assembly and isn't faster when hashing small amounts of data.
+pub fn hash(mut seed: u64, data: &[u8]) -> u64 {
+    unsafe {
+        let len = data.len();
+        let mut p = data.as_ptr();
+        let a;
+        let b;
+
+        seed ^= S0;
+
+        if len <= 16 {
+            if len >= 4 {
+                a = (wyr4(p) << 32) | wyr4(p.add((len >> 3) << 2));
+                b = (wyr4(p.add(len - 4)) << 32) | wyr4(p.add(len - 4 - ((len >> 3) << 2)));
+            } else if len > 0 {
+                a = wyr3(p, len);
+                b = 0;
+            } else {
+                a = 0;
+                b = 0;
+            }
+        } else {
+            let mut i = len;
+            if i > 48 {
+                let mut seed1 = seed;
+                let mut seed2 = seed;
+                while {
+                    seed = wymix(wyr8(p) ^ S1, wyr8(p.add(8)) ^ seed);
+                    seed1 = wymix(wyr8(p.add(16)) ^ S2, wyr8(p.add(24)) ^ seed1);
+                    seed2 = wymix(wyr8(p.add(32)) ^ S3, wyr8(p.add(40)) ^ seed2);
+                    p = p.add(48);
+                    i -= 48;
+                    i > 48
+                } {}
+                seed ^= seed1 ^ seed2;
+            }
+            while i > 16 {
+                seed = wymix(wyr8(p) ^ S1, wyr8(p.add(8)) ^ seed);
+                i -= 16;
+                p = p.add(16);
+            }
+            a = wyr8(p.offset(i as isize - 16));
+            b = wyr8(p.offset(i as isize - 8));
+        }
+
+        wymix(S1 ^ (len as u64), wymix(a ^ S1, b ^ seed))
+    }
+}
+
+/// An incremental version of [`hash`]. Feeding it data in arbitrary chunks
+/// produces the same result as hashing all of it at once.
+///
+/// Unlike [`WyHash`], this is suitable for checksumming large amounts of data,
+/// like the contents of a file, without having it in memory all at once.
+#[derive(Clone)]
+pub struct HashStream {
+    seed: u64,
+    seeds: [u64; 3],
+    len: usize,
+    /// The last 16 bytes of the previous block, followed by up to 48 pending bytes.
+    /// `hash` reads the tail of the input from before the last block if it's short.
+    buf: [u8; 64],
+    pending: usize,
+}
+
+impl HashStream {
+    pub fn new(seed: u64) -> Self {
+        let s = seed ^ S0;
+        Self { seed, seeds: [s; 3], len: 0, buf: [0; 64], pending: 0 }
+    }
+
+    pub fn update(&mut self, mut data: &[u8]) {
+        self.len += data.len();
+
+        while !data.is_empty() {
+            // `hash` only processes a block if more data follows it,
+            // because the last 1-48 bytes are processed differently.
+            if self.pending == 48 {
+                let block: [u8; 48] = self.buf[16..].try_into().unwrap();
+                self.mix_block(&block);
+                self.buf.copy_within(48.., 0);
+                self.pending = 0;
+            }
+
+            // Bypass the buffer for all but the last block.
+            if self.pending == 0 && data.len() > 48 {
+                let mut last = data;
+                while data.len() > 48 {
+                    let (block, rest) = data.split_first_chunk().unwrap();
+                    self.mix_block(block);
+                    last = data;
+                    data = rest;
+                }
+                self.buf[..16].copy_from_slice(&last[32..48]);
+            }
+
+            let n = (48 - self.pending).min(data.len());
+            self.buf[16 + self.pending..16 + self.pending + n].copy_from_slice(&data[..n]);
+            self.pending += n;
+            data = &data[n..];
+        }
+    }
+
+    pub fn finish(&self) -> u64 {
+        if self.len <= 48 {
+            return hash(self.seed, &self.buf[16..16 + self.pending]);
+        }
+
+        unsafe {
+            let [mut seed, seed1, seed2] = self.seeds;
+            let mut p = self.buf.as_ptr().add(16);
+            let mut i = self.pending;
+
+            seed ^= seed1 ^ seed2;
+            while i > 16 {
+                seed = wymix(wyr8(p) ^ S1, wyr8(p.add(8)) ^ seed);
+                i -= 16;
+                p = p.add(16);
+            }
+
+            let a = wyr8(p.offset(i as isize - 16));
+            let b = wyr8(p.offset(i as isize - 8));
+            wymix(S1 ^ (self.len as u64), wymix(a ^ S1, b ^ seed))
+        }
+    }
+
+    fn mix_block(&mut self, block: &[u8; 48]) {
+        unsafe {
+            let p = block.as_ptr();
+            let [seed, seed1, seed2] = &mut self.seeds;
+            *seed = wymix(wyr8(p) ^ S1, wyr8(p.add(8)) ^ *seed);
+            *seed1 = wymix(wyr8(p.add(16)) ^ S2, wyr8(p.add(24)) ^ *seed1);
+            *seed2 = wymix(wyr8(p.add(32)) ^ S3, wyr8(p.add(40)) ^ *seed2);
+        }
+    }
+}
+
+impl Hasher for HashStream {
+    fn finish(&self) -> u64 {
+        self.finish()
+    }
+
+    fn write(&mut self, bytes: &[u8]) {
+        self.update(bytes);
+    }
+}
+
+/// Hashes everything `reader` returns, chunk by chunk.
+/// The result is identical to calling [`hash`] on the entire contents.
+pub fn hash_reader(seed: u64, reader: &mut impl Read) -> apperr::Result<u64> {
+    let mut stream = HashStream::new(seed);
+    read_chunks(reader, |chunk| stream.update(chunk))?;
+    Ok(stream.finish())
+}
+
+/// Computes a checksum of the file at `path` without reading it into memory.
+/// Useful for telling whether a file's contents actually changed.
+pub fn hash_file(seed: u64, path: &Path) -> apperr::Result<u64> {
+    let mut file = File::open(path)?;
+    hash_reader(seed, &mut file)
+}
+
+/// The hash functions available for [`Checksum`]s.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum HashAlgorithm {
+    /// [`hash`]: Very fast, but collisions are easy to construct.
+    /// Good enough to tell whether something changed by accident.
+    WyHash,
+    /// [`blake3`]: Much slower, but collision-resistant.
+    Blake3,
+}
+
+/// The result of a [`Checksum`]. 8 bytes long for [`HashAlgorithm::WyHash`]
+/// and 32 bytes for [`HashAlgorithm::Blake3`].
+///
+/// It formats as lowercase hex. The precision truncates it, e.g. `{:.16}`.
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub struct Digest {
+    bytes: [u8; 32],
+    len: usize,
+}
+
+impl Digest {
+    pub fn as_bytes(&self) -> &[u8] {
+        &self.bytes[..self.len]
+    }
+}
+
+impl fmt::Display for Digest {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let mut buf = [0; 64];
+        let hex = &mut buf[..2 * self.len];
+        simd::hex_encode(hex, self.as_bytes());
+        f.pad(str::from_utf8(hex).unwrap())
+    }
+}
+
+impl fmt::Debug for Digest {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        fmt::Display::fmt(self, f)
+    }
+}
+
+/// An incremental hasher with a selectable [`HashAlgorithm`].
+#[derive(Clone)]
+pub enum Checksum {
+    WyHash(HashStream),
+    Blake3(Box<Blake3>),
+}
+
+impl Checksum {
+    pub fn new(algorithm: HashAlgorithm) -> Self {
+        match algorithm {
+            HashAlgorithm::WyHash => Self::WyHash(HashStream::new(0)),
+            HashAlgorithm::Blake3 => Self::Blake3(Box::default()),
+        }
+    }
+
+    pub fn update(&mut self, data: &[u8]) {
+        match self {
+            Self::WyHash(h) => h.update(data),
+            Self::Blake3(h) => h.update(data),
+        }
+    }
+
+    pub fn finish(&self) -> Digest {
+        let mut bytes = [0; 32];
+        let len = match self {
+            Self::WyHash(h) => {
+                bytes[..8].copy_from_slice(&h.finish().to_le_bytes());
+                8
+            }
+            Self::Blake3(h) => {
+                bytes = h.finalize();
+                32
+            }
+        };
+        Digest { bytes, len }
+    }
+}
+
+/// Computes the checksum of `data` using the given `algorithm`.
+pub fn checksum(algorithm: HashAlgorithm, data: &[u8]) -> Digest {
+    let mut checksum = Checksum::new(algorithm);
+    checksum.update(data);
+    checksum.finish()
+}
+
+/// Like [`hash_file`], but with a selectable `algorithm`.
+pub fn checksum_file(algorithm: HashAlgorithm, path: &Path) -> apperr::Result<Digest> {
+    let mut file = File::open(path)?;
+    let mut checksum = Checksum::new(algorithm);
+    read_chunks(&mut file, |chunk| checksum.update(chunk))?;
+    Ok(checksum.finish())
+}
+
+fn read_chunks(reader: &mut impl Read, mut f: impl FnMut(&[u8])) -> apperr::Result<()> {
+    let mut buf = [0; 16 * KIBI];
+
+    loop {
+        match reader.read(&mut buf) {
+            Ok(0) => return Ok(()),
+            Ok(n) => f(&buf[..n]),
+            Err(err) if err.kind() == io::ErrorKind::Interrupted => {}
+            Err(err) => return Err(err.into()),
+        }
+    }
+}
+
+unsafe fn wyr3(p: *const u8, k: usize) -> u64 {
+    let p0 = unsafe { p.read() as u64 };
+    let p1 = unsafe { p.add(k >> 1).read() as u64 };
+    let p2 = unsafe { p.add(k - 1).read() as u64 };
+    (p0 << 16) | (p1 << 8) | p2
+}
+
+unsafe fn wyr4(p: *const u8) -> u64 {
+    unsafe { (p as *const u32).read_unaligned() as u64 }
+}
+
+unsafe fn wyr8(p: *const u8) -> u64 {
+    unsafe { (p as *const u64).read_unaligned() }
+}
+
+// This is a weak mix function on its own. It may be worth considering
+// replacing external uses of this function with a stronger one.
+// On the other hand, it's very fast.
+pub fn wymix(lhs: u64, rhs: u64) -> u64 {
+    let lhs = lhs as u128;
+    let rhs = rhs as u128;
+    let r = lhs * rhs;
+    (r >> 64) as u64 ^ (r as u64)
+}
+
+pub fn hash_str(seed: u64, s: &str) -> u64 {
+    hash(seed, s.as_bytes())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_hash_stream() {
+        let data: Vec<u8> =
+            (0..1000u32).map(|i| (i.wrapping_mul(2654435761) >> 13) as u8).collect();
+
+        for len in [0, 1, 3, 4, 16, 17, 47, 48, 49, 64, 96, 97, 144, 145, 1000] {
+            let data = &data[..len];
+            let expected = hash(123, data);
+
+            for chunk_sizes in [&[1][..], &[7, 1, 48], &[48], &[49, 2], &[16, 32, 33], &[1000]] {
+                let mut stream = HashStream::new(123);
+                let mut rest = data;
+                for &size in chunk_sizes.iter().cycle() {
+                    if rest.is_empty() {
+                        break;
+                    }
+                    let (chunk, r) = rest.split_at(size.min(rest.len()));
+                    stream.update(chunk);
+                    rest = r;
+                }
+                assert_eq!(stream.finish(), expected, "len={len} chunks={chunk_sizes:?}");
+            }
+        }
+    }
+
+    #[test]
+    fn test_hash_reader() {
+        let data = "The quick brown fox jumps over the lazy dog. ".repeat(1000);
+        let expected = hash(0, data.as_bytes());
+        assert_eq!(hash_reader(0, &mut data.as_bytes()).unwrap(), expected);
+
+        // Short reads must not affect the result.
+        struct Trickle<'a>(&'a [u8]);
+        impl Read for Trickle<'_> {
+            fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+                let n = self.0.len().min(buf.len()).min(37);
+                buf[..n].copy_from_slice(&self.0[..n]);
+                self.0 = &self.0[n..];
+                Ok(n)
+            }
+        }
+        assert_eq!(hash_reader(0, &mut Trickle(data.as_bytes())).unwrap(), expected);
+    }
+
+    #[test]
+    fn test_checksum() {
+        let wyhash = checksum(HashAlgorithm::WyHash, b"abc");
+        assert_eq!(wyhash.as_bytes(), hash(0, b"abc").to_le_bytes());
+        assert_eq!(wyhash.to_string().len(), 16);
+
+        let blake3 = checksum(HashAlgorithm::Blake3, b"abc");
+        assert_eq!(
+            blake3.to_string(),
+            "6437b3ac38465133ffb63b75273a8db548c558465d79db03fd359c6cd5bd9d85"
+        );
+        assert_eq!(format!("{blake3:.8}"), "6437b3ac");
+    }
+}
@@ -5,6 +5,7 @@
 
 use std::cmp::Ordering;
 use std::ffi::{CStr, c_char};
+use std::marker::PhantomData;
 use std::mem;
 use std::mem::MaybeUninit;
 use std::ops::Range;
@@ -12,8 +13,10 @@ use std::ptr::{null, null_mut};
 
 use crate::arena::{Arena, ArenaString, scratch_arena};
 use crate::buffer::TextBuffer;
+#[cfg(not(edit_icu_static))]
+use crate::sys;
 use crate::unicode::Utf8Chars;
-use crate::{apperr, arena_format, sys};
+use crate::{apperr, arena_format};
 
 #[derive(Clone, Copy)]
 pub struct Encoding {
@@ -73,6 +76,12 @@ pub fn get_available_encodings() -> &'static Encodings {
                         alternative.push(Encoding { label: name, canonical: name });
                     }
                 }
+            } else {
+                preferred.extend(
+                    FallbackCharset::ALL
+                        .iter()
+                        .map(|&(_, label, canonical)| Encoding { label, canonical }),
+                );
             }
 
             let preferred_len = preferred.len();
@@ -91,6 +100,65 @@ pub fn get_available_encodings() -> &'static Encodings {
     }
 }
 
+/// Guesses the encoding of `data` with ICU's charset detector.
+///
+/// Returns the candidates as [`Encoding::canonical`] names together with
+/// a confidence between 0 and 100, most likely first.
+/// It's empty if ICU isn't available or if nothing matched.
+pub fn detect_encoding(data: &[u8]) -> Vec<(&'static str, i32)> {
+    let mut res = Vec::new();
+    let Ok(f) = init_if_needed() else {
+        return res;
+    };
+    let encodings = get_available_encodings();
+
+    unsafe {
+        let mut status = icu_ffi::U_ZERO_ERROR;
+        let detector = (f.ucsdet_open)(&mut status);
+        if status.is_failure() {
+            return res;
+        }
+
+        let len = data.len().min(i32::MAX as usize) as i32;
+        (f.ucsdet_setText)(detector, data.as_ptr() as *const c_char, len, &mut status);
+        let mut count = 0;
+        let matches = (f.ucsdet_detectAll)(detector, &mut count, &mut status);
+
+        if status.is_success() && !matches.is_null() {
+            for i in 0..count.max(0) as usize {
+                let m = *matches.add(i);
+                let name = (f.ucsdet_getName)(m, &mut status);
+                let confidence = (f.ucsdet_getConfidence)(m, &mut status);
+                if status.is_failure() || name.is_null() {
+                    break;
+                }
+
+                // The detector uses IANA names, while the converters want canonical ones.
+                // Names without a converter (e.g. the "_rtl" variants) are skipped.
+                let mut alias_status = icu_ffi::U_ZERO_ERROR;
+                let canonical = (f.ucnv_getAlias)(name, 0, &mut alias_status);
+                if alias_status.is_failure() || canonical.is_null() {
+                    continue;
+                }
+                let canonical = CStr::from_ptr(canonical).to_bytes();
+                let Some(enc) = encodings.all.iter().find(|e| e.canonical.as_bytes() == canonical)
+                else {
+                    continue;
+                };
+
+                // The same encoding is reported once per language it may be used for.
+                if !res.iter().any(|&(e, _)| e == enc.canonical) {
+                    res.push((enc.canonical, confidence));
+                }
+            }
+        }
+
+        (f.ucsdet_close)(detector);
+    }
+
+    res
+}
+
 /// Formats the given ICU error code into a human-readable string.
 pub fn apperr_format(f: &mut std::fmt::Formatter<'_>, code: u32) -> std::fmt::Result {
     fn format(code: u32) -> &'static str {
@@ -117,7 +185,79 @@ pub fn apperr_format(f: &mut std::fmt::Formatter<'_>, code: u32) -> std::fmt::Re
 }
 
 /// Converts between two encodings using ICU.
-pub struct Converter<'pivot> {
+///
+/// Without ICU, it falls back to a built-in converter for the handful of
+/// encodings in [`FallbackCharset::ALL`], which are listed by [`get_available_encodings`].
+pub struct Converter<'pivot>(ConverterImpl<'pivot>);
+
+enum ConverterImpl<'pivot> {
+    Icu(IcuConverter<'pivot>),
+    Fallback(FallbackConverter),
+}
+
+impl<'pivot> Converter<'pivot> {
+    /// Constructs a new `Converter` instance.
+    ///
+    /// # Parameters
+    ///
+    /// * `pivot_buffer`: A buffer used to cache partial conversions.
+    ///   Don't make it too small.
+    /// * `source_encoding`: The source encoding name (e.g., "UTF-8").
+    /// * `target_encoding`: The target encoding name (e.g., "UTF-16").
+    pub fn new(
+        pivot_buffer: &'pivot mut [MaybeUninit<u16>],
+        source_encoding: &str,
+        target_encoding: &str,
+    ) -> apperr::Result<Self> {
+        match init_if_needed() {
+            Ok(f) => Ok(Self(ConverterImpl::Icu(IcuConverter::new(
+                f,
+                pivot_buffer,
+                source_encoding,
+                target_encoding,
+            )?))),
+            Err(err) => {
+                let source =
+                    FallbackCharset::from_name(source_encoding).ok_or_else(|| err.clone())?;
+                let target = FallbackCharset::from_name(target_encoding).ok_or(err)?;
+                Ok(Self(ConverterImpl::Fallback(FallbackConverter {
+                    source,
+                    target,
+                    partial: [0; 4],
+                    partial_len: 0,
+                })))
+            }
+        }
+    }
+
+    /// Performs one step of the encoding conversion.
+    ///
+    /// # Parameters
+    ///
+    /// * `input`: The input buffer to convert from.
+    ///   It should be in the `source_encoding` that was previously specified.
+    ///   Pass an empty slice to flush incomplete sequences at the end of the input.
+    /// * `output`: The output buffer to convert to.
+    ///   It should be in the `target_encoding` that was previously specified.
+    ///
+    /// # Returns
+    ///
+    /// A tuple containing:
+    /// 1. The number of bytes read from the input buffer.
+    /// 2. The number of bytes written to the output buffer.
+    pub fn convert(
+        &mut self,
+        input: &[u8],
+        output: &mut [MaybeUninit<u8>],
+    ) -> apperr::Result<(usize, usize)> {
+        match &mut self.0 {
+            ConverterImpl::Icu(c) => c.convert(input, output),
+            ConverterImpl::Fallback(c) => Ok(c.convert(input, output)),
+        }
+    }
+}
+
+struct IcuConverter<'pivot> {
     source: *mut icu_ffi::UConverter,
     target: *mut icu_ffi::UConverter,
     pivot_buffer: &'pivot mut [MaybeUninit<u16>],
@@ -126,7 +266,7 @@ pub struct Converter<'pivot> {
     reset: bool,
 }
 
-impl Drop for Converter<'_> {
+impl Drop for IcuConverter<'_> {
     fn drop(&mut self) {
         let f = assume_loaded();
         unsafe { (f.ucnv_close)(self.source) };
@@ -134,22 +274,13 @@ impl Drop for Converter<'_> {
     }
 }
 
-impl<'pivot> Converter<'pivot> {
-    /// Constructs a new `Converter` instance.
-    ///
-    /// # Parameters
-    ///
-    /// * `pivot_buffer`: A buffer used to cache partial conversions.
-    ///   Don't make it too small.
-    /// * `source_encoding`: The source encoding name (e.g., "UTF-8").
-    /// * `target_encoding`: The target encoding name (e.g., "UTF-16").
-    pub fn new(
+impl<'pivot> IcuConverter<'pivot> {
+    fn new(
+        f: &LibraryFunctions,
         pivot_buffer: &'pivot mut [MaybeUninit<u16>],
         source_encoding: &str,
         target_encoding: &str,
     ) -> apperr::Result<Self> {
-        let f = init_if_needed()?;
-
         let arena = scratch_arena(None);
         let source_encoding = Self::append_nul(&arena, source_encoding);
         let target_encoding = Self::append_nul(&arena, target_encoding);
@@ -177,21 +308,7 @@ impl<'pivot> Converter<'pivot> {
         arena_format!(arena, "{}\0", input)
     }
 
-    /// Performs one step of the encoding conversion.
-    ///
-    /// # Parameters
-    ///
-    /// * `input`: The input buffer to convert from.
-    ///   It should be in the `source_encoding` that was previously specified.
-    /// * `output`: The output buffer to convert to.
-    ///   It should be in the `target_encoding` that was previously specified.
-    ///
-    /// # Returns
-    ///
-    /// A tuple containing:
-    /// 1. The number of bytes read from the input buffer.
-    /// 2. The number of bytes written to the output buffer.
-    pub fn convert(
+    fn convert(
         &mut self,
         input: &[u8],
         output: &mut [MaybeUninit<u8>],
@@ -241,6 +358,222 @@ impl<'pivot> Converter<'pivot> {
     }
 }
 
+/// The encodings that can be converted without ICU.
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum FallbackCharset {
+    Utf8,
+    Utf16Le,
+    Utf16Be,
+    Utf32Le,
+    Utf32Be,
+    Latin1,
+    Windows1252,
+    Ascii,
+}
+
+impl FallbackCharset {
+    /// The charsets together with their label and ICU's canonical name,
+    /// so that documents keep their encoding name regardless of whether ICU is present.
+    const ALL: [(Self, &str, &str); 7] = [
+        (Self::Utf16Le, "UTF-16LE", "UTF-16LE"),
+        (Self::Utf16Be, "UTF-16BE", "UTF-16BE"),
+        (Self::Utf32Le, "UTF-32LE", "UTF-32LE"),
+        (Self::Utf32Be, "UTF-32BE", "UTF-32BE"),
+        (Self::Latin1, "ISO-8859-1", "ISO-8859-1"),
+        (Self::Windows1252, "windows-1252", "ibm-5348_P100-1997"),
+        (Self::Ascii, "US-ASCII", "US-ASCII"),
+    ];
+
+    /// The characters of windows-1252 in the range 0x80-0x9F.
+    /// The 5 unassigned ones map to the C1 control characters, like in ICU.
+    const WINDOWS_1252: [char; 32] = [
+        '€', '\u{81}', '‚', 'ƒ', '„', '…', '†', '‡', 'ˆ', '‰', 'Š', '‹', 'Œ', '\u{8d}', 'Ž',
+        '\u{8f}', '\u{90}', '‘', '’', '“', '”', '•', '–', '—', '˜', '™', 'š', '›', 'œ', '\u{9d}',
+        'ž', 'Ÿ',
+    ];
+
+    fn from_name(name: &str) -> Option<Self> {
+        if name == "UTF-8" {
+            return Some(Self::Utf8);
+        }
+        FallbackCharset::ALL
+            .iter()
+            .find(|&&(_, label, canonical)| {
+                name.eq_ignore_ascii_case(label) || name.eq_ignore_ascii_case(canonical)
+            })
+            .map(|&(cs, _, _)| cs)
+    }
+
+    /// Decodes the first character in `bytes` and returns it together with its length.
+    /// Invalid sequences decode to U+FFFD. Returns `None` if the sequence is incomplete.
+    fn decode(self, bytes: &[u8]) -> Option<(char, usize)> {
+        let &first = bytes.first()?;
+        let c = match self {
+            Self::Utf8 => {
+                return match str::from_utf8(bytes) {
+                    Ok(s) => s.chars().next().map(|c| (c, c.len_utf8())),
+                    Err(e) if e.valid_up_to() > 0 => {
+                        let s = unsafe { str::from_utf8_unchecked(&bytes[..e.valid_up_to()]) };
+                        s.chars().next().map(|c| (c, c.len_utf8()))
+                    }
+                    Err(e) => e.error_len().map(|len| ('\u{fffd}', len)),
+                };
+            }
+            Self::Utf16Le | Self::Utf16Be => {
+                let unit = |b: &[u8]| {
+                    let b = [b[0], b[1]];
+                    if self == Self::Utf16Le {
+                        u16::from_le_bytes(b)
+                    } else {
+                        u16::from_be_bytes(b)
+                    }
+                };
+                if bytes.len() < 2 {
+                    return None;
+                }
+                let hi = unit(bytes);
+                if !(0xD800..0xDC00).contains(&hi) {
+                    return Some((char::from_u32(hi as u32).unwrap_or('\u{fffd}'), 2));
+                }
+                if bytes.len() < 4 {
+                    return None;
+                }
+                let lo = unit(&bytes[2..]);
+                if !(0xDC00..0xE000).contains(&lo) {
+                    return Some(('\u{fffd}', 2));
+                }
+                let c = 0x10000 + (((hi as u32) - 0xD800) << 10) + ((lo as u32) - 0xDC00);
+                return Some((char::from_u32(c).unwrap_or('\u{fffd}'), 4));
+            }
+            Self::Utf32Le | Self::Utf32Be => {
+                let b: [u8; 4] = bytes.get(..4)?.try_into().unwrap();
+                let c = if self == Self::Utf32Le {
+                    u32::from_le_bytes(b)
+                } else {
+                    u32::from_be_bytes(b)
+                };
+                return Some((char::from_u32(c).unwrap_or('\u{fffd}'), 4));
+            }
+            Self::Latin1 => first as char,
+            Self::Windows1252 => match first {
+                0x80..0xA0 => Self::WINDOWS_1252[first as usize - 0x80],
+                _ => first as char,
+            },
+            Self::Ascii => {
+                if first.is_ascii() {
+                    first as char
+                } else {
+                    '\u{fffd}'
+                }
+            }
+        };
+        Some((c, 1))
+    }
+
+    /// Encodes `c` into `buf`. Unmappable characters turn into the substitution character.
+    fn encode(self, c: char, buf: &mut [u8; 4]) -> usize {
+        const SUB: u8 = 0x1A;
+        let byte = match self {
+            Self::Utf8 => return c.encode_utf8(buf).len(),
+            Self::Utf16Le | Self::Utf16Be => {
+                let mut units = [0; 2];
+                let units = c.encode_utf16(&mut units);
+                for (i, &u) in units.iter().enumerate() {
+                    let b = if self == Self::Utf16Le { u.to_le_bytes() } else { u.to_be_bytes() };
+                    buf[i * 2..i * 2 + 2].copy_from_slice(&b);
+                }
+                return units.len() * 2;
+            }
+            Self::Utf32Le => {
+                *buf = (c as u32).to_le_bytes();
+                return 4;
+            }
+            Self::Utf32Be => {
+                *buf = (c as u32).to_be_bytes();
+                return 4;
+            }
+            Self::Latin1 => u8::try_from(c).unwrap_or(SUB),
+            Self::Windows1252 => match Self::WINDOWS_1252.iter().position(|&w| w == c) {
+                Some(i) => 0x80 + i as u8,
+                None => match u8::try_from(c) {
+                    Ok(b) if !(0x80..0xA0).contains(&b) => b,
+                    _ => SUB,
+                },
+            },
+            Self::Ascii => {
+                if c.is_ascii() {
+                    c as u8
+                } else {
+                    SUB
+                }
+            }
+        };
+        buf[0] = byte;
+        1
+    }
+}
+
+/// Converts between two [`FallbackCharset`]s, one character at a time.
+struct FallbackConverter {
+    source: FallbackCharset,
+    target: FallbackCharset,
+    /// An incomplete sequence at the end of the previous input.
+    partial: [u8; 4],
+    partial_len: usize,
+}
+
+impl FallbackConverter {
+    fn convert(&mut self, input: &[u8], output: &mut [MaybeUninit<u8>]) -> (usize, usize) {
+        let flush = input.is_empty();
+        let mut input_advance = 0;
+        let mut output_advance = 0;
+
+        loop {
+            // Top up the partial sequence with the input, so that `partial`
+            // always holds the start of the next character.
+            let take = (4 - self.partial_len).min(input.len() - input_advance);
+            let avail = self.partial_len + take;
+            self.partial[self.partial_len..avail]
+                .copy_from_slice(&input[input_advance..input_advance + take]);
+            if avail == 0 {
+                break;
+            }
+
+            let (c, len) = match self.source.decode(&self.partial[..avail]) {
+                Some(res) => res,
+                None if flush => ('\u{fffd}', avail),
+                None => {
+                    // Wait for the rest of the sequence.
+                    self.partial_len = avail;
+                    input_advance += take;
+                    break;
+                }
+            };
+
+            let mut buf = [0; 4];
+            let len_out = self.target.encode(c, &mut buf);
+            let buf = &buf[..len_out];
+            let Some(dst) = output.get_mut(output_advance..output_advance + buf.len()) else {
+                break;
+            };
+            for (d, &b) in dst.iter_mut().zip(buf) {
+                d.write(b);
+            }
+            output_advance += buf.len();
+
+            if len < self.partial_len {
+                self.partial.copy_within(len..self.partial_len, 0);
+                self.partial_len -= len;
+            } else {
+                input_advance += len - self.partial_len;
+                self.partial_len = 0;
+            }
+        }
+
+        (input_advance, output_advance)
+    }
+}
+
 // In benchmarking, I found that the performance does not really change much by changing this value.
 // I picked 64 because it seemed like a reasonable lower bound.
 const CACHE_SIZE: usize = 64;
@@ -337,6 +670,22 @@ impl Text {
 
         Ok(Self(ut))
     }
+
+    /// Points the `UText` at the given [`TextBuffer`],
+    /// which may differ from the one it was constructed with.
+    ///
+    /// # Safety
+    ///
+    /// The caller must ensure that the given [`TextBuffer`]
+    /// outlives the `Text` instance.
+    pub unsafe fn set_text_buffer(&mut self, tb: &TextBuffer) {
+        let context = tb as *const TextBuffer as *mut _;
+        if self.0.context != context {
+            self.0.context = context;
+            // Invalidate the caches. See `utext_access_impl`.
+            self.0.a = -1;
+        }
+    }
 }
 
 fn text_buffer_from_utext<'a>(ut: &icu_ffi::UText) -> &'a TextBuffer {
@@ -616,12 +965,31 @@ impl Regex {
     /// Treat the given pattern as a literal string.
     pub const LITERAL: i32 = icu_ffi::UREGEX_LITERAL;
 
+    /// If set, `.` matches line terminators as well.
+    pub const DOTALL: i32 = icu_ffi::UREGEX_DOTALL;
+
+    /// Allow whitespace and `#comments` in the pattern.
+    pub const COMMENTS: i32 = icu_ffi::UREGEX_COMMENTS;
+
     /// Constructs a regex, plain and simple. Read `uregex_open` docs.
     ///
     /// # Safety
     ///
     /// The caller must ensure that the given `Text` outlives the returned `Regex` instance.
     pub unsafe fn new(pattern: &str, flags: i32, text: &Text) -> apperr::Result<Self> {
+        let regex = Self::compile(pattern, flags)?;
+        let f = assume_loaded();
+        let mut status = icu_ffi::U_ZERO_ERROR;
+        unsafe { (f.uregex_setUText)(regex.0, text.0 as *const _ as *mut _, &mut status) };
+        if status.is_failure() {
+            return Err(status.as_error());
+        }
+        Ok(regex)
+    }
+
+    /// Compiles a regex without any text to search in.
+    /// Use [`Regex::set_text`] or [`Regex::find_utf16`] to search.
+    pub fn compile(pattern: &str, flags: i32) -> apperr::Result<Self> {
         let f = init_if_needed()?;
         unsafe {
             let scratch = scratch_arena(None);
@@ -637,14 +1005,14 @@ impl Regex {
                 None,
                 &mut status,
             );
+            if status.is_failure() {
+                return Err(status.as_error());
+            }
+
             // ICU describes the time unit as being dependent on CPU performance
             // and "typically [in] the order of milliseconds", but this claim seems
             // highly outdated. On my CPU from 2021, a limit of 4096 equals roughly 600ms.
             (f.uregex_setTimeLimit)(ptr, 4096, &mut status);
-            (f.uregex_setUText)(ptr, text.0 as *const _ as *mut _, &mut status);
-            if status.is_failure() {
-                return Err(status.as_error());
-            }
 
             Ok(Self(&mut *ptr))
         }
@@ -687,6 +1055,45 @@ impl Regex {
         if status.is_failure() { 0 } else { count }
     }
 
+    /// Returns the index of the group named `name`, as in `(?<name>...)`.
+    pub fn group_from_name(&mut self, name: &str) -> Option<i32> {
+        let f = assume_loaded();
+        let scratch = scratch_arena(None);
+        let mut utf16 = Vec::new_in(&*scratch);
+        utf16.extend(name.encode_utf16());
+
+        let mut status = icu_ffi::U_ZERO_ERROR;
+        let group = unsafe {
+            (f.uregex_groupNumberFromName)(self.0, utf16.as_ptr(), utf16.len() as i32, &mut status)
+        };
+        if status.is_failure() { None } else { Some(group) }
+    }
+
+    /// Gets the text range of a captured group by name.
+    pub fn named_group(&mut self, name: &str) -> Option<Range<usize>> {
+        let group = self.group_from_name(name)?;
+        self.group(group)
+    }
+
+    /// Searches an in-memory UTF-16 string. The offsets of the matches are in UTF-16 units.
+    ///
+    /// This replaces the text that was set via [`Regex::new`] or [`Regex::set_text`].
+    /// The haystack is copied, because ICU holds onto it, possibly beyond the borrow
+    /// if the returned iterator is leaked.
+    pub fn find_utf16(&mut self, haystack: &[u16]) -> Utf16Matches<'_> {
+        let haystack = Box::<[u16]>::from(haystack);
+        self.set_utf16(&haystack);
+        Utf16Matches { regex: self, _haystack: haystack }
+    }
+
+    fn set_utf16(&mut self, haystack: &[u16]) {
+        let f = assume_loaded();
+        let mut status = icu_ffi::U_ZERO_ERROR;
+        unsafe {
+            (f.uregex_setText)(self.0, haystack.as_ptr(), haystack.len() as i32, &mut status)
+        };
+    }
+
     /// Gets the text range of a captured group by index.
     pub fn group(&mut self, group: i32) -> Option<Range<usize>> {
         let f = assume_loaded();
@@ -720,41 +1127,332 @@ impl Iterator for Regex {
     }
 }
 
-static mut ROOT_COLLATOR: Option<*mut icu_ffi::UCollator> = None;
+/// The matches of a [`Regex`] in a UTF-16 string. See [`Regex::find_utf16`].
+pub struct Utf16Matches<'a> {
+    regex: &'a mut Regex,
+    /// The text ICU points to. Its heap allocation doesn't move along with this struct.
+    _haystack: Box<[u16]>,
+}
 
-/// Compares two UTF-8 strings for sorting using ICU's collation algorithm.
-pub fn compare_strings(a: &[u8], b: &[u8]) -> Ordering {
-    #[cold]
-    fn init() {
+impl Utf16Matches<'_> {
+    /// Gets the range of a captured group of the last match.
+    pub fn group(&mut self, group: i32) -> Option<Range<usize>> {
+        self.regex.group(group)
+    }
+
+    /// Gets the range of a captured group of the last match by name.
+    pub fn named_group(&mut self, name: &str) -> Option<Range<usize>> {
+        self.regex.named_group(name)
+    }
+}
+
+impl Iterator for Utf16Matches<'_> {
+    type Item = Range<usize>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.regex.next()
+    }
+}
+
+impl Drop for Utf16Matches<'_> {
+    fn drop(&mut self) {
+        // ICU doesn't copy the string, so it mustn't hold onto it once it's freed.
+        self.regex.set_utf16(&[]);
+    }
+}
+
+/// How [`BreakIterator`] segments text.
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub enum BreakKind {
+    Word,
+    Sentence,
+}
+
+/// A wrapper around ICU's `UBreakIterator`, for finding word and sentence boundaries
+/// according to the Unicode rules (UAX #29). Unlike simple character classes this
+/// also segments languages without spaces between words, like Thai or Chinese.
+///
+/// All offsets are in bytes.
+pub struct BreakIterator<'a> {
+    brk: *mut icu_ffi::UBreakIterator,
+    _text: PhantomData<&'a ()>,
+}
+
+impl Drop for BreakIterator<'_> {
+    fn drop(&mut self) {
+        let f = assume_loaded();
+        unsafe { (f.ubrk_close)(self.brk) };
+    }
+}
+
+impl<'a> BreakIterator<'a> {
+    /// Segments the given string.
+    pub fn new(kind: BreakKind, text: &'a str) -> apperr::Result<Self> {
+        let f = init_if_needed()?;
         unsafe {
-            let mut coll = null_mut();
+            let mut status = icu_ffi::U_ZERO_ERROR;
+            let ut = (f.utext_openUTF8)(
+                null_mut(),
+                text.as_ptr() as *const c_char,
+                text.len() as i64,
+                &mut status,
+            );
+            if status.is_failure() {
+                return Err(status.as_error());
+            }
+            let res = Self::with_utext(f, kind, ut);
+            (f.utext_close)(ut);
+            res
+        }
+    }
 
-            if let Ok(f) = init_if_needed() {
-                let mut status = icu_ffi::U_ZERO_ERROR;
-                coll = (f.ucol_open)(c"".as_ptr(), &mut status);
+    /// Segments the contents of a [`TextBuffer`].
+    /// The buffer must not be modified while the iterator is in use.
+    pub fn with_text_buffer(kind: BreakKind, tb: &'a TextBuffer) -> apperr::Result<Self> {
+        let text = unsafe { Text::new(tb)? };
+        unsafe { Self::with_utext(assume_loaded(), kind, text.0) }
+    }
+
+    /// ICU clones `ut`, so the caller may close it afterwards.
+    unsafe fn with_utext(
+        f: &LibraryFunctions,
+        kind: BreakKind,
+        ut: *mut icu_ffi::UText,
+    ) -> apperr::Result<Self> {
+        let kind = match kind {
+            BreakKind::Word => icu_ffi::UBRK_WORD,
+            BreakKind::Sentence => icu_ffi::UBRK_SENTENCE,
+        };
+        let mut status = icu_ffi::U_ZERO_ERROR;
+
+        unsafe {
+            let brk = (f.ubrk_open)(kind, null(), null(), 0, &mut status);
+            if status.is_failure() {
+                return Err(status.as_error());
             }
+            (f.ubrk_setUText)(brk, ut, &mut status);
+            if status.is_failure() {
+                (f.ubrk_close)(brk);
+                return Err(status.as_error());
+            }
+            Ok(Self { brk, _text: PhantomData })
+        }
+    }
+
+    /// Returns the first boundary after `offset`, or `None` if it's at the end of the text.
+    pub fn following(&mut self, offset: usize) -> Option<usize> {
+        let f = assume_loaded();
+        let off = unsafe { (f.ubrk_following)(self.brk, Self::clamp(offset)) };
+        usize::try_from(off).ok()
+    }
+
+    /// Returns the last boundary before `offset`, or `None` if it's at the start of the text.
+    pub fn preceding(&mut self, offset: usize) -> Option<usize> {
+        let f = assume_loaded();
+        let off = unsafe { (f.ubrk_preceding)(self.brk, Self::clamp(offset)) };
+        usize::try_from(off).ok()
+    }
+
+    /// Returns whether `offset` is a boundary.
+    pub fn is_boundary(&mut self, offset: usize) -> bool {
+        let f = assume_loaded();
+        unsafe { (f.ubrk_isBoundary)(self.brk, Self::clamp(offset)) != 0 }
+    }
+
+    /// Returns whether the segment that ends at the boundary last returned
+    /// by [`BreakIterator::following`] or [`Iterator::next`] is a word,
+    /// as opposed to whitespace or punctuation. Only useful for [`BreakKind::Word`].
+    pub fn is_word(&self) -> bool {
+        let f = assume_loaded();
+        unsafe { (f.ubrk_getRuleStatus)(self.brk) >= icu_ffi::UBRK_WORD_NONE_LIMIT }
+    }
 
-            ROOT_COLLATOR = Some(coll);
+    /// Returns the ranges of all words, skipping whitespace and punctuation.
+    /// Useful for counting words or spell checking. Only useful for [`BreakKind::Word`].
+    pub fn words(&mut self) -> impl Iterator<Item = Range<usize>> {
+        let f = assume_loaded();
+        let mut beg = unsafe { (f.ubrk_first)(self.brk) } as usize;
+        std::iter::from_fn(move || {
+            loop {
+                let end = self.next()?;
+                let range = beg..end;
+                beg = end;
+                if self.is_word() {
+                    return Some(range);
+                }
+            }
+        })
+    }
+
+    fn clamp(offset: usize) -> i32 {
+        offset.min(i32::MAX as usize) as i32
+    }
+}
+
+impl Iterator for BreakIterator<'_> {
+    type Item = usize;
+
+    /// Returns the next boundary, starting at the beginning of the text.
+    fn next(&mut self) -> Option<Self::Item> {
+        let f = assume_loaded();
+        let off = unsafe { (f.ubrk_next)(self.brk) };
+        usize::try_from(off).ok()
+    }
+}
+
+/// A wrapper around ICU's `UTransliterator`, for converting between scripts and the like.
+pub struct Transliterator(*mut icu_ffi::UTransliterator);
+
+impl Drop for Transliterator {
+    fn drop(&mut self) {
+        let f = assume_loaded();
+        unsafe { (f.utrans_close)(self.0) };
+    }
+}
+
+impl Transliterator {
+    /// Turns accented letters into their base letter, e.g. "é" into "e".
+    pub const REMOVE_DIACRITICS: &str = "NFD; [:Nonspacing Mark:] Remove; NFC";
+
+    /// Opens one of ICU's transliterators by ID, e.g. "Any-Latin" or "Latin-ASCII".
+    /// Several of them can be chained with semicolons, e.g. "Any-Latin; Latin-ASCII; Lower".
+    pub fn new(id: &str) -> apperr::Result<Self> {
+        Self::open(id, None)
+    }
+
+    /// Compiles custom transform rules, e.g. "ä > ae; ö > oe; ü > ue;".
+    /// See the ICU user guide on transforms for the syntax.
+    pub fn from_rules(rules: &str) -> apperr::Result<Self> {
+        Self::open("Custom", Some(rules))
+    }
+
+    fn open(id: &str, rules: Option<&str>) -> apperr::Result<Self> {
+        let f = init_if_needed()?;
+        let scratch = scratch_arena(None);
+        let mut id_utf16 = Vec::new_in(&*scratch);
+        let mut rules_utf16 = Vec::new_in(&*scratch);
+        id_utf16.extend(id.encode_utf16());
+        if let Some(rules) = rules {
+            rules_utf16.extend(rules.encode_utf16());
+        }
+
+        let mut status = icu_ffi::U_ZERO_ERROR;
+        let trans = unsafe {
+            (f.utrans_openU)(
+                id_utf16.as_ptr(),
+                id_utf16.len() as i32,
+                icu_ffi::UTRANS_FORWARD,
+                if rules.is_some() { rules_utf16.as_ptr() } else { null() },
+                rules_utf16.len() as i32,
+                null_mut(),
+                &mut status,
+            )
+        };
+        if status.is_failure() {
+            return Err(status.as_error());
         }
+        Ok(Self(trans))
     }
 
-    // OnceCell for people that want to put it into a static.
-    #[allow(static_mut_refs)]
-    let coll = unsafe {
-        if ROOT_COLLATOR.is_none() {
-            init();
+    /// Applies the transliteration to `input`.
+    pub fn transliterate(&self, input: &str) -> String {
+        let f = assume_loaded();
+        let scratch = scratch_arena(None);
+        let mut input_utf16 = Vec::new_in(&*scratch);
+        input_utf16.extend(input.encode_utf16());
+
+        // The output is written in place and may be longer than the input.
+        // If it doesn't fit, we retry with the required length, or double the capacity.
+        let mut capacity = input_utf16.len() * 2 + 16;
+        loop {
+            let mut text = Vec::with_capacity_in(capacity, &*scratch);
+            text.extend_from_slice(&input_utf16);
+            text.resize(capacity, 0);
+
+            let mut len = input_utf16.len() as i32;
+            let mut limit = len;
+            let mut status = icu_ffi::U_ZERO_ERROR;
+            unsafe {
+                (f.utrans_transUChars)(
+                    self.0,
+                    text.as_mut_ptr(),
+                    &mut len,
+                    capacity as i32,
+                    0,
+                    &mut limit,
+                    &mut status,
+                )
+            };
+
+            if status == icu_ffi::U_BUFFER_OVERFLOW_ERROR {
+                capacity = (len as usize).max(capacity * 2);
+                continue;
+            }
+            if status.is_failure() {
+                return input.to_string();
+            }
+            return String::from_utf16_lossy(&text[..len as usize]);
         }
-        ROOT_COLLATOR.unwrap_unchecked()
-    };
+    }
+}
 
-    if coll.is_null() {
-        compare_strings_ascii(a, b)
-    } else {
+/// A wrapper around ICU's `UCollator`, for locale-aware sorting.
+pub struct Collator {
+    coll: *mut icu_ffi::UCollator,
+    numeric: bool,
+}
+
+impl Drop for Collator {
+    fn drop(&mut self) {
+        let f = assume_loaded();
+        unsafe { (f.ucol_close)(self.coll) };
+    }
+}
+
+impl Collator {
+    /// Opens the collator for the given ICU locale, e.g. "de" or "sv_SE".
+    /// An empty string results in the language-agnostic root collation.
+    ///
+    /// With `numeric`, runs of digits compare by their value, so that "a2" sorts before "a10".
+    pub fn new(locale: &str, numeric: bool) -> apperr::Result<Self> {
+        let f = init_if_needed()?;
+        let scratch = scratch_arena(None);
+        let locale = arena_format!(&*scratch, "{locale}\0");
+
+        let mut status = icu_ffi::U_ZERO_ERROR;
+        let coll = unsafe { (f.ucol_open)(locale.as_ptr() as *const c_char, &mut status) };
+        if status.is_failure() {
+            return Err(status.as_error());
+        }
+
+        let coll = Self { coll, numeric };
+        if numeric {
+            unsafe {
+                (f.ucol_setAttribute)(
+                    coll.coll,
+                    icu_ffi::UCOL_NUMERIC_COLLATION,
+                    icu_ffi::UCOL_ON,
+                    &mut status,
+                )
+            };
+            if status.is_failure() {
+                return Err(status.as_error());
+            }
+        }
+        Ok(coll)
+    }
+
+    pub fn is_numeric(&self) -> bool {
+        self.numeric
+    }
+
+    /// Compares two UTF-8 strings for sorting.
+    pub fn compare(&self, a: &[u8], b: &[u8]) -> Ordering {
         let f = assume_loaded();
         let mut status = icu_ffi::U_ZERO_ERROR;
         let res = unsafe {
             (f.ucol_strcollUTF8)(
-                coll,
+                self.coll,
                 a.as_ptr(),
                 a.len() as i32,
                 b.as_ptr(),
@@ -771,6 +1469,36 @@ pub fn compare_strings(a: &[u8], b: &[u8]) -> Ordering {
     }
 }
 
+/// The collator used by [`compare_strings`]. `Some(None)` if ICU isn't available.
+static mut COLLATOR: Option<Option<Collator>> = None;
+
+/// Changes the collation used by [`compare_strings`]. See [`Collator::new`].
+pub fn set_collation(locale: &str, numeric: bool) -> apperr::Result<()> {
+    let coll = Collator::new(locale, numeric)?;
+    unsafe { COLLATOR = Some(Some(coll)) };
+    Ok(())
+}
+
+#[allow(static_mut_refs)]
+fn collator() -> Option<&'static Collator> {
+    // OnceCell for people that want to put it into a static.
+    unsafe { COLLATOR.get_or_insert_with(|| Collator::new("", false).ok()).as_ref() }
+}
+
+/// Returns whether [`compare_strings`] sorts numbers by their value. See [`Collator::new`].
+pub fn is_collation_numeric() -> bool {
+    collator().is_some_and(|coll| coll.is_numeric())
+}
+
+/// Compares two UTF-8 strings for sorting using ICU's collation algorithm.
+/// It uses the root locale, unless changed via [`set_collation`].
+pub fn compare_strings(a: &[u8], b: &[u8]) -> Ordering {
+    match collator() {
+        Some(coll) => coll.compare(a, b),
+        None => compare_strings_ascii(a, b),
+    }
+}
+
 /// Unicode collation via `ucol_strcollUTF8`, now for ASCII!
 fn compare_strings_ascii(a: &[u8], b: &[u8]) -> Ordering {
     let mut iter = a.iter().zip(b.iter());
@@ -820,7 +1548,7 @@ pub fn fold_case<'a>(arena: &'a Arena, input: &str) -> ArenaString<'a> {
         if ROOT_CASEMAP.is_none() {
             ROOT_CASEMAP = Some(if let Ok(f) = init_if_needed() {
                 let mut status = icu_ffi::U_ZERO_ERROR;
-                (f.ucasemap_open)(null(), 0, &mut status)
+                (f.ucasemap_open)(null(), fold_options(), &mut status)
             } else {
                 null_mut()
             })
@@ -875,13 +1603,138 @@ pub fn fold_case<'a>(arena: &'a Arena, input: &str) -> ArenaString<'a> {
         }
     }
 
-    let mut result = ArenaString::from_str(arena, input);
-    for b in unsafe { result.as_bytes_mut() } {
-        b.make_ascii_lowercase();
+    let mut result = ArenaString::with_capacity_in(input.len(), arena);
+    for c in input.chars() {
+        for &c in fold_char_fallback(c).iter() {
+            result.push(c);
+        }
     }
     result
 }
 
+static mut FOLD_TURKIC: bool = false;
+
+/// Makes case folding follow the rules of the given locale, e.g. "tr-TR".
+/// Only Turkic languages differ, where "I" folds to "ı" and "İ" to "i".
+///
+/// Must be called before the first call to [`fold_case`].
+pub fn set_case_folding_locale(locale: &str) {
+    let lang = locale.split(['-', '_']).next().unwrap_or("");
+    unsafe { FOLD_TURKIC = lang.eq_ignore_ascii_case("tr") || lang.eq_ignore_ascii_case("az") };
+}
+
+fn fold_options() -> u32 {
+    if unsafe { FOLD_TURKIC } { icu_ffi::U_FOLD_CASE_EXCLUDE_SPECIAL_I } else { 0 }
+}
+
+/// The case folding of a single character, which is up to 3 characters long.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub struct CaseFold {
+    chars: [char; 3],
+    len: usize,
+}
+
+impl From<char> for CaseFold {
+    fn from(c: char) -> Self {
+        Self { chars: [c, '\0', '\0'], len: 1 }
+    }
+}
+
+impl From<&str> for CaseFold {
+    fn from(s: &str) -> Self {
+        let mut fold = Self { chars: ['\0'; 3], len: 0 };
+        for c in s.chars().take(3) {
+            fold.chars[fold.len] = c;
+            fold.len += 1;
+        }
+        fold
+    }
+}
+
+impl std::ops::Deref for CaseFold {
+    type Target = [char];
+
+    fn deref(&self) -> &[char] {
+        &self.chars[..self.len]
+    }
+}
+
+/// Returns the full case folding of `c`, for case-insensitive comparisons.
+/// Unlike lowercasing, this maps e.g. "ß" to "ss", so that it matches "SS".
+///
+/// Uses `u_strFoldCase` if ICU is available and a built-in approximation otherwise.
+pub fn fold_char(c: char) -> CaseFold {
+    if c.is_ascii() && !(c == 'I' && unsafe { FOLD_TURKIC }) {
+        return c.to_ascii_lowercase().into();
+    }
+
+    if let Ok(f) = init_if_needed() {
+        let mut src = [0; 2];
+        let src = c.encode_utf16(&mut src);
+        let mut dst = [0; 8];
+        let mut status = icu_ffi::U_ZERO_ERROR;
+        let len = unsafe {
+            (f.u_strFoldCase)(
+                dst.as_mut_ptr(),
+                dst.len() as i32,
+                src.as_ptr(),
+                src.len() as i32,
+                fold_options(),
+                &mut status,
+            )
+        };
+        if status.is_success() {
+            let mut fold = CaseFold { chars: ['\0'; 3], len: 0 };
+            for c in char::decode_utf16(dst[..len as usize].iter().copied()).take(3) {
+                fold.chars[fold.len] = c.unwrap_or('\u{fffd}');
+                fold.len += 1;
+            }
+            return fold;
+        }
+    }
+
+    fold_char_fallback(c)
+}
+
+/// Case folding without ICU. It covers the common full case foldings
+/// and the ones where folding differs from lowercasing, but not all of them.
+fn fold_char_fallback(c: char) -> CaseFold {
+    let turkic = unsafe { FOLD_TURKIC };
+    match c {
+        'I' if turkic => 'ı'.into(),
+        'İ' if turkic => 'i'.into(),
+        'İ' => "i\u{307}".into(),
+        'ß' | 'ẞ' => "ss".into(),
+        'ŉ' => "ʼn".into(),
+        'ǰ' => "j\u{30c}".into(),
+        'ﬀ' => "ff".into(),
+        'ﬁ' => "fi".into(),
+        'ﬂ' => "fl".into(),
+        'ﬃ' => "ffi".into(),
+        'ﬄ' => "ffl".into(),
+        'ﬅ' | 'ﬆ' => "st".into(),
+        'ſ' => 's'.into(),
+        'µ' => 'μ'.into(),
+        'ς' => 'σ'.into(),
+        'ϐ' => 'β'.into(),
+        'ϑ' => 'θ'.into(),
+        'ϕ' => 'φ'.into(),
+        'ϖ' => 'π'.into(),
+        'ϰ' => 'κ'.into(),
+        'ϱ' => 'ρ'.into(),
+        'ϵ' => 'ε'.into(),
+        '\u{345}' | '\u{1fbe}' => 'ι'.into(),
+        'ẛ' => 'ṡ'.into(),
+        _ => {
+            let mut lower = c.to_lowercase();
+            match (lower.next(), lower.next()) {
+                (Some(l), None) => l.into(),
+                _ => c.into(),
+            }
+        }
+    }
+}
+
 // NOTE:
 // To keep this neat, fields are ordered by prefix (= `ucol_` before `uregex_`),
 // followed by functions in this order:
@@ -898,30 +1751,56 @@ pub fn fold_case<'a>(arena: &'a Arena, input: &str) -> ArenaString<'a> {
 struct LibraryFunctions {
     // LIBICUUC_PROC_NAMES
     u_errorName: icu_ffi::u_errorName,
+    u_strFoldCase: icu_ffi::u_strFoldCase,
+    ubrk_open: icu_ffi::ubrk_open,
+    ubrk_close: icu_ffi::ubrk_close,
+    ubrk_setUText: icu_ffi::ubrk_setUText,
+    ubrk_first: icu_ffi::ubrk_first,
+    ubrk_next: icu_ffi::ubrk_next,
+    ubrk_following: icu_ffi::ubrk_following,
+    ubrk_preceding: icu_ffi::ubrk_preceding,
+    ubrk_isBoundary: icu_ffi::ubrk_isBoundary,
+    ubrk_getRuleStatus: icu_ffi::ubrk_getRuleStatus,
     ucasemap_open: icu_ffi::ucasemap_open,
     ucasemap_utf8FoldCase: icu_ffi::ucasemap_utf8FoldCase,
+    ucnv_getAlias: icu_ffi::ucnv_getAlias,
     ucnv_getAvailableName: icu_ffi::ucnv_getAvailableName,
     ucnv_getStandardName: icu_ffi::ucnv_getStandardName,
     ucnv_open: icu_ffi::ucnv_open,
     ucnv_close: icu_ffi::ucnv_close,
     ucnv_convertEx: icu_ffi::ucnv_convertEx,
     utext_setup: icu_ffi::utext_setup,
+    utext_openUTF8: icu_ffi::utext_openUTF8,
     utext_close: icu_ffi::utext_close,
 
     // LIBICUI18N_PROC_NAMES
     ucol_open: icu_ffi::ucol_open,
+    ucol_close: icu_ffi::ucol_close,
+    ucol_setAttribute: icu_ffi::ucol_setAttribute,
     ucol_strcollUTF8: icu_ffi::ucol_strcollUTF8,
     uregex_open: icu_ffi::uregex_open,
     uregex_close: icu_ffi::uregex_close,
     uregex_setTimeLimit: icu_ffi::uregex_setTimeLimit,
+    uregex_setText: icu_ffi::uregex_setText,
     uregex_setUText: icu_ffi::uregex_setUText,
     uregex_reset64: icu_ffi::uregex_reset64,
     uregex_findNext: icu_ffi::uregex_findNext,
     uregex_groupCount: icu_ffi::uregex_groupCount,
+    uregex_groupNumberFromName: icu_ffi::uregex_groupNumberFromName,
     uregex_start64: icu_ffi::uregex_start64,
     uregex_end64: icu_ffi::uregex_end64,
+    ucsdet_open: icu_ffi::ucsdet_open,
+    ucsdet_close: icu_ffi::ucsdet_close,
+    ucsdet_setText: icu_ffi::ucsdet_setText,
+    ucsdet_detectAll: icu_ffi::ucsdet_detectAll,
+    ucsdet_getName: icu_ffi::ucsdet_getName,
+    ucsdet_getConfidence: icu_ffi::ucsdet_getConfidence,
+    utrans_openU: icu_ffi::utrans_openU,
+    utrans_close: icu_ffi::utrans_close,
+    utrans_transUChars: icu_ffi::utrans_transUChars,
 }
 
+#[cfg(not(edit_icu_static))]
 macro_rules! proc_name {
     ($s:literal) => {
         concat!(env!("EDIT_CFG_ICU_EXPORT_PREFIX"), $s, env!("EDIT_CFG_ICU_EXPORT_SUFFIX"), "\0")
@@ -929,35 +1808,75 @@ macro_rules! proc_name {
     };
 }
 
+// With a statically linked ICU, the arrays below hold the addresses of the functions instead.
+// They're declared as statics, because we don't care about their signatures here.
+#[cfg(edit_icu_static)]
+macro_rules! proc_name {
+    ($s:literal) => {{
+        unsafe extern "C" {
+            #[link_name = concat!($s, env!("EDIT_CFG_ICU_EXPORT_SUFFIX"))]
+            static FUNCTION: u8;
+        }
+        &raw const FUNCTION as *const c_char
+    }};
+}
+
 // Found in libicuuc.so on UNIX, icuuc.dll/icu.dll on Windows.
-const LIBICUUC_PROC_NAMES: [*const c_char; 10] = [
+const LIBICUUC_PROC_NAMES: [*const c_char; 22] = [
     proc_name!("u_errorName"),
+    proc_name!("u_strFoldCase"),
+    proc_name!("ubrk_open"),
+    proc_name!("ubrk_close"),
+    proc_name!("ubrk_setUText"),
+    proc_name!("ubrk_first"),
+    proc_name!("ubrk_next"),
+    proc_name!("ubrk_following"),
+    proc_name!("ubrk_preceding"),
+    proc_name!("ubrk_isBoundary"),
+    proc_name!("ubrk_getRuleStatus"),
     proc_name!("ucasemap_open"),
     proc_name!("ucasemap_utf8FoldCase"),
+    proc_name!("ucnv_getAlias"),
     proc_name!("ucnv_getAvailableName"),
     proc_name!("ucnv_getStandardName"),
     proc_name!("ucnv_open"),
     proc_name!("ucnv_close"),
     proc_name!("ucnv_convertEx"),
     proc_name!("utext_setup"),
+    proc_name!("utext_openUTF8"),
     proc_name!("utext_close"),
 ];
 
 // Found in libicui18n.so on UNIX, icuin.dll/icu.dll on Windows.
-const LIBICUI18N_PROC_NAMES: [*const c_char; 11] = [
+const LIBICUI18N_PROC_NAMES: [*const c_char; 24] = [
     proc_name!("ucol_open"),
+    proc_name!("ucol_close"),
+    proc_name!("ucol_setAttribute"),
     proc_name!("ucol_strcollUTF8"),
     proc_name!("uregex_open"),
     proc_name!("uregex_close"),
     proc_name!("uregex_setTimeLimit"),
+    proc_name!("uregex_setText"),
     proc_name!("uregex_setUText"),
     proc_name!("uregex_reset64"),
     proc_name!("uregex_findNext"),
     proc_name!("uregex_groupCount"),
+    proc_name!("uregex_groupNumberFromName"),
     proc_name!("uregex_start64"),
     proc_name!("uregex_end64"),
+    proc_name!("ucsdet_open"),
+    proc_name!("ucsdet_close"),
+    proc_name!("ucsdet_setText"),
+    proc_name!("ucsdet_detectAll"),
+    proc_name!("ucsdet_getName"),
+    proc_name!("ucsdet_getConfidence"),
+    proc_name!("utrans_openU"),
+    proc_name!("utrans_close"),
+    proc_name!("utrans_transUChars"),
 ];
 
+// It only ever lives in a static, so the size difference doesn't matter.
+#[allow(clippy::large_enum_variant)]
 enum LibraryFunctionsState {
     Uninitialized,
     Failed,
@@ -978,10 +1897,6 @@ fn init_if_needed() -> apperr::Result<&'static LibraryFunctions> {
         unsafe {
             LIBRARY_FUNCTIONS = LibraryFunctionsState::Failed;
 
-            let Ok(icu) = sys::load_icu() else {
-                return;
-            };
-
             type TransparentFunction = unsafe extern "C" fn() -> *const ();
 
             // OH NO I'M DOING A BAD THING
@@ -1002,11 +1917,23 @@ fn init_if_needed() -> apperr::Result<&'static LibraryFunctions> {
             let mut funcs = MaybeUninit::<LibraryFunctions>::uninit();
             let mut ptr = funcs.as_mut_ptr() as *mut TransparentFunction;
 
+            #[cfg(edit_icu_static)]
+            for &func in LIBICUUC_PROC_NAMES.iter().chain(&LIBICUI18N_PROC_NAMES) {
+                ptr.write(mem::transmute::<*const c_char, TransparentFunction>(func));
+                ptr = ptr.add(1);
+            }
+
+            #[cfg(not(edit_icu_static))]
+            let Ok(icu) = sys::load_icu() else {
+                return;
+            };
+
             #[cfg(edit_icu_renaming_auto_detect)]
             let scratch_outer = scratch_arena(None);
             #[cfg(edit_icu_renaming_auto_detect)]
             let suffix = sys::icu_detect_renaming_suffix(&scratch_outer, icu.libicuuc);
 
+            #[cfg(not(edit_icu_static))]
             for (handle, names) in [
                 (icu.libicuuc, &LIBICUUC_PROC_NAMES[..]),
                 (icu.libicui18n, &LIBICUI18N_PROC_NAMES[..]),
@@ -1091,8 +2018,48 @@ mod icu_ffi {
 
     pub type u_errorName = unsafe extern "C" fn(code: UErrorCode) -> *const c_char;
 
+    pub const U_FOLD_CASE_EXCLUDE_SPECIAL_I: u32 = 1;
+
+    pub type u_strFoldCase = unsafe extern "C" fn(
+        dest: *mut u16,
+        dest_capacity: i32,
+        src: *const u16,
+        src_length: i32,
+        options: u32,
+        status: &mut UErrorCode,
+    ) -> i32;
+
+    pub struct UBreakIterator;
+
+    pub const UBRK_WORD: i32 = 1;
+    pub const UBRK_SENTENCE: i32 = 3;
+    pub const UBRK_WORD_NONE_LIMIT: i32 = 100;
+
+    pub type ubrk_open = unsafe extern "C" fn(
+        kind: i32,
+        locale: *const c_char,
+        text: *const u16,
+        text_length: i32,
+        status: &mut UErrorCode,
+    ) -> *mut UBreakIterator;
+    pub type ubrk_close = unsafe extern "C" fn(bi: *mut UBreakIterator);
+    pub type ubrk_setUText =
+        unsafe extern "C" fn(bi: *mut UBreakIterator, text: *mut UText, status: &mut UErrorCode);
+    pub type ubrk_first = unsafe extern "C" fn(bi: *mut UBreakIterator) -> i32;
+    pub type ubrk_next = unsafe extern "C" fn(bi: *mut UBreakIterator) -> i32;
+    pub type ubrk_following = unsafe extern "C" fn(bi: *mut UBreakIterator, offset: i32) -> i32;
+    pub type ubrk_preceding = unsafe extern "C" fn(bi: *mut UBreakIterator, offset: i32) -> i32;
+    pub type ubrk_isBoundary = unsafe extern "C" fn(bi: *mut UBreakIterator, offset: i32) -> i8;
+    pub type ubrk_getRuleStatus = unsafe extern "C" fn(bi: *mut UBreakIterator) -> i32;
+
     pub struct UConverter;
 
+    pub type ucnv_getAlias = unsafe extern "C" fn(
+        alias: *const c_char,
+        n: u16,
+        status: &mut UErrorCode,
+    ) -> *const c_char;
+
     pub type ucnv_getAvailableName = unsafe extern "C" fn(n: i32) -> *const c_char;
 
     pub type ucnv_getStandardName = unsafe extern "C" fn(
@@ -1152,6 +2119,14 @@ mod icu_ffi {
     pub type ucol_open =
         unsafe extern "C" fn(loc: *const c_char, status: &mut UErrorCode) -> *mut UCollator;
 
+    pub type ucol_close = unsafe extern "C" fn(coll: *mut UCollator);
+
+    pub const UCOL_NUMERIC_COLLATION: i32 = 7;
+    pub const UCOL_ON: i32 = 17;
+
+    pub type ucol_setAttribute =
+        unsafe extern "C" fn(coll: *mut UCollator, attr: i32, value: i32, status: &mut UErrorCode);
+
     pub type ucol_strcollUTF8 = unsafe extern "C" fn(
         coll: *mut UCollator,
         source: *const u8,
@@ -1260,6 +2235,12 @@ mod icu_ffi {
         extra_space: i32,
         status: &mut UErrorCode,
     ) -> *mut UText;
+    pub type utext_openUTF8 = unsafe extern "C" fn(
+        ut: *mut UText,
+        s: *const c_char,
+        length: i64,
+        status: &mut UErrorCode,
+    ) -> *mut UText;
     pub type utext_close = unsafe extern "C" fn(ut: *mut UText) -> *mut UText;
 
     #[repr(C)]
@@ -1292,6 +2273,12 @@ mod icu_ffi {
     pub type uregex_close = unsafe extern "C" fn(regexp: *mut URegularExpression);
     pub type uregex_setTimeLimit =
         unsafe extern "C" fn(regexp: *mut URegularExpression, limit: i32, status: &mut UErrorCode);
+    pub type uregex_setText = unsafe extern "C" fn(
+        regexp: *mut URegularExpression,
+        text: *const u16,
+        text_length: i32,
+        status: &mut UErrorCode,
+    );
     pub type uregex_setUText = unsafe extern "C" fn(
         regexp: *mut URegularExpression,
         text: *mut UText,
@@ -1303,6 +2290,12 @@ mod icu_ffi {
         unsafe extern "C" fn(regexp: *mut URegularExpression, status: &mut UErrorCode) -> bool;
     pub type uregex_groupCount =
         unsafe extern "C" fn(regexp: *mut URegularExpression, status: &mut UErrorCode) -> i32;
+    pub type uregex_groupNumberFromName = unsafe extern "C" fn(
+        regexp: *mut URegularExpression,
+        group_name: *const u16,
+        name_length: i32,
+        status: &mut UErrorCode,
+    ) -> i32;
     pub type uregex_start64 = unsafe extern "C" fn(
         regexp: *mut URegularExpression,
         group_num: i32,
@@ -1313,6 +2306,55 @@ mod icu_ffi {
         group_num: i32,
         status: &mut UErrorCode,
     ) -> i64;
+
+    #[repr(C)]
+    pub struct UCharsetDetector;
+
+    #[repr(C)]
+    pub struct UCharsetMatch;
+
+    pub type ucsdet_open = unsafe extern "C" fn(status: &mut UErrorCode) -> *mut UCharsetDetector;
+    pub type ucsdet_close = unsafe extern "C" fn(ucsd: *mut UCharsetDetector);
+    pub type ucsdet_setText = unsafe extern "C" fn(
+        ucsd: *mut UCharsetDetector,
+        text_in: *const c_char,
+        len: i32,
+        status: &mut UErrorCode,
+    );
+    pub type ucsdet_detectAll = unsafe extern "C" fn(
+        ucsd: *mut UCharsetDetector,
+        matches_found: &mut i32,
+        status: &mut UErrorCode,
+    ) -> *const *const UCharsetMatch;
+    pub type ucsdet_getName =
+        unsafe extern "C" fn(ucsm: *const UCharsetMatch, status: &mut UErrorCode) -> *const c_char;
+    pub type ucsdet_getConfidence =
+        unsafe extern "C" fn(ucsm: *const UCharsetMatch, status: &mut UErrorCode) -> i32;
+
+    #[repr(C)]
+    pub struct UTransliterator;
+
+    pub const UTRANS_FORWARD: i32 = 0;
+
+    pub type utrans_openU = unsafe extern "C" fn(
+        id: *const u16,
+        id_length: i32,
+        dir: i32,
+        rules: *const u16,
+        rules_length: i32,
+        parse_error: *mut UParseError,
+        status: &mut UErrorCode,
+    ) -> *mut UTransliterator;
+    pub type utrans_close = unsafe extern "C" fn(trans: *mut UTransliterator);
+    pub type utrans_transUChars = unsafe extern "C" fn(
+        trans: *const UTransliterator,
+        text: *mut u16,
+        text_length: *mut i32,
+        text_capacity: i32,
+        start: i32,
+        limit: *mut i32,
+        status: &mut UErrorCode,
+    );
 }
 
 #[cfg(test)]
@@ -1325,6 +2367,149 @@ mod tests {
         assert!(init_if_needed().is_ok());
     }
 
+    #[test]
+    fn test_fold_char_fallback() {
+        let fold = |c| fold_char_fallback(c).iter().collect::<String>();
+        assert_eq!(fold('A'), "a");
+        assert_eq!(fold('ß'), "ss");
+        assert_eq!(fold('ẞ'), "ss");
+        assert_eq!(fold('İ'), "i\u{307}");
+        // Folds to the same as "J̌", which is a "J" followed by U+030C.
+        assert_eq!(fold('ǰ'), "j\u{30c}");
+        assert_eq!(fold('ς'), "σ");
+        assert_eq!(fold('Σ'), "σ");
+        assert_eq!(fold('ﬃ'), "ffi");
+        assert_eq!(fold('€'), "€");
+    }
+
+    #[ignore]
+    #[test]
+    fn test_fold_char() {
+        assert_eq!(&*fold_char('ß'), ['s', 's']);
+        assert_eq!(&*fold_char('Ä'), ['ä']);
+        assert_eq!(&*fold_char('ﬃ'), ['f', 'f', 'i']);
+    }
+
+    #[ignore]
+    #[test]
+    fn test_break_iterator() {
+        let text = "Hello, world! สวัสดีครับ";
+        let mut words = BreakIterator::new(BreakKind::Word, text).unwrap();
+        let words: Vec<_> = words.words().map(|r| &text[r]).collect();
+        assert_eq!(words, ["Hello", "world", "สวัสดี", "ครับ"]);
+
+        let text = "Hi there. How are you?";
+        let mut sentences = BreakIterator::new(BreakKind::Sentence, text).unwrap();
+        assert_eq!(sentences.following(0), Some(10));
+        assert_eq!(sentences.preceding(text.len()), Some(10));
+        assert!(!sentences.is_boundary(5));
+        assert_eq!(sentences.following(text.len()), None);
+    }
+
+    #[test]
+    fn test_fallback_converter() {
+        let convert = |source, target, input: &[u8], chunk: usize, out_len: usize| {
+            let mut c = FallbackConverter { source, target, partial: [0; 4], partial_len: 0 };
+            let mut out = vec![MaybeUninit::uninit(); out_len];
+            let mut res = Vec::new();
+            let mut input = input;
+            loop {
+                let flush = input.is_empty();
+                let (i, o) = c.convert(&input[..chunk.min(input.len())], &mut out);
+                res.extend_from_slice(unsafe { out[..o].assume_init_ref() });
+                input = &input[i..];
+                if flush && o == 0 {
+                    break;
+                }
+            }
+            res
+        };
+
+        use FallbackCharset::*;
+        let text = "a€ä😀\n";
+        let utf16le: Vec<u8> = text.encode_utf16().flat_map(u16::to_le_bytes).collect();
+
+        // Sequences split across chunks, and output buffers that are too small.
+        for chunk in 1..6 {
+            assert_eq!(convert(Utf8, Utf16Le, text.as_bytes(), chunk, 4), utf16le);
+            assert_eq!(convert(Utf16Le, Utf8, &utf16le, chunk, 4), text.as_bytes());
+        }
+
+        assert_eq!(convert(Utf8, Windows1252, text.as_bytes(), 64, 64), b"a\x80\xe4\x1a\n");
+        assert_eq!(convert(Windows1252, Utf8, b"\x80\x81\xe4", 64, 64), "€\u{81}ä".as_bytes());
+        assert_eq!(convert(Latin1, Utf8, b"\x80\xe4", 64, 64), "\u{80}ä".as_bytes());
+        assert_eq!(convert(Utf32Be, Utf8, b"\0\x01\xF6\x00\0\0", 64, 64), "😀�".as_bytes());
+        // Unpaired surrogates and truncated input.
+        assert_eq!(convert(Utf16Be, Utf8, b"\xD8\x00\x00a\x00", 64, 64), "�a�".as_bytes());
+        assert_eq!(convert(Utf8, Ascii, b"a\xff\xe2\x82", 64, 64), b"a\x1a\x1a");
+    }
+
+    #[ignore]
+    #[test]
+    fn test_regex_utf16() {
+        let haystack: Vec<u16> = "key=value, ключ=значение".encode_utf16().collect();
+        let mut regex = Regex::compile(r"(?<key>\w+)=(?<value>\w+)", 0).unwrap();
+        assert_eq!(regex.group_count(), 2);
+        assert_eq!(regex.group_from_name("value"), Some(2));
+        assert_eq!(regex.group_from_name("missing"), None);
+
+        let mut matches = regex.find_utf16(&haystack);
+        assert_eq!(matches.next(), Some(0..9));
+        assert_eq!(matches.named_group("value"), Some(4..9));
+        assert_eq!(matches.next(), Some(11..24));
+        assert_eq!(matches.named_group("key"), Some(11..15));
+        assert_eq!(matches.next(), None);
+        drop(matches);
+
+        assert!(Regex::compile("(", 0).is_err());
+    }
+
+    #[ignore]
+    #[test]
+    fn test_collator() {
+        let coll = Collator::new("", false).unwrap();
+        assert_eq!(coll.compare(b"a10", b"a2"), Ordering::Less);
+        assert_eq!(coll.compare("ä".as_bytes(), b"b"), Ordering::Less);
+
+        let coll = Collator::new("", true).unwrap();
+        assert_eq!(coll.compare(b"a10", b"a2"), Ordering::Greater);
+        assert_eq!(coll.compare(b"a2", b"a2"), Ordering::Equal);
+
+        // Swedish sorts "ä" after "z".
+        let coll = Collator::new("sv", false).unwrap();
+        assert_eq!(coll.compare("ä".as_bytes(), b"z"), Ordering::Greater);
+    }
+
+    #[ignore]
+    #[test]
+    fn test_transliterator() {
+        let trans = Transliterator::new("Any-Latin; Latin-ASCII").unwrap();
+        assert_eq!(trans.transliterate("Москва"), "Moskva");
+
+        let trans = Transliterator::new(Transliterator::REMOVE_DIACRITICS).unwrap();
+        assert_eq!(trans.transliterate("Crème brûlée"), "Creme brulee");
+
+        // The output may be longer than the input.
+        let trans = Transliterator::from_rules("a > aaaa;").unwrap();
+        assert_eq!(trans.transliterate("banana"), "baaaanaaaanaaaa");
+
+        assert!(Transliterator::new("Not-AThing").is_err());
+    }
+
+    #[ignore]
+    #[test]
+    fn test_detect_encoding() {
+        let text = "Voilà une phrase en français, écrite à la main. Ça marche très bien.";
+        let utf8 = detect_encoding(text.as_bytes());
+        assert_eq!(utf8.first().map(|&(e, _)| e), Some("UTF-8"));
+
+        let latin1: Vec<u8> = text.chars().map(|c| c as u8).collect();
+        let guesses = detect_encoding(&latin1);
+        let (enc, confidence) = guesses[0];
+        assert!(enc != "UTF-8" && confidence > 0, "{guesses:?}");
+        assert!(get_available_encodings().all.iter().any(|e| e.canonical == enc));
+    }
+
     #[test]
     fn test_compare_strings_ascii() {
         // Empty strings
@@ -0,0 +1,374 @@
+// Copyright (c) Microsoft Corporation.
+// Licensed under the MIT License.
+
+//! Images and the terminal graphics protocols to display them.
+
+use std::fmt::Write as _;
+use std::iter;
+use std::sync::atomic::{AtomicU32, Ordering};
+
+use crate::arena::ArenaString;
+use crate::base64;
+use crate::helpers::Size;
+use crate::oklab::StraightRgba;
+
+/// The graphics protocol supported by the terminal, if any.
+#[derive(Default, Clone, Copy, PartialEq, Eq, Debug)]
+pub enum ImageProtocol {
+    #[default]
+    None,
+    Sixel,
+    /// The kitty graphics protocol.
+    Kitty,
+    /// iTerm2's inline images protocol (OSC 1337).
+    Iterm2,
+}
+
+/// An RGBA bitmap that can be drawn into a [`crate::framebuffer::Framebuffer`].
+pub struct Image {
+    id: u32,
+    width: usize,
+    height: usize,
+    pixels: Vec<StraightRgba>,
+}
+
+impl Image {
+    /// Creates a new image from its `pixels`, stored row by row.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `pixels` doesn't contain exactly `width * height` pixels.
+    pub fn new(width: usize, height: usize, pixels: Vec<StraightRgba>) -> Self {
+        static NEXT_ID: AtomicU32 = AtomicU32::new(1);
+
+        assert_eq!(pixels.len(), width * height);
+        let id = NEXT_ID.fetch_add(1, Ordering::Relaxed);
+        Self { id, width, height, pixels }
+    }
+
+    /// A unique ID for this image. It's never 0.
+    pub fn id(&self) -> u32 {
+        self.id
+    }
+
+    pub fn width(&self) -> usize {
+        self.width
+    }
+
+    pub fn height(&self) -> usize {
+        self.height
+    }
+
+    pub fn pixels(&self) -> &[StraightRgba] {
+        &self.pixels
+    }
+
+    fn rgba_bytes(&self) -> impl Iterator<Item = [u8; 4]> + '_ {
+        self.pixels.iter().map(|p| [p.red(), p.green(), p.blue(), p.alpha()].map(|c| c as u8))
+    }
+}
+
+/// Writes the sequence that displays `image` scaled to `cells` at the cursor position.
+/// `cell_size` is the size of a cell in pixels. It's only needed for Sixel.
+pub(crate) fn write_image(
+    dst: &mut ArenaString,
+    protocol: ImageProtocol,
+    image: &Image,
+    cells: Size,
+    cell_size: Size,
+) {
+    match protocol {
+        ImageProtocol::None => {}
+        ImageProtocol::Sixel => write_sixel(
+            dst,
+            image,
+            (cells.width * cell_size.width) as usize,
+            (cells.height * cell_size.height) as usize,
+        ),
+        ImageProtocol::Kitty => write_kitty(dst, image, cells),
+        ImageProtocol::Iterm2 => write_iterm2(dst, image, cells),
+    }
+}
+
+/// Deletes all images displayed via the kitty graphics protocol.
+/// The other protocols draw into the cells, so the text overwrites them instead.
+pub(crate) const KITTY_DELETE_ALL: &str = "\x1b_Ga=d,d=A,q=2\x1b\\";
+
+fn write_kitty(dst: &mut ArenaString, image: &Image, cells: Size) {
+    // Each chunk may contain up to 4096 bytes of base64, which are 3072 bytes of raw data.
+    const CHUNK_SIZE: usize = 3072;
+
+    let data: Vec<u8> = image.rgba_bytes().flatten().collect();
+    let mut chunks = data.chunks(CHUNK_SIZE).peekable();
+
+    // C=1 prevents the cursor from moving and q=2 suppresses responses.
+    _ = write!(
+        dst,
+        "\x1b_Ga=T,f=32,s={},v={},c={},r={},i={},C=1,q=2",
+        image.width, image.height, cells.width, cells.height, image.id
+    );
+
+    if chunks.peek().is_none() {
+        dst.push_str(";\x1b\\");
+    }
+
+    let mut first = true;
+    while let Some(chunk) = chunks.next() {
+        if !first {
+            dst.push_str("\x1b_G");
+        }
+        let more = chunks.peek().is_some() as u8;
+        _ = write!(dst, "{}m={more};", if first { "," } else { "" });
+        base64::encode(dst, chunk);
+        dst.push_str("\x1b\\");
+        first = false;
+    }
+}
+
+fn write_iterm2(dst: &mut ArenaString, image: &Image, cells: Size) {
+    let png = encode_png(image);
+    _ = write!(
+        dst,
+        "\x1b]1337;File=inline=1;size={};width={};height={};preserveAspectRatio=0:",
+        png.len(),
+        cells.width,
+        cells.height
+    );
+    base64::encode(dst, &png);
+    dst.push('\x07');
+}
+
+/// Encodes the image as Sixel, resized to `width` x `height` pixels.
+/// Colors are reduced to the 6x6x6 color cube and translucent pixels are left unpainted.
+fn write_sixel(dst: &mut ArenaString, image: &Image, width: usize, height: usize) {
+    const TRANSPARENT: u8 = u8::MAX;
+
+    if image.width == 0 || image.height == 0 || width == 0 || height == 0 {
+        return;
+    }
+
+    // Nearest neighbor scaling and quantization in one go.
+    let mut indices = Vec::with_capacity(width * height);
+    for y in 0..height {
+        let src_y = y * image.height / height;
+        for x in 0..width {
+            let src_x = x * image.width / width;
+            let p = image.pixels[src_y * image.width + src_x];
+            indices.push(if p.alpha() < 0x80 {
+                TRANSPARENT
+            } else {
+                let level = |c: u32| ((c * 5 + 127) / 255) as u8;
+                level(p.red()) * 36 + level(p.green()) * 6 + level(p.blue())
+            });
+        }
+    }
+
+    // P2=1 keeps unpainted pixels transparent. The raster attributes
+    // (the part after the ") announce the pixel aspect ratio and size.
+    _ = write!(dst, "\x1bP0;1;0q\"1;1;{width};{height}");
+
+    let mut defined = [false; 216];
+    let mut used = [false; 216];
+
+    for band in (0..height).step_by(6) {
+        let rows = (height - band).min(6);
+        let band_indices = &indices[band * width..(band + rows) * width];
+
+        used.fill(false);
+        for &i in band_indices {
+            if i != TRANSPARENT {
+                used[i as usize] = true;
+            }
+        }
+
+        for color in 0..216 {
+            if !used[color] {
+                continue;
+            }
+
+            if defined[color] {
+                _ = write!(dst, "#{color}");
+            } else {
+                defined[color] = true;
+                // The color components are in percent.
+                let percent = |v: usize| v * 20;
+                _ = write!(
+                    dst,
+                    "#{color};2;{};{};{}",
+                    percent(color / 36),
+                    percent(color / 6 % 6),
+                    percent(color % 6)
+                );
+            }
+
+            let sixel_at = |x: usize| {
+                let mut bits = 0;
+                for dy in 0..rows {
+                    if band_indices[dy * width + x] as usize == color {
+                        bits |= 1 << dy;
+                    }
+                }
+                bits
+            };
+
+            // Run-length encode the sixels of this color.
+            let mut x = 0;
+            while x < width {
+                let bits = sixel_at(x);
+                let beg = x;
+                while {
+                    x += 1;
+                    x < width && sixel_at(x) == bits
+                } {}
+
+                let ch = (b'?' + bits) as char;
+                match x - beg {
+                    n @ 1..=3 => {
+                        for _ in 0..n {
+                            dst.push(ch);
+                        }
+                    }
+                    n => _ = write!(dst, "!{n}{ch}"),
+                }
+            }
+
+            // Return to the start of the band for the next color.
+            dst.push('$');
+        }
+
+        // Advance to the next band.
+        dst.push('-');
+    }
+
+    dst.push_str("\x1b\\");
+}
+
+/// Encodes the image as an uncompressed PNG file.
+fn encode_png(image: &Image) -> Vec<u8> {
+    fn chunk(out: &mut Vec<u8>, kind: &[u8; 4], data: &[u8]) {
+        out.extend_from_slice(&(data.len() as u32).to_be_bytes());
+        let beg = out.len();
+        out.extend_from_slice(kind);
+        out.extend_from_slice(data);
+        let crc = crc32(&out[beg..]);
+        out.extend_from_slice(&crc.to_be_bytes());
+    }
+
+    let mut out = Vec::new();
+    out.extend_from_slice(b"\x89PNG\r\n\x1a\n");
+
+    let mut ihdr = Vec::with_capacity(13);
+    ihdr.extend_from_slice(&(image.width as u32).to_be_bytes());
+    ihdr.extend_from_slice(&(image.height as u32).to_be_bytes());
+    // 8 bits per channel, RGBA, default compression, filtering and no interlacing.
+    ihdr.extend_from_slice(&[8, 6, 0, 0, 0]);
+    chunk(&mut out, b"IHDR", &ihdr);
+
+    // Each scanline starts with its filter type (0 = none).
+    let mut raw = Vec::with_capacity(image.height * (1 + image.width * 4));
+    let mut pixels = image.rgba_bytes();
+    for _ in 0..image.height {
+        raw.extend(iter::once(0).chain(pixels.by_ref().take(image.width).flatten()));
+    }
+
+    // A zlib stream made of "stored" (= uncompressed) deflate blocks.
+    let mut zlib = Vec::with_capacity(raw.len() + raw.len() / 65535 * 5 + 16);
+    zlib.extend_from_slice(&[0x78, 0x01]);
+    let mut blocks = raw.chunks(65535).peekable();
+    if blocks.peek().is_none() {
+        zlib.extend_from_slice(&[1, 0, 0, 0xff, 0xff]);
+    }
+    while let Some(block) = blocks.next() {
+        let len = block.len() as u16;
+        zlib.push(blocks.peek().is_none() as u8);
+        zlib.extend_from_slice(&len.to_le_bytes());
+        zlib.extend_from_slice(&(!len).to_le_bytes());
+        zlib.extend_from_slice(block);
+    }
+    zlib.extend_from_slice(&adler32(&raw).to_be_bytes());
+    chunk(&mut out, b"IDAT", &zlib);
+
+    chunk(&mut out, b"IEND", &[]);
+    out
+}
+
+fn crc32(data: &[u8]) -> u32 {
+    let mut crc = !0u32;
+    for &b in data {
+        crc ^= b as u32;
+        for _ in 0..8 {
+            crc = (crc >> 1) ^ (0xedb88320 & (crc & 1).wrapping_neg());
+        }
+    }
+    !crc
+}
+
+fn adler32(data: &[u8]) -> u32 {
+    let mut a = 1u32;
+    let mut b = 0u32;
+    // 5552 is the largest n such that the sums can't overflow before the modulo.
+    for chunk in data.chunks(5552) {
+        for &d in chunk {
+            a += d as u32;
+            b += a;
+        }
+        a %= 65521;
+        b %= 65521;
+    }
+    (b << 16) | a
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::arena::Arena;
+
+    fn pixel(rgba: u32) -> StraightRgba {
+        StraightRgba::from_be(rgba)
+    }
+
+    #[test]
+    fn test_checksums() {
+        assert_eq!(crc32(b"IEND"), 0xae426082);
+        assert_eq!(adler32(b"Wikipedia"), 0x11e60398);
+    }
+
+    #[test]
+    fn test_png() {
+        let image = Image::new(1, 1, vec![pixel(0x11223344)]);
+        let png = encode_png(&image);
+        assert!(png.starts_with(b"\x89PNG\r\n\x1a\n\x00\x00\x00\x0dIHDR"));
+        assert!(png.ends_with(b"IEND\xae\x42\x60\x82"));
+        // The filter byte followed by the pixel, in a final stored block.
+        assert!(png.windows(10).any(|w| w == [1, 5, 0, 0xfa, 0xff, 0, 0x11, 0x22, 0x33, 0x44]));
+    }
+
+    #[test]
+    fn test_sixel() {
+        let arena = Arena::new(64 * 1024).unwrap();
+        let mut out = ArenaString::new_in(&arena);
+        let red = pixel(0xff0000ff);
+        let clear = pixel(0);
+        let image = Image::new(2, 2, vec![red, red, red, clear]);
+
+        write_sixel(&mut out, &image, 4, 2);
+        // 2 rows of red = 0b11 and 1 row = 0b01, offset by '?'.
+        assert_eq!(out, "\x1bP0;1;0q\"1;1;4;2#180;2;100;0;0BB@@$-\x1b\\");
+    }
+
+    #[test]
+    fn test_kitty() {
+        let arena = Arena::new(64 * 1024).unwrap();
+        let mut out = ArenaString::new_in(&arena);
+        let image = Image::new(1, 1000, vec![pixel(0x000000ff); 1000]);
+        let cells = Size { width: 2, height: 3 };
+
+        write_kitty(&mut out, &image, cells);
+        let id = image.id();
+        assert!(out.starts_with(&format!(
+            "\x1b_Ga=T,f=32,s=1,v=1000,c=2,r=3,i={id},C=1,q=2,m=1;AAAA/wAAAP8"
+        )));
+        assert!(out.contains("\x1b\\\x1b_Gm=0;AAAA/w"));
+        assert!(out.ends_with("\x1b\\"));
+    }
+}
@@ -8,6 +8,7 @@
 
 use std::mem;
 
+use crate::base64::{self, Alphabet};
 use crate::helpers::{CoordType, Point, Size};
 use crate::vt;
 
@@ -107,6 +108,7 @@ pub mod vk {
     pub const BACK: InputKey = InputKey::new(0x08);
     pub const TAB: InputKey = InputKey::new('\t' as u32);
     pub const RETURN: InputKey = InputKey::new('\r' as u32);
+    pub const CAPITAL: InputKey = InputKey::new(0x14);
     pub const ESCAPE: InputKey = InputKey::new(0x1B);
     pub const SPACE: InputKey = InputKey::new(' ' as u32);
     pub const PRIOR: InputKey = InputKey::new(0x21);
@@ -161,6 +163,8 @@ pub mod vk {
     pub const Y: InputKey = InputKey::new('Y' as u32);
     pub const Z: InputKey = InputKey::new('Z' as u32);
 
+    pub const APPS: InputKey = InputKey::new(0x5D);
+
     pub const NUMPAD0: InputKey = InputKey::new(0x60);
     pub const NUMPAD1: InputKey = InputKey::new(0x61);
     pub const NUMPAD2: InputKey = InputKey::new(0x62);
@@ -248,6 +252,20 @@ pub struct InputMouse {
     pub scroll: Point,
 }
 
+/// A chunk of a clipboard paste.
+///
+/// Large pastes arrive over many reads from stdin. Instead of buffering them up,
+/// each read yields the part of the paste it contained.
+#[derive(Clone, Copy)]
+pub struct InputPaste<'a> {
+    /// The pasted text in this chunk. May be empty.
+    pub text: &'a str,
+    /// Whether this is the first chunk of the paste.
+    pub first: bool,
+    /// Whether this is the last chunk of the paste.
+    pub last: bool,
+}
+
 /// Primary result type of the parser.
 pub enum Input<'input> {
     /// Window resize event.
@@ -255,18 +273,132 @@ pub enum Input<'input> {
     /// Text input.
     /// Note that [`Input::Keyboard`] events can also be text.
     Text(&'input str),
-    /// A clipboard paste.
-    Paste(Vec<u8>),
+    /// A single character of text input, for terminals that report
+    /// the text as a codepoint instead of UTF-8, like win32-input-mode.
+    Char(char),
+    /// A clipboard paste, or a chunk of it.
+    Paste(InputPaste<'input>),
+    /// The contents of the terminal's clipboard, in response to an OSC 52 query.
+    /// It's empty if the terminal declined to share it.
+    HostClipboard(Vec<u8>),
     /// Keyboard input.
     Keyboard(InputKey),
+    /// A key was released. Only reported by terminals
+    /// that support the kitty keyboard protocol.
+    KeyRelease(InputKey),
     /// Mouse input.
     Mouse(InputMouse),
 }
 
+/// Rewrites keys before they're dispatched, e.g. to swap Ctrl+H and Backspace.
+///
+/// Each key is mapped at most once, which means that mappings can't chain,
+/// but `a=b,b=a` swaps two keys. Only exact matches, including the modifiers, are mapped.
+#[derive(Default, Clone)]
+pub struct KeyRemap {
+    entries: Vec<(InputKey, InputKey)>,
+}
+
+impl KeyRemap {
+    /// Parses a comma-separated list of `from=to` pairs, e.g. `ctrl+h=backspace,capslock=escape`.
+    ///
+    /// A key is written as any number of `ctrl+`, `alt+` and `shift+` modifiers, followed by
+    /// a letter, a digit, `f1` to `f24`, or a key name like `backspace` or `pageup`.
+    ///
+    /// Keys that type text, like `a`, `shift+1` or `space`, are rejected as the key to map from.
+    /// Terminals report them as [`Input::Text`], and so they would never match.
+    pub fn parse(spec: &str) -> Option<Self> {
+        let mut remap = Self::default();
+        for entry in spec.split(',').map(str::trim).filter(|e| !e.is_empty()) {
+            let (from, to) = entry.split_once('=')?;
+            let from = parse_key(from)?;
+            let types_text = matches!(from.modifiers(), kbmod::NONE | kbmod::SHIFT)
+                && InputKey::from_ascii(from.key().value() as u8 as char).is_some();
+            if types_text {
+                return None;
+            }
+            remap.insert(from, parse_key(to)?);
+        }
+        Some(remap)
+    }
+
+    /// Maps `from` to `to`, replacing any previous mapping of `from`.
+    pub fn insert(&mut self, from: InputKey, to: InputKey) {
+        self.entries.retain(|&(f, _)| f != from);
+        self.entries.push((from, to));
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    /// Returns what `key` maps to.
+    pub fn map(&self, key: InputKey) -> InputKey {
+        self.entries.iter().find(|&&(from, _)| from == key).map_or(key, |&(_, to)| to)
+    }
+}
+
+/// Parses a key like `ctrl+shift+pageup`. See [`KeyRemap::parse`].
+fn parse_key(s: &str) -> Option<InputKey> {
+    const NAMES: [(&str, InputKey); 19] = [
+        ("backspace", vk::BACK),
+        ("tab", vk::TAB),
+        ("enter", vk::RETURN),
+        ("return", vk::RETURN),
+        ("capslock", vk::CAPITAL),
+        ("escape", vk::ESCAPE),
+        ("esc", vk::ESCAPE),
+        ("space", vk::SPACE),
+        ("pageup", vk::PRIOR),
+        ("pagedown", vk::NEXT),
+        ("end", vk::END),
+        ("home", vk::HOME),
+        ("left", vk::LEFT),
+        ("up", vk::UP),
+        ("right", vk::RIGHT),
+        ("down", vk::DOWN),
+        ("insert", vk::INSERT),
+        ("delete", vk::DELETE),
+        ("menu", vk::APPS),
+    ];
+
+    let s = s.trim().to_ascii_lowercase();
+    let mut parts = s.split('+');
+    let name = parts.next_back()?;
+    let mut modifiers = kbmod::NONE;
+
+    for modifier in parts {
+        modifiers |= match modifier {
+            "ctrl" => kbmod::CTRL,
+            "alt" => kbmod::ALT,
+            "shift" => kbmod::SHIFT,
+            _ => return None,
+        };
+    }
+
+    let key = if let Some(&(_, key)) = NAMES.iter().find(|&&(n, _)| n == name) {
+        key
+    } else if let Some(n) = name.strip_prefix('f').and_then(|n| n.parse::<u32>().ok())
+        && (1..=24).contains(&n)
+    {
+        InputKey::new(vk::F1.value() + n - 1)
+    } else if let [ch @ (b'a'..=b'z' | b'0'..=b'9')] = name.as_bytes() {
+        InputKey::new(ch.to_ascii_uppercase() as u32)
+    } else {
+        return None;
+    };
+
+    Some(key | modifiers)
+}
+
 /// Parses VT sequences into input events.
 pub struct Parser {
     bracketed_paste: bool,
-    bracketed_paste_buf: Vec<u8>,
+    bracketed_paste_first: bool,
+    osc_buf: String,
+    /// A UTF-16 lead surrogate from win32-input-mode, waiting for its trail surrogate.
+    win32_lead_surrogate: u16,
+    remap: KeyRemap,
     x10_mouse_want: bool,
     x10_mouse_buf: [u8; 3],
     x10_mouse_len: usize,
@@ -279,13 +411,21 @@ impl Parser {
     pub fn new() -> Self {
         Self {
             bracketed_paste: false,
-            bracketed_paste_buf: Vec::new(),
+            bracketed_paste_first: false,
+            osc_buf: String::new(),
+            win32_lead_surrogate: 0,
+            remap: KeyRemap::default(),
             x10_mouse_want: false,
             x10_mouse_buf: [0; 3],
             x10_mouse_len: 0,
         }
     }
 
+    /// Sets the keys to rewrite before they're returned as input events.
+    pub fn set_remap(&mut self, remap: KeyRemap) {
+        self.remap = remap;
+    }
+
     /// Takes an [`vt::Stream`] and returns a [`Stream`]
     /// that turns VT sequences into input events.
     pub fn parse<'parser, 'vt, 'input>(
@@ -306,6 +446,18 @@ impl<'input> Iterator for Stream<'_, '_, 'input> {
     type Item = Input<'input>;
 
     fn next(&mut self) -> Option<Input<'input>> {
+        let input = self.next_unmapped()?;
+        let remap = &self.parser.remap;
+        Some(match input {
+            Input::Keyboard(key) if !remap.is_empty() => Input::Keyboard(remap.map(key)),
+            Input::KeyRelease(key) if !remap.is_empty() => Input::KeyRelease(remap.map(key)),
+            input => input,
+        })
+    }
+}
+
+impl<'input> Stream<'_, '_, 'input> {
+    fn next_unmapped(&mut self) -> Option<Input<'input>> {
         loop {
             if self.parser.bracketed_paste {
                 return self.handle_bracketed_paste();
@@ -373,9 +525,7 @@ impl<'input> Iterator for Stream<'_, '_, 'input> {
                         'A'..='H' => {
                             let vk = KEYPAD_LUT[csi.final_byte as usize - 'A' as usize];
                             if vk != 0 {
-                                return Some(Input::Keyboard(
-                                    InputKey::new(vk as u32) | Self::parse_modifiers(csi),
-                                ));
+                                return Some(Self::keyboard_event(csi, InputKey::new(vk as u32)));
                             }
                         }
                         'Z' => return Some(Input::Keyboard(kbmod::SHIFT | vk::TAB)),
@@ -420,18 +570,55 @@ impl<'input> Iterator for Stream<'_, '_, 'input> {
                             const LUT_LEN: u16 = LUT.len() as u16;
 
                             match csi.params[0] {
+                                // xterm's modifyOtherKeys: `CSI 27 ; <modifiers> ; <code> ~`.
+                                27 => {
+                                    if let Some(input) = Self::modify_other_keys(csi) {
+                                        return Some(input);
+                                    }
+                                }
                                 0..LUT_LEN => {
                                     let vk = LUT[csi.params[0] as usize];
                                     if vk != 0 {
-                                        return Some(Input::Keyboard(
-                                            InputKey::new(vk as u32) | Self::parse_modifiers(csi),
+                                        return Some(Self::keyboard_event(
+                                            csi,
+                                            InputKey::new(vk as u32),
                                         ));
                                     }
                                 }
-                                200 => self.parser.bracketed_paste = true,
+                                200 => {
+                                    self.parser.bracketed_paste = true;
+                                    self.parser.bracketed_paste_first = true;
+                                }
                                 _ => {}
                             }
                         }
+                        // win32-input-mode: `CSI <vk> ; <sc> ; <uc> ; <kd> ; <cs> ; <rc> _`.
+                        '_' if csi.private_byte == '\0' => {
+                            let lead_surrogate = &mut self.parser.win32_lead_surrogate;
+                            if let Some(input) = Self::win32_input(csi, lead_surrogate) {
+                                return Some(input);
+                            }
+                        }
+                        // The kitty keyboard protocol: `CSI <code>:<alternates> ; <modifiers>:<event> u`.
+                        'u' if csi.private_byte == '\0' => {
+                            let code = csi.params[0];
+                            if let Some(key) = Self::kitty_key(code) {
+                                return Some(Self::keyboard_event(csi, key));
+                            }
+                            // Other printable keys are text, unless Ctrl or Alt are held.
+                            // Those are shortcuts, with the character as the key, just like
+                            // in the legacy encoding of Alt+<key>. See `kitty_key()`.
+                            if (0x20..=0x7e).contains(&code) {
+                                let modifiers = Self::parse_modifiers(csi);
+                                if modifiers != kbmod::NONE && modifiers != kbmod::SHIFT {
+                                    let key = InputKey::new(code as u32);
+                                    return Some(Self::keyboard_event(csi, key));
+                                }
+                                if csi.param_group(1).get(1) != Some(&3) {
+                                    return Some(Input::Text(printable(code as u8)));
+                                }
+                            }
+                        }
                         'm' | 'M' if csi.private_byte == '<' => {
                             let btn = csi.params[0];
                             let mut mouse = InputMouse {
@@ -479,6 +666,12 @@ impl<'input> Iterator for Stream<'_, '_, 'input> {
                         _ => {}
                     }
                 }
+                vt::Token::Osc { data, partial } => {
+                    if let Some(input) = self.handle_osc(data, partial) {
+                        return Some(input);
+                    }
+                }
+                vt::Token::Cancel => self.parser.osc_buf.clear(),
                 _ => {}
             }
         }
@@ -513,17 +706,43 @@ impl<'input> Stream<'_, '_, 'input> {
             end = self.stream.offset();
         }
 
-        if end != beg {
-            self.parser
-                .bracketed_paste_buf
-                .extend_from_slice(&self.stream.input().as_bytes()[beg..end]);
+        let last = !self.parser.bracketed_paste;
+        if end == beg && !last {
+            return None;
         }
 
-        if !self.parser.bracketed_paste {
-            Some(Input::Paste(mem::take(&mut self.parser.bracketed_paste_buf)))
-        } else {
-            None
+        Some(Input::Paste(InputPaste {
+            text: &self.stream.input()[beg..end],
+            first: mem::take(&mut self.parser.bracketed_paste_first),
+            last,
+        }))
+    }
+
+    /// Parses OSC responses. Only the OSC 52 clipboard contents are of interest:
+    /// `OSC 52 ; <selection> ; <base64> ST`.
+    ///
+    /// They can be large and arrive over several reads, which is why they're buffered.
+    fn handle_osc(&mut self, data: &str, partial: bool) -> Option<Input<'input>> {
+        let buf = &mut self.parser.osc_buf;
+        if partial || !buf.is_empty() {
+            buf.push_str(data);
+            if partial {
+                return None;
+            }
         }
+
+        let data = if buf.is_empty() { data } else { buf.as_str() };
+        let input = data.strip_prefix("52;").map(|data| {
+            let (_, encoded) = data.split_once(';').unwrap_or_default();
+            let mut decoded = Vec::new();
+            if !base64::decode_lenient(&mut decoded, encoded.as_bytes(), Alphabet::Standard) {
+                decoded.clear();
+            }
+            Input::HostClipboard(decoded)
+        });
+
+        buf.clear();
+        input
     }
 
     /// Implements the X10 mouse protocol via `CSI M CbCxCy`.
@@ -569,9 +788,145 @@ impl<'input> Stream<'_, '_, 'input> {
         }))
     }
 
+    /// Turns the `key` of a CSI sequence into a key press or release,
+    /// depending on the kitty keyboard protocol event type, if any.
+    fn keyboard_event(csi: &vt::Csi, key: InputKey) -> Input<'input> {
+        let key = key | Self::parse_modifiers(csi);
+        match csi.param_group(1).get(1) {
+            Some(3) => Input::KeyRelease(key),
+            // 1 is a press and 2 a repeat.
+            _ => Input::Keyboard(key),
+        }
+    }
+
+    fn modify_other_keys(csi: &vt::Csi) -> Option<Input<'input>> {
+        let code = csi.params[2];
+        let modifiers = Self::parse_modifiers(csi);
+
+        // Printable characters that are only modified by Shift are just text.
+        // xterm sends them this way when modifyOtherKeys is set to 2.
+        if (modifiers == kbmod::NONE || modifiers == kbmod::SHIFT) && (0x20..=0x7e).contains(&code)
+        {
+            return Some(Input::Text(printable(code as u8)));
+        }
+
+        // Same key codes as the kitty keyboard protocol for the keys that xterm encodes.
+        if let Some(key) = Self::kitty_key(code) {
+            return Some(Input::Keyboard(key | modifiers));
+        }
+
+        // Other printable characters, like Ctrl+",", have no key code of their own.
+        // As in the legacy encoding of Alt+<key>, the character is the key.
+        (0x20..=0x7e)
+            .contains(&code)
+            .then(|| Input::Keyboard(InputKey::new(code as u32) | modifiers))
+    }
+
+    /// Windows Terminal's win32-input-mode reports the fields of a Win32 `KEY_EVENT_RECORD`:
+    /// The virtual key code, the scan code, the UTF-16 text, whether it's a press or release,
+    /// the modifier state and the repeat count. Since our key codes are virtual key codes,
+    /// this maps almost 1:1, and distinguishes the numpad from the rest of the keyboard.
+    fn win32_input(csi: &vt::Csi, lead_surrogate: &mut u16) -> Option<Input<'input>> {
+        const RIGHT_ALT_PRESSED: u16 = 0x01;
+        const LEFT_ALT_PRESSED: u16 = 0x02;
+        const RIGHT_CTRL_PRESSED: u16 = 0x04;
+        const LEFT_CTRL_PRESSED: u16 = 0x08;
+        const SHIFT_PRESSED: u16 = 0x10;
+
+        let vk = csi.params[0];
+        let uc = csi.params[2];
+        let key_down = csi.params[3] != 0;
+        let state = csi.params[4];
+
+        // The modifier keys themselves are reported via `state`.
+        // Shift, Ctrl, Alt, the Windows keys, NumLock, ScrollLock and their left/right variants.
+        // CapsLock is passed through, since it's useful for remapping. See [`KeyRemap`].
+        if matches!(vk, 0x10..=0x12 | 0x5b | 0x5c | 0x90 | 0x91 | 0xa0..=0xa5) {
+            return None;
+        }
+
+        let mut modifiers = kbmod::NONE;
+        if state & SHIFT_PRESSED != 0 {
+            modifiers |= kbmod::SHIFT;
+        }
+        if state & (LEFT_ALT_PRESSED | RIGHT_ALT_PRESSED) != 0 {
+            modifiers |= kbmod::ALT;
+        }
+        if state & (LEFT_CTRL_PRESSED | RIGHT_CTRL_PRESSED) != 0 {
+            modifiers |= kbmod::CTRL;
+        }
+
+        if !key_down {
+            return (vk != 0).then(|| Input::KeyRelease(InputKey::new(vk as u32) | modifiers));
+        }
+
+        // Characters outside the BMP are split into two events, one per surrogate.
+        let lead = mem::take(lead_surrogate);
+        let ch = match uc {
+            0xd800..0xdc00 => {
+                *lead_surrogate = uc;
+                return None;
+            }
+            0xdc00..0xe000 if lead != 0 => {
+                char::from_u32(0x10000 + ((lead as u32 - 0xd800) << 10) + (uc as u32 - 0xdc00))
+            }
+            _ => char::from_u32(uc as u32),
+        }
+        .unwrap_or('\0');
+
+        // AltGr is reported as Ctrl+Alt, but if it produced text, that's what the user wanted.
+        let altgr = state & (RIGHT_ALT_PRESSED | LEFT_CTRL_PRESSED)
+            == (RIGHT_ALT_PRESSED | LEFT_CTRL_PRESSED);
+        let is_text = ch >= ' ' && ch != '\x7f';
+        if is_text && (altgr || !modifiers.contains(kbmod::CTRL_ALT)) {
+            return Some(if ch.is_ascii() {
+                Input::Text(printable(ch as u8))
+            } else {
+                Input::Char(ch)
+            });
+        }
+
+        // A dead key (no text and no Ctrl/Alt), whose accent is part of the next key's text.
+        if ch == '\0'
+            && !modifiers.contains(kbmod::CTRL_ALT)
+            && matches!(vk, 0x30..=0x5a | 0xba..=0xe2)
+        {
+            return None;
+        }
+
+        (vk != 0).then(|| Input::Keyboard(InputKey::new(vk as u32) | modifiers))
+    }
+
+    /// Maps a kitty keyboard protocol key code to a key.
+    /// The codes are Unicode codepoints, or from the private use area for functional keys.
+    /// Only letters and digits share their codes with virtual keys. Other printable
+    /// characters would collide with unrelated ones, e.g. "," with `VK_SNAPSHOT`.
+    fn kitty_key(code: u16) -> Option<InputKey> {
+        let key = match code {
+            8 | 127 => vk::BACK,
+            9 => vk::TAB,
+            13 | 57414 => vk::RETURN, // 57414 = KP_ENTER
+            27 => vk::ESCAPE,
+            0x20 => vk::SPACE,
+            0x30..=0x39 | 0x41..=0x5a => InputKey::new(code as u32),
+            0x61..=0x7a => InputKey::new(code as u32 & !0x20), // Shift a-z to A-Z
+            57358 => vk::CAPITAL,
+            57363 => vk::APPS,
+            57376..=57387 => InputKey::new(vk::F13.value() + code as u32 - 57376),
+            57399..=57408 => InputKey::new(vk::NUMPAD0.value() + code as u32 - 57399),
+            57409 => vk::DECIMAL,
+            57410 => vk::DIVIDE,
+            57411 => vk::MULTIPLY,
+            57412 => vk::SUBTRACT,
+            57413 => vk::ADD,
+            _ => return None,
+        };
+        Some(key)
+    }
+
     fn parse_modifiers(csi: &vt::Csi) -> InputKeyMod {
         let mut modifiers = kbmod::NONE;
-        let p1 = csi.params[1].saturating_sub(1);
+        let p1 = csi.param_group(1).first().copied().unwrap_or(0).saturating_sub(1);
         if (p1 & 0x01) != 0 {
             modifiers |= kbmod::SHIFT;
         }
@@ -584,3 +939,221 @@ impl<'input> Stream<'_, '_, 'input> {
         modifiers
     }
 }
+
+/// Returns a `'static` string of the given printable ASCII character.
+fn printable(ch: u8) -> &'static str {
+    const PRINTABLE: &str = concat!(
+        " !\"#$%&'()*+,-./0123456789:;<=>?",
+        "@ABCDEFGHIJKLMNOPQRSTUVWXYZ[\\]^_",
+        "`abcdefghijklmnopqrstuvwxyz{|}~",
+    );
+    let i = ch as usize - 0x20;
+    &PRINTABLE[i..i + 1]
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse_keys(input: &str) -> Vec<(bool, u32)> {
+        let mut vt_parser = vt::Parser::new();
+        let mut parser = Parser::new();
+        parser
+            .parse(vt_parser.parse(input))
+            .filter_map(|input| match input {
+                Input::Keyboard(key) => Some((true, key.value())),
+                Input::KeyRelease(key) => Some((false, key.value())),
+                _ => None,
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_bracketed_paste_chunks() {
+        let mut vt_parser = vt::Parser::new();
+        let mut parser = Parser::new();
+        let mut chunks = Vec::new();
+
+        for input in ["\x1b[200~foo", "\x1b[Abar", "\x1b[201~"] {
+            for input in parser.parse(vt_parser.parse(input)) {
+                if let Input::Paste(paste) = input {
+                    chunks.push((paste.text.to_string(), paste.first, paste.last));
+                }
+            }
+        }
+
+        assert_eq!(
+            chunks,
+            [
+                ("foo".to_string(), true, false),
+                ("\x1b[Abar".to_string(), false, false),
+                (String::new(), false, true),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_osc52_response() {
+        let mut vt_parser = vt::Parser::new();
+        let mut parser = Parser::new();
+        let mut clipboard = None;
+
+        for input in ["\x1b]52;c;aGVsbG8g", "d29ybGQ=\x1b\\"] {
+            for input in parser.parse(vt_parser.parse(input)) {
+                if let Input::HostClipboard(data) = input {
+                    clipboard = Some(data);
+                }
+            }
+        }
+
+        assert_eq!(clipboard.as_deref(), Some(&b"hello world"[..]));
+    }
+
+    #[test]
+    fn test_modify_other_keys() {
+        let mut vt_parser = vt::Parser::new();
+        let mut parser = Parser::new();
+        let mut stream = parser.parse(vt_parser.parse("\x1b[27;2;33~"));
+        assert!(matches!(stream.next(), Some(Input::Text("!"))));
+
+        let keys = parse_keys(concat!(
+            // Ctrl+Shift+A
+            "\x1b[27;6;65~",
+            // Ctrl+Return
+            "\x1b[27;5;13~",
+            // Alt+Tab
+            "\x1b[27;3;9~",
+            // Ctrl+","
+            "\x1b[27;5;44~",
+            // Ctrl+Alt+"!"
+            "\x1b[27;7;33~",
+        ));
+        assert_eq!(
+            keys,
+            [
+                (true, (kbmod::CTRL_SHIFT | vk::A).value()),
+                (true, (kbmod::CTRL | vk::RETURN).value()),
+                (true, (kbmod::ALT | vk::TAB).value()),
+                (true, (kbmod::CTRL | InputKey::new(',' as u32)).value()),
+                (true, (kbmod::CTRL_ALT | InputKey::new('!' as u32)).value()),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_win32_input_mode() {
+        let mut vt_parser = vt::Parser::new();
+        let mut parser = Parser::new();
+        let mut stream = parser.parse(vt_parser.parse(concat!(
+            // Shift press, then Shift+A
+            "\x1b[16;42;0;1;16;1_\x1b[65;30;65;1;16;1_",
+            // AltGr+Q on a German layout
+            "\x1b[81;16;64;1;9;1_",
+            // Dead key ´ followed by e
+            "\x1b[221;13;0;1;0;1_\x1b[69;18;233;1;0;1_",
+            // U+1F600 as a surrogate pair
+            "\x1b[0;0;55357;1;0;1_\x1b[0;0;56832;1;0;1_",
+        )));
+        assert!(matches!(stream.next(), Some(Input::Text("A"))));
+        assert!(matches!(stream.next(), Some(Input::Text("@"))));
+        assert!(matches!(stream.next(), Some(Input::Char('é'))));
+        assert!(matches!(stream.next(), Some(Input::Char('\u{1f600}'))));
+        assert!(stream.next().is_none());
+
+        let keys = parse_keys(concat!(
+            // Ctrl+A press and release
+            "\x1b[65;30;1;1;8;1_\x1b[65;30;1;0;8;1_",
+            // Numpad Enter, which is an "enhanced" key
+            "\x1b[13;28;13;1;256;1_",
+            // Ctrl+Numpad+, as opposed to Ctrl+=
+            "\x1b[107;78;0;1;40;1_",
+            // Up
+            "\x1b[38;72;0;1;256;1_",
+        ));
+        assert_eq!(
+            keys,
+            [
+                (true, (kbmod::CTRL | vk::A).value()),
+                (false, (kbmod::CTRL | vk::A).value()),
+                (true, vk::RETURN.value()),
+                (true, (kbmod::CTRL | vk::ADD).value()),
+                (true, vk::UP.value()),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_key_remap() {
+        let remap =
+            KeyRemap::parse("ctrl+h=backspace, backspace=Ctrl+H,capslock=esc,alt+f12=shift+x")
+                .unwrap();
+        assert_eq!(remap.map(kbmod::CTRL | vk::H).value(), vk::BACK.value());
+        assert_eq!(remap.map(vk::BACK).value(), (kbmod::CTRL | vk::H).value());
+        assert_eq!(remap.map(vk::CAPITAL).value(), vk::ESCAPE.value());
+        assert_eq!(remap.map(kbmod::ALT | vk::F12).value(), (kbmod::SHIFT | vk::X).value());
+        // Only exact matches are mapped.
+        assert_eq!(remap.map(kbmod::CTRL | vk::BACK).value(), (kbmod::CTRL | vk::BACK).value());
+
+        assert!(KeyRemap::parse("").is_some_and(|r| r.is_empty()));
+        assert!(KeyRemap::parse("ctrl+h").is_none());
+        assert!(KeyRemap::parse("hyper+h=a").is_none());
+        assert!(KeyRemap::parse("f25=a").is_none());
+        // Keys that arrive as text can't be mapped.
+        assert!(KeyRemap::parse("a=ctrl+a").is_none());
+        assert!(KeyRemap::parse("shift+1=f1").is_none());
+        assert!(KeyRemap::parse("space=tab").is_none());
+        assert!(KeyRemap::parse("alt+a=ctrl+a").is_some());
+
+        // Ctrl+H arrives as BS, Backspace as DEL, and CapsLock only in win32-input-mode.
+        let mut vt_parser = vt::Parser::new();
+        let mut parser = Parser::new();
+        parser.set_remap(remap);
+        let keys: Vec<_> = parser
+            .parse(vt_parser.parse("\x08\x7f\x1b[20;58;0;1;0;1_"))
+            .filter_map(|input| match input {
+                Input::Keyboard(key) => Some(key.value()),
+                _ => None,
+            })
+            .collect();
+        assert_eq!(keys, [vk::BACK.value(), (kbmod::CTRL | vk::H).value(), vk::ESCAPE.value()]);
+    }
+
+    #[test]
+    fn test_kitty_keyboard() {
+        let mut vt_parser = vt::Parser::new();
+        let mut parser = Parser::new();
+        let mut stream = parser.parse(vt_parser.parse("\x1b[44u"));
+        assert!(matches!(stream.next(), Some(Input::Text(","))));
+        let mut stream = parser.parse(vt_parser.parse("\x1b[44;2u"));
+        assert!(matches!(stream.next(), Some(Input::Text(","))));
+
+        let keys = parse_keys(concat!(
+            // Ctrl+I, which is indistinguishable from Tab in the legacy encoding.
+            "\x1b[105;5u",
+            // Ctrl+Shift+A, with the shifted key as an alternate key code.
+            "\x1b[97:65;6u",
+            // The release of the Escape key.
+            "\x1b[27;1:3u",
+            // The release of Shift+Up.
+            "\x1b[1;2:3A",
+            // Ctrl+Delete, pressed.
+            "\x1b[3;5:1~",
+            // Ctrl+",", which is a shortcut and not text.
+            "\x1b[44;5u",
+            // Its release.
+            "\x1b[44;5:3u",
+        ));
+        assert_eq!(
+            keys,
+            [
+                (true, (kbmod::CTRL | vk::I).value()),
+                (true, (kbmod::CTRL_SHIFT | vk::A).value()),
+                (false, vk::ESCAPE.value()),
+                (false, (kbmod::SHIFT | vk::UP).value()),
+                (true, (kbmod::CTRL | vk::DELETE).value()),
+                (true, (kbmod::CTRL | InputKey::new(',' as u32)).value()),
+                (false, (kbmod::CTRL | InputKey::new(',' as u32)).value()),
+            ]
+        );
+    }
+}
@@ -0,0 +1,284 @@
+// Copyright (c) Microsoft Corporation.
+// Licensed under the MIT License.
+
+//! A small JSON reformatter.
+//!
+//! It validates the input as it goes, but never builds a DOM.
+//! Strings and numbers are copied verbatim, so the output is byte-for-byte
+//! identical to the input, minus (or plus) some whitespace.
+
+/// Pretty-prints `src`, indenting each nesting level with `indent`.
+///
+/// On failure, returns the byte offset of the offending character
+/// (or `src.len()` if the input ended prematurely).
+pub fn pretty(src: &[u8], indent: &str) -> Result<Vec<u8>, usize> {
+    reformat(src, Some(indent.as_bytes()))
+}
+
+/// Strips all insignificant whitespace from `src`.
+///
+/// On failure, returns the byte offset of the offending character
+/// (or `src.len()` if the input ended prematurely).
+pub fn minify(src: &[u8]) -> Result<Vec<u8>, usize> {
+    reformat(src, None)
+}
+
+struct Formatter<'a> {
+    src: &'a [u8],
+    off: usize,
+    out: Vec<u8>,
+    indent: Option<&'a [u8]>,
+    // The closing brackets of all currently open containers.
+    // It's a manual stack, so that deeply nested input can't overflow ours.
+    stack: Vec<u8>,
+}
+
+fn reformat(src: &[u8], indent: Option<&[u8]>) -> Result<Vec<u8>, usize> {
+    let mut f =
+        Formatter { src, off: 0, out: Vec::with_capacity(src.len()), indent, stack: Vec::new() };
+    let mut want_value = true;
+
+    loop {
+        f.skip_whitespace();
+
+        if want_value {
+            match f.peek() {
+                open @ (b'{' | b'[') => {
+                    // In ASCII, '}' and ']' both follow their opening bracket by 2.
+                    let close = open + 2;
+                    f.off += 1;
+                    f.out.push(open);
+                    f.skip_whitespace();
+
+                    if f.peek() == close {
+                        f.off += 1;
+                        f.out.push(close);
+                        want_value = false;
+                        continue;
+                    }
+
+                    f.stack.push(close);
+                    f.newline();
+                    if close == b'}' {
+                        f.key()?;
+                    }
+                    continue;
+                }
+                b'"' => f.string()?,
+                b'-' | b'0'..=b'9' => f.number()?,
+                b't' => f.literal(b"true")?,
+                b'f' => f.literal(b"false")?,
+                b'n' => f.literal(b"null")?,
+                _ => return Err(f.off),
+            }
+            want_value = false;
+        } else {
+            let Some(&close) = f.stack.last() else {
+                // The top-level value is complete. Only whitespace may follow.
+                if f.off < src.len() {
+                    return Err(f.off);
+                }
+                break;
+            };
+
+            match f.peek() {
+                b',' => {
+                    f.off += 1;
+                    f.out.push(b',');
+                    f.newline();
+                    if close == b'}' {
+                        f.skip_whitespace();
+                        f.key()?;
+                    }
+                    want_value = true;
+                }
+                c if c == close => {
+                    f.off += 1;
+                    f.stack.pop();
+                    f.newline();
+                    f.out.push(close);
+                }
+                _ => return Err(f.off),
+            }
+        }
+    }
+
+    // Retain the trailing newline of the input, if any, so that
+    // formatting an entire document doesn't change its last line.
+    if src.ends_with(b"\n") {
+        f.out.push(b'\n');
+    }
+
+    Ok(f.out)
+}
+
+impl Formatter<'_> {
+    /// Returns the current byte, or 0 at the end of the input.
+    /// A literal 0 byte is invalid JSON anyway, so this is unambiguous.
+    fn peek(&self) -> u8 {
+        self.src.get(self.off).copied().unwrap_or(0)
+    }
+
+    fn skip_whitespace(&mut self) {
+        while matches!(self.peek(), b' ' | b'\t' | b'\r' | b'\n') {
+            self.off += 1;
+        }
+    }
+
+    fn newline(&mut self) {
+        if let Some(indent) = self.indent {
+            self.out.push(b'\n');
+            for _ in 0..self.stack.len() {
+                self.out.extend_from_slice(indent);
+            }
+        }
+    }
+
+    fn key(&mut self) -> Result<(), usize> {
+        if self.peek() != b'"' {
+            return Err(self.off);
+        }
+        self.string()?;
+
+        self.skip_whitespace();
+        if self.peek() != b':' {
+            return Err(self.off);
+        }
+        self.off += 1;
+        self.out.push(b':');
+        if self.indent.is_some() {
+            self.out.push(b' ');
+        }
+        Ok(())
+    }
+
+    fn string(&mut self) -> Result<(), usize> {
+        let beg = self.off;
+        self.off += 1;
+
+        loop {
+            match self.peek() {
+                b'"' => break,
+                b'\\' => {
+                    self.off += 1;
+                    match self.peek() {
+                        b'"' | b'\\' | b'/' | b'b' | b'f' | b'n' | b'r' | b't' => {}
+                        b'u' => {
+                            for _ in 0..4 {
+                                self.off += 1;
+                                if !self.peek().is_ascii_hexdigit() {
+                                    return Err(self.off);
+                                }
+                            }
+                        }
+                        _ => return Err(self.off),
+                    }
+                }
+                // This includes the 0 returned at the end of the input.
+                ..0x20 => return Err(self.off),
+                _ => {}
+            }
+            self.off += 1;
+        }
+
+        self.off += 1;
+        self.out.extend_from_slice(&self.src[beg..self.off]);
+        Ok(())
+    }
+
+    fn number(&mut self) -> Result<(), usize> {
+        let beg = self.off;
+
+        if self.peek() == b'-' {
+            self.off += 1;
+        }
+
+        match self.peek() {
+            b'0' => self.off += 1,
+            b'1'..=b'9' => self.digits(),
+            _ => return Err(self.off),
+        }
+
+        if self.peek() == b'.' {
+            self.off += 1;
+            if !self.peek().is_ascii_digit() {
+                return Err(self.off);
+            }
+            self.digits();
+        }
+
+        if matches!(self.peek(), b'e' | b'E') {
+            self.off += 1;
+            if matches!(self.peek(), b'+' | b'-') {
+                self.off += 1;
+            }
+            if !self.peek().is_ascii_digit() {
+                return Err(self.off);
+            }
+            self.digits();
+        }
+
+        self.out.extend_from_slice(&self.src[beg..self.off]);
+        Ok(())
+    }
+
+    fn digits(&mut self) {
+        while self.peek().is_ascii_digit() {
+            self.off += 1;
+        }
+    }
+
+    fn literal(&mut self, lit: &[u8]) -> Result<(), usize> {
+        for &b in lit {
+            if self.peek() != b {
+                return Err(self.off);
+            }
+            self.off += 1;
+        }
+        self.out.extend_from_slice(lit);
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_pretty() {
+        let src = br#"{"a":[1,-2.5e+3,{}],"b" : {"c":null , "d":[]},"e":"x\"\u00e9y"}"#;
+        let expected = r#"{
+  "a": [
+    1,
+    -2.5e+3,
+    {}
+  ],
+  "b": {
+    "c": null,
+    "d": []
+  },
+  "e": "x\"\u00e9y"
+}"#;
+        assert_eq!(pretty(src, "  ").unwrap(), expected.as_bytes());
+    }
+
+    #[test]
+    fn test_minify() {
+        let src = b"[\n\ttrue,\n\tfalse,\n\t{ \"k\": \"v w\" }\n]\n";
+        assert_eq!(minify(src).unwrap(), b"[true,false,{\"k\":\"v w\"}]\n");
+        assert_eq!(minify(b"  42  ").unwrap(), b"42");
+    }
+
+    #[test]
+    fn test_errors() {
+        assert_eq!(minify(b""), Err(0));
+        assert_eq!(minify(b"[1,]"), Err(3));
+        assert_eq!(minify(b"{\"a\" 1}"), Err(5));
+        assert_eq!(minify(b"{\"a\":1"), Err(6));
+        assert_eq!(minify(b"[01]"), Err(2));
+        assert_eq!(minify(b"\"\\x\""), Err(2));
+        assert_eq!(minify(b"[tru]"), Err(4));
+        assert_eq!(minify(b"[1] [2]"), Err(4));
+        assert_eq!(minify(b"{1:2}"), Err(1));
+    }
+}
@@ -15,6 +15,11 @@
     feature(stdarch_loongarch, stdarch_loongarch_feature_detection, loongarch_target_feature),
     allow(clippy::incompatible_msrv)
 )]
+#![cfg_attr(
+    target_arch = "riscv64",
+    feature(stdarch_riscv_feature_detection, riscv_target_feature),
+    allow(clippy::incompatible_msrv)
+)]
 #![allow(clippy::missing_transmute_annotations, clippy::new_without_default, stable_features)]
 
 #[macro_use]
@@ -23,6 +28,7 @@ pub mod arena;
 pub mod apperr;
 pub mod base64;
 pub mod buffer;
+pub mod capabilities;
 pub mod cell;
 pub mod clipboard;
 pub mod document;
@@ -31,9 +37,12 @@ pub mod fuzzy;
 pub mod hash;
 pub mod helpers;
 pub mod icu;
+pub mod image;
 pub mod input;
+pub mod json;
 pub mod oklab;
 pub mod path;
+pub mod recording;
 pub mod simd;
 pub mod sys;
 pub mod tui;
@@ -4,10 +4,12 @@
 //! Oklab colorspace conversions.
 //!
 //! Implements Oklab as defined at: <https://bottosson.github.io/posts/oklab/>
+//! and its polar form OKLCH, which is more convenient for deriving colors.
 
 #![allow(clippy::excessive_precision)]
 
 use std::fmt::Debug;
+use std::sync::OnceLock;
 
 use crate::simd::MemsetSafe;
 
@@ -94,6 +96,69 @@ impl StraightRgba {
 
         Oklab([l, a, b, alpha])
     }
+
+    pub fn as_oklch(self) -> Oklch {
+        self.as_oklab().as_oklch()
+    }
+
+    /// Returns the relative luminance as defined by WCAG 2, ignoring alpha.
+    pub fn luminance(self) -> f32 {
+        0.2126 * srgb_to_linear(self.red())
+            + 0.7152 * srgb_to_linear(self.green())
+            + 0.0722 * srgb_to_linear(self.blue())
+    }
+
+    /// Returns the WCAG 2 contrast ratio between `self` and `other`, ignoring alpha.
+    /// It ranges from 1 (no contrast) to 21 (black and white).
+    pub fn contrast_ratio(self, other: StraightRgba) -> f32 {
+        let a = self.luminance();
+        let b = other.luminance();
+        (a.max(b) + 0.05) / (a.min(b) + 0.05)
+    }
+
+    /// Returns the APCA lightness contrast (Lc) of `self` as text on the background `bg`,
+    /// ignoring alpha. It's positive for dark text on light backgrounds and negative otherwise,
+    /// ranging from roughly -108 to 106. Unlike the WCAG 2 ratio, it's perceptually uniform,
+    /// in particular for dark colors. See: <https://github.com/Myndex/apca-w3>
+    ///
+    /// As a rule of thumb, body text wants |Lc| >= 60, and any text at all |Lc| >= 30.
+    pub fn apca_contrast(self, bg: StraightRgba) -> f32 {
+        // APCA-W3 0.0.98G-4g constants.
+        fn screen_luminance(c: StraightRgba) -> f32 {
+            let f = |c: u32| (c as f32 * (1.0 / 255.0)).powf(2.4);
+            let y = 0.2126729 * f(c.red()) + 0.7151522 * f(c.green()) + 0.0721750 * f(c.blue());
+            // Soft clamp for near-black colors, to account for flare.
+            if y < 0.022 { y + (0.022 - y).powf(1.414) } else { y }
+        }
+
+        let text = screen_luminance(self);
+        let bg = screen_luminance(bg);
+
+        if (bg - text).abs() < 0.0005 {
+            return 0.0;
+        }
+
+        if bg > text {
+            let sapc = (bg.powf(0.56) - text.powf(0.57)) * 1.14;
+            if sapc < 0.1 { 0.0 } else { (sapc - 0.027) * 100.0 }
+        } else {
+            let sapc = (bg.powf(0.65) - text.powf(0.62)) * 1.14;
+            if sapc > -0.1 { 0.0 } else { (sapc + 0.027) * 100.0 }
+        }
+    }
+
+    /// Returns the color among `candidates` that is most readable as text on `self`,
+    /// as judged by [`StraightRgba::apca_contrast`].
+    ///
+    /// # Panics
+    ///
+    /// Panics if `candidates` is empty.
+    pub fn most_readable(self, candidates: &[StraightRgba]) -> StraightRgba {
+        *candidates
+            .iter()
+            .max_by(|a, b| a.apca_contrast(self).abs().total_cmp(&b.apca_contrast(self).abs()))
+            .unwrap()
+    }
 }
 
 impl Debug for StraightRgba {
@@ -109,6 +174,11 @@ unsafe impl MemsetSafe for StraightRgba {}
 pub struct Oklab([f32; 4]);
 
 impl Oklab {
+    #[inline]
+    pub const fn new(lightness: f32, a: f32, b: f32, alpha: f32) -> Self {
+        Self([lightness, a, b, alpha])
+    }
+
     #[inline]
     pub const fn lightness(self) -> f32 {
         self.0[0]
@@ -155,6 +225,21 @@ impl Oklab {
         StraightRgba(r | (g << 8) | (b << 16) | (a << 24))
     }
 
+    pub fn as_oklch(&self) -> Oklch {
+        let chroma = self.a().hypot(self.b());
+        let hue = self.b().atan2(self.a()).to_degrees().rem_euclid(360.0);
+        Oklch([self.lightness(), chroma, hue, self.alpha()])
+    }
+
+    /// Returns the squared euclidean distance to `other`, ignoring alpha.
+    /// Thanks to the perceptual uniformity of Oklab, this is a decent measure of color difference.
+    pub fn distance_squared(&self, other: &Self) -> f32 {
+        let l = self.lightness() - other.lightness();
+        let a = self.a() - other.a();
+        let b = self.b() - other.b();
+        l * l + a * a + b * b
+    }
+
     /// Porter-Duff "over" composition. It's for Lab, but it works just like with RGB.
     /// The benefit of the Oklab colorspace is its perceptual uniformity, which RGB lacks.
     /// This can be observed easily when blending red and green for instance.
@@ -175,6 +260,139 @@ impl Oklab {
     }
 }
 
+/// An OKLCH color with alpha: The polar form of [`Oklab`].
+/// Lightness and chroma are the same as in Oklab, and the hue is in degrees.
+///
+/// Varying only one of them results in perceptually consistent changes,
+/// which makes it suitable to derive a palette from a few seed colors.
+#[derive(Clone, Copy)]
+pub struct Oklch([f32; 4]);
+
+impl Oklch {
+    #[inline]
+    pub const fn new(lightness: f32, chroma: f32, hue: f32, alpha: f32) -> Self {
+        Self([lightness, chroma, hue, alpha])
+    }
+
+    #[inline]
+    pub const fn lightness(self) -> f32 {
+        self.0[0]
+    }
+
+    #[inline]
+    pub const fn chroma(self) -> f32 {
+        self.0[1]
+    }
+
+    #[inline]
+    pub const fn hue(self) -> f32 {
+        self.0[2]
+    }
+
+    #[inline]
+    pub const fn alpha(self) -> f32 {
+        self.0[3]
+    }
+
+    pub fn with_lightness(self, lightness: f32) -> Self {
+        Self([lightness, self.chroma(), self.hue(), self.alpha()])
+    }
+
+    pub fn with_chroma(self, chroma: f32) -> Self {
+        Self([self.lightness(), chroma, self.hue(), self.alpha()])
+    }
+
+    pub fn with_hue(self, hue: f32) -> Self {
+        Self([self.lightness(), self.chroma(), hue.rem_euclid(360.0), self.alpha()])
+    }
+
+    pub fn with_alpha(self, alpha: f32) -> Self {
+        Self([self.lightness(), self.chroma(), self.hue(), alpha])
+    }
+
+    /// Rotates the hue by the given number of degrees.
+    pub fn rotate_hue(self, degrees: f32) -> Self {
+        self.with_hue(self.hue() + degrees)
+    }
+
+    /// Returns `steps` colors with the lightness evenly spaced from this color's to `lightness`,
+    /// including both ends. Chroma, hue and alpha stay the same.
+    pub fn lightness_ramp(self, lightness: f32, steps: usize) -> impl Iterator<Item = Self> {
+        let beg = self.lightness();
+        let step = if steps > 1 { (lightness - beg) / (steps - 1) as f32 } else { 0.0 };
+        (0..steps).map(move |i| self.with_lightness(beg + step * i as f32))
+    }
+
+    pub fn as_oklab(&self) -> Oklab {
+        let (sin, cos) = self.hue().to_radians().sin_cos();
+        Oklab([self.lightness(), self.chroma() * cos, self.chroma() * sin, self.alpha()])
+    }
+
+    /// Colors outside of the sRGB gamut get their channels clamped.
+    pub fn as_rgba(&self) -> StraightRgba {
+        self.as_oklab().as_rgba()
+    }
+}
+
+/// The default colors of xterm for the 16 VT colors.
+/// These vary between terminals, unlike the rest of the 256-color palette.
+#[rustfmt::skip]
+const XTERM_VT_COLORS: [u32; 16] = [
+    0x000000, 0xcd0000, 0x00cd00, 0xcdcd00, 0x0000ee, 0xcd00cd, 0x00cdcd, 0xe5e5e5,
+    0x7f7f7f, 0xff0000, 0x00ff00, 0xffff00, 0x5c5cff, 0xff00ff, 0x00ffff, 0xffffff,
+];
+
+/// Returns the color of the xterm 256-color palette entry `index`.
+/// Entries 16-231 are a 6x6x6 color cube and 232-255 a grayscale ramp.
+pub fn xterm_color(index: u8) -> StraightRgba {
+    let (r, g, b) = match index {
+        0..16 => {
+            let c = XTERM_VT_COLORS[index as usize];
+            (c >> 16, (c >> 8) & 0xff, c & 0xff)
+        }
+        16..232 => {
+            let i = index as u32 - 16;
+            let level = |v: u32| if v == 0 { 0 } else { 55 + 40 * v };
+            (level(i / 36), level(i / 6 % 6), level(i % 6))
+        }
+        _ => {
+            let v = 8 + 10 * (index as u32 - 232);
+            (v, v, v)
+        }
+    };
+    StraightRgba(0xff000000 | b << 16 | g << 8 | r)
+}
+
+/// Returns the index of the perceptually nearest color in `palette`, ignoring alpha.
+/// Returns 0 if `palette` is empty.
+pub fn nearest_color(palette: &[StraightRgba], color: StraightRgba) -> usize {
+    let target = color.as_oklab();
+    nearest_oklab(palette.iter().map(|c| c.as_oklab()), &target)
+}
+
+/// Returns the index of the perceptually nearest entry among 16-255 of the xterm 256-color palette.
+/// The 16 VT colors are skipped, because they vary between terminals. See [`nearest_color`].
+pub fn nearest_xterm_color(color: StraightRgba) -> u8 {
+    static TABLE: OnceLock<[Oklab; 240]> = OnceLock::new();
+    let table = TABLE.get_or_init(|| std::array::from_fn(|i| xterm_color(i as u8 + 16).as_oklab()));
+    16 + nearest_oklab(table.iter().copied(), &color.as_oklab()) as u8
+}
+
+fn nearest_oklab(palette: impl Iterator<Item = Oklab>, target: &Oklab) -> usize {
+    let mut best = 0;
+    let mut best_distance = f32::MAX;
+
+    for (i, c) in palette.enumerate() {
+        let distance = c.distance_squared(target);
+        if distance < best_distance {
+            best = i;
+            best_distance = distance;
+        }
+    }
+
+    best
+}
+
 fn srgb_to_linear(c: u32) -> f32 {
     SRGB_TO_RGB_LUT[(c & 0xff) as usize]
 }
@@ -239,4 +457,68 @@ mod tests {
         let blended = lower.oklab_blend(upper);
         assert_eq!(blended, expected);
     }
+
+    #[test]
+    fn test_oklch() {
+        let orange = StraightRgba::from_be(0xff8000ff);
+        let lch = orange.as_oklch();
+        assert!((lch.lightness() - 0.73).abs() < 0.01);
+        assert!((lch.chroma() - 0.19).abs() < 0.01);
+        assert!((lch.hue() - 53.0).abs() < 1.0);
+        assert!(lch.as_oklab().distance_squared(&orange.as_oklab()) < 1e-9);
+
+        // Hues wrap around.
+        assert!((lch.rotate_hue(-90.0).hue() - 323.0).abs() < 1.0);
+        assert!((lch.rotate_hue(360.0).hue() - lch.hue()).abs() < 0.01);
+
+        let ramp: Vec<_> = lch.lightness_ramp(0.33, 5).map(|c| c.lightness()).collect();
+        assert_eq!(ramp.len(), 5);
+        assert!((ramp[2] - 0.53).abs() < 0.01);
+        assert!((ramp[4] - 0.33).abs() < 0.0001);
+        assert!(ramp.windows(2).all(|w| w[0] > w[1]));
+    }
+
+    #[test]
+    fn test_nearest_color() {
+        assert_eq!(xterm_color(1), StraightRgba::from_be(0xcd0000ff));
+        assert_eq!(xterm_color(196), StraightRgba::from_be(0xff0000ff));
+        assert_eq!(xterm_color(110), StraightRgba::from_be(0x87afd7ff));
+        assert_eq!(xterm_color(232), StraightRgba::from_be(0x080808ff));
+        assert_eq!(xterm_color(255), StraightRgba::from_be(0xeeeeeeff));
+
+        // Every palette entry maps to itself.
+        for i in 16..=255 {
+            assert_eq!(nearest_xterm_color(xterm_color(i)), i);
+        }
+        assert_eq!(nearest_xterm_color(StraightRgba::from_be(0xfe0101ff)), 196);
+        assert_eq!(nearest_xterm_color(StraightRgba::from_be(0x7f7f7fff)), 244);
+
+        let vt: Vec<_> = (0..16).map(xterm_color).collect();
+        assert_eq!(nearest_color(&vt, StraightRgba::from_be(0xd01010ff)), 1);
+        assert_eq!(nearest_color(&vt, StraightRgba::from_be(0x101010ff)), 0);
+        assert_eq!(nearest_color(&[], StraightRgba::from_be(0x101010ff)), 0);
+    }
+
+    #[test]
+    fn test_contrast() {
+        let black = StraightRgba::from_be(0x000000ff);
+        let white = StraightRgba::from_be(0xffffffff);
+        let gray = StraightRgba::from_be(0x888888ff);
+        let green = StraightRgba::from_be(0x3fae3aff);
+
+        assert!((black.contrast_ratio(white) - 21.0).abs() < 0.01);
+        assert!((white.contrast_ratio(black) - 21.0).abs() < 0.01);
+        assert!((gray.contrast_ratio(gray) - 1.0).abs() < 0.01);
+
+        // Reference values from the APCA-W3 implementation.
+        assert!((black.apca_contrast(white) - 106.04).abs() < 0.01);
+        assert!((white.apca_contrast(black) + 107.88).abs() < 0.01);
+        assert!((gray.apca_contrast(white) - 63.06).abs() < 0.01);
+        assert_eq!(gray.apca_contrast(gray), 0.0);
+
+        // The WCAG 2 ratio famously prefers black text on mid-tones. APCA doesn't.
+        assert!(black.contrast_ratio(green) > white.contrast_ratio(green));
+        assert_eq!(green.most_readable(&[black, white]), white);
+        assert_eq!(StraightRgba::from_be(0xffc944ff).most_readable(&[black, white]), black);
+    }
 }
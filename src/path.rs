@@ -3,9 +3,264 @@
 
 //! Path related helpers.
 
+use std::borrow::Cow;
+use std::cmp::Ordering;
 use std::ffi::{OsStr, OsString};
+use std::fs;
 use std::path::{Component, MAIN_SEPARATOR_STR, Path, PathBuf};
 
+use crate::helpers::{CoordType, Point};
+use crate::sys::{self, KnownDir};
+use crate::unicode::{MeasurementConfig, measure_width};
+
+/// Returns the directory that the editor keeps the given kind of files in.
+/// It doesn't get created until someone writes into it.
+pub fn app_dir(dir: KnownDir) -> Option<PathBuf> {
+    let mut path = sys::known_dir(dir)?;
+    path.push("edit");
+    Some(path)
+}
+
+/// Turns a path as typed by the user into an absolute, normalized one.
+///
+/// A leading `~` or `~user` is replaced with the home directory, and `$VAR`, `${VAR}`,
+/// as well as `%VAR%` on Windows, with the value of the environment variable.
+/// Unlike in shells, unknown users and variables are kept as is, because
+/// there's a good chance that they're actually part of a file name.
+/// Relative results are joined with `cwd`, after which the path gets [`normalize`]d.
+pub fn expand(cwd: &Path, path: &Path) -> PathBuf {
+    let path = expand_with(path.as_os_str(), sys::home_dir, |name| std::env::var_os(name));
+    normalize(&cwd.join(path))
+}
+
+fn expand_with(
+    path: &OsStr,
+    home_dir: impl Fn(Option<&OsStr>) -> Option<PathBuf>,
+    var: impl Fn(&OsStr) -> Option<OsString>,
+) -> OsString {
+    let src = path.as_encoded_bytes();
+    let mut res = Vec::with_capacity(src.len());
+    let mut beg = 0;
+
+    if let Some(rest) = src.strip_prefix(b"~") {
+        let len = rest.iter().position(|&b| is_separator(b)).unwrap_or(rest.len());
+        let user = if len == 0 {
+            None
+        } else {
+            Some(unsafe { OsStr::from_encoded_bytes_unchecked(&rest[..len]) })
+        };
+        if let Some(home) = home_dir(user) {
+            res.extend_from_slice(home.as_os_str().as_encoded_bytes());
+            beg = 1 + len;
+        }
+    }
+
+    let mut off = beg;
+    while off < src.len() {
+        let b = src[off];
+        let (name, end) = match b {
+            b'$' if src.get(off + 1) == Some(&b'{') => {
+                match src[off + 2..].iter().position(|&b| b == b'}') {
+                    Some(len) => (&src[off + 2..off + 2 + len], off + 3 + len),
+                    None => (&src[..0], off + 1),
+                }
+            }
+            b'$' => {
+                let rest = &src[off + 1..];
+                let len = rest
+                    .iter()
+                    .position(|&b| !(b.is_ascii_alphanumeric() || b == b'_'))
+                    .unwrap_or(rest.len());
+                (&rest[..len], off + 1 + len)
+            }
+            b'%' if cfg!(windows) => match src[off + 1..].iter().position(|&b| b == b'%') {
+                Some(len) => (&src[off + 1..off + 1 + len], off + 2 + len),
+                None => (&src[..0], off + 1),
+            },
+            _ => {
+                off += 1;
+                continue;
+            }
+        };
+
+        let value = if name.is_empty() || name[0].is_ascii_digit() {
+            None
+        } else {
+            var(unsafe { OsStr::from_encoded_bytes_unchecked(name) })
+        };
+        if let Some(value) = value {
+            res.extend_from_slice(&src[beg..off]);
+            res.extend_from_slice(value.as_encoded_bytes());
+            beg = end;
+            off = end;
+        } else {
+            off += 1;
+        }
+    }
+
+    res.extend_from_slice(&src[beg..]);
+    // SAFETY: We only split `src` at ASCII characters and joined it with other `OsStr`s.
+    unsafe { OsString::from_encoded_bytes_unchecked(res) }
+}
+
+fn is_separator(b: u8) -> bool {
+    b == b'/' || (cfg!(windows) && b == b'\\')
+}
+
+/// Abbreviates `path` for display, so that it fits into `width` columns.
+///
+/// The home directory gets replaced with `~`. If that isn't enough, directories
+/// are dropped from the middle, keeping the root and as many trailing components
+/// as fit, e.g. `~/…/src/main.rs`. As a last resort, the text gets cut in the middle.
+pub fn shorten_for_display(path: &Path, width: CoordType) -> String {
+    let home = sys::home_dir(None);
+    let home = home.as_deref().map(|h| h.to_string_lossy());
+    shorten_with(&path.to_string_lossy(), home.as_deref(), width)
+}
+
+fn shorten_with(path: &str, home: Option<&str>, width: CoordType) -> String {
+    let mut path = Cow::Borrowed(path);
+
+    if let Some(home) =
+        home.map(|h| h.trim_end_matches(|c: char| c.is_ascii() && is_separator(c as u8)))
+        && !home.is_empty()
+        && let Some(rest) = path.strip_prefix(home)
+        && rest.bytes().next().is_none_or(is_separator)
+    {
+        path = Cow::Owned(format!("~{rest}"));
+    }
+
+    if measure_width(&path) <= width {
+        return path.into_owned();
+    }
+
+    // The root is either the leading separators (`/`, `\\server`),
+    // or everything up to the first one (`~/`, `C:\`).
+    let bytes = path.as_bytes();
+    let root_len = match bytes.iter().position(|&b| !is_separator(b)) {
+        Some(0) => bytes.iter().position(|&b| is_separator(b)).map_or(0, |i| i + 1),
+        Some(i) => i,
+        None => bytes.len(),
+    };
+
+    // Keep as many trailing components as fit, starting with the file name.
+    let mut best = None;
+    let root_width = measure_width(&path[..root_len]) + 1;
+    for (i, _) in bytes.iter().enumerate().skip(root_len).rev().filter(|&(_, &b)| is_separator(b)) {
+        let tail = &path[i..];
+        if root_width + measure_width(tail) > width {
+            break;
+        }
+        best = Some(tail);
+    }
+    if let Some(tail) = best {
+        return format!("{}…{}", &path[..root_len], tail);
+    }
+
+    // Cut the text in the middle, the same way `Overflow::TruncateMiddle` does.
+    if width <= 0 {
+        return String::new();
+    }
+    let mut cfg = MeasurementConfig::new(&bytes);
+    let actual_width = measure_width(&path);
+    let beg = cfg.goto_visual(Point { x: (width - 1) / 2, y: 0 });
+    let end = cfg.goto_visual(Point { x: actual_width - width / 2, y: 0 });
+    format!("{}…{}", &path[..beg.offset], &path[end.offset..])
+}
+
+/// Whether the file system compares file names case-insensitively.
+/// That's the default on Windows and macOS, even if either can be configured otherwise.
+const CASE_INSENSITIVE: bool = cfg!(any(windows, target_vendor = "apple"));
+
+/// Compares two paths the way the file system would (usually), component by component.
+/// On Windows and macOS that's case-insensitive, and on Windows
+/// `\\?\` verbatim prefixes and `/` vs. `\` separators are ignored as well.
+pub fn compare(a: &Path, b: &Path) -> Ordering {
+    let a = strip_verbatim_prefix(a);
+    let b = strip_verbatim_prefix(b);
+    if !CASE_INSENSITIVE {
+        return a.cmp(&b);
+    }
+
+    // Converting the components to `str` is lossy, but then again that only happens
+    // for invalid UTF-16 on Windows, which can't be compared case-insensitively anyway.
+    let lower = |c: Component<'_>| {
+        let str = c.as_os_str().to_string_lossy();
+        str.chars().flat_map(char::to_lowercase).collect::<String>()
+    };
+    a.components().map(lower).cmp(b.components().map(lower))
+}
+
+/// Whether the two paths refer to the same file, as far as that can be told
+/// without touching the file system. See [`compare`].
+pub fn equals(a: &Path, b: &Path) -> bool {
+    compare(a, b) == Ordering::Equal
+}
+
+/// Removes the `\\?\` and `\\?\UNC\` prefixes that Windows uses for verbatim paths,
+/// if the path can be represented without them. It's a no-op on other platforms.
+pub fn strip_verbatim_prefix(path: &Path) -> Cow<'_, Path> {
+    if cfg!(windows) {
+        strip_verbatim_prefix_bytes(path.as_os_str().as_encoded_bytes()).map_or(
+            Cow::Borrowed(path),
+            |bytes| {
+                // SAFETY: We only split and joined it at ASCII characters.
+                Cow::Owned(PathBuf::from(unsafe { OsString::from_encoded_bytes_unchecked(bytes) }))
+            },
+        )
+    } else {
+        Cow::Borrowed(path)
+    }
+}
+
+fn strip_verbatim_prefix_bytes(path: &[u8]) -> Option<Vec<u8>> {
+    let rest = path.strip_prefix(br"\\?\")?;
+
+    if let [drive, b':', ..] = rest
+        && drive.is_ascii_alphabetic()
+        && (rest.len() == 2 || rest[2] == b'\\')
+    {
+        return Some(rest.to_vec());
+    }
+
+    if rest.len() > 4 && rest[..4].eq_ignore_ascii_case(br"UNC\") {
+        let mut res = Vec::with_capacity(rest.len() - 2);
+        res.extend_from_slice(br"\\");
+        res.extend_from_slice(&rest[4..]);
+        return Some(res);
+    }
+
+    None
+}
+
+/// Returns the absolute, normalized form of `path` with all symlinks resolved.
+///
+/// Unlike [`fs::canonicalize`], the path doesn't have to exist: If it doesn't, its longest
+/// existing ancestor gets canonicalized and the rest is appended as is. On Windows,
+/// verbatim prefixes are stripped if possible, since they're confusing to look at.
+pub fn canonicalize(path: &Path) -> PathBuf {
+    let path = normalize(path);
+    let mut base = path.as_path();
+    let mut rest = Vec::new();
+
+    let canonical = loop {
+        if let Ok(canonical) = fs::canonicalize(base) {
+            break canonical;
+        }
+        match (base.parent(), base.file_name()) {
+            (Some(parent), Some(name)) => {
+                rest.push(name);
+                base = parent;
+            }
+            _ => return path,
+        }
+    };
+
+    let mut canonical = strip_verbatim_prefix(&canonical).into_owned();
+    canonical.extend(rest.iter().rev());
+    canonical
+}
+
 /// Normalizes a given path by removing redundant components.
 /// The given path must be absolute (e.g. by joining it with the current working directory).
 pub fn normalize(path: &Path) -> PathBuf {
@@ -55,6 +310,112 @@ mod tests {
         normalize(Path::new(s)).into_os_string()
     }
 
+    fn exp(s: &str) -> OsString {
+        let home_dir = |user: Option<&OsStr>| match user.and_then(|u| u.to_str()) {
+            None => Some(PathBuf::from("/home/me")),
+            Some("bob") => Some(PathBuf::from("/home/bob")),
+            Some(_) => None,
+        };
+        let var = |name: &OsStr| match name.to_str()? {
+            "FOO" => Some(OsString::from("foo")),
+            "EMPTY" => Some(OsString::new()),
+            _ => None,
+        };
+        expand_with(OsStr::new(s), home_dir, var)
+    }
+
+    #[test]
+    fn test_compare() {
+        assert!(equals(Path::new("/a/b"), Path::new("/a//b/")));
+        assert!(!equals(Path::new("/a/b"), Path::new("/a/c")));
+        assert_eq!(compare(Path::new("/a/b"), Path::new("/a/c")), Ordering::Less);
+        assert_eq!(equals(Path::new("/a/B"), Path::new("/a/b")), CASE_INSENSITIVE);
+        assert_eq!(equals(Path::new("/Ä"), Path::new("/ä")), CASE_INSENSITIVE);
+    }
+
+    #[test]
+    fn test_strip_verbatim_prefix() {
+        let strip = |s: &str| {
+            strip_verbatim_prefix_bytes(s.as_bytes()).map(|b| String::from_utf8(b).unwrap())
+        };
+        assert_eq!(strip(r"\\?\C:\a\b").as_deref(), Some(r"C:\a\b"));
+        assert_eq!(strip(r"\\?\C:").as_deref(), Some(r"C:"));
+        assert_eq!(strip(r"\\?\UNC\server\share\a").as_deref(), Some(r"\\server\share\a"));
+        assert_eq!(strip(r"\\?\Volume{1234}\a"), None);
+        assert_eq!(strip(r"\\?\C:a"), None);
+        assert_eq!(strip(r"C:\a"), None);
+        assert_eq!(strip(r"\\server\share"), None);
+    }
+
+    #[test]
+    fn test_canonicalize() {
+        let dir =
+            std::env::temp_dir().join(format!("edit-test-canonicalize-{}", std::process::id()));
+        _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(dir.join("real")).unwrap();
+        let dir = fs::canonicalize(&dir).unwrap();
+        let dir = strip_verbatim_prefix(&dir).into_owned();
+
+        assert_eq!(
+            canonicalize(&dir.join("real/./missing/../a.txt")),
+            dir.join("real").join("a.txt")
+        );
+        assert_eq!(canonicalize(&dir.join("missing/a.txt")), dir.join("missing").join("a.txt"));
+
+        #[cfg(unix)]
+        {
+            std::os::unix::fs::symlink(dir.join("real"), dir.join("link")).unwrap();
+            assert_eq!(canonicalize(&dir.join("link/a.txt")), dir.join("real").join("a.txt"));
+        }
+
+        _ = fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn test_shorten_for_display() {
+        let home = Some("/home/me");
+        assert_eq!(shorten_with("/home/me/a/b.txt", home, 100), "~/a/b.txt");
+        assert_eq!(shorten_with("/home/me", home, 100), "~");
+        assert_eq!(shorten_with("/home/meow/a", home, 100), "/home/meow/a");
+        assert_eq!(shorten_with("/usr/lib/a/b", home, 100), "/usr/lib/a/b");
+
+        let path = "/home/me/src/edit/src/bin/main.rs";
+        assert_eq!(shorten_with(path, home, 31), "~/src/edit/src/bin/main.rs");
+        assert_eq!(shorten_with(path, home, 20), "~/…/src/bin/main.rs");
+        assert_eq!(shorten_with(path, home, 12), "~/…/main.rs");
+        assert_eq!(shorten_with(path, None, 20), "/…/src/bin/main.rs");
+        assert_eq!(shorten_with(path, None, 12), "/…/main.rs");
+
+        // The file name alone is too long.
+        assert_eq!(shorten_with("/a/abcdefghij", None, 7), "/a/…hij");
+        assert_eq!(shorten_with("日本語日本語", None, 7), "日…本語");
+        assert_eq!(shorten_with("abc", None, 0), "");
+    }
+
+    #[test]
+    fn test_expand() {
+        assert_eq!(exp("~"), "/home/me");
+        assert_eq!(exp("~/a"), "/home/me/a");
+        assert_eq!(exp("~bob/a"), "/home/bob/a");
+        assert_eq!(exp("~alice/a"), "~alice/a");
+        assert_eq!(exp("a/~"), "a/~");
+
+        assert_eq!(exp("$FOO/a"), "foo/a");
+        assert_eq!(exp("a${FOO}b"), "afoob");
+        assert_eq!(exp("a$FOO.b"), "afoo.b");
+        assert_eq!(exp("a$EMPTY/b"), "a/b");
+        assert_eq!(exp("$BAR/$FOO"), "$BAR/foo");
+        assert_eq!(exp("$1$ ${FOO"), "$1$ ${FOO");
+        assert_eq!(exp("~/$FOO"), "/home/me/foo");
+
+        #[cfg(windows)]
+        {
+            assert_eq!(exp(r"%FOO%\a"), r"foo\a");
+            assert_eq!(exp(r"100%\%FOO%"), r"100%\foo");
+            assert_eq!(exp(r"~\a"), r"/home/me\a");
+        }
+    }
+
     #[cfg(unix)]
     #[test]
     fn test_unix() {
@@ -67,6 +428,8 @@ mod tests {
         assert_eq!(norm("/a//b/c"), "/a/b/c");
         assert_eq!(norm("/a/b/c/../../../../d"), "/d");
         assert_eq!(norm("//"), "/");
+        assert_eq!(expand(Path::new("/a"), Path::new("b/./../c")), Path::new("/a/c"));
+        assert_eq!(expand(Path::new("/a"), Path::new("/b/c/..")), Path::new("/b"));
     }
 
     #[cfg(windows)]
@@ -0,0 +1,240 @@
+// Copyright (c) Microsoft Corporation.
+// Licensed under the MIT License.
+
+//! Recording and replaying raw terminal input.
+//!
+//! A recording captures every read from stdin, including empty ones caused by timeouts,
+//! so that replaying it produces the same sequence of frames. It's meant for regression
+//! tests and for reproducing bugs that users run into.
+//!
+//! The file format is that of asciicast v2, with input events only:
+//! ```text
+//! {"version": 2, "width": 80, "height": 24}
+//! [0.015312, "i", "\u001b[?2026;2$y"]
+//! [1.204870, "i", "a"]
+//! ```
+
+use std::fmt::Write as _;
+use std::io::{self, Write};
+use std::time::{Duration, Instant};
+
+use crate::arena::scratch_arena;
+use crate::helpers::{CoordType, Size};
+use crate::input::{self, Input};
+use crate::tui::{Context, Tui};
+use crate::{apperr, vt};
+
+/// Writes the input it's given to a recording.
+pub struct Recorder<W: Write> {
+    writer: W,
+    start: Instant,
+    line: String,
+}
+
+impl<W: Write> Recorder<W> {
+    /// Starts a new recording for a terminal of the given size.
+    pub fn new(mut writer: W, size: Size) -> io::Result<Self> {
+        writeln!(
+            writer,
+            r#"{{"version": 2, "width": {}, "height": {}}}"#,
+            size.width, size.height
+        )?;
+        Ok(Self { writer, start: Instant::now(), line: String::new() })
+    }
+
+    /// Appends the result of a read from stdin. Pass an empty string for timeouts.
+    pub fn record(&mut self, input: &str) -> io::Result<()> {
+        self.line.clear();
+        _ = write!(self.line, "[{:.6}, \"i\", ", self.start.elapsed().as_secs_f64());
+        json_escape(&mut self.line, input);
+        self.line.push_str("]\n");
+        self.writer.write_all(self.line.as_bytes())
+    }
+}
+
+/// A single read from stdin.
+pub struct Event {
+    /// When the input was read, relative to the start of the recording.
+    pub time: Duration,
+    pub input: String,
+}
+
+/// A parsed recording. See [`Recorder`].
+pub struct Recording {
+    /// The size of the terminal when the recording started.
+    pub size: Size,
+    pub events: Vec<Event>,
+}
+
+impl Recording {
+    /// Parses a recording. Lines that aren't input events are ignored,
+    /// so that asciicast files with output events can be used as well.
+    pub fn parse(text: &str) -> apperr::Result<Self> {
+        let mut lines = text.lines();
+        let header = lines.next().unwrap_or("");
+        let size = Size {
+            width: json_number(header, "width").ok_or(apperr::APP_JSON_INVALID)? as CoordType,
+            height: json_number(header, "height").ok_or(apperr::APP_JSON_INVALID)? as CoordType,
+        };
+        let mut events = Vec::new();
+
+        for line in lines {
+            let Some(line) = line.trim().strip_prefix('[').and_then(|l| l.strip_suffix(']')) else {
+                continue;
+            };
+            let Some((time, rest)) = line.split_once(',') else {
+                continue;
+            };
+            let Some(data) = rest.trim_start().strip_prefix(r#""i","#) else {
+                continue;
+            };
+            let time = time.trim().parse::<f64>().map_err(|_| apperr::APP_JSON_INVALID)?;
+            let input = json_unescape(data.trim()).ok_or(apperr::APP_JSON_INVALID)?;
+            events.push(Event { time: Duration::from_secs_f64(time.max(0.0)), input });
+        }
+
+        Ok(Self { size, events })
+    }
+
+    /// Feeds the recording through the same pipeline as the editor:
+    /// [`vt::Parser`], [`input::Parser`] and [`Tui`], calling `draw` for each frame.
+    ///
+    /// It doesn't wait between the events, but the VT parser is told the recorded
+    /// timestamps, so that its timeouts behave the same as during the recording.
+    pub fn replay(&self, tui: &mut Tui, mut draw: impl FnMut(&mut Context)) {
+        let mut vt_parser = vt::Parser::new();
+        let mut input_parser = input::Parser::new();
+        let start = Instant::now();
+
+        let mut frame = |tui: &mut Tui, input: Option<Input>| {
+            let mut ctx = tui.create_context(input);
+            draw(&mut ctx);
+        };
+
+        frame(tui, Some(Input::Resize(self.size)));
+
+        for event in &self.events {
+            let vt_iter = vt_parser.parse_at(&event.input, start + event.time);
+            for input in input_parser.parse(vt_iter) {
+                frame(tui, Some(input));
+            }
+            frame(tui, None);
+            while tui.needs_settling() {
+                frame(tui, None);
+            }
+            tui.render(&scratch_arena(None));
+        }
+    }
+}
+
+fn json_escape(dst: &mut String, s: &str) {
+    dst.push('"');
+    for c in s.chars() {
+        match c {
+            '"' => dst.push_str("\\\""),
+            '\\' => dst.push_str("\\\\"),
+            '\n' => dst.push_str("\\n"),
+            '\r' => dst.push_str("\\r"),
+            '\t' => dst.push_str("\\t"),
+            '\0'..='\x1f' | '\x7f' => _ = write!(dst, "\\u{:04x}", c as u32),
+            _ => dst.push(c),
+        }
+    }
+    dst.push('"');
+}
+
+fn json_unescape(s: &str) -> Option<String> {
+    let s = s.strip_prefix('"')?.strip_suffix('"')?;
+    let mut out = String::with_capacity(s.len());
+    let mut chars = s.chars();
+
+    let hex4 = |chars: &mut std::str::Chars| -> Option<u32> {
+        let hex = chars.as_str().get(..4)?;
+        let val = u32::from_str_radix(hex, 16).ok()?;
+        chars.nth(3);
+        Some(val)
+    };
+
+    while let Some(c) = chars.next() {
+        if c != '\\' {
+            out.push(c);
+            continue;
+        }
+        let c = match chars.next()? {
+            'n' => '\n',
+            'r' => '\r',
+            't' => '\t',
+            'b' => '\x08',
+            'f' => '\x0c',
+            'u' => {
+                let mut val = hex4(&mut chars)?;
+                // A UTF-16 surrogate pair.
+                if (0xd800..0xdc00).contains(&val) {
+                    chars.as_str().strip_prefix("\\u")?;
+                    chars.nth(1);
+                    let low = hex4(&mut chars)?;
+                    val = 0x10000 + ((val - 0xd800) << 10) + (low.checked_sub(0xdc00)? & 0x3ff);
+                }
+                char::from_u32(val).unwrap_or('\u{fffd}')
+            }
+            c => c,
+        };
+        out.push(c);
+    }
+
+    Some(out)
+}
+
+/// Finds `"key": <number>` in a single-level JSON object.
+fn json_number(json: &str, key: &str) -> Option<u32> {
+    let (_, rest) = json.split_once(&format!("\"{key}\""))?;
+    let rest = rest.trim_start().strip_prefix(':')?.trim_start();
+    let end = rest.find(|c: char| !c.is_ascii_digit()).unwrap_or(rest.len());
+    rest[..end].parse().ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::buffer::TextBuffer;
+
+    #[test]
+    fn test_json_string() {
+        let text = "a\"b\\c\x1b[A\u{1f600}\0";
+        let mut escaped = String::new();
+        json_escape(&mut escaped, text);
+        assert_eq!(escaped, "\"a\\\"b\\\\c\\u001b[A\u{1f600}\\u0000\"");
+        assert_eq!(json_unescape(&escaped).as_deref(), Some(text));
+        assert_eq!(json_unescape(r#""😀\n""#).as_deref(), Some("\u{1f600}\n"));
+        assert_eq!(json_unescape(r#""\u12""#), None);
+    }
+
+    #[test]
+    fn test_record_and_replay() {
+        let mut recorder = Recorder::new(Vec::new(), Size { width: 40, height: 10 }).unwrap();
+        recorder.record("\x1b[200~foo\r\x1b[201~").unwrap();
+        recorder.record("bar").unwrap();
+        recorder.record("\x7f").unwrap();
+
+        let text = String::from_utf8(recorder.writer).unwrap();
+        let recording = Recording::parse(&text).unwrap();
+        assert_eq!(recording.size, Size { width: 40, height: 10 });
+        assert_eq!(recording.events.len(), 3);
+        assert_eq!(recording.events[0].input, "\x1b[200~foo\r\x1b[201~");
+
+        let mut tui = Tui::new().unwrap();
+        let buffer = TextBuffer::new_rc(false).unwrap();
+        let mut focused = false;
+        recording.replay(&mut tui, |ctx| {
+            ctx.textarea("text", buffer.clone());
+            if !focused {
+                ctx.steal_focus();
+                focused = true;
+            }
+        });
+
+        let mut contents = Vec::new();
+        buffer.borrow().extract_range(0..usize::MAX, &mut contents);
+        assert_eq!(contents, b"foo\nba");
+    }
+}
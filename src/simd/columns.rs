@@ -0,0 +1,220 @@
+// Copyright (c) Microsoft Corporation.
+// Licensed under the MIT License.
+
+use crate::helpers::CoordType;
+
+/// Starting from the `offset` in `haystack` at the display column `column`,
+/// this measures printable ASCII characters (1 column each) and tabs (up to the
+/// next multiple of `tab_size`) and returns the new offset and column.
+///
+/// It stops at any other byte, once `column_stop` is reached,
+/// or if the next character would go past `column_stop`.
+/// `tab_size` must be at least 1.
+pub fn columns_fwd(
+    haystack: &[u8],
+    offset: usize,
+    column: CoordType,
+    column_stop: CoordType,
+    tab_size: CoordType,
+) -> (usize, CoordType) {
+    debug_assert!(tab_size >= 1);
+    let offset = offset.min(haystack.len());
+    unsafe { columns_fwd_raw(haystack, offset, column, column_stop, tab_size) }
+}
+
+unsafe fn columns_fwd_raw(
+    haystack: &[u8],
+    offset: usize,
+    column: CoordType,
+    column_stop: CoordType,
+    tab_size: CoordType,
+) -> (usize, CoordType) {
+    #[cfg(any(target_arch = "x86", target_arch = "x86_64"))]
+    return unsafe { COLUMNS_FWD_DISPATCH(haystack, offset, column, column_stop, tab_size) };
+
+    #[cfg(target_arch = "aarch64")]
+    if super::enabled("neon") {
+        return unsafe { columns_fwd_neon(haystack, offset, column, column_stop, tab_size) };
+    }
+
+    #[allow(unreachable_code)]
+    return columns_fwd_fallback(haystack, offset, column, column_stop, tab_size);
+}
+
+fn columns_fwd_fallback(
+    haystack: &[u8],
+    mut offset: usize,
+    mut column: CoordType,
+    column_stop: CoordType,
+    tab_size: CoordType,
+) -> (usize, CoordType) {
+    while offset < haystack.len() {
+        let width = match haystack[offset] {
+            b'\t' => tab_size - (column % tab_size),
+            0x20..=0x7e => 1,
+            _ => break,
+        };
+        if column_stop - column < width {
+            break;
+        }
+        offset += 1;
+        column += width;
+    }
+    (offset, column)
+}
+
+#[cfg(any(target_arch = "x86", target_arch = "x86_64"))]
+type ColumnsFwdFn = unsafe fn(&[u8], usize, CoordType, CoordType, CoordType) -> (usize, CoordType);
+
+// See `MEMCHR2_DISPATCH`.
+#[cfg(any(target_arch = "x86", target_arch = "x86_64"))]
+static mut COLUMNS_FWD_DISPATCH: ColumnsFwdFn = columns_fwd_dispatch;
+
+#[cfg(any(target_arch = "x86", target_arch = "x86_64"))]
+unsafe fn columns_fwd_dispatch(
+    haystack: &[u8],
+    offset: usize,
+    column: CoordType,
+    column_stop: CoordType,
+    tab_size: CoordType,
+) -> (usize, CoordType) {
+    let func = if super::enabled("avx2") {
+        columns_fwd_avx2
+    } else {
+        |h: &[u8], o, c, s, t| columns_fwd_fallback(h, o, c, s, t)
+    };
+    unsafe { COLUMNS_FWD_DISPATCH = func };
+    unsafe { func(haystack, offset, column, column_stop, tab_size) }
+}
+
+// Blocks of printable ASCII are simply 1 column per byte. Anything else (mostly tabs)
+// is measured by the fallback, one block at a time.
+#[cfg(any(target_arch = "x86", target_arch = "x86_64"))]
+#[target_feature(enable = "avx2")]
+unsafe fn columns_fwd_avx2(
+    haystack: &[u8],
+    mut offset: usize,
+    mut column: CoordType,
+    column_stop: CoordType,
+    tab_size: CoordType,
+) -> (usize, CoordType) {
+    unsafe {
+        #[cfg(target_arch = "x86")]
+        use std::arch::x86::*;
+        #[cfg(target_arch = "x86_64")]
+        use std::arch::x86_64::*;
+
+        let beg = haystack.as_ptr();
+        let space = _mm256_set1_epi8(0x1f);
+        let del = _mm256_set1_epi8(0x7f);
+
+        while offset + 32 <= haystack.len() && column_stop - column >= 32 {
+            let v = _mm256_loadu_si256(beg.add(offset) as *const _);
+            // Bytes >= 0x80 are negative and thus fail the first comparison.
+            let printable =
+                _mm256_andnot_si256(_mm256_cmpeq_epi8(v, del), _mm256_cmpgt_epi8(v, space));
+
+            if _mm256_movemask_epi8(printable) == -1 {
+                offset += 32;
+                column += 32;
+                continue;
+            }
+
+            let end = offset + 32;
+            (offset, column) =
+                columns_fwd_fallback(&haystack[..end], offset, column, column_stop, tab_size);
+            if offset != end {
+                return (offset, column);
+            }
+        }
+
+        columns_fwd_fallback(haystack, offset, column, column_stop, tab_size)
+    }
+}
+
+// See `columns_fwd_avx2`.
+#[cfg(target_arch = "aarch64")]
+unsafe fn columns_fwd_neon(
+    haystack: &[u8],
+    mut offset: usize,
+    mut column: CoordType,
+    column_stop: CoordType,
+    tab_size: CoordType,
+) -> (usize, CoordType) {
+    unsafe {
+        use std::arch::aarch64::*;
+
+        let beg = haystack.as_ptr();
+        let space = vdupq_n_u8(0x20);
+        let range = vdupq_n_u8(0x7f - 0x20);
+
+        while offset + 16 <= haystack.len() && column_stop - column >= 16 {
+            let v = vld1q_u8(beg.add(offset));
+            // `v - 0x20 < 0x5f` is true only for 0x20..=0x7e.
+            let printable = vcltq_u8(vsubq_u8(v, space), range);
+
+            if vminvq_u8(printable) == 0xff {
+                offset += 16;
+                column += 16;
+                continue;
+            }
+
+            let end = offset + 16;
+            (offset, column) =
+                columns_fwd_fallback(&haystack[..end], offset, column, column_stop, tab_size);
+            if offset != end {
+                return (offset, column);
+            }
+        }
+
+        columns_fwd_fallback(haystack, offset, column, column_stop, tab_size)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_columns_fwd() {
+        let text = b"0123456789abcdef0123456789abcdef0123456789abcdef";
+        assert_eq!(columns_fwd(text, 0, 0, CoordType::MAX, 4), (48, 48));
+        assert_eq!(columns_fwd(text, 3, 10, 40, 4), (33, 40));
+        assert_eq!(columns_fwd(text, 48, 0, 100, 4), (48, 0));
+
+        let text =
+            b"aaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaa\tb\t\tc\r\naaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaa";
+        assert_eq!(columns_fwd(text, 0, 0, CoordType::MAX, 8), (45, 65));
+        assert_eq!(columns_fwd(text, 0, 0, CoordType::MAX, 3), (45, 49));
+        // A tab can't be partially consumed.
+        assert_eq!(columns_fwd(text, 0, 0, 47, 8), (40, 40));
+        assert_eq!(columns_fwd(text, 41, 41, 48, 8), (43, 48));
+        // Non-ASCII characters stop the measurement.
+        let text = "aaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaé".as_bytes();
+        assert_eq!(columns_fwd(text, 0, 0, CoordType::MAX, 8), (36, 36));
+    }
+
+    #[test]
+    fn test_columns_fwd_random() {
+        const ALPHABET: &[u8] = b"\t\t0123456789abcdef\xc3\xa9\n";
+
+        let mut rng = crate::simd::test::make_rng();
+        let text: Vec<u8> = (0..4096).map(|_| ALPHABET[rng() % ALPHABET.len()]).collect();
+        // Long runs of printable ASCII, with the occasional tab.
+        let long: Vec<u8> =
+            (0..4096).map(|_| if rng().is_multiple_of(50) { b'\t' } else { b'x' }).collect();
+
+        for text in [&text, &long] {
+            for offset in (0..text.len()).step_by(37) {
+                for (column, column_stop, tab_size) in
+                    [(0, CoordType::MAX, 4), (3, 1000, 8), (offset as CoordType, 5000, 3)]
+                {
+                    assert_eq!(
+                        columns_fwd(text, offset, column, column_stop, tab_size),
+                        columns_fwd_fallback(text, offset, column, column_stop, tab_size),
+                    );
+                }
+            }
+        }
+    }
+}
@@ -0,0 +1,211 @@
+// Copyright (c) Microsoft Corporation.
+// Licensed under the MIT License.
+
+//! Finding control characters.
+
+use std::ptr;
+
+/// Returns the index of the first byte in `haystack` that is a C0 control character
+/// (0x00-0x1F), DEL (0x7F), or 0xC2, which starts the UTF-8 encoding of C1 control characters.
+/// If none is found, `haystack.len()` is returned.
+/// `offset` specifies the index to start searching from.
+///
+/// 0xC2 also starts U+00A0-U+00BF. Callers that care about C1 controls need to check the next byte.
+pub fn find_control(haystack: &[u8], offset: usize) -> usize {
+    unsafe {
+        let beg = haystack.as_ptr();
+        let end = beg.add(haystack.len());
+        let it = beg.add(offset.min(haystack.len()));
+        let it = find_control_raw(it, end);
+        it.offset_from_unsigned(beg)
+    }
+}
+
+unsafe fn find_control_raw(beg: *const u8, end: *const u8) -> *const u8 {
+    #[cfg(any(target_arch = "x86", target_arch = "x86_64"))]
+    return unsafe { FIND_CONTROL_DISPATCH(beg, end) };
+
+    #[cfg(target_arch = "aarch64")]
+    if super::enabled("neon") {
+        return unsafe { find_control_neon(beg, end) };
+    }
+
+    #[allow(unreachable_code)]
+    return unsafe { find_control_fallback(beg, end) };
+}
+
+#[inline(always)]
+fn is_control(ch: u8) -> bool {
+    ch < 0x20 || ch == 0x7f || ch == 0xc2
+}
+
+unsafe fn find_control_fallback(mut beg: *const u8, end: *const u8) -> *const u8 {
+    unsafe {
+        while !ptr::eq(beg, end) && !is_control(*beg) {
+            beg = beg.add(1);
+        }
+        beg
+    }
+}
+
+// See `MEMCHR2_DISPATCH`.
+#[cfg(any(target_arch = "x86", target_arch = "x86_64"))]
+static mut FIND_CONTROL_DISPATCH: unsafe fn(beg: *const u8, end: *const u8) -> *const u8 =
+    find_control_dispatch;
+
+#[cfg(any(target_arch = "x86", target_arch = "x86_64"))]
+unsafe fn find_control_dispatch(beg: *const u8, end: *const u8) -> *const u8 {
+    let func = if super::enabled("avx512") {
+        find_control_avx512
+    } else if super::enabled("avx2") {
+        find_control_avx2
+    } else {
+        find_control_fallback
+    };
+    unsafe { FIND_CONTROL_DISPATCH = func };
+    unsafe { func(beg, end) }
+}
+
+#[cfg(any(target_arch = "x86", target_arch = "x86_64"))]
+#[target_feature(enable = "avx512bw")]
+unsafe fn find_control_avx512(mut beg: *const u8, end: *const u8) -> *const u8 {
+    unsafe {
+        #[cfg(target_arch = "x86")]
+        use std::arch::x86::*;
+        #[cfg(target_arch = "x86_64")]
+        use std::arch::x86_64::*;
+
+        let c0_end = _mm512_set1_epi8(0x20);
+        let del = _mm512_set1_epi8(0x7f);
+        let c1 = _mm512_set1_epi8(0xc2u8 as i8);
+        let mut remaining = end.offset_from_unsigned(beg);
+
+        while remaining >= 64 {
+            let v = _mm512_loadu_si512(beg as *const _);
+            let m = _mm512_cmplt_epu8_mask(v, c0_end)
+                | _mm512_cmpeq_epi8_mask(v, del)
+                | _mm512_cmpeq_epi8_mask(v, c1);
+
+            if m != 0 {
+                return beg.add(m.trailing_zeros() as usize);
+            }
+
+            beg = beg.add(64);
+            remaining -= 64;
+        }
+
+        if remaining > 0 {
+            // See `memchr2_avx512`.
+            let k = (1u64 << remaining) - 1;
+            let v = _mm512_maskz_loadu_epi8(k, beg as *const _);
+            let m = _mm512_mask_cmplt_epu8_mask(k, v, c0_end)
+                | _mm512_mask_cmpeq_epi8_mask(k, v, del)
+                | _mm512_mask_cmpeq_epi8_mask(k, v, c1);
+
+            if m != 0 {
+                return beg.add(m.trailing_zeros() as usize);
+            }
+
+            beg = beg.add(remaining);
+        }
+
+        beg
+    }
+}
+
+#[cfg(any(target_arch = "x86", target_arch = "x86_64"))]
+#[target_feature(enable = "avx2")]
+unsafe fn find_control_avx2(mut beg: *const u8, end: *const u8) -> *const u8 {
+    unsafe {
+        #[cfg(target_arch = "x86")]
+        use std::arch::x86::*;
+        #[cfg(target_arch = "x86_64")]
+        use std::arch::x86_64::*;
+
+        let c0_max = _mm256_set1_epi8(0x1f);
+        let del = _mm256_set1_epi8(0x7f);
+        let c1 = _mm256_set1_epi8(0xc2u8 as i8);
+        let mut remaining = end.offset_from_unsigned(beg);
+
+        while remaining >= 32 {
+            let v = _mm256_loadu_si256(beg as *const _);
+            // There's no unsigned comparison, but `min(v, 0x1f) == v` is the same as `v <= 0x1f`.
+            let a = _mm256_cmpeq_epi8(_mm256_min_epu8(v, c0_max), v);
+            let b = _mm256_cmpeq_epi8(v, del);
+            let c = _mm256_cmpeq_epi8(v, c1);
+            let c = _mm256_or_si256(_mm256_or_si256(a, b), c);
+            let m = _mm256_movemask_epi8(c) as u32;
+
+            if m != 0 {
+                return beg.add(m.trailing_zeros() as usize);
+            }
+
+            beg = beg.add(32);
+            remaining -= 32;
+        }
+
+        find_control_fallback(beg, end)
+    }
+}
+
+#[cfg(target_arch = "aarch64")]
+unsafe fn find_control_neon(mut beg: *const u8, end: *const u8) -> *const u8 {
+    unsafe {
+        use std::arch::aarch64::*;
+
+        if end.offset_from_unsigned(beg) >= 16 {
+            let c0_end = vdupq_n_u8(0x20);
+            let del = vdupq_n_u8(0x7f);
+            let c1 = vdupq_n_u8(0xc2);
+
+            loop {
+                let v = vld1q_u8(beg as *const _);
+                let a = vcltq_u8(v, c0_end);
+                let b = vceqq_u8(v, del);
+                let c = vceqq_u8(v, c1);
+                let c = vorrq_u8(vorrq_u8(a, b), c);
+
+                // See `memchr2_neon`.
+                let m = vreinterpretq_u16_u8(c);
+                let m = vshrn_n_u16(m, 4);
+                let m = vreinterpret_u64_u8(m);
+                let m = vget_lane_u64(m, 0);
+
+                if m != 0 {
+                    return beg.add(m.trailing_zeros() as usize >> 2);
+                }
+
+                beg = beg.add(16);
+                if end.offset_from_unsigned(beg) < 16 {
+                    break;
+                }
+            }
+        }
+
+        find_control_fallback(beg, end)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_find_control() {
+        assert_eq!(find_control(b"", 0), 0);
+        assert_eq!(find_control(b"abc", 0), 3);
+
+        for (i, &ch) in [0x00, 0x09, 0x1b, 0x1f, 0x7f, 0xc2].iter().enumerate() {
+            let mut haystack = vec![b'a'; 100];
+            haystack[40 + i] = ch;
+            haystack[90] = ch;
+            assert_eq!(find_control(&haystack, 0), 40 + i, "{ch:#x}");
+            assert_eq!(find_control(&haystack, 41 + i), 90, "{ch:#x}");
+        }
+
+        // Bytes right next to the matched ranges.
+        let haystack: Vec<u8> = (0x20..=0xff).filter(|&b| b != 0x7f && b != 0xc2).collect();
+        assert_eq!(find_control(&haystack, 0), haystack.len());
+        assert_eq!(find_control("a\u{85}b".as_bytes(), 0), 1);
+    }
+}
@@ -0,0 +1,298 @@
+// Copyright (c) Microsoft Corporation.
+// Licensed under the MIT License.
+
+//! Hex encoding and decoding, e.g. for checksums.
+
+const HEX_DIGITS: &[u8; 16] = b"0123456789abcdef";
+
+/// Encodes `src` as lowercase hex digits into `dst`, 2 per byte.
+///
+/// # Panics
+///
+/// Panics if `dst` is shorter than `2 * src.len()`.
+pub fn hex_encode(dst: &mut [u8], src: &[u8]) {
+    assert!(dst.len() / 2 >= src.len());
+    unsafe { hex_encode_raw(dst, src) }
+}
+
+/// Decodes pairs of hex digits (of either case) from `src` into `dst`.
+///
+/// Returns the number of bytes written, which is less than `src.len() / 2`
+/// if `dst` is too short or if an invalid digit is encountered.
+/// In the latter case, the invalid pair starts at `2 * result`.
+pub fn hex_decode(dst: &mut [u8], src: &[u8]) -> usize {
+    let len = dst.len().min(src.len() / 2);
+    unsafe { hex_decode_raw(&mut dst[..len], &src[..len * 2]) }
+}
+
+unsafe fn hex_encode_raw(dst: &mut [u8], src: &[u8]) {
+    #[cfg(any(target_arch = "x86", target_arch = "x86_64"))]
+    return unsafe { HEX_ENCODE_DISPATCH(dst, src) };
+
+    #[cfg(target_arch = "aarch64")]
+    if super::enabled("neon") {
+        return unsafe { hex_encode_neon(dst, src) };
+    }
+
+    #[allow(unreachable_code)]
+    hex_encode_fallback(dst, src, 0)
+}
+
+unsafe fn hex_decode_raw(dst: &mut [u8], src: &[u8]) -> usize {
+    #[cfg(any(target_arch = "x86", target_arch = "x86_64"))]
+    return unsafe { HEX_DECODE_DISPATCH(dst, src) };
+
+    #[cfg(target_arch = "aarch64")]
+    if super::enabled("neon") {
+        return unsafe { hex_decode_neon(dst, src) };
+    }
+
+    #[allow(unreachable_code)]
+    hex_decode_fallback(dst, src, 0)
+}
+
+fn hex_encode_fallback(dst: &mut [u8], src: &[u8], offset: usize) {
+    for (i, &b) in src.iter().enumerate().skip(offset) {
+        dst[2 * i] = HEX_DIGITS[(b >> 4) as usize];
+        dst[2 * i + 1] = HEX_DIGITS[(b & 15) as usize];
+    }
+}
+
+/// Decodes `dst.len()` bytes, starting at `dst[offset]`. See `hex_decode`.
+fn hex_decode_fallback(dst: &mut [u8], src: &[u8], offset: usize) -> usize {
+    fn digit(c: u8) -> Option<u8> {
+        match c {
+            b'0'..=b'9' => Some(c - b'0'),
+            b'a'..=b'f' => Some(c - b'a' + 10),
+            b'A'..=b'F' => Some(c - b'A' + 10),
+            _ => None,
+        }
+    }
+
+    for i in offset..dst.len() {
+        match (digit(src[2 * i]), digit(src[2 * i + 1])) {
+            (Some(hi), Some(lo)) => dst[i] = (hi << 4) | lo,
+            _ => return i,
+        }
+    }
+    dst.len()
+}
+
+// See `MEMCHR2_DISPATCH`.
+#[cfg(any(target_arch = "x86", target_arch = "x86_64"))]
+static mut HEX_ENCODE_DISPATCH: unsafe fn(dst: &mut [u8], src: &[u8]) = hex_encode_dispatch;
+
+#[cfg(any(target_arch = "x86", target_arch = "x86_64"))]
+unsafe fn hex_encode_dispatch(dst: &mut [u8], src: &[u8]) {
+    let func = if super::enabled("avx2") {
+        hex_encode_avx2
+    } else {
+        |d: &mut [u8], s: &[u8]| hex_encode_fallback(d, s, 0)
+    };
+    unsafe { HEX_ENCODE_DISPATCH = func };
+    unsafe { func(dst, src) }
+}
+
+#[cfg(any(target_arch = "x86", target_arch = "x86_64"))]
+#[target_feature(enable = "avx2")]
+unsafe fn hex_encode_avx2(dst: &mut [u8], src: &[u8]) {
+    unsafe {
+        #[cfg(target_arch = "x86")]
+        use std::arch::x86::*;
+        #[cfg(target_arch = "x86_64")]
+        use std::arch::x86_64::*;
+
+        let lut = _mm256_broadcastsi128_si256(_mm_loadu_si128(HEX_DIGITS.as_ptr() as *const _));
+        let mask = _mm256_set1_epi8(0x0f);
+        let mut i = 0;
+
+        while i + 32 <= src.len() {
+            let v = _mm256_loadu_si256(src.as_ptr().add(i) as *const _);
+            // There's no 8-bit shift, but the mask removes the bits shifted in from the neighbor.
+            let hi = _mm256_shuffle_epi8(lut, _mm256_and_si256(_mm256_srli_epi16(v, 4), mask));
+            let lo = _mm256_shuffle_epi8(lut, _mm256_and_si256(v, mask));
+
+            // The unpacks work per 128-bit lane: `a` holds bytes 0-7 and 16-23, `b` 8-15 and 24-31.
+            let a = _mm256_unpacklo_epi8(hi, lo);
+            let b = _mm256_unpackhi_epi8(hi, lo);
+            let out = dst.as_mut_ptr().add(2 * i);
+            _mm256_storeu_si256(out as *mut _, _mm256_permute2x128_si256(a, b, 0x20));
+            _mm256_storeu_si256(out.add(32) as *mut _, _mm256_permute2x128_si256(a, b, 0x31));
+
+            i += 32;
+        }
+
+        hex_encode_fallback(dst, src, i)
+    }
+}
+
+// See `MEMCHR2_DISPATCH`.
+#[cfg(any(target_arch = "x86", target_arch = "x86_64"))]
+static mut HEX_DECODE_DISPATCH: unsafe fn(dst: &mut [u8], src: &[u8]) -> usize =
+    hex_decode_dispatch;
+
+#[cfg(any(target_arch = "x86", target_arch = "x86_64"))]
+unsafe fn hex_decode_dispatch(dst: &mut [u8], src: &[u8]) -> usize {
+    let func = if super::enabled("avx2") {
+        hex_decode_avx2
+    } else {
+        |d: &mut [u8], s: &[u8]| hex_decode_fallback(d, s, 0)
+    };
+    unsafe { HEX_DECODE_DISPATCH = func };
+    unsafe { func(dst, src) }
+}
+
+#[cfg(any(target_arch = "x86", target_arch = "x86_64"))]
+#[target_feature(enable = "avx2")]
+unsafe fn hex_decode_avx2(dst: &mut [u8], src: &[u8]) -> usize {
+    unsafe {
+        #[cfg(target_arch = "x86")]
+        use std::arch::x86::*;
+        #[cfg(target_arch = "x86_64")]
+        use std::arch::x86_64::*;
+
+        /// Converts 32 hex digits to their values. The second result is false if any is invalid.
+        #[inline(always)]
+        unsafe fn digits(v: __m256i) -> (__m256i, bool) {
+            unsafe {
+                // Unsigned `x < n` is `min(x, n - 1) == x`.
+                let d = _mm256_sub_epi8(v, _mm256_set1_epi8(b'0' as i8));
+                let is_digit = _mm256_cmpeq_epi8(_mm256_min_epu8(d, _mm256_set1_epi8(9)), d);
+                let l = _mm256_sub_epi8(
+                    _mm256_or_si256(v, _mm256_set1_epi8(0x20)),
+                    _mm256_set1_epi8(b'a' as i8),
+                );
+                let is_letter = _mm256_cmpeq_epi8(_mm256_min_epu8(l, _mm256_set1_epi8(5)), l);
+                let l = _mm256_add_epi8(l, _mm256_set1_epi8(10));
+                let valid = _mm256_or_si256(is_digit, is_letter);
+                (_mm256_blendv_epi8(l, d, is_digit), _mm256_movemask_epi8(valid) == -1)
+            }
+        }
+
+        // Combines each pair of digits to `hi * 16 + lo` as a 16-bit integer.
+        let weights = _mm256_set1_epi16(0x0110);
+        let mut i = 0;
+
+        while i + 32 <= dst.len() {
+            let p = src.as_ptr().add(2 * i);
+            let (a, a_valid) = digits(_mm256_loadu_si256(p as *const _));
+            let (b, b_valid) = digits(_mm256_loadu_si256(p.add(32) as *const _));
+            if !(a_valid && b_valid) {
+                // Let the fallback find the invalid pair.
+                break;
+            }
+
+            let a = _mm256_maddubs_epi16(a, weights);
+            let b = _mm256_maddubs_epi16(b, weights);
+            // The pack works per 128-bit lane, so the 64-bit blocks need to be reordered.
+            let bytes = _mm256_permute4x64_epi64(_mm256_packus_epi16(a, b), 0b11_01_10_00);
+            _mm256_storeu_si256(dst.as_mut_ptr().add(i) as *mut _, bytes);
+
+            i += 32;
+        }
+
+        hex_decode_fallback(dst, src, i)
+    }
+}
+
+#[cfg(target_arch = "aarch64")]
+unsafe fn hex_encode_neon(dst: &mut [u8], src: &[u8]) {
+    unsafe {
+        use std::arch::aarch64::*;
+
+        let lut = vld1q_u8(HEX_DIGITS.as_ptr());
+        let mut i = 0;
+
+        while i + 16 <= src.len() {
+            let v = vld1q_u8(src.as_ptr().add(i));
+            let hi = vqtbl1q_u8(lut, vshrq_n_u8(v, 4));
+            let lo = vqtbl1q_u8(lut, vandq_u8(v, vdupq_n_u8(0x0f)));
+            // `vst2q_u8` interleaves the two registers for us.
+            vst2q_u8(dst.as_mut_ptr().add(2 * i), uint8x16x2_t(hi, lo));
+            i += 16;
+        }
+
+        hex_encode_fallback(dst, src, i)
+    }
+}
+
+#[cfg(target_arch = "aarch64")]
+unsafe fn hex_decode_neon(dst: &mut [u8], src: &[u8]) -> usize {
+    unsafe {
+        use std::arch::aarch64::*;
+
+        /// Converts 16 hex digits to their values. The second result is false if any is invalid.
+        #[inline(always)]
+        unsafe fn digits(v: uint8x16_t) -> (uint8x16_t, bool) {
+            unsafe {
+                let d = vsubq_u8(v, vdupq_n_u8(b'0'));
+                let is_digit = vcltq_u8(d, vdupq_n_u8(10));
+                let l = vsubq_u8(vorrq_u8(v, vdupq_n_u8(0x20)), vdupq_n_u8(b'a'));
+                let is_letter = vcltq_u8(l, vdupq_n_u8(6));
+                let l = vaddq_u8(l, vdupq_n_u8(10));
+                let valid = vorrq_u8(is_digit, is_letter);
+                (vbslq_u8(is_digit, d, l), vminvq_u8(valid) == 0xff)
+            }
+        }
+
+        let mut i = 0;
+
+        while i + 16 <= dst.len() {
+            // `vld2q_u8` splits the even (high) and odd (low) digits.
+            let v = vld2q_u8(src.as_ptr().add(2 * i));
+            let (hi, hi_valid) = digits(v.0);
+            let (lo, lo_valid) = digits(v.1);
+            if !(hi_valid && lo_valid) {
+                // Let the fallback find the invalid pair.
+                break;
+            }
+
+            vst1q_u8(dst.as_mut_ptr().add(i), vorrq_u8(vshlq_n_u8(hi, 4), lo));
+            i += 16;
+        }
+
+        hex_decode_fallback(dst, src, i)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_hex_encode() {
+        let src: Vec<u8> = (0..=255).chain(0..77).collect();
+        let mut dst = vec![0; src.len() * 2];
+        hex_encode(&mut dst, &src);
+
+        let expected: String = src.iter().map(|b| format!("{b:02x}")).collect();
+        assert_eq!(dst, expected.as_bytes());
+    }
+
+    #[test]
+    fn test_hex_decode() {
+        let src: Vec<u8> = (0..=255).chain(0..77).collect();
+        let mut hex: Vec<u8> = src.iter().flat_map(|b| format!("{b:02X}").into_bytes()).collect();
+        // Mixed case.
+        hex[..100].make_ascii_lowercase();
+
+        let mut dst = vec![0; src.len()];
+        assert_eq!(hex_decode(&mut dst, &hex), src.len());
+        assert_eq!(dst, src);
+
+        // A short `dst` or an odd number of digits.
+        assert_eq!(hex_decode(&mut dst[..10], &hex), 10);
+        assert_eq!(hex_decode(&mut dst, &hex[..21]), 10);
+
+        // Invalid digits, in every position of a SIMD block.
+        for pos in 0..hex.len() {
+            for invalid in [b'g', b'G', b'/', b':', b'@', b'`', 0xff] {
+                let saved = hex[pos];
+                hex[pos] = invalid;
+                assert_eq!(hex_decode(&mut dst, &hex), pos / 2);
+                assert_eq!(dst[..pos / 2], src[..pos / 2]);
+                hex[pos] = saved;
+            }
+        }
+    }
+}
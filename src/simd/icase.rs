@@ -0,0 +1,239 @@
+// Copyright (c) Microsoft Corporation.
+// Licensed under the MIT License.
+
+//! ASCII case-insensitive substring search.
+
+/// Returns the index of the first ASCII case-insensitive occurrence of `needle` in `haystack`,
+/// or of the first non-ASCII byte, whichever comes first. If neither is found,
+/// `haystack.len()` is returned. `offset` specifies the index to start searching from.
+///
+/// It stops at non-ASCII bytes, because matching them requires Unicode case folding:
+/// For instance, "ß" matches "ss". `needle` must be ASCII and shouldn't be empty.
+pub fn find_ascii_icase(haystack: &[u8], offset: usize, needle: &[u8]) -> usize {
+    debug_assert!(needle.is_ascii());
+    let offset = offset.min(haystack.len());
+    if needle.is_empty() {
+        return offset;
+    }
+    unsafe { find_ascii_icase_raw(haystack, offset, needle) }
+}
+
+unsafe fn find_ascii_icase_raw(haystack: &[u8], offset: usize, needle: &[u8]) -> usize {
+    #[cfg(any(target_arch = "x86", target_arch = "x86_64"))]
+    return unsafe { FIND_ASCII_ICASE_DISPATCH(haystack, offset, needle) };
+
+    #[cfg(target_arch = "aarch64")]
+    if super::enabled("neon") {
+        return unsafe { find_ascii_icase_neon(haystack, offset, needle) };
+    }
+
+    #[allow(unreachable_code)]
+    return find_ascii_icase_fallback(haystack, offset, needle);
+}
+
+fn find_ascii_icase_fallback(haystack: &[u8], offset: usize, needle: &[u8]) -> usize {
+    for i in offset..haystack.len() {
+        if !haystack[i].is_ascii() || matches_at(haystack, i, needle) {
+            return i;
+        }
+    }
+    haystack.len()
+}
+
+#[inline(always)]
+fn matches_at(haystack: &[u8], i: usize, needle: &[u8]) -> bool {
+    haystack.get(i..i + needle.len()).is_some_and(|h| h.eq_ignore_ascii_case(needle))
+}
+
+/// Verifies the candidates in `mask` and returns the first match, or the first
+/// non-ASCII byte in `non_ascii` if it comes first. Both masks have one bit set
+/// per byte, every `bits_per_byte` bits, starting at `haystack[i]`.
+#[inline(always)]
+fn check_candidates(
+    haystack: &[u8],
+    i: usize,
+    needle: &[u8],
+    mut mask: u64,
+    non_ascii: u64,
+    bits_per_byte: u32,
+) -> Option<usize> {
+    let stop = non_ascii.trailing_zeros();
+    if stop < 64 {
+        mask &= (1 << stop) - 1;
+    }
+
+    while mask != 0 {
+        let j = i + (mask.trailing_zeros() / bits_per_byte) as usize;
+        if matches_at(haystack, j, needle) {
+            return Some(j);
+        }
+        mask &= mask - 1;
+    }
+
+    if stop < 64 { Some(i + (stop / bits_per_byte) as usize) } else { None }
+}
+
+// See `MEMCHR2_DISPATCH`.
+#[cfg(any(target_arch = "x86", target_arch = "x86_64"))]
+static mut FIND_ASCII_ICASE_DISPATCH: unsafe fn(&[u8], usize, &[u8]) -> usize =
+    find_ascii_icase_dispatch;
+
+#[cfg(any(target_arch = "x86", target_arch = "x86_64"))]
+unsafe fn find_ascii_icase_dispatch(haystack: &[u8], offset: usize, needle: &[u8]) -> usize {
+    let func = if super::enabled("avx2") {
+        find_ascii_icase_avx2
+    } else {
+        |h: &[u8], o, n: &[u8]| find_ascii_icase_fallback(h, o, n)
+    };
+    unsafe { FIND_ASCII_ICASE_DISPATCH = func };
+    unsafe { func(haystack, offset, needle) }
+}
+
+// This is the "generic SIMD" algorithm from http://0x80.pl/notesen/2016-11-28-simd-strfind.html:
+// Candidates are positions where both the first and last byte of the needle match.
+#[cfg(any(target_arch = "x86", target_arch = "x86_64"))]
+#[target_feature(enable = "avx2")]
+unsafe fn find_ascii_icase_avx2(haystack: &[u8], offset: usize, needle: &[u8]) -> usize {
+    unsafe {
+        #[cfg(target_arch = "x86")]
+        use std::arch::x86::*;
+        #[cfg(target_arch = "x86_64")]
+        use std::arch::x86_64::*;
+
+        #[inline(always)]
+        unsafe fn to_lower(v: __m256i) -> __m256i {
+            unsafe {
+                // Bytes >= 0x80 are negative and thus never uppercase.
+                let ge_a = _mm256_cmpgt_epi8(v, _mm256_set1_epi8(b'A' as i8 - 1));
+                let le_z = _mm256_cmpgt_epi8(_mm256_set1_epi8(b'Z' as i8 + 1), v);
+                let upper = _mm256_and_si256(ge_a, le_z);
+                _mm256_or_si256(v, _mm256_and_si256(upper, _mm256_set1_epi8(0x20)))
+            }
+        }
+
+        let last_off = needle.len() - 1;
+        let first = _mm256_set1_epi8(needle[0].to_ascii_lowercase() as i8);
+        let last = _mm256_set1_epi8(needle[last_off].to_ascii_lowercase() as i8);
+        let beg = haystack.as_ptr();
+        let mut i = offset;
+
+        while i + last_off + 32 <= haystack.len() {
+            let a = _mm256_loadu_si256(beg.add(i) as *const _);
+            let b = _mm256_loadu_si256(beg.add(i + last_off) as *const _);
+            let eq_first = _mm256_cmpeq_epi8(to_lower(a), first);
+            let eq_last = _mm256_cmpeq_epi8(to_lower(b), last);
+            let mask = _mm256_movemask_epi8(_mm256_and_si256(eq_first, eq_last)) as u32 as u64;
+            let non_ascii = _mm256_movemask_epi8(a) as u32 as u64;
+
+            if (mask | non_ascii) != 0
+                && let Some(hit) = check_candidates(haystack, i, needle, mask, non_ascii, 1)
+            {
+                return hit;
+            }
+
+            i += 32;
+        }
+
+        find_ascii_icase_fallback(haystack, i, needle)
+    }
+}
+
+#[cfg(target_arch = "aarch64")]
+unsafe fn find_ascii_icase_neon(haystack: &[u8], offset: usize, needle: &[u8]) -> usize {
+    unsafe {
+        use std::arch::aarch64::*;
+
+        #[inline(always)]
+        unsafe fn to_lower(v: uint8x16_t) -> uint8x16_t {
+            unsafe {
+                // `v - 'A' <= 'Z' - 'A'` is true only for uppercase letters.
+                let upper = vcleq_u8(vsubq_u8(v, vdupq_n_u8(b'A')), vdupq_n_u8(b'Z' - b'A'));
+                vorrq_u8(v, vandq_u8(upper, vdupq_n_u8(0x20)))
+            }
+        }
+
+        // See `memchr2_neon`. Only the top bit of each nibble is kept,
+        // so that there's a single bit per byte for `check_candidates`.
+        #[inline(always)]
+        unsafe fn movemask(v: uint8x16_t) -> u64 {
+            unsafe {
+                let m = vshrn_n_u16(vreinterpretq_u16_u8(v), 4);
+                vget_lane_u64(vreinterpret_u64_u8(m), 0) & 0x8888888888888888
+            }
+        }
+
+        let last_off = needle.len() - 1;
+        let first = vdupq_n_u8(needle[0].to_ascii_lowercase());
+        let last = vdupq_n_u8(needle[last_off].to_ascii_lowercase());
+        let beg = haystack.as_ptr();
+        let mut i = offset;
+
+        while i + last_off + 16 <= haystack.len() {
+            let a = vld1q_u8(beg.add(i));
+            let b = vld1q_u8(beg.add(i + last_off));
+            let eq_first = vceqq_u8(to_lower(a), first);
+            let eq_last = vceqq_u8(to_lower(b), last);
+            let mask = movemask(vandq_u8(eq_first, eq_last));
+            let non_ascii = movemask(vcgeq_u8(a, vdupq_n_u8(0x80)));
+
+            if (mask | non_ascii) != 0
+                && let Some(hit) = check_candidates(haystack, i, needle, mask, non_ascii, 4)
+            {
+                return hit;
+            }
+
+            i += 16;
+        }
+
+        find_ascii_icase_fallback(haystack, i, needle)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_find_ascii_icase() {
+        let haystack = b"The quick brown fox jumps over the lazy dog. THE QUICK BROWN FOX JUMPS";
+        assert_eq!(find_ascii_icase(haystack, 0, b"the"), 0);
+        assert_eq!(find_ascii_icase(haystack, 1, b"the"), 31);
+        assert_eq!(find_ascii_icase(haystack, 32, b"the"), 45);
+        assert_eq!(find_ascii_icase(haystack, 0, b"Fox Jumps"), 16);
+        assert_eq!(find_ascii_icase(haystack, 17, b"fox jumps"), 61);
+        assert_eq!(find_ascii_icase(haystack, 0, b"jumps"), 20);
+        assert_eq!(find_ascii_icase(haystack, 62, b"jumps"), haystack.len() - 5);
+        assert_eq!(find_ascii_icase(haystack, 0, b"cat"), haystack.len());
+        assert_eq!(find_ascii_icase(haystack, 0, b"g"), 42);
+        assert_eq!(find_ascii_icase(b"", 0, b"a"), 0);
+        // Non-letters must match exactly: '@' | 0x20 == '`'.
+        assert_eq!(find_ascii_icase(b"`@`@`@`@`@`@`@`@`@`@`@`@`@`@`@`@`@`@", 0, b"@@"), 36);
+
+        // It stops at non-ASCII bytes, even in the middle of a candidate.
+        let haystack =
+            "aaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaax straße xx"
+                .as_bytes();
+        assert_eq!(find_ascii_icase(haystack, 0, b"STRASSE"), 71);
+        assert_eq!(find_ascii_icase(haystack, 0, b"X s"), 65);
+        assert_eq!(find_ascii_icase(haystack, 73, b"xx"), 75);
+    }
+
+    #[test]
+    fn test_find_ascii_icase_random() {
+        let text = crate::simd::test::generate_random_text(4096);
+        let haystack = text.to_ascii_uppercase();
+        let haystack = haystack.as_bytes();
+
+        for needle_len in [1, 2, 3, 5, 17, 40] {
+            let mut offset = 0;
+            for _ in 0..50 {
+                let needle = &text.as_bytes()[offset % 3000..offset % 3000 + needle_len];
+                let expected = (offset..haystack.len())
+                    .find(|&i| matches_at(haystack, i, needle))
+                    .unwrap_or(haystack.len());
+                assert_eq!(find_ascii_icase(haystack, offset, needle), expected);
+                offset += 61;
+            }
+        }
+    }
+}
@@ -34,11 +34,13 @@ unsafe fn lines_bwd_raw(
     line: CoordType,
     line_stop: CoordType,
 ) -> (*const u8, CoordType) {
-    #[cfg(any(target_arch = "x86_64", target_arch = "loongarch64"))]
+    #[cfg(any(target_arch = "x86_64", target_arch = "loongarch64", target_arch = "riscv64"))]
     return unsafe { LINES_BWD_DISPATCH(beg, end, line, line_stop) };
 
     #[cfg(target_arch = "aarch64")]
-    return unsafe { lines_bwd_neon(beg, end, line, line_stop) };
+    if super::enabled("neon") {
+        return unsafe { lines_bwd_neon(beg, end, line, line_stop) };
+    }
 
     #[allow(unreachable_code)]
     return unsafe { lines_bwd_fallback(beg, end, line, line_stop) };
@@ -65,7 +67,7 @@ unsafe fn lines_bwd_fallback(
     }
 }
 
-#[cfg(any(target_arch = "x86_64", target_arch = "loongarch64"))]
+#[cfg(any(target_arch = "x86_64", target_arch = "loongarch64", target_arch = "riscv64"))]
 static mut LINES_BWD_DISPATCH: unsafe fn(
     beg: *const u8,
     end: *const u8,
@@ -80,11 +82,78 @@ unsafe fn lines_bwd_dispatch(
     line: CoordType,
     line_stop: CoordType,
 ) -> (*const u8, CoordType) {
-    let func = if is_x86_feature_detected!("avx2") { lines_bwd_avx2 } else { lines_bwd_fallback };
+    let func = if super::enabled("avx512") {
+        lines_bwd_avx512
+    } else if super::enabled("avx2") {
+        lines_bwd_avx2
+    } else {
+        lines_bwd_fallback
+    };
     unsafe { LINES_BWD_DISPATCH = func };
     unsafe { func(beg, end, line, line_stop) }
 }
 
+#[cfg(target_arch = "x86_64")]
+#[target_feature(enable = "avx512bw")]
+unsafe fn lines_bwd_avx512(
+    beg: *const u8,
+    mut end: *const u8,
+    mut line: CoordType,
+    line_stop: CoordType,
+) -> (*const u8, CoordType) {
+    unsafe {
+        use std::arch::x86_64::*;
+
+        let lf = _mm512_set1_epi8(b'\n' as i8);
+        let off = end.addr() & 63;
+        if off != 0 && off < end.offset_from_unsigned(beg) {
+            (end, line) = lines_bwd_fallback(end.sub(off), end, line, line_stop);
+        }
+
+        while end.offset_from_unsigned(beg) >= 256 {
+            let chunk_start = end.sub(256);
+
+            let v1 = _mm512_loadu_si512(chunk_start.add(0) as *const _);
+            let v2 = _mm512_loadu_si512(chunk_start.add(64) as *const _);
+            let v3 = _mm512_loadu_si512(chunk_start.add(128) as *const _);
+            let v4 = _mm512_loadu_si512(chunk_start.add(192) as *const _);
+
+            let mut sum = _mm512_setzero_si512();
+            sum = _mm512_sub_epi8(sum, _mm512_movm_epi8(_mm512_cmpeq_epi8_mask(v1, lf)));
+            sum = _mm512_sub_epi8(sum, _mm512_movm_epi8(_mm512_cmpeq_epi8_mask(v2, lf)));
+            sum = _mm512_sub_epi8(sum, _mm512_movm_epi8(_mm512_cmpeq_epi8_mask(v3, lf)));
+            sum = _mm512_sub_epi8(sum, _mm512_movm_epi8(_mm512_cmpeq_epi8_mask(v4, lf)));
+
+            let sum = _mm512_sad_epu8(sum, _mm512_setzero_si512());
+            let sum = _mm512_reduce_add_epi64(sum);
+
+            let line_next = line - sum as CoordType;
+            if line_next <= line_stop {
+                break;
+            }
+
+            end = chunk_start;
+            line = line_next;
+        }
+
+        while end.offset_from_unsigned(beg) >= 64 {
+            let chunk_start = end.sub(64);
+            let v = _mm512_loadu_si512(chunk_start as *const _);
+            let sum = _mm512_cmpeq_epi8_mask(v, lf).count_ones();
+
+            let line_next = line - sum as CoordType;
+            if line_next <= line_stop {
+                break;
+            }
+
+            end = chunk_start;
+            line = line_next;
+        }
+
+        lines_bwd_fallback(beg, end, line, line_stop)
+    }
+}
+
 #[cfg(target_arch = "x86_64")]
 #[target_feature(enable = "avx2")]
 unsafe fn lines_bwd_avx2(
@@ -169,11 +238,9 @@ unsafe fn lines_bwd_dispatch(
     line: CoordType,
     line_stop: CoordType,
 ) -> (*const u8, CoordType) {
-    use std::arch::is_loongarch_feature_detected;
-
-    let func = if is_loongarch_feature_detected!("lasx") {
+    let func = if super::enabled("lasx") {
         lines_bwd_lasx
-    } else if is_loongarch_feature_detected!("lsx") {
+    } else if super::enabled("lsx") {
         lines_bwd_lsx
     } else {
         lines_bwd_fallback
@@ -394,6 +461,69 @@ unsafe fn lines_bwd_neon(
     }
 }
 
+#[cfg(target_arch = "riscv64")]
+unsafe fn lines_bwd_dispatch(
+    beg: *const u8,
+    end: *const u8,
+    line: CoordType,
+    line_stop: CoordType,
+) -> (*const u8, CoordType) {
+    let func = if super::enabled("rvv") { lines_bwd_rvv } else { lines_bwd_fallback };
+    unsafe { LINES_BWD_DISPATCH = func };
+    unsafe { func(beg, end, line, line_stop) }
+}
+
+// See `memchr2_rvv`.
+#[cfg(target_arch = "riscv64")]
+#[target_feature(enable = "v")]
+unsafe fn lines_bwd_rvv(
+    beg: *const u8,
+    mut end: *const u8,
+    mut line: CoordType,
+    line_stop: CoordType,
+) -> (*const u8, CoordType) {
+    unsafe {
+        use std::arch::asm;
+
+        let mut remaining = end.offset_from_unsigned(beg);
+
+        while remaining > 0 {
+            let vl: usize;
+            let chunk_start: *const u8;
+            let sum: usize;
+
+            asm!(
+                "vsetvli {vl}, {len}, e8, m8, ta, ma",
+                "sub {chunk_start}, {end}, {vl}",
+                "vle8.v v8, ({chunk_start})",
+                "vmseq.vx v0, v8, {lf}",
+                "vcpop.m {sum}, v0",
+                vl = out(reg) vl,
+                chunk_start = out(reg) chunk_start,
+                sum = out(reg) sum,
+                len = in(reg) remaining,
+                end = in(reg) end,
+                lf = in(reg) b'\n' as usize,
+                out("v0") _,
+                out("v8") _, out("v9") _, out("v10") _, out("v11") _,
+                out("v12") _, out("v13") _, out("v14") _, out("v15") _,
+                options(nostack, readonly, preserves_flags),
+            );
+
+            let line_next = line - sum as CoordType;
+            if line_next <= line_stop {
+                break;
+            }
+
+            end = chunk_start;
+            remaining -= vl;
+            line = line_next;
+        }
+
+        lines_bwd_fallback(beg, end, line, line_stop)
+    }
+}
+
 #[cfg(test)]
 mod test {
     use super::*;
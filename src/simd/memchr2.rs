@@ -21,11 +21,18 @@ pub fn memchr2(needle1: u8, needle2: u8, haystack: &[u8], offset: usize) -> usiz
 }
 
 unsafe fn memchr2_raw(needle1: u8, needle2: u8, beg: *const u8, end: *const u8) -> *const u8 {
-    #[cfg(any(target_arch = "x86", target_arch = "x86_64", target_arch = "loongarch64"))]
+    #[cfg(any(
+        target_arch = "x86",
+        target_arch = "x86_64",
+        target_arch = "loongarch64",
+        target_arch = "riscv64"
+    ))]
     return unsafe { MEMCHR2_DISPATCH(needle1, needle2, beg, end) };
 
     #[cfg(target_arch = "aarch64")]
-    return unsafe { memchr2_neon(needle1, needle2, beg, end) };
+    if super::enabled("neon") {
+        return unsafe { memchr2_neon(needle1, needle2, beg, end) };
+    }
 
     #[allow(unreachable_code)]
     return unsafe { memchr2_fallback(needle1, needle2, beg, end) };
@@ -53,7 +60,12 @@ unsafe fn memchr2_fallback(
 // itself to the correct implementation on the first call. This reduces binary size.
 // It would also reduce branches if we had >2 implementations (a jump still needs to be predicted).
 // NOTE that this ONLY works if Control Flow Guard is disabled on Windows.
-#[cfg(any(target_arch = "x86", target_arch = "x86_64", target_arch = "loongarch64"))]
+#[cfg(any(
+    target_arch = "x86",
+    target_arch = "x86_64",
+    target_arch = "loongarch64",
+    target_arch = "riscv64"
+))]
 static mut MEMCHR2_DISPATCH: unsafe fn(
     needle1: u8,
     needle2: u8,
@@ -63,13 +75,66 @@ static mut MEMCHR2_DISPATCH: unsafe fn(
 
 #[cfg(any(target_arch = "x86", target_arch = "x86_64"))]
 unsafe fn memchr2_dispatch(needle1: u8, needle2: u8, beg: *const u8, end: *const u8) -> *const u8 {
-    let func = if is_x86_feature_detected!("avx2") { memchr2_avx2 } else { memchr2_fallback };
+    let func = if super::enabled("avx512") {
+        memchr2_avx512
+    } else if super::enabled("avx2") {
+        memchr2_avx2
+    } else {
+        memchr2_fallback
+    };
     unsafe { MEMCHR2_DISPATCH = func };
     unsafe { func(needle1, needle2, beg, end) }
 }
 
-// FWIW, I found that adding support for AVX512 was not useful at the time,
-// as it only marginally improved file load performance by <5%.
+// This only marginally improves file load performance (<5%), but searching
+// buffers that fit into the cache is about 1.4x faster than with AVX2.
+#[cfg(any(target_arch = "x86", target_arch = "x86_64"))]
+#[target_feature(enable = "avx512bw")]
+unsafe fn memchr2_avx512(
+    needle1: u8,
+    needle2: u8,
+    mut beg: *const u8,
+    end: *const u8,
+) -> *const u8 {
+    unsafe {
+        #[cfg(target_arch = "x86")]
+        use std::arch::x86::*;
+        #[cfg(target_arch = "x86_64")]
+        use std::arch::x86_64::*;
+
+        let n1 = _mm512_set1_epi8(needle1 as i8);
+        let n2 = _mm512_set1_epi8(needle2 as i8);
+        let mut remaining = end.offset_from_unsigned(beg);
+
+        while remaining >= 64 {
+            let v = _mm512_loadu_si512(beg as *const _);
+            let m = _mm512_cmpeq_epi8_mask(v, n1) | _mm512_cmpeq_epi8_mask(v, n2);
+
+            if m != 0 {
+                return beg.add(m.trailing_zeros() as usize);
+            }
+
+            beg = beg.add(64);
+            remaining -= 64;
+        }
+
+        if remaining > 0 {
+            // Masked loads don't fault on the masked out bytes, which makes handling the tail easy.
+            let k = (1u64 << remaining) - 1;
+            let v = _mm512_maskz_loadu_epi8(k, beg as *const _);
+            let m = _mm512_mask_cmpeq_epi8_mask(k, v, n1) | _mm512_mask_cmpeq_epi8_mask(k, v, n2);
+
+            if m != 0 {
+                return beg.add(m.trailing_zeros() as usize);
+            }
+
+            beg = beg.add(remaining);
+        }
+
+        beg
+    }
+}
+
 #[cfg(any(target_arch = "x86", target_arch = "x86_64"))]
 #[target_feature(enable = "avx2")]
 unsafe fn memchr2_avx2(needle1: u8, needle2: u8, mut beg: *const u8, end: *const u8) -> *const u8 {
@@ -104,11 +169,9 @@ unsafe fn memchr2_avx2(needle1: u8, needle2: u8, mut beg: *const u8, end: *const
 
 #[cfg(target_arch = "loongarch64")]
 unsafe fn memchr2_dispatch(needle1: u8, needle2: u8, beg: *const u8, end: *const u8) -> *const u8 {
-    use std::arch::is_loongarch_feature_detected;
-
-    let func = if is_loongarch_feature_detected!("lasx") {
+    let func = if super::enabled("lasx") {
         memchr2_lasx
-    } else if is_loongarch_feature_detected!("lsx") {
+    } else if super::enabled("lsx") {
         memchr2_lsx
     } else {
         memchr2_fallback
@@ -221,6 +284,59 @@ unsafe fn memchr2_neon(needle1: u8, needle2: u8, mut beg: *const u8, end: *const
     }
 }
 
+#[cfg(target_arch = "riscv64")]
+unsafe fn memchr2_dispatch(needle1: u8, needle2: u8, beg: *const u8, end: *const u8) -> *const u8 {
+    let func = if super::enabled("rvv") { memchr2_rvv } else { memchr2_fallback };
+    unsafe { MEMCHR2_DISPATCH = func };
+    unsafe { func(needle1, needle2, beg, end) }
+}
+
+// There are no RVV intrinsics in Rust yet, hence the inline assembly.
+#[cfg(target_arch = "riscv64")]
+#[target_feature(enable = "v")]
+unsafe fn memchr2_rvv(needle1: u8, needle2: u8, mut beg: *const u8, end: *const u8) -> *const u8 {
+    unsafe {
+        use std::arch::asm;
+
+        let mut remaining = end.offset_from_unsigned(beg);
+
+        while remaining > 0 {
+            let vl: usize;
+            let idx: isize;
+
+            // `vsetvli` clamps the length to what fits into the registers (here: 8 of them, m8),
+            // which takes care of the tail. `vfirst.m` returns -1 if there's no match.
+            asm!(
+                "vsetvli {vl}, {len}, e8, m8, ta, ma",
+                "vle8.v v8, ({ptr})",
+                "vmseq.vx v0, v8, {n1}",
+                "vmseq.vx v1, v8, {n2}",
+                "vmor.mm v0, v0, v1",
+                "vfirst.m {idx}, v0",
+                vl = out(reg) vl,
+                idx = out(reg) idx,
+                len = in(reg) remaining,
+                ptr = in(reg) beg,
+                n1 = in(reg) needle1 as usize,
+                n2 = in(reg) needle2 as usize,
+                out("v0") _, out("v1") _,
+                out("v8") _, out("v9") _, out("v10") _, out("v11") _,
+                out("v12") _, out("v13") _, out("v14") _, out("v15") _,
+                options(nostack, readonly, preserves_flags),
+            );
+
+            if idx >= 0 {
+                return beg.add(idx as usize);
+            }
+
+            beg = beg.add(vl);
+            remaining -= vl;
+        }
+
+        beg
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use std::slice;
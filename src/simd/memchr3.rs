@@ -0,0 +1,389 @@
+// Copyright (c) Microsoft Corporation.
+// Licensed under the MIT License.
+
+//! `memchr`, but with three needles.
+
+use std::ptr;
+
+/// `memchr`, but with three needles.
+///
+/// Returns the index of the first occurrence of any needle in the
+/// `haystack`. If no needle is found, `haystack.len()` is returned.
+/// `offset` specifies the index to start searching from.
+pub fn memchr3(needle1: u8, needle2: u8, needle3: u8, haystack: &[u8], offset: usize) -> usize {
+    unsafe {
+        let beg = haystack.as_ptr();
+        let end = beg.add(haystack.len());
+        let it = beg.add(offset.min(haystack.len()));
+        let it = memchr3_raw(needle1, needle2, needle3, it, end);
+        it.offset_from_unsigned(beg)
+    }
+}
+
+unsafe fn memchr3_raw(
+    needle1: u8,
+    needle2: u8,
+    needle3: u8,
+    beg: *const u8,
+    end: *const u8,
+) -> *const u8 {
+    #[cfg(any(target_arch = "x86", target_arch = "x86_64", target_arch = "loongarch64"))]
+    return unsafe { MEMCHR3_DISPATCH(needle1, needle2, needle3, beg, end) };
+
+    #[cfg(target_arch = "aarch64")]
+    if super::enabled("neon") {
+        return unsafe { memchr3_neon(needle1, needle2, needle3, beg, end) };
+    }
+
+    #[allow(unreachable_code)]
+    return unsafe { memchr3_fallback(needle1, needle2, needle3, beg, end) };
+}
+
+unsafe fn memchr3_fallback(
+    needle1: u8,
+    needle2: u8,
+    needle3: u8,
+    mut beg: *const u8,
+    end: *const u8,
+) -> *const u8 {
+    unsafe {
+        while !ptr::eq(beg, end) {
+            let ch = *beg;
+            if ch == needle1 || ch == needle2 || ch == needle3 {
+                break;
+            }
+            beg = beg.add(1);
+        }
+        beg
+    }
+}
+
+// In order to make `memchr3_raw` slim and fast, we use a function pointer that updates
+// itself to the correct implementation on the first call. This reduces binary size.
+// It would also reduce branches if we had >2 implementations (a jump still needs to be predicted).
+// NOTE that this ONLY works if Control Flow Guard is disabled on Windows.
+#[cfg(any(target_arch = "x86", target_arch = "x86_64", target_arch = "loongarch64"))]
+static mut MEMCHR3_DISPATCH: unsafe fn(
+    needle1: u8,
+    needle2: u8,
+    needle3: u8,
+    beg: *const u8,
+    end: *const u8,
+) -> *const u8 = memchr3_dispatch;
+
+#[cfg(any(target_arch = "x86", target_arch = "x86_64"))]
+unsafe fn memchr3_dispatch(
+    needle1: u8,
+    needle2: u8,
+    needle3: u8,
+    beg: *const u8,
+    end: *const u8,
+) -> *const u8 {
+    let func = if super::enabled("avx512") {
+        memchr3_avx512
+    } else if super::enabled("avx2") {
+        memchr3_avx2
+    } else {
+        memchr3_fallback
+    };
+    unsafe { MEMCHR3_DISPATCH = func };
+    unsafe { func(needle1, needle2, needle3, beg, end) }
+}
+
+#[cfg(any(target_arch = "x86", target_arch = "x86_64"))]
+#[target_feature(enable = "avx512bw")]
+unsafe fn memchr3_avx512(
+    needle1: u8,
+    needle2: u8,
+    needle3: u8,
+    mut beg: *const u8,
+    end: *const u8,
+) -> *const u8 {
+    unsafe {
+        #[cfg(target_arch = "x86")]
+        use std::arch::x86::*;
+        #[cfg(target_arch = "x86_64")]
+        use std::arch::x86_64::*;
+
+        let n1 = _mm512_set1_epi8(needle1 as i8);
+        let n2 = _mm512_set1_epi8(needle2 as i8);
+        let n3 = _mm512_set1_epi8(needle3 as i8);
+        let mut remaining = end.offset_from_unsigned(beg);
+
+        while remaining >= 64 {
+            let v = _mm512_loadu_si512(beg as *const _);
+            let m = _mm512_cmpeq_epi8_mask(v, n1)
+                | _mm512_cmpeq_epi8_mask(v, n2)
+                | _mm512_cmpeq_epi8_mask(v, n3);
+
+            if m != 0 {
+                return beg.add(m.trailing_zeros() as usize);
+            }
+
+            beg = beg.add(64);
+            remaining -= 64;
+        }
+
+        if remaining > 0 {
+            // See `memchr2_avx512`.
+            let k = (1u64 << remaining) - 1;
+            let v = _mm512_maskz_loadu_epi8(k, beg as *const _);
+            let m = _mm512_mask_cmpeq_epi8_mask(k, v, n1)
+                | _mm512_mask_cmpeq_epi8_mask(k, v, n2)
+                | _mm512_mask_cmpeq_epi8_mask(k, v, n3);
+
+            if m != 0 {
+                return beg.add(m.trailing_zeros() as usize);
+            }
+
+            beg = beg.add(remaining);
+        }
+
+        beg
+    }
+}
+
+#[cfg(any(target_arch = "x86", target_arch = "x86_64"))]
+#[target_feature(enable = "avx2")]
+unsafe fn memchr3_avx2(
+    needle1: u8,
+    needle2: u8,
+    needle3: u8,
+    mut beg: *const u8,
+    end: *const u8,
+) -> *const u8 {
+    unsafe {
+        #[cfg(target_arch = "x86")]
+        use std::arch::x86::*;
+        #[cfg(target_arch = "x86_64")]
+        use std::arch::x86_64::*;
+
+        let n1 = _mm256_set1_epi8(needle1 as i8);
+        let n2 = _mm256_set1_epi8(needle2 as i8);
+        let n3 = _mm256_set1_epi8(needle3 as i8);
+        let mut remaining = end.offset_from_unsigned(beg);
+
+        while remaining >= 32 {
+            let v = _mm256_loadu_si256(beg as *const _);
+            let a = _mm256_cmpeq_epi8(v, n1);
+            let b = _mm256_cmpeq_epi8(v, n2);
+            let c = _mm256_cmpeq_epi8(v, n3);
+            let c = _mm256_or_si256(_mm256_or_si256(a, b), c);
+            let m = _mm256_movemask_epi8(c) as u32;
+
+            if m != 0 {
+                return beg.add(m.trailing_zeros() as usize);
+            }
+
+            beg = beg.add(32);
+            remaining -= 32;
+        }
+
+        memchr3_fallback(needle1, needle2, needle3, beg, end)
+    }
+}
+
+#[cfg(target_arch = "loongarch64")]
+unsafe fn memchr3_dispatch(
+    needle1: u8,
+    needle2: u8,
+    needle3: u8,
+    beg: *const u8,
+    end: *const u8,
+) -> *const u8 {
+    let func = if super::enabled("lasx") {
+        memchr3_lasx
+    } else if super::enabled("lsx") {
+        memchr3_lsx
+    } else {
+        memchr3_fallback
+    };
+    unsafe { MEMCHR3_DISPATCH = func };
+    unsafe { func(needle1, needle2, needle3, beg, end) }
+}
+
+#[cfg(target_arch = "loongarch64")]
+#[target_feature(enable = "lasx")]
+unsafe fn memchr3_lasx(
+    needle1: u8,
+    needle2: u8,
+    needle3: u8,
+    mut beg: *const u8,
+    end: *const u8,
+) -> *const u8 {
+    unsafe {
+        use std::arch::loongarch64::*;
+
+        let n1 = lasx_xvreplgr2vr_b(needle1 as i32);
+        let n2 = lasx_xvreplgr2vr_b(needle2 as i32);
+        let n3 = lasx_xvreplgr2vr_b(needle3 as i32);
+
+        let off = beg.align_offset(32);
+        if off != 0 && off < end.offset_from_unsigned(beg) {
+            beg = memchr3_lsx(needle1, needle2, needle3, beg, beg.add(off));
+        }
+
+        while end.offset_from_unsigned(beg) >= 32 {
+            let v = lasx_xvld::<0>(beg as *const _);
+            let a = lasx_xvseq_b(v, n1);
+            let b = lasx_xvseq_b(v, n2);
+            let c = lasx_xvseq_b(v, n3);
+            let c = lasx_xvor_v(lasx_xvor_v(a, b), c);
+            let m = lasx_xvmskltz_b(c);
+            let l = lasx_xvpickve2gr_wu::<0>(m);
+            let h = lasx_xvpickve2gr_wu::<4>(m);
+            let m = (h << 16) | l;
+
+            if m != 0 {
+                return beg.add(m.trailing_zeros() as usize);
+            }
+
+            beg = beg.add(32);
+        }
+
+        memchr3_fallback(needle1, needle2, needle3, beg, end)
+    }
+}
+
+#[cfg(target_arch = "loongarch64")]
+#[target_feature(enable = "lsx")]
+unsafe fn memchr3_lsx(
+    needle1: u8,
+    needle2: u8,
+    needle3: u8,
+    mut beg: *const u8,
+    end: *const u8,
+) -> *const u8 {
+    unsafe {
+        use std::arch::loongarch64::*;
+
+        let n1 = lsx_vreplgr2vr_b(needle1 as i32);
+        let n2 = lsx_vreplgr2vr_b(needle2 as i32);
+        let n3 = lsx_vreplgr2vr_b(needle3 as i32);
+
+        let off = beg.align_offset(16);
+        if off != 0 && off < end.offset_from_unsigned(beg) {
+            beg = memchr3_fallback(needle1, needle2, needle3, beg, beg.add(off));
+        }
+
+        while end.offset_from_unsigned(beg) >= 16 {
+            let v = lsx_vld::<0>(beg as *const _);
+            let a = lsx_vseq_b(v, n1);
+            let b = lsx_vseq_b(v, n2);
+            let c = lsx_vseq_b(v, n3);
+            let c = lsx_vor_v(lsx_vor_v(a, b), c);
+            let m = lsx_vmskltz_b(c);
+            let m = lsx_vpickve2gr_wu::<0>(m);
+
+            if m != 0 {
+                return beg.add(m.trailing_zeros() as usize);
+            }
+
+            beg = beg.add(16);
+        }
+
+        memchr3_fallback(needle1, needle2, needle3, beg, end)
+    }
+}
+
+#[cfg(target_arch = "aarch64")]
+unsafe fn memchr3_neon(
+    needle1: u8,
+    needle2: u8,
+    needle3: u8,
+    mut beg: *const u8,
+    end: *const u8,
+) -> *const u8 {
+    unsafe {
+        use std::arch::aarch64::*;
+
+        if end.offset_from_unsigned(beg) >= 16 {
+            let n1 = vdupq_n_u8(needle1);
+            let n2 = vdupq_n_u8(needle2);
+            let n3 = vdupq_n_u8(needle3);
+
+            loop {
+                let v = vld1q_u8(beg as *const _);
+                let a = vceqq_u8(v, n1);
+                let b = vceqq_u8(v, n2);
+                let c = vceqq_u8(v, n3);
+                let c = vorrq_u8(vorrq_u8(a, b), c);
+
+                // https://community.arm.com/arm-community-blogs/b/servers-and-cloud-computing-blog/posts/porting-x86-vector-bitmask-optimizations-to-arm-neon
+                let m = vreinterpretq_u16_u8(c);
+                let m = vshrn_n_u16(m, 4);
+                let m = vreinterpret_u64_u8(m);
+                let m = vget_lane_u64(m, 0);
+
+                if m != 0 {
+                    return beg.add(m.trailing_zeros() as usize >> 2);
+                }
+
+                beg = beg.add(16);
+                if end.offset_from_unsigned(beg) < 16 {
+                    break;
+                }
+            }
+        }
+
+        memchr3_fallback(needle1, needle2, needle3, beg, end)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::slice;
+
+    use super::*;
+    use crate::sys;
+
+    #[test]
+    fn test_empty() {
+        assert_eq!(memchr3(b'a', b'b', b'c', b"", 0), 0);
+    }
+
+    #[test]
+    fn test_basic() {
+        let haystack = b"abcdefghijklmnopqrstuvwxyzABCDEFGHIJKLMNOPQRSTUVWXYZ";
+        let haystack = &haystack[..43];
+
+        assert_eq!(memchr3(b'a', b'y', b'z', haystack, 0), 0);
+        assert_eq!(memchr3(b'x', b'q', b'p', haystack, 0), 15);
+        assert_eq!(memchr3(b'Y', b'Z', b'Q', haystack, 0), 42);
+        assert_eq!(memchr3(b'0', b'5', b'9', haystack, 0), haystack.len());
+    }
+
+    // Test that it doesn't match before/after the start offset respectively.
+    #[test]
+    fn test_with_offset() {
+        let haystack = b"abcdefghabcdefghabcdefghabcdefghabcdefgh";
+
+        assert_eq!(memchr3(b'a', b'b', b'h', haystack, 0), 0);
+        assert_eq!(memchr3(b'a', b'b', b'h', haystack, 1), 1);
+        assert_eq!(memchr3(b'a', b'b', b'h', haystack, 2), 7);
+        assert_eq!(memchr3(b'a', b'b', b'h', haystack, 9), 9);
+        assert_eq!(memchr3(b'a', b'b', b'h', haystack, 16), 16);
+        assert_eq!(memchr3(b'a', b'b', b'h', haystack, 41), 40);
+    }
+
+    // Test memory access safety at page boundaries.
+    // The test is a success if it doesn't segfault.
+    #[test]
+    fn test_page_boundary() {
+        let page = unsafe {
+            const PAGE_SIZE: usize = 64 * 1024; // 64 KiB to cover many architectures.
+
+            // 3 pages: uncommitted, committed, uncommitted
+            let ptr = sys::virtual_reserve(PAGE_SIZE * 3).unwrap();
+            sys::virtual_commit(ptr.add(PAGE_SIZE), PAGE_SIZE).unwrap();
+            slice::from_raw_parts_mut(ptr.add(PAGE_SIZE).as_ptr(), PAGE_SIZE)
+        };
+
+        page.fill(b'a');
+
+        // Test if it seeks beyond the page boundary.
+        assert_eq!(memchr3(b'\0', b'\0', b'\0', &page[page.len() - 40..], 0), 40);
+        // Test if it seeks before the page boundary for the masked/partial load.
+        assert_eq!(memchr3(b'\0', b'\0', b'\0', &page[..10], 0), 10);
+    }
+}
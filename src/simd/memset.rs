@@ -76,7 +76,9 @@ fn memset_raw(beg: *mut u8, end: *mut u8, val: u64) {
     return unsafe { MEMSET_DISPATCH(beg, end, val) };
 
     #[cfg(target_arch = "aarch64")]
-    return unsafe { memset_neon(beg, end, val) };
+    if super::enabled("neon") {
+        return unsafe { memset_neon(beg, end, val) };
+    }
 
     #[allow(unreachable_code)]
     return unsafe { memset_fallback(beg, end, val) };
@@ -113,7 +115,13 @@ static mut MEMSET_DISPATCH: unsafe fn(beg: *mut u8, end: *mut u8, val: u64) = me
 
 #[cfg(any(target_arch = "x86", target_arch = "x86_64"))]
 fn memset_dispatch(beg: *mut u8, end: *mut u8, val: u64) {
-    let func = if is_x86_feature_detected!("avx2") { memset_avx2 } else { memset_sse2 };
+    let func = if super::enabled("avx512") {
+        memset_avx512
+    } else if super::enabled("avx2") {
+        memset_avx2
+    } else {
+        memset_sse2
+    };
     unsafe { MEMSET_DISPATCH = func };
     unsafe { func(beg, end, val) }
 }
@@ -167,6 +175,39 @@ unsafe fn memset_sse2(mut beg: *mut u8, end: *mut u8, val: u64) {
     }
 }
 
+#[cfg(any(target_arch = "x86", target_arch = "x86_64"))]
+#[target_feature(enable = "avx512f")]
+fn memset_avx512(mut beg: *mut u8, end: *mut u8, val: u64) {
+    unsafe {
+        #[cfg(target_arch = "x86")]
+        use std::arch::x86::*;
+        #[cfg(target_arch = "x86_64")]
+        use std::arch::x86_64::*;
+
+        let mut remaining = end.offset_from_unsigned(beg);
+
+        if remaining >= 256 {
+            let fill = _mm512_set1_epi64(val as i64);
+
+            loop {
+                _mm512_storeu_si512(beg as *mut _, fill);
+                _mm512_storeu_si512(beg.add(64) as *mut _, fill);
+                _mm512_storeu_si512(beg.add(128) as *mut _, fill);
+                _mm512_storeu_si512(beg.add(192) as *mut _, fill);
+
+                beg = beg.add(256);
+                remaining -= 256;
+                if remaining < 256 {
+                    break;
+                }
+            }
+        }
+
+        // AVX512F implies AVX2.
+        memset_avx2(beg, end, val)
+    }
+}
+
 #[cfg(any(target_arch = "x86", target_arch = "x86_64"))]
 #[target_feature(enable = "avx2")]
 fn memset_avx2(mut beg: *mut u8, end: *mut u8, val: u64) {
@@ -237,11 +278,9 @@ fn memset_avx2(mut beg: *mut u8, end: *mut u8, val: u64) {
 
 #[cfg(target_arch = "loongarch64")]
 fn memset_dispatch(beg: *mut u8, end: *mut u8, val: u64) {
-    use std::arch::is_loongarch_feature_detected;
-
-    let func = if is_loongarch_feature_detected!("lasx") {
+    let func = if super::enabled("lasx") {
         memset_lasx
-    } else if is_loongarch_feature_detected!("lsx") {
+    } else if super::enabled("lsx") {
         memset_lsx
     } else {
         memset_fallback
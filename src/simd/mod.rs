@@ -2,17 +2,116 @@
 // Licensed under the MIT License.
 
 //! Provides various high-throughput utilities.
+//!
+//! The best available instruction set extensions are picked at runtime. For troubleshooting,
+//! the `EDIT_SIMD` environment variable can restrict them: `EDIT_SIMD=avx2` disables AVX-512,
+//! `EDIT_SIMD=fallback` disables all of them, and so on. See [`active_features`].
 
+mod columns;
+mod control;
+mod hex;
+mod icase;
 pub mod lines_bwd;
 pub mod lines_fwd;
 mod memchr2;
+mod memchr3;
 mod memset;
 
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+pub use columns::*;
+pub use control::*;
+pub use hex::*;
+pub use icase::*;
 pub use lines_bwd::*;
 pub use lines_fwd::*;
 pub use memchr2::*;
+pub use memchr3::*;
 pub use memset::*;
 
+/// The extensions used on this architecture, from most to least capable.
+#[cfg(any(target_arch = "x86", target_arch = "x86_64"))]
+const EXTENSIONS: &[&str] = &["avx512", "avx2"];
+#[cfg(target_arch = "aarch64")]
+const EXTENSIONS: &[&str] = &["neon"];
+#[cfg(target_arch = "loongarch64")]
+const EXTENSIONS: &[&str] = &["lasx", "lsx"];
+#[cfg(target_arch = "riscv64")]
+const EXTENSIONS: &[&str] = &["rvv"];
+#[cfg(not(any(
+    target_arch = "x86",
+    target_arch = "x86_64",
+    target_arch = "aarch64",
+    target_arch = "loongarch64",
+    target_arch = "riscv64"
+)))]
+const EXTENSIONS: &[&str] = &[];
+
+/// Index of the best extension in `EXTENSIONS` that `EDIT_SIMD` allows, or `usize::MAX` if
+/// it hasn't been read yet. Dispatching happens lazily, so this has to be lazy as well.
+static ALLOWED_FROM: AtomicUsize = AtomicUsize::new(usize::MAX);
+
+fn allowed_from() -> usize {
+    let mut idx = ALLOWED_FROM.load(Ordering::Relaxed);
+    if idx == usize::MAX {
+        idx = parse_override(std::env::var("EDIT_SIMD").ok().as_deref());
+        ALLOWED_FROM.store(idx, Ordering::Relaxed);
+    }
+    idx
+}
+
+/// Unknown values (including extensions of other architectures) are ignored.
+fn parse_override(value: Option<&str>) -> usize {
+    match value {
+        Some(v) if v.eq_ignore_ascii_case("fallback") => EXTENSIONS.len(),
+        Some(v) => EXTENSIONS.iter().position(|e| e.eq_ignore_ascii_case(v)).unwrap_or(0),
+        None => 0,
+    }
+}
+
+/// Returns whether the CPU supports the given entry of `EXTENSIONS` and `EDIT_SIMD` allows it.
+#[inline]
+fn enabled(ext: &str) -> bool {
+    let Some(idx) = EXTENSIONS.iter().position(|&e| e == ext) else {
+        debug_assert!(false, "unknown extension: {ext}");
+        return false;
+    };
+    idx >= allowed_from() && detected(ext)
+}
+
+#[allow(unused_variables)]
+fn detected(ext: &str) -> bool {
+    #[cfg(any(target_arch = "x86", target_arch = "x86_64"))]
+    return match ext {
+        // `memset` only needs AVX512F, but every CPU with AVX512BW has that as well.
+        "avx512" => is_x86_feature_detected!("avx512bw"),
+        "avx2" => is_x86_feature_detected!("avx2"),
+        _ => false,
+    };
+
+    #[cfg(target_arch = "aarch64")]
+    return true;
+
+    #[cfg(target_arch = "loongarch64")]
+    return match ext {
+        "lasx" => std::arch::is_loongarch_feature_detected!("lasx"),
+        "lsx" => std::arch::is_loongarch_feature_detected!("lsx"),
+        _ => false,
+    };
+
+    #[cfg(target_arch = "riscv64")]
+    return std::arch::is_riscv_feature_detected!("v");
+
+    #[allow(unreachable_code)]
+    false
+}
+
+/// Returns the SIMD instruction set extensions in use, from most to least capable,
+/// e.g. `["avx512", "avx2"]`. It's empty if only the scalar fallbacks are used.
+pub fn active_features() -> impl Iterator<Item = &'static str> {
+    EXTENSIONS.iter().copied().filter(|e| enabled(e))
+}
+
 #[cfg(test)]
 mod test {
     // Knuth's MMIX LCG
@@ -40,4 +139,17 @@ mod test {
     pub fn count_lines(text: &str) -> usize {
         text.lines().count()
     }
+
+    #[test]
+    fn test_parse_override() {
+        use super::*;
+
+        assert_eq!(parse_override(None), 0);
+        assert_eq!(parse_override(Some("")), 0);
+        assert_eq!(parse_override(Some("bogus")), 0);
+        assert_eq!(parse_override(Some("Fallback")), EXTENSIONS.len());
+        for (i, ext) in EXTENSIONS.iter().enumerate() {
+            assert_eq!(parse_override(Some(ext)), i);
+        }
+    }
 }
@@ -0,0 +1,179 @@
+// Copyright (c) Microsoft Corporation.
+// Licensed under the MIT License.
+
+//! Memory-mapped files, for reading large files without copying them into memory.
+//!
+//! Uses `mmap` on Unix and file mapping objects on Windows.
+
+use std::fs::File;
+use std::ops::Deref;
+use std::ptr::NonNull;
+
+use crate::apperr;
+
+/// How the mapped memory may be accessed.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum MmapMode {
+    ReadOnly,
+    /// Writes are private to the mapping and never reach the file.
+    CopyOnWrite,
+}
+
+/// Hints about how the mapping is going to be accessed. See [`Mmap::advise`].
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum MmapAdvice {
+    Normal,
+    /// Read ahead aggressively and drop pages soon after they were read. Ideal for searching.
+    Sequential,
+    /// Don't read ahead.
+    Random,
+    /// Start reading the pages in the background.
+    WillNeed,
+    /// The pages can be dropped. On Linux this also discards copy-on-write changes.
+    DontNeed,
+}
+
+/// A memory-mapped view of a file. Dereferences to the mapped bytes.
+///
+/// If another process truncates the file while it's mapped, accessing the missing pages
+/// crashes the process with a SIGBUS. Windows prevents this by refusing the truncation.
+pub struct Mmap {
+    /// The start of the mapping, aligned to [`super::mmap_granularity`].
+    base: NonNull<u8>,
+    /// The size of the mapping, starting at `base`.
+    map_len: usize,
+    /// The start of the requested range, relative to `base`.
+    offset: usize,
+    mode: MmapMode,
+}
+
+// The mapping is just memory, and the file handle isn't needed after creating it.
+unsafe impl Send for Mmap {}
+unsafe impl Sync for Mmap {}
+
+impl Mmap {
+    /// Maps the entire `file`.
+    ///
+    /// # Safety
+    ///
+    /// See [`Mmap::map_range`].
+    pub unsafe fn map(file: &File, mode: MmapMode) -> apperr::Result<Self> {
+        unsafe { Self::map_range(file, 0, usize::MAX, mode) }
+    }
+
+    /// Maps `len` bytes of `file` starting at `offset`, clamped to the end of the file.
+    /// The `offset` doesn't need to be aligned: The mapping starts at the preceding
+    /// page boundary, but only the requested range is exposed.
+    ///
+    /// # Safety
+    ///
+    /// The mapped bytes are only valid for as long as no one else modifies or truncates
+    /// the file. The caller must ensure that, e.g. by only mapping files it owns.
+    /// Otherwise the bytes may change under a shared reference, or accessing them
+    /// may crash the process (see [`Mmap`]).
+    pub unsafe fn map_range(
+        file: &File,
+        offset: u64,
+        len: usize,
+        mode: MmapMode,
+    ) -> apperr::Result<Self> {
+        let file_len = file.metadata()?.len();
+        let offset = offset.min(file_len);
+        let len = len.min(usize::try_from(file_len - offset).unwrap_or(usize::MAX));
+        let aligned = offset & !(super::mmap_granularity() as u64 - 1);
+        let delta = (offset - aligned) as usize;
+
+        if len == 0 {
+            // Zero-length mappings aren't allowed.
+            return Ok(Self { base: NonNull::dangling(), map_len: 0, offset: 0, mode });
+        }
+
+        let map_len = delta.checked_add(len).ok_or(apperr::APP_FILE_TOO_LARGE)?;
+        let base = unsafe { super::mmap_file(file, aligned, map_len, mode)? };
+        Ok(Self { base, map_len, offset: delta, mode })
+    }
+
+    pub fn mode(&self) -> MmapMode {
+        self.mode
+    }
+
+    /// Returns the mapped bytes for modification, if the mode is [`MmapMode::CopyOnWrite`].
+    pub fn as_mut_slice(&mut self) -> Option<&mut [u8]> {
+        if self.mode != MmapMode::CopyOnWrite {
+            return None;
+        }
+        let len = self.len();
+        Some(unsafe { std::slice::from_raw_parts_mut(self.base.add(self.offset).as_ptr(), len) })
+    }
+
+    /// Tells the OS how the mapping is going to be accessed. It's only a hint and may be ignored.
+    pub fn advise(&self, advice: MmapAdvice) {
+        if self.map_len != 0 {
+            unsafe { super::mmap_advise(self.base, self.map_len, advice) };
+        }
+    }
+}
+
+impl Deref for Mmap {
+    type Target = [u8];
+
+    fn deref(&self) -> &[u8] {
+        let len = self.map_len - self.offset;
+        unsafe { std::slice::from_raw_parts(self.base.add(self.offset).as_ptr(), len) }
+    }
+}
+
+impl Drop for Mmap {
+    fn drop(&mut self) {
+        if self.map_len != 0 {
+            unsafe { super::mmap_release(self.base, self.map_len) };
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Write as _;
+    use std::{env, fs};
+
+    use super::*;
+
+    #[test]
+    fn test_mmap() {
+        let path = env::temp_dir().join(format!("edit-mmap-{}", std::process::id()));
+        let granularity = crate::sys::mmap_granularity();
+        let data: Vec<u8> = (0..granularity * 2 + 123).map(|i| i as u8).collect();
+        fs::File::create(&path).unwrap().write_all(&data).unwrap();
+        let file = File::open(&path).unwrap();
+
+        let map = unsafe { Mmap::map(&file, MmapMode::ReadOnly) }.unwrap();
+        assert_eq!(&map[..], &data[..]);
+
+        // Unaligned offsets, and ranges past the end of the file.
+        let map_range =
+            |offset, len| unsafe { Mmap::map_range(&file, offset, len, MmapMode::ReadOnly) };
+        let map = map_range(granularity as u64 + 7, 100).unwrap();
+        assert_eq!(&map[..], &data[granularity + 7..granularity + 107]);
+        let map = map_range(data.len() as u64 - 3, 100).unwrap();
+        assert_eq!(&map[..], &data[data.len() - 3..]);
+        let map = map_range(data.len() as u64 + 1, 100).unwrap();
+        assert!(map.is_empty());
+
+        // Copy-on-write changes don't reach the file.
+        let mut map = unsafe { Mmap::map(&file, MmapMode::CopyOnWrite) }.unwrap();
+        map.advise(MmapAdvice::Sequential);
+        map.as_mut_slice().unwrap()[0] = 0xff;
+        assert_eq!(map[0], 0xff);
+        drop(map);
+        assert_eq!(fs::read(&path).unwrap(), data);
+
+        fs::File::create(&path).unwrap();
+        let mut map =
+            unsafe { Mmap::map(&File::open(&path).unwrap(), MmapMode::ReadOnly) }.unwrap();
+        assert!(map.is_empty());
+        assert!(map.as_mut_slice().is_none());
+
+        drop(file);
+        fs::remove_file(&path).unwrap();
+    }
+}
@@ -3,6 +3,11 @@
 
 //! Platform abstractions.
 
+use std::sync::atomic::{AtomicU8, Ordering};
+
+mod mmap;
+mod process;
+mod pty;
 #[cfg(unix)]
 mod unix;
 #[cfg(windows)]
@@ -11,7 +16,62 @@ mod windows;
 #[cfg(not(windows))]
 pub use std::fs::canonicalize;
 
+pub use mmap::{Mmap, MmapAdvice, MmapMode};
+pub use process::Process;
+pub use pty::Pty;
 #[cfg(unix)]
 pub use unix::*;
 #[cfg(windows)]
 pub use windows::*;
+
+/// The standard streams the process got started with.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum StdStream {
+    Stdin,
+    Stdout,
+}
+
+/// The per-user directories that applications store their files in.
+/// See [`known_dir`].
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum KnownDir {
+    /// Settings that the user may edit by hand.
+    Config,
+    /// Data that should persist between sessions, but isn't worth backing up,
+    /// like the history or recovery files.
+    State,
+    /// Data that can be regenerated at any time.
+    Cache,
+    /// Data that the user wouldn't want to lose, like downloaded dictionaries.
+    Data,
+}
+
+/// Signals, or their equivalent on Windows, that the editor needs to react to.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Signal {
+    /// SIGTERM, SIGHUP, or the console window getting closed.
+    Terminate,
+    /// SIGTSTP. See [`suspend`].
+    Suspend,
+    /// SIGCONT, after the process got stopped.
+    Resume,
+}
+
+static PENDING_SIGNALS: AtomicU8 = AtomicU8::new(0);
+
+/// Marks the `signal` as pending. It's safe to call from signal handlers.
+fn signal_raise(signal: Signal) {
+    PENDING_SIGNALS.fetch_or(1 << signal as u8, Ordering::Relaxed);
+}
+
+/// Returns the next pending signal, if any.
+/// [`read_stdin`] returns early when one arrives, the same way it does on a timeout.
+pub fn take_signal() -> Option<Signal> {
+    let pending = PENDING_SIGNALS.load(Ordering::Relaxed);
+    if pending == 0 {
+        return None;
+    }
+    let bit = pending.trailing_zeros();
+    PENDING_SIGNALS.fetch_and(!(1 << bit), Ordering::Relaxed);
+    Some([Signal::Terminate, Signal::Suspend, Signal::Resume][bit as usize])
+}
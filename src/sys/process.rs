@@ -0,0 +1,231 @@
+// Copyright (c) Microsoft Corporation.
+// Licensed under the MIT License.
+
+//! Child processes with piped standard streams that never block the UI.
+//!
+//! Each pipe is serviced by a background thread. Whenever output arrives, they call
+//! [`wakeup`], which makes the main loop run another frame, in which the output can
+//! be collected without blocking via [`Process::read_stdout`].
+
+use std::io::{self, Read, Write};
+use std::process::{Child, Command, ExitStatus, Stdio};
+use std::sync::{Arc, Mutex, mpsc};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use super::wakeup;
+use crate::apperr;
+use crate::helpers::KIBI;
+
+#[derive(Default)]
+pub(super) struct Output {
+    data: Vec<u8>,
+    eof: bool,
+}
+
+pub(super) type SharedOutput = Arc<Mutex<Output>>;
+
+/// A running child process. It gets killed when dropped.
+pub struct Process {
+    child: Child,
+    stdin: Option<mpsc::Sender<Vec<u8>>>,
+    stdout: SharedOutput,
+    stderr: SharedOutput,
+    deadline: Option<Instant>,
+    status: Option<ExitStatus>,
+    timed_out: bool,
+}
+
+impl Process {
+    /// Spawns the `command` with all of its standard streams piped.
+    /// Arguments, environment and working directory are set up via `command`.
+    pub fn spawn(command: &mut Command) -> apperr::Result<Self> {
+        let mut child =
+            command.stdin(Stdio::piped()).stdout(Stdio::piped()).stderr(Stdio::piped()).spawn()?;
+        let stdin = child.stdin.take().map(spawn_writer);
+        let stdout = spawn_reader(child.stdout.take());
+        let stderr = spawn_reader(child.stderr.take());
+
+        Ok(Self { child, stdin, stdout, stderr, deadline: None, status: None, timed_out: false })
+    }
+
+    /// Queues the `data` to be written to the process' stdin.
+    pub fn write(&self, data: &[u8]) {
+        if let Some(stdin) = &self.stdin {
+            _ = stdin.send(data.to_vec());
+        }
+    }
+
+    /// Closes stdin once everything queued has been written.
+    /// Many tools only finish once they've read their input to the end.
+    pub fn close_stdin(&mut self) {
+        self.stdin = None;
+    }
+
+    /// Appends the stdout output received so far to `dst`.
+    /// Returns `false` once the process closed its stdout and nothing is left.
+    pub fn read_stdout(&self, dst: &mut Vec<u8>) -> bool {
+        take_output(&self.stdout, dst)
+    }
+
+    /// Same as [`Process::read_stdout`], but for stderr.
+    pub fn read_stderr(&self, dst: &mut Vec<u8>) -> bool {
+        take_output(&self.stderr, dst)
+    }
+
+    /// Kills the process if it's still running after `timeout`.
+    /// The timeout is checked by [`Process::try_wait`].
+    pub fn set_timeout(&mut self, timeout: Duration) {
+        self.deadline = Some(Instant::now() + timeout);
+    }
+
+    /// The time left until the timeout expires. The main loop should wait at most
+    /// this long for input, so that [`Process::try_wait`] gets called in time.
+    pub fn read_timeout(&self) -> Duration {
+        match self.deadline {
+            Some(deadline) if self.status.is_none() => {
+                deadline.saturating_duration_since(Instant::now())
+            }
+            _ => Duration::MAX,
+        }
+    }
+
+    /// Returns whether the process got killed because its timeout expired.
+    pub fn timed_out(&self) -> bool {
+        self.timed_out
+    }
+
+    /// Returns the exit status if the process has exited, without blocking.
+    pub fn try_wait(&mut self) -> apperr::Result<Option<ExitStatus>> {
+        if self.status.is_none() {
+            if let Some(deadline) = self.deadline
+                && Instant::now() >= deadline
+            {
+                self.timed_out = true;
+                self.kill();
+            }
+            self.status = self.child.try_wait()?;
+        }
+        Ok(self.status)
+    }
+
+    /// Kills the process. Its exit status can still be retrieved with [`Process::try_wait`].
+    pub fn kill(&mut self) {
+        if self.status.is_none() {
+            _ = self.child.kill();
+        }
+    }
+}
+
+impl Drop for Process {
+    fn drop(&mut self) {
+        // Don't leave zombies behind.
+        if self.status.is_none() {
+            _ = self.child.kill();
+            _ = self.child.wait();
+        }
+    }
+}
+
+pub(super) fn spawn_writer(mut stdin: impl Write + Send + 'static) -> mpsc::Sender<Vec<u8>> {
+    let (sender, receiver) = mpsc::channel::<Vec<u8>>();
+    // The loop ends once the sender is dropped, which then closes the pipe.
+    thread::spawn(move || {
+        for data in receiver {
+            if stdin.write_all(&data).is_err() {
+                break;
+            }
+        }
+    });
+    sender
+}
+
+pub(super) fn spawn_reader(pipe: Option<impl Read + Send + 'static>) -> SharedOutput {
+    let output = SharedOutput::default();
+
+    let Some(mut pipe) = pipe else {
+        output.lock().unwrap().eof = true;
+        return output;
+    };
+
+    let shared = output.clone();
+    thread::spawn(move || {
+        let mut buf = [0; 4 * KIBI];
+        loop {
+            let n = match pipe.read(&mut buf) {
+                Ok(n) => n,
+                Err(err) if err.kind() == io::ErrorKind::Interrupted => continue,
+                Err(_) => 0,
+            };
+
+            {
+                let mut output = shared.lock().unwrap();
+                if n == 0 {
+                    output.eof = true;
+                } else {
+                    output.data.extend_from_slice(&buf[..n]);
+                }
+            }
+
+            wakeup();
+            if n == 0 {
+                break;
+            }
+        }
+    });
+
+    output
+}
+
+pub(super) fn take_output(output: &Mutex<Output>, dst: &mut Vec<u8>) -> bool {
+    let mut output = output.lock().unwrap();
+    dst.append(&mut output.data);
+    !output.eof
+}
+
+#[cfg(all(test, unix))]
+mod tests {
+    use super::*;
+
+    fn wait(process: &mut Process) -> ExitStatus {
+        loop {
+            if let Some(status) = process.try_wait().unwrap() {
+                return status;
+            }
+            thread::sleep(Duration::from_millis(1));
+        }
+    }
+
+    #[test]
+    fn test_pipes() {
+        let mut process =
+            Process::spawn(Command::new("sh").args(["-c", "cat; echo $FOO >&2"]).env("FOO", "bar"))
+                .unwrap();
+        process.write(b"hello ");
+        process.write(b"world");
+        process.close_stdin();
+
+        let mut stdout = Vec::new();
+        let mut stderr = Vec::new();
+        while process.read_stdout(&mut stdout) | process.read_stderr(&mut stderr) {
+            thread::sleep(Duration::from_millis(1));
+        }
+
+        assert_eq!(stdout, b"hello world");
+        assert_eq!(stderr, b"bar\n");
+        assert!(wait(&mut process).success());
+        assert!(!process.timed_out());
+    }
+
+    #[test]
+    fn test_timeout() {
+        let mut process = Process::spawn(Command::new("sleep").arg("10")).unwrap();
+        process.set_timeout(Duration::from_millis(10));
+        assert!(process.read_timeout() <= Duration::from_millis(10));
+
+        let status = wait(&mut process);
+        assert!(!status.success());
+        assert!(process.timed_out());
+        assert_eq!(process.read_timeout(), Duration::MAX);
+    }
+}
@@ -0,0 +1,115 @@
+// Copyright (c) Microsoft Corporation.
+// Licensed under the MIT License.
+
+//! Pseudo-terminals, for running interactive programs like shells.
+//!
+//! Uses `openpty` on Unix and ConPTY on Windows. Like [`super::Process`], the
+//! output is read by a background thread which calls [`super::wakeup`].
+
+use std::process::Command;
+use std::sync::mpsc;
+
+use super::PtyChild;
+use super::process::{SharedOutput, spawn_reader, spawn_writer, take_output};
+use crate::apperr;
+use crate::helpers::Size;
+
+/// A child process running on a pseudo-terminal. It gets killed when dropped.
+pub struct Pty {
+    child: PtyChild,
+    input: mpsc::Sender<Vec<u8>>,
+    output: SharedOutput,
+    size: Size,
+    exit_code: Option<i32>,
+}
+
+impl Pty {
+    /// Spawns the `command` on a new pseudo-terminal of the given size.
+    /// Arguments, environment and working directory are set up via `command`.
+    pub fn spawn(command: &mut Command, size: Size) -> apperr::Result<Self> {
+        let (child, reader, writer) = PtyChild::spawn(command, size)?;
+        let input = spawn_writer(writer);
+        let output = spawn_reader(Some(reader));
+        Ok(Self { child, input, output, size, exit_code: None })
+    }
+
+    /// Queues the `data` to be written to the terminal, as if it was typed.
+    pub fn write(&self, data: &[u8]) {
+        _ = self.input.send(data.to_vec());
+    }
+
+    /// Appends the terminal output received so far to `dst`.
+    /// Returns `false` once the terminal got closed and nothing is left.
+    pub fn read(&self, dst: &mut Vec<u8>) -> bool {
+        take_output(&self.output, dst)
+    }
+
+    /// Propagates a new window size to the terminal and the program running in it.
+    pub fn resize(&mut self, size: Size) -> apperr::Result<()> {
+        if size != self.size {
+            self.child.resize(size)?;
+            self.size = size;
+        }
+        Ok(())
+    }
+
+    pub fn size(&self) -> Size {
+        self.size
+    }
+
+    /// Returns the exit code if the process has exited, without blocking.
+    pub fn try_wait(&mut self) -> apperr::Result<Option<i32>> {
+        if self.exit_code.is_none() {
+            self.exit_code = self.child.try_wait()?;
+        }
+        Ok(self.exit_code)
+    }
+
+    pub fn kill(&mut self) {
+        if self.exit_code.is_none() {
+            self.child.kill();
+        }
+    }
+}
+
+#[cfg(all(test, unix))]
+mod tests {
+    use std::thread;
+    use std::time::Duration;
+
+    use super::*;
+
+    fn read_until(pty: &Pty, output: &mut Vec<u8>, needle: &[u8]) {
+        for _ in 0..5000 {
+            pty.read(output);
+            if output.windows(needle.len()).any(|w| w == needle) {
+                return;
+            }
+            thread::sleep(Duration::from_millis(1));
+        }
+        panic!("{:?} not found in {:?}", needle, String::from_utf8_lossy(output));
+    }
+
+    #[test]
+    fn test_pty() {
+        let mut pty = Pty::spawn(
+            Command::new("sh").args(["-c", "stty size; read x; stty size; exit 3"]),
+            Size { width: 40, height: 10 },
+        )
+        .unwrap();
+        let mut output = Vec::new();
+        read_until(&pty, &mut output, b"10 40\r\n");
+
+        pty.resize(Size { width: 60, height: 20 }).unwrap();
+        pty.write(b"\n");
+        read_until(&pty, &mut output, b"20 60\r\n");
+
+        while pty.read(&mut output) {
+            thread::sleep(Duration::from_millis(1));
+        }
+        while pty.try_wait().unwrap().is_none() {
+            thread::sleep(Duration::from_millis(1));
+        }
+        assert_eq!(pty.try_wait().unwrap(), Some(3));
+    }
+}
@@ -6,14 +6,21 @@
 //! Read the `windows` module for reference.
 //! TODO: This reminds me that the sys API should probably be a trait.
 
-use std::ffi::{CStr, c_char, c_int, c_void};
-use std::fs::File;
+use std::ffi::{CStr, CString, OsStr, OsString, c_char, c_int, c_void};
+use std::fs::{self, File};
+use std::io::{self, Write as _};
 use std::mem::{self, ManuallyDrop, MaybeUninit};
 use std::os::fd::{AsRawFd as _, FromRawFd as _};
-use std::path::Path;
+use std::os::unix::ffi::OsStrExt as _;
+use std::os::unix::fs::{MetadataExt as _, OpenOptionsExt as _};
+use std::os::unix::process::{CommandExt as _, ExitStatusExt as _};
+use std::path::{Path, PathBuf};
+use std::process::{Child, Command, ExitStatus, Stdio};
 use std::ptr::{self, NonNull, null_mut};
-use std::{thread, time};
+use std::sync::OnceLock;
+use std::{env, thread, time};
 
+use super::{KnownDir, MmapAdvice, MmapMode, StdStream};
 use crate::arena::{Arena, ArenaString, scratch_arena};
 use crate::helpers::*;
 use crate::{apperr, arena_format};
@@ -42,6 +49,7 @@ struct State {
     // Buffer for incomplete UTF-8 sequences (max 4 bytes needed)
     utf8_buf: [u8; 4],
     utf8_len: usize,
+    watcher: Watcher,
 }
 
 static mut STATE: State = State {
@@ -52,11 +60,34 @@ static mut STATE: State = State {
     inject_resize: false,
     utf8_buf: [0; 4],
     utf8_len: 0,
+    watcher: Watcher { fd: -1, watches: Vec::new(), changes: Vec::new() },
 };
 
-extern "C" fn sigwinch_handler(_: libc::c_int) {
+extern "C" fn signal_handler(sig: libc::c_int) {
+    match sig {
+        libc::SIGWINCH => unsafe { STATE.inject_resize = true },
+        libc::SIGTERM | libc::SIGHUP => super::signal_raise(super::Signal::Terminate),
+        libc::SIGTSTP => super::signal_raise(super::Signal::Suspend),
+        libc::SIGCONT => super::signal_raise(super::Signal::Resume),
+        _ => {}
+    }
+    // The signal may have been delivered to another thread, in which case
+    // `read_stdin` doesn't get interrupted by it. So, wake it up explicitly.
+    if let Some(fds) = WAKEUP_PIPE.get() {
+        unsafe { libc::write(fds[1], [0u8].as_ptr() as *const c_void, 1) };
+    }
+}
+
+/// The signals handled by `signal_handler`.
+const HANDLED_SIGNALS: [c_int; 5] =
+    [libc::SIGWINCH, libc::SIGTERM, libc::SIGHUP, libc::SIGTSTP, libc::SIGCONT];
+
+fn install_signal_handler(sig: c_int) -> apperr::Result<()> {
     unsafe {
-        STATE.inject_resize = true;
+        let mut action: libc::sigaction = mem::zeroed();
+        action.sa_sigaction = signal_handler as *const () as libc::sighandler_t;
+        check_int_return(libc::sigaction(sig, &action, null_mut()))?;
+        Ok(())
     }
 }
 
@@ -70,14 +101,21 @@ pub fn switch_modes() -> apperr::Result<()> {
         if libc::isatty(STATE.stdin) == 0 {
             STATE.stdin = check_int_return(libc::open(c"/dev/tty".as_ptr(), libc::O_RDONLY))?;
         }
+        // Same for stdout, e.g. when our output gets piped into another program.
+        // `--help` and the like still get printed into the redirection.
+        if libc::isatty(STATE.stdout) == 0 {
+            STATE.stdout = check_int_return(libc::open(c"/dev/tty".as_ptr(), libc::O_WRONLY))?;
+        }
 
         // Store the stdin flags so we can more easily toggle `O_NONBLOCK` later on.
         STATE.stdin_flags = check_int_return(libc::fcntl(STATE.stdin, libc::F_GETFL))?;
 
-        // Set STATE.inject_resize to true whenever we get a SIGWINCH.
-        let mut sigwinch_action: libc::sigaction = mem::zeroed();
-        sigwinch_action.sa_sigaction = sigwinch_handler as libc::sighandler_t;
-        check_int_return(libc::sigaction(libc::SIGWINCH, &sigwinch_action, null_mut()))?;
+        // Set STATE.inject_resize to true whenever we get a SIGWINCH,
+        // and turn the others into `Signal`s. The handler needs the wakeup pipe.
+        wakeup_pipe();
+        for sig in HANDLED_SIGNALS {
+            install_signal_handler(sig)?;
+        }
 
         // Get the original terminal modes so we can disable raw mode on exit.
         let mut termios = MaybeUninit::<libc::termios>::uninit();
@@ -150,6 +188,31 @@ impl Drop for Deinit {
     }
 }
 
+/// Stops the process, the way SIGTSTP does without our handler, and returns once it got continued.
+/// In the meantime, the terminal is in its original mode. The caller must restore
+/// and redraw everything else it changed about the terminal.
+pub fn suspend() {
+    unsafe {
+        let mut raw = MaybeUninit::<libc::termios>::uninit();
+        let has_raw = libc::tcgetattr(STATE.stdout, raw.as_mut_ptr()) == 0;
+        #[allow(static_mut_refs)]
+        if let Some(termios) = &STATE.stdout_initial_termios {
+            libc::tcsetattr(STATE.stdout, libc::TCSANOW, termios);
+        }
+
+        // Stop the entire process group, like the shell expects it.
+        libc::signal(libc::SIGTSTP, libc::SIG_DFL);
+        libc::kill(0, libc::SIGTSTP);
+        _ = install_signal_handler(libc::SIGTSTP);
+
+        if has_raw {
+            libc::tcsetattr(STATE.stdout, libc::TCSANOW, raw.as_ptr());
+        }
+        // The terminal may have been resized while we were stopped.
+        STATE.inject_resize = true;
+    }
+}
+
 pub fn inject_window_size_into_stdin() {
     unsafe {
         STATE.inject_resize = true;
@@ -205,10 +268,20 @@ pub fn read_stdin(arena: &Arena, mut timeout: time::Duration) -> Option<ArenaStr
         }
 
         loop {
-            if timeout != time::Duration::MAX {
+            // If files are being watched or other threads may wake us up,
+            // we need to wait for those as well. poll() ignores negative fds.
+            let watch_fd = STATE.watcher.fd;
+            let wakeup_fd = WAKEUP_PIPE.get().map_or(-1, |fds| fds[0]);
+            if timeout != time::Duration::MAX || watch_fd >= 0 || wakeup_fd >= 0 {
                 let beg = time::Instant::now();
-
-                let mut pollfd = libc::pollfd { fd: STATE.stdin, events: libc::POLLIN, revents: 0 };
+                let infinite = timeout == time::Duration::MAX;
+
+                let mut pollfds = [
+                    libc::pollfd { fd: STATE.stdin, events: libc::POLLIN, revents: 0 },
+                    libc::pollfd { fd: watch_fd, events: libc::POLLIN, revents: 0 },
+                    libc::pollfd { fd: wakeup_fd, events: libc::POLLIN, revents: 0 },
+                ];
+                let nfds = pollfds.len() as libc::nfds_t;
                 let ret;
                 #[cfg(target_os = "linux")]
                 {
@@ -216,20 +289,53 @@ pub fn read_stdin(arena: &Arena, mut timeout: time::Duration) -> Option<ArenaStr
                         tv_sec: timeout.as_secs() as libc::time_t,
                         tv_nsec: timeout.subsec_nanos() as libc::c_long,
                     };
-                    ret = libc::ppoll(&mut pollfd, 1, &ts, ptr::null());
+                    let ts = if infinite { ptr::null() } else { &raw const ts };
+                    ret = libc::ppoll(pollfds.as_mut_ptr(), nfds, ts, ptr::null());
                 }
                 #[cfg(not(target_os = "linux"))]
                 {
-                    ret = libc::poll(&mut pollfd, 1, timeout.as_millis() as libc::c_int);
+                    let ms = if infinite { -1 } else { timeout.as_millis() as libc::c_int };
+                    ret = libc::poll(pollfds.as_mut_ptr(), nfds, ms);
                 }
                 if ret < 0 {
-                    return None; // Error? Let's assume it's an EOF.
+                    match errno() {
+                        libc::EINTR if STATE.inject_resize => break,
+                        libc::EINTR => continue,
+                        _ => return None, // Error? Let's assume it's an EOF.
+                    }
                 }
                 if ret == 0 {
                     break; // Timeout? We can stop reading.
                 }
+                if pollfds[2].revents != 0 {
+                    // Drain the pipe, so that the next poll() blocks again.
+                    let mut drain = [0u8; 64];
+                    while libc::read(wakeup_fd, drain.as_mut_ptr() as *mut c_void, drain.len()) > 0
+                    {
+                    }
+                }
+                // Files are watched through their parent directory, which reports their
+                // siblings as well. Only the events for the watched files are kept.
+                #[allow(static_mut_refs)]
+                let watcher = &mut STATE.watcher;
+                if pollfds[1].revents != 0 {
+                    watch_read(watcher.fd, &mut watcher.watches, &mut watcher.changes);
+                }
+                if pollfds[0].revents == 0 {
+                    // Only a watched file changed (see `file_watch_changes`) or we got woken up.
+                    if pollfds[2].revents != 0 || !watcher.changes.is_empty() {
+                        break;
+                    }
+                    // Otherwise, the events were for other files. Keep waiting.
+                    if !infinite {
+                        timeout = timeout.saturating_sub(beg.elapsed());
+                    }
+                    continue;
+                }
 
-                timeout = timeout.saturating_sub(beg.elapsed());
+                if !infinite {
+                    timeout = timeout.saturating_sub(beg.elapsed());
+                }
             };
 
             // If we're asked for a non-blocking read we need
@@ -304,6 +410,33 @@ pub fn read_stdin(arena: &Arena, mut timeout: time::Duration) -> Option<ArenaStr
     }
 }
 
+/// The self-pipe used by [`wakeup`]. Both ends are non-blocking.
+static WAKEUP_PIPE: OnceLock<[c_int; 2]> = OnceLock::new();
+
+fn wakeup_pipe() -> &'static [c_int; 2] {
+    WAKEUP_PIPE.get_or_init(|| unsafe {
+        let mut fds = [-1; 2];
+        if libc::pipe(fds.as_mut_ptr()) < 0 {
+            return [-1; 2];
+        }
+        for fd in fds {
+            libc::fcntl(fd, libc::F_SETFL, libc::fcntl(fd, libc::F_GETFL) | libc::O_NONBLOCK);
+            libc::fcntl(fd, libc::F_SETFD, libc::FD_CLOEXEC);
+        }
+        fds
+    })
+}
+
+/// Makes a pending or future [`read_stdin`] return early, as if it timed out.
+/// Unlike the rest of this module, it can be called from any thread.
+pub fn wakeup() {
+    let fds = wakeup_pipe();
+    if fds[1] >= 0 {
+        // If the pipe is full, a wakeup is already pending. That's fine.
+        unsafe { libc::write(fds[1], [0u8].as_ptr() as *const c_void, 1) };
+    }
+}
+
 pub fn write_stdout(text: &str) {
     if text.is_empty() {
         return;
@@ -347,14 +480,751 @@ fn set_tty_nonblocking(nonblock: bool) {
     }
 }
 
+/// Returns whether the given standard stream is a terminal, as opposed to a pipe or file.
+/// This refers to the original stream, even after [`switch_modes`] replaced it with the terminal.
+pub fn is_terminal(stream: StdStream) -> bool {
+    let fd = match stream {
+        StdStream::Stdin => libc::STDIN_FILENO,
+        StdStream::Stdout => libc::STDOUT_FILENO,
+    };
+    unsafe { libc::isatty(fd) != 0 }
+}
+
+/// Returns the original stdin if it's redirected (= piped input), so that it can be read
+/// into a document. It's taken over by the returned file and must only be called once.
 pub fn open_stdin_if_redirected() -> Option<File> {
-    unsafe {
-        // Did we reopen stdin during `init()`?
-        if STATE.stdin != libc::STDIN_FILENO {
-            Some(File::from_raw_fd(libc::STDIN_FILENO))
+    if is_terminal(StdStream::Stdin) {
+        None
+    } else {
+        Some(unsafe { File::from_raw_fd(libc::STDIN_FILENO) })
+    }
+}
+
+/// The platform-specific half of [`super::Pty`].
+pub(crate) struct PtyChild {
+    master: File,
+    child: Child,
+}
+
+impl PtyChild {
+    /// Spawns the `command` on a new pseudo-terminal of the given size.
+    /// Returns the child and the master side of the PTY for reading and writing.
+    pub(crate) fn spawn(command: &mut Command, size: Size) -> apperr::Result<(Self, File, File)> {
+        unsafe {
+            let mut master = -1;
+            let mut slave = -1;
+            let mut winsize = winsize_from_size(size);
+            check_int_return(libc::openpty(
+                &mut master,
+                &mut slave,
+                null_mut(),
+                null_mut(),
+                // Some platforms take a `*mut` here, others a `*const`.
+                &raw mut winsize,
+            ))?;
+            let master = File::from_raw_fd(master);
+            let slave = File::from_raw_fd(slave);
+            libc::fcntl(master.as_raw_fd(), libc::F_SETFD, libc::FD_CLOEXEC);
+            libc::fcntl(slave.as_raw_fd(), libc::F_SETFD, libc::FD_CLOEXEC);
+
+            command
+                .stdin(Stdio::from(slave.try_clone()?))
+                .stdout(Stdio::from(slave.try_clone()?))
+                .stderr(Stdio::from(slave));
+            command.pre_exec(|| {
+                // The child needs its own session to make the PTY its controlling terminal.
+                // At this point, stdin is already the slave side of the PTY.
+                if libc::setsid() < 0 || libc::ioctl(0, libc::TIOCSCTTY, 0) < 0 {
+                    return Err(io::Error::last_os_error());
+                }
+                Ok(())
+            });
+            let child = command.spawn();
+
+            // The `command` holds onto the slave side. If we don't close it,
+            // reading from the master never ends, even after the child exited.
+            command.stdin(Stdio::null()).stdout(Stdio::null()).stderr(Stdio::null());
+
+            let child = child?;
+            let reader = master.try_clone()?;
+            let writer = master.try_clone()?;
+            Ok((Self { master, child }, reader, writer))
+        }
+    }
+
+    /// Tells the PTY about the new size. The child gets a SIGWINCH.
+    pub(crate) fn resize(&mut self, size: Size) -> apperr::Result<()> {
+        let winsize = winsize_from_size(size);
+        let ret = unsafe { libc::ioctl(self.master.as_raw_fd(), libc::TIOCSWINSZ, &winsize) };
+        check_int_return(ret).map(|_| ())
+    }
+
+    pub(crate) fn kill(&mut self) {
+        _ = self.child.kill();
+    }
+
+    /// Returns the exit code if the child has exited. Signals are reported like a shell does: 128 + signal.
+    pub(crate) fn try_wait(&mut self) -> apperr::Result<Option<i32>> {
+        Ok(self.child.try_wait()?.map(exit_code))
+    }
+}
+
+impl Drop for PtyChild {
+    fn drop(&mut self) {
+        if let Ok(None) = self.child.try_wait() {
+            _ = self.child.kill();
+            _ = self.child.wait();
+        }
+    }
+}
+
+fn winsize_from_size(size: Size) -> libc::winsize {
+    libc::winsize {
+        ws_row: size.height.clamp(1, u16::MAX as CoordType) as u16,
+        ws_col: size.width.clamp(1, u16::MAX as CoordType) as u16,
+        ws_xpixel: 0,
+        ws_ypixel: 0,
+    }
+}
+
+fn exit_code(status: ExitStatus) -> i32 {
+    status.code().or_else(|| status.signal().map(|s| 128 + s)).unwrap_or(-1)
+}
+
+/// A command line tool for accessing the OS clipboard.
+struct ClipboardTool {
+    copy: &'static [&'static str],
+    paste: &'static [&'static str],
+}
+
+/// Finds the clipboard tool for the current session, if any. Over SSH there's none,
+/// because it'd access the remote machine's clipboard. OSC 52 is used instead.
+fn clipboard_tool() -> Option<&'static ClipboardTool> {
+    const PBCOPY: ClipboardTool = ClipboardTool { copy: &["pbcopy"], paste: &["pbpaste"] };
+    const WL_COPY: ClipboardTool =
+        ClipboardTool { copy: &["wl-copy"], paste: &["wl-paste", "--no-newline"] };
+    const XCLIP: ClipboardTool = ClipboardTool {
+        copy: &["xclip", "-selection", "clipboard"],
+        paste: &["xclip", "-selection", "clipboard", "-o"],
+    };
+    const XSEL: ClipboardTool = ClipboardTool {
+        copy: &["xsel", "--clipboard", "--input"],
+        paste: &["xsel", "--clipboard", "--output"],
+    };
+    static TOOL: OnceLock<Option<&'static ClipboardTool>> = OnceLock::new();
+
+    *TOOL.get_or_init(|| {
+        let has_env = |key| env::var_os(key).is_some_and(|v| !v.is_empty());
+        if has_env("SSH_CONNECTION") || has_env("SSH_TTY") {
+            return None;
+        }
+
+        let in_path = |name: &str| {
+            env::var_os("PATH")
+                .is_some_and(|paths| env::split_paths(&paths).any(|dir| dir.join(name).is_file()))
+        };
+        let candidates: &[&'static ClipboardTool] = if cfg!(target_os = "macos") {
+            &[&PBCOPY]
+        } else if has_env("WAYLAND_DISPLAY") {
+            // XWayland makes the X11 tools work as well.
+            &[&WL_COPY, &XCLIP, &XSEL]
+        } else if has_env("DISPLAY") {
+            &[&XCLIP, &XSEL]
         } else {
+            &[]
+        };
+        candidates.iter().copied().find(|tool| in_path(tool.copy[0]))
+    })
+}
+
+/// Returns whether [`clipboard_write`] and [`clipboard_read`] can be used.
+pub fn clipboard_available() -> bool {
+    clipboard_tool().is_some()
+}
+
+/// Copies the given text into the OS clipboard.
+///
+/// The tools can only offer a single format at a time,
+/// so the `html` flavor is ignored in favor of the plain text.
+pub fn clipboard_write(data: &[u8], _html: Option<&str>) -> apperr::Result<()> {
+    let tool = clipboard_tool().ok_or(errno_to_apperr(libc::ENOTSUP))?;
+    // xclip and wl-copy fork into the background to serve the clipboard contents.
+    // Their stdout and stderr must not be our terminal, or they'd hold onto it.
+    let mut child = Command::new(tool.copy[0])
+        .args(&tool.copy[1..])
+        .stdin(Stdio::piped())
+        .stdout(Stdio::null())
+        .stderr(Stdio::null())
+        .spawn()?;
+
+    let res = child.stdin.take().map_or(Ok(()), |mut stdin| stdin.write_all(data));
+    let status = child.wait()?;
+    res?;
+
+    if status.success() { Ok(()) } else { Err(errno_to_apperr(libc::EIO)) }
+}
+
+/// Returns the text in the OS clipboard.
+pub fn clipboard_read() -> apperr::Result<Vec<u8>> {
+    let tool = clipboard_tool().ok_or(errno_to_apperr(libc::ENOTSUP))?;
+    let output = Command::new(tool.paste[0])
+        .args(&tool.paste[1..])
+        .stdin(Stdio::null())
+        .stderr(Stdio::null())
+        .output()?;
+
+    if output.status.success() { Ok(output.stdout) } else { Err(errno_to_apperr(libc::EIO)) }
+}
+
+/// A path registered with [`file_watch`].
+struct Watch {
+    path: PathBuf,
+    /// inotify can't follow a file across an atomic save (= rename), so files are
+    /// watched through their parent directory instead. This is their name in it.
+    #[cfg(any(target_os = "linux", target_os = "android"))]
+    name: Option<Vec<u8>>,
+    /// The inotify watch descriptor, or the file descriptor registered with kqueue.
+    /// It's -1 while the path doesn't exist, in which case it's retried on every poll.
+    wd: c_int,
+}
+
+struct Watcher {
+    /// The inotify or kqueue file descriptor. -1 until something gets watched.
+    fd: c_int,
+    watches: Vec<Watch>,
+    /// Changes that [`read_stdin`] read already, for [`file_watch_changes`] to return.
+    changes: Vec<PathBuf>,
+}
+
+/// Starts watching the given file or directory for changes. See [`file_watch_changes`].
+///
+/// It's fine to watch a path that doesn't exist yet. Watching a path twice does nothing.
+pub fn file_watch(path: &Path) -> apperr::Result<()> {
+    #[allow(static_mut_refs)]
+    let watcher = unsafe { &mut STATE.watcher };
+    if watcher.watches.iter().any(|w| w.path == path) {
+        return Ok(());
+    }
+    if watcher.fd < 0 {
+        watcher.fd = watch_init()?;
+    }
+
+    let mut watch = Watch {
+        path: path.to_path_buf(),
+        #[cfg(any(target_os = "linux", target_os = "android"))]
+        name: if path.is_dir() {
+            None
+        } else {
+            Some(path.file_name().unwrap_or_default().as_bytes().to_vec())
+        },
+        wd: -1,
+    };
+    watch_open(watcher.fd, &mut watch);
+    watcher.watches.push(watch);
+    Ok(())
+}
+
+/// Stops watching the given path.
+pub fn file_unwatch(path: &Path) {
+    #[allow(static_mut_refs)]
+    let watcher = unsafe { &mut STATE.watcher };
+    if let Some(idx) = watcher.watches.iter().position(|w| w.path == path) {
+        let watch = watcher.watches.remove(idx);
+        watch_close(watcher.fd, watch.wd, &watcher.watches);
+    }
+}
+
+/// Returns the watched paths that changed since the last call, without blocking.
+///
+/// [`read_stdin`] returns early if there are any, the same way it does on a timeout.
+pub fn file_watch_changes() -> Vec<PathBuf> {
+    #[allow(static_mut_refs)]
+    let watcher = unsafe { &mut STATE.watcher };
+    let mut changes = mem::take(&mut watcher.changes);
+    if watcher.fd < 0 {
+        return changes;
+    }
+
+    watch_read(watcher.fd, &mut watcher.watches, &mut changes);
+
+    // Paths that were deleted or replaced get picked up again once they exist.
+    for watch in &mut watcher.watches {
+        if watch.wd < 0 && watch_open(watcher.fd, watch) && !changes.contains(&watch.path) {
+            changes.push(watch.path.clone());
+        }
+    }
+
+    changes
+}
+
+#[cfg(any(target_os = "linux", target_os = "android"))]
+const INOTIFY_MASK: u32 = libc::IN_ATTRIB
+    | libc::IN_MODIFY
+    | libc::IN_CLOSE_WRITE
+    | libc::IN_CREATE
+    | libc::IN_DELETE
+    | libc::IN_MOVED_FROM
+    | libc::IN_MOVED_TO
+    | libc::IN_DELETE_SELF
+    | libc::IN_MOVE_SELF;
+
+#[cfg(any(target_os = "linux", target_os = "android"))]
+fn watch_init() -> apperr::Result<c_int> {
+    check_int_return(unsafe { libc::inotify_init1(libc::IN_NONBLOCK | libc::IN_CLOEXEC) })
+}
+
+#[cfg(any(target_os = "linux", target_os = "android"))]
+fn watch_open(fd: c_int, watch: &mut Watch) -> bool {
+    let dir = match watch.name {
+        Some(_) => match watch.path.parent() {
+            Some(parent) if !parent.as_os_str().is_empty() => parent,
+            _ => Path::new("."),
+        },
+        None => watch.path.as_path(),
+    };
+    let Ok(dir) = CString::new(dir.as_os_str().as_bytes()) else {
+        return false;
+    };
+    // Multiple files in the same directory share the same watch descriptor.
+    let mask = INOTIFY_MASK | libc::IN_MASK_ADD | libc::IN_ONLYDIR;
+    watch.wd = unsafe { libc::inotify_add_watch(fd, dir.as_ptr(), mask) };
+    watch.wd >= 0
+}
+
+#[cfg(any(target_os = "linux", target_os = "android"))]
+fn watch_close(fd: c_int, wd: c_int, remaining: &[Watch]) {
+    if wd >= 0 && !remaining.iter().any(|w| w.wd == wd) {
+        unsafe { libc::inotify_rm_watch(fd, wd) };
+    }
+}
+
+#[cfg(any(target_os = "linux", target_os = "android"))]
+fn watch_read(fd: c_int, watches: &mut [Watch], changes: &mut Vec<PathBuf>) {
+    const HEADER_LEN: usize = mem::size_of::<libc::inotify_event>();
+    // u64 for the alignment of `inotify_event`.
+    let mut buf = [0u64; 512];
+
+    loop {
+        let len =
+            unsafe { libc::read(fd, buf.as_mut_ptr() as *mut c_void, mem::size_of_val(&buf)) };
+        if len <= 0 {
+            break;
+        }
+
+        let bytes = unsafe { std::slice::from_raw_parts(buf.as_ptr() as *const u8, len as usize) };
+        let mut off = 0;
+
+        while off + HEADER_LEN <= bytes.len() {
+            let event = unsafe {
+                ptr::read_unaligned(bytes.as_ptr().add(off) as *const libc::inotify_event)
+            };
+            let name_end = (off + HEADER_LEN + event.len as usize).min(bytes.len());
+            // The name is padded with NULs.
+            let name = bytes[off + HEADER_LEN..name_end].split(|&b| b == 0).next().unwrap();
+            off = name_end;
+
+            let overflow = event.mask & libc::IN_Q_OVERFLOW != 0;
+            // The directory itself is gone (or moved elsewhere), which ends its watch.
+            let gone = event.mask & (libc::IN_IGNORED | libc::IN_DELETE_SELF | libc::IN_MOVE_SELF);
+            if event.mask & libc::IN_MOVE_SELF != 0 {
+                unsafe { libc::inotify_rm_watch(fd, event.wd) };
+            }
+
+            for watch in watches.iter_mut() {
+                let hit = overflow
+                    || (watch.wd == event.wd
+                        && (gone != 0 || watch.name.as_ref().is_none_or(|n| n == name)));
+                if watch.wd == event.wd && gone != 0 {
+                    watch.wd = -1;
+                }
+                if hit && !changes.contains(&watch.path) {
+                    changes.push(watch.path.clone());
+                }
+            }
+        }
+    }
+}
+
+#[cfg(any(
+    target_vendor = "apple",
+    target_os = "freebsd",
+    target_os = "dragonfly",
+    target_os = "netbsd",
+    target_os = "openbsd"
+))]
+fn watch_init() -> apperr::Result<c_int> {
+    unsafe {
+        let fd = check_int_return(libc::kqueue())?;
+        libc::fcntl(fd, libc::F_SETFD, libc::FD_CLOEXEC);
+        Ok(fd)
+    }
+}
+
+#[cfg(any(
+    target_vendor = "apple",
+    target_os = "freebsd",
+    target_os = "dragonfly",
+    target_os = "netbsd",
+    target_os = "openbsd"
+))]
+fn watch_open(fd: c_int, watch: &mut Watch) -> bool {
+    // O_EVTONLY doesn't prevent the volume from being unmounted.
+    #[cfg(target_vendor = "apple")]
+    const OPEN_FLAGS: c_int = libc::O_EVTONLY | libc::O_CLOEXEC;
+    #[cfg(not(target_vendor = "apple"))]
+    const OPEN_FLAGS: c_int = libc::O_RDONLY | libc::O_CLOEXEC;
+
+    let Ok(path) = CString::new(watch.path.as_os_str().as_bytes()) else {
+        return false;
+    };
+
+    unsafe {
+        let file = libc::open(path.as_ptr(), OPEN_FLAGS);
+        if file < 0 {
+            return false;
+        }
+
+        // The struct layout differs between the BSDs, hence the field by field initialization.
+        let mut event: libc::kevent = mem::zeroed();
+        event.ident = file as _;
+        event.filter = libc::EVFILT_VNODE;
+        event.flags = libc::EV_ADD | libc::EV_CLEAR;
+        event.fflags = libc::NOTE_WRITE
+            | libc::NOTE_EXTEND
+            | libc::NOTE_ATTRIB
+            | libc::NOTE_DELETE
+            | libc::NOTE_RENAME
+            | libc::NOTE_REVOKE;
+        if libc::kevent(fd, &event, 1, null_mut(), 0, ptr::null()) < 0 {
+            libc::close(file);
+            return false;
+        }
+
+        watch.wd = file;
+        true
+    }
+}
+
+#[cfg(any(
+    target_vendor = "apple",
+    target_os = "freebsd",
+    target_os = "dragonfly",
+    target_os = "netbsd",
+    target_os = "openbsd"
+))]
+fn watch_close(_fd: c_int, wd: c_int, _remaining: &[Watch]) {
+    // Closing the file descriptor removes it from the kqueue.
+    if wd >= 0 {
+        unsafe { libc::close(wd) };
+    }
+}
+
+#[cfg(any(
+    target_vendor = "apple",
+    target_os = "freebsd",
+    target_os = "dragonfly",
+    target_os = "netbsd",
+    target_os = "openbsd"
+))]
+fn watch_read(fd: c_int, watches: &mut [Watch], changes: &mut Vec<PathBuf>) {
+    let mut events: [libc::kevent; 16] = unsafe { mem::zeroed() };
+    let zero = libc::timespec { tv_sec: 0, tv_nsec: 0 };
+
+    loop {
+        let n = unsafe {
+            libc::kevent(fd, ptr::null(), 0, events.as_mut_ptr(), events.len() as _, &zero)
+        };
+        if n <= 0 {
+            break;
+        }
+
+        for event in &events[..n as usize] {
+            let Some(watch) = watches.iter_mut().find(|w| w.wd == event.ident as c_int) else {
+                continue;
+            };
+            // The file was deleted or replaced. We need to reopen it by its path.
+            if event.fflags & (libc::NOTE_DELETE | libc::NOTE_RENAME | libc::NOTE_REVOKE) != 0 {
+                unsafe { libc::close(watch.wd) };
+                watch.wd = -1;
+            }
+            if !changes.contains(&watch.path) {
+                changes.push(watch.path.clone());
+            }
+        }
+
+        if (n as usize) < events.len() {
+            break;
+        }
+    }
+}
+
+#[cfg(not(any(
+    target_os = "linux",
+    target_os = "android",
+    target_vendor = "apple",
+    target_os = "freebsd",
+    target_os = "dragonfly",
+    target_os = "netbsd",
+    target_os = "openbsd"
+)))]
+fn watch_init() -> apperr::Result<c_int> {
+    Err(errno_to_apperr(libc::ENOTSUP))
+}
+
+#[cfg(not(any(
+    target_os = "linux",
+    target_os = "android",
+    target_vendor = "apple",
+    target_os = "freebsd",
+    target_os = "dragonfly",
+    target_os = "netbsd",
+    target_os = "openbsd"
+)))]
+fn watch_open(_fd: c_int, _watch: &mut Watch) -> bool {
+    false
+}
+
+#[cfg(not(any(
+    target_os = "linux",
+    target_os = "android",
+    target_vendor = "apple",
+    target_os = "freebsd",
+    target_os = "dragonfly",
+    target_os = "netbsd",
+    target_os = "openbsd"
+)))]
+fn watch_close(_fd: c_int, _wd: c_int, _remaining: &[Watch]) {}
+
+#[cfg(not(any(
+    target_os = "linux",
+    target_os = "android",
+    target_vendor = "apple",
+    target_os = "freebsd",
+    target_os = "dragonfly",
+    target_os = "netbsd",
+    target_os = "openbsd"
+)))]
+fn watch_read(_fd: c_int, _watches: &mut [Watch], _changes: &mut Vec<PathBuf>) {}
+
+/// Writes a file by letting `write` fill a temporary file, which then atomically replaces
+/// the original. This way, a crash or a full disk can't leave a half-written file behind.
+///
+/// If that would change the file's owner or break hard links, or if it can't be done
+/// for any other reason, the file is overwritten in place instead.
+pub fn safe_write(
+    path: &Path,
+    write: impl FnOnce(&mut File) -> apperr::Result<()>,
+) -> apperr::Result<()> {
+    // Replace the file that symlinks point to and not the links themselves.
+    let path = fs::canonicalize(path).unwrap_or_else(|_| path.to_path_buf());
+
+    let Some((mut file, temp)) = create_replacement(&path) else {
+        let mut file = File::create(&path)?;
+        write(&mut file)?;
+        file.sync_all()?;
+        return Ok(());
+    };
+
+    let res = write(&mut file)
+        .and_then(|_| Ok(file.sync_all()?))
+        .and_then(|_| Ok(fs::rename(&temp, &path)?));
+    if res.is_err() {
+        _ = fs::remove_file(&temp);
+        return res;
+    }
+
+    // Make the rename itself durable.
+    let dir = path.parent().filter(|p| !p.as_os_str().is_empty()).unwrap_or(Path::new("."));
+    if let Ok(dir) = File::open(dir) {
+        _ = dir.sync_all();
+    }
+    Ok(())
+}
+
+/// Creates a temporary file next to `path` with the same mode, owner and extended attributes.
+/// Returns `None` if `path` doesn't exist or if the replacement wouldn't be faithful.
+fn create_replacement(path: &Path) -> Option<(File, PathBuf)> {
+    let meta = fs::metadata(path).ok()?;
+    if !meta.is_file() || meta.nlink() > 1 {
+        return None;
+    }
+
+    let mut name = OsString::from(".");
+    name.push(path.file_name()?);
+    name.push(format!(".{}.tmp", std::process::id()));
+    let temp = path.with_file_name(name);
+    let file = fs::OpenOptions::new().write(true).create_new(true).mode(0o600).open(&temp).ok()?;
+
+    // chown() fails if we aren't allowed to give the file to the original owner and group.
+    // The mode must be set afterwards, because chown() may clear the setuid/setgid bits.
+    let fd = file.as_raw_fd();
+    let ok = unsafe {
+        libc::fchown(fd, meta.uid(), meta.gid()) == 0
+            && libc::fchmod(fd, (meta.mode() & 0o7777) as libc::mode_t) == 0
+    };
+    if !ok {
+        drop(file);
+        _ = fs::remove_file(&temp);
+        return None;
+    }
+
+    if let Ok(src) = File::open(path) {
+        copy_xattrs(&src, &file);
+    }
+    Some((file, temp))
+}
+
+/// Copies the extended attributes, which includes POSIX ACLs and SELinux labels on Linux.
+/// Those that we lack the privileges for are skipped.
+#[cfg(any(target_os = "linux", target_os = "android", target_vendor = "apple"))]
+fn copy_xattrs(src: &File, dst: &File) {
+    let (src, dst) = (src.as_raw_fd(), dst.as_raw_fd());
+
+    #[cfg(target_vendor = "apple")]
+    unsafe fn list(fd: c_int, buf: *mut c_char, len: usize) -> isize {
+        unsafe { libc::flistxattr(fd, buf, len, 0) }
+    }
+    #[cfg(target_vendor = "apple")]
+    unsafe fn get(fd: c_int, name: *const c_char, buf: *mut c_void, len: usize) -> isize {
+        unsafe { libc::fgetxattr(fd, name, buf, len, 0, 0) }
+    }
+    #[cfg(target_vendor = "apple")]
+    unsafe fn set(fd: c_int, name: *const c_char, buf: *const c_void, len: usize) {
+        unsafe { libc::fsetxattr(fd, name, buf, len, 0, 0) };
+    }
+    #[cfg(not(target_vendor = "apple"))]
+    unsafe fn list(fd: c_int, buf: *mut c_char, len: usize) -> isize {
+        unsafe { libc::flistxattr(fd, buf, len) }
+    }
+    #[cfg(not(target_vendor = "apple"))]
+    unsafe fn get(fd: c_int, name: *const c_char, buf: *mut c_void, len: usize) -> isize {
+        unsafe { libc::fgetxattr(fd, name, buf, len) }
+    }
+    #[cfg(not(target_vendor = "apple"))]
+    unsafe fn set(fd: c_int, name: *const c_char, buf: *const c_void, len: usize) {
+        unsafe { libc::fsetxattr(fd, name, buf, len, 0) };
+    }
+
+    unsafe {
+        let len = list(src, null_mut(), 0);
+        if len <= 0 {
+            return;
+        }
+        let mut names = vec![0u8; len as usize];
+        let len = list(src, names.as_mut_ptr() as *mut c_char, names.len());
+        if len <= 0 {
+            return;
+        }
+        names.truncate(len as usize);
+
+        let mut value = Vec::new();
+        // The names are a sequence of NUL terminated strings.
+        for name in names.split_inclusive(|&b| b == 0) {
+            let Ok(name) = CStr::from_bytes_with_nul(name) else {
+                continue;
+            };
+            let len = get(src, name.as_ptr(), null_mut(), 0);
+            if len < 0 {
+                continue;
+            }
+            value.resize(len as usize, 0);
+            let len = get(src, name.as_ptr(), value.as_mut_ptr() as *mut c_void, value.len());
+            if len >= 0 {
+                set(dst, name.as_ptr(), value.as_ptr() as *const c_void, len as usize);
+            }
+        }
+    }
+}
+
+#[cfg(not(any(target_os = "linux", target_os = "android", target_vendor = "apple")))]
+fn copy_xattrs(_src: &File, _dst: &File) {}
+
+/// Returns the base directory for the given kind of files, without an application suffix.
+///
+/// The `XDG_*_HOME` variables take precedence everywhere, as per the XDG Base Directory
+/// Specification. Otherwise, macOS uses `~/Library` and every other Unix the XDG defaults.
+pub fn known_dir(dir: KnownDir) -> Option<PathBuf> {
+    let (var, fallback) = match dir {
+        KnownDir::Config => {
+            ("XDG_CONFIG_HOME", known_dir_fallback(".config", "Application Support"))
+        }
+        KnownDir::State => {
+            ("XDG_STATE_HOME", known_dir_fallback(".local/state", "Application Support"))
+        }
+        KnownDir::Cache => ("XDG_CACHE_HOME", known_dir_fallback(".cache", "Caches")),
+        KnownDir::Data => {
+            ("XDG_DATA_HOME", known_dir_fallback(".local/share", "Application Support"))
+        }
+    };
+
+    // The spec says that relative paths are invalid and should be ignored.
+    if let Some(path) = env::var_os(var).map(PathBuf::from)
+        && path.is_absolute()
+    {
+        return Some(path);
+    }
+
+    let mut path = home_dir(None)?;
+    path.push(fallback);
+    Some(path)
+}
+
+#[cfg(target_vendor = "apple")]
+fn known_dir_fallback(_xdg: &'static str, library: &'static str) -> PathBuf {
+    Path::new("Library").join(library)
+}
+
+#[cfg(not(target_vendor = "apple"))]
+fn known_dir_fallback(xdg: &'static str, _library: &'static str) -> PathBuf {
+    PathBuf::from(xdg)
+}
+
+/// Returns the home directory of the given user, or that of the current one.
+/// For the current user that's `$HOME`, or the home directory in the user database if it's unset.
+pub fn home_dir(user: Option<&OsStr>) -> Option<PathBuf> {
+    let name = match user {
+        Some(user) => Some(CString::new(user.as_bytes()).ok()?),
+        None => {
+            if let Some(home) = env::var_os("HOME").map(PathBuf::from)
+                && home.is_absolute()
+            {
+                return Some(home);
+            }
             None
         }
+    };
+
+    unsafe {
+        let mut buf = vec![0 as c_char; 4096];
+        let mut pwd = MaybeUninit::<libc::passwd>::uninit();
+        let mut result = null_mut();
+        loop {
+            let ret = match &name {
+                Some(name) => libc::getpwnam_r(
+                    name.as_ptr(),
+                    pwd.as_mut_ptr(),
+                    buf.as_mut_ptr(),
+                    buf.len(),
+                    &mut result,
+                ),
+                None => libc::getpwuid_r(
+                    libc::getuid(),
+                    pwd.as_mut_ptr(),
+                    buf.as_mut_ptr(),
+                    buf.len(),
+                    &mut result,
+                ),
+            };
+            if ret != libc::ERANGE || buf.len() >= 1024 * 1024 {
+                break;
+            }
+            buf.resize(buf.len() * 2, 0);
+        }
+        if result.is_null() || (*result).pw_dir.is_null() {
+            return None;
+        }
+        let dir = CStr::from_ptr((*result).pw_dir).to_bytes();
+        Some(PathBuf::from(OsStr::from_bytes(dir)))
     }
 }
 
@@ -431,6 +1301,67 @@ pub unsafe fn virtual_commit(base: NonNull<u8>, size: usize) -> apperr::Result<(
     }
 }
 
+/// Returns the alignment that [`mmap_file`] offsets need: The page size.
+pub fn mmap_granularity() -> usize {
+    static PAGE_SIZE: OnceLock<usize> = OnceLock::new();
+    *PAGE_SIZE.get_or_init(|| match unsafe { libc::sysconf(libc::_SC_PAGESIZE) } {
+        n if n > 0 => n as usize,
+        _ => 4096,
+    })
+}
+
+/// Maps `len` bytes of `file` starting at `offset` into memory.
+///
+/// # Safety
+///
+/// The `offset` must be aligned to [`mmap_granularity`] and `len` must be non-zero.
+/// Release the mapping with [`mmap_release`].
+pub(crate) unsafe fn mmap_file(
+    file: &File,
+    offset: u64,
+    len: usize,
+    mode: MmapMode,
+) -> apperr::Result<NonNull<u8>> {
+    let (prot, flags) = match mode {
+        MmapMode::ReadOnly => (libc::PROT_READ, libc::MAP_SHARED),
+        MmapMode::CopyOnWrite => (libc::PROT_READ | libc::PROT_WRITE, libc::MAP_PRIVATE),
+    };
+    let offset = libc::off_t::try_from(offset).map_err(|_| errno_to_apperr(libc::EOVERFLOW))?;
+    unsafe {
+        let ptr = libc::mmap(null_mut(), len, prot, flags, file.as_raw_fd(), offset);
+        if ptr::eq(ptr, libc::MAP_FAILED) {
+            Err(errno_to_apperr(errno()))
+        } else {
+            Ok(NonNull::new_unchecked(ptr as *mut u8))
+        }
+    }
+}
+
+/// # Safety
+///
+/// Make sure to only pass pointers and sizes acquired from [`mmap_file`].
+pub(crate) unsafe fn mmap_release(base: NonNull<u8>, len: usize) {
+    unsafe {
+        libc::munmap(base.cast().as_ptr(), len);
+    }
+}
+
+/// # Safety
+///
+/// Make sure to only pass pointers and sizes acquired from [`mmap_file`].
+pub(crate) unsafe fn mmap_advise(base: NonNull<u8>, len: usize, advice: MmapAdvice) {
+    let advice = match advice {
+        MmapAdvice::Normal => libc::MADV_NORMAL,
+        MmapAdvice::Sequential => libc::MADV_SEQUENTIAL,
+        MmapAdvice::Random => libc::MADV_RANDOM,
+        MmapAdvice::WillNeed => libc::MADV_WILLNEED,
+        MmapAdvice::DontNeed => libc::MADV_DONTNEED,
+    };
+    unsafe {
+        libc::madvise(base.cast().as_ptr(), len, advice);
+    }
+}
+
 unsafe fn load_library(name: *const c_char) -> apperr::Result<NonNull<c_void>> {
     unsafe {
         NonNull::new(libc::dlopen(name, libc::RTLD_LAZY))
@@ -631,8 +1562,8 @@ pub fn apperr_format(f: &mut std::fmt::Formatter<'_>, code: u32) -> std::fmt::Re
     Ok(())
 }
 
-pub fn apperr_is_not_found(err: apperr::Error) -> bool {
-    err == errno_to_apperr(libc::ENOENT)
+pub fn apperr_is_not_found(err: &apperr::Error) -> bool {
+    *err.root() == errno_to_apperr(libc::ENOENT)
 }
 
 const fn errno_to_apperr(no: c_int) -> apperr::Error {
@@ -642,3 +1573,66 @@ const fn errno_to_apperr(no: c_int) -> apperr::Error {
 fn check_int_return(ret: libc::c_int) -> apperr::Result<libc::c_int> {
     if ret < 0 { Err(errno_to_apperr(errno())) } else { Ok(ret) }
 }
+
+#[cfg(test)]
+mod tests {
+    use std::io::Write as _;
+    use std::os::unix::fs::PermissionsExt as _;
+
+    use super::*;
+
+    fn write_str(path: &Path, s: &str) {
+        safe_write(path, |file| Ok(file.write_all(s.as_bytes())?)).unwrap();
+    }
+
+    #[test]
+    fn test_safe_write() {
+        let dir = env::temp_dir().join(format!("edit-safe-write-{}", std::process::id()));
+        _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("file.txt");
+
+        // New files are simply created.
+        write_str(&path, "a");
+        assert_eq!(fs::read_to_string(&path).unwrap(), "a");
+
+        // Existing files get replaced, but keep their mode.
+        fs::set_permissions(&path, fs::Permissions::from_mode(0o640)).unwrap();
+        let ino = fs::metadata(&path).unwrap().ino();
+        write_str(&path, "b");
+        let meta = fs::metadata(&path).unwrap();
+        assert_eq!(fs::read_to_string(&path).unwrap(), "b");
+        assert_eq!(meta.mode() & 0o777, 0o640);
+        assert_ne!(meta.ino(), ino);
+
+        // Symlinks stay symlinks.
+        let link = dir.join("link.txt");
+        std::os::unix::fs::symlink(&path, &link).unwrap();
+        write_str(&link, "c");
+        assert!(fs::symlink_metadata(&link).unwrap().is_symlink());
+        assert_eq!(fs::read_to_string(&path).unwrap(), "c");
+
+        // Hard links are written in place, so that they stay intact.
+        let hard_link = dir.join("hard.txt");
+        fs::hard_link(&path, &hard_link).unwrap();
+        let ino = fs::metadata(&path).unwrap().ino();
+        write_str(&path, "d");
+        assert_eq!(fs::metadata(&path).unwrap().ino(), ino);
+        assert_eq!(fs::read_to_string(&hard_link).unwrap(), "d");
+
+        // No temporary files are left behind.
+        assert_eq!(fs::read_dir(&dir).unwrap().count(), 3);
+        _ = fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn test_known_dir() {
+        for dir in [KnownDir::Config, KnownDir::State, KnownDir::Cache, KnownDir::Data] {
+            let path = known_dir(dir).unwrap();
+            assert!(path.is_absolute(), "{path:?}");
+        }
+        assert!(home_dir(None).is_some_and(|p| p.is_absolute()));
+        assert!(home_dir(Some(OsStr::new("root"))).is_some_and(|p| p.is_absolute()));
+        assert!(home_dir(Some(OsStr::new("no such user"))).is_none());
+    }
+}
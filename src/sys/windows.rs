@@ -1,25 +1,33 @@
 // Copyright (c) Microsoft Corporation.
 // Licensed under the MIT License.
 
-use std::ffi::{OsString, c_char, c_void};
+use std::ffi::{OsStr, OsString, c_char, c_void};
 use std::fmt::Write as _;
 use std::fs::{self, File};
 use std::mem::MaybeUninit;
-use std::os::windows::io::{AsRawHandle as _, FromRawHandle};
+use std::os::windows::ffi::{OsStrExt as _, OsStringExt as _};
+use std::os::windows::fs::OpenOptionsExt as _;
+use std::os::windows::io::{AsRawHandle as _, FromRawHandle, OwnedHandle};
 use std::path::{Path, PathBuf};
+use std::process::Command;
 use std::ptr::{self, NonNull, null, null_mut};
-use std::{mem, time};
+use std::sync::OnceLock;
+use std::{iter, mem, time};
 
 use windows_sys::Win32::Foundation::ERROR_INVALID_PARAMETER;
 use windows_sys::Win32::Storage::FileSystem;
 use windows_sys::Win32::System::Diagnostics::Debug;
-use windows_sys::Win32::System::{Console, IO, LibraryLoader, Memory, Threading};
+use windows_sys::Win32::System::{
+    Com, Console, DataExchange, IO, LibraryLoader, Memory, Threading,
+};
+use windows_sys::Win32::UI::Shell;
 use windows_sys::Win32::{Foundation, Globalization};
 use windows_sys::w;
 
-use crate::apperr;
+use super::{KnownDir, MmapAdvice, MmapMode, StdStream};
 use crate::arena::{Arena, ArenaString, scratch_arena};
 use crate::helpers::*;
+use crate::{apperr, path};
 
 macro_rules! w_env {
     ($s:literal) => {{
@@ -85,7 +93,9 @@ struct State {
     stdout_mode_old: u32,
     leading_surrogate: u16,
     inject_resize: bool,
-    wants_exit: bool,
+    /// Signaled when any of the `watches` has changes. Shared by all of them.
+    watch_event: Foundation::HANDLE,
+    watches: Vec<Watch>,
 }
 
 static mut STATE: State = State {
@@ -98,13 +108,21 @@ static mut STATE: State = State {
     stdout_mode_old: INVALID_CONSOLE_MODE,
     leading_surrogate: 0,
     inject_resize: false,
-    wants_exit: false,
+    watch_event: null_mut(),
+    watches: Vec::new(),
 };
 
-extern "system" fn console_ctrl_handler(_ctrl_type: u32) -> Foundation::BOOL {
-    unsafe {
-        STATE.wants_exit = true;
-        IO::CancelIoEx(STATE.stdin, null());
+extern "system" fn console_ctrl_handler(ctrl_type: u32) -> Foundation::BOOL {
+    super::signal_raise(super::Signal::Terminate);
+    wakeup();
+
+    // We get terminated as soon as we return from these. Give the main loop
+    // the time to exit on its own. Windows won't wait longer than ~5s anyway.
+    if matches!(
+        ctrl_type,
+        Console::CTRL_CLOSE_EVENT | Console::CTRL_LOGOFF_EVENT | Console::CTRL_SHUTDOWN_EVENT
+    ) {
+        unsafe { Threading::Sleep(10000) };
     }
     1
 }
@@ -145,9 +163,7 @@ pub fn switch_modes() -> apperr::Result<()> {
         };
 
         // Reopen stdin if it's redirected (= piped input).
-        if ptr::eq(STATE.stdin, Foundation::INVALID_HANDLE_VALUE)
-            || !matches!(FileSystem::GetFileType(STATE.stdin), FileSystem::FILE_TYPE_CHAR)
-        {
+        if !is_console_handle(STATE.stdin) {
             STATE.stdin = FileSystem::CreateFileW(
                 w!("CONIN$"),
                 Foundation::GENERIC_READ | Foundation::GENERIC_WRITE,
@@ -158,6 +174,19 @@ pub fn switch_modes() -> apperr::Result<()> {
                 null_mut(),
             );
         }
+        // Same for stdout, e.g. when our output gets piped into another program.
+        // `--help` and the like still get printed into the redirection.
+        if !is_console_handle(STATE.stdout) {
+            STATE.stdout = FileSystem::CreateFileW(
+                w!("CONOUT$"),
+                Foundation::GENERIC_READ | Foundation::GENERIC_WRITE,
+                FileSystem::FILE_SHARE_READ | FileSystem::FILE_SHARE_WRITE,
+                null_mut(),
+                FileSystem::OPEN_EXISTING,
+                0,
+                null_mut(),
+            );
+        }
         if ptr::eq(STATE.stdin, Foundation::INVALID_HANDLE_VALUE)
             || ptr::eq(STATE.stdout, Foundation::INVALID_HANDLE_VALUE)
         {
@@ -186,6 +215,8 @@ pub fn switch_modes() -> apperr::Result<()> {
                 | Console::DISABLE_NEWLINE_AUTO_RETURN,
         ))?;
 
+        // The handler and `read_stdin` rely on the wakeup event.
+        wakeup_event();
         check_bool_return(Console::SetConsoleCtrlHandler(Some(console_ctrl_handler), 1))?;
 
         STATE.stdin_cp_old = Console::GetConsoleCP();
@@ -282,20 +313,43 @@ pub fn read_stdin(arena: &Arena, mut timeout: time::Duration) -> Option<ArenaStr
 
     // Read until there's either a timeout or we have something to process.
     loop {
-        if timeout != time::Duration::MAX {
+        // If files are being watched or other threads may wake us up,
+        // we need to wait for those as well.
+        let mut handles = [unsafe { STATE.stdin }, null_mut(), null_mut()];
+        let mut count = 1;
+        for handle in [unsafe { STATE.watch_event }, WAKEUP_EVENT.get().map_or(null_mut(), |h| h.0)]
+        {
+            if !handle.is_null() {
+                handles[count] = handle;
+                count += 1;
+            }
+        }
+
+        if timeout != time::Duration::MAX || count > 1 {
             let beg = time::Instant::now();
+            let infinite = timeout == time::Duration::MAX;
+            let ms = if infinite { Threading::INFINITE } else { timeout.as_millis() as u32 };
 
-            match unsafe { Threading::WaitForSingleObject(STATE.stdin, timeout.as_millis() as u32) }
-            {
+            match unsafe {
+                Threading::WaitForMultipleObjects(count as u32, handles.as_ptr(), 0, ms)
+            } {
                 // Ready to read? Continue with reading below.
                 Foundation::WAIT_OBJECT_0 => {}
+                // Only a watched file changed (see `file_watch_changes`) or we got woken up.
+                ret if ret > Foundation::WAIT_OBJECT_0
+                    && ret < Foundation::WAIT_OBJECT_0 + count as u32 =>
+                {
+                    break;
+                }
                 // Timeout? Skip reading entirely.
                 Foundation::WAIT_TIMEOUT => break,
                 // Error? Tell the caller stdin is broken.
                 _ => return None,
             }
 
-            timeout = timeout.saturating_sub(beg.elapsed());
+            if !infinite {
+                timeout = timeout.saturating_sub(beg.elapsed());
+            }
         }
 
         // Read from stdin.
@@ -311,7 +365,7 @@ pub fn read_stdin(arena: &Arena, mut timeout: time::Duration) -> Option<ArenaStr
                 &mut read,
                 flags,
             );
-            if ok == 0 || STATE.wants_exit {
+            if ok == 0 {
                 return None;
             }
             input_buf[..read as usize].assume_init_ref()
@@ -400,6 +454,32 @@ pub fn read_stdin(arena: &Arena, mut timeout: time::Duration) -> Option<ArenaStr
     Some(text)
 }
 
+struct WakeupEvent(Foundation::HANDLE);
+
+// It's an event handle. Those are meant to be signaled from other threads.
+unsafe impl Send for WakeupEvent {}
+unsafe impl Sync for WakeupEvent {}
+
+/// The auto-reset event used by [`wakeup`].
+static WAKEUP_EVENT: OnceLock<WakeupEvent> = OnceLock::new();
+
+/// Suspending a process isn't a thing on Windows.
+pub fn suspend() {}
+
+fn wakeup_event() -> &'static WakeupEvent {
+    WAKEUP_EVENT
+        .get_or_init(|| WakeupEvent(unsafe { Threading::CreateEventW(null(), 0, 0, null()) }))
+}
+
+/// Makes a pending or future [`read_stdin`] return early, as if it timed out.
+/// Unlike the rest of this module, it can be called from any thread.
+pub fn wakeup() {
+    let event = wakeup_event();
+    if !event.0.is_null() {
+        unsafe { Threading::SetEvent(event.0) };
+    }
+}
+
 /// Writes a string to stdout.
 ///
 /// Use this instead of `print!` or `println!` to avoid
@@ -421,18 +501,555 @@ pub fn write_stdout(text: &str) {
     }
 }
 
+/// Returns whether the given standard stream is a console, as opposed to a pipe or file.
+/// This refers to the original stream, even after [`switch_modes`] replaced it with the console.
+pub fn is_terminal(stream: StdStream) -> bool {
+    let id = match stream {
+        StdStream::Stdin => Console::STD_INPUT_HANDLE,
+        StdStream::Stdout => Console::STD_OUTPUT_HANDLE,
+    };
+    is_console_handle(unsafe { Console::GetStdHandle(id) })
+}
+
+fn is_console_handle(handle: Foundation::HANDLE) -> bool {
+    !handle.is_null()
+        && !ptr::eq(handle, Foundation::INVALID_HANDLE_VALUE)
+        && unsafe { FileSystem::GetFileType(handle) } == FileSystem::FILE_TYPE_CHAR
+}
+
 /// Check if the stdin handle is redirected to a file, etc.
 ///
 /// # Returns
 ///
-/// * `Some(file)` if stdin is redirected.
+/// * `Some(file)` if stdin is redirected. It takes over the handle, so call this only once.
 /// * Otherwise, `None`.
 pub fn open_stdin_if_redirected() -> Option<File> {
     unsafe {
         let handle = Console::GetStdHandle(Console::STD_INPUT_HANDLE);
-        // Did we reopen stdin during `init()`?
-        if !std::ptr::eq(STATE.stdin, handle) { Some(File::from_raw_handle(handle)) } else { None }
+        if handle.is_null()
+            || ptr::eq(handle, Foundation::INVALID_HANDLE_VALUE)
+            || is_console_handle(handle)
+        {
+            None
+        } else {
+            Some(File::from_raw_handle(handle))
+        }
+    }
+}
+
+const CF_UNICODETEXT: u32 = 13;
+
+/// Returns whether [`clipboard_write`] and [`clipboard_read`] can be used.
+///
+/// Over SSH, the clipboard would be that of the remote machine. OSC 52 is used instead.
+pub fn clipboard_available() -> bool {
+    let has_env = |key| std::env::var_os(key).is_some_and(|v| !v.is_empty());
+    !has_env("SSH_CONNECTION") && !has_env("SSH_TTY")
+}
+
+/// Opens the clipboard, calls `f` and closes it again.
+fn with_clipboard<T>(f: impl FnOnce() -> apperr::Result<T>) -> apperr::Result<T> {
+    unsafe {
+        check_bool_return(DataExchange::OpenClipboard(null_mut()))?;
+        let res = f();
+        DataExchange::CloseClipboard();
+        res
+    }
+}
+
+/// Copies the given text into the OS clipboard.
+/// If given, the `html` fragment is offered as well, for rich-text applications.
+pub fn clipboard_write(data: &[u8], html: Option<&str>) -> apperr::Result<()> {
+    let text: Vec<u16> =
+        String::from_utf8_lossy(data).encode_utf16().chain(iter::once(0)).collect();
+    let text = unsafe { std::slice::from_raw_parts(text.as_ptr() as *const u8, text.len() * 2) };
+
+    with_clipboard(|| unsafe {
+        check_bool_return(DataExchange::EmptyClipboard())?;
+        clipboard_set_data(CF_UNICODETEXT, text)?;
+
+        // The plain text is what matters. The HTML is a nice-to-have.
+        if let Some(html) = html {
+            let format = DataExchange::RegisterClipboardFormatW(w!("HTML Format"));
+            if format != 0 {
+                _ = clipboard_set_data(format, clipboard_html(html).as_bytes());
+            }
+        }
+        Ok(())
+    })
+}
+
+/// Puts a copy of `data` into the open clipboard.
+unsafe fn clipboard_set_data(format: u32, data: &[u8]) -> apperr::Result<()> {
+    unsafe {
+        let mem = check_ptr_return(Memory::GlobalAlloc(Memory::GMEM_MOVEABLE, data.len()))?;
+        let dst = match check_ptr_return(Memory::GlobalLock(mem.as_ptr())) {
+            Ok(dst) => dst.as_ptr() as *mut u8,
+            Err(err) => {
+                Memory::GlobalFree(mem.as_ptr());
+                return Err(err);
+            }
+        };
+        ptr::copy_nonoverlapping(data.as_ptr(), dst, data.len());
+        Memory::GlobalUnlock(mem.as_ptr());
+
+        // On success, the clipboard owns the memory.
+        if DataExchange::SetClipboardData(format, mem.as_ptr()).is_null() {
+            let err = get_last_error();
+            Memory::GlobalFree(mem.as_ptr());
+            return Err(err);
+        }
+        Ok(())
+    }
+}
+
+/// Wraps an HTML fragment in the "HTML Format" envelope, which starts with
+/// a header containing the byte offsets of the document and the fragment in it.
+/// See: <https://learn.microsoft.com/en-us/windows/win32/dataxchg/html-clipboard-format>
+fn clipboard_html(fragment: &str) -> String {
+    const HEADER: &str = "Version:0.9\r\nStartHTML:0000000000\r\nEndHTML:0000000000\r\n\
+                          StartFragment:0000000000\r\nEndFragment:0000000000\r\n";
+    const PREFIX: &str = "<html><body>\r\n<!--StartFragment-->";
+    const SUFFIX: &str = "<!--EndFragment-->\r\n</body></html>";
+
+    let start_html = HEADER.len();
+    let start_fragment = start_html + PREFIX.len();
+    let end_fragment = start_fragment + fragment.len();
+    let end_html = end_fragment + SUFFIX.len();
+
+    let mut res = String::with_capacity(end_html + 1);
+    _ = write!(
+        res,
+        "Version:0.9\r\nStartHTML:{start_html:010}\r\nEndHTML:{end_html:010}\r\n\
+         StartFragment:{start_fragment:010}\r\nEndFragment:{end_fragment:010}\r\n"
+    );
+    debug_assert_eq!(res.len(), HEADER.len());
+    res.push_str(PREFIX);
+    res.push_str(fragment);
+    res.push_str(SUFFIX);
+    // The data must be NUL-terminated.
+    res.push('\0');
+    res
+}
+
+/// Returns the text in the OS clipboard.
+pub fn clipboard_read() -> apperr::Result<Vec<u8>> {
+    with_clipboard(|| unsafe {
+        if DataExchange::IsClipboardFormatAvailable(CF_UNICODETEXT) == 0 {
+            return Ok(Vec::new());
+        }
+
+        let mem = check_ptr_return(DataExchange::GetClipboardData(CF_UNICODETEXT))?;
+        let src = check_ptr_return(Memory::GlobalLock(mem.as_ptr()))?.as_ptr() as *const u16;
+        // The text is NUL-terminated, but that's not guaranteed to be within the allocation.
+        let cap = Memory::GlobalSize(mem.as_ptr()) / 2;
+        let wide = std::slice::from_raw_parts(src, cap);
+        let wide = &wide[..wide.iter().position(|&c| c == 0).unwrap_or(cap)];
+        let text = String::from_utf16_lossy(wide).into_bytes();
+        Memory::GlobalUnlock(mem.as_ptr());
+        Ok(text)
+    })
+}
+
+/// A path registered with [`file_watch`].
+struct Watch {
+    path: PathBuf,
+    /// `ReadDirectoryChangesW` only works on directories, so files are watched
+    /// through their parent directory instead. This is their lowercase name in it.
+    name: Option<String>,
+    /// The directory with a pending `ReadDirectoryChangesW`.
+    /// `None` while it doesn't exist, in which case it's retried on every poll.
+    dir: Option<File>,
+    // Boxed, because they must not move while the read is pending.
+    overlapped: Box<IO::OVERLAPPED>,
+    buffer: Box<[u32; 1024]>,
+}
+
+impl Drop for Watch {
+    fn drop(&mut self) {
+        // The kernel must be done with the buffers before we free them.
+        if let Some(dir) = &self.dir {
+            unsafe {
+                let mut bytes = 0;
+                IO::CancelIoEx(dir.as_raw_handle(), &*self.overlapped);
+                IO::GetOverlappedResult(dir.as_raw_handle(), &*self.overlapped, &mut bytes, 1);
+            }
+        }
+    }
+}
+
+/// Starts watching the given file or directory for changes. See [`file_watch_changes`].
+///
+/// It's fine to watch a path that doesn't exist yet. Watching a path twice does nothing.
+pub fn file_watch(path: &Path) -> apperr::Result<()> {
+    unsafe {
+        #[allow(static_mut_refs)]
+        let watches = &mut STATE.watches;
+        if watches.iter().any(|w| w.path == path) {
+            return Ok(());
+        }
+        if STATE.watch_event.is_null() {
+            // Manual-reset, because `file_watch_changes` checks all watches anyway.
+            STATE.watch_event =
+                check_ptr_return(Threading::CreateEventW(null(), 1, 0, null()))?.as_ptr();
+        }
+
+        let name = if path.is_dir() {
+            None
+        } else {
+            Some(path.file_name().unwrap_or_default().to_string_lossy().to_lowercase())
+        };
+        let mut watch = Watch {
+            path: path.to_path_buf(),
+            name,
+            dir: None,
+            overlapped: Box::new(mem::zeroed()),
+            buffer: Box::new([0; 1024]),
+        };
+        watch_open(&mut watch);
+        watches.push(watch);
+        Ok(())
+    }
+}
+
+/// Stops watching the given path.
+pub fn file_unwatch(path: &Path) {
+    unsafe {
+        #[allow(static_mut_refs)]
+        STATE.watches.retain(|w| w.path != path);
+    }
+}
+
+/// Returns the watched paths that changed since the last call, without blocking.
+///
+/// [`read_stdin`] returns early if there are any, the same way it does on a timeout.
+pub fn file_watch_changes() -> Vec<PathBuf> {
+    let mut changes = Vec::new();
+
+    unsafe {
+        if STATE.watch_event.is_null() {
+            return changes;
+        }
+
+        // Reset it first, so that reads completing in the meantime signal it again.
+        Threading::ResetEvent(STATE.watch_event);
+
+        #[allow(static_mut_refs)]
+        for watch in &mut STATE.watches {
+            let changed = match watch.dir.as_ref().map(|dir| dir.as_raw_handle()) {
+                // Directories that were deleted get picked up again once they exist.
+                None => watch_open(watch),
+                Some(handle) => {
+                    let mut bytes = 0;
+                    if IO::GetOverlappedResult(handle, &*watch.overlapped, &mut bytes, 0) != 0 {
+                        // 0 bytes means that the buffer overflowed. Assume the worst.
+                        let changed = bytes == 0 || watch_matches(watch, bytes as usize);
+                        if !watch_issue(watch) {
+                            watch.dir = None;
+                        }
+                        changed
+                    } else if Foundation::GetLastError() == Foundation::ERROR_IO_INCOMPLETE {
+                        false
+                    } else {
+                        // The directory is gone.
+                        watch.dir = None;
+                        true
+                    }
+                }
+            };
+            if changed && !changes.contains(&watch.path) {
+                changes.push(watch.path.clone());
+            }
+        }
     }
+
+    changes
+}
+
+fn watch_open(watch: &mut Watch) -> bool {
+    let dir = match watch.name {
+        Some(_) => match watch.path.parent() {
+            Some(parent) if !parent.as_os_str().is_empty() => parent,
+            _ => Path::new("."),
+        },
+        None => watch.path.as_path(),
+    };
+    let Ok(dir) = fs::OpenOptions::new()
+        .access_mode(FileSystem::FILE_LIST_DIRECTORY)
+        .share_mode(
+            FileSystem::FILE_SHARE_READ
+                | FileSystem::FILE_SHARE_WRITE
+                | FileSystem::FILE_SHARE_DELETE,
+        )
+        .custom_flags(FileSystem::FILE_FLAG_BACKUP_SEMANTICS | FileSystem::FILE_FLAG_OVERLAPPED)
+        .open(dir)
+    else {
+        return false;
+    };
+
+    watch.dir = Some(dir);
+    if !watch_issue(watch) {
+        watch.dir = None;
+        return false;
+    }
+    true
+}
+
+/// Starts the next asynchronous `ReadDirectoryChangesW` on the watch.
+fn watch_issue(watch: &mut Watch) -> bool {
+    let Some(dir) = &watch.dir else {
+        return false;
+    };
+
+    unsafe {
+        *watch.overlapped = mem::zeroed();
+        watch.overlapped.hEvent = STATE.watch_event;
+        FileSystem::ReadDirectoryChangesW(
+            dir.as_raw_handle(),
+            watch.buffer.as_mut_ptr() as *mut c_void,
+            mem::size_of_val(&*watch.buffer) as u32,
+            0,
+            FileSystem::FILE_NOTIFY_CHANGE_FILE_NAME
+                | FileSystem::FILE_NOTIFY_CHANGE_DIR_NAME
+                | FileSystem::FILE_NOTIFY_CHANGE_ATTRIBUTES
+                | FileSystem::FILE_NOTIFY_CHANGE_SIZE
+                | FileSystem::FILE_NOTIFY_CHANGE_LAST_WRITE,
+            null_mut(),
+            &mut *watch.overlapped,
+            None,
+        ) != 0
+    }
+}
+
+/// Checks whether the `len` bytes of `FILE_NOTIFY_INFORMATION`s in the buffer concern the watched path.
+fn watch_matches(watch: &Watch, len: usize) -> bool {
+    let Some(name) = &watch.name else {
+        return true;
+    };
+    let buf = watch.buffer.as_ptr() as *const u8;
+    let mut off = 0;
+
+    unsafe {
+        loop {
+            let info = &*(buf.add(off) as *const FileSystem::FILE_NOTIFY_INFORMATION);
+            let file_name = std::slice::from_raw_parts(
+                info.FileName.as_ptr(),
+                info.FileNameLength as usize / 2,
+            );
+            if String::from_utf16_lossy(file_name).to_lowercase() == *name {
+                return true;
+            }
+
+            let next = info.NextEntryOffset as usize;
+            if next == 0 || off + next >= len {
+                return false;
+            }
+            off += next;
+        }
+    }
+}
+
+/// The platform-specific half of [`super::Pty`].
+pub(crate) struct PtyChild {
+    console: Console::HPCON,
+    process: Foundation::HANDLE,
+}
+
+impl PtyChild {
+    /// Spawns the `command` on a new pseudo console of the given size.
+    /// Returns the child and the pipes for reading from and writing to the console.
+    ///
+    /// `std::process` can't be used, because it always passes our own standard
+    /// handles to the child, which then wouldn't use the pseudo console for I/O.
+    pub(crate) fn spawn(command: &mut Command, size: Size) -> apperr::Result<(Self, File, File)> {
+        unsafe {
+            let (input_read, input_write) = std::io::pipe()?;
+            let (output_read, output_write) = std::io::pipe()?;
+
+            let mut console: Console::HPCON = mem::zeroed();
+            let hr = Console::CreatePseudoConsole(
+                coord_from_size(size),
+                input_read.as_raw_handle(),
+                output_write.as_raw_handle(),
+                0,
+                &mut console,
+            );
+            if hr < 0 {
+                return Err(apperr::Error::new_sys(hr as u32));
+            }
+            // The pseudo console has its own copies of these now.
+            drop(input_read);
+            drop(output_write);
+
+            let mut child = Self { console, process: null_mut() };
+            child.process = spawn_with_pseudo_console(command, console)?;
+
+            let reader = File::from(OwnedHandle::from(output_read));
+            let writer = File::from(OwnedHandle::from(input_write));
+            Ok((child, reader, writer))
+        }
+    }
+
+    /// Tells the pseudo console about the new size.
+    pub(crate) fn resize(&mut self, size: Size) -> apperr::Result<()> {
+        let hr = unsafe { Console::ResizePseudoConsole(self.console, coord_from_size(size)) };
+        if hr < 0 { Err(apperr::Error::new_sys(hr as u32)) } else { Ok(()) }
+    }
+
+    pub(crate) fn kill(&mut self) {
+        unsafe { Threading::TerminateProcess(self.process, 1) };
+    }
+
+    /// Returns the exit code if the child has exited.
+    pub(crate) fn try_wait(&mut self) -> apperr::Result<Option<i32>> {
+        unsafe {
+            if Threading::WaitForSingleObject(self.process, 0) != Foundation::WAIT_OBJECT_0 {
+                return Ok(None);
+            }
+            let mut code = 0;
+            check_bool_return(Threading::GetExitCodeProcess(self.process, &mut code))?;
+            Ok(Some(code as i32))
+        }
+    }
+}
+
+impl Drop for PtyChild {
+    fn drop(&mut self) {
+        unsafe {
+            if !self.process.is_null() {
+                if Threading::WaitForSingleObject(self.process, 0) != Foundation::WAIT_OBJECT_0 {
+                    Threading::TerminateProcess(self.process, 1);
+                }
+                Foundation::CloseHandle(self.process);
+            }
+            // This also closes the output pipe, which ends our reader thread.
+            Console::ClosePseudoConsole(self.console);
+        }
+    }
+}
+
+fn coord_from_size(size: Size) -> Console::COORD {
+    Console::COORD {
+        X: size.width.clamp(1, i16::MAX as CoordType) as i16,
+        Y: size.height.clamp(1, i16::MAX as CoordType) as i16,
+    }
+}
+
+/// Does what [`Command::spawn`] would, but attaches the child to the given pseudo console.
+fn spawn_with_pseudo_console(
+    command: &Command,
+    console: Console::HPCON,
+) -> apperr::Result<Foundation::HANDLE> {
+    unsafe {
+        let mut cmdline = Vec::new();
+        append_quoted_arg(&mut cmdline, command.get_program());
+        for arg in command.get_args() {
+            cmdline.push(b' ' as u16);
+            append_quoted_arg(&mut cmdline, arg);
+        }
+        cmdline.push(0);
+
+        // Only pass an environment block if the command modifies it.
+        let mut env_block = Vec::new();
+        if command.get_envs().len() != 0 {
+            let mut vars: Vec<(OsString, OsString)> = std::env::vars_os().collect();
+            for (key, value) in command.get_envs() {
+                vars.retain(|(k, _)| !k.eq_ignore_ascii_case(key));
+                if let Some(value) = value {
+                    vars.push((key.to_owned(), value.to_owned()));
+                }
+            }
+            // Windows expects the block to be sorted, case-insensitively.
+            vars.sort_by_cached_key(|(k, _)| k.to_ascii_uppercase());
+            for (key, value) in vars {
+                env_block.extend(key.encode_wide());
+                env_block.push(b'=' as u16);
+                env_block.extend(value.encode_wide());
+                env_block.push(0);
+            }
+            env_block.push(0);
+        }
+
+        let cwd: Option<Vec<u16>> = command
+            .get_current_dir()
+            .map(|dir| dir.as_os_str().encode_wide().chain(iter::once(0)).collect());
+
+        let mut attr_list_size = 0;
+        Threading::InitializeProcThreadAttributeList(null_mut(), 1, 0, &mut attr_list_size);
+        // `usize` for the alignment.
+        let mut attr_list = vec![0usize; attr_list_size.div_ceil(mem::size_of::<usize>())];
+        let attr_list = attr_list.as_mut_ptr() as Threading::LPPROC_THREAD_ATTRIBUTE_LIST;
+        check_bool_return(Threading::InitializeProcThreadAttributeList(
+            attr_list,
+            1,
+            0,
+            &mut attr_list_size,
+        ))?;
+
+        let res = (|| {
+            check_bool_return(Threading::UpdateProcThreadAttribute(
+                attr_list,
+                0,
+                Threading::PROC_THREAD_ATTRIBUTE_PSEUDOCONSOLE as usize,
+                console as *const c_void,
+                mem::size_of::<Console::HPCON>(),
+                null_mut(),
+                null(),
+            ))?;
+
+            let mut startup_info: Threading::STARTUPINFOEXW = mem::zeroed();
+            startup_info.StartupInfo.cb = mem::size_of::<Threading::STARTUPINFOEXW>() as u32;
+            startup_info.lpAttributeList = attr_list;
+
+            let mut process_info: Threading::PROCESS_INFORMATION = mem::zeroed();
+            check_bool_return(Threading::CreateProcessW(
+                null(),
+                cmdline.as_mut_ptr(),
+                null(),
+                null(),
+                0,
+                Threading::EXTENDED_STARTUPINFO_PRESENT | Threading::CREATE_UNICODE_ENVIRONMENT,
+                if env_block.is_empty() { null() } else { env_block.as_ptr() as *const c_void },
+                cwd.as_ref().map_or(null(), |cwd| cwd.as_ptr()),
+                &startup_info.StartupInfo,
+                &mut process_info,
+            ))?;
+
+            Foundation::CloseHandle(process_info.hThread);
+            Ok(process_info.hProcess)
+        })();
+
+        Threading::DeleteProcThreadAttributeList(attr_list);
+        res
+    }
+}
+
+/// Appends `arg` to a command line, quoted the way `CommandLineToArgvW` expects it.
+fn append_quoted_arg(cmdline: &mut Vec<u16>, arg: &OsStr) {
+    let needs_quotes = arg.is_empty()
+        || arg.encode_wide().any(|c| c == b' ' as u16 || c == b'\t' as u16 || c == b'"' as u16);
+    if !needs_quotes {
+        cmdline.extend(arg.encode_wide());
+        return;
+    }
+
+    cmdline.push(b'"' as u16);
+    let mut backslashes = 0;
+    for c in arg.encode_wide() {
+        if c == b'\\' as u16 {
+            backslashes += 1;
+        } else {
+            // Backslashes are only special if they precede a quote.
+            if c == b'"' as u16 {
+                cmdline.extend(iter::repeat_n(b'\\' as u16, backslashes + 1));
+            }
+            backslashes = 0;
+        }
+        cmdline.push(c);
+    }
+    // The same goes for the closing quote.
+    cmdline.extend(iter::repeat_n(b'\\' as u16, backslashes));
+    cmdline.push(b'"' as u16);
 }
 
 pub fn drives() -> impl Iterator<Item = char> {
@@ -466,7 +1083,7 @@ impl PartialEq for FileId {
                 let b: &[u8; SIZE] = unsafe { mem::transmute(right) };
                 a == b
             }
-            (Self::Path(left), Self::Path(right)) => left == right,
+            (Self::Path(left), Self::Path(right)) => path::equals(left, right),
             _ => false,
         }
     }
@@ -474,6 +1091,103 @@ impl PartialEq for FileId {
 
 impl Eq for FileId {}
 
+/// Writes a file by letting `write` fill a temporary file, which then atomically replaces
+/// the original. This way, a crash or a full disk can't leave a half-written file behind.
+///
+/// If that would break hard links, or if it can't be done for any other reason,
+/// the file is overwritten in place instead.
+pub fn safe_write(
+    path: &Path,
+    write: impl FnOnce(&mut File) -> apperr::Result<()>,
+) -> apperr::Result<()> {
+    let Some((mut file, temp)) = create_replacement(path) else {
+        let mut file = File::create(path)?;
+        write(&mut file)?;
+        file.sync_all()?;
+        return Ok(());
+    };
+
+    let mut res = write(&mut file).and_then(|_| Ok(file.sync_all()?));
+    drop(file);
+
+    if res.is_ok() {
+        let path = path.as_os_str().encode_wide().chain(iter::once(0)).collect::<Vec<_>>();
+        let temp = temp.as_os_str().encode_wide().chain(iter::once(0)).collect::<Vec<_>>();
+        // Unlike a rename, this keeps the original's attributes, ACLs and alternate data streams.
+        res = check_bool_return(unsafe {
+            FileSystem::ReplaceFileW(
+                path.as_ptr(),
+                temp.as_ptr(),
+                null(),
+                FileSystem::REPLACEFILE_IGNORE_MERGE_ERRORS
+                    | FileSystem::REPLACEFILE_IGNORE_ACL_ERRORS,
+                null(),
+                null(),
+            )
+        });
+    }
+    if res.is_err() {
+        _ = fs::remove_file(&temp);
+    }
+    res
+}
+
+/// Creates a temporary file next to `path`.
+/// Returns `None` if `path` doesn't exist or if replacing it would break hard links.
+fn create_replacement(path: &Path) -> Option<(File, PathBuf)> {
+    let original = File::open(path).ok()?;
+    let mut info: FileSystem::BY_HANDLE_FILE_INFORMATION = unsafe { mem::zeroed() };
+    let ok = unsafe { FileSystem::GetFileInformationByHandle(original.as_raw_handle(), &mut info) };
+    if ok == 0
+        || info.nNumberOfLinks > 1
+        || info.dwFileAttributes & FileSystem::FILE_ATTRIBUTE_DIRECTORY != 0
+    {
+        return None;
+    }
+
+    let mut name = OsString::from(".");
+    name.push(path.file_name()?);
+    name.push(format!(".{}.tmp", std::process::id()));
+    let temp = path.with_file_name(name);
+    let file = fs::OpenOptions::new().write(true).create_new(true).open(&temp).ok()?;
+    Some((file, temp))
+}
+
+/// Returns the base directory for the given kind of files, without an application suffix.
+///
+/// Settings and data roam with the user's profile, while state and caches stay on this machine.
+pub fn known_dir(dir: KnownDir) -> Option<PathBuf> {
+    let id = match dir {
+        KnownDir::Config | KnownDir::Data => &Shell::FOLDERID_RoamingAppData,
+        KnownDir::State | KnownDir::Cache => &Shell::FOLDERID_LocalAppData,
+    };
+
+    unsafe {
+        let mut ptr = null_mut();
+        let hr = Shell::SHGetKnownFolderPath(id, 0, null_mut(), &mut ptr);
+        let path = if hr >= 0 && !ptr.is_null() {
+            let len = (0..).take_while(|&i| *ptr.add(i) != 0).count();
+            Some(PathBuf::from(OsString::from_wide(std::slice::from_raw_parts(ptr, len))))
+        } else {
+            None
+        };
+        // The docs say that it must be freed even if the call failed.
+        Com::CoTaskMemFree(ptr as *const c_void);
+        path
+    }
+}
+
+/// Returns the home directory of the current user, i.e. `%USERPROFILE%`.
+///
+/// Looking up other users isn't supported, because their profile
+/// may live anywhere and we can't read it without their token anyway.
+pub fn home_dir(user: Option<&OsStr>) -> Option<PathBuf> {
+    if user.is_some() {
+        return None;
+    }
+    std::env::var_os("USERPROFILE").map(PathBuf::from).filter(|p| p.is_absolute())
+}
+
 /// Returns a unique identifier for the given file by handle or path.
 pub fn file_id(file: Option<&File>, path: &Path) -> apperr::Result<FileId> {
     let file = match file {
@@ -481,7 +1195,7 @@ pub fn file_id(file: Option<&File>, path: &Path) -> apperr::Result<FileId> {
         None => &File::open(path)?,
     };
 
-    file_id_from_handle(file).or_else(|_| Ok(FileId::Path(std::fs::canonicalize(path)?)))
+    file_id_from_handle(file).or_else(|_| Ok(FileId::Path(path::canonicalize(path))))
 }
 
 fn file_id_from_handle(file: &File) -> apperr::Result<FileId> {
@@ -502,18 +1216,8 @@ fn file_id_from_handle(file: &File) -> apperr::Result<FileId> {
 /// This differs from [`fs::canonicalize`] in that it strips the `\\?\` UNC
 /// prefix on Windows. This is because it's confusing/ugly when displaying it.
 pub fn canonicalize(path: &Path) -> std::io::Result<PathBuf> {
-    let mut path = fs::canonicalize(path)?;
-    let path = path.as_mut_os_string();
-    let mut path = mem::take(path).into_encoded_bytes();
-
-    if path.len() > 6 && &path[0..4] == br"\\?\" && path[4].is_ascii_uppercase() && path[5] == b':'
-    {
-        path.drain(0..4);
-    }
-
-    let path = unsafe { OsString::from_encoded_bytes_unchecked(path) };
-    let path = PathBuf::from(path);
-    Ok(path)
+    let path = fs::canonicalize(path)?;
+    Ok(path::strip_verbatim_prefix(&path).into_owned())
 }
 
 /// Reserves a virtual memory region of the given size.
@@ -580,6 +1284,70 @@ pub unsafe fn virtual_commit(base: NonNull<u8>, size: usize) -> apperr::Result<(
     }
 }
 
+/// Returns the alignment that [`mmap_file`] offsets need: The allocation granularity.
+/// It's been 64 KiB on every version of Windows and on every architecture.
+pub fn mmap_granularity() -> usize {
+    64 * KIBI
+}
+
+/// Maps `len` bytes of `file` starting at `offset` into memory.
+///
+/// # Safety
+///
+/// The `offset` must be aligned to [`mmap_granularity`] and `len` must be non-zero.
+/// Release the mapping with [`mmap_release`].
+pub(crate) unsafe fn mmap_file(
+    file: &File,
+    offset: u64,
+    len: usize,
+    mode: MmapMode,
+) -> apperr::Result<NonNull<u8>> {
+    let (protect, access) = match mode {
+        MmapMode::ReadOnly => (Memory::PAGE_READONLY, Memory::FILE_MAP_READ),
+        MmapMode::CopyOnWrite => (Memory::PAGE_WRITECOPY, Memory::FILE_MAP_COPY),
+    };
+    unsafe {
+        let mapping =
+            Memory::CreateFileMappingW(file.as_raw_handle(), null(), protect, 0, 0, null());
+        if mapping.is_null() {
+            return Err(get_last_error());
+        }
+        let view =
+            Memory::MapViewOfFile(mapping, access, (offset >> 32) as u32, offset as u32, len);
+        let res = check_ptr_return(view.Value as *mut u8);
+        // The view keeps the mapping object alive.
+        Foundation::CloseHandle(mapping);
+        res
+    }
+}
+
+/// # Safety
+///
+/// Make sure to only pass pointers acquired from [`mmap_file`].
+pub(crate) unsafe fn mmap_release(base: NonNull<u8>, _len: usize) {
+    unsafe {
+        Memory::UnmapViewOfFile(Memory::MEMORY_MAPPED_VIEW_ADDRESS {
+            Value: base.as_ptr() as *mut _,
+        });
+    }
+}
+
+/// # Safety
+///
+/// Make sure to only pass pointers and sizes acquired from [`mmap_file`].
+pub(crate) unsafe fn mmap_advise(base: NonNull<u8>, len: usize, advice: MmapAdvice) {
+    // Windows only has an equivalent for prefetching. Its read-ahead is good enough otherwise.
+    if advice == MmapAdvice::WillNeed {
+        unsafe {
+            let range = Memory::WIN32_MEMORY_RANGE_ENTRY {
+                VirtualAddress: base.as_ptr() as *mut _,
+                NumberOfBytes: len,
+            };
+            Memory::PrefetchVirtualMemory(Threading::GetCurrentProcess(), 1, &range, 0);
+        }
+    }
+}
+
 unsafe fn get_module(name: *const u16) -> apperr::Result<NonNull<c_void>> {
     unsafe { check_ptr_return(LibraryLoader::GetModuleHandleW(name)) }
 }
@@ -769,8 +1537,8 @@ pub fn apperr_format(f: &mut std::fmt::Formatter<'_>, code: u32) -> std::fmt::Re
 }
 
 /// Checks if the given error is a "file not found" error.
-pub fn apperr_is_not_found(err: apperr::Error) -> bool {
-    err == gle_to_apperr(Foundation::ERROR_FILE_NOT_FOUND)
+pub fn apperr_is_not_found(err: &apperr::Error) -> bool {
+    *err.root() == gle_to_apperr(Foundation::ERROR_FILE_NOT_FOUND)
 }
 
 fn check_bool_return(ret: Foundation::BOOL) -> apperr::Result<()> {
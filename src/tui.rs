@@ -83,8 +83,9 @@
 //! guarantees that all of the parent nodes must have equivalent IDs as well.
 //! This turns "is the focus anywhere inside this subtree" into an O(1) check.
 //!
-//! The reason "classnames" are used is because I was hoping to add theming
-//! in the future with a syntax similar to CSS (simplified, however).
+//! The reason "classnames" are used is theming: Widgets resolve their colors
+//! by [`ThemeRole`], which can be overridden per classname via
+//! [`Tui::set_widget_theme_colors`]. It's a far cry from CSS, but it's a start.
 //!
 //! # Example
 //!
@@ -144,31 +145,45 @@
 //! ```
 
 use std::arch::breakpoint;
-#[cfg(debug_assertions)]
-use std::collections::HashSet;
+use std::borrow::Cow;
+use std::collections::VecDeque;
 use std::fmt::Write as _;
+use std::ops::{Range, RangeInclusive};
+use std::sync::{Arc, Mutex};
 use std::{iter, mem, ptr, time};
 
+#[cfg(debug_assertions)]
+use crate::arena::ArenaHashMap;
 use crate::arena::{Arena, ArenaString, scratch_arena};
 use crate::buffer::{CursorMovement, MoveLineDirection, RcTextBuffer, TextBuffer, TextBufferCell};
+use crate::capabilities::Capabilities;
 use crate::cell::*;
 use crate::clipboard::Clipboard;
 use crate::document::WriteableDocument;
-use crate::framebuffer::{Attributes, Framebuffer, INDEXED_COLORS_COUNT, IndexedColor};
+use crate::framebuffer::{
+    Attributes, CaptureFormat, Framebuffer, INDEXED_COLORS_COUNT, IndexedColor,
+};
 use crate::hash::*;
 use crate::helpers::*;
 use crate::input::{InputKeyMod, kbmod, vk};
 use crate::oklab::StraightRgba;
-use crate::{apperr, arena_format, input, simd, unicode};
+use crate::{apperr, arena_format, input, simd, sys, unicode, vt};
 
 const ROOT_ID: u64 = 0x14057B7EF767814F; // Knuth's MMIX constant
 const SHIFT_TAB: InputKey = vk::TAB.with_modifiers(kbmod::SHIFT);
+const SHIFT_F10: InputKey = vk::F10.with_modifiers(kbmod::SHIFT);
 const KBMOD_FOR_WORD_NAV: InputKeyMod =
     if cfg!(target_os = "macos") { kbmod::ALT } else { kbmod::CTRL };
+/// With Left/Right, shrinks/expands the selection. Alt+Shift is word selection on macOS.
+pub const KBMOD_FOR_EXPAND_SELECTION: InputKeyMod =
+    if cfg!(target_os = "macos") { kbmod::CTRL_SHIFT } else { kbmod::ALT_SHIFT };
+/// How long we wait for the second key of a chord. See [`Context::consume_chord()`].
+const CHORD_TIMEOUT: time::Duration = time::Duration::from_secs(3);
 
 type Input<'input> = input::Input<'input>;
 type InputKey = input::InputKey;
 type InputMouseState = input::InputMouseState;
+type InputPaste<'input> = input::InputPaste<'input>;
 
 /// Since [`TextBuffer`] creation and management is expensive,
 /// we cache instances of them for reuse between frames.
@@ -179,10 +194,18 @@ struct CachedTextBuffer {
     seen: bool,
 }
 
+/// A repeating timer created with [`Context::timer()`].
+struct Timer {
+    id: u64,
+    deadline: time::Instant,
+    seen: bool,
+}
+
 /// Since [`Context::editline()`] and [`Context::textarea()`]
 /// do almost the same thing, this abstracts over the two.
 enum TextBufferPayload<'a> {
     Editline(&'a mut dyn WriteableDocument),
+    Editbox(&'a mut dyn WriteableDocument),
     Textarea(RcTextBuffer),
 }
 
@@ -219,6 +242,65 @@ pub struct FloatSpec {
     pub offset_y: f32,
 }
 
+/// The axis along which a flex container lays out its children.
+#[derive(Default, Clone, Copy, PartialEq, Eq)]
+pub enum FlexDirection {
+    /// Left to right, or right to left with [`Tui::setup_rtl()`].
+    Row,
+    /// Top to bottom.
+    #[default]
+    Column,
+}
+
+/// The alignment of flex children on the cross axis.
+#[derive(Default, Clone, Copy, PartialEq, Eq)]
+pub enum FlexAlign {
+    /// Fill the entire cross axis.
+    #[default]
+    Stretch,
+    Start,
+    Center,
+    End,
+}
+
+/// Controls the layout of a flex container. See [`Context::flex_begin`].
+#[derive(Default, Clone, Copy)]
+pub struct FlexSpec {
+    pub direction: FlexDirection,
+    /// The space between children along the main axis.
+    pub gap: CoordType,
+    pub align: FlexAlign,
+}
+
+/// Controls the size of a child of a flex container along the main axis.
+/// See [`Context::attr_flex`].
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub struct FlexItem {
+    /// The share of the remaining space that this child receives, relative to its siblings.
+    pub grow: u16,
+    /// The share of the missing space that this child gives up, if its siblings don't fit.
+    pub shrink: u16,
+    pub min: CoordType,
+    pub max: CoordType,
+}
+
+impl Default for FlexItem {
+    fn default() -> Self {
+        Self { grow: 0, shrink: 1, min: 0, max: COORD_TYPE_SAFE_MAX }
+    }
+}
+
+/// Controls the layout of a split container. See [`Context::split_begin`].
+#[derive(Default, Clone, Copy)]
+pub struct SplitSpec {
+    /// [`FlexDirection::Row`] puts the panes side by side, separated by a vertical divider.
+    pub direction: FlexDirection,
+    /// The initial size of the first pane. Defaults to half of the available space.
+    pub position: Option<CoordType>,
+    /// The minimum size of either pane.
+    pub min: CoordType,
+}
+
 /// Informs you about the change that was made to the list selection.
 #[derive(Clone, Copy, PartialEq, Eq)]
 pub enum ListSelection {
@@ -231,6 +313,104 @@ pub enum ListSelection {
     Activated,
 }
 
+/// Filters a large number of items over multiple frames, for use with [`Context::virtual_list_begin`].
+///
+/// While [`ListFilter::is_done`] returns `false`, call [`ListFilter::step`] once per frame
+/// and request another frame via [`Context::needs_rerender`]. The matches found so far
+/// can be shown in the meantime. They're sorted by item index.
+#[derive(Default)]
+pub struct ListFilter {
+    matches: Vec<u32>,
+    /// The items that are being filtered, if it's not all of them. See [`ListFilter::refine`].
+    candidates: Option<Vec<u32>>,
+    /// The number of items or candidates that were checked so far.
+    checked: usize,
+    done: bool,
+}
+
+impl ListFilter {
+    /// Filters all items from scratch, e.g. because the filter changed entirely.
+    pub fn restart(&mut self) {
+        self.matches.clear();
+        self.candidates = None;
+        self.checked = 0;
+        self.done = false;
+    }
+
+    /// Filters only the current matches again. Use this if the filter got
+    /// stricter, e.g. because the user typed another character.
+    pub fn refine(&mut self) {
+        let candidates = if self.done || self.candidates.is_some() {
+            // An unfinished refinement still has unchecked candidates.
+            let mut candidates = self.candidates.take().unwrap_or_default();
+            let unchecked = candidates.split_off(self.checked.min(candidates.len()));
+            let mut c = mem::take(&mut self.matches);
+            c.extend_from_slice(&unchecked);
+            c
+        } else {
+            // The initial pass isn't done yet. Just start over.
+            return self.restart();
+        };
+        self.candidates = Some(candidates);
+        self.checked = 0;
+        self.done = false;
+    }
+
+    /// Checks items with `is_match` until `deadline` or until all `count` items were checked.
+    /// `count` must not change between calls, unless [`ListFilter::restart`] is called.
+    pub fn step(
+        &mut self,
+        count: usize,
+        deadline: time::Instant,
+        mut is_match: impl FnMut(usize) -> bool,
+    ) {
+        // Checking the time is comparatively expensive.
+        const BATCH: usize = 1024;
+
+        let total = self.candidates.as_ref().map_or(count, |c| c.len());
+        while !self.done {
+            let end = (self.checked + BATCH).min(total);
+            for i in self.checked..end {
+                let item = match &self.candidates {
+                    Some(c) => c[i] as usize,
+                    None => i,
+                };
+                if is_match(item) {
+                    self.matches.push(item as u32);
+                }
+            }
+
+            self.checked = end;
+            self.done = end == total;
+            if time::Instant::now() >= deadline {
+                break;
+            }
+        }
+
+        if self.done {
+            self.candidates = None;
+        }
+    }
+
+    pub fn is_done(&self) -> bool {
+        self.done
+    }
+
+    /// The indices of the matching items.
+    pub fn matches(&self) -> &[u32] {
+        &self.matches
+    }
+}
+
+/// A column of a grid. See [`Context::grid_begin`].
+#[derive(Clone, Copy)]
+pub struct GridColumn<'a> {
+    /// The text shown in the header row.
+    pub title: &'a str,
+    /// The width in columns. 0 sizes the column to fit its widest cell.
+    pub width: CoordType,
+}
+
 /// Controls the position of a node relative to its parent.
 #[derive(Default)]
 pub enum Position {
@@ -260,6 +440,120 @@ pub enum Overflow {
     TruncateTail,
 }
 
+/// The orientation of a scrollbar, see [`Context::scrollbar`].
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub enum ScrollbarAxis {
+    Vertical,
+    Horizontal,
+}
+
+/// Restricts what can be entered into an editline. See [`EditlineSpec`].
+#[derive(Default, Clone, Copy, PartialEq, Eq)]
+pub enum InputFilter<'a> {
+    #[default]
+    Any,
+    /// Only the ASCII digits 0-9.
+    Numeric,
+    /// Only the characters in the given string.
+    Chars(&'a str),
+    /// A fixed pattern, where `#` stands for a digit, `A` for a letter, `*` for any character
+    /// and everything else for itself. Such literals are inserted automatically while typing.
+    Mask(&'a str),
+}
+
+impl InputFilter<'_> {
+    /// Checks `text` against the filter and returns `None` if it's rejected.
+    /// For masks, missing literals are inserted, and trailing ones are appended if `grew` is set.
+    fn apply<'t>(&self, text: &'t str, grew: bool) -> Option<Cow<'t, str>> {
+        match *self {
+            Self::Any => Some(Cow::Borrowed(text)),
+            Self::Numeric => {
+                text.bytes().all(|b| b.is_ascii_digit()).then_some(Cow::Borrowed(text))
+            }
+            Self::Chars(set) => {
+                text.chars().all(|c| set.contains(c)).then_some(Cow::Borrowed(text))
+            }
+            Self::Mask(mask) => {
+                let is_literal = |m: char| !matches!(m, '#' | 'A' | '*');
+                let mut result = String::with_capacity(mask.len());
+                let mut mask = mask.chars().peekable();
+
+                for ch in text.chars() {
+                    loop {
+                        match mask.next()? {
+                            '#' if ch.is_ascii_digit() => break,
+                            'A' if ch.is_alphabetic() => break,
+                            '*' => break,
+                            m if !is_literal(m) => return None,
+                            m if m == ch => break,
+                            m => result.push(m),
+                        }
+                    }
+                    result.push(ch);
+                }
+
+                if grew {
+                    while let Some(&m) = mask.peek()
+                        && is_literal(m)
+                    {
+                        result.push(m);
+                        mask.next();
+                    }
+                }
+
+                Some(if result == text { Cow::Borrowed(text) } else { Cow::Owned(result) })
+            }
+        }
+    }
+}
+
+/// Additional options for an editline. See [`Context::editline_with_spec`].
+#[derive(Default, Clone, Copy)]
+pub struct EditlineSpec<'a> {
+    /// Shown dimmed while the editline is empty.
+    pub placeholder: &'a str,
+    /// Edits that would result in text that doesn't pass the filter are undone.
+    pub filter: InputFilter<'a>,
+    /// Hides the text behind bullets and prevents it from being copied.
+    pub password: bool,
+    /// If set, the editline is highlighted and the message is shown
+    /// on its right side, as long as there's room for it.
+    pub error: Option<&'a str>,
+}
+
+/// The semantic roles that widgets resolve their colors from.
+/// See [`Tui::set_theme_colors`] and [`Context::attr_theme`].
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum ThemeRole {
+    /// Floating containers, like dropdowns, menus and toasts.
+    Floater,
+    /// Modal dialogs.
+    Modal,
+    /// The focused item of lists and menus.
+    Selection,
+    /// Focused buttons and checkboxes. By default they're drawn in reverse video.
+    FocusedButton,
+    /// Focused text input fields.
+    Input,
+    /// Unfocused text input fields.
+    InputInactive,
+    /// The track of progress bars.
+    Track,
+    Warning,
+    Error,
+}
+
+impl ThemeRole {
+    const COUNT: usize = Self::Error as usize + 1;
+}
+
+/// The colors of a [`ThemeRole`].
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub struct ThemeColors {
+    pub bg: StraightRgba,
+    pub fg: StraightRgba,
+}
+
 /// Controls the style with which a button label renders
 #[derive(Clone, Copy)]
 pub struct ButtonStyle {
@@ -295,6 +589,112 @@ impl Default for ButtonStyle {
     }
 }
 
+/// An entry in a menu created with [`Context::context_menu`].
+#[derive(Clone, Copy)]
+pub struct MenuItem<'a> {
+    text: &'a str,
+    accelerator: char,
+    shortcut: InputKey,
+    enabled: bool,
+    separator: bool,
+}
+
+impl<'a> MenuItem<'a> {
+    /// A horizontal line that separates groups of items.
+    pub const SEPARATOR: Self =
+        Self { text: "", accelerator: '\0', shortcut: vk::NULL, enabled: false, separator: true };
+
+    /// Creates an enabled item with the given text.
+    pub fn new(text: &'a str) -> Self {
+        Self { text, accelerator: '\0', shortcut: vk::NULL, enabled: true, separator: false }
+    }
+    /// Underlines the given character and lets it activate the item.
+    ///
+    /// Must provide an upper-case ASCII character.
+    pub fn accelerator(self, accelerator: char) -> Self {
+        Self { accelerator, ..self }
+    }
+    /// Shows the given shortcut next to the item. It's only a hint and isn't handled by the menu.
+    pub fn shortcut(self, shortcut: InputKey) -> Self {
+        Self { shortcut, ..self }
+    }
+    /// Disabled items are drawn dimmed and can't be focused or activated.
+    pub fn enabled(self, enabled: bool) -> Self {
+        Self { enabled, ..self }
+    }
+}
+
+/// The severity of a toast. See [`ToastQueue`].
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum ToastKind {
+    Info,
+    Warning,
+    Error,
+}
+
+/// A thread-safe handle for showing toasts: short, non-modal notifications.
+///
+/// Toasts pushed from other threads are picked up the next time [`Context::toasts()`]
+/// is drawn. Pushing doesn't wake up the UI thread, so if it might be idle, request
+/// a wakeup for as long as the background work is running (e.g. with a spinner).
+#[derive(Clone, Default)]
+pub struct ToastQueue {
+    pending: Arc<Mutex<Vec<(ToastKind, String)>>>,
+}
+
+impl ToastQueue {
+    /// Queues a toast with the given text.
+    pub fn push(&self, kind: ToastKind, text: impl Into<String>) {
+        if let Ok(mut pending) = self.pending.lock() {
+            pending.push((kind, text.into()));
+        }
+    }
+
+    fn take(&self) -> Vec<(ToastKind, String)> {
+        self.pending.lock().map(|mut p| mem::take(&mut *p)).unwrap_or_default()
+    }
+}
+
+/// Whether the terminal answers OSC 52 clipboard queries.
+///
+/// There's no way to ask for it without actually reading the clipboard,
+/// so we find out when the user first pastes. See [`Tui::filter_clipboard_read()`].
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum ClipboardReadSupport {
+    Unknown,
+    Supported,
+    Unsupported,
+}
+
+/// An OSC 52 clipboard query that is waiting for the terminal's response.
+struct ClipboardRead {
+    /// The paste shortcut that is replayed once the clipboard contents arrived.
+    key: InputKey,
+    /// When we give up and paste the internal clipboard instead.
+    deadline: time::Instant,
+    /// Whether the query has been written to the terminal.
+    sent: bool,
+}
+
+/// An input that was queued by [`Tui::filter_clipboard_read()`].
+enum QueuedInput {
+    Input(Input<'static>),
+    /// A paste chunk. Its text is copied, as [`InputPaste`] borrows it from the input buffer.
+    Paste {
+        text: String,
+        first: bool,
+        last: bool,
+    },
+}
+
+/// A toast that is currently visible.
+struct Toast {
+    id: u64,
+    kind: ToastKind,
+    text: String,
+    expires: time::Instant,
+}
+
 /// There's two types of lifetimes the TUI code needs to manage:
 /// * Across frames
 /// * Per frame
@@ -316,10 +716,10 @@ pub struct Tui {
     framebuffer: Framebuffer,
 
     modifier_translations: ModifierTranslations,
-    floater_default_bg: StraightRgba,
-    floater_default_fg: StraightRgba,
-    modal_default_bg: StraightRgba,
-    modal_default_fg: StraightRgba,
+    /// Colors set via [`Tui::set_theme_colors`].
+    theme_roles: [Option<ThemeColors>; ThemeRole::COUNT],
+    /// Colors set via [`Tui::set_widget_theme_colors`].
+    theme_widgets: Vec<(&'static str, ThemeRole, ThemeColors)>,
 
     /// Last known terminal size.
     ///
@@ -359,6 +759,16 @@ pub struct Tui {
     /// This way we can track if the focus changed, because then we
     /// need to scroll the node into view if it's within a scrollarea.
     focused_node_for_scrolling: u64,
+    /// For each open modal, from bottom to top, its ID and the focus path from before it opened.
+    /// When a modal closes, the focus returns to where it was, even inside another modal.
+    modal_focus_stack: Vec<(u64, Vec<u64>)>,
+    /// The ID of the node whose context menu is open and the menu's position.
+    context_menu: Option<(u64, Point)>,
+    /// The classname passed to [`Context::request_focus()`], if it's still pending.
+    focus_request: Option<&'static str>,
+    /// Set once a frame has completed without fulfilling the `focus_request`.
+    /// It's dropped if the next frame doesn't fulfill it either.
+    focus_request_stale: bool,
 
     /// A list of cached text buffers used for [`Context::editline()`].
     cached_text_buffers: Vec<CachedTextBuffer>,
@@ -366,9 +776,38 @@ pub struct Tui {
     /// The clipboard contents.
     clipboard: Clipboard,
 
+    /// See [`Tui::buffer_render_time()`].
+    buffer_render_time: time::Duration,
+
     settling_have: i32,
     settling_want: i32,
-    read_timeout: time::Duration,
+    /// The earliest of the wakeups requested during the last frame(s).
+    wakeup: Option<time::Instant>,
+    /// The timers created with [`Context::timer()`].
+    timers: Vec<Timer>,
+    /// See [`Tui::toast_queue()`].
+    toast_queue: ToastQueue,
+    /// See [`Context::paste_progress()`].
+    paste_progress: Option<usize>,
+    /// The first key of a chord that waits for its second key, and when it expires.
+    chord_pending: Option<(InputKey, time::Instant)>,
+    /// The first keys of the chords that were passed to [`Context::consume_chord()`] during the last frame.
+    chord_prefixes: Vec<InputKey>,
+    /// Whether pastes read the OS clipboard directly. See [`Tui::setup_native_clipboard()`].
+    native_clipboard: bool,
+    clipboard_read_support: ClipboardReadSupport,
+    clipboard_read: Option<ClipboardRead>,
+    /// The input that arrived while a paste was held back. See [`Tui::filter_clipboard_read()`].
+    clipboard_queue: VecDeque<QueuedInput>,
+    /// The text of the paste chunk last replayed from `clipboard_queue`.
+    clipboard_replay: String,
+    /// The toasts drawn by [`Context::toasts()`], oldest first.
+    toasts: Vec<Toast>,
+    /// Used to assign each toast a unique ID.
+    toast_counter: u64,
+    capabilities: Capabilities,
+    /// Whether the layout is mirrored for right-to-left languages. See [`Tui::setup_rtl()`].
+    rtl: bool,
 }
 
 impl Tui {
@@ -392,10 +831,8 @@ impl Tui {
                 alt: "Alt",
                 shift: "Shift",
             },
-            floater_default_bg: StraightRgba::zero(),
-            floater_default_fg: StraightRgba::zero(),
-            modal_default_bg: StraightRgba::zero(),
-            modal_default_fg: StraightRgba::zero(),
+            theme_roles: [None; ThemeRole::COUNT],
+            theme_widgets: Vec::new(),
 
             size: Size { width: 0, height: 0 },
             mouse_position: Point::MIN,
@@ -411,14 +848,34 @@ impl Tui {
 
             focused_node_path: Vec::with_capacity(16),
             focused_node_for_scrolling: ROOT_ID,
+            modal_focus_stack: Vec::new(),
+            context_menu: None,
+            focus_request: None,
+            focus_request_stale: false,
 
             cached_text_buffers: Vec::with_capacity(16),
 
             clipboard: Default::default(),
 
+            buffer_render_time: time::Duration::ZERO,
+
             settling_have: 0,
             settling_want: 0,
-            read_timeout: time::Duration::MAX,
+            wakeup: None,
+            timers: Vec::new(),
+            toast_queue: ToastQueue::default(),
+            paste_progress: None,
+            chord_pending: None,
+            chord_prefixes: Vec::new(),
+            native_clipboard: false,
+            clipboard_read_support: ClipboardReadSupport::Unknown,
+            clipboard_read: None,
+            clipboard_queue: VecDeque::new(),
+            clipboard_replay: String::new(),
+            toasts: Vec::new(),
+            toast_counter: 0,
+            capabilities: Capabilities::default(),
+            rtl: false,
         };
         Self::clean_node_path(&mut tui.mouse_down_node_path);
         Self::clean_node_path(&mut tui.focused_node_path);
@@ -430,35 +887,136 @@ impl Tui {
         self.framebuffer.set_indexed_colors(colors);
     }
 
+    /// Redraws the entire screen during the next [`Tui::render`],
+    /// because the terminal contents got lost.
+    pub fn invalidate(&mut self) {
+        self.framebuffer.invalidate();
+    }
+
+    /// Makes pastes read the OS clipboard via [`sys::clipboard_read()`],
+    /// instead of querying the terminal's clipboard via OSC 52.
+    pub fn setup_native_clipboard(&mut self, enabled: bool) {
+        self.native_clipboard = enabled;
+    }
+
+    /// Mirrors the layout horizontally for right-to-left languages:
+    /// Rows, table cells and split panes are laid out from right to left,
+    /// left/right alignments swap, and popups open towards the left of their anchor.
+    /// The contents of text nodes and text areas are unaffected.
+    pub fn setup_rtl(&mut self, rtl: bool) {
+        self.rtl = rtl;
+    }
+
+    /// Sets up the detected terminal features.
+    pub fn setup_capabilities(&mut self, capabilities: Capabilities) {
+        self.capabilities = capabilities;
+        self.framebuffer.set_extended_underlines(capabilities.extended_underlines);
+        self.framebuffer.set_color_depth(capabilities.color_depth);
+        self.framebuffer.set_image_protocol(
+            capabilities.image_protocol,
+            capabilities.cell_pixel_size.unwrap_or(Size { width: 10, height: 20 }),
+        );
+    }
+
+    /// Returns the features supported by the terminal.
+    pub fn capabilities(&self) -> Capabilities {
+        self.capabilities
+    }
+
     /// Set up translations for Ctrl/Alt/Shift modifiers.
     pub fn setup_modifier_translations(&mut self, translations: ModifierTranslations) {
         self.modifier_translations = translations;
     }
 
-    /// Set the default background color for floaters (dropdowns, etc.).
-    pub fn set_floater_default_bg(&mut self, color: StraightRgba) {
-        self.floater_default_bg = color;
+    /// Sets the colors of the given theme `role` for all widgets.
+    /// Unless set, they're derived from the indexed color palette.
+    ///
+    /// If the colors are opaque and the text wouldn't be legible,
+    /// the foreground color is replaced with a contrasting one.
+    pub fn set_theme_colors(&mut self, role: ThemeRole, colors: ThemeColors) {
+        self.theme_roles[role as usize] = Some(self.legible_theme_colors(colors));
     }
 
-    /// Set the default foreground color for floaters (dropdowns, etc.).
-    pub fn set_floater_default_fg(&mut self, color: StraightRgba) {
-        self.floater_default_fg = color;
+    /// Sets the colors of the given theme `role`, but only for nodes with the given `classname`.
+    /// These take precedence over the ones set via [`Tui::set_theme_colors`].
+    pub fn set_widget_theme_colors(
+        &mut self,
+        classname: &'static str,
+        role: ThemeRole,
+        colors: ThemeColors,
+    ) {
+        let colors = self.legible_theme_colors(colors);
+        match self.theme_widgets.iter_mut().find(|(c, r, _)| *c == classname && *r == role) {
+            Some((.., c)) => *c = colors,
+            None => self.theme_widgets.push((classname, role, colors)),
+        }
     }
 
-    /// Set the default background color for modals.
-    pub fn set_modal_default_bg(&mut self, color: StraightRgba) {
-        self.modal_default_bg = color;
+    fn legible_theme_colors(&self, colors: ThemeColors) -> ThemeColors {
+        // APCA considers |Lc| < 30 unsuitable for any kind of text.
+        // Translucent colors depend on what's below them, so we can't judge them here.
+        if colors.bg.alpha() == 0xff
+            && colors.fg.alpha() == 0xff
+            && colors.fg.apca_contrast(colors.bg).abs() < 30.0
+        {
+            ThemeColors { bg: colors.bg, fg: self.contrasted(colors.bg) }
+        } else {
+            colors
+        }
     }
 
-    /// Set the default foreground color for modals.
-    pub fn set_modal_default_fg(&mut self, color: StraightRgba) {
-        self.modal_default_fg = color;
+    /// Resolves the colors of a theme `role` for a node with the given `classname`.
+    /// Returns `None` if the role doesn't use colors by default, like [`ThemeRole::FocusedButton`].
+    fn theme_colors(&self, classname: &str, role: ThemeRole) -> Option<ThemeColors> {
+        if let Some(&(.., colors)) =
+            self.theme_widgets.iter().find(|(c, r, _)| *c == classname && *r == role)
+        {
+            return Some(colors);
+        }
+        if let Some(colors) = self.theme_roles[role as usize] {
+            return Some(colors);
+        }
+
+        let contrasted = |bg| ThemeColors { bg, fg: self.contrasted(bg) };
+        Some(match role {
+            ThemeRole::Floater | ThemeRole::Modal => {
+                ThemeColors { bg: StraightRgba::zero(), fg: StraightRgba::zero() }
+            }
+            ThemeRole::Selection => contrasted(self.indexed(IndexedColor::Green)),
+            ThemeRole::FocusedButton => return None,
+            ThemeRole::Input => ThemeColors {
+                bg: self.indexed(IndexedColor::Background),
+                fg: self.indexed(IndexedColor::Foreground),
+            },
+            ThemeRole::InputInactive => ThemeColors {
+                bg: self.indexed_alpha(IndexedColor::Background, 1, 2),
+                fg: self.contrasted(self.indexed(IndexedColor::Background)),
+            },
+            ThemeRole::Track => ThemeColors {
+                bg: self.indexed(IndexedColor::BrightBlack),
+                fg: self.indexed(IndexedColor::BrightWhite),
+            },
+            ThemeRole::Warning => contrasted(self.indexed(IndexedColor::Yellow)),
+            ThemeRole::Error => contrasted(self.indexed(IndexedColor::Red)),
+        })
     }
 
-    /// If the TUI is currently running animations, etc.,
-    /// this will return a timeout smaller than [`time::Duration::MAX`].
+    /// If the TUI is currently running animations, timers, etc.,
+    /// this will return the time until the next wakeup that was requested.
+    /// Otherwise, it returns [`time::Duration::MAX`].
+    ///
+    /// Pass it to [`crate::sys::read_stdin()`], so that a frame is drawn
+    /// once it elapses, even if there's no input.
     pub fn read_timeout(&mut self) -> time::Duration {
-        mem::replace(&mut self.read_timeout, time::Duration::MAX)
+        match self.wakeup.take() {
+            Some(deadline) => deadline.saturating_duration_since(time::Instant::now()),
+            None => time::Duration::MAX,
+        }
+    }
+
+    /// Returns how long the last [`Tui::render()`] spent rendering the text buffers.
+    pub fn buffer_render_time(&self) -> time::Duration {
+        self.buffer_render_time
     }
 
     /// Returns the viewport size.
@@ -497,6 +1055,11 @@ impl Tui {
         &self.clipboard
     }
 
+    /// Returns a handle for showing toasts, which can be sent to other threads.
+    pub fn toast_queue(&self) -> ToastQueue {
+        self.toast_queue.clone()
+    }
+
     /// Returns the clipboard (mutable).
     pub fn clipboard_mut(&mut self) -> &mut Clipboard {
         &mut self.clipboard
@@ -525,15 +1088,28 @@ impl Tui {
 
         let now = std::time::Instant::now();
         let mut input_text = None;
+        let mut input_char = None;
+        let mut input_paste = None;
         let mut input_keyboard = None;
+        let mut input_chord = None;
         let mut input_mouse_modifiers = kbmod::NONE;
         let mut input_mouse_click = 0;
+        let mut input_context_click = false;
         let mut input_scroll_delta = Point { x: 0, y: 0 };
+        let input = self.filter_clipboard_read(input, now);
         // `input_consumed` should be `true` if we're in the settling phase which is indicated by
         // `self.needs_settling() == true`. However, there's a possibility for it being true from
         // a previous frame, and we do have fresh new input. In that case want `input_consumed`
         // to be false of course which is ensured by checking for `input.is_none()`.
         let input_consumed = self.needs_settling() && input.is_none();
+        let is_key_input =
+            matches!(input, Some(Input::Text(_) | Input::Char(_) | Input::Keyboard(_)));
+
+        if let Some((_, deadline)) = self.chord_pending
+            && now >= deadline
+        {
+            self.chord_pending = None;
+        }
 
         if self.scroll_to_focused() {
             self.needs_more_settling();
@@ -558,15 +1134,31 @@ impl Tui {
                     input_keyboard = InputKey::from_ascii(ch as char)
                 }
             }
+            Some(Input::Char(ch)) => {
+                input_char = Some(ch);
+            }
             Some(Input::Paste(paste)) => {
-                let clipboard = self.clipboard_mut();
-                clipboard.write(paste);
-                clipboard.mark_as_synchronized();
-                input_keyboard = Some(kbmod::CTRL | vk::V);
+                // Pastes that fit into a single read are mirrored into our clipboard, so that
+                // they can be pasted again with Ctrl+V. Larger ones are only streamed into
+                // the focused textarea, to avoid holding onto a second copy of them.
+                if paste.first && paste.last {
+                    let clipboard = self.clipboard_mut();
+                    clipboard.write(paste.text.as_bytes().to_vec());
+                    clipboard.mark_as_synchronized();
+                }
+
+                let received = if paste.first { 0 } else { self.paste_progress.unwrap_or(0) };
+                self.paste_progress =
+                    if paste.last { None } else { Some(received + paste.text.len()) };
+                input_paste = Some(paste);
             }
             Some(Input::Keyboard(keyboard)) => {
                 input_keyboard = Some(keyboard);
             }
+            // Nothing in the UI reacts to key releases yet.
+            Some(Input::KeyRelease(_)) => {}
+            // Handled by `filter_clipboard_read()`.
+            Some(Input::HostClipboard(_)) => {}
             Some(Input::Mouse(mouse)) => {
                 let mut next_state = mouse.state;
                 let next_position = mouse.position;
@@ -626,10 +1218,15 @@ impl Tui {
                     let mut target = 0;
                     if next_state == InputMouseState::Left {
                         target = focused_node.map_or(0, |n| n.borrow().id);
-                        Self::build_node_path(focused_node, &mut self.focused_node_path);
+                        if focused_node.is_none_or(|n| !n.borrow().attributes.no_focus_on_click) {
+                            Self::build_node_path(focused_node, &mut self.focused_node_path);
+                        }
                         self.needs_more_settling(); // See `needs_more_settling()`.
                     }
 
+                    // On right-mouse-down we open context menus.
+                    input_context_click = next_state == InputMouseState::Right;
+
                     // Double-/Triple-/Etc.-clicks are triggered on mouse-down,
                     // unlike the first initial click, which is triggered on mouse-up.
                     if self.mouse_click_counter != 0 {
@@ -682,6 +1279,29 @@ impl Tui {
             }
         }
 
+        // The prefixes are registered anew by each frame. The ones of the last frame apply to this input.
+        let mut chord_prefixes = mem::take(&mut self.chord_prefixes);
+
+        if is_key_input && let Some((prefix, _)) = self.chord_pending.take() {
+            // The key after the first key of a chord is only seen by `consume_chord()`.
+            // If it doesn't complete any chord, it's dropped.
+            input_chord = input_keyboard.take().map(|key| (prefix, key));
+            input_text = None;
+            input_char = None;
+        } else if let Some(key) = input_keyboard
+            && chord_prefixes.contains(&key)
+        {
+            let deadline = now + CHORD_TIMEOUT;
+            self.chord_pending = Some((key, deadline));
+            self.wakeup = Some(self.wakeup.map_or(deadline, |w| w.min(deadline)));
+            input_keyboard = None;
+            input_text = None;
+        }
+
+        // Reuse the allocation.
+        chord_prefixes.clear();
+        self.chord_prefixes = chord_prefixes;
+
         if !input_consumed {
             // Every time there's input, we naturally need to re-render at least once.
             self.settling_have = 0;
@@ -691,15 +1311,20 @@ impl Tui {
         // TODO: There should be a way to do this without unsafe.
         // Allocating from the arena borrows the arena, and so allocating the tree here borrows self.
         // This conflicts with us passing a mutable reference to `self` into the struct below.
-        let tree = Tree::new(unsafe { mem::transmute::<&Arena, &Arena>(&self.arena_next) });
+        let arena = unsafe { mem::transmute::<&Arena, &Arena>(&self.arena_next) };
+        let tree = Tree::new(arena);
 
         Context {
             tui: self,
 
             input_text,
+            input_char,
+            input_paste,
             input_keyboard,
+            input_chord,
             input_mouse_modifiers,
             input_mouse_click,
+            input_context_click,
             input_scroll_delta,
             input_consumed,
 
@@ -710,7 +1335,9 @@ impl Tui {
             needs_settling: false,
 
             #[cfg(debug_assertions)]
-            seen_ids: HashSet::new(),
+            seen_ids: ArenaHashMap::new_in(arena),
+            #[cfg(debug_assertions)]
+            seen_shortcuts: ArenaHashMap::new_in(arena),
         }
     }
 
@@ -726,12 +1353,22 @@ impl Tui {
         ctx.block_end();
 
         // Ensure that focus doesn't escape the active modal.
+        // Floats on top of it, like context menus, may hold the focus however.
         if let Some(node) = ctx.last_modal
-            && !self.is_subtree_focused(&node.borrow())
+            && !Tree::iterate_siblings(Some(node)).any(|n| self.is_subtree_focused(&n.borrow()))
         {
             ctx.steal_focus_for(node);
         }
 
+        // Focus requests may be issued after the requested node was already drawn,
+        // so they're given one more frame before they're dropped.
+        if self.focus_request.is_some() {
+            if self.focus_request_stale {
+                self.focus_request = None;
+            }
+            self.focus_request_stale = !self.focus_request_stale;
+        }
+
         // If nodes have appeared or disappeared, we need to re-render.
         // Same, if the focus has changed (= changes the highlight color, etc.).
         let mut needs_settling = ctx.needs_settling;
@@ -747,6 +1384,19 @@ impl Tui {
             self.prev_node_map = NodeMap::new(mem::transmute(&self.arena_next), &self.prev_tree);
         }
 
+        // Unwind the focus of modals that have been closed, topmost first.
+        let mut restore_focus = None;
+        while let Some((id, _)) = self.modal_focus_stack.last()
+            && self.prev_node_map.get(*id).is_none()
+        {
+            restore_focus = self.modal_focus_stack.pop().map(|(_, path)| path);
+        }
+        self.modal_focus_stack.retain(|(id, _)| self.prev_node_map.get(*id).is_some());
+        if let Some(path) = restore_focus {
+            self.focused_node_path = path;
+            needs_settling = true;
+        }
+
         let mut focus_path_pop_min = 0;
         // If the user pressed Escape, we move the focus to a parent node.
         if !ctx.input_consumed && ctx.consume_shortcut(vk::ESCAPE) {
@@ -769,6 +1419,9 @@ impl Tui {
         // Remove cached text editors that are no longer in use.
         self.cached_text_buffers.retain(|c| c.seen);
 
+        // Same for timers. Unlike text editors, they must be seen again in each frame.
+        self.timers.retain_mut(|t| mem::take(&mut t.seen));
+
         for root in Tree::iterate_siblings(Some(self.prev_tree.root_first)) {
             let mut root = root.borrow_mut();
             root.compute_intrinsic_size();
@@ -784,15 +1437,28 @@ impl Tui {
                 let mut x = 0;
                 let mut y = 0;
 
-                if let Some(node) = root.parent {
-                    let node = node.borrow();
-                    x = node.outer.left;
-                    y = node.outer.top;
-                }
-
                 let size = root.intrinsic_to_outer();
 
-                x += (float.offset_x - float.gravity_x * size.width as f32) as CoordType;
+                match root.parent {
+                    // Popups attached to a widget open towards the left in RTL layouts.
+                    // Root floats (modals, context menus, etc.) are positioned in screen-space.
+                    Some(node) if self.rtl => {
+                        let node = node.borrow();
+                        x = node.outer.right
+                            - (float.offset_x + (1.0 - float.gravity_x) * size.width as f32)
+                                as CoordType;
+                        y = node.outer.top;
+                    }
+                    Some(node) => {
+                        let node = node.borrow();
+                        x = node.outer.left;
+                        y = node.outer.top;
+                        x += (float.offset_x - float.gravity_x * size.width as f32) as CoordType;
+                    }
+                    None => {
+                        x += (float.offset_x - float.gravity_x * size.width as f32) as CoordType;
+                    }
+                }
                 y += (float.offset_y - float.gravity_y * size.height as f32) as CoordType;
 
                 root.outer.left = x;
@@ -809,7 +1475,7 @@ impl Tui {
             root.inner_clipped = root.inner;
 
             let outer = root.outer;
-            root.layout_children(outer);
+            root.layout_children(outer, self.rtl);
         }
     }
 
@@ -852,11 +1518,169 @@ impl Tui {
     /// Renders the last frame into the framebuffer and returns the VT output.
     pub fn render<'a>(&mut self, arena: &'a Arena) -> ArenaString<'a> {
         self.framebuffer.flip(self.size);
+        self.buffer_render_time = time::Duration::ZERO;
+
+        // Only the topmost modal dims what's below it, so that stacked modals don't compound it.
+        let topmost_modal = self
+            .prev_tree
+            .iterate_roots_rev()
+            .find(|root| matches!(root.borrow().content, NodeContent::Modal(_)));
+
         for child in self.prev_tree.iterate_roots() {
+            if opt_ptr_eq(Some(child), topmost_modal) {
+                let rect =
+                    Rect { left: 0, top: 0, right: self.size.width, bottom: self.size.height };
+                let dim = self.indexed_alpha(IndexedColor::Background, 1, 2);
+                self.framebuffer.blend_bg(rect, dim);
+                self.framebuffer.blend_fg(rect, dim);
+            }
+
             let mut child = child.borrow_mut();
             self.render_node(&mut child);
         }
-        self.framebuffer.render(arena)
+
+        let mut output = self.framebuffer.render(arena);
+        if let Some(read) = &mut self.clipboard_read
+            && !read.sent
+        {
+            let beg = output.len();
+            output.push_str("\x1b]52;c;?\x1b\\");
+            vt::wrap_passthrough(&mut output, beg, self.capabilities.multiplexer);
+            read.sent = true;
+        }
+        output
+    }
+
+    /// Pastes (Ctrl+V, Ctrl+Shift+V, Ctrl+Alt+V, Shift+Insert) first query the terminal's clipboard
+    /// via OSC 52, if the terminal is known to support it (see [`Capabilities::osc52_clipboard`])
+    /// and unless the OS clipboard is accessible directly (see [`Tui::setup_native_clipboard()`]),
+    /// so that text copied in other applications can be pasted. The shortcut is held back
+    /// until the response arrives and then replayed, with the internal clipboard updated.
+    /// Any input that arrives in the meantime is queued and replayed after it, in order.
+    ///
+    /// If the terminal doesn't respond in time, we paste the internal clipboard
+    /// and don't ask again. If the terminal declines the request (empty response),
+    /// we fall back to the internal clipboard just for that paste.
+    fn filter_clipboard_read<'input>(
+        &mut self,
+        input: Option<Input<'input>>,
+        now: time::Instant,
+    ) -> Option<Input<'input>> {
+        let is_paste = |key| {
+            key == kbmod::CTRL | vk::V
+                || key == kbmod::CTRL_SHIFT | vk::V
+                || key == kbmod::CTRL_ALT | vk::V
+                || key == kbmod::SHIFT | vk::INSERT
+        };
+
+        if let Some(read) = &self.clipboard_read {
+            let key = read.key;
+            match input {
+                Some(Input::HostClipboard(data)) => {
+                    self.clipboard_read_support = ClipboardReadSupport::Supported;
+                    // If it's what we put there ourselves, keep the internal one,
+                    // as it knows whether it's a line copy. See `Clipboard::is_line_copy()`.
+                    if !data.is_empty() && data != self.clipboard.read() {
+                        self.clipboard.write(data);
+                        self.clipboard.mark_as_synchronized();
+                    }
+                }
+                _ if now >= read.deadline => {
+                    self.clipboard_read_support = ClipboardReadSupport::Unsupported;
+                    self.queue_input(input);
+                }
+                _ => {
+                    self.queue_input(input);
+                    return None;
+                }
+            }
+
+            self.clipboard_read = None;
+            if !self.clipboard_queue.is_empty() {
+                self.wakeup = Some(now);
+            }
+            return Some(Input::Keyboard(key));
+        }
+
+        // Until the queue is drained, new input goes to its end.
+        if !self.clipboard_queue.is_empty() {
+            self.queue_input(input);
+            let next = self.dequeue_input();
+            if !self.clipboard_queue.is_empty() {
+                self.wakeup = Some(now);
+            }
+            return next;
+        }
+
+        match input {
+            // The OS clipboard can be read synchronously, without holding back the shortcut.
+            Some(Input::Keyboard(key)) if is_paste(key) && self.native_clipboard => {
+                if let Ok(data) = sys::clipboard_read()
+                    && !data.is_empty()
+                    && data != self.clipboard.read()
+                {
+                    self.clipboard.write(data);
+                    self.clipboard.mark_as_synchronized();
+                }
+                input
+            }
+            Some(Input::Keyboard(key))
+                if is_paste(key)
+                    && self.capabilities.osc52_clipboard
+                    && self.clipboard_read_support != ClipboardReadSupport::Unsupported =>
+            {
+                let deadline = now + time::Duration::from_millis(500);
+                self.clipboard_read = Some(ClipboardRead { key, deadline, sent: false });
+                self.wakeup = Some(self.wakeup.map_or(deadline, |w| w.min(deadline)));
+                None
+            }
+            // A response that arrived after we gave up waiting for it.
+            Some(Input::HostClipboard(_)) => None,
+            _ => input,
+        }
+    }
+
+    /// Appends `input` to the queue of [`Tui::filter_clipboard_read()`].
+    /// Text is borrowed from the input buffer, so it's queued as individual characters,
+    /// while pastes are copied, so that they're still pasted in one go.
+    fn queue_input(&mut self, input: Option<Input>) {
+        let queue = &mut self.clipboard_queue;
+        let input = match input {
+            Some(Input::Text(text)) => {
+                queue.extend(text.chars().map(|ch| QueuedInput::Input(Input::Char(ch))));
+                return;
+            }
+            Some(Input::Paste(InputPaste { text, first, last })) => {
+                queue.push_back(QueuedInput::Paste { text: text.to_string(), first, last });
+                return;
+            }
+            Some(Input::Resize(size)) => Input::Resize(size),
+            Some(Input::Char(ch)) => Input::Char(ch),
+            Some(Input::Keyboard(key)) => Input::Keyboard(key),
+            Some(Input::KeyRelease(key)) => Input::KeyRelease(key),
+            Some(Input::Mouse(mouse)) => Input::Mouse(mouse),
+            Some(Input::HostClipboard(_)) | None => return,
+        };
+        queue.push_back(QueuedInput::Input(input));
+    }
+
+    /// Pops the next input off the queue of [`Tui::filter_clipboard_read()`].
+    fn dequeue_input<'input>(&mut self) -> Option<Input<'input>> {
+        match self.clipboard_queue.pop_front()? {
+            QueuedInput::Input(input) => Some(input),
+            QueuedInput::Paste { text, first, last } => {
+                self.clipboard_replay = text;
+                // SAFETY: `clipboard_replay` is only replaced by the next call to this function.
+                // That requires a `&mut self`, which the [`Context`] holding onto the text borrows.
+                let text = unsafe { mem::transmute::<&str, &'input str>(&self.clipboard_replay) };
+                Some(Input::Paste(InputPaste { text, first, last }))
+            }
+        }
+    }
+
+    /// Returns the contents of the frame last returned by [`Tui::render`]. See [`Framebuffer::capture`].
+    pub fn capture<'a>(&self, arena: &'a Arena, format: CaptureFormat) -> ArenaString<'a> {
+        self.framebuffer.capture(arena, format)
     }
 
     /// Recursively renders each node and its children.
@@ -932,14 +1756,7 @@ impl Tui {
             }
 
             self.framebuffer.replace_attr(outer_clipped, Attributes::All, Attributes::None);
-
-            if matches!(node.content, NodeContent::Modal(_)) {
-                let rect =
-                    Rect { left: 0, top: 0, right: self.size.width, bottom: self.size.height };
-                let dim = self.indexed_alpha(IndexedColor::Background, 1, 2);
-                self.framebuffer.blend_bg(rect, dim);
-                self.framebuffer.blend_fg(rect, dim);
-            }
+            self.framebuffer.set_hyperlink(outer_clipped, "", "");
         }
 
         self.framebuffer.blend_bg(outer_clipped, node.attributes.bg);
@@ -949,6 +1766,12 @@ impl Tui {
             self.framebuffer.reverse(outer_clipped);
         }
 
+        if let Some(uri) = &node.attributes.hyperlink {
+            let scratch = scratch_arena(None);
+            let id = arena_format!(&scratch, "{:x}", node.id);
+            self.framebuffer.set_hyperlink(outer_clipped, uri, &id);
+        }
+
         let inner = node.inner;
         let inner_clipped = node.inner_clipped;
         if inner_clipped.is_empty() {
@@ -987,11 +1810,17 @@ impl Tui {
                     destination.right -= 1;
                 }
 
+                let beg = time::Instant::now();
                 if let Some(res) =
                     tb.render(tc.scroll_offset, destination, tc.has_focus, &mut self.framebuffer)
                 {
                     tc.scroll_offset_x_max = res.visual_pos_x_max;
                 }
+                self.buffer_render_time += beg.elapsed();
+
+                if tc.single_line && !destination.is_empty() {
+                    self.render_editline_extras(tc, &tb, destination);
+                }
 
                 if !tc.single_line {
                     // Render the scrollbar.
@@ -1001,7 +1830,7 @@ impl Tui {
                         right: inner_clipped.right,
                         bottom: inner_clipped.bottom,
                     };
-                    tc.thumb_height = self.framebuffer.draw_scrollbar(
+                    tc.scrollbar.thumb_size = self.framebuffer.draw_scrollbar(
                         inner_clipped,
                         track,
                         tc.scroll_offset.y,
@@ -1009,6 +1838,36 @@ impl Tui {
                     );
                 }
             }
+            NodeContent::List(content) if content.is_virtual => {
+                let track = Rect {
+                    left: inner.right,
+                    top: inner.top,
+                    right: inner.right + 1,
+                    bottom: inner.bottom,
+                };
+                content.scrollbar.thumb_size = self.framebuffer.draw_scrollbar(
+                    outer_clipped,
+                    track,
+                    content.scroll as CoordType,
+                    content.count as CoordType,
+                );
+            }
+            NodeContent::Scrollbar(sb) => {
+                sb.state.thumb_size = match sb.axis {
+                    ScrollbarAxis::Vertical => self.framebuffer.draw_scrollbar(
+                        outer_clipped,
+                        node.outer,
+                        sb.offset,
+                        sb.content_size,
+                    ),
+                    ScrollbarAxis::Horizontal => self.framebuffer.draw_scrollbar_horizontal(
+                        outer_clipped,
+                        node.outer,
+                        sb.offset,
+                        sb.content_size,
+                    ),
+                };
+            }
             NodeContent::Scrollarea(sc) => {
                 let content = node.children.first.unwrap().borrow();
                 let track = Rect {
@@ -1017,13 +1876,38 @@ impl Tui {
                     right: inner.right + 1,
                     bottom: inner.bottom,
                 };
-                sc.thumb_height = self.framebuffer.draw_scrollbar(
+                sc.scrollbar.thumb_size = self.framebuffer.draw_scrollbar(
                     outer_clipped,
                     track,
                     sc.scroll_offset.y,
                     content.intrinsic_size.height,
                 );
             }
+            NodeContent::Split(split) => {
+                let divider = split.divider.intersect(outer_clipped);
+                if !divider.is_empty() {
+                    let scratch = scratch_arena(None);
+                    let mut fill = ArenaString::new_in(&scratch);
+                    match split.direction {
+                        FlexDirection::Row => fill.push('│'),
+                        FlexDirection::Column => fill.push_repeat('─', divider.width() as usize),
+                    }
+                    for y in divider.top..divider.bottom {
+                        self.framebuffer.replace_text(y, divider.left, divider.right, &fill);
+                    }
+                }
+            }
+            NodeContent::Separator => {
+                let scratch = scratch_arena(None);
+                let mut fill = ArenaString::new_in(&scratch);
+                fill.push_repeat('─', outer_clipped.width() as usize);
+                self.framebuffer.replace_text(
+                    outer_clipped.top,
+                    outer_clipped.left,
+                    outer_clipped.right,
+                    &fill,
+                );
+            }
             _ => {}
         }
 
@@ -1033,6 +1917,43 @@ impl Tui {
         }
     }
 
+    /// Draws the placeholder, password bullets and error message of an editline.
+    fn render_editline_extras(&mut self, tc: &TextareaContent, tb: &TextBuffer, destination: Rect) {
+        let y = destination.top;
+        let text_width =
+            (tc.scroll_offset_x_max - tc.scroll_offset.x).clamp(0, destination.width());
+
+        if tb.text_length() == 0 && !tc.placeholder.is_empty() {
+            self.framebuffer.replace_text(y, destination.left, destination.right, &tc.placeholder);
+            let rect = Rect { top: y, bottom: y + 1, ..destination };
+            self.framebuffer
+                .blend_fg(rect, self.framebuffer.indexed_alpha(IndexedColor::Foreground, 1, 2));
+        } else if tc.password && text_width > 0 {
+            let scratch = scratch_arena(None);
+            let mut fill = ArenaString::new_in(&scratch);
+            fill.push_repeat('•', text_width as usize);
+            self.framebuffer.replace_text(
+                y,
+                destination.left,
+                destination.left + text_width,
+                &fill,
+            );
+        }
+
+        if !tc.error.is_empty() {
+            let error_width = unicode::measure_width(&tc.error);
+            // Keep a gap of 1 column to the text.
+            if text_width + 1 + error_width <= destination.width() {
+                self.framebuffer.replace_text(
+                    y,
+                    destination.right - error_width,
+                    destination.right,
+                    &tc.error,
+                );
+            }
+        }
+    }
+
     fn render_styled_text(
         &mut self,
         target: Rect,
@@ -1324,10 +2245,18 @@ pub struct Context<'a, 'input> {
 
     /// Current text input, if any.
     input_text: Option<&'input str>,
+    /// Current text input of a single character, if any. See [`Input::Char`].
+    input_char: Option<char>,
+    /// Current chunk of a paste, if any.
+    input_paste: Option<InputPaste<'input>>,
     /// Current keyboard input, if any.
     input_keyboard: Option<InputKey>,
+    /// The two keys of the chord that was just entered, if any.
+    input_chord: Option<(InputKey, InputKey)>,
     input_mouse_modifiers: InputKeyMod,
     input_mouse_click: CoordType,
+    /// Whether a right-click requests a context menu. See [`Context::context_menu`].
+    input_context_click: bool,
     /// By how much the mouse wheel was scrolled since the last frame.
     input_scroll_delta: Point,
     input_consumed: bool,
@@ -1339,7 +2268,10 @@ pub struct Context<'a, 'input> {
     needs_settling: bool,
 
     #[cfg(debug_assertions)]
-    seen_ids: HashSet<u64>,
+    seen_ids: ArenaHashMap<'a, u64, ()>,
+    /// The [`InputKey::value()`] of every shortcut passed to [`Context::consume_shortcut()`] this frame.
+    #[cfg(debug_assertions)]
+    seen_shortcuts: ArenaHashMap<'a, u32, ()>,
 }
 
 impl<'a> Drop for Context<'a, '_> {
@@ -1400,6 +2332,22 @@ impl<'a> Context<'a, '_> {
         &mut self.tui.clipboard
     }
 
+    /// Returns the number of bytes received so far, while a paste is streamed in.
+    pub fn paste_progress(&self) -> Option<usize> {
+        self.tui.paste_progress
+    }
+
+    /// Shows a toast. See [`ToastQueue`] for showing them from other threads.
+    pub fn toast(&mut self, kind: ToastKind, text: impl Into<String>) {
+        self.tui.toast_queue.push(kind, text);
+        self.needs_rerender();
+    }
+
+    /// Switches the layout direction, starting with this frame. See [`Tui::setup_rtl()`].
+    pub fn set_rtl(&mut self, rtl: bool) {
+        self.tui.rtl = rtl;
+    }
+
     /// Tell the UI framework that your state changed and you need another layout pass.
     pub fn needs_rerender(&mut self) {
         // If this hits, the call stack is responsible is trying to deadlock you.
@@ -1407,6 +2355,49 @@ impl<'a> Context<'a, '_> {
         self.needs_settling = true;
     }
 
+    /// Requests that a frame is drawn once `deadline` has passed, even if there's no input.
+    ///
+    /// The request only applies to the next wait for input. If you're waiting for something
+    /// that's further out, you need to request it again in every frame until it's reached.
+    pub fn request_wakeup_at(&mut self, deadline: time::Instant) {
+        let wakeup = self.tui.wakeup.get_or_insert(deadline);
+        *wakeup = (*wakeup).min(deadline);
+    }
+
+    /// Like [`Context::request_wakeup_at()`], but relative to now.
+    pub fn request_wakeup(&mut self, after: time::Duration) {
+        self.request_wakeup_at(time::Instant::now() + after);
+    }
+
+    /// A timer that fires every `interval`, returning true once each time it does.
+    /// It's identified by the `classname` within the current node and keeps
+    /// running for as long as it's called each frame. Otherwise, it's dropped.
+    ///
+    /// If the interval elapsed multiple times since the last call, it only fires once.
+    pub fn timer(&mut self, classname: &'static str, interval: time::Duration) -> bool {
+        let id = hash_str(self.tree.current_node.borrow().id, classname);
+        let now = time::Instant::now();
+
+        let timers = &mut self.tui.timers;
+        let timer = match timers.iter_mut().position(|t| t.id == id) {
+            Some(i) => &mut timers[i],
+            None => {
+                timers.push(Timer { id, deadline: now + interval, seen: false });
+                timers.last_mut().unwrap()
+            }
+        };
+
+        let fired = now >= timer.deadline;
+        if fired {
+            timer.deadline = now + interval;
+        }
+        timer.seen = true;
+
+        let deadline = timer.deadline;
+        self.request_wakeup_at(deadline);
+        fired
+    }
+
     /// Begins a generic UI block (container) with a unique ID derived from the given `classname`.
     pub fn block_begin(&mut self, classname: &'static str) {
         let parent = self.tree.current_node;
@@ -1419,9 +2410,8 @@ impl<'a> Context<'a, '_> {
 
         // If this hits, you have tried to create a block with the same ID as a previous one
         // somewhere up this call stack. Change the classname, or use next_block_id_mixin().
-        // TODO: HashMap
         #[cfg(debug_assertions)]
-        if !self.seen_ids.insert(id) {
+        if self.seen_ids.insert(id, ()).is_some() {
             panic!("Duplicate node ID: {id:x}");
         }
 
@@ -1433,6 +2423,11 @@ impl<'a> Context<'a, '_> {
         }
 
         self.tree.push_child(node);
+
+        if self.tui.focus_request == Some(classname) {
+            self.tui.focus_request = None;
+            self.steal_focus_for(node);
+        }
     }
 
     /// Ends the current UI block, returning to its parent container.
@@ -1480,7 +2475,6 @@ impl<'a> Context<'a, '_> {
 
         let forward = input == vk::TAB;
         let mut focused_start = focused;
-        let mut focused_next = focused;
 
         // We may be in a focus void right now (= doesn't want to be tabbed into),
         // so first we must go up the tree until we're outside of it.
@@ -1498,21 +2492,34 @@ impl<'a> Context<'a, '_> {
             }
         }
 
-        Tree::visit_all(focus_well, focused_start, forward, |node| {
+        // Collect the candidates in document order, so that they can be sorted by their tab index.
+        let mut candidates = Vec::new_in(self.arena());
+        let mut keys = Vec::new_in(self.arena());
+        let mut start_key = None;
+        let mut position = 0;
+        Tree::visit_all(focus_well, focus_well, true, |node| {
             let n = node.borrow();
+            // Wrapping turns an index of 0 into u16::MAX, which sorts it last.
+            let key = (n.attributes.tab_index.wrapping_sub(1), position);
+            position += 1;
+            if ptr::eq(node, focused_start) {
+                start_key = Some(key);
+            }
             if n.attributes.focusable && !ptr::eq(node, focused_start) {
-                focused_next = node;
-                VisitControl::Stop
-            } else if n.attributes.focus_void {
+                candidates.push(node);
+                keys.push(key);
+            }
+            if n.attributes.focus_void {
                 VisitControl::SkipChildren
             } else {
                 VisitControl::Continue
             }
         });
 
-        if ptr::eq(focused_next, focused_start) {
+        let Some(idx) = tab_order_next(&keys, start_key, forward) else {
             return;
-        }
+        };
+        let focused_next = candidates[idx];
 
         Tui::build_node_path(Some(focused_next), &mut self.tui.focused_node_path);
         self.set_input_consumed();
@@ -1530,6 +2537,30 @@ impl<'a> Context<'a, '_> {
         last_node.attributes.focusable = true;
     }
 
+    /// Sets the position of the current node in the Tab order of its focus well.
+    /// Nodes with a non-zero index come first in ascending order,
+    /// followed by the ones with an index of 0 in the order they were drawn.
+    pub fn attr_tab_index(&mut self, index: u16) {
+        let mut last_node = self.tree.last_node.borrow_mut();
+        last_node.attributes.tab_index = index;
+    }
+
+    /// Sets whether a left click on the current node moves the focus to it.
+    /// It's enabled by default. If disabled, it can still be reached via Tab.
+    pub fn attr_focus_on_click(&mut self, enabled: bool) {
+        let mut last_node = self.tree.last_node.borrow_mut();
+        last_node.attributes.no_focus_on_click = !enabled;
+    }
+
+    /// Requests that the next node with the given classname receives the focus.
+    /// The request remains pending until the end of the next frame,
+    /// which allows it to be issued before or after the node is drawn.
+    pub fn request_focus(&mut self, classname: &'static str) {
+        self.tui.focus_request = Some(classname);
+        self.tui.focus_request_stale = false;
+        self.needs_rerender();
+    }
+
     /// If this is the first time the current node is being drawn,
     /// it'll steal the active focus.
     pub fn focus_on_first_present(&mut self) {
@@ -1585,6 +2616,7 @@ impl<'a> Context<'a, '_> {
 
     /// Causes keyboard focus to be unable to escape this node and its children.
     /// It's a "well" because if the focus is inside it, it can't escape.
+    /// It also serves as the scope for Tab traversal and [`Context::attr_tab_index()`].
     pub fn attr_focus_well(&mut self) {
         let mut last_node = self.tree.last_node.borrow_mut();
         last_node.attributes.focus_well = true;
@@ -1624,8 +2656,23 @@ impl<'a> Context<'a, '_> {
             offset_x: spec.offset_x,
             offset_y: spec.offset_y,
         });
-        ln.attributes.bg = self.tui.floater_default_bg;
-        ln.attributes.fg = self.tui.floater_default_fg;
+        if let Some(colors) = self.tui.theme_colors(ln.classname, ThemeRole::Floater) {
+            ln.attributes.bg = colors.bg;
+            ln.attributes.fg = colors.fg;
+        }
+    }
+
+    /// Colors the current node according to the given theme `role`,
+    /// taking overrides for its classname into account.
+    pub fn attr_theme(&mut self, role: ThemeRole) {
+        let mut last_node = self.tree.last_node.borrow_mut();
+        match self.tui.theme_colors(last_node.classname, role) {
+            Some(colors) => {
+                last_node.attributes.bg = colors.bg;
+                last_node.attributes.fg = colors.fg;
+            }
+            None => last_node.attributes.reverse = true,
+        }
     }
 
     /// Gives the current node a border.
@@ -1674,9 +2721,24 @@ impl<'a> Context<'a, '_> {
         last_node.attributes.reverse = true;
     }
 
+    /// Turns the current node into a clickable hyperlink to `uri`,
+    /// on terminals that support OSC 8.
+    pub fn attr_hyperlink(&mut self, uri: &str) {
+        let uri = ArenaString::from_str(self.arena(), uri);
+        let mut last_node = self.tree.last_node.borrow_mut();
+        last_node.attributes.hyperlink = Some(uri);
+    }
+
     /// Checks if the current keyboard input matches the given shortcut,
     /// consumes it if it is and returns true in that case.
     pub fn consume_shortcut(&mut self, shortcut: InputKey) -> bool {
+        debug_assert!(
+            !self.tui.chord_prefixes.contains(&shortcut),
+            "the shortcut is shadowed by a chord starting with the same key"
+        );
+        #[cfg(debug_assertions)]
+        self.seen_shortcuts.insert(shortcut.value(), ());
+
         if !self.input_consumed && self.input_keyboard == Some(shortcut) {
             self.set_input_consumed();
             true
@@ -1685,6 +2747,35 @@ impl<'a> Context<'a, '_> {
         }
     }
 
+    /// Returns `true` if the two-key chord `prefix`, `key` was entered, e.g. Ctrl+K Ctrl+C.
+    ///
+    /// Once this was called for a `prefix`, pressing it doesn't produce keyboard input anymore,
+    /// but waits for the second key instead, for up to [`CHORD_TIMEOUT`]. This shadows any
+    /// single-key shortcut for `prefix`, which is why [`Context::consume_shortcut()`]
+    /// asserts against it in debug builds.
+    pub fn consume_chord(&mut self, prefix: InputKey, key: InputKey) -> bool {
+        // The same check as in `consume_shortcut()`, for shortcuts that were consumed earlier in the frame.
+        #[cfg(debug_assertions)]
+        debug_assert!(
+            !self.seen_shortcuts.contains_key(&prefix.value()),
+            "the shortcut is shadowed by a chord starting with the same key"
+        );
+        if !self.tui.chord_prefixes.contains(&prefix) {
+            self.tui.chord_prefixes.push(prefix);
+        }
+        if !self.input_consumed && self.input_chord == Some((prefix, key)) {
+            self.set_input_consumed();
+            true
+        } else {
+            false
+        }
+    }
+
+    /// Returns the first key of a chord, while its second key is awaited.
+    pub fn chord_pending(&self) -> Option<InputKey> {
+        self.tui.chord_pending.map(|(prefix, _)| prefix)
+    }
+
     /// Returns current keyboard input, if any.
     /// Returns None if the input was already consumed.
     pub fn keyboard_input(&self) -> Option<InputKey> {
@@ -1737,9 +2828,15 @@ impl<'a> Context<'a, '_> {
             offset_y: self.tui.size.height as f32 * 0.5,
         });
         self.attr_border();
-        self.attr_background_rgba(self.tui.modal_default_bg);
-        self.attr_foreground_rgba(self.tui.modal_default_fg);
+        self.attr_theme(ThemeRole::Modal);
         self.attr_focus_well();
+
+        let id = self.tree.last_node.borrow().id;
+        if self.tui.prev_node_map.get(id).is_none()
+            && !self.tui.modal_focus_stack.iter().any(|&(i, _)| i == id)
+        {
+            self.tui.modal_focus_stack.push((id, self.tui.focused_node_path.clone()));
+        }
         self.focus_on_first_present();
 
         let mut last_node = self.tree.last_node.borrow_mut();
@@ -1831,7 +2928,125 @@ impl<'a> Context<'a, '_> {
     }
 
     fn table_end_row(&mut self) {
-        self.table_move_focus(vk::LEFT, vk::RIGHT);
+        if self.tui.rtl {
+            self.table_move_focus(vk::RIGHT, vk::LEFT);
+        } else {
+            self.table_move_focus(vk::LEFT, vk::RIGHT);
+        }
+    }
+
+    /// Begins a flex container, which lays out its children along a single axis,
+    /// similar to CSS flexbox. Use [`Context::attr_flex`] on the children to control their size.
+    pub fn flex_begin(&mut self, classname: &'static str, spec: FlexSpec) {
+        self.block_begin(classname);
+        self.tree.last_node.borrow_mut().content =
+            NodeContent::Flex(FlexSpec { gap: spec.gap.max(0), ..spec });
+    }
+
+    /// Ends the current flex container.
+    pub fn flex_end(&mut self) {
+        self.block_end();
+    }
+
+    /// Controls how the current node is sized inside its flex container.
+    pub fn attr_flex(&mut self, item: FlexItem) {
+        let mut last_node = self.tree.last_node.borrow_mut();
+        let min = item.min.max(0);
+        last_node.attributes.flex = FlexItem { min, max: item.max.max(min), ..item };
+    }
+
+    /// Begins a container that is split into two resizable panes, with a divider between them.
+    /// The first pane is started right away, the second one via [`Context::split_next`].
+    ///
+    /// The divider can be dragged with the mouse, or moved with Ctrl+Alt+Arrow keys
+    /// while the focus is inside the container. Splits can be nested inside a pane.
+    pub fn split_begin(&mut self, classname: &'static str, spec: SplitSpec) {
+        self.block_begin(classname);
+
+        let container_node = self.tree.last_node;
+        {
+            let mut container = container_node.borrow_mut();
+            let mut content = SplitContent {
+                direction: spec.direction,
+                min: spec.min.max(0),
+                position: spec.position,
+                drag_start: None,
+                divider: Rect::default(),
+            };
+            if let Some(prev) = self.tui.prev_node_map.get(container.id)
+                && let NodeContent::Split(prev) = &prev.borrow().content
+            {
+                content.position = prev.position;
+                content.drag_start = prev.drag_start;
+                content.divider = prev.divider;
+            }
+            container.content = NodeContent::Split(content);
+        }
+
+        self.block_begin("first");
+
+        // Ensure that attribute modifications apply to the outer container.
+        self.tree.last_node = container_node;
+    }
+
+    /// Ends the first pane of the current split container and begins the second one.
+    pub fn split_next(&mut self) {
+        self.block_end();
+        self.block_begin("second");
+    }
+
+    /// Ends the current split container.
+    pub fn split_end(&mut self) {
+        self.block_end(); // second pane
+        self.block_end(); // outer container
+
+        let mut container = self.tree.last_node.borrow_mut();
+        let focused = self.tui.is_subtree_focused(&container);
+        let NodeContent::Split(split) = &mut container.content else {
+            unreachable!();
+        };
+
+        if !self.tui.mouse_is_drag {
+            split.drag_start = None;
+        }
+        if self.input_consumed {
+            return;
+        }
+
+        let position = split.position.unwrap_or(0);
+        if self.tui.mouse_state == InputMouseState::Left
+            && self.tui.mouse_is_drag
+            // The divider moves along while it's being dragged.
+            && (split.drag_start.is_some() || split.divider.contains(self.tui.mouse_down_position))
+        {
+            let start = *split.drag_start.get_or_insert(position);
+            let mut dx = self.tui.mouse_position.x - self.tui.mouse_down_position.x;
+            if self.tui.rtl {
+                // The first pane is on the right, so dragging to the right shrinks it.
+                dx = -dx;
+            }
+            let (delta, _) = split.direction.split(Size {
+                width: dx,
+                height: self.tui.mouse_position.y - self.tui.mouse_down_position.y,
+            });
+            split.position = Some(start + delta);
+            self.set_input_consumed();
+        } else if focused && let Some(key) = self.input_keyboard {
+            let (shrink, grow) = match split.direction {
+                FlexDirection::Row if self.tui.rtl => {
+                    (kbmod::CTRL_ALT | vk::RIGHT, kbmod::CTRL_ALT | vk::LEFT)
+                }
+                FlexDirection::Row => (kbmod::CTRL_ALT | vk::LEFT, kbmod::CTRL_ALT | vk::RIGHT),
+                FlexDirection::Column => (kbmod::CTRL_ALT | vk::UP, kbmod::CTRL_ALT | vk::DOWN),
+            };
+            let delta = match key {
+                k if k == shrink => -1,
+                k if k == grow => 1,
+                _ => return,
+            };
+            split.position = Some(position + delta);
+            self.set_input_consumed();
+        }
     }
 
     /// Ends the current table block.
@@ -2014,7 +3229,7 @@ impl<'a> Context<'a, '_> {
         self.button_label(classname, text, style);
         self.attr_focusable();
         if self.is_focused() {
-            self.attr_reverse();
+            self.attr_theme(ThemeRole::FocusedButton);
         }
         self.button_activated()
     }
@@ -2025,7 +3240,7 @@ impl<'a> Context<'a, '_> {
         self.styled_label_begin(classname);
         self.attr_focusable();
         if self.is_focused() {
-            self.attr_reverse();
+            self.attr_theme(ThemeRole::FocusedButton);
         }
         self.styled_label_add_text(if *checked { "[🗹 " } else { "[☐ " });
         self.styled_label_add_text(text);
@@ -2056,15 +3271,38 @@ impl<'a> Context<'a, '_> {
     /// Creates a text input field.
     /// Returns true if the text contents changed.
     pub fn editline(&mut self, classname: &'static str, text: &mut dyn WriteableDocument) -> bool {
-        self.textarea_internal(classname, TextBufferPayload::Editline(text))
+        self.textarea_internal(classname, TextBufferPayload::Editline(text), &Default::default())
+    }
+
+    /// Like [`Context::editline`], but with a placeholder, input filter, etc.
+    /// Returns true if the text contents changed.
+    pub fn editline_with_spec(
+        &mut self,
+        classname: &'static str,
+        text: &mut dyn WriteableDocument,
+        spec: &EditlineSpec,
+    ) -> bool {
+        self.textarea_internal(classname, TextBufferPayload::Editline(text), spec)
+    }
+
+    /// Creates a multi-line text input field, e.g. for commit messages.
+    /// Unlike [`Context::textarea`], it's backed by the given string.
+    /// Returns true if the text contents changed.
+    pub fn editbox(&mut self, classname: &'static str, text: &mut dyn WriteableDocument) -> bool {
+        self.textarea_internal(classname, TextBufferPayload::Editbox(text), &Default::default())
     }
 
     /// Creates a text area.
     pub fn textarea(&mut self, classname: &'static str, tb: RcTextBuffer) {
-        self.textarea_internal(classname, TextBufferPayload::Textarea(tb));
+        self.textarea_internal(classname, TextBufferPayload::Textarea(tb), &Default::default());
     }
 
-    fn textarea_internal(&mut self, classname: &'static str, payload: TextBufferPayload) -> bool {
+    fn textarea_internal(
+        &mut self,
+        classname: &'static str,
+        payload: TextBufferPayload,
+        spec: &EditlineSpec,
+    ) -> bool {
         self.block_begin(classname);
         self.block_end();
 
@@ -2072,7 +3310,7 @@ impl<'a> Context<'a, '_> {
         let node = &mut *node;
         let single_line = match &payload {
             TextBufferPayload::Editline(_) => true,
-            TextBufferPayload::Textarea(_) => false,
+            TextBufferPayload::Editbox(_) | TextBufferPayload::Textarea(_) => false,
         };
 
         let buffer = {
@@ -2092,6 +3330,11 @@ impl<'a> Context<'a, '_> {
                         node_id: node.id,
                         editor: match &payload {
                             TextBufferPayload::Editline(_) => TextBuffer::new_rc(true).unwrap(),
+                            TextBufferPayload::Editbox(_) => {
+                                let tb = TextBuffer::new_rc(true).unwrap();
+                                tb.borrow_mut().set_word_wrap(true);
+                                tb
+                            }
                             TextBufferPayload::Textarea(tb) => tb.clone(),
                         },
                         seen: true,
@@ -2109,12 +3352,15 @@ impl<'a> Context<'a, '_> {
         node.content = NodeContent::Textarea(TextareaContent {
             buffer,
             scroll_offset: Default::default(),
-            scroll_offset_y_drag_start: CoordType::MIN,
             scroll_offset_x_max: 0,
-            thumb_height: 0,
+            scrollbar: Default::default(),
             preferred_column: 0,
+            preferred_column_pos: Point::MIN,
             single_line,
             has_focus: self.tui.is_node_focused(node.id),
+            placeholder: ArenaString::from_str(self.arena(), spec.placeholder),
+            error: ArenaString::from_str(self.arena(), spec.error.unwrap_or_default()),
+            password: spec.password,
         });
 
         let content = match node.content {
@@ -2122,18 +3368,28 @@ impl<'a> Context<'a, '_> {
             _ => unreachable!(),
         };
 
-        if let TextBufferPayload::Editline(text) = &payload {
-            content.buffer.borrow_mut().copy_from_str(*text);
+        match &payload {
+            TextBufferPayload::Editline(text) => content.buffer.borrow_mut().copy_from_str(*text),
+            TextBufferPayload::Editbox(text) => {
+                content.buffer.borrow_mut().copy_from_str_multiline(*text)
+            }
+            TextBufferPayload::Textarea(_) => {}
         }
 
+        // Needed to undo edits that don't pass the input filter.
+        let (length_before, cursor_before) = {
+            let tb = content.buffer.borrow();
+            (tb.text_length(), tb.cursor_logical_pos())
+        };
+
         if let Some(node_prev) = self.tui.prev_node_map.get(node.id) {
             let node_prev = node_prev.borrow();
             if let NodeContent::Textarea(content_prev) = &node_prev.content {
                 content.scroll_offset = content_prev.scroll_offset;
-                content.scroll_offset_y_drag_start = content_prev.scroll_offset_y_drag_start;
                 content.scroll_offset_x_max = content_prev.scroll_offset_x_max;
-                content.thumb_height = content_prev.thumb_height;
+                content.scrollbar = content_prev.scrollbar;
                 content.preferred_column = content_prev.preferred_column;
+                content.preferred_column_pos = content_prev.preferred_column_pos;
 
                 let mut text_width = node_prev.inner.width();
                 if !single_line {
@@ -2158,11 +3414,27 @@ impl<'a> Context<'a, '_> {
             }
         }
 
-        let dirty;
+        let mut dirty;
         {
             let mut tb = content.buffer.borrow_mut();
             dirty = tb.is_dirty();
-            if dirty && let TextBufferPayload::Editline(text) = payload {
+            if dirty
+                && let TextBufferPayload::Editline(text) | TextBufferPayload::Editbox(text) =
+                    payload
+            {
+                if spec.filter != InputFilter::Any {
+                    let mut edited = String::new();
+                    tb.save_as_string(&mut edited);
+                    match spec.filter.apply(&edited, tb.text_length() > length_before) {
+                        Some(Cow::Borrowed(_)) => {}
+                        Some(Cow::Owned(fixed)) => tb.copy_from_str(&fixed),
+                        None => {
+                            tb.copy_from_str(text);
+                            tb.cursor_move_to_logical(cursor_before);
+                            dirty = false;
+                        }
+                    }
+                }
                 tb.save_as_string(text);
             }
         }
@@ -2170,11 +3442,16 @@ impl<'a> Context<'a, '_> {
         self.textarea_adjust_scroll_offset(content);
 
         if single_line {
-            node.attributes.fg = self.indexed(IndexedColor::Foreground);
-            node.attributes.bg = self.indexed(IndexedColor::Background);
-            if !content.has_focus {
-                node.attributes.fg = self.contrasted(node.attributes.bg);
-                node.attributes.bg = self.indexed_alpha(IndexedColor::Background, 1, 2);
+            let role = if spec.error.is_some() {
+                ThemeRole::Error
+            } else if content.has_focus {
+                ThemeRole::Input
+            } else {
+                ThemeRole::InputInactive
+            };
+            if let Some(colors) = self.tui.theme_colors(node.classname, role) {
+                node.attributes.bg = colors.bg;
+                node.attributes.fg = colors.fg;
             }
         }
 
@@ -2267,7 +3544,7 @@ impl<'a> Context<'a, '_> {
                         tc.scroll_offset.y += delta_y;
 
                         if delta_x != 0 || delta_y != 0 {
-                            self.tui.read_timeout = time::Duration::from_millis(25);
+                            self.request_wakeup(time::Duration::from_millis(25));
                         }
                     }
                 } else {
@@ -2291,26 +3568,16 @@ impl<'a> Context<'a, '_> {
                         },
                     }
                 }
-            } else if track_rect.contains(self.tui.mouse_down_position) {
-                if self.tui.mouse_state == InputMouseState::Release {
-                    tc.scroll_offset_y_drag_start = CoordType::MIN;
-                } else if self.tui.mouse_is_drag {
-                    if tc.scroll_offset_y_drag_start == CoordType::MIN {
-                        tc.scroll_offset_y_drag_start = tc.scroll_offset.y;
-                    }
-
-                    // The textarea supports 1 height worth of "scrolling beyond the end".
-                    // `track_height` is the same as the viewport height.
-                    let scrollable_height = tb.visual_line_count() - 1;
-
-                    if scrollable_height > 0 {
-                        let trackable = track_rect.height() - tc.thumb_height;
-                        let delta_y = mouse.y - self.tui.mouse_down_position.y;
-                        tc.scroll_offset.y = tc.scroll_offset_y_drag_start
-                            + (delta_y as i64 * scrollable_height as i64 / trackable as i64)
-                                as CoordType;
-                    }
-                }
+            } else if let Some(y) = self.scrollbar_handle_mouse(
+                &mut tc.scrollbar,
+                track_rect,
+                ScrollbarAxis::Vertical,
+                tc.scroll_offset.y,
+                // The textarea supports 1 height worth of "scrolling beyond the end".
+                tb.visual_line_count() - 1,
+                inner.height(),
+            ) {
+                tc.scroll_offset.y = y;
             }
 
             self.set_input_consumed();
@@ -2322,13 +3589,36 @@ impl<'a> Context<'a, '_> {
         }
 
         let mut write: &[u8] = &[];
+        let mut char_buf = [0; 4];
 
-        if let Some(input) = &self.input_text {
+        if let Some(paste) = self.input_paste {
+            tb.paste_chunk(paste.text.as_bytes(), paste.first, paste.last);
+            change_preferred_column = true;
+            make_cursor_visible = true;
+        } else if let Some(input) = &self.input_text {
             write = input.as_bytes();
+        } else if let Some(ch) = self.input_char {
+            write = ch.encode_utf8(&mut char_buf).as_bytes();
         } else if let Some(input) = &self.input_keyboard {
             let key = input.key();
             let modifiers = input.modifiers();
 
+            // The cursor may have been moved by something other than this textarea,
+            // e.g. by a search, in which case the column to return to is stale.
+            if !tb.is_goal_column_enabled() || tc.preferred_column_pos != tb.cursor_visual_pos() {
+                tc.preferred_column = tb.cursor_visual_pos().x;
+            }
+
+            // Don't let passwords leak into the clipboard.
+            if tc.password
+                && matches!(
+                    (key, modifiers),
+                    (vk::C | vk::X | vk::INSERT, kbmod::CTRL) | (vk::DELETE, kbmod::SHIFT)
+                )
+            {
+                return false;
+            }
+
             make_cursor_visible = true;
 
             match key {
@@ -2508,7 +3798,9 @@ impl<'a> Context<'a, '_> {
                     } else {
                         CursorMovement::Grapheme
                     };
-                    if modifiers.contains(kbmod::SHIFT) {
+                    if modifiers == KBMOD_FOR_EXPAND_SELECTION && !single_line {
+                        tb.selection_shrink();
+                    } else if modifiers.contains(kbmod::SHIFT) {
                         tb.selection_update_delta(granularity, -1);
                     } else if let Some((beg, _)) = tb.selection_range() {
                         unsafe { tb.set_cursor(beg) };
@@ -2570,7 +3862,9 @@ impl<'a> Context<'a, '_> {
                     } else {
                         CursorMovement::Grapheme
                     };
-                    if modifiers.contains(kbmod::SHIFT) {
+                    if modifiers == KBMOD_FOR_EXPAND_SELECTION && !single_line {
+                        tb.selection_expand();
+                    } else if modifiers.contains(kbmod::SHIFT) {
                         tb.selection_update_delta(granularity, 1);
                     } else if let Some((_, end)) = tb.selection_range() {
                         unsafe { tb.set_cursor(end) };
@@ -2683,6 +3977,8 @@ impl<'a> Context<'a, '_> {
                 },
                 vk::V => match modifiers {
                     kbmod::CTRL => tb.paste(self.clipboard_ref()),
+                    kbmod::CTRL_SHIFT => tb.paste_verbatim(self.clipboard_ref()),
+                    kbmod::CTRL_ALT => tb.paste_reindent(self.clipboard_ref()),
                     _ => return false,
                 },
                 vk::Y => match modifiers {
@@ -2716,6 +4012,7 @@ impl<'a> Context<'a, '_> {
         if change_preferred_column {
             tc.preferred_column = tb.cursor_visual_pos().x;
         }
+        tc.preferred_column_pos = tb.cursor_visual_pos();
 
         self.set_input_consumed();
         make_cursor_visible
@@ -2768,8 +4065,7 @@ impl<'a> Context<'a, '_> {
             let mut container = self.tree.last_node.borrow_mut();
             container.content = NodeContent::Scrollarea(ScrollareaContent {
                 scroll_offset: Point::MIN,
-                scroll_offset_y_drag_start: CoordType::MIN,
-                thumb_height: 0,
+                scrollbar: Default::default(),
             });
 
             if intrinsic_size.width > 0 || intrinsic_size.height > 0 {
@@ -2829,46 +4125,25 @@ impl<'a> Context<'a, '_> {
                 sc.scroll_offset.y += self.input_scroll_delta.y;
                 self.set_input_consumed();
             } else if self.tui.mouse_state != InputMouseState::None {
-                match self.tui.mouse_state {
-                    InputMouseState::Left => {
-                        if self.tui.mouse_is_drag {
-                            // We don't need to look up the previous track node,
-                            // since it has a fixed size based on the container size.
-                            let track_rect = Rect {
-                                left: container_rect.right,
-                                top: container_rect.top,
-                                right: container_rect.right + 1,
-                                bottom: container_rect.bottom,
-                            };
-                            if track_rect.contains(self.tui.mouse_down_position) {
-                                if sc.scroll_offset_y_drag_start == CoordType::MIN {
-                                    sc.scroll_offset_y_drag_start = sc.scroll_offset.y;
-                                }
-
-                                let content = prev_container.children.first.unwrap().borrow();
-                                let content_rect = content.inner;
-                                let content_height = content_rect.height();
-                                let track_height = track_rect.height();
-                                let scrollable_height = content_height - track_height;
-
-                                if scrollable_height > 0 {
-                                    let trackable = track_height - sc.thumb_height;
-                                    let delta_y =
-                                        self.tui.mouse_position.y - self.tui.mouse_down_position.y;
-                                    sc.scroll_offset.y = sc.scroll_offset_y_drag_start
-                                        + (delta_y as i64 * scrollable_height as i64
-                                            / trackable as i64)
-                                            as CoordType;
-                                }
-
-                                self.set_input_consumed();
-                            }
-                        }
-                    }
-                    InputMouseState::Release => {
-                        sc.scroll_offset_y_drag_start = CoordType::MIN;
-                    }
-                    _ => {}
+                // We don't need to look up the previous track node,
+                // since it has a fixed size based on the container size.
+                let track_rect = Rect {
+                    left: container_rect.right,
+                    top: container_rect.top,
+                    right: container_rect.right + 1,
+                    bottom: container_rect.bottom,
+                };
+                let content = prev_container.children.first.unwrap().borrow();
+                let scrollable_height = content.inner.height() - track_rect.height();
+                if let Some(y) = self.scrollbar_handle_mouse(
+                    &mut sc.scrollbar,
+                    track_rect,
+                    ScrollbarAxis::Vertical,
+                    sc.scroll_offset.y,
+                    scrollable_height,
+                    track_rect.height(),
+                ) {
+                    sc.scroll_offset.y = y;
                 }
             } else if self.tui.is_subtree_focused_alt(container_id, container_depth)
                 && let Some(key) = self.input_keyboard
@@ -2896,12 +4171,10 @@ impl<'a> Context<'a, '_> {
             .prev_node_map
             .get(last_node.id)
             .and_then(|node| match &node.borrow().content {
-                NodeContent::List(content) => {
-                    Some(ListContent { selected: content.selected, selected_node: None })
-                }
+                NodeContent::List(content) => Some(ListContent { selected_node: None, ..*content }),
                 _ => None,
             })
-            .unwrap_or(ListContent { selected: 0, selected_node: None });
+            .unwrap_or_default();
 
         last_node.attributes.focus_void = true;
         last_node.content = NodeContent::List(content);
@@ -2919,8 +4192,11 @@ impl<'a> Context<'a, '_> {
     pub fn styled_list_item_begin(&mut self) {
         let list = self.tree.current_node;
         let idx = list.borrow().child_count;
+        self.list_item_begin_with_id(idx as u64);
+    }
 
-        self.next_block_id_mixin(idx as u64);
+    fn list_item_begin_with_id(&mut self, id: u64) {
+        self.next_block_id_mixin(id);
         self.styled_label_begin("item");
         self.styled_label_add_text("  ");
         self.attr_focusable();
@@ -3086,24 +4362,674 @@ impl<'a> Context<'a, '_> {
         if !opt_ptr_eq(selected_now, Some(selected_next))
             && let NodeContent::List(content) = &mut self.tree.last_node.borrow_mut().content
         {
-            content.selected_node = Some(selected_next);
+            content.selected_node = Some(selected_next);
+        }
+
+        // Now that we know which item is selected we can mark it as such.
+        if let NodeContent::Text(content) = &mut selected_next.borrow_mut().content {
+            unsafe {
+                content.text.as_bytes_mut()[0] = b'>';
+            }
+        }
+
+        // If the list has focus, we also delegate focus to the selected item and colorize it.
+        if contains_focus {
+            {
+                let mut node = selected_next.borrow_mut();
+                if let Some(colors) = self.tui.theme_colors(node.classname, ThemeRole::Selection) {
+                    node.attributes.bg = colors.bg;
+                    node.attributes.fg = colors.fg;
+                }
+            }
+            self.steal_focus_for(selected_next);
+        }
+    }
+
+    /// Creates a progress bar, `width` columns wide. `progress` ranges from 0 to 1.
+    pub fn progress_bar(&mut self, classname: &'static str, progress: f32, width: CoordType) {
+        // U+258F to U+2589, the 1/8th to 7/8th left block element glyphs: ▏▎▍▌▋▊▉
+        const PARTIAL: [&str; 8] = ["", "▏", "▎", "▍", "▌", "▋", "▊", "▉"];
+
+        let width = width.max(0);
+        let eighths = (progress.clamp(0.0, 1.0) * (width * 8) as f32).round() as CoordType;
+        let full = eighths / 8;
+        let partial = PARTIAL[(eighths % 8) as usize];
+
+        let mut text = ArenaString::with_capacity_in(width as usize * 3, self.arena());
+        text.push_repeat('█', full as usize);
+        text.push_str(partial);
+        text.push_repeat(' ', (width - full - !partial.is_empty() as CoordType) as usize);
+
+        self.label(classname, &text);
+        self.attr_theme(ThemeRole::Track);
+    }
+
+    /// Creates a scrollbar for content that's laid out by the caller.
+    /// Its size along the `axis` must be set, e.g. via [`Context::attr_intrinsic_size`],
+    /// and is assumed to be the size of the viewport.
+    ///
+    /// `offset` is the scroll position of the content and `content_size` its size along the `axis`.
+    /// Dragging the thumb or clicking the track updates the `offset`, in which case `true` is returned.
+    pub fn scrollbar(
+        &mut self,
+        classname: &'static str,
+        axis: ScrollbarAxis,
+        offset: &mut CoordType,
+        content_size: CoordType,
+    ) -> bool {
+        self.block_begin(classname);
+
+        let mut state = ScrollbarState::default();
+        let mut changed = false;
+        if let Some(node_prev) = self.tui.prev_node_map.get(self.tree.last_node.borrow().id) {
+            let node_prev = node_prev.borrow();
+            if let NodeContent::Scrollbar(sb) = &node_prev.content {
+                state = sb.state;
+            }
+
+            let track = node_prev.outer;
+            let track_len = match axis {
+                ScrollbarAxis::Vertical => track.height(),
+                ScrollbarAxis::Horizontal => track.width(),
+            };
+            let scrollable = content_size - track_len;
+            if self.tui.mouse_state != InputMouseState::None
+                && let Some(o) = self
+                    .scrollbar_handle_mouse(&mut state, track, axis, *offset, scrollable, track_len)
+                && o != *offset
+            {
+                *offset = o;
+                changed = true;
+                self.needs_rerender();
+            }
+        }
+
+        {
+            let mut last_node = self.tree.last_node.borrow_mut();
+            match axis {
+                ScrollbarAxis::Vertical => last_node.intrinsic_size.width = 1,
+                ScrollbarAxis::Horizontal => last_node.intrinsic_size.height = 1,
+            }
+            last_node.intrinsic_size_set = true;
+            last_node.content = NodeContent::Scrollbar(ScrollbarContent {
+                axis,
+                offset: *offset,
+                content_size,
+                state,
+            });
+        }
+
+        self.block_end();
+        changed
+    }
+
+    /// Handles clicks and drags on the scrollbar `track` of the previous frame.
+    ///
+    /// Dragging moves the content proportional to the thumb, while clicking the track
+    /// next to the thumb scrolls by a `page`. `scrollable` is the maximum scroll offset.
+    /// Returns the new offset, clamped to `0..=scrollable`, if the mouse interacted with the track.
+    fn scrollbar_handle_mouse(
+        &mut self,
+        state: &mut ScrollbarState,
+        track: Rect,
+        axis: ScrollbarAxis,
+        offset: CoordType,
+        scrollable: CoordType,
+        page: CoordType,
+    ) -> Option<CoordType> {
+        if !self.tui.mouse_is_drag {
+            state.drag_start = None;
+        }
+        if self.input_consumed || !track.contains(self.tui.mouse_down_position) {
+            return None;
+        }
+        self.set_input_consumed();
+        if scrollable <= 0 {
+            return None;
+        }
+
+        let (track_start, track_len, mouse, mouse_down) = match axis {
+            ScrollbarAxis::Vertical => (
+                track.top,
+                track.height(),
+                self.tui.mouse_position.y,
+                self.tui.mouse_down_position.y,
+            ),
+            ScrollbarAxis::Horizontal => (
+                track.left,
+                track.width(),
+                self.tui.mouse_position.x,
+                self.tui.mouse_down_position.x,
+            ),
+        };
+        let trackable = (track_len - state.thumb_size).max(1) as i64;
+
+        let offset = match self.tui.mouse_state {
+            InputMouseState::Left if self.tui.mouse_is_drag => {
+                let start = *state.drag_start.get_or_insert(offset);
+                let delta = (mouse - mouse_down) as i64 * scrollable as i64 / trackable;
+                start + delta as CoordType
+            }
+            // Like buttons, clicks on the track trigger on mouse-up.
+            InputMouseState::Release if !self.tui.mouse_is_drag => {
+                let thumb_start = track_start
+                    + ((offset.clamp(0, scrollable) as i64 * trackable + scrollable as i64 / 2)
+                        / scrollable as i64) as CoordType;
+                if mouse_down < thumb_start {
+                    offset - page.max(1)
+                } else if mouse_down >= thumb_start + state.thumb_size {
+                    offset + page.max(1)
+                } else {
+                    return None;
+                }
+            }
+            _ => return None,
+        };
+        Some(offset.clamp(0, scrollable))
+    }
+
+    /// Creates a slider, `width` columns wide, for picking a value in `range` in increments of `step`.
+    /// It can be adjusted with the arrow keys, the mouse wheel, or by clicking and dragging.
+    /// Returns true if the value changed.
+    pub fn slider(
+        &mut self,
+        classname: &'static str,
+        value: &mut i64,
+        range: RangeInclusive<i64>,
+        step: i64,
+        width: CoordType,
+    ) -> bool {
+        let width = width.max(1);
+        let (min, max) = (*range.start(), *range.end());
+        let span = (max - min).max(1);
+
+        self.block_begin(classname);
+        self.attr_focusable();
+
+        let mut next = self.stepper_handle_input(*value, min, max, step);
+        if let Some(outer) = self.prev_outer()
+            && self.tui.mouse_state == InputMouseState::Left
+            && self.contains_mouse_down()
+        {
+            // Map the mouse to the closest value along the track.
+            let x = (self.tui.mouse_position.x - outer.left).clamp(0, width - 1) as i64;
+            let cols = (width - 1).max(1) as i64;
+            next = Some(min + (x * span + cols / 2) / cols);
+        }
+        let changed = self.value_update(value, next, min, max, step);
+
+        // The caller may have passed a value outside the range. The knob must stay on the track.
+        let clamped = (*value).clamp(min, max.max(min));
+        let pos = ((clamped - min) * (width - 1) as i64 + span / 2) / span;
+        let mut text = ArenaString::with_capacity_in(width as usize * 3, self.arena());
+        text.push_repeat('━', pos as usize);
+        text.push('●');
+        text.push_repeat('─', (width - 1) as usize - pos as usize);
+
+        self.label("track", &text);
+        self.block_end();
+
+        if self.is_focused() {
+            self.attr_theme(ThemeRole::FocusedButton);
+        }
+        changed
+    }
+
+    /// Creates a numeric stepper for picking a value in `range` in increments of `step`.
+    /// It's drawn as `◂ value ▸` and can be adjusted with the arrow keys, the mouse wheel,
+    /// or by clicking the arrows. Returns true if the value changed.
+    pub fn stepper(
+        &mut self,
+        classname: &'static str,
+        value: &mut i64,
+        range: RangeInclusive<i64>,
+        step: i64,
+    ) -> bool {
+        let (min, max) = (*range.start(), *range.end());
+        // Pad the value, so that the stepper doesn't change its size while stepping.
+        let digits = min.to_string().len().max(max.to_string().len());
+        // The arrows and spaces around the value.
+        let width = digits as CoordType + 4;
+
+        self.block_begin(classname);
+        self.attr_focusable();
+
+        let mut next = self.stepper_handle_input(*value, min, max, step);
+        if let Some(outer) = self.prev_outer()
+            && self.input_mouse_click != 0
+            && self.contains_mouse_down()
+        {
+            let left = self.tui.mouse_down_position.x < outer.left + width / 2;
+            next = Some(if left { *value - step } else { *value + step });
+            self.set_input_consumed();
+        }
+        let changed = self.value_update(value, next, min, max, step);
+
+        let text = arena_format!(self.arena(), "◂ {:>digits$} ▸", *value);
+        self.label("value", &text);
+        self.block_end();
+
+        if self.is_focused() {
+            self.attr_theme(ThemeRole::FocusedButton);
+        }
+        changed
+    }
+
+    /// The outer rectangle of the current node in the previous frame.
+    fn prev_outer(&self) -> Option<Rect> {
+        let id = self.tree.last_node.borrow().id;
+        self.tui.prev_node_map.get(id).map(|n| n.borrow().outer)
+    }
+
+    /// Handles the keyboard and mouse wheel input of sliders and steppers.
+    /// Returns the requested new value, which may be out of range.
+    fn stepper_handle_input(&mut self, value: i64, min: i64, max: i64, step: i64) -> Option<i64> {
+        if self.input_consumed {
+            return None;
+        }
+
+        if self.input_scroll_delta.y != 0
+            && self.prev_outer().is_some_and(|r| r.contains(self.tui.mouse_position))
+        {
+            self.set_input_consumed();
+            // Scrolling up increases the value.
+            return Some(value - self.input_scroll_delta.y as i64 * step);
+        }
+
+        if !self.is_focused() {
+            return None;
+        }
+        let next = match self.input_keyboard? {
+            vk::LEFT | vk::DOWN => value - step,
+            vk::RIGHT | vk::UP => value + step,
+            vk::PRIOR => value + 10 * step,
+            vk::NEXT => value - 10 * step,
+            vk::HOME => min,
+            vk::END => max,
+            _ => return None,
+        };
+        self.set_input_consumed();
+        Some(next)
+    }
+
+    /// Snaps `next` to the `step` grid within `min..=max` and stores it in `value`.
+    /// Returns true if the value changed.
+    fn value_update(
+        &mut self,
+        value: &mut i64,
+        next: Option<i64>,
+        min: i64,
+        max: i64,
+        step: i64,
+    ) -> bool {
+        let Some(next) = next else {
+            return false;
+        };
+        let step = step.max(1);
+        let snapped = min + ((next - min).max(0) + step / 2) / step * step;
+        let next = if next >= max { max } else { snapped.clamp(min, max) };
+        if next == *value {
+            return false;
+        }
+        *value = next;
+        self.needs_rerender();
+        true
+    }
+
+    /// Creates a spinner to indicate an operation of unknown duration.
+    /// It's animated for as long as it's drawn each frame.
+    pub fn spinner(&mut self, classname: &'static str) {
+        const FRAMES: [&str; 10] = ["⠋", "⠙", "⠹", "⠸", "⠼", "⠴", "⠦", "⠧", "⠇", "⠏"];
+        const INTERVAL: u128 = 100;
+
+        let now = time::SystemTime::now()
+            .duration_since(time::UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_millis();
+        self.label(classname, FRAMES[(now / INTERVAL % FRAMES.len() as u128) as usize]);
+
+        // Wake up for the next frame, even if there's no input.
+        self.request_wakeup(time::Duration::from_millis((INTERVAL - now % INTERVAL) as u64));
+    }
+
+    /// Draws the queued toasts (see [`ToastQueue`]), stacked upwards with
+    /// the newest one at the bottom. `corner` is the bottom-right corner of the stack.
+    ///
+    /// Each toast is dismissed when clicked or after a few seconds.
+    pub fn toasts(&mut self, classname: &'static str, corner: Point) {
+        const DURATION: time::Duration = time::Duration::from_secs(5);
+        // The text plus the border.
+        const HEIGHT: CoordType = 3;
+
+        let now = time::Instant::now();
+        for (kind, text) in self.tui.toast_queue.take() {
+            self.tui.toast_counter += 1;
+            let id = self.tui.toast_counter;
+            self.tui.toasts.push(Toast { id, kind, text, expires: now + DURATION });
+        }
+        self.tui.toasts.retain(|t| t.expires > now);
+
+        let toasts = mem::take(&mut self.tui.toasts);
+        let mut dismissed = None;
+
+        for (i, toast) in toasts.iter().rev().enumerate() {
+            let role = match toast.kind {
+                ToastKind::Info => ThemeRole::Floater,
+                ToastKind::Warning => ThemeRole::Warning,
+                ToastKind::Error => ThemeRole::Error,
+            };
+
+            self.next_block_id_mixin(toast.id);
+            self.block_begin(classname);
+            self.attr_float(FloatSpec {
+                anchor: Anchor::Root,
+                gravity_x: 1.0,
+                gravity_y: 1.0,
+                offset_x: corner.x as f32,
+                offset_y: (corner.y - i as CoordType * HEIGHT) as f32,
+            });
+            self.attr_border();
+            self.attr_padding(Rect::two(0, 1));
+            self.attr_theme(role);
+            // Focusable, so that clicks register, but without stealing the focus from the user.
+            self.attr_focusable();
+            self.attr_focus_on_click(false);
+
+            self.label("text", &toast.text);
+            self.attr_overflow(Overflow::TruncateTail);
+
+            self.block_end();
+
+            if self.input_mouse_click != 0 && self.contains_mouse_down() {
+                dismissed = Some(toast.id);
+            }
+        }
+
+        self.tui.toasts = toasts;
+        if let Some(id) = dismissed {
+            self.tui.toasts.retain(|t| t.id != id);
+            self.needs_rerender();
+        }
+        if let Some(toast) = self.tui.toasts.first() {
+            self.request_wakeup_at(toast.expires);
+        }
+    }
+
+    /// Creates a grid: a list of rows with a header row and aligned columns.
+    ///
+    /// Exactly one row is selected, like in [`Context::list_begin`].
+    /// Columns that don't fit can be scrolled horizontally with Left/Right or the mouse wheel.
+    pub fn grid_begin(&mut self, classname: &'static str, columns: &[GridColumn]) {
+        self.block_begin(classname);
+
+        {
+            let mut last_node = self.tree.last_node.borrow_mut();
+            let scroll_x = self
+                .tui
+                .prev_node_map
+                .get(last_node.id)
+                .and_then(|node| match &node.borrow().content {
+                    NodeContent::Grid(content) => Some(content.scroll_x),
+                    _ => None,
+                })
+                .unwrap_or(0);
+            let mut widths = Vec::with_capacity_in(columns.len(), self.arena());
+            widths.extend(columns.iter().map(|c| c.width.max(0)));
+            last_node.content = NodeContent::Grid(GridContent { widths, scroll_x });
+        }
+
+        self.styled_label_begin("header");
+        self.styled_label_set_attributes(Attributes::Underlined);
+        self.styled_label_add_text("  ");
+        self.grid_add_cells(columns.iter().map(|c| c.title));
+        self.styled_label_end();
+
+        self.list_begin("rows");
+    }
+
+    /// Appends a row to the current grid. There should be one cell per column.
+    /// Like labels, the cells must not contain control characters.
+    pub fn grid_row(&mut self, select: bool, cells: &[&str]) -> ListSelection {
+        self.styled_list_item_begin();
+        self.grid_add_cells(cells.iter().copied());
+        self.styled_list_item_end(select)
+    }
+
+    /// Until [`Context::grid_end`] knows the column widths, the cells are separated by US (unit separator).
+    fn grid_add_cells<'s>(&mut self, cells: impl Iterator<Item = &'s str>) {
+        for (i, cell) in cells.enumerate() {
+            if i != 0 {
+                self.styled_label_add_text("\x1f");
+            }
+            self.styled_label_add_text(cell);
+        }
+    }
+
+    /// Ends the current grid block.
+    pub fn grid_end(&mut self) {
+        self.list_end();
+
+        let grid_node = self.tree.current_node;
+        let mut grid = grid_node.borrow_mut();
+        let grid_id = grid.id;
+        let (Some(header), Some(list)) = (grid.children.first, grid.children.last) else {
+            drop(grid);
+            self.block_end();
+            return;
+        };
+        let focused = self.tui.is_subtree_focused(&grid);
+        let NodeContent::Grid(content) = &mut grid.content else {
+            unreachable!();
+        };
+
+        let rows =
+            || iter::once(header).chain(Tree::iterate_siblings(list.borrow().children.first));
+
+        // Size the auto-sized columns to fit their widest cell.
+        let mut widths = content.widths.clone();
+        for row in rows() {
+            let row = row.borrow();
+            let NodeContent::Text(text) = &row.content else {
+                continue;
+            };
+            for (column, cell) in text.text[GRID_PREFIX_WIDTH..].split('\x1f').enumerate() {
+                if column >= widths.len() {
+                    widths.push(0);
+                    content.widths.push(0);
+                }
+                if content.widths[column] == 0 {
+                    widths[column] = widths[column].max(unicode::measure_width(cell));
+                }
+            }
+        }
+
+        let total_width = widths.iter().sum::<CoordType>()
+            + GRID_CELL_GAP * widths.len().saturating_sub(1) as CoordType;
+        let prev_list = self.tui.prev_node_map.get(list.borrow().id);
+        let viewport_width = prev_list
+            .map_or(total_width, |n| n.borrow().inner.width() - GRID_PREFIX_WIDTH as CoordType);
+        let mut scroll_x = content.scroll_x;
+
+        if !self.input_consumed {
+            let grid_rect = self.tui.prev_node_map.get(grid_id).map(|n| n.borrow().outer);
+            let mut column_starts = widths.iter().scan(0, |x, &w| {
+                let start = *x;
+                *x += w + GRID_CELL_GAP;
+                Some(start)
+            });
+
+            if self.input_scroll_delta.x != 0
+                && grid_rect.is_some_and(|r| r.contains(self.tui.mouse_position))
+            {
+                scroll_x += self.input_scroll_delta.x;
+                self.set_input_consumed();
+            } else if focused && self.input_keyboard == Some(vk::LEFT) {
+                scroll_x = column_starts.take_while(|&x| x < scroll_x).last().unwrap_or(0);
+                self.set_input_consumed();
+            } else if focused && self.input_keyboard == Some(vk::RIGHT) {
+                scroll_x = column_starts.find(|&x| x > scroll_x).unwrap_or(scroll_x);
+                self.set_input_consumed();
+            }
+        }
+
+        scroll_x = scroll_x.min(total_width - viewport_width).max(0);
+        if content.scroll_x != scroll_x {
+            content.scroll_x = scroll_x;
+            self.needs_rerender();
+        }
+
+        // Now replace the raw cells with the aligned text.
+        let arena = self.arena();
+        for row in rows() {
+            let mut row = row.borrow_mut();
+            let NodeContent::Text(text) = &mut row.content else {
+                continue;
+            };
+            text.text = grid_format_row(arena, &text.text, &widths, scroll_x);
+            row.intrinsic_size.width = unicode::measure_width(&text.text);
+        }
+
+        drop(grid);
+        self.block_end();
+    }
+
+    /// Creates a list with `count` items, of which only the visible ones are laid out.
+    ///
+    /// Returns the range of items to create via [`Context::virtual_list_item`].
+    /// The list needs a fixed height, e.g. via [`Context::attr_intrinsic_size`].
+    pub fn virtual_list_begin(&mut self, classname: &'static str, count: usize) -> Range<usize> {
+        self.list_begin(classname);
+
+        let list_node = self.tree.last_node;
+        let mut list = list_node.borrow_mut();
+        list.attributes.padding.right = 1; // for the scrollbar
+        let prev_rect = self.tui.prev_node_map.get(list.id).map(|n| n.borrow().inner);
+        // On the first frame we don't know the height yet. The screen height is an upper bound.
+        let height = prev_rect.map_or(self.tui.size.height, |r| r.height()).max(1) as usize;
+        let focused = self.tui.is_subtree_focused(&list);
+        // The scrollbar is drawn into the right padding.
+        let scrollbar_track = prev_rect.map(|r| Rect {
+            left: r.right,
+            top: r.top,
+            right: r.right + 1,
+            bottom: r.bottom,
+        });
+        let NodeContent::List(content) = &mut list.content else {
+            unreachable!();
+        };
+
+        content.is_virtual = true;
+        content.count = count;
+        content.select_index = None;
+        let mut scroll = content.scroll;
+
+        if !self.input_consumed && count > 0 {
+            if self.input_scroll_delta.y != 0
+                && prev_rect.is_some_and(|r| r.contains(self.tui.mouse_position))
+            {
+                scroll = scroll.saturating_add_signed(self.input_scroll_delta.y);
+                self.set_input_consumed();
+            } else if let Some(track) = scrollbar_track
+                && track.contains(self.tui.mouse_down_position)
+            {
+                if let Some(offset) = self.scrollbar_handle_mouse(
+                    &mut content.scrollbar,
+                    track,
+                    ScrollbarAxis::Vertical,
+                    scroll as CoordType,
+                    count.saturating_sub(height) as CoordType,
+                    height as CoordType - 1,
+                ) {
+                    scroll = offset as usize;
+                }
+            } else if focused && let Some(key) = self.input_keyboard {
+                // Keyboard navigation must be handled here, because the target may not be visible yet.
+                let current = content.selected_index.min(count - 1);
+                let target = match key {
+                    vk::UP => Some(current.saturating_sub(1)),
+                    vk::DOWN => Some(current + 1),
+                    vk::PRIOR => Some(current.saturating_sub(height - 1)),
+                    vk::NEXT => Some(current + height - 1),
+                    vk::HOME => Some(0),
+                    vk::END => Some(count - 1),
+                    _ => None,
+                };
+                if let Some(target) = target {
+                    let target = target.min(count - 1);
+                    scroll = scroll.clamp((target + 1).saturating_sub(height), target);
+                    content.select_index = Some(target);
+                    self.set_input_consumed();
+                }
+            }
+        }
+
+        scroll = scroll.min(count.saturating_sub(height));
+        if content.scroll != scroll {
+            content.scroll = scroll;
+            self.needs_rerender();
+        }
+        scroll..(scroll + height).min(count)
+    }
+
+    /// Creates an item of the current virtual list.
+    ///
+    /// `key` must uniquely identify the item. This way, the selection sticks
+    /// to the same item, even if the list contents are filtered or reordered.
+    pub fn virtual_list_item(&mut self, key: u64, select: bool, text: &str) -> ListSelection {
+        let list_node = self.tree.current_node;
+        let (index, select_index) = {
+            let list = list_node.borrow();
+            let NodeContent::List(content) = &list.content else {
+                unreachable!();
+            };
+            (content.scroll + list.child_count, content.select_index)
+        };
+
+        self.list_item_begin_with_id(key);
+        self.styled_label_add_text(text);
+
+        let selection = if let Some(select_index) = select_index {
+            // The selection was moved with the keyboard. Ignore the item that has the focus.
+            self.styled_label_end();
+            let item = self.tree.last_node;
+            let mut list = list_node.borrow_mut();
+            let NodeContent::List(content) = &mut list.content else {
+                unreachable!();
+            };
+            if index == select_index {
+                content.selected = item.borrow().id;
+                content.selected_node = Some(item);
+                ListSelection::Selected
+            } else {
+                ListSelection::Unchanged
+            }
+        } else {
+            self.styled_list_item_end(select)
+        };
+
+        let mut list = list_node.borrow_mut();
+        if let NodeContent::List(content) = &mut list.content
+            && opt_ptr_eq(content.selected_node, Some(self.tree.last_node))
+        {
+            content.selected_index = index;
         }
 
-        // Now that we know which item is selected we can mark it as such.
-        if let NodeContent::Text(content) = &mut selected_next.borrow_mut().content {
-            unsafe {
-                content.text.as_bytes_mut()[0] = b'>';
-            }
-        }
+        selection
+    }
 
-        // If the list has focus, we also delegate focus to the selected item and colorize it.
-        if contains_focus {
-            {
-                let mut node = selected_next.borrow_mut();
-                node.attributes.bg = self.indexed(IndexedColor::Green);
-                node.attributes.fg = self.contrasted(self.indexed(IndexedColor::Green));
-            }
-            self.steal_focus_for(selected_next);
+    /// Ends the current virtual list.
+    pub fn virtual_list_end(&mut self) {
+        let selection_hidden = match &self.tree.current_node.borrow().content {
+            NodeContent::List(content) => content.selected != 0 && content.selected_node.is_none(),
+            _ => unreachable!(),
+        };
+
+        if selection_hidden {
+            // Unlike `list_end`, don't select the first visible item just because
+            // the selected one was scrolled out of view or filtered out.
+            self.block_end();
+        } else {
+            self.list_end();
         }
     }
 
@@ -3136,12 +5062,10 @@ impl<'a> Context<'a, '_> {
             && self.consume_shortcut(kbmod::ALT | InputKey::new(accelerator as u32));
 
         if contains_focus || keyboard_focus {
-            self.attr_background_rgba(self.tui.floater_default_bg);
-            self.attr_foreground_rgba(self.tui.floater_default_fg);
+            self.attr_theme(ThemeRole::Floater);
 
             if self.is_focused() {
-                self.attr_background_rgba(self.indexed(IndexedColor::Green));
-                self.attr_foreground_rgba(self.contrasted(self.indexed(IndexedColor::Green)));
+                self.attr_theme(ThemeRole::Selection);
             }
 
             self.next_block_id_mixin(mixin);
@@ -3194,8 +5118,7 @@ impl<'a> Context<'a, '_> {
         }
 
         if self.is_focused() {
-            self.attr_background_rgba(self.indexed(IndexedColor::Green));
-            self.attr_foreground_rgba(self.contrasted(self.indexed(IndexedColor::Green)));
+            self.attr_theme(ThemeRole::Selection);
         }
 
         let clicked =
@@ -3250,6 +5173,117 @@ impl<'a> Context<'a, '_> {
         self.table_end();
     }
 
+    /// Attaches a context menu to the current node. It opens when the node is right-clicked,
+    /// or when it contains the focus and the menu key or Shift+F10 is pressed.
+    ///
+    /// Returns the index of the activated item, if any.
+    /// Since this creates a new node, call it after setting the attributes of the current one.
+    pub fn context_menu(&mut self, classname: &'static str, items: &[MenuItem]) -> Option<usize> {
+        let owner = self.tree.last_node;
+        let owner_id = owner.borrow().id;
+        let mut opened = false;
+
+        if self.input_context_click && self.contains_mouse_down() {
+            // Only the innermost node with a context menu gets to open it.
+            self.input_context_click = false;
+            self.tui.context_menu = Some((owner_id, self.tui.mouse_position));
+            opened = true;
+        } else if !self.input_consumed
+            && self.contains_focus()
+            && matches!(self.input_keyboard, Some(vk::APPS) | Some(SHIFT_F10))
+        {
+            let position = self.tui.prev_node_map.get(owner_id).map_or(Point::default(), |n| {
+                let r = n.borrow().inner_clipped;
+                Point { x: r.left, y: r.top }
+            });
+            self.set_input_consumed();
+            self.tui.context_menu = Some((owner_id, position));
+            opened = true;
+        }
+
+        let (id, position) = self.tui.context_menu?;
+        if id != owner_id {
+            return None;
+        }
+
+        self.table_begin(classname);
+
+        // Clicking elsewhere or otherwise moving the focus out of the menu closes it.
+        // The menu isn't a root yet (see `attr_float`), which is why `contains_focus()` can't be used.
+        let menu_id = self.tree.last_node.borrow().id;
+        if !opened && !self.tui.is_subtree_focused_alt(menu_id, 0) {
+            self.table_end();
+            self.tui.context_menu = None;
+            self.needs_rerender();
+            return None;
+        }
+
+        self.attr_float(FloatSpec {
+            anchor: Anchor::Root,
+            gravity_x: 0.0,
+            gravity_y: 0.0,
+            offset_x: position.x as f32,
+            offset_y: position.y as f32,
+        });
+        self.attr_border();
+        self.attr_focusable();
+        if opened {
+            self.steal_focus();
+        }
+
+        let mut activated = None;
+        let mut first = true;
+
+        for (i, item) in items.iter().enumerate() {
+            self.table_next_row();
+
+            if item.separator {
+                self.tree.last_node.borrow_mut().content = NodeContent::Separator;
+                // The separator is drawn across the whole row,
+                // but the row needs a cell to get a height.
+                self.block_begin("separator");
+                self.attr_intrinsic_size(Size { width: 0, height: 1 });
+                self.block_end();
+                continue;
+            }
+
+            if item.enabled {
+                self.attr_focusable();
+                if first {
+                    first = false;
+                    self.inherit_focus();
+                }
+                if self.is_focused() {
+                    self.attr_theme(ThemeRole::Selection);
+                }
+                if self.button_activated()
+                    || (item.accelerator != '\0'
+                        && self.consume_shortcut(InputKey::new(item.accelerator as u32)))
+                {
+                    activated = Some(i);
+                }
+            } else {
+                self.attr_foreground_rgba(self.indexed_alpha(IndexedColor::Foreground, 1, 2));
+            }
+
+            let mut style = ButtonStyle::default().bracketed(false);
+            if item.accelerator != '\0' {
+                style = style.accelerator(item.accelerator);
+            }
+            self.button_label("item", item.text, style);
+            self.menubar_shortcut(item.shortcut);
+        }
+
+        self.table_end();
+
+        if activated.is_some() || (self.contains_focus() && self.consume_shortcut(vk::ESCAPE)) {
+            self.tui.context_menu = None;
+            self.steal_focus_for(owner);
+        }
+
+        activated
+    }
+
     /// Renders a button label with an optional accelerator character
     /// May also renders a checkbox or square brackets for inline buttons
     fn button_label(&mut self, classname: &'static str, text: &str, style: ButtonStyle) {
@@ -3311,23 +5345,7 @@ impl<'a> Context<'a, '_> {
     }
 
     fn menubar_shortcut(&mut self, shortcut: InputKey) {
-        let shortcut_letter = shortcut.value() as u8 as char;
-        if shortcut_letter.is_ascii_uppercase() {
-            let mut shortcut_text = ArenaString::new_in(self.arena());
-            if shortcut.modifiers_contains(kbmod::CTRL) {
-                shortcut_text.push_str(self.tui.modifier_translations.ctrl);
-                shortcut_text.push('+');
-            }
-            if shortcut.modifiers_contains(kbmod::ALT) {
-                shortcut_text.push_str(self.tui.modifier_translations.alt);
-                shortcut_text.push('+');
-            }
-            if shortcut.modifiers_contains(kbmod::SHIFT) {
-                shortcut_text.push_str(self.tui.modifier_translations.shift);
-                shortcut_text.push('+');
-            }
-            shortcut_text.push(shortcut_letter);
-
+        if let Some(shortcut_text) = self.shortcut_text(shortcut) {
             self.label("shortcut", &shortcut_text);
         } else {
             self.block_begin("shortcut");
@@ -3335,6 +5353,31 @@ impl<'a> Context<'a, '_> {
         }
         self.attr_padding(Rect { left: 2, top: 0, right: 2, bottom: 0 });
     }
+
+    /// Formats a shortcut like "Ctrl+Shift+S" with the translated modifier names.
+    /// Only shortcuts with a letter key are supported.
+    pub fn shortcut_text(&self, shortcut: InputKey) -> Option<ArenaString<'a>> {
+        let shortcut_letter = shortcut.key().value() as u8 as char;
+        if shortcut.key().value() > 0x7f || !shortcut_letter.is_ascii_uppercase() {
+            return None;
+        }
+
+        let mut shortcut_text = ArenaString::new_in(self.arena());
+        if shortcut.modifiers_contains(kbmod::CTRL) {
+            shortcut_text.push_str(self.tui.modifier_translations.ctrl);
+            shortcut_text.push('+');
+        }
+        if shortcut.modifiers_contains(kbmod::ALT) {
+            shortcut_text.push_str(self.tui.modifier_translations.alt);
+            shortcut_text.push('+');
+        }
+        if shortcut.modifiers_contains(kbmod::SHIFT) {
+            shortcut_text.push_str(self.tui.modifier_translations.shift);
+            shortcut_text.push('+');
+        }
+        shortcut_text.push(shortcut_letter);
+        Some(shortcut_text)
+    }
 }
 
 /// See [`Tree::visit_all`].
@@ -3342,7 +5385,6 @@ impl<'a> Context<'a, '_> {
 enum VisitControl {
     Continue,
     SkipChildren,
-    Stop,
 }
 
 /// Stores the root of the "DOM" tree of the UI.
@@ -3520,7 +5562,6 @@ impl<'a> Tree<'a> {
                         }
                     }
                     VisitControl::SkipChildren => {}
-                    VisitControl::Stop => return,
                 }
 
                 loop {
@@ -3625,24 +5666,214 @@ struct FloatAttributes {
 
 /// NOTE: Must not contain items that require drop().
 #[derive(Default)]
-struct NodeAttributes {
+struct NodeAttributes<'a> {
     float: Option<FloatAttributes>,
     position: Position,
     padding: Rect,
     bg: StraightRgba,
     fg: StraightRgba,
     reverse: bool,
+    hyperlink: Option<ArenaString<'a>>,
     bordered: bool,
     focusable: bool,
     focus_well: bool, // Prevents focus from leaving via Tab
     focus_void: bool, // Prevents focus from entering via Tab
+    tab_index: u16,
+    no_focus_on_click: bool,
+    flex: FlexItem,
+}
+
+impl FlexDirection {
+    /// Splits a size into its (main, cross) axis components.
+    fn split(self, size: Size) -> (CoordType, CoordType) {
+        match self {
+            Self::Row => (size.width, size.height),
+            Self::Column => (size.height, size.width),
+        }
+    }
+
+    /// The inverse of [`FlexDirection::split`].
+    fn join(self, main: CoordType, cross: CoordType) -> Size {
+        match self {
+            Self::Row => Size { width: main, height: cross },
+            Self::Column => Size { width: cross, height: main },
+        }
+    }
+}
+
+/// Picks the next Tab stop among `keys` (tab order, document position), coming from `start`.
+/// The `keys` must be in document order. Returns `None` if there's nothing to move to.
+fn tab_order_next(
+    keys: &[(u16, usize)],
+    start: Option<(u16, usize)>,
+    forward: bool,
+) -> Option<usize> {
+    let mut next: Option<usize> = None;
+    let mut wrap: Option<usize> = None;
+
+    for (i, &key) in keys.iter().enumerate() {
+        let better =
+            |best: Option<usize>, after: bool| best.is_none_or(|b| (key < keys[b]) == after);
+        let beyond = start.is_none_or(|s| if forward { key > s } else { key < s });
+        if beyond && better(next, forward) {
+            next = Some(i);
+        }
+        if better(wrap, forward) {
+            wrap = Some(i);
+        }
+    }
+
+    next.or(wrap)
+}
+
+/// Grows or shrinks the `sizes` of the flex `items` (item, base size, cross size),
+/// so that they add up to `available`, within the limits of their min/max sizes.
+fn flex_distribute(
+    items: &[(FlexItem, CoordType, CoordType)],
+    sizes: &mut [CoordType],
+    available: CoordType,
+) {
+    // Each round, the children that hit their min/max size are frozen and the rest is
+    // distributed among the others. Each round freezes at least one child, or it's the last.
+    for _ in 0..=items.len() {
+        let free = available - sizes.iter().sum::<CoordType>();
+        let growing = free > 0;
+        let weight = |sizes: &[CoordType], i: usize| {
+            let (flex, ..) = items[i];
+            let (w, limit) = if growing { (flex.grow, flex.max) } else { (flex.shrink, flex.min) };
+            if sizes[i] == limit { 0 } else { w as CoordType }
+        };
+
+        let total_weight = (0..items.len()).map(|i| weight(sizes, i)).sum::<CoordType>();
+        if free == 0 || total_weight == 0 {
+            break;
+        }
+
+        // Distribute the remainder of the integer division to the first children.
+        let mut remainder = free.abs() % total_weight;
+        let mut clamped = false;
+        for i in 0..items.len() {
+            let w = weight(sizes, i);
+            if w == 0 {
+                continue;
+            }
+
+            let mut delta = free.abs() / total_weight * w;
+            if remainder > 0 {
+                let extra = remainder.min(w);
+                delta += extra;
+                remainder -= extra;
+            }
+
+            let (flex, ..) = items[i];
+            let size = sizes[i] + if growing { delta } else { -delta };
+            sizes[i] = size.clamp(flex.min, flex.max);
+            clamped |= sizes[i] != size;
+        }
+
+        if !clamped {
+            break;
+        }
+    }
+}
+
+/// Flips `rect` horizontally within `within`. Used for right-to-left layouts.
+fn mirror_rect(rect: Rect, within: Rect) -> Rect {
+    Rect {
+        left: within.left + within.right - rect.right,
+        right: within.left + within.right - rect.left,
+        ..rect
+    }
+}
+
+/// Returns the size of the first pane of a split container, given the `available` space
+/// along its main axis, which includes the 1 cell wide divider.
+fn split_first_size(
+    available: CoordType,
+    position: Option<CoordType>,
+    min: CoordType,
+) -> CoordType {
+    let panes = (available - 1).max(0);
+    let first = position.unwrap_or(panes / 2).min(panes - min).max(min);
+    first.clamp(0, panes)
+}
+
+/// NOTE: Must not contain items that require drop().
+struct GridContent<'a> {
+    /// The widths requested by the [`GridColumn`]s.
+    widths: Vec<CoordType, &'a Arena>,
+    scroll_x: CoordType,
+}
+
+/// The width of the selection marker in front of each grid row. See [`Context::styled_list_item_begin`].
+const GRID_PREFIX_WIDTH: usize = 2;
+const GRID_CELL_GAP: CoordType = 2;
+
+/// Turns the raw text of a grid row (see [`Context::grid_add_cells`])
+/// into cells padded or truncated to `widths`, scrolled by `scroll_x` columns.
+fn grid_format_row<'a>(
+    arena: &'a Arena,
+    raw: &str,
+    widths: &[CoordType],
+    scroll_x: CoordType,
+) -> ArenaString<'a> {
+    let scratch = scratch_arena(Some(arena));
+    let (prefix, cells) = raw.split_at(GRID_PREFIX_WIDTH);
+    let mut line = ArenaString::new_in(&scratch);
+
+    for (column, cell) in cells.split('\x1f').enumerate() {
+        let width = widths[column];
+        if column != 0 {
+            line.push_repeat(' ', GRID_CELL_GAP as usize);
+        }
+
+        let bytes = cell.as_bytes();
+        let end = unicode::MeasurementConfig::new(&bytes).goto_visual(Point { x: width, y: 0 });
+        if end.offset < cell.len() {
+            // Truncate with an ellipsis.
+            let end =
+                unicode::MeasurementConfig::new(&bytes).goto_visual(Point { x: width - 1, y: 0 });
+            line.push_str(&cell[..end.offset]);
+            line.push('…');
+            line.push_repeat(' ', (width - 1 - end.visual_pos.x) as usize);
+        } else {
+            line.push_str(cell);
+            if column + 1 != widths.len() {
+                line.push_repeat(' ', (width - end.visual_pos.x) as usize);
+            }
+        }
+    }
+
+    let mut result = ArenaString::from_str(arena, prefix);
+    let bytes = line.as_bytes();
+    let mut cfg = unicode::MeasurementConfig::new(&bytes);
+    let mut beg = cfg.goto_visual(Point { x: scroll_x, y: 0 });
+    if beg.visual_pos.x < scroll_x {
+        // Don't show half of a wide glyph.
+        beg = cfg.goto_visual(Point { x: scroll_x + 1, y: 0 });
+    }
+    result.push_repeat(' ', (beg.visual_pos.x - scroll_x).max(0) as usize);
+    result.push_str(&line[beg.offset..]);
+    result
 }
 
 /// NOTE: Must not contain items that require drop().
+#[derive(Default, Clone, Copy)]
 struct ListContent<'a> {
     selected: u64,
     // Points to the Node that holds this ListContent instance, if any>.
     selected_node: Option<&'a NodeCell<'a>>,
+    /// The remaining fields are only used by [`Context::virtual_list_begin`].
+    is_virtual: bool,
+    /// The index of the first visible item.
+    scroll: usize,
+    /// The total number of items.
+    count: usize,
+    /// The index of the selected item when it was last visible.
+    selected_index: usize,
+    /// An item to select during this frame, after the selection was moved with the keyboard.
+    select_index: Option<usize>,
+    scrollbar: ScrollbarState,
 }
 
 /// NOTE: Must not contain items that require drop().
@@ -3674,21 +5905,56 @@ struct TextareaContent<'a> {
 
     // Carries over between frames.
     scroll_offset: Point,
-    scroll_offset_y_drag_start: CoordType,
     scroll_offset_x_max: CoordType,
-    thumb_height: CoordType,
+    scrollbar: ScrollbarState,
+    /// The column that vertical movement aims for. It's remembered across shorter lines.
     preferred_column: CoordType,
+    /// The cursor position after `preferred_column` was last used or updated.
+    preferred_column_pos: Point,
 
     single_line: bool,
     has_focus: bool,
+
+    // Only used by editlines. See [`EditlineSpec`].
+    placeholder: ArenaString<'a>,
+    error: ArenaString<'a>,
+    password: bool,
 }
 
 /// NOTE: Must not contain items that require drop().
 #[derive(Clone)]
 struct ScrollareaContent {
     scroll_offset: Point,
-    scroll_offset_y_drag_start: CoordType,
-    thumb_height: CoordType,
+    scrollbar: ScrollbarState,
+}
+
+/// The mouse interaction state of a scrollbar. Carries over between frames.
+#[derive(Default, Clone, Copy)]
+struct ScrollbarState {
+    /// The scroll offset at the time the thumb drag started.
+    drag_start: Option<CoordType>,
+    /// The size of the thumb as it was last rendered.
+    thumb_size: CoordType,
+}
+
+/// NOTE: Must not contain items that require drop().
+struct SplitContent {
+    direction: FlexDirection,
+    min: CoordType,
+    /// The size of the first pane. Carries over between frames.
+    position: Option<CoordType>,
+    /// The position at the time the divider drag started.
+    drag_start: Option<CoordType>,
+    /// The divider between the panes, as it was laid out.
+    divider: Rect,
+}
+
+/// NOTE: Must not contain items that require drop().
+struct ScrollbarContent {
+    axis: ScrollbarAxis,
+    offset: CoordType,
+    content_size: CoordType,
+    state: ScrollbarState,
 }
 
 /// NOTE: Must not contain items that require drop().
@@ -3697,11 +5963,16 @@ enum NodeContent<'a> {
     #[default]
     None,
     List(ListContent<'a>),
+    Grid(GridContent<'a>),
+    Flex(FlexSpec),
     Modal(ArenaString<'a>), // title
     Table(TableContent<'a>),
     Text(TextContent<'a>),
     Textarea(TextareaContent<'a>),
     Scrollarea(ScrollareaContent),
+    Scrollbar(ScrollbarContent),
+    Split(SplitContent),
+    Separator,
 }
 
 /// NOTE: Must not contain items that require drop().
@@ -3763,7 +6034,7 @@ struct Node<'a> {
     children: NodeChildren<'a>,
     child_count: usize,
 
-    attributes: NodeAttributes,
+    attributes: NodeAttributes<'a>,
     content: NodeContent<'a>,
 
     intrinsic_size: Size,
@@ -3875,6 +6146,46 @@ impl Node<'_> {
                     self.intrinsic_size_set = true;
                 }
             }
+            NodeContent::Split(split) => {
+                let direction = split.direction;
+                // The divider is 1 cell wide.
+                let mut main = 1;
+                let mut cross = 0;
+
+                for child in Tree::iterate_siblings(self.children.first) {
+                    let mut child = child.borrow_mut();
+                    child.compute_intrinsic_size();
+
+                    let (m, c) = direction.split(child.intrinsic_to_outer());
+                    main += m;
+                    cross = cross.max(c);
+                }
+
+                if !self.intrinsic_size_set {
+                    self.intrinsic_size = direction.join(main, cross);
+                    self.intrinsic_size_set = true;
+                }
+            }
+            NodeContent::Flex(spec) => {
+                let spec = *spec;
+                let mut main = 0;
+                let mut cross = 0;
+
+                for child in Tree::iterate_siblings(self.children.first) {
+                    let mut child = child.borrow_mut();
+                    child.compute_intrinsic_size();
+
+                    let (m, c) = spec.direction.split(child.intrinsic_to_outer());
+                    main += m.clamp(child.attributes.flex.min, child.attributes.flex.max);
+                    cross = cross.max(c);
+                }
+                main += spec.gap * self.child_count.saturating_sub(1) as CoordType;
+
+                if !self.intrinsic_size_set {
+                    self.intrinsic_size = spec.direction.join(main, cross);
+                    self.intrinsic_size_set = true;
+                }
+            }
             _ => {
                 let mut max_width = 0;
                 let mut total_height = 0;
@@ -3899,7 +6210,8 @@ impl Node<'_> {
 
     /// Lays out the children of this node.
     /// The clip rect restricts "rendering" to a certain area (the viewport).
-    fn layout_children(&mut self, clip: Rect) {
+    /// `rtl` mirrors the position of each child within this node. See [`Tui::setup_rtl()`].
+    fn layout_children(&mut self, clip: Rect, rtl: bool) {
         if self.children.first.is_none() || self.inner.is_empty() {
             return;
         }
@@ -3934,6 +6246,9 @@ impl Node<'_> {
                         cell.outer.right = x + size.width;
                         cell.outer.bottom = y + size.height;
                         cell.outer = cell.outer.intersect(self.inner);
+                        if rtl {
+                            cell.outer = mirror_rect(cell.outer, self.inner);
+                        }
                         cell.inner = cell.outer_to_inner(cell.outer);
                         cell.outer_clipped = cell.outer.intersect(clip);
                         cell.inner_clipped = cell.inner.intersect(clip);
@@ -3941,13 +6256,109 @@ impl Node<'_> {
                         x += size.width + spec.cell_gap.width;
                         row_height = row_height.max(size.height);
 
-                        cell.layout_children(clip);
+                        cell.layout_children(clip, rtl);
                     }
 
                     x = self.inner.left;
                     y += row_height + spec.cell_gap.height;
                 }
             }
+            NodeContent::Flex(spec) => {
+                let spec = *spec;
+                let scratch = scratch_arena(None);
+                let mut items = Vec::with_capacity_in(self.child_count, &*scratch);
+                items.extend(Tree::iterate_siblings(self.children.first).map(|child| {
+                    let child = child.borrow();
+                    let flex = child.attributes.flex;
+                    let (main, cross) = spec.direction.split(child.intrinsic_to_outer());
+                    (flex, main.clamp(flex.min, flex.max), cross)
+                }));
+
+                let (inner_main, inner_cross) = spec
+                    .direction
+                    .split(Size { width: self.inner.width(), height: self.inner.height() });
+                let gaps = spec.gap * items.len().saturating_sub(1) as CoordType;
+                let mut sizes = Vec::with_capacity_in(items.len(), &*scratch);
+                sizes.extend(items.iter().map(|&(_, main, _)| main));
+                flex_distribute(&items, &mut sizes, inner_main - gaps);
+
+                let (origin_main, origin_cross) =
+                    spec.direction.split(Size { width: self.inner.left, height: self.inner.top });
+                let mut pos = origin_main;
+
+                for (i, child) in Tree::iterate_siblings(self.children.first).enumerate() {
+                    let mut child = child.borrow_mut();
+                    let main = sizes[i];
+                    let cross = match spec.align {
+                        FlexAlign::Stretch => inner_cross,
+                        _ => items[i].2.min(inner_cross),
+                    };
+                    let cross_offset = match spec.align {
+                        FlexAlign::Stretch | FlexAlign::Start => 0,
+                        FlexAlign::Center => (inner_cross - cross) / 2,
+                        FlexAlign::End => inner_cross - cross,
+                    };
+
+                    let origin = spec.direction.join(pos, origin_cross + cross_offset);
+                    let size = spec.direction.join(main, cross);
+                    child.outer = Rect {
+                        left: origin.width,
+                        top: origin.height,
+                        right: origin.width + size.width,
+                        bottom: origin.height + size.height,
+                    };
+                    child.outer = child.outer.intersect(self.inner);
+                    if rtl {
+                        child.outer = mirror_rect(child.outer, self.inner);
+                    }
+                    child.inner = child.outer_to_inner(child.outer);
+                    child.outer_clipped = child.outer.intersect(clip);
+                    child.inner_clipped = child.inner.intersect(clip);
+                    child.layout_children(clip, rtl);
+
+                    pos += main + spec.gap;
+                }
+            }
+            NodeContent::Split(split) => {
+                let direction = split.direction;
+                let (inner_main, inner_cross) = direction
+                    .split(Size { width: self.inner.width(), height: self.inner.height() });
+                let (origin_main, origin_cross) =
+                    direction.split(Size { width: self.inner.left, height: self.inner.top });
+                let first = split_first_size(inner_main, split.position, split.min);
+                split.position = Some(first);
+
+                let rect = |pos: CoordType, main: CoordType| {
+                    let origin = direction.join(pos, origin_cross);
+                    let size = direction.join(main, inner_cross);
+                    Rect {
+                        left: origin.width,
+                        top: origin.height,
+                        right: origin.width + size.width,
+                        bottom: origin.height + size.height,
+                    }
+                };
+                split.divider = rect(origin_main + first, 1).intersect(self.inner);
+                if rtl {
+                    split.divider = mirror_rect(split.divider, self.inner);
+                }
+
+                for (i, child) in Tree::iterate_siblings(self.children.first).enumerate() {
+                    let mut child = child.borrow_mut();
+                    child.outer = match i {
+                        0 => rect(origin_main, first),
+                        _ => rect(origin_main + first + 1, (inner_main - first - 1).max(0)),
+                    };
+                    child.outer = child.outer.intersect(self.inner);
+                    if rtl {
+                        child.outer = mirror_rect(child.outer, self.inner);
+                    }
+                    child.inner = child.outer_to_inner(child.outer);
+                    child.outer_clipped = child.outer.intersect(clip);
+                    child.inner_clipped = child.inner.intersect(clip);
+                    child.layout_children(clip, rtl);
+                }
+            }
             NodeContent::Scrollarea(sc) => {
                 let mut content = self.children.first.unwrap().borrow_mut();
 
@@ -3973,7 +6384,7 @@ impl Node<'_> {
                 content.inner_clipped = content.inner.intersect(self.inner_clipped);
 
                 let clip = content.inner_clipped;
-                content.layout_children(clip);
+                content.layout_children(clip, rtl);
             }
             _ => {
                 let width = self.inner.right - self.inner.left;
@@ -3999,6 +6410,9 @@ impl Node<'_> {
                     child.outer.bottom = y + size.height;
 
                     child.outer = child.outer.intersect(self.inner);
+                    if rtl {
+                        child.outer = mirror_rect(child.outer, self.inner);
+                    }
                     child.inner = child.outer_to_inner(child.outer);
                     child.outer_clipped = child.outer.intersect(clip);
                     child.inner_clipped = child.inner.intersect(clip);
@@ -4008,9 +6422,579 @@ impl Node<'_> {
 
                 for child in Tree::iterate_siblings(self.children.first) {
                     let mut child = child.borrow_mut();
-                    child.layout_children(clip);
+                    child.layout_children(clip, rtl);
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::input::InputMouse;
+
+    fn mouse(state: InputMouseState, position: Point) -> Input<'static> {
+        Input::Mouse(InputMouse {
+            state,
+            modifiers: kbmod::NONE,
+            position,
+            scroll: Point::default(),
+        })
+    }
+
+    /// Draws frames with `draw` until the layout settled, rendering each one.
+    /// Only the first frame gets the `input`.
+    fn settle(tui: &mut Tui, mut input: Option<Input>, mut draw: impl FnMut(&mut Context)) {
+        loop {
+            let mut ctx = tui.create_context(input.take());
+            draw(&mut ctx);
+            drop(ctx);
+            // Some sizes, like that of a scrollbar thumb, are only known after rendering.
+            tui.render(&scratch_arena(None));
+            if !tui.needs_settling() {
+                break;
+            }
+        }
+    }
+
+    #[test]
+    fn test_context_menu() {
+        let mut tui = Tui::new().unwrap();
+        let items = [
+            MenuItem::new("A").enabled(false),
+            MenuItem::new("B"),
+            MenuItem::SEPARATOR,
+            MenuItem::new("C").accelerator('C'),
+        ];
+        let mut frame = |input: Option<Input>| {
+            let mut activated = None;
+            settle(&mut tui, input, |ctx| {
+                ctx.label("target", "Target");
+                activated = activated.or(ctx.context_menu("menu", &items));
+            });
+            activated
+        };
+        let target = Point { x: 1, y: 0 };
+
+        frame(Some(Input::Resize(Size { width: 80, height: 25 })));
+        frame(Some(mouse(InputMouseState::Right, target)));
+        frame(Some(mouse(InputMouseState::None, target)));
+        // "A" is disabled, so "B" is focused first.
+        assert_eq!(frame(Some(Input::Keyboard(vk::RETURN))), Some(1));
+        // Activating an item closes the menu.
+        assert_eq!(frame(Some(Input::Keyboard(vk::C))), None);
+
+        frame(Some(mouse(InputMouseState::Right, target)));
+        frame(Some(mouse(InputMouseState::None, target)));
+        assert_eq!(frame(Some(Input::Keyboard(vk::C))), Some(3));
+    }
+
+    #[test]
+    fn test_timer() {
+        let interval = time::Duration::from_secs(3600);
+        let mut tui = Tui::new().unwrap();
+        let frame = |tui: &mut Tui, poll: bool| {
+            let mut ctx = tui.create_context(None);
+            let fired = poll && ctx.timer("timer", interval);
+            drop(ctx);
+            fired
+        };
+
+        assert!(!frame(&mut tui, true));
+        let timeout = tui.read_timeout();
+        assert!(timeout > time::Duration::ZERO && timeout <= interval);
+        assert_eq!(tui.read_timeout(), time::Duration::MAX);
+
+        // Instead of waiting for it, the deadline is moved into the past.
+        tui.timers[0].deadline -= interval;
+        assert!(frame(&mut tui, true));
+        assert!(!frame(&mut tui, true));
+
+        // Timers that aren't polled for a frame are dropped and start over.
+        frame(&mut tui, false);
+        assert!(tui.timers.is_empty());
+        assert!(!frame(&mut tui, true));
+    }
+
+    #[test]
+    fn test_toasts() {
+        let mut tui = Tui::new().unwrap();
+        let queue = tui.toast_queue();
+        std::thread::spawn(move || queue.push(ToastKind::Info, "Done")).join().unwrap();
+
+        let mut frame = |input: Option<Input>| {
+            settle(&mut tui, input, |ctx| ctx.toasts("toast", Point { x: 80, y: 24 }));
+            tui.toasts.len()
+        };
+        let close = Point { x: 76, y: 22 };
+
+        assert_eq!(frame(Some(Input::Resize(Size { width: 80, height: 25 }))), 1);
+        assert_eq!(frame(Some(mouse(InputMouseState::Left, close))), 1);
+        assert_eq!(frame(Some(mouse(InputMouseState::None, close))), 0);
+    }
+
+    #[test]
+    fn test_scrollbar() {
+        let mut tui = Tui::new().unwrap();
+        let mut offset = 0;
+
+        let mut frame = |input: Option<Input>| {
+            settle(&mut tui, input, |ctx| {
+                ctx.scrollbar("scrollbar", ScrollbarAxis::Vertical, &mut offset, 100);
+                ctx.attr_intrinsic_size(Size { width: 1, height: 10 });
+            });
+            offset
+        };
+        let mouse_at = |state, y| Some(mouse(state, Point { x: 0, y }));
+
+        frame(Some(Input::Resize(Size { width: 80, height: 25 })));
+        frame(None);
+
+        // Clicking below the 1 row tall thumb scrolls down by a page.
+        assert_eq!(frame(mouse_at(InputMouseState::Left, 5)), 0);
+        assert_eq!(frame(mouse_at(InputMouseState::None, 5)), 10);
+
+        // The thumb is now in row 1. Dragging it by 3 rows scrolls by 3/9th of the content.
+        assert_eq!(frame(mouse_at(InputMouseState::Left, 1)), 10);
+        assert_eq!(frame(mouse_at(InputMouseState::Left, 4)), 40);
+        assert_eq!(frame(mouse_at(InputMouseState::None, 4)), 40);
+
+        // Clicking above the thumb scrolls up by a page.
+        assert_eq!(frame(mouse_at(InputMouseState::Left, 0)), 40);
+        assert_eq!(frame(mouse_at(InputMouseState::None, 0)), 30);
+    }
+
+    #[test]
+    fn test_split() {
+        let mut tui = Tui::new().unwrap();
+        let mut frame = |input: Option<Input>| {
+            let mut position = None;
+            settle(&mut tui, input, |ctx| {
+                ctx.split_begin(
+                    "split",
+                    SplitSpec { direction: FlexDirection::Row, position: None, min: 10 },
+                );
+                ctx.attr_intrinsic_size(Size { width: 80, height: 10 });
+                ctx.button("left", "Left", ButtonStyle::default());
+                ctx.split_next();
+                ctx.button("right", "Right", ButtonStyle::default());
+                ctx.split_end();
+                position = match &ctx.tree.last_node.borrow().content {
+                    NodeContent::Split(split) => split.position,
+                    _ => unreachable!(),
+                };
+            });
+            position
+        };
+        let mouse_at = |state, x| Some(mouse(state, Point { x, y: 0 }));
+
+        frame(Some(Input::Resize(Size { width: 80, height: 25 })));
+        // 79 columns for the panes, and 1 for the divider.
+        assert_eq!(frame(None), Some(39));
+
+        frame(mouse_at(InputMouseState::Left, 39));
+        assert_eq!(frame(mouse_at(InputMouseState::Left, 49)), Some(49));
+        // Dragging past the minimum size of the second pane is clamped.
+        frame(mouse_at(InputMouseState::Left, 75));
+        assert_eq!(frame(mouse_at(InputMouseState::None, 75)), Some(69));
+
+        // Focus the left button, so that the divider can be moved with the keyboard.
+        frame(mouse_at(InputMouseState::Left, 1));
+        frame(mouse_at(InputMouseState::None, 1));
+        assert_eq!(frame(Some(Input::Keyboard(kbmod::CTRL_ALT | vk::LEFT))), Some(68));
+    }
+
+    #[test]
+    fn test_input_filter() {
+        let apply = |filter: InputFilter, text, grew| filter.apply(text, grew).map(Cow::into_owned);
+
+        assert_eq!(apply(InputFilter::Numeric, "123", true).as_deref(), Some("123"));
+        assert_eq!(apply(InputFilter::Numeric, "12a", true), None);
+        assert_eq!(apply(InputFilter::Chars("0123456789:"), "12:3", true).as_deref(), Some("12:3"));
+        assert_eq!(apply(InputFilter::Chars("0123456789:"), "12,3", true), None);
+
+        let date = InputFilter::Mask("##.##.####");
+        // Literals are appended while typing, but not while deleting.
+        assert_eq!(apply(date, "12", true).as_deref(), Some("12."));
+        assert_eq!(apply(date, "12", false).as_deref(), Some("12"));
+        // Missing literals are inserted and typed ones are accepted.
+        assert_eq!(apply(date, "1203", true).as_deref(), Some("12.03."));
+        assert_eq!(apply(date, "12.03.2025", true).as_deref(), Some("12.03.2025"));
+        assert_eq!(apply(date, "12.a", true), None);
+        assert_eq!(apply(date, "12.03.20251", true), None);
+    }
+
+    #[test]
+    fn test_theme_colors() {
+        let mut tui = Tui::new().unwrap();
+        let red = ThemeColors {
+            bg: tui.indexed(IndexedColor::Red),
+            fg: tui.indexed(IndexedColor::White),
+        };
+        let blue = ThemeColors {
+            bg: tui.indexed(IndexedColor::Blue),
+            fg: tui.indexed(IndexedColor::White),
+        };
+
+        assert!(tui.theme_colors("ok", ThemeRole::FocusedButton).is_none());
+        assert!(tui.theme_colors("ok", ThemeRole::Selection) != Some(red));
+
+        tui.set_theme_colors(ThemeRole::FocusedButton, red);
+        tui.set_widget_theme_colors("cancel", ThemeRole::FocusedButton, blue);
+        assert!(tui.theme_colors("ok", ThemeRole::FocusedButton) == Some(red));
+        assert!(tui.theme_colors("cancel", ThemeRole::FocusedButton) == Some(blue));
+        assert!(tui.theme_colors("cancel", ThemeRole::Selection) != Some(blue));
+
+        // Illegible text gets a contrasting color instead.
+        let yellow = tui.indexed(IndexedColor::BrightYellow);
+        tui.set_theme_colors(
+            ThemeRole::Warning,
+            ThemeColors { bg: yellow, fg: tui.indexed(IndexedColor::BrightWhite) },
+        );
+        assert!(
+            tui.theme_colors("ok", ThemeRole::Warning)
+                == Some(ThemeColors { bg: yellow, fg: tui.indexed(IndexedColor::Black) })
+        );
+    }
+
+    #[test]
+    fn test_chords() {
+        let mut tui = Tui::new().unwrap();
+        let ctrl_k = kbmod::CTRL | vk::K;
+        let mut frame = |input: Option<Input>| {
+            let mut ctx = tui.create_context(input);
+            let chord =
+                ctx.consume_chord(ctrl_k, kbmod::CTRL | vk::C) || ctx.consume_chord(ctrl_k, vk::Z);
+            (chord, ctx.keyboard_input(), ctx.chord_pending())
+        };
+
+        frame(Some(Input::Resize(Size { width: 80, height: 25 })));
+
+        // Ctrl+K waits for the second key.
+        let (chord, key, pending) = frame(Some(Input::Keyboard(ctrl_k)));
+        assert!(!chord && key.is_none() && pending == Some(ctrl_k));
+        let (chord, key, pending) = frame(Some(Input::Keyboard(kbmod::CTRL | vk::C)));
+        assert!(chord && key.is_none() && pending.is_none());
+
+        // The second key can be text.
+        frame(Some(Input::Keyboard(ctrl_k)));
+        assert!(frame(Some(Input::Text("z"))).0);
+
+        // An unknown second key is swallowed and the next one is regular input again.
+        frame(Some(Input::Keyboard(ctrl_k)));
+        let (chord, key, _) = frame(Some(Input::Keyboard(kbmod::CTRL | vk::X)));
+        assert!(!chord && key.is_none());
+        let (chord, key, _) = frame(Some(Input::Keyboard(kbmod::CTRL | vk::C)));
+        assert!(!chord && key == Some(kbmod::CTRL | vk::C));
+
+        // The chord expires.
+        frame(Some(Input::Keyboard(ctrl_k)));
+        tui.chord_pending.as_mut().unwrap().1 -= CHORD_TIMEOUT;
+        let mut frame = |input: Option<Input>| {
+            let mut ctx = tui.create_context(input);
+            (ctx.consume_chord(ctrl_k, kbmod::CTRL | vk::C), ctx.chord_pending())
+        };
+        assert!(frame(None).1.is_none());
+        assert!(!frame(Some(Input::Keyboard(kbmod::CTRL | vk::C))).0);
+
+        // Prefixes that aren't registered anymore are regular input again.
+        tui.create_context(None);
+        let ctx = tui.create_context(Some(Input::Keyboard(ctrl_k)));
+        assert!(ctx.keyboard_input() == Some(ctrl_k) && ctx.chord_pending().is_none());
+    }
+
+    #[test]
+    #[cfg(debug_assertions)]
+    #[should_panic = "shadowed by a chord"]
+    fn test_chord_shadows_earlier_shortcut() {
+        let mut tui = Tui::new().unwrap();
+        let mut ctx = tui.create_context(None);
+        ctx.consume_shortcut(kbmod::CTRL | vk::K);
+        ctx.consume_chord(kbmod::CTRL | vk::K, kbmod::CTRL | vk::C);
+    }
+
+    #[test]
+    fn test_clipboard_read() {
+        let mut tui = Tui::new().unwrap();
+        let scratch = scratch_arena(None);
+        let now = time::Instant::now();
+
+        // Terminals that aren't known to support OSC 52 aren't asked.
+        let paste = Some(Input::Keyboard(kbmod::CTRL | vk::V));
+        assert!(tui.filter_clipboard_read(paste, now).is_some());
+        tui.capabilities.osc52_clipboard = true;
+
+        // The paste shortcut is held back until the terminal responded.
+        let paste = Some(Input::Keyboard(kbmod::CTRL | vk::V));
+        assert!(tui.filter_clipboard_read(paste, now).is_none());
+        assert!(tui.render(&scratch).ends_with("\x1b]52;c;?\x1b\\"));
+
+        let response = Some(Input::HostClipboard(b"foo".to_vec()));
+        let replayed = tui.filter_clipboard_read(response, now);
+        assert!(matches!(replayed, Some(Input::Keyboard(key)) if key == kbmod::CTRL | vk::V));
+        assert_eq!(tui.clipboard_ref().read(), b"foo");
+
+        // Without a response, the internal clipboard is used after a while, and from then on.
+        tui.clipboard_read_support = ClipboardReadSupport::Unknown;
+        let paste = Some(Input::Keyboard(kbmod::SHIFT | vk::INSERT));
+        assert!(tui.filter_clipboard_read(paste, now).is_none());
+        assert!(tui.filter_clipboard_read(None, now).is_none());
+        let later = now + time::Duration::from_secs(1);
+        assert!(tui.filter_clipboard_read(None, later).is_some());
+        let paste = Some(Input::Keyboard(kbmod::CTRL | vk::V));
+        assert!(tui.filter_clipboard_read(paste, later).is_some());
+
+        // Input that arrives while waiting is replayed after the paste, in order.
+        tui.clipboard_read_support = ClipboardReadSupport::Unknown;
+        let paste = Some(Input::Keyboard(kbmod::CTRL | vk::V));
+        assert!(tui.filter_clipboard_read(paste, now).is_none());
+        assert!(tui.filter_clipboard_read(Some(Input::Text("ab")), now).is_none());
+        let response = Some(Input::HostClipboard(b"foo".to_vec()));
+        let replayed = tui.filter_clipboard_read(response, now);
+        assert!(matches!(replayed, Some(Input::Keyboard(key)) if key == kbmod::CTRL | vk::V));
+        let enter = Some(Input::Keyboard(vk::RETURN));
+        assert!(matches!(tui.filter_clipboard_read(enter, now), Some(Input::Char('a'))));
+        assert!(matches!(tui.filter_clipboard_read(None, now), Some(Input::Char('b'))));
+        let replayed = tui.filter_clipboard_read(None, now);
+        assert!(matches!(replayed, Some(Input::Keyboard(key)) if key == vk::RETURN));
+        assert!(tui.filter_clipboard_read(None, now).is_none());
+
+        // Pastes that arrive while waiting are replayed as pastes, not as individual characters.
+        let paste = Some(Input::Keyboard(kbmod::CTRL | vk::V));
+        assert!(tui.filter_clipboard_read(paste, now).is_none());
+        let text = String::from("a\r\n\tb");
+        let bracketed = Some(Input::Paste(InputPaste { text: &text, first: true, last: true }));
+        assert!(tui.filter_clipboard_read(bracketed, now).is_none());
+        drop(text);
+        let response = Some(Input::HostClipboard(b"foo".to_vec()));
+        assert!(tui.filter_clipboard_read(response, now).is_some());
+        let replayed = tui.filter_clipboard_read(None, now);
+        assert!(matches!(
+            replayed,
+            Some(Input::Paste(InputPaste { text: "a\r\n\tb", first: true, last: true }))
+        ));
+    }
+
+    #[test]
+    fn test_slider_and_stepper() {
+        let mut tui = Tui::new().unwrap();
+        let mut slider = 50;
+        let mut stepper = 5;
+
+        let mut frame = |input: Option<Input>| {
+            settle(&mut tui, input, |ctx| {
+                ctx.slider("slider", &mut slider, 0..=100, 10, 11);
+                ctx.stepper("stepper", &mut stepper, 0..=10, 1);
+            });
+            (slider, stepper)
+        };
+        let mouse_at = |state, x, y| Some(mouse(state, Point { x, y }));
+
+        frame(Some(Input::Resize(Size { width: 80, height: 25 })));
+
+        // Clicking the slider track jumps to the value under the mouse.
+        frame(mouse_at(InputMouseState::Left, 3, 0));
+        assert_eq!(frame(mouse_at(InputMouseState::None, 3, 0)), (30, 5));
+        assert_eq!(frame(Some(Input::Keyboard(vk::RIGHT))), (40, 5));
+        assert_eq!(frame(Some(Input::Keyboard(vk::END))), (100, 5));
+
+        // The stepper is drawn as "◂  5 ▸". Clicking its right half increments it.
+        frame(mouse_at(InputMouseState::Left, 5, 1));
+        assert_eq!(frame(mouse_at(InputMouseState::None, 5, 1)), (100, 6));
+        assert_eq!(frame(Some(Input::Keyboard(vk::PRIOR))), (100, 10));
+        assert_eq!(frame(Some(Input::Keyboard(vk::LEFT))), (100, 9));
+    }
+
+    #[test]
+    fn test_rtl() {
+        fn find<'a>(node: Option<&'a NodeCell<'a>>, classname: &str) -> Option<Rect> {
+            for n in Tree::iterate_siblings(node) {
+                let n = n.borrow();
+                if n.classname == classname {
+                    return Some(n.outer);
+                }
+                if let Some(rect) = find(n.children.first, classname) {
+                    return Some(rect);
                 }
             }
+            None
         }
+
+        let layout = |rtl: bool| {
+            let mut tui = Tui::new().unwrap();
+            tui.setup_rtl(rtl);
+            let input = Some(Input::Resize(Size { width: 20, height: 5 }));
+            settle(&mut tui, input, |ctx| {
+                ctx.flex_begin(
+                    "row",
+                    FlexSpec { direction: FlexDirection::Row, ..Default::default() },
+                );
+                ctx.label("a", "ab");
+                ctx.label("b", "cde");
+                ctx.block_begin("popup");
+                ctx.attr_float(FloatSpec { offset_y: 1.0, ..Default::default() });
+                ctx.attr_intrinsic_size(Size { width: 4, height: 2 });
+                ctx.block_end();
+                ctx.flex_end();
+            });
+            let root = Some(tui.prev_tree.root_first);
+            ["a", "b", "popup"].map(|c| {
+                let r = find(root, c).unwrap();
+                (r.left, r.right)
+            })
+        };
+
+        assert_eq!(layout(false), [(0, 2), (2, 5), (2, 6)]);
+        // The row is reversed and the popup is right-aligned with the label it's attached to.
+        assert_eq!(layout(true), [(18, 20), (15, 18), (14, 18)]);
+    }
+
+    #[test]
+    fn test_goal_column() {
+        let mut tui = Tui::new().unwrap();
+        let tb = TextBuffer::new_rc(true).unwrap();
+        tb.borrow_mut().write_raw("abcdef\n\tx\n漢字漢字\nab\nabcdefgh".as_bytes());
+        tb.borrow_mut().cursor_move_to_logical(Point { x: 5, y: 0 });
+
+        let mut frame = |input: Option<Input>| {
+            settle(&mut tui, input, |ctx| {
+                ctx.textarea("textarea", tb.clone());
+                ctx.steal_focus();
+                ctx.attr_intrinsic_size(Size { width: 40, height: 10 });
+            });
+            tb.borrow().cursor_visual_pos()
+        };
+
+        frame(Some(Input::Resize(Size { width: 40, height: 10 })));
+        // The tab ends at column 4 and "x" at 5. The wide "漢" covers column 5 and is skipped.
+        let down: Vec<_> = (0..4).map(|_| frame(Some(Input::Keyboard(vk::DOWN))).x).collect();
+        assert_eq!(down, [5, 4, 2, 5]);
+
+        // A cursor moved by something other than the textarea sets a new column.
+        tb.borrow_mut().cursor_move_to_logical(Point { x: 1, y: 4 });
+        assert_eq!(frame(Some(Input::Keyboard(vk::UP))), Point { x: 1, y: 3 });
+
+        tb.borrow_mut().set_goal_column_enabled(false);
+        tb.borrow_mut().cursor_move_to_logical(Point { x: 5, y: 0 });
+        let down: Vec<_> = (0..4).map(|_| frame(Some(Input::Keyboard(vk::DOWN))).x).collect();
+        assert_eq!(down, [5, 4, 2, 2]);
+    }
+
+    #[test]
+    fn test_split_first_size() {
+        assert_eq!(split_first_size(11, None, 0), 5);
+        assert_eq!(split_first_size(11, Some(0), 0), 0);
+        assert_eq!(split_first_size(11, Some(0), 3), 3);
+        assert_eq!(split_first_size(11, Some(10), 3), 7);
+        // If both panes can't fit their minimum size, the first one wins.
+        assert_eq!(split_first_size(5, Some(0), 3), 3);
+        assert_eq!(split_first_size(0, None, 3), 0);
+    }
+
+    #[test]
+    fn test_modal_focus_unwinding() {
+        let mut tui = Tui::new().unwrap();
+        let frame = |tui: &mut Tui, input: Option<Input>, top: bool| {
+            let mut focused = false;
+            settle(tui, input, |ctx| {
+                ctx.modal_begin("bottom", "");
+                ctx.button("a", "A", ButtonStyle::default());
+                ctx.button("b", "B", ButtonStyle::default());
+                focused = ctx.is_focused();
+                ctx.modal_end();
+                if top {
+                    ctx.modal_begin("top", "");
+                    ctx.button("c", "C", ButtonStyle::default());
+                    ctx.modal_end();
+                }
+            });
+            focused
+        };
+
+        frame(&mut tui, Some(Input::Resize(Size { width: 80, height: 25 })), false);
+        assert!(!frame(&mut tui, Some(Input::Keyboard(vk::TAB)), false));
+        assert!(frame(&mut tui, Some(Input::Keyboard(vk::TAB)), false));
+
+        // Opening another modal moves the focus into it.
+        assert!(!frame(&mut tui, None, true));
+        // Closing it, returns the focus to the button "B".
+        assert!(frame(&mut tui, None, false));
+    }
+
+    #[test]
+    fn test_tab_order_next() {
+        // Two nodes with an explicit index (0 and 1, i.e. tab index 1 and 2) and two without.
+        let keys = [(u16::MAX, 1), (1, 2), (u16::MAX, 4), (0, 5)];
+
+        assert_eq!(tab_order_next(&keys, None, true), Some(3));
+        assert_eq!(tab_order_next(&keys, None, false), Some(2));
+        assert_eq!(tab_order_next(&keys, Some((0, 5)), true), Some(1));
+        assert_eq!(tab_order_next(&keys, Some((1, 2)), true), Some(0));
+        assert_eq!(tab_order_next(&keys, Some((u16::MAX, 3)), true), Some(2));
+        assert_eq!(tab_order_next(&keys, Some((u16::MAX, 3)), false), Some(0));
+        // Wraps around at either end.
+        assert_eq!(tab_order_next(&keys, Some((u16::MAX, 4)), true), Some(3));
+        assert_eq!(tab_order_next(&keys, Some((0, 5)), false), Some(2));
+        assert_eq!(tab_order_next(&[], Some((0, 0)), true), None);
+    }
+
+    #[test]
+    fn test_flex_distribute() {
+        let item = |grow, shrink, min, max, base| (FlexItem { grow, shrink, min, max }, base, 0);
+        let distribute = |items: &[(FlexItem, CoordType, CoordType)], available| {
+            let mut sizes: Vec<_> = items.iter().map(|i| i.1).collect();
+            flex_distribute(items, &mut sizes, available);
+            sizes
+        };
+
+        // Growing: 1:2, with the remainder going to the first child.
+        let items = [item(1, 1, 0, 100, 10), item(2, 1, 0, 100, 10), item(0, 1, 0, 100, 5)];
+        assert_eq!(distribute(&items, 35), [14, 16, 5]);
+        // The second child hits its max, so the first one gets the rest.
+        let items = [item(1, 1, 0, 100, 10), item(1, 1, 0, 12, 10)];
+        assert_eq!(distribute(&items, 40), [28, 12]);
+        // Shrinking, but not below the min size.
+        let items = [item(0, 1, 8, 100, 10), item(0, 1, 0, 100, 10)];
+        assert_eq!(distribute(&items, 10), [8, 2]);
+        // Nothing can shrink: overflow.
+        let items = [item(0, 0, 0, 100, 10), item(0, 0, 0, 100, 10)];
+        assert_eq!(distribute(&items, 10), [10, 10]);
+    }
+
+    #[test]
+    fn test_list_filter() {
+        let far = time::Instant::now() + time::Duration::from_secs(3600);
+        let mut filter = ListFilter::default();
+
+        filter.restart();
+        filter.step(5000, time::Instant::now(), |i| i % 2 == 0);
+        assert!(!filter.is_done());
+        assert_eq!(filter.matches().len(), 512);
+
+        // Refining an unfinished filter starts over.
+        filter.refine();
+        filter.step(5000, far, |i| i % 2 == 0);
+        assert!(filter.is_done());
+        assert_eq!(filter.matches().len(), 2500);
+
+        // Refining a finished filter only checks the matches.
+        filter.refine();
+        filter.step(5000, far, |i| {
+            assert!(i % 2 == 0);
+            i % 1000 == 0
+        });
+        assert_eq!(filter.matches(), &[0, 1000, 2000, 3000, 4000]);
+    }
+
+    #[test]
+    fn test_grid_format_row() {
+        let arena = Arena::new(64 * 1024).unwrap();
+        let format = |raw, scroll_x| grid_format_row(&arena, raw, &[3, 4, 2], scroll_x);
+
+        assert_eq!(format("  a\x1fb\x1fc", 0), "  a    b     c");
+        assert_eq!(format("> abcd\x1fb\x1fcde", 0), "> ab…  b     c…");
+        // Scrolling keeps the selection marker and doesn't cut wide glyphs in half.
+        assert_eq!(format("> a\x1f漢字\x1fc", 6), ">  字  c");
     }
 }
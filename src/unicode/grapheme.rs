@@ -0,0 +1,199 @@
+// Copyright (c) Microsoft Corporation.
+// Licensed under the MIT License.
+
+use std::iter;
+use std::ops::Range;
+
+use super::Utf8Chars;
+use super::tables::*;
+
+/// An iterator over the grapheme clusters ("user-perceived characters") of UTF-8 text.
+/// It yields the byte range of each cluster, front to back or back to front.
+///
+/// Like [`Utf8Chars`] it works on unsanitized byte slices,
+/// treating invalid UTF-8 sequences as U+FFFD.
+#[derive(Clone)]
+pub struct Graphemes<'a> {
+    text: &'a [u8],
+    front: usize,
+    back: usize,
+}
+
+impl<'a> Graphemes<'a> {
+    pub fn new(text: &'a [u8]) -> Self {
+        Self { text, front: 0, back: text.len() }
+    }
+}
+
+impl Iterator for Graphemes<'_> {
+    type Item = Range<usize>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.front >= self.back {
+            return None;
+        }
+        let beg = self.front;
+        self.front = cluster_end(&self.text[..self.back], beg);
+        Some(beg..self.front)
+    }
+}
+
+impl DoubleEndedIterator for Graphemes<'_> {
+    fn next_back(&mut self) -> Option<Self::Item> {
+        if self.front >= self.back {
+            return None;
+        }
+        let end = self.back;
+        self.back = cluster_start(self.text, self.front, end);
+        Some(self.back..end)
+    }
+}
+
+impl iter::FusedIterator for Graphemes<'_> {}
+
+/// Returns the offset of the grapheme cluster boundary after `offset`,
+/// assuming that `offset` itself is a boundary. Returns `text.len()` at the end.
+pub fn grapheme_next(text: &[u8], offset: usize) -> usize {
+    cluster_end(text, offset.min(text.len()))
+}
+
+/// Returns the offset of the grapheme cluster boundary before `offset`. Returns 0 at the start.
+pub fn grapheme_prev(text: &[u8], offset: usize) -> usize {
+    cluster_start(text, 0, offset.min(text.len()))
+}
+
+/// Same loop as in `MeasurementConfig::measure_forward`, minus the measuring.
+fn cluster_end(text: &[u8], beg: usize) -> usize {
+    let mut it = Utf8Chars::new(text, beg);
+    let Some(ch) = it.next() else {
+        return beg;
+    };
+    let mut props_last_char = ucd_grapheme_cluster_lookup(ch);
+    let mut state = 0;
+
+    loop {
+        let offset = it.offset();
+        let Some(ch) = it.next() else {
+            return offset;
+        };
+        let props = ucd_grapheme_cluster_lookup(ch);
+        state = ucd_grapheme_cluster_joins(state, props_last_char, props);
+        if ucd_grapheme_cluster_joins_done(state) {
+            return offset;
+        }
+        props_last_char = props;
+    }
+}
+
+/// Finds the start of the cluster that ends at `end`, without going past `min`.
+///
+/// Whether two characters join may depend on what precedes them (regional indicator pairs,
+/// emoji ZWJ sequences), so we can't simply segment backwards. Instead, we seek back to a
+/// boundary that doesn't depend on that state and segment forward from there.
+fn cluster_start(text: &[u8], min: usize, end: usize) -> usize {
+    if end <= min {
+        return end;
+    }
+
+    let mut safe = char_start(text, min, end);
+    while safe > min {
+        let prev = char_start(text, min, safe);
+        let mut it = Utf8Chars::new(text, prev);
+        let lead = it.next().map_or(0, ucd_grapheme_cluster_lookup);
+        // An invalid sequence may decode differently when starting at `prev`.
+        if it.offset() == safe {
+            let trail = it.next().map_or(0, ucd_grapheme_cluster_lookup);
+            if ucd_grapheme_cluster_joins_done(ucd_grapheme_cluster_joins(0, lead, trail))
+                && ucd_grapheme_cluster_joins_done(ucd_grapheme_cluster_joins(1, lead, trail))
+            {
+                break;
+            }
+        }
+        safe = prev;
+    }
+
+    loop {
+        let next = cluster_end(&text[..end], safe);
+        if next >= end {
+            return safe;
+        }
+        safe = next;
+    }
+}
+
+/// Returns the start of the character preceding `offset`,
+/// or `offset - 1` if it's in the middle of an invalid sequence.
+fn char_start(text: &[u8], min: usize, offset: usize) -> usize {
+    let lower = offset.saturating_sub(4).max(min);
+    let mut beg = offset - 1;
+    while beg > lower && (text[beg] & 0xc0) == 0x80 {
+        beg -= 1;
+    }
+    if Utf8Chars::new(&text[..offset], beg).nth(1).is_none() { beg } else { offset - 1 }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn clusters(text: &str) -> Vec<&str> {
+        Graphemes::new(text.as_bytes()).map(|r| &text[r]).collect()
+    }
+
+    fn clusters_rev(text: &str) -> Vec<&str> {
+        let mut v: Vec<_> = Graphemes::new(text.as_bytes()).rev().map(|r| &text[r]).collect();
+        v.reverse();
+        v
+    }
+
+    #[test]
+    fn test_graphemes() {
+        let tests: &[&[&str]] = &[
+            &[],
+            &["a", "b", "c"],
+            &["a", "\r\n", "\n", "\r"],
+            &["e\u{301}", "x\u{308}\u{301}"],
+            &["👨\u{200d}👩\u{200d}👧", "👍🏽", "x"],
+            &["🇩🇪", "🇫🇷", "🇺"],
+            &["각", "ᄀ\u{1161}\u{11a8}"],
+            &["\u{fffd}", "\u{fffd}", "a"],
+        ];
+
+        for &expected in tests {
+            let text: String = expected.concat();
+            assert_eq!(clusters(&text), expected, "{text:?}");
+            assert_eq!(clusters_rev(&text), expected, "{text:?}");
+        }
+    }
+
+    #[test]
+    fn test_graphemes_regional_indicators() {
+        // The pairing of regional indicators depends on how many precede them.
+        let text = "a🇩🇪🇫🇷🇮🇹";
+        let b = text.as_bytes();
+        assert_eq!(grapheme_prev(b, b.len()), b.len() - 8);
+        assert_eq!(grapheme_prev(b, 17), 9);
+        assert_eq!(grapheme_prev(b, 9), 1);
+        assert_eq!(grapheme_next(b, 1), 9);
+
+        // Mixing forward and backward iteration.
+        let mut it = Graphemes::new(b);
+        assert_eq!(it.next(), Some(0..1));
+        assert_eq!(it.next_back(), Some(17..25));
+        assert_eq!(it.next(), Some(1..9));
+        assert_eq!(it.next_back(), Some(9..17));
+        assert_eq!(it.next(), None);
+        assert_eq!(it.next_back(), None);
+    }
+
+    #[test]
+    fn test_graphemes_invalid_utf8() {
+        let text = b"a\xf0\x9f\x98\xe2\x82\xacb\x80";
+        let ranges: Vec<_> = Graphemes::new(text).collect();
+        let mut ranges_rev: Vec<_> = Graphemes::new(text).rev().collect();
+        ranges_rev.reverse();
+        assert_eq!(ranges, ranges_rev);
+        assert_eq!(ranges.first(), Some(&(0..1)));
+        assert_eq!(ranges.last(), Some(&(text.len() - 1..text.len())));
+    }
+}
@@ -7,6 +7,7 @@ use super::Utf8Chars;
 use super::tables::*;
 use crate::document::ReadableDocument;
 use crate::helpers::{CoordType, Point};
+use crate::simd;
 
 // On one hand it's disgusting that I wrote this as a global variable, but on the
 // other hand, this isn't a public library API, and it makes the code a lot cleaner,
@@ -27,6 +28,23 @@ fn ambiguous_width() -> usize {
     unsafe { AMBIGUOUS_WIDTH }
 }
 
+static mut EMOJI_PRESENTATION_WIDTH: CoordType = 2;
+
+/// Sets the width of characters followed by U+FE0F (VARIATION SELECTOR-16), which requests
+/// their emoji presentation, as in "❤️". Unicode says that they're two columns wide,
+/// but some terminals still draw them with the width of the base character.
+///
+/// Defaults to 2.
+pub fn setup_emoji_presentation_width(width: CoordType) {
+    unsafe { EMOJI_PRESENTATION_WIDTH = width };
+}
+
+#[inline]
+fn emoji_presentation_width() -> CoordType {
+    // SAFETY: See `ambiguous_width`.
+    unsafe { EMOJI_PRESENTATION_WIDTH }
+}
+
 /// Stores a position inside a [`ReadableDocument`].
 ///
 /// The cursor tracks both the absolute byte-offset,
@@ -184,6 +202,47 @@ impl<'doc> MeasurementConfig<'doc> {
                 break;
             }
 
+            // Without word wrap, long runs of ASCII (e.g. minified files) can be measured
+            // in bulk, if the current cluster starts with the ASCII character we just read.
+            if self.word_wrap_column == 0
+                && let beg = offset.wrapping_sub(chunk_range.start)
+                && chunk_iter.offset() == beg.wrapping_add(1)
+                && chunk_iter.source().get(beg).is_some_and(u8::is_ascii)
+            {
+                let text = chunk_iter.source();
+                let limit = (offset_target - offset)
+                    .min((logical_target_x - logical_pos_x) as usize)
+                    .min(text.len() - beg);
+                let (mut end, mut col) = simd::columns_fwd(
+                    &text[..beg + limit],
+                    beg,
+                    column,
+                    visual_target_x,
+                    self.tab_size,
+                );
+
+                // The next character may join with the last one, e.g. if it's a combining mark.
+                // Tabs are control characters, which never join.
+                if end > beg && text.get(end).is_some_and(|&b| b >= 0x80) && text[end - 1] != b'\t'
+                {
+                    end -= 1;
+                    col -= 1;
+                }
+
+                if end > beg {
+                    offset += end - beg;
+                    logical_pos_x += (end - beg) as CoordType;
+                    visual_pos_x += col - column;
+                    column = col;
+
+                    // Start over at the new offset, like when reverting to a wrap opportunity below.
+                    chunk_iter = Utf8Chars::new(b"", 0);
+                    chunk_range = offset..offset;
+                    props_next_cluster = ucd_start_of_text_properties();
+                    continue;
+                }
+            }
+
             let props_current_cluster = props_next_cluster;
             let mut props_last_char;
             let mut offset_next_cluster;
@@ -225,6 +284,10 @@ impl<'doc> MeasurementConfig<'doc> {
                 if ucd_grapheme_cluster_joins_done(state) {
                     break;
                 }
+
+                if ch == '\u{fe0f}' {
+                    width = width.max(emoji_presentation_width());
+                }
             }
 
             if offset_next_cluster == offset {
@@ -407,6 +470,10 @@ impl<'doc> MeasurementConfig<'doc> {
                         if ucd_grapheme_cluster_joins_done(state) {
                             break;
                         }
+
+                        if ch == '\u{fe0f}' {
+                            width = width.max(emoji_presentation_width());
+                        }
                     }
 
                     if offset_next_cluster == offset {
@@ -477,6 +544,14 @@ impl<'doc> MeasurementConfig<'doc> {
     }
 }
 
+/// Returns the number of columns that `text` takes up on a single line.
+pub fn measure_width(text: &str) -> CoordType {
+    MeasurementConfig::new(&text.as_bytes())
+        .goto_visual(Point { x: CoordType::MAX, y: 0 })
+        .visual_pos
+        .x
+}
+
 /// Returns an offset past a newline.
 ///
 /// If `offset` is right in front of a newline,
@@ -568,6 +643,21 @@ mod test {
         );
     }
 
+    #[test]
+    fn test_measure_forward_emoji() {
+        // ZWJ sequences, skin tones, flags and VS16 presentations are all single wide clusters,
+        // even if they start with a narrow character like "❤" or "☝".
+        let text = "👨‍👩‍👧|👍🏽|☝🏿|🇩🇪|❤️|❤‍🔥|🏳️‍🌈|❤|".as_bytes();
+        let mut cfg = MeasurementConfig::new(&text);
+        let mut widths = Vec::new();
+        for x in (0..16).step_by(2) {
+            let beg = cfg.goto_logical(Point { x, y: 0 });
+            let end = cfg.goto_logical(Point { x: x + 1, y: 0 });
+            widths.push(end.visual_pos.x - beg.visual_pos.x);
+        }
+        assert_eq!(widths, [2, 2, 2, 2, 2, 2, 2, 1]);
+    }
+
     #[test]
     fn test_measure_forward_word_wrap() {
         //   |foo␣  |
@@ -689,6 +779,41 @@ mod test {
         );
     }
 
+    #[test]
+    fn test_measure_forward_ascii_runs() {
+        // Long runs of ASCII are measured in bulk. A huge word wrap column
+        // doesn't wrap anything, but takes the regular path for comparison.
+        // (The text ends in a newline, because the word wrap lookahead may
+        // otherwise put the cursor past the end of the text on the next line.)
+        let line = "0123456789abcdefghijklmnopqrstuvwxyz\tx\t\tyz e\u{301}e\u{301}ABCDEFGHIJKLMNOPQRSTUVWXYZ 漢字 ❤️🇩🇪\t";
+        let text = format!("{line}\r\n{line}{line}\n\n{line}\n");
+        let chunks = [&text.as_bytes()[..50], &text.as_bytes()[50..]];
+        let doc = ChunkedDoc(&chunks);
+
+        for tab_size in [1, 4, 8] {
+            let cfg = MeasurementConfig::new(&doc).with_tab_size(tab_size);
+            let cfg_wrap = cfg.clone().with_word_wrap_column(1_000_000);
+            let cmp = |a: Cursor, b: Cursor| {
+                assert_eq!(
+                    (a.offset, a.logical_pos, a.visual_pos),
+                    (b.offset, b.logical_pos, b.visual_pos)
+                );
+                assert_eq!(a.column, b.column);
+            };
+
+            for y in 0..5 {
+                for x in 0..200 {
+                    let pos = Point { x, y };
+                    cmp(cfg.clone().goto_logical(pos), cfg_wrap.clone().goto_logical(pos));
+                    cmp(cfg.clone().goto_visual(pos), cfg_wrap.clone().goto_visual(pos));
+                }
+            }
+            for offset in 0..text.len() + 1 {
+                cmp(cfg.clone().goto_offset(offset), cfg_wrap.clone().goto_offset(offset));
+            }
+        }
+    }
+
     #[test]
     fn test_measure_forward_chunk_boundaries() {
         let chunks = [
@@ -3,9 +3,11 @@
 
 //! Everything related to Unicode lives here.
 
+mod grapheme;
 mod measurement;
 mod tables;
 mod utf8;
 
+pub use grapheme::*;
 pub use measurement::*;
 pub use utf8::*;
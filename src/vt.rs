@@ -5,9 +5,16 @@
 
 use std::time;
 
-use crate::simd::memchr2;
+use crate::arena::ArenaString;
+use crate::simd::find_control;
 use crate::unicode::Utf8Chars;
 
+/// How long we wait for the remainder of an OSC, DCS or APC sequence.
+///
+/// Alt+] for instance is `ESC ]`, which looks just like the start of an OSC sequence.
+/// Without a timeout, we would swallow all input until the next BEL or ST.
+const STRING_TIMEOUT: time::Duration = time::Duration::from_millis(500);
+
 /// The parser produces these tokens.
 pub enum Token<'parser, 'input> {
     /// A bunch of text. Doesn't contain any control characters.
@@ -32,6 +39,14 @@ pub enum Token<'parser, 'input> {
     /// The sequence may be split up into multiple tokens if the input
     /// is given in chunks. This is indicated by the `partial` field.
     Dcs { data: &'input str, partial: bool },
+    /// An APC sequence started with `ESC _`.
+    ///
+    /// The sequence may be split up into multiple tokens if the input
+    /// is given in chunks. This is indicated by the `partial` field.
+    Apc { data: &'input str, partial: bool },
+    /// An OSC, DCS or APC sequence was cancelled, either with CAN or SUB, or because
+    /// the rest of it didn't arrive in time. Any partial data received so far is void.
+    Cancel,
 }
 
 /// Stores the state of the parser.
@@ -43,8 +58,10 @@ enum State {
     Csi,
     Osc,
     Dcs,
+    Apc,
     OscEsc,
     DcsEsc,
+    ApcEsc,
 }
 
 /// A single CSI sequence, parsed for your convenience.
@@ -53,6 +70,9 @@ pub struct Csi {
     pub params: [u16; 32],
     /// The number of parameters stored in [`Csi::params`].
     pub param_count: usize,
+    /// Bit `n` is set if `params[n]` is a colon-separated sub-parameter of the one before it.
+    /// See [`Csi::param_group`].
+    pub subparams: u32,
     /// The private byte, if any. `0` if none.
     ///
     /// The private byte is the first character right after the
@@ -64,18 +84,62 @@ pub struct Csi {
     pub final_byte: char,
 }
 
+impl Csi {
+    /// Returns the `n`-th semicolon-separated parameter, followed by its
+    /// colon-separated sub-parameters. For instance, `[5, 3]` for the `5:3` in `CSI 97;5:3u`.
+    pub fn param_group(&self, n: usize) -> &[u16] {
+        let count = self.param_count.min(self.params.len());
+        let mut group = 0;
+        let mut beg = 0;
+
+        for i in 1..=count {
+            if i == count || (self.subparams & (1 << i)) == 0 {
+                if group == n {
+                    return &self.params[beg..i];
+                }
+                group += 1;
+                beg = i;
+            }
+        }
+
+        &[]
+    }
+
+    fn clear(&mut self) {
+        self.private_byte = '\0';
+        self.final_byte = '\0';
+        self.subparams = 0;
+        // Including the parameter after the last one, which may be left over from an aborted sequence.
+        let used = (self.param_count + 1).min(self.params.len());
+        self.params[..used].fill(0);
+        self.param_count = 0;
+    }
+}
+
 pub struct Parser {
     state: State,
     // Csi is not part of State, because it allows us
     // to more quickly erase and reuse the struct.
     csi: Csi,
+    /// When we last received input. Used for [`STRING_TIMEOUT`].
+    last_input: time::Instant,
+    /// The time of the current [`Parser::parse_at()`] call.
+    now: time::Instant,
 }
 
 impl Parser {
     pub fn new() -> Self {
         Self {
             state: State::Ground,
-            csi: Csi { params: [0; 32], param_count: 0, private_byte: '\0', final_byte: '\0' },
+            csi: Csi {
+                params: [0; 32],
+                param_count: 0,
+                subparams: 0,
+                private_byte: '\0',
+                final_byte: '\0',
+            },
+            last_input: time::Instant::now(),
+            now: time::Instant::now(),
         }
     }
 
@@ -92,6 +156,12 @@ impl Parser {
             // However, there seems to be issues with OpenSSH on Windows.
             // See: https://github.com/PowerShell/Win32-OpenSSH/issues/2275
             State::Esc => time::Duration::from_millis(100),
+            State::Osc
+            | State::Dcs
+            | State::Apc
+            | State::OscEsc
+            | State::DcsEsc
+            | State::ApcEsc => STRING_TIMEOUT.saturating_sub(self.last_input.elapsed()),
             _ => time::Duration::MAX,
         }
     }
@@ -104,6 +174,20 @@ impl Parser {
         &'parser mut self,
         input: &'input str,
     ) -> Stream<'parser, 'input> {
+        self.parse_at(input, time::Instant::now())
+    }
+
+    /// Like [`Parser::parse()`], but for input that was received at the given time.
+    /// This makes the timeouts deterministic when replaying recorded input.
+    pub fn parse_at<'parser, 'input>(
+        &'parser mut self,
+        input: &'input str,
+        now: time::Instant,
+    ) -> Stream<'parser, 'input> {
+        self.now = now;
+        if !input.is_empty() {
+            self.last_input = now;
+        }
         Stream { parser: self, input, off: 0 }
     }
 }
@@ -138,6 +222,17 @@ impl<'input> Stream<'_, 'input> {
         len
     }
 
+    /// Returns the C1 control at the current offset, if any.
+    ///
+    /// Terminals that are set to send 8-bit controls (S8C1T) encode them as
+    /// UTF-8 (`U+0080` to `U+009F`), since that's what our input is.
+    fn peek_c1(&self) -> Option<u8> {
+        match self.input.as_bytes()[self.off..] {
+            [0xc2, c @ 0x80..=0x9f, ..] => Some(c),
+            _ => None,
+        }
+    }
+
     fn decode_next(&mut self) -> char {
         let mut iter = Utf8Chars::new(self.input.as_bytes(), self.off);
         let c = iter.next().unwrap_or('\0');
@@ -162,12 +257,27 @@ impl<'input> Stream<'_, 'input> {
             self.parser.state = State::Ground;
             return Some(Token::Esc('\0'));
         }
+        if input.is_empty()
+            && matches!(
+                self.parser.state,
+                State::Osc
+                    | State::Dcs
+                    | State::Apc
+                    | State::OscEsc
+                    | State::DcsEsc
+                    | State::ApcEsc
+            )
+            && self.parser.now.saturating_duration_since(self.parser.last_input) >= STRING_TIMEOUT
+        {
+            self.parser.state = State::Ground;
+            return Some(Token::Cancel);
+        }
 
         while self.off < bytes.len() {
             // TODO: The state machine can be roughly broken up into two parts:
             // * Wants to parse 1 `char` at a time: Ground, Esc, Ss3
             //   These could all be unified to a single call to `decode_next()`.
-            // * Wants to bulk-process bytes: Csi, Osc, Dcs
+            // * Wants to bulk-process bytes: Csi, Osc, Dcs, Apc
             // We should do that so the UTF8 handling is a bit more "unified".
             match self.parser.state {
                 State::Ground => match bytes[self.off] {
@@ -175,30 +285,48 @@ impl<'input> Stream<'_, 'input> {
                         self.parser.state = State::Esc;
                         self.off += 1;
                     }
+                    0xc2 if let Some(c1) = self.peek_c1() => {
+                        self.off += 2;
+                        // The 8-bit forms of the sequences we understand. Anything else
+                        // (including a stray ST) is dropped, since it has no 7-bit meaning to us.
+                        self.parser.state = match c1 {
+                            0x8f => State::Ss3,
+                            0x90 => State::Dcs,
+                            0x9b => {
+                                self.parser.csi.clear();
+                                State::Csi
+                            }
+                            0x9d => State::Osc,
+                            0x9f => State::Apc,
+                            _ => State::Ground,
+                        };
+                    }
                     c @ (0x00..0x20 | 0x7f) => {
                         self.off += 1;
                         return Some(Token::Ctrl(c as char));
                     }
                     _ => {
                         let beg = self.off;
-                        while {
-                            self.off += 1;
-                            self.off < bytes.len()
-                                && bytes[self.off] >= 0x20
-                                && bytes[self.off] != 0x7f
-                        } {}
+                        self.off += 1;
+                        loop {
+                            self.off = find_control(bytes, self.off);
+                            // 0xC2 also starts regular characters, like U+00A0.
+                            if self.off < bytes.len()
+                                && bytes[self.off] == 0xc2
+                                && self.peek_c1().is_none()
+                            {
+                                self.off += 1;
+                                continue;
+                            }
+                            break;
+                        }
                         return Some(Token::Text(&input[beg..self.off]));
                     }
                 },
                 State::Esc => match self.decode_next() {
                     '[' => {
                         self.parser.state = State::Csi;
-                        self.parser.csi.private_byte = '\0';
-                        self.parser.csi.final_byte = '\0';
-                        while self.parser.csi.param_count > 0 {
-                            self.parser.csi.param_count -= 1;
-                            self.parser.csi.params[self.parser.csi.param_count] = 0;
-                        }
+                        self.parser.csi.clear();
                     }
                     ']' => {
                         self.parser.state = State::Osc;
@@ -209,6 +337,9 @@ impl<'input> Stream<'_, 'input> {
                     'P' => {
                         self.parser.state = State::Dcs;
                     }
+                    '_' => {
+                        self.parser.state = State::Apc;
+                    }
                     c => {
                         self.parser.state = State::Ground;
                         return Some(Token::Esc(c));
@@ -256,52 +387,85 @@ impl<'input> Stream<'_, 'input> {
                                 return Some(Token::Csi(&self.parser.csi));
                             }
                             b';' => self.parser.csi.param_count += 1,
+                            b':' => {
+                                self.parser.csi.param_count += 1;
+                                if self.parser.csi.param_count < self.parser.csi.params.len() {
+                                    self.parser.csi.subparams |= 1 << self.parser.csi.param_count;
+                                }
+                            }
                             b'<'..=b'?' => self.parser.csi.private_byte = c as char,
+                            // A new escape sequence or CAN/SUB abort the CSI sequence.
+                            // Otherwise, binary garbage could keep us in this state indefinitely.
+                            0x1b => {
+                                self.parser.state = State::Esc;
+                                break;
+                            }
+                            0x18 | 0x1a => {
+                                self.parser.state = State::Ground;
+                                break;
+                            }
                             _ => {}
                         }
                     }
                 }
-                State::Osc | State::Dcs => {
+                State::Osc | State::Dcs | State::Apc => {
                     let beg = self.off;
-                    let mut data;
-                    let mut partial;
+                    let data;
+                    let mut partial = false;
 
+                    // Find any indication for the end of the OSC/DCS/APC sequence:
+                    // BEL, ESC and the 8-bit ST (U+009C) terminate it, while CAN and SUB abort it.
+                    let mut st = None;
+                    self.off = beg;
                     loop {
-                        // Find any indication for the end of the OSC/DCS sequence.
-                        self.off = memchr2(b'\x07', b'\x1b', bytes, self.off);
+                        self.off = find_control(bytes, self.off);
+                        match bytes.get(self.off) {
+                            None | Some(0x07 | 0x1b) => break,
+                            Some(0x18 | 0x1a) => {
+                                self.off += 1;
+                                self.parser.state = State::Ground;
+                                return Some(Token::Cancel);
+                            }
+                            Some(0xc2) if bytes.get(self.off + 1) == Some(&0x9c) => {
+                                st = Some(self.off);
+                                break;
+                            }
+                            _ => self.off += 1,
+                        }
+                    }
 
+                    if let Some(end) = st {
+                        data = &input[beg..end];
+                        self.off = end + 2;
+                    } else {
                         data = &input[beg..self.off];
-                        partial = self.off >= bytes.len();
-
                         // Encountered the end of the input before finding the terminator.
-                        if partial {
-                            break;
-                        }
+                        partial = self.off >= bytes.len();
 
-                        let c = bytes[self.off];
-                        self.off += 1;
+                        if !partial && bytes[self.off] == 0x1b {
+                            self.off += 1;
 
-                        if c == 0x1b {
-                            // It's only a string terminator if it's followed by \.
-                            // We're at the end so we're saving the state and will continue next time.
                             if self.off >= bytes.len() {
+                                // It's only a string terminator if it's followed by \.
+                                // We're at the end so we're saving the state and will continue next time.
                                 self.parser.state = match self.parser.state {
                                     State::Osc => State::OscEsc,
-                                    _ => State::DcsEsc,
+                                    State::Dcs => State::DcsEsc,
+                                    _ => State::ApcEsc,
                                 };
                                 partial = true;
-                                break;
-                            }
-
-                            // False alarm: Not a string terminator.
-                            if bytes[self.off] != b'\\' {
-                                continue;
+                            } else if bytes[self.off] == b'\\' {
+                                self.off += 1;
+                            } else {
+                                // Any other escape sequence aborts the string.
+                                // This way, a stray `ESC ]` can't swallow the input that follows it.
+                                self.parser.state = State::Esc;
+                                return Some(Token::Cancel);
                             }
-
+                        } else if !partial {
+                            // BEL
                             self.off += 1;
                         }
-
-                        break;
                     }
 
                     let state = self.parser.state;
@@ -309,12 +473,13 @@ impl<'input> Stream<'_, 'input> {
                         self.parser.state = State::Ground;
                     }
                     return match state {
-                        State::Osc => Some(Token::Osc { data, partial }),
-                        _ => Some(Token::Dcs { data, partial }),
+                        State::Osc | State::OscEsc => Some(Token::Osc { data, partial }),
+                        State::Dcs | State::DcsEsc => Some(Token::Dcs { data, partial }),
+                        _ => Some(Token::Apc { data, partial }),
                     };
                 }
-                State::OscEsc | State::DcsEsc => {
-                    // We were processing an OSC/DCS sequence and the last byte was an escape character.
+                State::OscEsc | State::DcsEsc | State::ApcEsc => {
+                    // We were processing an OSC/DCS/APC sequence and the last byte was an escape character.
                     // It's only a string terminator if it's followed by \ (= "\x1b\\").
                     if bytes[self.off] == b'\\' {
                         // It was indeed a string terminator and we can now tell the caller about it.
@@ -326,20 +491,14 @@ impl<'input> Stream<'_, 'input> {
 
                         return match state {
                             State::OscEsc => Some(Token::Osc { data: "", partial: false }),
-                            _ => Some(Token::Dcs { data: "", partial: false }),
+                            State::DcsEsc => Some(Token::Dcs { data: "", partial: false }),
+                            _ => Some(Token::Apc { data: "", partial: false }),
                         };
                     } else {
-                        // False alarm: Not a string terminator.
-                        // We'll return the escape character as a separate token.
-                        // Processing will continue from the current state (`bytes[self.off]`).
-                        self.parser.state = match self.parser.state {
-                            State::OscEsc => State::Osc,
-                            _ => State::Dcs,
-                        };
-                        return match self.parser.state {
-                            State::Osc => Some(Token::Osc { data: "\x1b", partial: true }),
-                            _ => Some(Token::Dcs { data: "\x1b", partial: true }),
-                        };
+                        // Not a string terminator, but the start of another escape sequence,
+                        // which aborts the string. Processing continues from `bytes[self.off]`.
+                        self.parser.state = State::Esc;
+                        return Some(Token::Cancel);
                     }
                 }
             }
@@ -348,3 +507,172 @@ impl<'input> Stream<'_, 'input> {
         None
     }
 }
+
+/// A terminal multiplexer that sits between us and the terminal.
+///
+/// It interprets our output itself, and drops sequences it doesn't know about,
+/// unless they're wrapped with [`wrap_passthrough`].
+#[derive(Default, Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Multiplexer {
+    #[default]
+    None,
+    Tmux,
+    Screen,
+}
+
+/// Wraps the escape sequence at `dst[beg..]` so that the `multiplexer`
+/// passes it through to the terminal as-is. Does nothing without one.
+///
+/// tmux only does so if its `allow-passthrough` option is enabled.
+/// The sequence should be a single OSC, DCS or APC sequence, terminated by ST.
+pub fn wrap_passthrough(dst: &mut ArenaString, beg: usize, multiplexer: Multiplexer) {
+    if multiplexer == Multiplexer::None || beg >= dst.len() {
+        return;
+    }
+
+    let seq = dst[beg..].to_string();
+    dst.replace_range(beg.., "");
+
+    match multiplexer {
+        Multiplexer::None => {}
+        // `DCS tmux; <sequence> ST`, where each ESC in the sequence is doubled.
+        Multiplexer::Tmux => {
+            dst.reserve(seq.len() + 16);
+            dst.push_str("\x1bPtmux;");
+            for (i, part) in seq.split('\x1b').enumerate() {
+                if i > 0 {
+                    dst.push_str("\x1b\x1b");
+                }
+                dst.push_str(part);
+            }
+            dst.push_str("\x1b\\");
+        }
+        // `DCS <sequence> ST`, but screen limits the length of DCS strings to 768 bytes,
+        // so longer ones are split up. The ST in the sequence would end the DCS early,
+        // which is why it's replaced with a BEL.
+        Multiplexer::Screen => {
+            const CHUNK: usize = 768;
+            let seq = match seq.strip_suffix("\x1b\\") {
+                Some(seq) => seq.to_string() + "\x07",
+                None => seq,
+            };
+            let mut rest = seq.as_str();
+
+            dst.reserve(seq.len() + seq.len() / CHUNK * 4 + 4);
+            while !rest.is_empty() {
+                let mut end = rest.len().min(CHUNK);
+                while !rest.is_char_boundary(end) {
+                    end -= 1;
+                }
+                dst.push_str("\x1bP");
+                dst.push_str(&rest[..end]);
+                dst.push_str("\x1b\\");
+                rest = &rest[end..];
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::arena::Arena;
+
+    #[test]
+    fn test_string_cancel() {
+        let mut parser = Parser::new();
+        let mut stream = parser.parse("\x1bPabc\x18x\x1b_a\x1ab");
+        assert!(matches!(stream.next(), Some(Token::Cancel)));
+        assert!(matches!(stream.next(), Some(Token::Text("x"))));
+        assert!(matches!(stream.next(), Some(Token::Cancel)));
+        assert!(matches!(stream.next(), Some(Token::Text("b"))));
+        assert!(stream.next().is_none());
+
+        // An incomplete sequence is only cancelled once the timeout elapsed.
+        let mut stream = parser.parse("\x1b]foo");
+        assert!(matches!(stream.next(), Some(Token::Osc { data: "foo", partial: true })));
+        assert!(parser.parse("").next().is_none());
+        parser.last_input -= STRING_TIMEOUT;
+        assert!(matches!(parser.parse("").next(), Some(Token::Cancel)));
+        assert!(matches!(parser.parse("a").next(), Some(Token::Text("a"))));
+    }
+
+    #[test]
+    fn test_c1_controls() {
+        let mut parser = Parser::new();
+
+        // 8-bit CSI, OSC (terminated by an 8-bit ST) and SS3.
+        let mut stream = parser.parse("a\u{9b}1;5Ab\u{9d}52;c;YQ==\u{9c}\u{8f}P");
+        assert!(matches!(stream.next(), Some(Token::Text("a"))));
+        assert!(
+            matches!(stream.next(), Some(Token::Csi(csi)) if csi.final_byte == 'A' && csi.params[..2] == [1, 5])
+        );
+        assert!(matches!(stream.next(), Some(Token::Text("b"))));
+        assert!(matches!(stream.next(), Some(Token::Osc { data: "52;c;YQ==", partial: false })));
+        assert!(matches!(stream.next(), Some(Token::SS3('P'))));
+        assert!(stream.next().is_none());
+
+        // Other C1 controls are dropped. A U+009C inside other characters isn't an ST.
+        let mut stream = parser.parse("x\u{85}\u{9c}y\x1b]a\u{201c}b\x07");
+        assert!(matches!(stream.next(), Some(Token::Text("x"))));
+        assert!(matches!(stream.next(), Some(Token::Text("y"))));
+        assert!(matches!(stream.next(), Some(Token::Osc { data: "a\u{201c}b", partial: false })));
+        assert!(stream.next().is_none());
+    }
+
+    #[test]
+    fn test_garbage_recovery() {
+        let mut parser = Parser::new();
+
+        // A stray OSC or CSI introducer doesn't swallow the sequence that follows it.
+        let mut stream = parser.parse("\x1b]foo\x1b[201~\x1b[1\x1b[A");
+        assert!(matches!(stream.next(), Some(Token::Cancel)));
+        assert!(
+            matches!(stream.next(), Some(Token::Csi(csi)) if csi.final_byte == '~' && csi.params[0] == 201)
+        );
+        assert!(
+            matches!(stream.next(), Some(Token::Csi(csi)) if csi.final_byte == 'A' && csi.param_count == 0)
+        );
+        assert!(stream.next().is_none());
+
+        // Same, if the ESC is split from the rest.
+        assert!(matches!(
+            parser.parse("\x1bPfoo\x1b").next(),
+            Some(Token::Dcs { data: "foo", partial: true })
+        ));
+        let mut stream = parser.parse("[B");
+        assert!(matches!(stream.next(), Some(Token::Cancel)));
+        assert!(matches!(stream.next(), Some(Token::Csi(csi)) if csi.final_byte == 'B'));
+
+        // CAN aborts a CSI sequence.
+        let mut stream = parser.parse("\x1b[12\x18z");
+        assert!(matches!(stream.next(), Some(Token::Text("z"))));
+    }
+
+    #[test]
+    fn test_wrap_passthrough() {
+        let arena = Arena::new(64 * 1024).unwrap();
+        let wrap = |seq: &str, multiplexer| {
+            let mut dst = ArenaString::new_in(&arena);
+            dst.push_str("x");
+            dst.push_str(seq);
+            wrap_passthrough(&mut dst, 1, multiplexer);
+            dst
+        };
+
+        assert_eq!(wrap("\x1b]52;c;YQ==\x1b\\", Multiplexer::None), "x\x1b]52;c;YQ==\x1b\\");
+        assert_eq!(
+            wrap("\x1b]52;c;YQ==\x1b\\", Multiplexer::Tmux),
+            "x\x1bPtmux;\x1b\x1b]52;c;YQ==\x1b\x1b\\\x1b\\"
+        );
+        assert_eq!(
+            wrap("\x1b]52;c;YQ==\x1b\\", Multiplexer::Screen),
+            "x\x1bP\x1b]52;c;YQ==\x07\x1b\\"
+        );
+
+        let long = format!("\x1b]52;c;{}\x1b\\", "A".repeat(1000));
+        let wrapped = wrap(&long, Multiplexer::Screen);
+        assert_eq!(wrapped.matches("\x1bP").count(), 2);
+        assert!(wrapped.ends_with("AAA\x07\x1b\\"));
+    }
+}